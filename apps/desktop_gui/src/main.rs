@@ -15,10 +15,12 @@ mod ui;
 
 use arboard::{Clipboard, ImageData};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use client_core::{
-    AttachmentUpload, ClientEvent, ClientHandle, DurableMlsSessionManager, PassthroughCrypto,
-    RealtimeClient, VoiceConnectOptions, VoiceParticipantState, VoiceSessionSnapshot,
+    AttachmentUpload, ClientEvent, ClientHandle, DurableMlsSessionManager, MlsAtRestKeySource,
+    PassthroughCrypto, RealtimeClient, ServerTrust, VoiceConnectOptions, VoiceParticipantState,
+    VoiceSessionSnapshot,
 };
 use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
 use eframe::egui;
@@ -27,9 +29,15 @@ use image::GenericImageView;
 use reqwest::Client as HttpClient;
 use serde::{Deserialize, Serialize};
 use shared::{
-    domain::{ChannelId, ChannelKind, FileId, GuildId, MessageId, Role},
+    domain::{
+        AuditAction, ChannelId, ChannelKind, FileId, GuildId, MessageId, ModerationAction, Role,
+        ThreadId, UserId,
+    },
     protocol::{
-        AttachmentPayload, ChannelSummary, GuildSummary, MemberSummary, MessagePayload, ServerEvent,
+        AttachmentPayload, AuditLogEntry, AuditLogFilter, ChannelSummary, CreateChannelRequest,
+        DirectChannelSummary, GuildStorageUsage, GuildSummary, MemberSummary, MessagePayload,
+        ModerationRequest, RetentionPolicy, ServerEvent, StorageUsageSummary, ThreadSummary,
+        UpdateChannelRequest,
     },
 };
 
@@ -65,6 +73,27 @@ struct CliArgs {
 
     #[arg(long)]
     data_dir: Option<PathBuf>,
+
+    /// PEM file of CA certificates to trust for https:// and wss:// instead of the public roots.
+    #[arg(long, conflicts_with = "server_cert_sha256")]
+    server_ca_file: Option<PathBuf>,
+
+    /// SHA-256 of the server certificate to accept, as logged by a self-signed server.
+    #[arg(long)]
+    server_cert_sha256: Option<String>,
+}
+
+impl CliArgs {
+    /// The flags take precedence over `CLIENT_TLS_CERT_SHA256` / `CLIENT_TLS_CA_FILE`.
+    fn server_trust(&self) -> anyhow::Result<ServerTrust> {
+        if let Some(fingerprint) = &self.server_cert_sha256 {
+            return ServerTrust::pinned_sha256(fingerprint);
+        }
+        if let Some(path) = &self.server_ca_file {
+            return ServerTrust::custom_ca_file(path);
+        }
+        ServerTrust::from_env()
+    }
 }
 #[derive(Debug, Clone)]
 pub struct AppPaths {
@@ -101,6 +130,14 @@ enum BackendCommand {
     Login {
         server_url: String,
         username: String,
+        local_passphrase: Option<String>,
+        restore_passphrase: Option<String>,
+    },
+    UploadMlsBackup {
+        passphrase: String,
+    },
+    RotateLocalMlsKey {
+        new_passphrase: String,
     },
     ListGuilds,
     ListChannels {
@@ -130,9 +167,86 @@ enum BackendCommand {
     CreateInvite {
         guild_id: GuildId,
     },
+    CreateGuild {
+        name: String,
+    },
+    RenameGuild {
+        guild_id: GuildId,
+        name: String,
+    },
+    DeleteGuild {
+        guild_id: GuildId,
+        confirm_name: String,
+    },
+    LeaveGuild {
+        guild_id: GuildId,
+    },
+    TransferOwnership {
+        guild_id: GuildId,
+        new_owner_id: UserId,
+    },
+    ModerateMember {
+        guild_id: GuildId,
+        target_user_id: UserId,
+        request: ModerationRequest,
+    },
+    LoadAuditLog {
+        guild_id: GuildId,
+        filter: AuditLogFilter,
+    },
+    LoadStorageUsage {
+        guild_id: GuildId,
+    },
+    CreateChannel {
+        guild_id: GuildId,
+        request: CreateChannelRequest,
+    },
+    UpdateChannel {
+        channel_id: ChannelId,
+        request: UpdateChannelRequest,
+    },
+    ReorderChannels {
+        guild_id: GuildId,
+        channel_ids: Vec<ChannelId>,
+    },
+    DeleteChannel {
+        channel_id: ChannelId,
+    },
+    SetChannelRetention {
+        channel_id: ChannelId,
+        retention: RetentionPolicy,
+    },
+    SetMessageExpiry {
+        channel_id: ChannelId,
+        expires_in_seconds: Option<u64>,
+    },
     JoinWithInvite {
         invite_code: String,
     },
+    ListDirectChannels,
+    CreateDirectChannel {
+        usernames: Vec<String>,
+    },
+    ListThreads {
+        channel_id: ChannelId,
+    },
+    CreateThread {
+        channel_id: ChannelId,
+        parent_message_id: MessageId,
+        name: String,
+    },
+    SetThreadArchived {
+        thread_id: ThreadId,
+        archived: bool,
+    },
+    LoadThreadMessages {
+        thread_id: ThreadId,
+        before: Option<MessageId>,
+    },
+    SendThreadMessage {
+        thread_id: ThreadId,
+        text: String,
+    },
     ConnectVoice {
         guild_id: GuildId,
         channel_id: ChannelId,
@@ -142,9 +256,25 @@ enum BackendCommand {
 
 enum UiEvent {
     LoginOk,
+    /// Whether the local MLS database is unlocked by the key file rather than a passphrase.
+    LocalMlsKeyFileInUse(bool),
     Info(String),
     InviteCreated(String),
     JoinedGuild(GuildId),
+    LeftGuild(GuildId),
+    DirectChannelsLoaded(Vec<DirectChannelSummary>),
+    DirectChannelOpened(DirectChannelSummary),
+    ThreadsLoaded {
+        channel_id: ChannelId,
+        threads: Vec<ThreadSummary>,
+    },
+    ThreadOpened(ThreadSummary),
+    AuditLogLoaded {
+        guild_id: GuildId,
+        entries: Vec<AuditLogEntry>,
+        append: bool,
+    },
+    StorageUsageLoaded(GuildStorageUsage),
     SenderDirectoryUpdated {
         user_id: i64,
         username: String,
@@ -153,7 +283,8 @@ enum UiEvent {
     AttachmentPreviewLoaded {
         file_id: FileId,
         image: PreviewImage,
-        original_bytes: Vec<u8>,
+        /// `None` when the preview came from the attachment's thumbnail.
+        original_bytes: Option<Vec<u8>>,
     },
     AttachmentPreviewFailed {
         file_id: FileId,
@@ -171,6 +302,16 @@ enum UiEvent {
         participants: Vec<VoiceParticipantState>,
     },
     VoiceOperationFailed(String),
+    UploadProgress {
+        upload_id: String,
+        filename: String,
+        sent_bytes: u64,
+        total_bytes: u64,
+    },
+    UploadFinished {
+        upload_id: String,
+    },
+    AttachmentPreviewAvailable(client_core::AttachmentPreview),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -193,7 +334,11 @@ enum UiErrorContext {
 
 fn classify_login_failure(message: &str) -> String {
     let lower = message.to_ascii_lowercase();
-    if lower.contains("backend worker startup failure")
+    if lower.contains("wrong passphrase or key file")
+        || lower.contains("local mls database is encrypted")
+    {
+        "Local MLS database is locked; check the local passphrase and retry sign-in.".to_string()
+    } else if lower.contains("backend worker startup failure")
         || lower.contains("failed to initialize persistent mls backend")
         || lower.contains("failed to build backend runtime")
     {
@@ -400,7 +545,7 @@ enum AttachmentPreviewState {
     Loading,
     Ready {
         image: PreviewImage,
-        original_bytes: Vec<u8>,
+        original_bytes: Option<Vec<u8>>,
         preview_png: Option<Vec<u8>>,
         texture: Option<egui::TextureHandle>,
     },
@@ -516,6 +661,7 @@ const MAX_COMPOSER_PANEL_HEIGHT: f32 = 220.0;
 const DEFAULT_LEFT_USER_PANEL_HEIGHT: f32 = DEFAULT_COMPOSER_PANEL_HEIGHT;
 const MIN_LEFT_USER_PANEL_HEIGHT: f32 = 56.0;
 const MAX_LEFT_USER_PANEL_HEIGHT: f32 = 220.0;
+const AUDIT_LOG_PAGE_SIZE: u32 = 50;
 
 impl Default for PersistedDesktopSettings {
     fn default() -> Self {
@@ -613,14 +759,107 @@ struct LoginUiState {
     focus: Option<LoginFocusField>,
     attempted_auto_focus: bool,
     last_login_click_tick: u64,
+    local_passphrase: String,
+    use_key_file: bool,
+    restore_backup: bool,
+    backup_passphrase: String,
+}
+
+/// Drafts backing the guild settings window; channel drafts are seeded lazily
+/// from the current channel list and dropped whenever the server updates them.
+#[derive(Debug, Clone, Default)]
+struct GuildSettingsUiState {
+    open: bool,
+    guild_name: String,
+    new_guild_name: String,
+    new_channel: ChannelDraft,
+    new_channel_kind: Option<ChannelKind>,
+    channel_drafts: HashMap<ChannelId, ChannelDraft>,
+    transfer_target: Option<UserId>,
+    delete_confirm_name: String,
+    moderation_target: Option<UserId>,
+    moderation_reason: String,
+    audit_action_filter: Option<AuditAction>,
+    audit_entries: Vec<AuditLogEntry>,
+    audit_has_more: bool,
+    storage_usage: Option<GuildStorageUsage>,
+}
+
+/// Threads of the channels we have looked at, plus the thread open in the side pane.
+/// Thread messages live here rather than in the channel's message list.
+#[derive(Clone, Default)]
+struct ThreadPaneUiState {
+    threads: HashMap<ChannelId, Vec<ThreadSummary>>,
+    messages: HashMap<ThreadId, Vec<DisplayMessage>>,
+    open: Option<ThreadId>,
+    composer: String,
+    show_archived: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ChannelDraft {
+    name: String,
+    topic: String,
+    category: String,
+    retention_hours: String,
+    retention_messages: String,
+}
+
+impl ChannelDraft {
+    fn from_channel(channel: &ChannelSummary) -> Self {
+        Self {
+            name: channel.name.clone(),
+            topic: channel.topic.clone().unwrap_or_default(),
+            category: channel.category.clone().unwrap_or_default(),
+            retention_hours: channel
+                .retention
+                .max_age_seconds
+                .map(|seconds| (seconds / 3600).to_string())
+                .unwrap_or_default(),
+            retention_messages: channel
+                .retention
+                .max_messages
+                .map(|count| count.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Reads the retention fields; blank means unlimited, anything unparsable is `None`.
+    fn retention(&self) -> Option<RetentionPolicy> {
+        let parse = |value: &str| -> Option<Option<u64>> {
+            let value = value.trim();
+            if value.is_empty() {
+                Some(None)
+            } else {
+                value.parse::<u64>().ok().filter(|v| *v > 0).map(Some)
+            }
+        };
+        Some(RetentionPolicy {
+            max_age_seconds: parse(&self.retention_hours)?.map(|hours| hours * 3600),
+            max_messages: parse(&self.retention_messages)?,
+        })
+    }
 }
 
+/// Disappearing-message timers offered in the composer.
+const MESSAGE_TIMER_OPTIONS: [(Option<u64>, &str); 5] = [
+    (None, "Off"),
+    (Some(5 * 60), "5 minutes"),
+    (Some(60 * 60), "1 hour"),
+    (Some(24 * 60 * 60), "1 day"),
+    (Some(7 * 24 * 60 * 60), "7 days"),
+];
+
 impl Default for LoginUiState {
     fn default() -> Self {
         Self {
             focus: Some(LoginFocusField::Username),
             attempted_auto_focus: false,
             last_login_click_tick: 0,
+            local_passphrase: String::new(),
+            use_key_file: false,
+            restore_backup: false,
+            backup_passphrase: String::new(),
         }
     }
 }
@@ -633,6 +872,7 @@ struct DesktopGuiApp {
     username: String,
     invite_code_input: String,
     auth_session_established: bool,
+    local_key_file_in_use: bool,
     presence_preference: AccountPresence,
     notifications_enabled: bool,
     desktop_notifications_enabled: bool,
@@ -641,6 +881,7 @@ struct DesktopGuiApp {
 
     composer: String,
     pending_attachment: Option<PathBuf>,
+    upload_progress: BTreeMap<String, UploadProgress>,
     attachment_preview_cache: HashMap<AttachmentPreviewCacheKey, AttachmentPreview>,
 
     guilds: Vec<GuildSummary>,
@@ -657,12 +898,23 @@ struct DesktopGuiApp {
 
     sender_directory: HashMap<i64, String>,
     attachment_previews: HashMap<FileId, AttachmentPreviewState>,
+    /// Sizes and blurhash placeholders sent with image attachments, with the placeholder
+    /// texture once it has been drawn.
+    image_placeholders:
+        HashMap<FileId, (client_core::AttachmentPreview, Option<egui::TextureHandle>)>,
     expanded_preview: Option<FileId>,
     hovered_message: Option<MessageId>,
 
     voice_ui: VoiceSessionUiState,
 
     settings_open: bool,
+    guild_settings: GuildSettingsUiState,
+    direct_channels: Vec<DirectChannelSummary>,
+    new_direct_usernames: String,
+    thread_pane: ThreadPaneUiState,
+    message_timers: HashMap<ChannelId, u64>,
+    backup_passphrase_draft: String,
+    local_passphrase_draft: String,
     view_state: AppViewState,
 
     theme: ThemeSettings,
@@ -679,6 +931,12 @@ struct DesktopGuiApp {
     tick: u64,
 }
 
+struct UploadProgress {
+    filename: String,
+    sent_bytes: u64,
+    total_bytes: u64,
+}
+
 #[derive(Clone)]
 enum AttachmentPreview {
     Image {
@@ -724,6 +982,7 @@ impl DesktopGuiApp {
             username: startup.username.clone(),
             invite_code_input: String::new(),
             auth_session_established: false,
+            local_key_file_in_use: false,
             presence_preference: AccountPresence::Online,
             notifications_enabled: true,
             desktop_notifications_enabled: true,
@@ -731,6 +990,7 @@ impl DesktopGuiApp {
             display_name_draft: startup.display_name.clone(),
             composer: String::new(),
             pending_attachment: None,
+            upload_progress: BTreeMap::new(),
             attachment_preview_cache: HashMap::new(),
             guilds: Vec::new(),
             channels: Vec::new(),
@@ -743,10 +1003,18 @@ impl DesktopGuiApp {
             status_banner: None,
             sender_directory: HashMap::new(),
             attachment_previews: HashMap::new(),
+            image_placeholders: HashMap::new(),
             expanded_preview: None,
             hovered_message: None,
             voice_ui: VoiceSessionUiState::new(),
             settings_open: false,
+            guild_settings: GuildSettingsUiState::default(),
+            direct_channels: Vec::new(),
+            new_direct_usernames: String::new(),
+            thread_pane: ThreadPaneUiState::default(),
+            message_timers: HashMap::new(),
+            backup_passphrase_draft: String::new(),
+            local_passphrase_draft: String::new(),
             view_state: AppViewState::Login,
             theme,
             applied_theme: None,
@@ -776,9 +1044,20 @@ impl DesktopGuiApp {
                     self.selected_channel = None;
                     self.sender_directory.clear();
                     self.attachment_previews.clear();
+                    self.image_placeholders.clear();
                     self.expanded_preview = None;
                     self.voice_ui = VoiceSessionUiState::new();
+                    self.direct_channels.clear();
+                    self.thread_pane = ThreadPaneUiState::default();
                     queue_command(&self.cmd_tx, BackendCommand::ListGuilds, &mut self.status);
+                    queue_command(
+                        &self.cmd_tx,
+                        BackendCommand::ListDirectChannels,
+                        &mut self.status,
+                    );
+                }
+                UiEvent::LocalMlsKeyFileInUse(in_use) => {
+                    self.local_key_file_in_use = in_use;
                 }
                 UiEvent::Info(message) => {
                     self.status = message;
@@ -792,6 +1071,49 @@ impl DesktopGuiApp {
                         "Invite created, copied to clipboard, and inserted into the Invite field"
                             .to_string();
                 }
+                UiEvent::LeftGuild(guild_id) => {
+                    self.remove_guild(guild_id);
+                    self.status = "Left guild".to_string();
+                }
+                UiEvent::DirectChannelsLoaded(direct_channels) => {
+                    self.direct_channels = direct_channels;
+                }
+                UiEvent::DirectChannelOpened(direct) => {
+                    let channel_id = direct.channel.channel_id;
+                    self.upsert_direct_channel(direct);
+                    self.select_direct_channel(channel_id);
+                }
+                UiEvent::ThreadsLoaded {
+                    channel_id,
+                    threads,
+                } => {
+                    self.thread_pane.threads.insert(channel_id, threads);
+                }
+                UiEvent::ThreadOpened(thread) => {
+                    let thread_id = thread.thread_id;
+                    self.upsert_thread(thread);
+                    self.open_thread(thread_id);
+                }
+                UiEvent::AuditLogLoaded {
+                    guild_id,
+                    entries,
+                    append,
+                } => {
+                    if self.selected_guild == Some(guild_id) {
+                        let state = &mut self.guild_settings;
+                        state.audit_has_more = entries.len() == AUDIT_LOG_PAGE_SIZE as usize;
+                        if append {
+                            state.audit_entries.extend(entries);
+                        } else {
+                            state.audit_entries = entries;
+                        }
+                    }
+                }
+                UiEvent::StorageUsageLoaded(usage) => {
+                    if self.selected_guild == Some(usage.guild_id) {
+                        self.guild_settings.storage_usage = Some(usage);
+                    }
+                }
                 UiEvent::JoinedGuild(guild_id) => {
                     self.selected_guild = Some(guild_id);
                     self.selected_channel = None;
@@ -841,6 +1163,28 @@ impl DesktopGuiApp {
                         }
                     }
                 }
+                UiEvent::UploadProgress {
+                    upload_id,
+                    filename,
+                    sent_bytes,
+                    total_bytes,
+                } => {
+                    self.upload_progress.insert(
+                        upload_id,
+                        UploadProgress {
+                            filename,
+                            sent_bytes,
+                            total_bytes,
+                        },
+                    );
+                }
+                UiEvent::UploadFinished { upload_id } => {
+                    self.upload_progress.remove(&upload_id);
+                }
+                UiEvent::AttachmentPreviewAvailable(preview) => {
+                    self.image_placeholders
+                        .insert(preview.file_id.clone(), (preview, None));
+                }
                 UiEvent::VoiceOperationFailed(err) => {
                     self.voice_ui.connection_status = VoiceSessionConnectionStatus::Error;
                     self.voice_ui.last_error = Some(err.clone());
@@ -899,7 +1243,27 @@ impl DesktopGuiApp {
                     }
 
                     let ids = self.message_ids.entry(message.channel_id).or_default();
-                    if ids.insert(message.message_id) {
+                    if !ids.insert(message.message_id) {
+                        continue;
+                    }
+                    if let Some(thread_id) = message.thread_id {
+                        if let Some(thread) = self
+                            .thread_pane
+                            .threads
+                            .get_mut(&message.channel_id)
+                            .and_then(|threads| {
+                                threads.iter_mut().find(|t| t.thread_id == thread_id)
+                            })
+                        {
+                            thread.message_count += 1;
+                        }
+                        let messages = self.thread_pane.messages.entry(thread_id).or_default();
+                        messages.push(DisplayMessage {
+                            wire: message,
+                            plaintext,
+                        });
+                        messages.sort_by_key(|m| m.wire.message_id.0);
+                    } else {
                         let messages = self.messages.entry(message.channel_id).or_default();
                         messages.push(DisplayMessage {
                             wire: message,
@@ -911,10 +1275,15 @@ impl DesktopGuiApp {
                 UiEvent::Server(server_event) => match server_event {
                     ServerEvent::GuildUpdated { guild } => {
                         let guild_id = guild.guild_id;
-                        if !self.guilds.iter().any(|g| g.guild_id == guild_id) {
+                        if let Some(existing) =
+                            self.guilds.iter_mut().find(|g| g.guild_id == guild_id)
+                        {
+                            *existing = guild;
+                        } else {
                             self.guilds.push(guild);
                         }
-                        if self.selected_guild.is_none() {
+                        if self.selected_guild.is_none() && self.selected_direct_channel().is_none()
+                        {
                             self.selected_guild = Some(guild_id);
                             self.channels.clear();
                             queue_command(
@@ -929,27 +1298,73 @@ impl DesktopGuiApp {
                             );
                         }
                     }
-                    ServerEvent::ChannelUpdated { channel } => {
-                        if !self
+                    ServerEvent::ChannelUpdated { channel }
+                        if self.selected_guild == Some(channel.guild_id) =>
+                    {
+                        let channel_id = channel.channel_id;
+                        self.guild_settings.channel_drafts.remove(&channel_id);
+                        if let Some(existing) = self
                             .channels
-                            .iter()
-                            .any(|c| c.channel_id == channel.channel_id)
+                            .iter_mut()
+                            .find(|c| c.channel_id == channel_id)
                         {
-                            let channel_id = channel.channel_id;
+                            *existing = channel;
+                        } else {
                             self.channels.push(channel);
-                            if self.selected_channel.is_none() {
-                                self.selected_channel = Some(channel_id);
-                                queue_command(
-                                    &self.cmd_tx,
-                                    BackendCommand::SelectChannel { channel_id },
-                                    &mut self.status,
-                                );
-                            }
                         }
+                        self.channels.sort_by_key(|c| (c.position, c.channel_id.0));
+                        if self.selected_channel.is_none() {
+                            self.selected_channel = Some(channel_id);
+                            queue_command(
+                                &self.cmd_tx,
+                                BackendCommand::SelectChannel { channel_id },
+                                &mut self.status,
+                            );
+                        }
+                    }
+                    ServerEvent::GuildMembersUpdated { guild_id, members }
+                        if self.selected_guild == Some(guild_id) =>
+                    {
+                        self.members.insert(guild_id, members);
+                    }
+                    ServerEvent::ChannelDeleted { channel_id, .. } => {
+                        self.channels.retain(|c| c.channel_id != channel_id);
+                        self.guild_settings.channel_drafts.remove(&channel_id);
+                        self.messages.remove(&channel_id);
+                        self.message_ids.remove(&channel_id);
+                        self.thread_pane.threads.remove(&channel_id);
+                        if self.selected_channel == Some(channel_id) {
+                            self.selected_channel = None;
+                        }
+                    }
+                    ServerEvent::GuildDeleted { guild_id } => self.remove_guild(guild_id),
+                    ServerEvent::ThreadUpdated { thread } => self.upsert_thread(thread),
+                    ServerEvent::MessagesExpired {
+                        channel_id,
+                        message_ids,
+                        ..
+                    } => self.remove_messages(channel_id, &message_ids),
+                    ServerEvent::DirectChannelUpdated { channel } => {
+                        self.upsert_direct_channel(channel);
+                    }
+                    ServerEvent::UserLeft { guild_id, user_id }
+                    | ServerEvent::UserKicked {
+                        guild_id,
+                        target_user_id: user_id,
                     }
-                    ServerEvent::GuildMembersUpdated { guild_id, members } => {
-                        if self.selected_guild == Some(guild_id) {
-                            self.members.insert(guild_id, members);
+                    | ServerEvent::UserBanned {
+                        guild_id,
+                        target_user_id: user_id,
+                    } => {
+                        // We left from another session or were removed; other members show up
+                        // through the membership update that follows.
+                        let left_self = self.members.get(&guild_id).is_some_and(|members| {
+                            members
+                                .iter()
+                                .any(|m| m.user_id == user_id && m.username == self.username)
+                        });
+                        if left_self {
+                            self.remove_guild(guild_id);
                         }
                     }
                     ServerEvent::Error(err) => {
@@ -961,6 +1376,134 @@ impl DesktopGuiApp {
         }
     }
 
+    fn remove_guild(&mut self, guild_id: GuildId) {
+        self.guilds.retain(|g| g.guild_id != guild_id);
+        self.members.remove(&guild_id);
+        if self.selected_guild == Some(guild_id) {
+            self.selected_guild = None;
+            self.selected_channel = None;
+            self.channels.clear();
+            self.guild_settings.open = false;
+        }
+    }
+
+    /// Adds a conversation to the top of the sidebar, or refreshes it in place.
+    fn upsert_direct_channel(&mut self, direct: DirectChannelSummary) {
+        let channel_id = direct.channel.channel_id;
+        if let Some(existing) = self
+            .direct_channels
+            .iter_mut()
+            .find(|d| d.channel.channel_id == channel_id)
+        {
+            *existing = direct;
+        } else {
+            self.direct_channels.insert(0, direct);
+        }
+    }
+
+    /// Leaves the current guild view and opens a direct conversation in the message pane.
+    fn select_direct_channel(&mut self, channel_id: ChannelId) {
+        self.selected_guild = None;
+        self.channels.clear();
+        self.selected_channel = Some(channel_id);
+        queue_command(
+            &self.cmd_tx,
+            BackendCommand::SelectChannel { channel_id },
+            &mut self.status,
+        );
+    }
+
+    fn selected_direct_channel(&self) -> Option<&DirectChannelSummary> {
+        let channel_id = self.selected_channel?;
+        self.direct_channels
+            .iter()
+            .find(|direct| direct.channel.channel_id == channel_id)
+    }
+
+    /// Names everyone in the conversation except ourselves.
+    fn direct_channel_label(&self, direct: &DirectChannelSummary) -> String {
+        let others: Vec<&str> = direct
+            .participants
+            .iter()
+            .filter(|participant| participant.username != self.username)
+            .map(|participant| participant.username.as_str())
+            .collect();
+        if others.is_empty() {
+            self.username.clone()
+        } else {
+            others.join(", ")
+        }
+    }
+
+    /// Drops messages the server purged, from the channel and from any thread pane.
+    fn remove_messages(&mut self, channel_id: ChannelId, message_ids: &[MessageId]) {
+        if let Some(messages) = self.messages.get_mut(&channel_id) {
+            messages.retain(|m| !message_ids.contains(&m.wire.message_id));
+        }
+        if let Some(ids) = self.message_ids.get_mut(&channel_id) {
+            for message_id in message_ids {
+                ids.remove(message_id);
+            }
+        }
+        for messages in self.thread_pane.messages.values_mut() {
+            messages.retain(|m| {
+                m.wire.channel_id != channel_id || !message_ids.contains(&m.wire.message_id)
+            });
+        }
+    }
+
+    /// Hides disappearing messages whose timer ran out without waiting for the server purge.
+    fn drop_expired_messages(&mut self, now: DateTime<Utc>) {
+        let expired = |m: &DisplayMessage| m.wire.expires_at.is_some_and(|at| at <= now);
+        for (channel_id, messages) in &mut self.messages {
+            if let Some(ids) = self.message_ids.get_mut(channel_id) {
+                for message in messages.iter().filter(|m| expired(m)) {
+                    ids.remove(&message.wire.message_id);
+                }
+            }
+            messages.retain(|m| !expired(m));
+        }
+        for messages in self.thread_pane.messages.values_mut() {
+            messages.retain(|m| !expired(m));
+        }
+    }
+
+    /// Records a created or updated thread. Threads of channels we have not listed yet are
+    /// skipped; they arrive with the channel's thread list.
+    fn upsert_thread(&mut self, thread: ThreadSummary) {
+        let Some(threads) = self.thread_pane.threads.get_mut(&thread.channel_id) else {
+            return;
+        };
+        if let Some(existing) = threads.iter_mut().find(|t| t.thread_id == thread.thread_id) {
+            *existing = thread;
+        } else {
+            threads.insert(0, thread);
+        }
+    }
+
+    fn open_thread(&mut self, thread_id: ThreadId) {
+        self.thread_pane.open = Some(thread_id);
+        self.thread_pane.composer.clear();
+        queue_command(
+            &self.cmd_tx,
+            BackendCommand::LoadThreadMessages {
+                thread_id,
+                before: None,
+            },
+            &mut self.status,
+        );
+    }
+
+    /// The thread shown in the side pane, if it belongs to the selected channel.
+    fn open_thread_summary(&self) -> Option<&ThreadSummary> {
+        let thread_id = self.thread_pane.open?;
+        self.thread_pane
+            .threads
+            .get(&self.selected_channel?)?
+            .iter()
+            .find(|thread| thread.thread_id == thread_id)
+    }
+
     fn oldest_message_id(&self, channel_id: ChannelId) -> Option<MessageId> {
         self.messages
             .get(&channel_id)
@@ -981,6 +1524,10 @@ impl DesktopGuiApp {
             .iter()
             .find(|channel| channel.channel_id == channel_id && channel.kind == ChannelKind::Text)
             .map(|channel| channel.channel_id)
+            .or_else(|| {
+                self.selected_direct_channel()
+                    .map(|direct| direct.channel.channel_id)
+            })
     }
 
     fn voice_status_badge(&self) -> (&'static str, egui::Color32) {
@@ -1028,74 +1575,532 @@ impl DesktopGuiApp {
         self.applied_readability = Some(self.readability);
     }
 
-    fn show_settings_window(&mut self, ctx: &egui::Context) {
-        if !self.settings_open {
+    fn open_guild_settings(&mut self) {
+        self.guild_settings.open = true;
+        self.guild_settings.channel_drafts.clear();
+        self.guild_settings.audit_entries.clear();
+        self.guild_settings.audit_has_more = false;
+        self.guild_settings.storage_usage = None;
+        self.guild_settings.guild_name = self
+            .selected_guild
+            .and_then(|guild_id| self.guilds.iter().find(|g| g.guild_id == guild_id))
+            .map(|guild| guild.name.clone())
+            .unwrap_or_default();
+    }
+
+    fn show_guild_settings_window(&mut self, ctx: &egui::Context) {
+        if !self.guild_settings.open {
             return;
         }
 
-        egui::Window::new("Settings")
-            .open(&mut self.settings_open)
-            .resizable(false)
+        let mut open = self.guild_settings.open;
+        let mut commands = Vec::new();
+        let selected_guild = self.selected_guild;
+        let channels = &self.channels;
+        let guild_name = selected_guild
+            .and_then(|guild_id| self.guilds.iter().find(|g| g.guild_id == guild_id))
+            .map(|guild| guild.name.as_str())
+            .unwrap_or_default();
+        let members = selected_guild
+            .and_then(|guild_id| self.members.get(&guild_id))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let is_owner = members
+            .iter()
+            .any(|m| m.username == self.username && m.role == Role::Owner);
+        let state = &mut self.guild_settings;
+
+        egui::Window::new("Guild Settings")
+            .open(&mut open)
+            .resizable(true)
+            .default_width(520.0)
             .show(ctx, |ui| {
-                ui.label("Theme preset");
-                egui::ComboBox::from_id_salt("theme_preset")
-                    .selected_text(self.theme.preset.label())
-                    .show_ui(ui, |ui| {
-                        ui.selectable_value(
-                            &mut self.theme.preset,
-                            ThemePreset::DiscordDark,
-                            ThemePreset::DiscordDark.label(),
-                        );
-                        ui.selectable_value(
-                            &mut self.theme.preset,
-                            ThemePreset::DiscordLegacy,
-                            ThemePreset::DiscordLegacy.label(),
-                        );
-                        ui.selectable_value(
-                            &mut self.theme.preset,
-                            ThemePreset::AtomOneDark,
-                            ThemePreset::AtomOneDark.label(),
-                        );
-                        ui.selectable_value(
-                            &mut self.theme.preset,
-                            ThemePreset::EguiLight,
-                            ThemePreset::EguiLight.label(),
-                        );
+                if let Some(guild_id) = selected_guild {
+                    ui.label("Guild name");
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut state.guild_name);
+                        let name = state.guild_name.trim();
+                        if ui
+                            .add_enabled(!name.is_empty(), egui::Button::new("Rename"))
+                            .clicked()
+                        {
+                            commands.push(BackendCommand::RenameGuild {
+                                guild_id,
+                                name: name.to_string(),
+                            });
+                        }
                     });
 
-                ui.separator();
-                ui.label("Accent color");
-                ui.color_edit_button_srgba(&mut self.theme.accent_color);
-                ui.small("Used for selected rows, hover emphasis, and primary actions.");
-                ui.add(
-                    egui::Slider::new(&mut self.theme.panel_rounding, 0..=16)
-                        .text("Panel rounding"),
-                );
-                ui.checkbox(
-                    &mut self.theme.list_row_shading,
-                    "Use shaded backgrounds for guild/channel rows",
-                );
-                ui.separator();
-                ui.label("Readability");
-                ui.add(
-                    egui::Slider::new(&mut self.readability.text_scale, 0.8..=1.4)
-                        .text("Text scale")
-                        .step_by(0.05),
-                );
-                ui.checkbox(&mut self.readability.compact_density, "Compact UI density");
-                ui.checkbox(
-                    &mut self.readability.show_timestamps,
-                    "Show message timestamps",
-                );
-                ui.checkbox(
-                    &mut self.readability.message_bubble_backgrounds,
-                    "Show chat message bubble backgrounds",
-                );
+                    ui.separator();
+                    ui.label("Channels");
+                    egui::ScrollArea::vertical()
+                        .id_salt("guild_settings_channels")
+                        .max_height(280.0)
+                        .show(ui, |ui| {
+                            for (index, channel) in channels.iter().enumerate() {
+                                let draft = state
+                                    .channel_drafts
+                                    .entry(channel.channel_id)
+                                    .or_insert_with(|| ChannelDraft::from_channel(channel));
+                                ui.push_id(channel.channel_id.0, |ui| {
+                                    egui::Grid::new("channel_fields").num_columns(2).show(
+                                        ui,
+                                        |ui| {
+                                            ui.label("Name");
+                                            ui.text_edit_singleline(&mut draft.name);
+                                            ui.end_row();
+                                            ui.label("Topic");
+                                            ui.text_edit_singleline(&mut draft.topic);
+                                            ui.end_row();
+                                            ui.label("Category");
+                                            ui.text_edit_singleline(&mut draft.category);
+                                            ui.end_row();
+                                            ui.label("Keep (hours)");
+                                            ui.add(
+                                                egui::TextEdit::singleline(
+                                                    &mut draft.retention_hours,
+                                                )
+                                                .hint_text("forever"),
+                                            );
+                                            ui.end_row();
+                                            ui.label("Keep (messages)");
+                                            ui.add(
+                                                egui::TextEdit::singleline(
+                                                    &mut draft.retention_messages,
+                                                )
+                                                .hint_text("all"),
+                                            );
+                                            ui.end_row();
+                                        },
+                                    );
+                                    ui.horizontal(|ui| {
+                                        if ui.button("Save").clicked() {
+                                            commands.push(BackendCommand::UpdateChannel {
+                                                channel_id: channel.channel_id,
+                                                request: UpdateChannelRequest {
+                                                    name: Some(draft.name.trim().to_string()),
+                                                    topic: Some(draft.topic.trim().to_string()),
+                                                    category: Some(
+                                                        draft.category.trim().to_string(),
+                                                    ),
+                                                },
+                                            });
+                                        }
+                                        let retention = draft.retention();
+                                        if ui
+                                            .add_enabled(
+                                                retention.is_some_and(|r| r != channel.retention),
+                                                egui::Button::new("Save retention"),
+                                            )
+                                            .on_disabled_hover_text(
+                                                "Use whole numbers, or leave blank for no limit",
+                                            )
+                                            .clicked()
+                                        {
+                                            if let Some(retention) = retention {
+                                                commands.push(BackendCommand::SetChannelRetention {
+                                                    channel_id: channel.channel_id,
+                                                    retention,
+                                                });
+                                            }
+                                        }
+                                        let mut swap_with = None;
+                                        if ui
+                                            .add_enabled(index > 0, egui::Button::new("Up"))
+                                            .clicked()
+                                        {
+                                            swap_with = Some(index - 1);
+                                        }
+                                        if ui
+                                            .add_enabled(
+                                                index + 1 < channels.len(),
+                                                egui::Button::new("Down"),
+                                            )
+                                            .clicked()
+                                        {
+                                            swap_with = Some(index + 1);
+                                        }
+                                        if let Some(other) = swap_with {
+                                            let mut channel_ids: Vec<ChannelId> =
+                                                channels.iter().map(|c| c.channel_id).collect();
+                                            channel_ids.swap(index, other);
+                                            commands.push(BackendCommand::ReorderChannels {
+                                                guild_id,
+                                                channel_ids,
+                                            });
+                                        }
+                                        if ui.button("Delete").clicked() {
+                                            commands.push(BackendCommand::DeleteChannel {
+                                                channel_id: channel.channel_id,
+                                            });
+                                        }
+                                    });
+                                });
+                                ui.separator();
+                            }
+                        });
+
+                    ui.label("New channel");
+                    let kind = state.new_channel_kind.get_or_insert(ChannelKind::Text);
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(kind, ChannelKind::Text, "Text");
+                        ui.selectable_value(kind, ChannelKind::Voice, "Voice");
+                    });
+                    let kind = *kind;
+                    egui::Grid::new("new_channel_fields")
+                        .num_columns(2)
+                        .show(ui, |ui| {
+                            ui.label("Name");
+                            ui.text_edit_singleline(&mut state.new_channel.name);
+                            ui.end_row();
+                            ui.label("Topic");
+                            ui.text_edit_singleline(&mut state.new_channel.topic);
+                            ui.end_row();
+                            ui.label("Category");
+                            ui.text_edit_singleline(&mut state.new_channel.category);
+                            ui.end_row();
+                        });
+                    let name = state.new_channel.name.trim().to_string();
+                    if ui
+                        .add_enabled(!name.is_empty(), egui::Button::new("Create channel"))
+                        .clicked()
+                    {
+                        let draft = std::mem::take(&mut state.new_channel);
+                        let non_empty = |value: String| {
+                            let value = value.trim().to_string();
+                            (!value.is_empty()).then_some(value)
+                        };
+                        commands.push(BackendCommand::CreateChannel {
+                            guild_id,
+                            request: CreateChannelRequest {
+                                name,
+                                kind,
+                                topic: non_empty(draft.topic),
+                                category: non_empty(draft.category),
+                            },
+                        });
+                    }
+
+                    ui.separator();
+                    if is_owner {
+                        ui.horizontal(|ui| {
+                            ui.label("Attachment storage");
+                            if ui.button("Refresh").clicked() {
+                                commands.push(BackendCommand::LoadStorageUsage { guild_id });
+                            }
+                        });
+                        if let Some(usage) = &state.storage_usage {
+                            ui.small(format!("Guild: {}", format_storage_usage(&usage.guild)));
+                            ui.small(format!(
+                                "Your uploads: {}",
+                                format_storage_usage(&usage.user)
+                            ));
+                        }
+
+                        ui.separator();
+                        ui.label("Transfer ownership");
+                        ui.horizontal(|ui| {
+                            let selected_text = state
+                                .transfer_target
+                                .and_then(|id| members.iter().find(|m| m.user_id == id))
+                                .map(|m| m.username.as_str())
+                                .unwrap_or("Choose a member");
+                            egui::ComboBox::from_id_salt("transfer_owner")
+                                .selected_text(selected_text)
+                                .show_ui(ui, |ui| {
+                                    for member in members.iter().filter(|m| m.role != Role::Owner) {
+                                        ui.selectable_value(
+                                            &mut state.transfer_target,
+                                            Some(member.user_id),
+                                            member.username.as_str(),
+                                        );
+                                    }
+                                });
+                            if ui
+                                .add_enabled(
+                                    state.transfer_target.is_some(),
+                                    egui::Button::new("Transfer"),
+                                )
+                                .clicked()
+                            {
+                                if let Some(new_owner_id) = state.transfer_target.take() {
+                                    commands.push(BackendCommand::TransferOwnership {
+                                        guild_id,
+                                        new_owner_id,
+                                    });
+                                }
+                            }
+                        });
+
+                        ui.separator();
+                        ui.label("Delete guild");
+                        ui.small(format!(
+                            "This removes every channel, message and file. Type \"{guild_name}\" to confirm."
+                        ));
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut state.delete_confirm_name);
+                            let confirmed = state.delete_confirm_name.trim() == guild_name;
+                            if ui
+                                .add_enabled(confirmed, egui::Button::new("Delete guild"))
+                                .clicked()
+                            {
+                                commands.push(BackendCommand::DeleteGuild {
+                                    guild_id,
+                                    confirm_name: std::mem::take(&mut state.delete_confirm_name),
+                                });
+                            }
+                        });
+                    } else if ui.button("Leave guild").clicked() {
+                        commands.push(BackendCommand::LeaveGuild { guild_id });
+                    }
+
+                    ui.separator();
+                    ui.label("Moderate a member");
+                    ui.horizontal(|ui| {
+                        let selected_text = state
+                            .moderation_target
+                            .and_then(|id| members.iter().find(|m| m.user_id == id))
+                            .map(|m| m.username.as_str())
+                            .unwrap_or("Choose a member");
+                        egui::ComboBox::from_id_salt("moderation_target")
+                            .selected_text(selected_text)
+                            .show_ui(ui, |ui| {
+                                for member in members.iter().filter(|m| m.username != self.username)
+                                {
+                                    ui.selectable_value(
+                                        &mut state.moderation_target,
+                                        Some(member.user_id),
+                                        member.username.as_str(),
+                                    );
+                                }
+                            });
+                        ui.add(
+                            egui::TextEdit::singleline(&mut state.moderation_reason)
+                                .hint_text("Reason (optional)"),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        for (action, label) in [
+                            (ModerationAction::Kick, "Kick"),
+                            (ModerationAction::Ban, "Ban"),
+                            (ModerationAction::Mute, "Mute"),
+                            (ModerationAction::Unmute, "Unmute"),
+                        ] {
+                            let clicked = ui
+                                .add_enabled(
+                                    state.moderation_target.is_some(),
+                                    egui::Button::new(label),
+                                )
+                                .clicked();
+                            if let (true, Some(target_user_id)) =
+                                (clicked, state.moderation_target)
+                            {
+                                let reason = state.moderation_reason.trim().to_string();
+                                commands.push(BackendCommand::ModerateMember {
+                                    guild_id,
+                                    target_user_id,
+                                    request: ModerationRequest {
+                                        action,
+                                        reason: (!reason.is_empty()).then_some(reason),
+                                    },
+                                });
+                                state.moderation_reason.clear();
+                            }
+                        }
+                    });
+
+                    ui.separator();
+                    ui.label("Audit log");
+                    ui.horizontal(|ui| {
+                        let selected_text = state
+                            .audit_action_filter
+                            .map(AuditAction::as_str)
+                            .unwrap_or("All actions");
+                        egui::ComboBox::from_id_salt("audit_action_filter")
+                            .selected_text(selected_text)
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut state.audit_action_filter, None, "All actions");
+                                for action in AuditAction::ALL {
+                                    ui.selectable_value(
+                                        &mut state.audit_action_filter,
+                                        Some(action),
+                                        action.as_str(),
+                                    );
+                                }
+                            });
+                        if ui.button("Refresh").clicked() {
+                            commands.push(BackendCommand::LoadAuditLog {
+                                guild_id,
+                                filter: AuditLogFilter {
+                                    limit: Some(AUDIT_LOG_PAGE_SIZE),
+                                    action: state.audit_action_filter,
+                                    ..AuditLogFilter::default()
+                                },
+                            });
+                        }
+                    });
+                    egui::ScrollArea::vertical()
+                        .id_salt("guild_settings_audit_log")
+                        .max_height(220.0)
+                        .show(ui, |ui| {
+                            for entry in &state.audit_entries {
+                                ui.label(format_audit_entry(entry));
+                            }
+                            if state.audit_has_more && ui.button("Load older").clicked() {
+                                commands.push(BackendCommand::LoadAuditLog {
+                                    guild_id,
+                                    filter: AuditLogFilter {
+                                        before: state.audit_entries.last().map(|e| e.entry_id),
+                                        limit: Some(AUDIT_LOG_PAGE_SIZE),
+                                        action: state.audit_action_filter,
+                                        ..AuditLogFilter::default()
+                                    },
+                                });
+                            }
+                        });
+                    ui.separator();
+                }
+
+                ui.label("Create a new guild");
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut state.new_guild_name);
+                    let name = state.new_guild_name.trim().to_string();
+                    if ui
+                        .add_enabled(!name.is_empty(), egui::Button::new("Create guild"))
+                        .clicked()
+                    {
+                        state.new_guild_name.clear();
+                        commands.push(BackendCommand::CreateGuild { name });
+                    }
+                });
+            });
+
+        self.guild_settings.open = open;
+        for command in commands {
+            queue_command(&self.cmd_tx, command, &mut self.status);
+        }
+    }
+
+    fn show_settings_window(&mut self, ctx: &egui::Context) {
+        if !self.settings_open {
+            return;
+        }
+
+        egui::Window::new("Settings")
+            .open(&mut self.settings_open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Theme preset");
+                egui::ComboBox::from_id_salt("theme_preset")
+                    .selected_text(self.theme.preset.label())
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut self.theme.preset,
+                            ThemePreset::DiscordDark,
+                            ThemePreset::DiscordDark.label(),
+                        );
+                        ui.selectable_value(
+                            &mut self.theme.preset,
+                            ThemePreset::DiscordLegacy,
+                            ThemePreset::DiscordLegacy.label(),
+                        );
+                        ui.selectable_value(
+                            &mut self.theme.preset,
+                            ThemePreset::AtomOneDark,
+                            ThemePreset::AtomOneDark.label(),
+                        );
+                        ui.selectable_value(
+                            &mut self.theme.preset,
+                            ThemePreset::EguiLight,
+                            ThemePreset::EguiLight.label(),
+                        );
+                    });
+
+                ui.separator();
+                ui.label("Accent color");
+                ui.color_edit_button_srgba(&mut self.theme.accent_color);
+                ui.small("Used for selected rows, hover emphasis, and primary actions.");
+                ui.add(
+                    egui::Slider::new(&mut self.theme.panel_rounding, 0..=16)
+                        .text("Panel rounding"),
+                );
+                ui.checkbox(
+                    &mut self.theme.list_row_shading,
+                    "Use shaded backgrounds for guild/channel rows",
+                );
+                ui.separator();
+                ui.label("Readability");
+                ui.add(
+                    egui::Slider::new(&mut self.readability.text_scale, 0.8..=1.4)
+                        .text("Text scale")
+                        .step_by(0.05),
+                );
+                ui.checkbox(&mut self.readability.compact_density, "Compact UI density");
+                ui.checkbox(
+                    &mut self.readability.show_timestamps,
+                    "Show message timestamps",
+                );
+                ui.checkbox(
+                    &mut self.readability.message_bubble_backgrounds,
+                    "Show chat message bubble backgrounds",
+                );
 
                 if ui.button("Reset all settings to defaults").clicked() {
                     self.theme = ThemeSettings::discord_default();
                     self.readability = UiReadabilitySettings::defaults();
                 }
+
+                ui.separator();
+                ui.label("Encrypted MLS backup");
+                ui.small(
+                    "Uploads your encryption identity and channel state, sealed with this passphrase, so a new install can restore it.",
+                );
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.backup_passphrase_draft)
+                        .password(true)
+                        .hint_text("Backup passphrase"),
+                );
+                let can_upload = self.auth_session_established
+                    && !self.backup_passphrase_draft.trim().is_empty();
+                if ui
+                    .add_enabled(can_upload, egui::Button::new("Upload encrypted backup"))
+                    .clicked()
+                {
+                    let passphrase = std::mem::take(&mut self.backup_passphrase_draft);
+                    queue_command(
+                        &self.cmd_tx,
+                        BackendCommand::UploadMlsBackup { passphrase },
+                        &mut self.status,
+                    );
+                }
+
+                ui.separator();
+                ui.label("Local MLS database key");
+                if self.local_key_file_in_use {
+                    ui.colored_label(
+                        egui::Color32::YELLOW,
+                        "Local MLS state is unlocked by a key file stored next to the database, so anyone who can read the database can read the key. Set a passphrase below.",
+                    );
+                }
+                ui.small("Re-encrypts local MLS state under a new passphrase.");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.local_passphrase_draft)
+                        .password(true)
+                        .hint_text("New local passphrase"),
+                );
+                let can_rotate =
+                    self.auth_session_established && !self.local_passphrase_draft.is_empty();
+                if ui
+                    .add_enabled(can_rotate, egui::Button::new("Rotate local key"))
+                    .clicked()
+                {
+                    let new_passphrase = std::mem::take(&mut self.local_passphrase_draft);
+                    queue_command(
+                        &self.cmd_tx,
+                        BackendCommand::RotateLocalMlsKey { new_passphrase },
+                        &mut self.status,
+                    );
+                }
             });
     }
 
@@ -1245,6 +2250,43 @@ impl DesktopGuiApp {
                                 );
                                 self.server_url = server_url_buf;
                                 self.username = username_buf;
+
+                                ui.add_space(6.0);
+                                ui.label(egui::RichText::new("Local passphrase").strong());
+                                ui.add_sized(
+                                    [ui.available_width(), 34.0],
+                                    egui::TextEdit::singleline(&mut self.login_ui.local_passphrase)
+                                        .id_salt("login_local_passphrase")
+                                        .password(true)
+                                        .hint_text("Encrypts local MLS state on this device"),
+                                );
+                                ui.checkbox(
+                                    &mut self.login_ui.use_key_file,
+                                    "Use a key file instead of a passphrase",
+                                );
+                                if self.login_ui.use_key_file {
+                                    ui.colored_label(
+                                        egui::Color32::YELLOW,
+                                        "The key file is stored next to the database and does not protect it from anyone who can read your profile directory.",
+                                    );
+                                }
+
+                                ui.add_space(6.0);
+                                ui.checkbox(
+                                    &mut self.login_ui.restore_backup,
+                                    "Restore encrypted MLS backup after sign in",
+                                );
+                                if self.login_ui.restore_backup {
+                                    ui.add_sized(
+                                        [ui.available_width(), 34.0],
+                                        egui::TextEdit::singleline(
+                                            &mut self.login_ui.backup_passphrase,
+                                        )
+                                        .id_salt("login_backup_passphrase")
+                                        .password(true)
+                                        .hint_text("Backup passphrase"),
+                                    );
+                                }
                                 if !self.auth_session_established {
                                     self.display_name_draft = self.username.clone();
                                 }
@@ -1324,6 +2366,31 @@ impl DesktopGuiApp {
             return;
         }
 
+        let restore_passphrase = if self.login_ui.restore_backup {
+            if self.login_ui.backup_passphrase.is_empty() {
+                self.status = "Backup passphrase is required to restore".to_string();
+                self.status_banner = Some(StatusBanner {
+                    severity: StatusBannerSeverity::Error,
+                    message: "Please enter your backup passphrase.".to_string(),
+                });
+                return;
+            }
+            Some(std::mem::take(&mut self.login_ui.backup_passphrase))
+        } else {
+            None
+        };
+
+        if self.login_ui.local_passphrase.is_empty() && !self.login_ui.use_key_file {
+            self.status = "Local passphrase is required".to_string();
+            self.status_banner = Some(StatusBanner {
+                severity: StatusBannerSeverity::Error,
+                message: "Please enter a local passphrase, or choose to use a key file."
+                    .to_string(),
+            });
+            return;
+        }
+        let local_passphrase = std::mem::take(&mut self.login_ui.local_passphrase);
+
         self.auth_session_established = false;
         self.status_banner = None;
         self.display_name_draft = username.clone();
@@ -1332,6 +2399,8 @@ impl DesktopGuiApp {
             BackendCommand::Login {
                 server_url: server,
                 username,
+                local_passphrase: (!local_passphrase.is_empty()).then_some(local_passphrase),
+                restore_passphrase,
             },
             &mut self.status,
         );
@@ -1887,13 +2956,13 @@ impl DesktopGuiApp {
 
         ui.painter().rect_filled(
             rect,
-            egui::CornerRadius::same(u8::from(self.theme.panel_rounding)),
+            egui::CornerRadius::same(self.theme.panel_rounding),
             row_fill,
         );
         if row_stroke != egui::Stroke::NONE {
             ui.painter().rect_stroke(
                 rect,
-                egui::CornerRadius::same(u8::from(self.theme.panel_rounding)),
+                egui::CornerRadius::same(self.theme.panel_rounding),
                 row_stroke,
                 egui::StrokeKind::Middle,
             );
@@ -2038,6 +3107,61 @@ impl DesktopGuiApp {
 
                                                 ui.add_space(6.0);
                                             }
+
+                                            ui.separator();
+                                            ui.label("Direct Messages");
+                                            ui.add(
+                                                egui::TextEdit::singleline(
+                                                    &mut self.new_direct_usernames,
+                                                )
+                                                .hint_text("alice, bob"),
+                                            );
+                                            let usernames: Vec<String> = self
+                                                .new_direct_usernames
+                                                .split(',')
+                                                .map(|name| name.trim().to_string())
+                                                .filter(|name| !name.is_empty())
+                                                .collect();
+                                            if ui
+                                                .add_enabled(
+                                                    !usernames.is_empty(),
+                                                    egui::Button::new("Message"),
+                                                )
+                                                .clicked()
+                                            {
+                                                self.new_direct_usernames.clear();
+                                                queue_command(
+                                                    &self.cmd_tx,
+                                                    BackendCommand::CreateDirectChannel {
+                                                        usernames,
+                                                    },
+                                                    &mut self.status,
+                                                );
+                                            }
+                                            ui.add_space(6.0);
+
+                                            let mut clicked_direct = None;
+                                            for direct in &self.direct_channels {
+                                                let channel_id = direct.channel.channel_id;
+                                                let label = format!(
+                                                    "@  {}",
+                                                    self.direct_channel_label(direct)
+                                                );
+                                                let response = self.render_nav_row(
+                                                    ui,
+                                                    &label,
+                                                    style.layout.channel_row_height,
+                                                    self.selected_channel == Some(channel_id),
+                                                    discord_dark,
+                                                );
+                                                if response.clicked() {
+                                                    clicked_direct = Some(channel_id);
+                                                }
+                                                ui.add_space(6.0);
+                                            }
+                                            if let Some(channel_id) = clicked_direct {
+                                                self.select_direct_channel(channel_id);
+                                            }
                                         });
                                     });
 
@@ -2108,6 +3232,7 @@ impl DesktopGuiApp {
         let icon = match kind {
             ChannelKind::Text => "#",
             ChannelKind::Voice => "🔊",
+            ChannelKind::Direct => "@",
         };
         let selected = self.selected_channel == Some(channel_id);
 
@@ -2187,10 +3312,51 @@ impl DesktopGuiApp {
                             } else {
                                 ui.label("Loading members for selected guild…");
                             }
+                        } else if let Some(direct) = self.selected_direct_channel() {
+                            for participant in &direct.participants {
+                                ui.label(participant.username.as_str());
+                            }
                         } else {
                             ui.label("Select a guild to view members.");
                         }
 
+                        if let Some(channel_id) = self.selected_text_channel_id() {
+                            ui.separator();
+                            ui.add_space(style.layout.section_vertical_gap);
+                            ui.horizontal(|ui| {
+                                ui.heading("Threads");
+                                ui.checkbox(&mut self.thread_pane.show_archived, "Archived");
+                                if ui.button("Refresh").clicked() {
+                                    queue_command(
+                                        &self.cmd_tx,
+                                        BackendCommand::ListThreads { channel_id },
+                                        &mut self.status,
+                                    );
+                                }
+                            });
+                            let threads: Vec<ThreadSummary> = self
+                                .thread_pane
+                                .threads
+                                .get(&channel_id)
+                                .into_iter()
+                                .flatten()
+                                .filter(|thread| self.thread_pane.show_archived || !thread.archived)
+                                .cloned()
+                                .collect();
+                            if threads.is_empty() {
+                                ui.weak("No threads in this channel.");
+                            }
+                            for thread in threads {
+                                let open = self.thread_pane.open == Some(thread.thread_id);
+                                if ui
+                                    .selectable_label(open, thread_button_label(&thread))
+                                    .clicked()
+                                {
+                                    self.open_thread(thread.thread_id);
+                                }
+                            }
+                        }
+
                         ui.separator();
                         ui.add_space(style.layout.section_vertical_gap);
                         ui.heading("Voice");
@@ -2362,6 +3528,16 @@ impl DesktopGuiApp {
                                         }
                                     }
 
+                                    if ui
+                                        .add_enabled(
+                                            self.auth_session_established,
+                                            egui::Button::new("Guild Settings…"),
+                                        )
+                                        .clicked()
+                                    {
+                                        self.open_guild_settings();
+                                    }
+
                                     if !create_enabled {
                                         ui.add_space(8.0);
                                         ui.label(
@@ -2444,17 +3620,142 @@ impl DesktopGuiApp {
             });
     }
 
-    fn show_main_workspace(&mut self, ctx: &egui::Context) {
+    /// Side pane for the open thread: its replies, archive controls and a reply box.
+    fn show_thread_side_panel(&mut self, ctx: &egui::Context, style: MainWorkspaceStyle) {
+        let Some(thread) = self.open_thread_summary().cloned() else {
+            return;
+        };
+        let thread_id = thread.thread_id;
+        egui::SidePanel::right("thread_panel")
+            .default_width(style.layout.members_panel_width * 1.5)
+            .show(ctx, |ui| {
+                egui::Frame::NONE
+                    .fill(style.colors.members_bg)
+                    .inner_margin(egui::Margin::symmetric(
+                        style.layout.toolbar_h_padding as i8,
+                        style.layout.toolbar_v_padding as i8,
+                    ))
+                    .show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.heading(format!("🧵 {}", thread.name));
+                            if ui.small_button("✕").on_hover_text("Close thread").clicked() {
+                                self.thread_pane.open = None;
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            let archive_label = if thread.archived {
+                                "Unarchive"
+                            } else {
+                                "Archive"
+                            };
+                            if ui.button(archive_label).clicked() {
+                                queue_command(
+                                    &self.cmd_tx,
+                                    BackendCommand::SetThreadArchived {
+                                        thread_id,
+                                        archived: !thread.archived,
+                                    },
+                                    &mut self.status,
+                                );
+                            }
+                            if ui.button("Load older").clicked() {
+                                let before = self
+                                    .thread_pane
+                                    .messages
+                                    .get(&thread_id)
+                                    .and_then(|messages| messages.first())
+                                    .map(|message| message.wire.message_id);
+                                if before.is_some() {
+                                    queue_command(
+                                        &self.cmd_tx,
+                                        BackendCommand::LoadThreadMessages { thread_id, before },
+                                        &mut self.status,
+                                    );
+                                }
+                            }
+                        });
+                        if thread.archived {
+                            ui.weak("This thread is archived; unarchive it to reply.");
+                        }
+                        ui.separator();
+
+                        let messages = self
+                            .thread_pane
+                            .messages
+                            .get(&thread_id)
+                            .cloned()
+                            .unwrap_or_default();
+                        egui::ScrollArea::vertical()
+                            .max_height((ui.available_height() - 48.0).max(80.0))
+                            .stick_to_bottom(true)
+                            .show(ui, |ui| {
+                                if messages.is_empty() {
+                                    ui.weak("No replies yet.");
+                                }
+                                for msg in &messages {
+                                    let sender_display = msg
+                                        .wire
+                                        .sender_username
+                                        .clone()
+                                        .or_else(|| {
+                                            self.sender_directory
+                                                .get(&msg.wire.sender_id.0)
+                                                .cloned()
+                                        })
+                                        .unwrap_or_else(|| msg.wire.sender_id.0.to_string());
+                                    ui.label(egui::RichText::new(sender_display).strong());
+                                    ui.label(&msg.plaintext);
+                                    ui.add_space(6.0);
+                                }
+                            });
+
+                        ui.separator();
+                        ui.add_enabled_ui(
+                            !thread.archived && self.auth_session_established,
+                            |ui| {
+                                ui.horizontal(|ui| {
+                                    let response = ui.add(
+                                        egui::TextEdit::singleline(&mut self.thread_pane.composer)
+                                            .id_salt("thread_composer")
+                                            .hint_text("Reply in thread")
+                                            .desired_width((ui.available_width() - 64.0).max(64.0)),
+                                    );
+                                    let submitted = response.lost_focus()
+                                        && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                                    if (ui.button("Send").clicked() || submitted)
+                                        && !self.thread_pane.composer.trim().is_empty()
+                                    {
+                                        let text = std::mem::take(&mut self.thread_pane.composer)
+                                            .trim_end_matches('\n')
+                                            .to_string();
+                                        queue_command(
+                                            &self.cmd_tx,
+                                            BackendCommand::SendThreadMessage { thread_id, text },
+                                            &mut self.status,
+                                        );
+                                        response.request_focus();
+                                    }
+                                });
+                            },
+                        );
+                    });
+            });
+    }
+
+    fn show_main_workspace(&mut self, ctx: &egui::Context) {
         let style = self.main_workspace_style(ctx);
 
         self.show_top_bar(ctx, style);
 
         self.show_settings_window(ctx);
+        self.show_guild_settings_window(ctx);
 
         self.show_left_navigation_panel(ctx, style);
 
         self.show_members_side_panel(ctx, style);
 
+        self.show_thread_side_panel(ctx, style);
+
         egui::TopBottomPanel::bottom("composer_panel")
             .resizable(true)
             // pick a sane starting height once; egui will persist the resized height internally
@@ -2524,6 +3825,51 @@ impl DesktopGuiApp {
                         }
                     });
 
+                    if let Some(channel_id) = self.selected_channel {
+                        let current = self.message_timers.get(&channel_id).copied();
+                        let mut selected = current;
+                        ui.horizontal(|ui| {
+                            ui.small("⏳ Disappearing messages");
+                            egui::ComboBox::from_id_salt("message_timer")
+                                .selected_text(message_timer_label(selected))
+                                .show_ui(ui, |ui| {
+                                    for (seconds, label) in MESSAGE_TIMER_OPTIONS {
+                                        ui.selectable_value(&mut selected, seconds, label);
+                                    }
+                                });
+                        });
+                        if selected != current {
+                            match selected {
+                                Some(seconds) => self.message_timers.insert(channel_id, seconds),
+                                None => self.message_timers.remove(&channel_id),
+                            };
+                            queue_command(
+                                &self.cmd_tx,
+                                BackendCommand::SetMessageExpiry {
+                                    channel_id,
+                                    expires_in_seconds: selected,
+                                },
+                                &mut self.status,
+                            );
+                        }
+                    }
+
+                    for progress in self.upload_progress.values() {
+                        let fraction = if progress.total_bytes == 0 {
+                            1.0
+                        } else {
+                            progress.sent_bytes as f32 / progress.total_bytes as f32
+                        };
+                        ui.add(
+                            egui::ProgressBar::new(fraction)
+                                .text(format!(
+                                    "Uploading {} ({:.0}%)",
+                                    progress.filename,
+                                    fraction * 100.0
+                                )),
+                        );
+                    }
+
                     // If attachment previews can be tall, keep them from forcing layout thrash:
                     // show them in a small scroll area inside the panel.
                     if let Some(path) = self.pending_attachment.clone() {
@@ -2620,9 +3966,19 @@ impl DesktopGuiApp {
                 egui::ScrollArea::vertical().show(ui, |ui| {
                     ui.add_space(style.layout.toolbar_h_padding);
                     let mut hovered_message = None;
+                    let mut thread_to_open = None;
                     if let Some(channel_id) = self.selected_channel {
+                        let threads = self
+                            .thread_pane
+                            .threads
+                            .get(&channel_id)
+                            .cloned()
+                            .unwrap_or_default();
                         if let Some(messages) = self.messages.get(&channel_id).cloned() {
                             for msg in &messages {
+                                let thread = threads
+                                    .iter()
+                                    .find(|thread| thread.parent_message_id == msg.wire.message_id);
                                 let sender_display = msg
                                     .wire
                                     .sender_username
@@ -2679,8 +4035,58 @@ impl DesktopGuiApp {
                                                                     .weak(),
                                                             );
                                                         }
+                                                        if let Some(expires_at) =
+                                                            msg.wire.expires_at
+                                                        {
+                                                            ui.label(
+                                                                egui::RichText::new(format!(
+                                                                    "⏳ {}",
+                                                                    expiry_label(
+                                                                        expires_at,
+                                                                        Utc::now()
+                                                                    )
+                                                                ))
+                                                                .small()
+                                                                .weak(),
+                                                            )
+                                                            .on_hover_text(
+                                                                "Disappearing message",
+                                                            );
+                                                        }
+                                                        if thread.is_none()
+                                                            && self.hovered_message
+                                                                == Some(msg.wire.message_id)
+                                                            && ui
+                                                                .small_button("🧵 Thread")
+                                                                .on_hover_text(
+                                                                    "Start a thread on this message",
+                                                                )
+                                                                .clicked()
+                                                        {
+                                                            queue_command(
+                                                                &self.cmd_tx,
+                                                                BackendCommand::CreateThread {
+                                                                    channel_id,
+                                                                    parent_message_id: msg
+                                                                        .wire
+                                                                        .message_id,
+                                                                    name: default_thread_name(
+                                                                        &msg.plaintext,
+                                                                    ),
+                                                                },
+                                                                &mut self.status,
+                                                            );
+                                                        }
                                                     });
                                                     ui.label(&msg.plaintext);
+                                                    if let Some(thread) = thread {
+                                                        if ui
+                                                            .small_button(thread_button_label(thread))
+                                                            .clicked()
+                                                        {
+                                                            thread_to_open = Some(thread.thread_id);
+                                                        }
+                                                    }
                                                     if let Some(attachment) = &msg.wire.attachment {
                                                         if attachment_is_image(attachment) {
                                                             self.render_image_attachment_preview(
@@ -2700,7 +4106,7 @@ impl DesktopGuiApp {
                                                                     queue_command(
                                                                         &self.cmd_tx,
                                                                         BackendCommand::DownloadAttachment {
-                                                                            file_id: attachment.file_id,
+                                                                            file_id: attachment.file_id.clone(),
                                                                             filename: attachment
                                                                                 .filename
                                                                                 .clone(),
@@ -2738,6 +4144,9 @@ impl DesktopGuiApp {
                         }
                     }
                     self.hovered_message = hovered_message;
+                    if let Some(thread_id) = thread_to_open {
+                        self.open_thread(thread_id);
+                    }
                 });
 
                 if !self.auth_session_established {
@@ -2759,7 +4168,7 @@ impl DesktopGuiApp {
     ) {
         let state = self
             .attachment_previews
-            .entry(attachment.file_id)
+            .entry(attachment.file_id.clone())
             .or_insert(AttachmentPreviewState::NotRequested);
 
         if matches!(state, AttachmentPreviewState::NotRequested) {
@@ -2767,7 +4176,7 @@ impl DesktopGuiApp {
             queue_command(
                 &self.cmd_tx,
                 BackendCommand::FetchAttachmentPreview {
-                    file_id: attachment.file_id,
+                    file_id: attachment.file_id.clone(),
                 },
                 &mut self.status,
             );
@@ -2775,6 +4184,22 @@ impl DesktopGuiApp {
 
         match state {
             AttachmentPreviewState::Loading => {
+                if let Some((preview, texture)) =
+                    self.image_placeholders.get_mut(&attachment.file_id)
+                {
+                    let texture = texture.get_or_insert_with(|| {
+                        ui.ctx().load_texture(
+                            format!("attachment_placeholder_{}", attachment.file_id.0),
+                            blurhash_placeholder(preview),
+                            egui::TextureOptions::LINEAR,
+                        )
+                    });
+                    let size = placeholder_size(
+                        preview,
+                        (ui.available_width() * 0.75).clamp(120.0, 380.0),
+                    );
+                    ui.add(egui::Image::new(&*texture).fit_to_exact_size(size));
+                }
                 ui.horizontal(|ui| {
                     ui.spinner();
                     ui.label(format!("Loading preview for {}…", attachment.filename));
@@ -2830,7 +4255,7 @@ impl DesktopGuiApp {
                         );
 
                     if response.clicked() {
-                        self.expanded_preview = Some(attachment.file_id);
+                        self.expanded_preview = Some(attachment.file_id.clone());
                     }
 
                     let metadata = format!(
@@ -2844,7 +4269,7 @@ impl DesktopGuiApp {
                         self.render_image_context_menu(
                             ui,
                             &attachment.filename,
-                            Some(full_bytes.as_slice()),
+                            full_bytes.as_deref(),
                             preview_bytes.as_deref(),
                             None,
                             Some(&metadata),
@@ -2874,7 +4299,7 @@ impl DesktopGuiApp {
                 queue_command(
                     &self.cmd_tx,
                     BackendCommand::DownloadAttachment {
-                        file_id: attachment.file_id,
+                        file_id: attachment.file_id.clone(),
                         filename: attachment.filename.clone(),
                     },
                     &mut self.status,
@@ -2884,7 +4309,7 @@ impl DesktopGuiApp {
     }
 
     fn render_expanded_preview_window(&mut self, ctx: &egui::Context) {
-        let Some(file_id) = self.expanded_preview else {
+        let Some(file_id) = self.expanded_preview.clone() else {
             return;
         };
 
@@ -2914,6 +4339,21 @@ impl DesktopGuiApp {
     }
 }
 
+/// "1.2 MB of 10.0 GB", plus bytes still being uploaded.
+fn format_storage_usage(usage: &StorageUsageSummary) -> String {
+    let quota = usage
+        .quota_bytes
+        .map_or_else(|| "no limit".to_string(), human_readable_bytes);
+    let mut text = format!("{} of {quota}", human_readable_bytes(usage.used_bytes));
+    if usage.reserved_bytes > 0 {
+        text.push_str(&format!(
+            " ({} uploading)",
+            human_readable_bytes(usage.reserved_bytes)
+        ));
+    }
+    text
+}
+
 fn human_readable_bytes(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
@@ -2991,6 +4431,26 @@ fn write_clipboard_image(rgba: &[u8], width: usize, height: usize) -> Result<(),
         .map_err(|err| err.to_string())
 }
 
+/// Blurhash placeholders are smooth gradients, so a few pixels stretched to size suffice.
+fn blurhash_placeholder(preview: &client_core::AttachmentPreview) -> egui::ColorImage {
+    const WIDTH: u32 = 32;
+    let height = (WIDTH * preview.height / preview.width.max(1)).clamp(1, 64);
+    let rgba = preview
+        .placeholder_rgba(WIDTH, height)
+        .unwrap_or_else(|| vec![96; (WIDTH * height * 4) as usize]);
+    egui::ColorImage::from_rgba_unmultiplied([WIDTH as usize, height as usize], &rgba)
+}
+
+/// The on-screen size of an image preview, using the same limits as loaded previews.
+fn placeholder_size(preview: &client_core::AttachmentPreview, max_width: f32) -> egui::Vec2 {
+    let mut size = egui::vec2(preview.width.max(1) as f32, preview.height.max(1) as f32);
+    if size.x > max_width {
+        size *= max_width / size.x;
+    }
+    size.y = size.y.min(260.0);
+    size
+}
+
 fn decode_preview_image(bytes: &[u8]) -> Result<PreviewImage, String> {
     let dynamic = image::load_from_memory(bytes).map_err(|err| err.to_string())?;
     let resized = dynamic.thumbnail(1024, 1024).to_rgba8();
@@ -3006,6 +4466,8 @@ fn decode_preview_image(bytes: &[u8]) -> Result<PreviewImage, String> {
 fn queue_command(cmd_tx: &Sender<BackendCommand>, cmd: BackendCommand, status: &mut String) {
     let cmd_name = match &cmd {
         BackendCommand::Login { .. } => "login",
+        BackendCommand::UploadMlsBackup { .. } => "upload_mls_backup",
+        BackendCommand::RotateLocalMlsKey { .. } => "rotate_local_mls_key",
         BackendCommand::ListGuilds => "list_guilds",
         BackendCommand::ListChannels { .. } => "list_channels",
         BackendCommand::ListMembers { .. } => "list_members",
@@ -3015,7 +4477,28 @@ fn queue_command(cmd_tx: &Sender<BackendCommand>, cmd: BackendCommand, status: &
         BackendCommand::DownloadAttachment { .. } => "download_attachment",
         BackendCommand::FetchAttachmentPreview { .. } => "fetch_attachment_preview",
         BackendCommand::CreateInvite { .. } => "create_invite",
+        BackendCommand::CreateGuild { .. } => "create_guild",
+        BackendCommand::RenameGuild { .. } => "rename_guild",
+        BackendCommand::DeleteGuild { .. } => "delete_guild",
+        BackendCommand::LeaveGuild { .. } => "leave_guild",
+        BackendCommand::TransferOwnership { .. } => "transfer_ownership",
+        BackendCommand::ModerateMember { .. } => "moderate_member",
+        BackendCommand::LoadAuditLog { .. } => "load_audit_log",
+        BackendCommand::LoadStorageUsage { .. } => "load_storage_usage",
+        BackendCommand::CreateChannel { .. } => "create_channel",
+        BackendCommand::UpdateChannel { .. } => "update_channel",
+        BackendCommand::SetChannelRetention { .. } => "set_channel_retention",
+        BackendCommand::SetMessageExpiry { .. } => "set_message_expiry",
+        BackendCommand::ReorderChannels { .. } => "reorder_channels",
+        BackendCommand::DeleteChannel { .. } => "delete_channel",
         BackendCommand::JoinWithInvite { .. } => "join_with_invite",
+        BackendCommand::ListDirectChannels => "list_direct_channels",
+        BackendCommand::CreateDirectChannel { .. } => "create_direct_channel",
+        BackendCommand::ListThreads { .. } => "list_threads",
+        BackendCommand::CreateThread { .. } => "create_thread",
+        BackendCommand::SetThreadArchived { .. } => "set_thread_archived",
+        BackendCommand::LoadThreadMessages { .. } => "load_thread_messages",
+        BackendCommand::SendThreadMessage { .. } => "send_thread_message",
         BackendCommand::ConnectVoice { .. } => "connect_voice",
         BackendCommand::DisconnectVoice => "disconnect_voice",
     };
@@ -3044,6 +4527,7 @@ fn queue_command(cmd_tx: &Sender<BackendCommand>, cmd: BackendCommand, status: &
 }
 
 #[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
 struct DiscordDarkPalette {
     // Backgrounds:
     app_background: egui::Color32,
@@ -3217,6 +4701,9 @@ impl eframe::App for DesktopGuiApp {
         self.tick = self.tick.wrapping_add(1);
 
         self.process_ui_events();
+        if self.tick.is_multiple_of(10) {
+            self.drop_expired_messages(Utc::now());
+        }
         self.apply_theme_if_needed(ctx);
 
         match self.view_state {
@@ -3328,8 +4815,12 @@ fn resolve_user_mls_data_dir(base_dir: &std::path::Path, username: &str, user_id
         .join(format!("user_{user_id}"))
 }
 
-async fn fetch_user_id_for_login(server_url: &str, username: &str) -> Result<i64, String> {
-    let response = HttpClient::new()
+async fn fetch_user_id_for_login(
+    http: &HttpClient,
+    server_url: &str,
+    username: &str,
+) -> Result<i64, String> {
+    let response = http
         .post(format!("{server_url}/login"))
         .json(&serde_json::json!({ "username": username }))
         .send()
@@ -3348,15 +4839,109 @@ async fn fetch_user_id_for_login(server_url: &str, username: &str) -> Result<i64
 fn guild_id_from_invite(invite_code: &str) -> Option<GuildId> {
     let decoded = URL_SAFE_NO_PAD.decode(invite_code.as_bytes()).ok()?;
     let decoded_text = String::from_utf8(decoded).ok()?;
-    let guild_id = decoded_text.strip_prefix("guild:")?.parse::<i64>().ok()?;
+    let rest = decoded_text.strip_prefix("guild:")?;
+    let guild_id = rest.split(':').next()?.parse::<i64>().ok()?;
     Some(GuildId(guild_id))
 }
 
+/// Names a new thread after the first line of its parent message.
+fn default_thread_name(plaintext: &str) -> String {
+    let first_line = plaintext.lines().next().unwrap_or_default().trim();
+    if first_line.is_empty() {
+        "Thread".to_string()
+    } else {
+        first_line.chars().take(48).collect()
+    }
+}
+
+fn thread_button_label(thread: &ThreadSummary) -> String {
+    let archived = if thread.archived { " · archived" } else { "" };
+    format!(
+        "🧵 {} ({} {}){archived}",
+        thread.name,
+        thread.message_count,
+        if thread.message_count == 1 {
+            "reply"
+        } else {
+            "replies"
+        }
+    )
+}
+
+fn message_timer_label(expires_in_seconds: Option<u64>) -> &'static str {
+    MESSAGE_TIMER_OPTIONS
+        .iter()
+        .find(|(seconds, _)| *seconds == expires_in_seconds)
+        .map(|(_, label)| *label)
+        .unwrap_or("Custom")
+}
+
+/// Time left on a disappearing message, rounded down to its largest unit.
+fn expiry_label(expires_at: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let seconds = (expires_at - now).num_seconds().max(0);
+    match seconds {
+        s if s >= 86_400 => format!("{}d", s / 86_400),
+        s if s >= 3600 => format!("{}h", s / 3600),
+        s if s >= 60 => format!("{}m", s / 60),
+        s => format!("{s}s"),
+    }
+}
+
+/// One audit log line: when, who, what, to whom, and why.
+fn format_audit_entry(entry: &AuditLogEntry) -> String {
+    let actor = entry
+        .actor_username
+        .clone()
+        .unwrap_or_else(|| format!("user {}", entry.actor_id.0));
+    let mut line = format!(
+        "{} {actor} {}",
+        entry.created_at.format("%Y-%m-%d %H:%M"),
+        entry.action.as_str()
+    );
+    if let Some(target) = entry
+        .target_username
+        .clone()
+        .or_else(|| entry.target_user_id.map(|id| format!("user {}", id.0)))
+    {
+        line.push_str(&format!(" → {target}"));
+    }
+    if let Some(channel_id) = entry.target_channel_id {
+        line.push_str(&format!(" in channel {}", channel_id.0));
+    }
+    if let Some(details) = &entry.details {
+        line.push_str(&format!(" ({details})"));
+    }
+    if let Some(reason) = &entry.reason {
+        line.push_str(&format!(": {reason}"));
+    }
+    line
+}
+
+/// The key file is only used when the user opted out of a passphrase at sign in; it sits
+/// next to the database it unlocks.
+fn local_mls_key_source(
+    user_mls_state_dir: &std::path::Path,
+    passphrase: Option<String>,
+) -> MlsAtRestKeySource {
+    match passphrase {
+        Some(passphrase) => MlsAtRestKeySource::Passphrase(passphrase),
+        None => MlsAtRestKeySource::KeyFile(user_mls_state_dir.join("mls_state.key")),
+    }
+}
+
 async fn build_user_scoped_mls_client(
     base_dir: &std::path::Path,
     username: &str,
     user_id: i64,
-) -> Result<Arc<RealtimeClient<PassthroughCrypto>>, String> {
+    local_passphrase: Option<String>,
+    server_trust: &ServerTrust,
+) -> Result<
+    (
+        Arc<RealtimeClient<PassthroughCrypto>>,
+        Arc<DurableMlsSessionManager>,
+    ),
+    String,
+> {
     let profile_dir = resolve_user_profile_data_dir(base_dir, username);
     let user_mls_state_dir = resolve_user_mls_data_dir(base_dir, username, user_id);
 
@@ -3386,7 +4971,10 @@ async fn build_user_scoped_mls_client(
     let mls_db_url = DurableMlsSessionManager::sqlite_url_for_gui_data_dir(&user_mls_state_dir);
     let device_id = desktop_gui_device_id_for_username(username);
 
-    let mls_manager = DurableMlsSessionManager::initialize(&mls_db_url, user_id, &device_id)
+    let key_source = local_mls_key_source(&user_mls_state_dir, local_passphrase);
+
+    let mls_manager =
+        DurableMlsSessionManager::initialize_encrypted(&mls_db_url, user_id, &device_id, &key_source)
         .await
         .map_err(|err| {
             format!(
@@ -3395,13 +4983,17 @@ async fn build_user_scoped_mls_client(
             )
         })?;
 
-    Ok(RealtimeClient::new_with_mls_session_manager(
-        PassthroughCrypto,
-        mls_manager,
-    ))
+    let client =
+        RealtimeClient::new_with_server_trust(PassthroughCrypto, mls_manager.clone(), server_trust)
+            .map_err(|err| format!("invalid server TLS trust settings: {err:#}"))?;
+    Ok((client, mls_manager))
 }
 
-fn spawn_backend_thread(cmd_rx: Receiver<BackendCommand>, ui_tx: Sender<UiEvent>) {
+fn spawn_backend_thread(
+    cmd_rx: Receiver<BackendCommand>,
+    ui_tx: Sender<UiEvent>,
+    server_trust: anyhow::Result<ServerTrust>,
+) {
     thread::spawn(move || {
         let _ = ui_tx.try_send(UiEvent::Info("Backend worker starting...".to_string()));
         let runtime = match tokio::runtime::Builder::new_multi_thread()
@@ -3420,6 +5012,20 @@ fn spawn_backend_thread(cmd_rx: Receiver<BackendCommand>, ui_tx: Sender<UiEvent>
         };
 
         runtime.block_on(async move {
+            let (login_http, server_trust) =
+                match server_trust.and_then(|trust| Ok((trust.http_client()?, trust))) {
+                    Ok(built) => built,
+                    Err(err) => {
+                        let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                            UiErrorContext::BackendStartup,
+                            format!(
+                                "backend worker startup failure: invalid server TLS trust settings: {err:#}"
+                            ),
+                        )));
+                        tracing::error!("invalid server TLS trust settings: {err:#}");
+                        return;
+                    }
+                };
             let mls_state_dir = match resolve_mls_gui_data_dir() {
                 Ok(path) => path,
                 Err(attempted) => {
@@ -3464,14 +5070,17 @@ fn spawn_backend_thread(cmd_rx: Receiver<BackendCommand>, ui_tx: Sender<UiEvent>
             let mut client = RealtimeClient::new(PassthroughCrypto);
             let _ = ui_tx.try_send(UiEvent::Info("Backend worker ready".to_string()));
 
+            let mut mls_manager: Option<(Arc<DurableMlsSessionManager>, PathBuf)> = None;
             let mut event_task: Option<tokio::task::JoinHandle<()>> = None;
             while let Ok(cmd) = cmd_rx.recv() {
                 match cmd {
                     BackendCommand::Login {
                         server_url,
                         username,
+                        local_passphrase,
+                        restore_passphrase,
                     } => {
-                        let user_id = match fetch_user_id_for_login(&server_url, &username).await {
+                        let user_id = match fetch_user_id_for_login(&login_http, &server_url, &username).await {
                             Ok(user_id) => user_id,
                             Err(err) => {
                                 let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
@@ -3482,8 +5091,17 @@ fn spawn_backend_thread(cmd_rx: Receiver<BackendCommand>, ui_tx: Sender<UiEvent>
                             }
                         };
 
-                        let rebound_client = match build_user_scoped_mls_client(&mls_state_dir, &username, user_id).await {
-                            Ok(client) => client,
+                        let key_file_in_use = local_passphrase.is_none();
+                        let (rebound_client, rebound_manager) = match build_user_scoped_mls_client(
+                            &mls_state_dir,
+                            &username,
+                            user_id,
+                            local_passphrase,
+                            &server_trust,
+                        )
+                        .await
+                        {
+                            Ok(built) => built,
                             Err(err) => {
                                 let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
                                     UiErrorContext::Login,
@@ -3521,6 +5139,23 @@ fn spawn_backend_thread(cmd_rx: Receiver<BackendCommand>, ui_tx: Sender<UiEvent>
                                         channel_id,
                                         participants,
                                     },
+                                    ClientEvent::UploadProgress {
+                                        upload_id,
+                                        filename,
+                                        sent_bytes,
+                                        total_bytes,
+                                    } => UiEvent::UploadProgress {
+                                        upload_id,
+                                        filename,
+                                        sent_bytes,
+                                        total_bytes,
+                                    },
+                                    ClientEvent::UploadFinished { upload_id } => {
+                                        UiEvent::UploadFinished { upload_id }
+                                    }
+                                    ClientEvent::AttachmentPreviewAvailable(preview) => {
+                                        UiEvent::AttachmentPreviewAvailable(preview)
+                                    }
                                     ClientEvent::Error(err) => UiEvent::Error(
                                         UiError::from_message(UiErrorContext::DecryptMessage, err),
                                     ),
@@ -3529,9 +5164,26 @@ fn spawn_backend_thread(cmd_rx: Receiver<BackendCommand>, ui_tx: Sender<UiEvent>
                             }
                         }));
 
+                        let _ = ui_tx.try_send(UiEvent::LocalMlsKeyFileInUse(key_file_in_use));
                         client = rebound_client;
-                        match client.login(&server_url, &username, "").await {
-                            Ok(()) => {
+                        mls_manager = Some((
+                            rebound_manager,
+                            resolve_user_mls_data_dir(&mls_state_dir, &username, user_id),
+                        ));
+                        let login_result = match restore_passphrase {
+                            Some(passphrase) => client
+                                .login_with_mls_backup(&server_url, &username, &passphrase)
+                                .await
+                                .map(Some),
+                            None => client.login(&server_url, &username, "").await.map(|()| None),
+                        };
+                        match login_result {
+                            Ok(restored) => {
+                                if let Some(restored) = restored {
+                                    let _ = ui_tx.try_send(UiEvent::Info(format!(
+                                        "Restored {restored} channel group(s) from encrypted backup"
+                                    )));
+                                }
                                 let _ = ui_tx.try_send(UiEvent::LoginOk);
                             }
                             Err(err) => {
@@ -3542,6 +5194,46 @@ fn spawn_backend_thread(cmd_rx: Receiver<BackendCommand>, ui_tx: Sender<UiEvent>
                             }
                         }
                     }
+                    BackendCommand::UploadMlsBackup { passphrase } => {
+                        match client.upload_mls_backup(&passphrase).await {
+                            Ok(()) => {
+                                let _ = ui_tx.try_send(UiEvent::Info(
+                                    "Encrypted MLS backup uploaded".to_string(),
+                                ));
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    format!("MLS backup upload failed: {err}"),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::RotateLocalMlsKey { new_passphrase } => {
+                        let Some((manager, user_mls_state_dir)) = mls_manager.as_ref() else {
+                            let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                UiErrorContext::General,
+                                "sign in before rotating the local MLS key".to_string(),
+                            )));
+                            continue;
+                        };
+                        let key_source =
+                            local_mls_key_source(user_mls_state_dir, Some(new_passphrase));
+                        match manager.rotate_at_rest_key(&key_source).await {
+                            Ok(()) => {
+                                let _ = ui_tx.try_send(UiEvent::LocalMlsKeyFileInUse(false));
+                                let _ = ui_tx.try_send(UiEvent::Info(
+                                    "Local MLS database key rotated".to_string(),
+                                ));
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    format!("local MLS key rotation failed: {err:#}"),
+                                )));
+                            }
+                        }
+                    }
                     BackendCommand::ListGuilds => {
                         tracing::info!("backend: list_guilds");
                         if let Err(err) = client.list_guilds().await {
@@ -3580,6 +5272,11 @@ fn spawn_backend_thread(cmd_rx: Receiver<BackendCommand>, ui_tx: Sender<UiEvent>
                                 UiErrorContext::General,
                                 err.to_string(),
                             )));
+                        } else if let Ok(threads) = client.list_threads(channel_id, true).await {
+                            let _ = ui_tx.try_send(UiEvent::ThreadsLoaded {
+                                channel_id,
+                                threads,
+                            });
                         }
                     }
                     BackendCommand::LoadMoreMessages { channel_id, before } => {
@@ -3617,7 +5314,7 @@ fn spawn_backend_thread(cmd_rx: Receiver<BackendCommand>, ui_tx: Sender<UiEvent>
                                             AttachmentUpload {
                                                 filename,
                                                 mime_type,
-                                                ciphertext: bytes,
+                                                bytes,
                                             },
                                         )
                                         .await
@@ -3637,19 +5334,23 @@ fn spawn_backend_thread(cmd_rx: Receiver<BackendCommand>, ui_tx: Sender<UiEvent>
                         }
                     }
                     BackendCommand::FetchAttachmentPreview { file_id } => {
-                        match client.download_file(file_id).await {
-                            Ok(bytes) => {
-                                // `bytes` might be Vec<u8>, bytes::Bytes, or something slice-like.
-                                // Make an owned Vec<u8> once, use it for both decode + storage.
-                                let slice: &[u8] = bytes.as_ref();
-                                let owned: Vec<u8> = slice.to_vec();
-
+                        // Prefer the small thumbnail; older messages only have the original.
+                        let fetched = match client.download_thumbnail(file_id.clone()).await {
+                            Ok(Some(thumbnail)) => Ok((thumbnail, false)),
+                            Ok(None) => client
+                                .download_file(file_id.clone())
+                                .await
+                                .map(|bytes| (bytes, true)),
+                            Err(err) => Err(err),
+                        };
+                        match fetched {
+                            Ok((owned, is_original)) => {
                                 match decode_preview_image(&owned) {
                                     Ok(image) => {
                                         let _ = ui_tx.try_send(UiEvent::AttachmentPreviewLoaded {
                                             file_id,
                                             image,
-                                            original_bytes: owned,
+                                            original_bytes: is_original.then_some(owned),
                                         });
                                     }
                                     Err(err) => {
@@ -3713,6 +5414,307 @@ fn spawn_backend_thread(cmd_rx: Receiver<BackendCommand>, ui_tx: Sender<UiEvent>
                             }
                         }
                     }
+                    BackendCommand::CreateGuild { name } => {
+                        match client.create_guild(&name).await {
+                            Ok(guild) => {
+                                let _ = ui_tx.try_send(UiEvent::Info(format!(
+                                    "Created guild {}",
+                                    guild.name
+                                )));
+                                let _ = ui_tx.try_send(UiEvent::Server(ServerEvent::GuildUpdated {
+                                    guild,
+                                }));
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::RenameGuild { guild_id, name } => {
+                        match client.rename_guild(guild_id, &name).await {
+                            Ok(guild) => {
+                                let _ = ui_tx.try_send(UiEvent::Server(ServerEvent::GuildUpdated {
+                                    guild,
+                                }));
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::DeleteGuild {
+                        guild_id,
+                        confirm_name,
+                    } => {
+                        match client.delete_guild(guild_id, &confirm_name).await {
+                            Ok(()) => {
+                                let _ = ui_tx.try_send(UiEvent::Server(ServerEvent::GuildDeleted {
+                                    guild_id,
+                                }));
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::LeaveGuild { guild_id } => {
+                        match client.leave_guild(guild_id).await {
+                            Ok(()) => {
+                                let _ = ui_tx.try_send(UiEvent::LeftGuild(guild_id));
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::TransferOwnership {
+                        guild_id,
+                        new_owner_id,
+                    } => {
+                        match client.transfer_ownership(guild_id, new_owner_id).await {
+                            Ok(members) => {
+                                let _ = ui_tx.try_send(UiEvent::Info(
+                                    "Guild ownership transferred".to_string(),
+                                ));
+                                let _ = ui_tx.try_send(UiEvent::Server(
+                                    ServerEvent::GuildMembersUpdated { guild_id, members },
+                                ));
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::ListDirectChannels => match client.list_direct_channels().await {
+                        Ok(direct_channels) => {
+                            let _ = ui_tx.try_send(UiEvent::DirectChannelsLoaded(direct_channels));
+                        }
+                        Err(err) => {
+                            let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                UiErrorContext::General,
+                                err.to_string(),
+                            )));
+                        }
+                    },
+                    BackendCommand::CreateDirectChannel { usernames } => {
+                        match client.create_direct_channel(usernames).await {
+                            Ok(direct) => {
+                                let _ = ui_tx.try_send(UiEvent::DirectChannelOpened(direct));
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::ListThreads { channel_id } => {
+                        match client.list_threads(channel_id, true).await {
+                            Ok(threads) => {
+                                let _ = ui_tx.try_send(UiEvent::ThreadsLoaded {
+                                    channel_id,
+                                    threads,
+                                });
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::CreateThread {
+                        channel_id,
+                        parent_message_id,
+                        name,
+                    } => match client
+                        .create_thread(channel_id, parent_message_id, &name)
+                        .await
+                    {
+                        Ok(thread) => {
+                            let _ = ui_tx.try_send(UiEvent::ThreadOpened(thread));
+                        }
+                        Err(err) => {
+                            let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                UiErrorContext::General,
+                                err.to_string(),
+                            )));
+                        }
+                    },
+                    BackendCommand::SetThreadArchived {
+                        thread_id,
+                        archived,
+                    } => match client.set_thread_archived(thread_id, archived).await {
+                        Ok(thread) => {
+                            let _ = ui_tx
+                                .try_send(UiEvent::Server(ServerEvent::ThreadUpdated { thread }));
+                        }
+                        Err(err) => {
+                            let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                UiErrorContext::General,
+                                err.to_string(),
+                            )));
+                        }
+                    },
+                    BackendCommand::LoadThreadMessages { thread_id, before } => {
+                        if let Err(err) = client.fetch_thread_messages(thread_id, 100, before).await
+                        {
+                            let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                UiErrorContext::General,
+                                err.to_string(),
+                            )));
+                        }
+                    }
+                    BackendCommand::SendThreadMessage { thread_id, text } => {
+                        if let Err(err) = client.send_thread_message(thread_id, &text).await {
+                            let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                UiErrorContext::SendMessage,
+                                err.to_string(),
+                            )));
+                        }
+                    }
+                    BackendCommand::ModerateMember {
+                        guild_id,
+                        target_user_id,
+                        request,
+                    } => {
+                        if let Err(err) = client
+                            .moderate_member(guild_id, target_user_id, request)
+                            .await
+                        {
+                            let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                UiErrorContext::General,
+                                err.to_string(),
+                            )));
+                        }
+                    }
+                    BackendCommand::LoadAuditLog { guild_id, filter } => {
+                        let append = filter.before.is_some();
+                        match client.audit_log(guild_id, filter).await {
+                            Ok(entries) => {
+                                let _ = ui_tx.try_send(UiEvent::AuditLogLoaded {
+                                    guild_id,
+                                    entries,
+                                    append,
+                                });
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::LoadStorageUsage { guild_id } => {
+                        match client.storage_usage(guild_id).await {
+                            Ok(usage) => {
+                                let _ = ui_tx.try_send(UiEvent::StorageUsageLoaded(usage));
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::CreateChannel { guild_id, request } => {
+                        match client.create_channel(guild_id, request).await {
+                            Ok(channel) => {
+                                let _ = ui_tx.try_send(UiEvent::Server(
+                                    ServerEvent::ChannelUpdated { channel },
+                                ));
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::UpdateChannel { channel_id, request } => {
+                        match client.update_channel(channel_id, request).await {
+                            Ok(channel) => {
+                                let _ = ui_tx.try_send(UiEvent::Server(
+                                    ServerEvent::ChannelUpdated { channel },
+                                ));
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::SetChannelRetention {
+                        channel_id,
+                        retention,
+                    } => match client.set_channel_retention(channel_id, retention).await {
+                        Ok(channel) => {
+                            let _ = ui_tx
+                                .try_send(UiEvent::Server(ServerEvent::ChannelUpdated { channel }));
+                        }
+                        Err(err) => {
+                            let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                UiErrorContext::General,
+                                err.to_string(),
+                            )));
+                        }
+                    },
+                    BackendCommand::SetMessageExpiry {
+                        channel_id,
+                        expires_in_seconds,
+                    } => {
+                        client
+                            .set_message_expiry(channel_id, expires_in_seconds)
+                            .await;
+                    }
+                    BackendCommand::ReorderChannels { guild_id, channel_ids } => {
+                        match client.reorder_channels(guild_id, channel_ids).await {
+                            Ok(()) => {
+                                tracing::debug!(guild_id = guild_id.0, "backend: channels reordered");
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
+                    BackendCommand::DeleteChannel { channel_id } => {
+                        match client.delete_channel(channel_id).await {
+                            Ok(()) => {
+                                tracing::debug!(channel_id = channel_id.0, "backend: channel deleted");
+                            }
+                            Err(err) => {
+                                let _ = ui_tx.try_send(UiEvent::Error(UiError::from_message(
+                                    UiErrorContext::General,
+                                    err.to_string(),
+                                )));
+                            }
+                        }
+                    }
                     BackendCommand::JoinWithInvite { invite_code } => {
                         tracing::info!("backend: join_with_invite");
                         match client.join_with_invite(&invite_code).await {
@@ -3769,7 +5771,8 @@ fn main() -> eframe::Result<()> {
 
     let (cmd_tx, cmd_rx) = bounded::<BackendCommand>(256);
     let (ui_tx, ui_rx) = bounded::<UiEvent>(2048);
-    spawn_backend_thread(cmd_rx, ui_tx);
+    let args = CliArgs::parse();
+    spawn_backend_thread(cmd_rx, ui_tx, args.server_trust());
 
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -3778,7 +5781,6 @@ fn main() -> eframe::Result<()> {
             .with_min_inner_size([980.0, 640.0]),
         ..Default::default()
     };
-    let args = CliArgs::parse();
 
     let startup = StartupConfig {
         server_url: args.server_url,
@@ -3843,7 +5845,9 @@ mod tests {
                 sender_username: Some("alice".to_string()),
                 ciphertext_b64: STANDARD.encode(b"ciphertext"),
                 attachment: None,
+                thread_id: None,
                 sent_at: "2024-01-01T00:00:00Z".parse().expect("timestamp"),
+                expires_at: None,
             },
             plaintext: "hello from mls".to_string(),
         };
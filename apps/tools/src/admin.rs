@@ -0,0 +1,388 @@
+//! `tools admin`: a client for the server's `/admin` API. Unlike the other subcommands it
+//! talks to a running server rather than opening the database.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::{Args, Subcommand};
+use client_core::ServerTrust;
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use shared::{
+    domain::LinkedDeviceSummary,
+    error::ApiError,
+    protocol::{
+        AdminGuildSummary, AdminUserSummary, MlsQueueSummary, ServerStorageStats,
+        SuspendUserRequest,
+    },
+};
+
+/// Read when `--admin-token` is not given, as the server does.
+const ADMIN_TOKEN_ENV: &str = "APP__ADMIN_TOKEN";
+
+#[derive(Args, Debug)]
+pub struct AdminArgs {
+    #[arg(long, default_value = "http://127.0.0.1:8443")]
+    server_url: String,
+    /// Defaults to `APP__ADMIN_TOKEN`.
+    #[arg(long)]
+    admin_token: Option<String>,
+    /// Print JSON instead of tables.
+    #[arg(long)]
+    json: bool,
+    #[command(subcommand)]
+    command: AdminCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum AdminCommand {
+    /// Lists accounts, optionally only those whose username contains `--search`.
+    Users {
+        #[arg(long)]
+        search: Option<String>,
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+    User {
+        user_id: i64,
+    },
+    /// Blocks login, WebSocket connections and guild access until unsuspended.
+    Suspend {
+        user_id: i64,
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    Unsuspend {
+        user_id: i64,
+    },
+    /// Lists every guild and direct conversation with member counts.
+    Guilds,
+    DeleteGuild {
+        guild_id: i64,
+    },
+    DeleteMessage {
+        message_id: i64,
+    },
+    /// Deletes an attachment even while messages still reference it.
+    DeleteFile {
+        file_id: String,
+    },
+    Devices {
+        user_id: i64,
+    },
+    RevokeDevice {
+        user_id: i64,
+        device_id: i64,
+    },
+    /// Pending MLS Welcomes and key packages, in total and per guild.
+    MlsQueues,
+    Storage,
+}
+
+/// HTTPS servers are trusted as the desktop client trusts them, through
+/// `CLIENT_TLS_CERT_SHA256` or `CLIENT_TLS_CA_FILE`.
+pub async fn run(args: AdminArgs) -> Result<()> {
+    let token = args
+        .admin_token
+        .or_else(|| std::env::var(ADMIN_TOKEN_ENV).ok())
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+        .with_context(|| format!("pass --admin-token or set {ADMIN_TOKEN_ENV}"))?;
+    let admin = AdminClient {
+        http: ServerTrust::from_env()?.http_client()?,
+        base_url: args.server_url.trim_end_matches('/').to_string(),
+        token,
+    };
+    let json = args.json;
+
+    match args.command {
+        AdminCommand::Users { search, limit } => {
+            let mut query = Vec::new();
+            if let Some(search) = search {
+                query.push(("search", search));
+            }
+            if let Some(limit) = limit {
+                query.push(("limit", limit.to_string()));
+            }
+            let users: Vec<AdminUserSummary> = admin
+                .send(admin.request(Method::GET, "/admin/users").query(&query))
+                .await?;
+            print_users(json, &users)?;
+        }
+        AdminCommand::User { user_id } => {
+            let user: AdminUserSummary = admin
+                .send(admin.request(Method::GET, &format!("/admin/users/{user_id}")))
+                .await?;
+            print_users(json, &[user])?;
+        }
+        AdminCommand::Suspend { user_id, reason } => {
+            let user: AdminUserSummary = admin
+                .send(
+                    admin
+                        .request(Method::PUT, &format!("/admin/users/{user_id}/suspension"))
+                        .json(&SuspendUserRequest { reason }),
+                )
+                .await?;
+            print_users(json, &[user])?;
+        }
+        AdminCommand::Unsuspend { user_id } => {
+            let user: AdminUserSummary = admin
+                .send(admin.request(
+                    Method::DELETE,
+                    &format!("/admin/users/{user_id}/suspension"),
+                ))
+                .await?;
+            print_users(json, &[user])?;
+        }
+        AdminCommand::Guilds => {
+            let guilds: Vec<AdminGuildSummary> = admin
+                .send(admin.request(Method::GET, "/admin/guilds"))
+                .await?;
+            if json {
+                return print_json(&guilds);
+            }
+            print_table(
+                &[
+                    "GUILD", "NAME", "OWNER", "DM", "MEMBERS", "CHANNELS", "BYTES", "CREATED",
+                ],
+                guilds.iter().map(|guild| {
+                    vec![
+                        guild.guild_id.0.to_string(),
+                        guild.name.clone(),
+                        guild.owner_id.0.to_string(),
+                        yes_no(guild.is_direct),
+                        guild.member_count.to_string(),
+                        guild.channel_count.to_string(),
+                        guild.used_bytes.to_string(),
+                        timestamp(guild.created_at),
+                    ]
+                }),
+            );
+        }
+        AdminCommand::DeleteGuild { guild_id } => {
+            admin
+                .send_empty(admin.request(Method::DELETE, &format!("/admin/guilds/{guild_id}")))
+                .await?;
+            print_deleted(json, "guild_id", json!(guild_id))?;
+        }
+        AdminCommand::DeleteMessage { message_id } => {
+            admin
+                .send_empty(admin.request(Method::DELETE, &format!("/admin/messages/{message_id}")))
+                .await?;
+            print_deleted(json, "message_id", json!(message_id))?;
+        }
+        AdminCommand::DeleteFile { file_id } => {
+            admin
+                .send_empty(admin.request(Method::DELETE, &format!("/admin/files/{file_id}")))
+                .await?;
+            print_deleted(json, "file_id", json!(file_id))?;
+        }
+        AdminCommand::Devices { user_id } => {
+            let devices: Vec<LinkedDeviceSummary> = admin
+                .send(admin.request(Method::GET, &format!("/admin/users/{user_id}/devices")))
+                .await?;
+            if json {
+                return print_json(&devices);
+            }
+            print_table(
+                &["DEVICE", "NAME", "STATE", "IDENTITY"],
+                devices.iter().map(|linked| {
+                    vec![
+                        linked.device.device_id.0.to_string(),
+                        linked.device.device_name.clone(),
+                        format!("{:?}", linked.link_state).to_ascii_lowercase(),
+                        linked.device.device_public_identity.clone(),
+                    ]
+                }),
+            );
+        }
+        AdminCommand::RevokeDevice { user_id, device_id } => {
+            admin
+                .send_empty(admin.request(
+                    Method::DELETE,
+                    &format!("/admin/users/{user_id}/devices/{device_id}"),
+                ))
+                .await?;
+            if json {
+                return print_json(
+                    &json!({ "user_id": user_id, "device_id": device_id, "revoked": true }),
+                );
+            }
+            println!("revoked device_id={device_id} user_id={user_id}");
+        }
+        AdminCommand::MlsQueues => {
+            let queue: MlsQueueSummary = admin
+                .send(admin.request(Method::GET, "/admin/mls/queues"))
+                .await?;
+            if json {
+                return print_json(&queue);
+            }
+            println!(
+                "pending_welcomes={} key_packages={}",
+                queue.pending_welcomes, queue.key_packages
+            );
+            if !queue.guilds.is_empty() {
+                println!();
+                print_table(
+                    &["GUILD", "PENDING_WELCOMES", "KEY_PACKAGES"],
+                    queue.guilds.iter().map(|guild| {
+                        vec![
+                            guild.guild_id.0.to_string(),
+                            guild.pending_welcomes.to_string(),
+                            guild.key_packages.to_string(),
+                        ]
+                    }),
+                );
+            }
+        }
+        AdminCommand::Storage => {
+            let stats: ServerStorageStats = admin
+                .send(admin.request(Method::GET, "/admin/storage"))
+                .await?;
+            if json {
+                return print_json(&stats);
+            }
+            print_table(
+                &["STAT", "VALUE"],
+                [
+                    ("users", stats.users),
+                    ("suspended_users", stats.suspended_users),
+                    ("guilds", stats.guilds),
+                    ("channels", stats.channels),
+                    ("messages", stats.messages),
+                    ("files", stats.files),
+                    ("file_bytes", stats.file_bytes),
+                    ("upload_sessions", stats.upload_sessions),
+                    ("database_bytes", stats.database_bytes),
+                ]
+                .into_iter()
+                .map(|(name, value)| vec![name.to_string(), value.to_string()]),
+            );
+        }
+    }
+    Ok(())
+}
+
+struct AdminClient {
+    http: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl AdminClient {
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{path}", self.base_url))
+            .bearer_auth(&self.token)
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let response = checked(request).await?;
+        response.json().await.context("unexpected response body")
+    }
+
+    async fn send_empty(&self, request: RequestBuilder) -> Result<()> {
+        checked(request).await?;
+        Ok(())
+    }
+}
+
+/// Turns an error status into the server's message, or the bare status when the body is
+/// not an [`ApiError`].
+async fn checked(request: RequestBuilder) -> Result<reqwest::Response> {
+    let response = request.send().await.context("admin request failed")?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    match response.json::<ApiError>().await {
+        Ok(error) if status == StatusCode::UNAUTHORIZED => {
+            bail!("{status}: {} (check the admin token)", error.message)
+        }
+        Ok(error) => bail!("{status}: {}", error.message),
+        Err(_) => bail!("{status}"),
+    }
+}
+
+fn print_users(json: bool, users: &[AdminUserSummary]) -> Result<()> {
+    if json {
+        return print_json(&users);
+    }
+    print_table(
+        &[
+            "USER",
+            "USERNAME",
+            "GUILDS",
+            "DEVICES",
+            "CREATED",
+            "SUSPENDED",
+            "REASON",
+        ],
+        users.iter().map(|user| {
+            vec![
+                user.user_id.0.to_string(),
+                user.username.clone(),
+                user.guild_count.to_string(),
+                user.device_count.to_string(),
+                timestamp(user.created_at),
+                user.suspended_at
+                    .map(timestamp)
+                    .unwrap_or_else(|| "-".to_string()),
+                user.suspension_reason
+                    .clone()
+                    .unwrap_or_else(|| "-".to_string()),
+            ]
+        }),
+    );
+    Ok(())
+}
+
+fn print_deleted(json: bool, key: &str, id: serde_json::Value) -> Result<()> {
+    if json {
+        return print_json(&json!({ key: id, "deleted": true }));
+    }
+    println!(
+        "deleted {key}={}",
+        id.as_str().map(str::to_string).unwrap_or(id.to_string())
+    );
+    Ok(())
+}
+
+fn print_json(value: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Left-aligned columns sized to their widest cell.
+fn print_table(headers: &[&str], rows: impl IntoIterator<Item = Vec<String>>) {
+    let rows: Vec<Vec<String>> = rows.into_iter().collect();
+    let mut widths: Vec<usize> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(headers.to_vec());
+    for row in &rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
+
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
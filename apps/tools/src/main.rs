@@ -1,7 +1,12 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use clap::{Parser, Subcommand};
-use shared::domain::ChannelKind;
-use storage::Storage;
+use rand_core::{OsRng, RngCore};
+use shared::domain::{AuditAction, ChannelKind, GuildId};
+use storage::{BlobStoreConfig, MaintenancePolicy, NewAuditEntry, S3Config, Storage};
+
+mod admin;
+mod mls_bench;
 
 #[derive(Parser, Debug)]
 struct Cli {
@@ -22,17 +27,65 @@ enum Command {
         name: String,
         kind: String,
     },
+    /// Creates an invite on behalf of the guild owner.
     Invite {
         guild_id: i64,
     },
+    /// Server administration through a running server's `/admin` API.
+    Admin(admin::AdminArgs),
+    /// Compares incremental MLS provider persistence with full snapshots on a scratch database.
+    MlsStorageBench {
+        #[arg(long, default_value_t = 32)]
+        members: usize,
+    },
+    /// Runs the server's maintenance pass once; unset TTLs use the server defaults.
+    Maintenance {
+        #[arg(long)]
+        consumed_welcome_ttl_seconds: Option<i64>,
+        #[arg(long)]
+        key_package_ttl_seconds: Option<i64>,
+        #[arg(long)]
+        device_link_ttl_seconds: Option<i64>,
+        #[arg(long)]
+        orphan_file_ttl_seconds: Option<i64>,
+        #[arg(long)]
+        upload_session_ttl_seconds: Option<i64>,
+        /// Skip the closing `VACUUM`.
+        #[arg(long)]
+        no_compact: bool,
+    },
+    /// Moves attachment ciphertext stored inline in SQLite into a blob store. S3 credentials
+    /// come from `APP__S3_ACCESS_KEY_ID` and `APP__S3_SECRET_ACCESS_KEY`.
+    MigrateBlobs {
+        #[arg(long, conflicts_with = "s3_bucket")]
+        blob_dir: Option<std::path::PathBuf>,
+        #[arg(long, requires = "s3_bucket")]
+        s3_endpoint: Option<String>,
+        #[arg(long, requires = "s3_endpoint")]
+        s3_bucket: Option<String>,
+        #[arg(long, default_value = "us-east-1")]
+        s3_region: String,
+        #[arg(long, default_value = "")]
+        s3_prefix: String,
+        #[arg(long, default_value_t = 100)]
+        batch_size: u32,
+        /// Skip the closing `VACUUM`.
+        #[arg(long)]
+        no_compact: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    let command = match cli.command {
+        Command::MlsStorageBench { members } => return mls_bench::run(members).await,
+        Command::Admin(args) => return admin::run(args).await,
+        command => command,
+    };
     let storage = Storage::new(&cli.database_url).await?;
 
-    match cli.command {
+    match command {
         Command::Guild {
             owner_user_id,
             name,
@@ -58,7 +111,98 @@ async fn main() -> Result<()> {
             println!("created channel_id={}", channel_id.0);
         }
         Command::Invite { guild_id } => {
-            println!("placeholder invite for guild_id={guild_id}: INVITE-TODO");
+            let guild_id = GuildId(guild_id);
+            let owner_id = storage
+                .list_all_guilds()
+                .await?
+                .into_iter()
+                .find(|guild| guild.guild_id == guild_id && !guild.is_direct)
+                .map(|guild| guild.owner_id)
+                .with_context(|| format!("guild_id={} not found", guild_id.0))?;
+            // The same shape of code the server hands out.
+            let mut secret = [0u8; 12];
+            OsRng.fill_bytes(&mut secret);
+            let payload = format!("guild:{}:{}", guild_id.0, URL_SAFE_NO_PAD.encode(secret));
+            let code = URL_SAFE_NO_PAD.encode(payload.as_bytes());
+            let invite = storage.create_invite(guild_id, owner_id, &code).await?;
+            storage
+                .append_audit_entry(&NewAuditEntry {
+                    details: Some(code),
+                    ..NewAuditEntry::new(guild_id, owner_id, AuditAction::InviteCreated)
+                })
+                .await?;
+            println!(
+                "created invite for guild_id={}: {}",
+                guild_id.0, invite.invite_code
+            );
+        }
+        Command::Maintenance {
+            consumed_welcome_ttl_seconds,
+            key_package_ttl_seconds,
+            device_link_ttl_seconds,
+            orphan_file_ttl_seconds,
+            upload_session_ttl_seconds,
+            no_compact,
+        } => {
+            let defaults = MaintenancePolicy::default();
+            let ttl = |seconds: Option<i64>, default| {
+                seconds.map(chrono::Duration::seconds).unwrap_or(default)
+            };
+            let policy = MaintenancePolicy {
+                consumed_welcome_ttl: ttl(
+                    consumed_welcome_ttl_seconds,
+                    defaults.consumed_welcome_ttl,
+                ),
+                key_package_ttl: ttl(key_package_ttl_seconds, defaults.key_package_ttl),
+                device_link_ttl: ttl(device_link_ttl_seconds, defaults.device_link_ttl),
+                orphan_file_ttl: ttl(orphan_file_ttl_seconds, defaults.orphan_file_ttl),
+                upload_session_ttl: ttl(upload_session_ttl_seconds, defaults.upload_session_ttl),
+                compact: !no_compact,
+            };
+            let report = storage.run_maintenance(policy, chrono::Utc::now()).await?;
+            println!(
+                "deleted welcomes={} key_packages={} device_link_tokens={} device_link_bundles={} files={} upload_sessions={}",
+                report.welcomes,
+                report.key_packages,
+                report.device_link_tokens,
+                report.device_link_bundles,
+                report.files,
+                report.upload_sessions
+            );
+            println!("reclaimed_bytes={}", report.reclaimed_bytes);
+        }
+        Command::MigrateBlobs {
+            blob_dir,
+            s3_endpoint,
+            s3_bucket,
+            s3_region,
+            s3_prefix,
+            batch_size,
+            no_compact,
+        } => {
+            let config = match (blob_dir, s3_endpoint, s3_bucket) {
+                (Some(dir), _, _) => BlobStoreConfig::Filesystem(dir),
+                (None, Some(endpoint), Some(bucket)) => BlobStoreConfig::S3(S3Config {
+                    endpoint,
+                    bucket,
+                    region: s3_region,
+                    prefix: s3_prefix,
+                    access_key_id: std::env::var("APP__S3_ACCESS_KEY_ID")
+                        .context("APP__S3_ACCESS_KEY_ID must be set for S3")?,
+                    secret_access_key: std::env::var("APP__S3_SECRET_ACCESS_KEY")
+                        .context("APP__S3_SECRET_ACCESS_KEY must be set for S3")?,
+                }),
+                _ => bail!("pass --blob-dir or --s3-endpoint with --s3-bucket"),
+            };
+            let storage = storage.with_blob_store(config.build()?);
+            let moved = storage.migrate_files_to_blob_store(batch_size).await?;
+            println!("moved files={moved}");
+            if !no_compact {
+                println!("reclaimed_bytes={}", storage.compact().await?);
+            }
+        }
+        Command::MlsStorageBench { .. } | Command::Admin(_) => {
+            unreachable!("handled before opening storage")
         }
     }
 
@@ -0,0 +1,237 @@
+//! Compares incremental provider-entry persistence against rewriting a full
+//! provider snapshot after every commit, using a scratch SQLite database.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use mls::{
+    MlsGroupHandle, MlsIdentity, MlsStore, PersistedGroupSnapshot, PersistentOpenMlsProvider,
+    ProviderChanges, ProviderEntry,
+};
+use shared::domain::{ChannelId, GuildId};
+use storage::Storage;
+
+const USER_ID: i64 = 1;
+const DEVICE_ID: &str = "bench";
+const GUILD_ID: GuildId = GuildId(1);
+const CHANNEL_ID: ChannelId = ChannelId(1);
+/// Snapshot writes go to their own channel row so they never touch the live group.
+const SNAPSHOT_CHANNEL_ID: ChannelId = ChannelId(2);
+
+#[derive(Default)]
+struct PersistStats {
+    bytes: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl PersistStats {
+    fn take(&self) -> (u64, Duration) {
+        (
+            self.bytes.swap(0, Ordering::Relaxed),
+            Duration::from_nanos(self.nanos.swap(0, Ordering::Relaxed)),
+        )
+    }
+}
+
+/// Delegates to `Storage` while counting what incremental group persists write.
+struct CountingStore {
+    storage: Storage,
+    stats: Arc<PersistStats>,
+}
+
+#[async_trait]
+impl MlsStore for CountingStore {
+    async fn save_identity_keys(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        identity_bytes: &[u8],
+    ) -> Result<()> {
+        self.storage
+            .save_identity_keys(user_id, device_id, identity_bytes)
+            .await
+    }
+
+    async fn load_identity_keys(&self, user_id: i64, device_id: &str) -> Result<Option<Vec<u8>>> {
+        self.storage.load_identity_keys(user_id, device_id).await
+    }
+
+    async fn save_group_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        snapshot: PersistedGroupSnapshot,
+    ) -> Result<()> {
+        self.storage
+            .save_group_state(user_id, device_id, guild_id, channel_id, snapshot)
+            .await
+    }
+
+    async fn load_group_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<Option<PersistedGroupSnapshot>> {
+        self.storage
+            .load_group_state(user_id, device_id, guild_id, channel_id)
+            .await
+    }
+
+    async fn save_pending_join_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        snapshot: PersistedGroupSnapshot,
+    ) -> Result<()> {
+        self.storage
+            .save_pending_join_state(user_id, device_id, guild_id, snapshot)
+            .await
+    }
+
+    async fn load_pending_join_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+    ) -> Result<Option<PersistedGroupSnapshot>> {
+        self.storage
+            .load_pending_join_state(user_id, device_id, guild_id)
+            .await
+    }
+
+    async fn clear_pending_join_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+    ) -> Result<()> {
+        self.storage
+            .clear_pending_join_state(user_id, device_id, guild_id)
+            .await
+    }
+
+    async fn load_group_provider_entries(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<Vec<ProviderEntry>> {
+        self.storage
+            .load_group_provider_entries(user_id, device_id, guild_id, channel_id)
+            .await
+    }
+
+    async fn save_group_state_incremental(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        snapshot: PersistedGroupSnapshot,
+        changes: &ProviderChanges,
+    ) -> Result<()> {
+        let bytes = snapshot.group_state_blob.len()
+            + snapshot.key_material_blob.len()
+            + changes
+                .upserts
+                .iter()
+                .map(|entry| entry.key.len() + entry.value.len())
+                .sum::<usize>()
+            + changes.deletes.iter().map(Vec::len).sum::<usize>();
+        let started = Instant::now();
+        self.storage
+            .save_group_state_incremental(
+                user_id, device_id, guild_id, channel_id, snapshot, changes,
+            )
+            .await?;
+        self.stats
+            .nanos
+            .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.stats.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+pub async fn run(members: usize) -> Result<()> {
+    let unique = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let db_path = std::env::temp_dir().join(format!("mls_storage_bench_{unique}.sqlite3"));
+    let storage = Storage::new(&format!("sqlite://{}", db_path.display())).await?;
+    let result = bench(storage, members).await;
+    let _ = std::fs::remove_file(&db_path);
+    result
+}
+
+async fn bench(storage: Storage, members: usize) -> Result<()> {
+    let stats = Arc::new(PersistStats::default());
+    let store = CountingStore {
+        storage: storage.clone(),
+        stats: stats.clone(),
+    };
+    let mut handle = MlsGroupHandle::new(
+        store,
+        USER_ID,
+        DEVICE_ID,
+        GUILD_ID,
+        CHANNEL_ID,
+        MlsIdentity::new_with_name(b"bench-owner".to_vec())?,
+    )
+    .await?;
+    handle.create_group(CHANNEL_ID).await?;
+    stats.take();
+
+    println!("members  incremental_bytes  incremental_us  snapshot_bytes  snapshot_us");
+    let (mut incremental_total, mut snapshot_total) = (0u64, 0u64);
+    let (mut incremental_time, mut snapshot_time) = (Duration::ZERO, Duration::ZERO);
+    for member in 1..members {
+        let provider = PersistentOpenMlsProvider::default();
+        let identity = MlsIdentity::new_with_name(format!("bench-member-{member}").into_bytes())?;
+        handle
+            .add_member(&identity.key_package_bytes(&provider)?)
+            .await?;
+        let (incremental_bytes, incremental_elapsed) = stats.take();
+
+        let started = Instant::now();
+        let snapshot = handle.full_snapshot()?;
+        let snapshot_bytes =
+            (snapshot.group_state_blob.len() + snapshot.key_material_blob.len()) as u64;
+        storage
+            .save_group_state(USER_ID, DEVICE_ID, GUILD_ID, SNAPSHOT_CHANNEL_ID, snapshot)
+            .await?;
+        let snapshot_elapsed = started.elapsed();
+
+        println!(
+            "{:>7}  {:>17}  {:>14}  {:>14}  {:>11}",
+            member + 1,
+            incremental_bytes,
+            incremental_elapsed.as_micros(),
+            snapshot_bytes,
+            snapshot_elapsed.as_micros()
+        );
+        incremental_total += incremental_bytes;
+        snapshot_total += snapshot_bytes;
+        incremental_time += incremental_elapsed;
+        snapshot_time += snapshot_elapsed;
+    }
+
+    println!(
+        "total    incremental={} bytes in {} ms, snapshot={} bytes in {} ms",
+        incremental_total,
+        incremental_time.as_millis(),
+        snapshot_total,
+        snapshot_time.as_millis()
+    );
+    Ok(())
+}
@@ -0,0 +1,205 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use shared::domain::FileId;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// Plaintext bytes sealed per attachment chunk. Each chunk is also one upload request, so
+/// an interrupted upload loses at most this much work.
+pub(crate) const ATTACHMENT_CHUNK_BYTES: usize = 1024 * 1024;
+
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const CHUNK_AAD_LABEL: &[u8] = b"proto-rtc/attachment-chunk/v1";
+/// Marks MLS plaintexts that carry a JSON [`MessageEnvelope`] rather than bare text. The
+/// leading NUL keeps typed text from ever matching.
+const ENVELOPE_PREFIX: &str = "\u{0}proto-rtc/envelope/v1\n";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AttachmentCryptoError {
+    #[error("attachment chunk {0} could not be decrypted")]
+    Decryption(u64),
+    #[error("malformed attachment key")]
+    MalformedKey,
+}
+
+/// A per-file key. Attachments are sealed with ChaCha20-Poly1305 in fixed-size chunks whose
+/// index and final-chunk flag are bound as associated data, so chunks cannot be reordered,
+/// dropped or truncated without failing to open.
+#[derive(Clone)]
+pub(crate) struct AttachmentKey {
+    key: Zeroizing<[u8; KEY_LEN]>,
+    chunk_bytes: usize,
+}
+
+impl AttachmentKey {
+    pub fn generate() -> Self {
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        OsRng.fill_bytes(key.as_mut());
+        Self {
+            key,
+            chunk_bytes: ATTACHMENT_CHUNK_BYTES,
+        }
+    }
+
+    pub fn from_envelope(attachment: &EnvelopeAttachment) -> Result<Self, AttachmentCryptoError> {
+        let bytes = Zeroizing::new(
+            STANDARD
+                .decode(attachment.key_b64.as_bytes())
+                .map_err(|_| AttachmentCryptoError::MalformedKey)?,
+        );
+        let key: [u8; KEY_LEN] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| AttachmentCryptoError::MalformedKey)?;
+        if attachment.chunk_bytes == 0 {
+            return Err(AttachmentCryptoError::MalformedKey);
+        }
+        Ok(Self {
+            key: Zeroizing::new(key),
+            chunk_bytes: attachment.chunk_bytes as usize,
+        })
+    }
+
+    pub fn to_envelope(&self, file_id: FileId) -> EnvelopeAttachment {
+        EnvelopeAttachment {
+            file_id,
+            key_b64: STANDARD.encode(self.key.as_ref()),
+            chunk_bytes: self.chunk_bytes as u32,
+            preview: None,
+        }
+    }
+
+    /// Number of sealed chunks for `plaintext_len` bytes; empty files still get one.
+    pub fn chunk_count(&self, plaintext_len: usize) -> u64 {
+        plaintext_len.div_ceil(self.chunk_bytes).max(1) as u64
+    }
+
+    pub fn ciphertext_len(&self, plaintext_len: usize) -> u64 {
+        plaintext_len as u64 + self.chunk_count(plaintext_len) * TAG_LEN as u64
+    }
+
+    /// Where sealed chunk `index` starts in the uploaded ciphertext.
+    pub fn ciphertext_offset(&self, index: u64) -> u64 {
+        index * (self.chunk_bytes + TAG_LEN) as u64
+    }
+
+    /// Seals chunk `index` of `plaintext`. Sealing is deterministic, so a resumed upload can
+    /// reproduce any chunk the server already holds.
+    pub fn seal_chunk(&self, plaintext: &[u8], index: u64) -> Vec<u8> {
+        let last = index + 1 == self.chunk_count(plaintext.len());
+        let start = (index as usize * self.chunk_bytes).min(plaintext.len());
+        let end = (start + self.chunk_bytes).min(plaintext.len());
+        self.cipher()
+            .encrypt(
+                &chunk_nonce(index),
+                Payload {
+                    msg: &plaintext[start..end],
+                    aad: &chunk_aad(index, last),
+                },
+            )
+            .expect("ChaCha20-Poly1305 encryption does not fail for in-memory chunks")
+    }
+
+    pub fn open(&self, ciphertext: &[u8]) -> Result<Vec<u8>, AttachmentCryptoError> {
+        let sealed_chunk = self.chunk_bytes + TAG_LEN;
+        let chunk_count = ciphertext.len().div_ceil(sealed_chunk).max(1);
+        let cipher = self.cipher();
+        let mut plaintext = Vec::with_capacity(ciphertext.len());
+        for index in 0..chunk_count {
+            let start = index * sealed_chunk;
+            let end = (start + sealed_chunk).min(ciphertext.len());
+            let index = index as u64;
+            let chunk = cipher
+                .decrypt(
+                    &chunk_nonce(index),
+                    Payload {
+                        msg: &ciphertext[start..end],
+                        aad: &chunk_aad(index, index as usize + 1 == chunk_count),
+                    },
+                )
+                .map_err(|_| AttachmentCryptoError::Decryption(index))?;
+            plaintext.extend_from_slice(&chunk);
+        }
+        Ok(plaintext)
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(self.key.as_ref()))
+    }
+}
+
+impl std::fmt::Debug for AttachmentKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AttachmentKey")
+            .field("chunk_bytes", &self.chunk_bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Every file has its own key, so the chunk index alone makes each nonce unique.
+fn chunk_nonce(index: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&index.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+fn chunk_aad(index: u64, last: bool) -> Vec<u8> {
+    let mut aad = CHUNK_AAD_LABEL.to_vec();
+    aad.extend_from_slice(&index.to_be_bytes());
+    aad.push(u8::from(last));
+    aad
+}
+
+/// The attachment key as it travels inside the MLS-encrypted message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct EnvelopeAttachment {
+    pub file_id: FileId,
+    pub key_b64: String,
+    pub chunk_bytes: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<Box<EnvelopePreview>>,
+}
+
+/// Set for image attachments. The thumbnail is a separate file with its own key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct EnvelopePreview {
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String,
+    pub thumbnail: EnvelopeAttachment,
+}
+
+/// What a message's MLS plaintext carries. Text-only messages stay bare text so older
+/// clients and archived plaintexts read the same as before.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct MessageEnvelope {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<EnvelopeAttachment>,
+}
+
+impl MessageEnvelope {
+    pub fn encode(&self) -> String {
+        if self.attachment.is_none() {
+            return self.text.clone();
+        }
+        let json = serde_json::to_string(self).expect("message envelopes serialize");
+        format!("{ENVELOPE_PREFIX}{json}")
+    }
+
+    pub fn decode(plaintext: &str) -> Self {
+        plaintext
+            .strip_prefix(ENVELOPE_PREFIX)
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_else(|| Self {
+                text: plaintext.to_string(),
+                attachment: None,
+            })
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use shared::domain::{ChannelId, GuildId, MessageId, UserId};
+use thiserror::Error;
+
+/// Recent plaintexts kept per channel for sharing and for re-rendering fetched backlog.
+pub(crate) const HISTORY_ARCHIVE_CHANNEL_CAPACITY: usize = 200;
+
+/// Bundles travel as MLS application messages in the epoch that added the recipient, so MLS
+/// authenticates the member that shared them.
+const HISTORY_BUNDLE_VERSION: u8 = 2;
+
+#[derive(Debug, Error)]
+pub enum HistoryShareError {
+    #[error("history bundle claims sender {claimed} but was signed by user {signed_by}")]
+    SenderMismatch { claimed: i64, signed_by: i64 },
+    #[error("history bundle is addressed to another channel or member")]
+    WrongRecipient,
+    #[error("unsupported history bundle version {0}")]
+    UnsupportedVersion(u8),
+    #[error("malformed history bundle: {0}")]
+    Malformed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SharedHistoryEntry {
+    pub message_id: MessageId,
+    pub sender_id: UserId,
+    pub plaintext: String,
+    /// Disappearing-message deadline; expired entries are never shown or shared.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl SharedHistoryEntry {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HistoryBundleV2 {
+    version: u8,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    target_user_id: i64,
+    entries: Vec<SharedHistoryEntry>,
+}
+
+/// Encodes `entries` for `target_user_id`; the result is encrypted as an MLS application
+/// message before it is posted.
+pub(crate) fn encode_history_bundle(
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    target_user_id: i64,
+    entries: Vec<SharedHistoryEntry>,
+) -> Result<Vec<u8>, HistoryShareError> {
+    serde_json::to_vec(&HistoryBundleV2 {
+        version: HISTORY_BUNDLE_VERSION,
+        guild_id,
+        channel_id,
+        target_user_id,
+        entries,
+    })
+    .map_err(|err| HistoryShareError::Malformed(err.to_string()))
+}
+
+/// Decodes a bundle decrypted from an MLS application message. `signed_by` is the member
+/// whose credential MLS verified; it must be the sender the server reported.
+pub(crate) fn decode_history_bundle(
+    plaintext: &[u8],
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    target_user_id: i64,
+    claimed_sender: UserId,
+    signed_by: i64,
+) -> Result<Vec<SharedHistoryEntry>, HistoryShareError> {
+    if claimed_sender.0 != signed_by {
+        return Err(HistoryShareError::SenderMismatch {
+            claimed: claimed_sender.0,
+            signed_by,
+        });
+    }
+    let bundle: HistoryBundleV2 = serde_json::from_slice(plaintext)
+        .map_err(|err| HistoryShareError::Malformed(err.to_string()))?;
+    if bundle.version != HISTORY_BUNDLE_VERSION {
+        return Err(HistoryShareError::UnsupportedVersion(bundle.version));
+    }
+    if bundle.guild_id != guild_id
+        || bundle.channel_id != channel_id
+        || bundle.target_user_id != target_user_id
+    {
+        return Err(HistoryShareError::WrongRecipient);
+    }
+    Ok(bundle.entries)
+}
+
+/// How far an archived plaintext can be trusted, lowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum EntrySource {
+    /// Shared by a member other than the message's sender.
+    SharedByOther,
+    /// Shared by the member who sent the message.
+    SharedByAuthor,
+    /// Decrypted on this device.
+    Decrypted,
+}
+
+#[derive(Debug)]
+struct ArchivedEntry {
+    entry: SharedHistoryEntry,
+    source: EntrySource,
+}
+
+/// In-memory record of recently decrypted plaintexts, keyed by channel. MLS forward secrecy
+/// means a message can only be decrypted once, so this is also what lets backlog fetched
+/// with `fetch_messages` render after it has been seen or shared.
+///
+/// Shared copies are only as honest as the member that shared them, so a message may have
+/// several candidates, one per claimed sender. A more trusted copy replaces a less trusted
+/// one, and lookups only match the sender the server recorded for the message.
+#[derive(Debug, Default)]
+pub(crate) struct HistoryArchive {
+    channels: HashMap<ChannelId, VecDeque<ArchivedEntry>>,
+}
+
+impl HistoryArchive {
+    /// Records a plaintext decrypted on this device, replacing any shared copies.
+    pub fn record(&mut self, channel_id: ChannelId, entry: SharedHistoryEntry) {
+        self.insert(channel_id, entry, EntrySource::Decrypted);
+    }
+
+    fn insert(&mut self, channel_id: ChannelId, entry: SharedHistoryEntry, source: EntrySource) {
+        let entries = self.channels.entry(channel_id).or_default();
+        let outranked = entries.iter().any(|existing| {
+            existing.entry.message_id == entry.message_id
+                && (existing.source == EntrySource::Decrypted
+                    || (existing.entry.sender_id == entry.sender_id && existing.source >= source))
+        });
+        if outranked {
+            return;
+        }
+        entries.retain(|existing| {
+            existing.entry.message_id != entry.message_id
+                || (source != EntrySource::Decrypted && existing.entry.sender_id != entry.sender_id)
+        });
+        entries.push_back(ArchivedEntry { entry, source });
+        while entries.len() > HISTORY_ARCHIVE_CHANNEL_CAPACITY {
+            entries.pop_front();
+        }
+    }
+
+    /// The plaintext of `message_id`, if one was archived for the sender the server reported.
+    pub fn get(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        sender_id: UserId,
+    ) -> Option<&SharedHistoryEntry> {
+        let now = Utc::now();
+        self.channels
+            .get(&channel_id)?
+            .iter()
+            .map(|archived| &archived.entry)
+            .find(|entry| {
+                entry.message_id == message_id
+                    && entry.sender_id == sender_id
+                    && !entry.is_expired(now)
+            })
+    }
+
+    pub fn entries(&self, channel_id: ChannelId) -> Vec<SharedHistoryEntry> {
+        let now = Utc::now();
+        self.channels
+            .get(&channel_id)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .map(|archived| &archived.entry)
+                    .filter(|entry| !entry.is_expired(now))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Drops plaintexts the server reported as expired.
+    pub fn remove(&mut self, channel_id: ChannelId, message_ids: &[MessageId]) {
+        if let Some(entries) = self.channels.get_mut(&channel_id) {
+            entries.retain(|archived| !message_ids.contains(&archived.entry.message_id));
+        }
+    }
+
+    /// Drops every plaintext whose disappearing timer has run out.
+    pub fn purge_expired(&mut self, now: DateTime<Utc>) {
+        for entries in self.channels.values_mut() {
+            entries.retain(|archived| !archived.entry.is_expired(now));
+        }
+        self.channels.retain(|_, entries| !entries.is_empty());
+    }
+
+    /// Imports entries shared by `shared_by`, keeping the channel ordered by message id.
+    pub fn merge(
+        &mut self,
+        channel_id: ChannelId,
+        shared_by: UserId,
+        shared: Vec<SharedHistoryEntry>,
+    ) {
+        for entry in shared {
+            let source = if entry.sender_id == shared_by {
+                EntrySource::SharedByAuthor
+            } else {
+                EntrySource::SharedByOther
+            };
+            self.insert(channel_id, entry, source);
+        }
+        if let Some(entries) = self.channels.get_mut(&channel_id) {
+            entries
+                .make_contiguous()
+                .sort_by_key(|archived| archived.entry.message_id.0);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use shared::{
+    domain::{DeviceId, KeyTransparencyAction, UserId},
+    protocol::{ConsistencyProofResponse, KeyTransparencyProof, SignedTreeHead},
+    transparency::{tree_head_signing_payload, verify_consistency, verify_inclusion, TreeHash},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum KeyTransparencyError {
+    #[error("server returned a key package without the device it belongs to")]
+    MissingDevice,
+    #[error("server returned a device key package without a key transparency proof")]
+    MissingProof,
+    #[error("key package for user {user_id} device {device_id} was not signed by the identity in the key transparency log")]
+    IdentityMismatch { user_id: i64, device_id: i64 },
+    #[error("key transparency log entry does not match user {user_id} device {device_id}")]
+    LeafMismatch { user_id: i64, device_id: i64 },
+    #[error("device {device_id} is revoked in the key transparency log")]
+    DeviceRevoked { device_id: i64 },
+    #[error("key transparency log key changed from the pinned key")]
+    LogKeyChanged,
+    #[error("invalid key transparency tree head signature")]
+    InvalidSignature,
+    #[error("key transparency inclusion proof failed for leaf {leaf_index}")]
+    InclusionProofFailed { leaf_index: u64 },
+    #[error("key transparency split view: tree heads of size {first_size} and {second_size} are inconsistent")]
+    SplitView { first_size: u64, second_size: u64 },
+    #[error("malformed key transparency data: {0}")]
+    Malformed(String),
+}
+
+/// Pinned log key and the largest tree head seen so far, used to detect a split-view log.
+/// Persisted with the device's MLS state so detection survives restarts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct KeyTransparencyState {
+    pinned_log_key_b64: Option<String>,
+    last_tree_head: Option<SignedTreeHead>,
+}
+
+impl KeyTransparencyState {
+    /// Reloads persisted state, rejecting a stored tree head the pinned key did not sign.
+    pub(crate) fn from_json(json: &str) -> Result<Self, KeyTransparencyError> {
+        let state: Self = serde_json::from_str(json)
+            .map_err(|_| KeyTransparencyError::Malformed("persisted state".to_string()))?;
+        if let Some(head) = &state.last_tree_head {
+            if state.pinned_log_key_b64.is_none() {
+                return Err(KeyTransparencyError::Malformed(
+                    "persisted state without a pinned log key".to_string(),
+                ));
+            }
+            state.verify_tree_head(head)?;
+        }
+        Ok(state)
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    pub(crate) fn last_tree_head(&self) -> Option<&SignedTreeHead> {
+        self.last_tree_head.as_ref()
+    }
+
+    /// Checks that `proof` is a signed, included `Register` entry for the given device.
+    pub(crate) fn verify_device_proof(
+        &self,
+        proof: &KeyTransparencyProof,
+        user_id: UserId,
+        device_id: DeviceId,
+    ) -> Result<(), KeyTransparencyError> {
+        let leaf = &proof.leaf;
+        if leaf.user_id != user_id || leaf.device_id != device_id {
+            return Err(KeyTransparencyError::LeafMismatch {
+                user_id: user_id.0,
+                device_id: device_id.0,
+            });
+        }
+        if leaf.action == KeyTransparencyAction::Revoke {
+            return Err(KeyTransparencyError::DeviceRevoked {
+                device_id: device_id.0,
+            });
+        }
+
+        let root = self.verify_tree_head(&proof.tree_head)?;
+        let path = decode_hashes(&proof.audit_path_b64)?;
+        if !verify_inclusion(
+            &leaf.leaf_hash(),
+            leaf.leaf_index,
+            proof.tree_head.tree_size,
+            &path,
+            &root,
+        ) {
+            return Err(KeyTransparencyError::InclusionProofFailed {
+                leaf_index: leaf.leaf_index,
+            });
+        }
+        Ok(())
+    }
+
+    /// The `(first_size, second_size)` consistency proof needed to relate `head` to the last
+    /// seen tree head, if any.
+    pub(crate) fn consistency_range(&self, head: &SignedTreeHead) -> Option<(u64, u64)> {
+        let last = self.last_tree_head.as_ref()?;
+        match last.tree_size.cmp(&head.tree_size) {
+            std::cmp::Ordering::Less => Some((last.tree_size, head.tree_size)),
+            std::cmp::Ordering::Greater => Some((head.tree_size, last.tree_size)),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+
+    /// Accepts a verified tree head, checking it against the last seen head. The
+    /// consistency proof must cover the range returned by [`Self::consistency_range`].
+    pub(crate) fn observe_tree_head(
+        &mut self,
+        head: &SignedTreeHead,
+        consistency: Option<&ConsistencyProofResponse>,
+    ) -> Result<(), KeyTransparencyError> {
+        let root = self.verify_tree_head(head)?;
+        let Some(last) = self.last_tree_head.clone() else {
+            self.pinned_log_key_b64 = Some(head.log_public_key_b64.clone());
+            self.last_tree_head = Some(head.clone());
+            return Ok(());
+        };
+        let last_root = decode_hash(&last.root_hash_b64)?;
+
+        if last.tree_size == head.tree_size {
+            if last_root != root {
+                return Err(KeyTransparencyError::SplitView {
+                    first_size: last.tree_size,
+                    second_size: head.tree_size,
+                });
+            }
+            return Ok(());
+        }
+
+        let (first_size, second_size, first_root, second_root) = if last.tree_size < head.tree_size
+        {
+            (last.tree_size, head.tree_size, last_root, root)
+        } else {
+            (head.tree_size, last.tree_size, root, last_root)
+        };
+        let consistent = match consistency {
+            Some(proof) if proof.first_size == first_size && proof.second_size == second_size => {
+                let nodes = decode_hashes(&proof.proof_b64)?;
+                verify_consistency(first_size, second_size, &first_root, &second_root, &nodes)
+            }
+            _ => false,
+        };
+        if !consistent {
+            return Err(KeyTransparencyError::SplitView {
+                first_size,
+                second_size,
+            });
+        }
+
+        if head.tree_size > last.tree_size {
+            self.last_tree_head = Some(head.clone());
+        }
+        Ok(())
+    }
+
+    fn verify_tree_head(&self, head: &SignedTreeHead) -> Result<TreeHash, KeyTransparencyError> {
+        if let Some(pinned) = &self.pinned_log_key_b64 {
+            if pinned != &head.log_public_key_b64 {
+                return Err(KeyTransparencyError::LogKeyChanged);
+            }
+        }
+        let key_bytes: [u8; 32] = STANDARD
+            .decode(&head.log_public_key_b64)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| KeyTransparencyError::Malformed("log public key".to_string()))?;
+        let key = VerifyingKey::from_bytes(&key_bytes)
+            .map_err(|_| KeyTransparencyError::Malformed("log public key".to_string()))?;
+        let signature_bytes: [u8; 64] = STANDARD
+            .decode(&head.signature_b64)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| KeyTransparencyError::Malformed("tree head signature".to_string()))?;
+        let root = decode_hash(&head.root_hash_b64)?;
+        let payload =
+            tree_head_signing_payload(head.tree_size, &root, head.timestamp.timestamp_millis());
+        key.verify(&payload, &Signature::from_bytes(&signature_bytes))
+            .map_err(|_| KeyTransparencyError::InvalidSignature)?;
+        Ok(root)
+    }
+}
+
+fn decode_hash(encoded: &str) -> Result<TreeHash, KeyTransparencyError> {
+    STANDARD
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| KeyTransparencyError::Malformed("tree hash".to_string()))
+}
+
+fn decode_hashes(encoded: &[String]) -> Result<Vec<TreeHash>, KeyTransparencyError> {
+    encoded.iter().map(|node| decode_hash(node)).collect()
+}
@@ -23,11 +23,18 @@ use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use shared::{
-    domain::{ChannelId, FileId, GuildId, MessageId},
+    domain::{ChannelId, DeviceId, FileId, GuildId, MessageId, ThreadId, UserId},
     protocol::{
-        AttachmentPayload, ChannelStateRecord, ChannelSummary, ClientRequest,
-        EncryptedChannelStateBundleV1, GuildSummary, KeyPackageResponse, MemberSummary,
-        MessagePayload, MlsBootstrapReason, ServerEvent, UploadKeyPackageResponse, WelcomeResponse,
+        ArchiveThreadRequest, AttachmentPayload, AuditLogEntry, AuditLogFilter, ChannelStateRecord,
+        ChannelSummary, ClientRequest, CompleteUploadRequest, ConsistencyProofResponse,
+        CreateChannelRequest, CreateDirectChannelRequest, CreateGuildRequest, CreateThreadRequest,
+        CreateUploadRequest, DeleteGuildRequest, DirectChannelSummary,
+        EncryptedChannelStateBundleV1, GuildStorageUsage, GuildSummary, HistoryBundleResponse,
+        InviteSummary, KeyPackageResponse, KeyTransparencyProof, LinkThumbnailRequest,
+        MemberSummary, MessagePayload, MlsBootstrapReason, ModerationRequest,
+        ReorderChannelsRequest, ReorderGuildsRequest, RetentionPolicy, ServerEvent, SignedTreeHead,
+        ThreadSummary, TransferOwnershipRequest, UpdateChannelRequest, UpdateGuildRequest,
+        UploadKeyPackageResponse, UploadStatus, WelcomeResponse,
     },
 };
 use thiserror::Error;
@@ -35,17 +42,39 @@ use tokio::{
     sync::{broadcast, Mutex, RwLock},
     task::JoinHandle,
 };
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio_tungstenite::{connect_async_tls_with_config, tungstenite::Message, Connector};
 use tracing::{debug, error, info, warn};
 use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
 use zeroize::Zeroize;
 
+mod attachments;
 pub mod error;
+mod history_share;
+mod key_transparency;
+mod mls_at_rest;
+mod mls_backup;
 mod mls_session_manager;
 pub mod protocol_client;
+mod thumbnails;
+mod tls;
 pub mod transport;
 pub mod types;
+pub use attachments::AttachmentCryptoError;
+pub use history_share::HistoryShareError;
+pub use key_transparency::KeyTransparencyError;
+pub use mls::{DecryptionFailure, MlsError, MlsInbound};
+pub use mls_at_rest::{MlsAtRestError, MlsAtRestKeySource};
+pub use mls_backup::{MlsBackupError, MlsBackupKdfParams};
 pub use mls_session_manager::DurableMlsSessionManager;
+pub use thumbnails::AttachmentPreview;
+pub use tls::{ServerTrust, TLS_CA_FILE_ENV, TLS_CERT_SHA256_ENV};
+
+use attachments::{AttachmentKey, EnvelopeAttachment, EnvelopePreview, MessageEnvelope};
+use history_share::{
+    decode_history_bundle, encode_history_bundle, HistoryArchive, SharedHistoryEntry,
+};
+use key_transparency::KeyTransparencyState;
+use thumbnails::Thumbnail;
 
 const LIVEKIT_E2EE_EXPORT_LABEL: &str = "livekit-e2ee";
 const LIVEKIT_E2EE_KEY_LEN: usize = 32;
@@ -53,6 +82,9 @@ const LIVEKIT_E2EE_CACHE_TTL: Duration = Duration::from_secs(90);
 const LIVEKIT_E2EE_INFO_PREFIX: &[u8] = b"proto-rtc/livekit-e2ee/v1";
 /// Deterministic application salt for LiveKit E2EE key derivation.
 const LIVEKIT_E2EE_APP_SALT: &[u8] = b"proto-rtc/livekit-e2ee-app-salt";
+/// Consecutive failed chunk uploads tolerated before an attachment upload gives up.
+const UPLOAD_RETRY_ATTEMPTS: u32 = 5;
+const UPLOAD_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
 const WELCOME_SYNC_RETRY_ATTEMPTS: usize = 6;
 const WELCOME_SYNC_RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
 const WELCOME_SYNC_RETRY_MAX_DELAY: Duration = Duration::from_secs(2);
@@ -60,6 +92,9 @@ const WELCOME_SYNC_COOLDOWN_AFTER_EXHAUSTED: Duration = Duration::from_secs(15);
 const BOOTSTRAP_KEY_PACKAGE_RETRY_ATTEMPTS: usize = 5;
 const BOOTSTRAP_REQUEST_MIN_INTERVAL: Duration = Duration::from_secs(30);
 const PROCESSED_MESSAGE_CACHE_MAX: usize = 4096;
+/// Times a rate-limited request is retried after waiting out the server's `retry_after`.
+const RATE_LIMIT_RETRY_ATTEMPTS: u32 = 3;
+const RATE_LIMIT_MAX_WAIT: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Clone, Copy)]
 enum MlsFailureCategory {
@@ -80,14 +115,42 @@ impl MlsFailureCategory {
     }
 }
 
-fn is_duplicate_member_add_error(err: &anyhow::Error) -> bool {
-    err.to_string()
-        .to_ascii_lowercase()
-        .contains("duplicate signature key")
-}
-
-fn is_wrong_epoch_error(err: &anyhow::Error) -> bool {
-    err.to_string().to_ascii_lowercase().contains("wrong epoch")
+/// Sends `request`, and on `429 Too Many Requests` sleeps for the server's `Retry-After`
+/// (or the body's `retry_after`) before trying again. Requests with streaming bodies cannot
+/// be cloned and are sent once. The last response is returned as is.
+async fn send_with_backoff(request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+    let mut attempt = 0;
+    loop {
+        let retry = match request.try_clone() {
+            Some(retry) if attempt < RATE_LIMIT_RETRY_ATTEMPTS => retry,
+            _ => return Ok(request.send().await?),
+        };
+        let response = retry.send().await?;
+        if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Ok(response);
+        }
+        attempt += 1;
+        let header_wait = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok());
+        let wait = match header_wait {
+            Some(seconds) => Some(seconds),
+            None => response
+                .json::<shared::error::ApiError>()
+                .await
+                .ok()
+                .and_then(|error| error.retry_after),
+        };
+        let wait = Duration::from_secs(wait.unwrap_or(1).max(1)).min(RATE_LIMIT_MAX_WAIT);
+        warn!(
+            attempt,
+            wait_seconds = wait.as_secs(),
+            "http: rate limited by server; backing off"
+        );
+        tokio::time::sleep(wait).await;
+    }
 }
 
 fn is_recovery_welcome_material_missing_404(status: reqwest::StatusCode, body: &str) -> bool {
@@ -103,21 +166,6 @@ fn is_recovery_welcome_unavailable_error(err: &anyhow::Error) -> bool {
         || (s.contains("409") && s.contains("no welcome material available for recovery"))
         || (s.contains("status 409") && s.contains("no welcome material available for recovery"))
 }
-fn is_expected_historical_mls_decrypt_error(err: &anyhow::Error) -> bool {
-    let msg = err.to_string().to_ascii_lowercase();
-
-    // Backend-specific / library-specific patterns commonly seen when trying to decrypt
-    // messages from before local membership (or from an inaccessible epoch/generation).
-    msg.contains("aead decryption")
-        || msg.contains("failed to process mls message")
-        || msg.contains("wrong epoch")
-        || msg.contains("secretreuseerror")
-        || msg.contains("requested secret was deleted")
-        || msg.contains("ciphertext generation out of bounds")
-        || msg.contains("message epoch")
-        || msg.contains("secret tree")
-        || msg.contains("unable to decrypt")
-}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum DecryptFailureKind {
@@ -127,25 +175,24 @@ enum DecryptFailureKind {
     Unexpected,
 }
 
-fn classify_decrypt_failure(err: &anyhow::Error) -> DecryptFailureKind {
-    let msg = err.to_string().to_ascii_lowercase();
-    if msg.contains("invalid base64")
-        || msg.contains("deserialize")
-        || msg.contains("trailing data")
-    {
-        return DecryptFailureKind::MalformedCiphertext;
-    }
-    if is_wrong_epoch_error(err)
-        || msg.contains("secretreuseerror")
-        || msg.contains("requested secret was deleted")
-        || msg.contains("ciphertext generation out of bounds")
-    {
-        return DecryptFailureKind::EpochDriftResync;
-    }
-    if is_expected_historical_mls_decrypt_error(err) {
-        return DecryptFailureKind::ExpectedHistoricalGap;
-    }
-    DecryptFailureKind::Unexpected
+fn classify_decrypt_failure(err: &MlsError) -> DecryptFailureKind {
+    match err {
+        // Secrets that aged out of the ratchet window cannot come back, so the message is
+        // skipped rather than resynced for.
+        MlsError::Decryption(DecryptionFailure::Expired) => {
+            DecryptFailureKind::ExpectedHistoricalGap
+        }
+        // An AEAD failure or unknown sender means the local key schedule or roster no longer
+        // matches the sender's; a resync either repairs it or isolates a tampered message.
+        MlsError::WrongEpoch
+        | MlsError::SecretReuse
+        | MlsError::GenerationOutOfBounds
+        | MlsError::Decryption(DecryptionFailure::Aead | DecryptionFailure::UnknownSender) => {
+            DecryptFailureKind::EpochDriftResync
+        }
+        MlsError::Malformed(_) => DecryptFailureKind::MalformedCiphertext,
+        _ => DecryptFailureKind::Unexpected,
+    }
 }
 
 fn welcome_retry_delay_with_jitter(
@@ -199,42 +246,69 @@ pub enum LiveKitE2eeKeyError {
 
 #[async_trait]
 pub trait MlsSessionManager: Send + Sync {
-    async fn key_package_bytes(&self, guild_id: GuildId) -> Result<Vec<u8>>;
+    async fn key_package_bytes(&self, guild_id: GuildId) -> Result<Vec<u8>, MlsError>;
+    /// Fingerprint of this device's MLS credential and signature key. It is registered
+    /// with the server and logged for the device in key transparency.
+    async fn device_public_identity(&self) -> Result<String, MlsError>;
+    /// Fingerprint of the credential a fetched key package was signed with, comparable
+    /// with the `device_public_identity` logged for its device.
+    async fn key_package_public_identity(
+        &self,
+        key_package_bytes: &[u8],
+    ) -> Result<String, MlsError> {
+        mls::key_package_fingerprint(key_package_bytes)
+    }
     async fn has_persisted_group_state(
         &self,
         guild_id: GuildId,
         channel_id: ChannelId,
     ) -> Result<bool>;
-    async fn open_or_create_group(&self, guild_id: GuildId, channel_id: ChannelId) -> Result<()>;
-    async fn encrypt_application(&self, channel_id: ChannelId, plaintext: &[u8])
-        -> Result<Vec<u8>>;
+    async fn open_or_create_group(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<(), MlsError>;
+    async fn encrypt_application(
+        &self,
+        channel_id: ChannelId,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, MlsError>;
+    /// Processes one inbound MLS message; commits and proposals are reported separately
+    /// from application payloads so an empty plaintext is never mistaken for a commit.
     async fn decrypt_application(
         &self,
         channel_id: ChannelId,
         ciphertext: &[u8],
-    ) -> Result<Vec<u8>>;
+    ) -> Result<MlsInbound, MlsError>;
+    /// Decrypts an application message and returns the user whose credential signed it.
+    /// Commits and proposals are rejected without being merged.
+    async fn decrypt_application_from_member(
+        &self,
+        channel_id: ChannelId,
+        ciphertext: &[u8],
+    ) -> Result<(i64, Vec<u8>), MlsError>;
     async fn add_member(
         &self,
         channel_id: ChannelId,
         key_package_bytes: &[u8],
-    ) -> Result<MlsAddMemberOutcome>;
+    ) -> Result<MlsAddMemberOutcome, MlsError>;
     async fn group_contains_key_package_identity(
         &self,
         channel_id: ChannelId,
         key_package_bytes: &[u8],
-    ) -> Result<bool>;
+    ) -> Result<bool, MlsError>;
     async fn join_from_welcome(
         &self,
         guild_id: GuildId,
         channel_id: ChannelId,
         welcome_bytes: &[u8],
-    ) -> Result<()>;
+    ) -> Result<(), MlsError>;
     async fn export_secret(
         &self,
         channel_id: ChannelId,
         label: &str,
         len: usize,
-    ) -> Result<Vec<u8>>;
+    ) -> Result<Vec<u8>, MlsError>;
     async fn reset_channel_group_state(
         &self,
         _guild_id: GuildId,
@@ -242,6 +316,15 @@ pub trait MlsSessionManager: Send + Sync {
     ) -> Result<bool> {
         Ok(false)
     }
+    /// Removes every group member whose user is not in `user_ids` and returns the commit to
+    /// post, or `None` when nobody had to be removed.
+    async fn retain_members(
+        &self,
+        _channel_id: ChannelId,
+        _user_ids: &[i64],
+    ) -> Result<Option<Vec<u8>>, MlsError> {
+        Ok(None)
+    }
     async fn export_group_state(
         &self,
         guild_id: GuildId,
@@ -266,6 +349,29 @@ pub trait MlsSessionManager: Send + Sync {
             channel_id.0
         ))
     }
+    /// Serializes identity and all group states, encrypted under `passphrase`.
+    async fn export_encrypted_backup(&self, passphrase: &str) -> Result<Vec<u8>> {
+        let _ = passphrase;
+        Err(anyhow!(
+            "MLS state backup unavailable for this session manager"
+        ))
+    }
+    /// Replaces local identity and group states with those from an encrypted backup and
+    /// returns the number of restored groups.
+    async fn restore_encrypted_backup(&self, passphrase: &str, backup: &[u8]) -> Result<usize> {
+        let _ = (passphrase, backup);
+        Err(anyhow!(
+            "MLS state restore unavailable for this session manager"
+        ))
+    }
+    /// Key transparency state saved with this device's MLS state, if any.
+    async fn load_key_transparency_state(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+    async fn save_key_transparency_state(&self, state_json: &str) -> Result<()> {
+        let _ = state_json;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -278,8 +384,12 @@ pub struct MissingMlsSessionManager;
 
 #[async_trait]
 impl MlsSessionManager for MissingMlsSessionManager {
-    async fn key_package_bytes(&self, guild_id: GuildId) -> Result<Vec<u8>> {
-        Err(anyhow!("MLS backend unavailable for guild {}", guild_id.0))
+    async fn key_package_bytes(&self, _guild_id: GuildId) -> Result<Vec<u8>, MlsError> {
+        Err(MlsError::Unavailable)
+    }
+
+    async fn device_public_identity(&self) -> Result<String, MlsError> {
+        Err(MlsError::Unavailable)
     }
 
     async fn has_persisted_group_state(
@@ -294,80 +404,70 @@ impl MlsSessionManager for MissingMlsSessionManager {
         ))
     }
 
-    async fn open_or_create_group(&self, guild_id: GuildId, channel_id: ChannelId) -> Result<()> {
-        Err(anyhow!(
-            "MLS backend unavailable for guild {} channel {}",
-            guild_id.0,
-            channel_id.0
-        ))
+    async fn open_or_create_group(
+        &self,
+        _guild_id: GuildId,
+        _channel_id: ChannelId,
+    ) -> Result<(), MlsError> {
+        Err(MlsError::Unavailable)
     }
 
     async fn encrypt_application(
         &self,
-        channel_id: ChannelId,
+        _channel_id: ChannelId,
         _plaintext: &[u8],
-    ) -> Result<Vec<u8>> {
-        Err(anyhow!(
-            "no active MLS group available for channel {}",
-            channel_id.0
-        ))
+    ) -> Result<Vec<u8>, MlsError> {
+        Err(MlsError::Unavailable)
     }
 
     async fn decrypt_application(
         &self,
-        channel_id: ChannelId,
+        _channel_id: ChannelId,
         _ciphertext: &[u8],
-    ) -> Result<Vec<u8>> {
-        Err(anyhow!(
-            "no active MLS group available for channel {}",
-            channel_id.0
-        ))
+    ) -> Result<MlsInbound, MlsError> {
+        Err(MlsError::Unavailable)
+    }
+
+    async fn decrypt_application_from_member(
+        &self,
+        _channel_id: ChannelId,
+        _ciphertext: &[u8],
+    ) -> Result<(i64, Vec<u8>), MlsError> {
+        Err(MlsError::Unavailable)
     }
 
     async fn add_member(
         &self,
-        channel_id: ChannelId,
+        _channel_id: ChannelId,
         _key_package_bytes: &[u8],
-    ) -> Result<MlsAddMemberOutcome> {
-        Err(anyhow!(
-            "no active MLS group available for channel {}",
-            channel_id.0
-        ))
+    ) -> Result<MlsAddMemberOutcome, MlsError> {
+        Err(MlsError::Unavailable)
     }
 
     async fn join_from_welcome(
         &self,
         _guild_id: GuildId,
-        channel_id: ChannelId,
+        _channel_id: ChannelId,
         _welcome_bytes: &[u8],
-    ) -> Result<()> {
-        Err(anyhow!(
-            "no active MLS group available for channel {}",
-            channel_id.0
-        ))
+    ) -> Result<(), MlsError> {
+        Err(MlsError::Unavailable)
     }
 
     async fn export_secret(
         &self,
-        channel_id: ChannelId,
+        _channel_id: ChannelId,
         _label: &str,
         _len: usize,
-    ) -> Result<Vec<u8>> {
-        Err(anyhow!(
-            "no active MLS group available for channel {}",
-            channel_id.0
-        ))
+    ) -> Result<Vec<u8>, MlsError> {
+        Err(MlsError::Unavailable)
     }
 
     async fn group_contains_key_package_identity(
         &self,
-        channel_id: ChannelId,
+        _channel_id: ChannelId,
         _key_package_bytes: &[u8],
-    ) -> Result<bool> {
-        Err(anyhow!(
-            "no active MLS group available for channel {}",
-            channel_id.0
-        ))
+    ) -> Result<bool, MlsError> {
+        Err(MlsError::Unavailable)
     }
 
     async fn reset_channel_group_state(
@@ -620,20 +720,33 @@ pub enum ClientEvent {
         channel_id: ChannelId,
         participants: Vec<VoiceParticipantState>,
     },
+    /// Encrypted bytes of an attachment the server has confirmed so far.
+    UploadProgress {
+        upload_id: String,
+        filename: String,
+        sent_bytes: u64,
+        total_bytes: u64,
+    },
+    /// The upload finished or gave up; failures are reported by the send call.
+    UploadFinished {
+        upload_id: String,
+    },
+    /// An image attachment has a thumbnail; fetch it with [`ClientHandle::download_thumbnail`].
+    AttachmentPreviewAvailable(AttachmentPreview),
     Error(String),
 }
 
+/// A file to attach. `bytes` is plaintext; the client encrypts it before upload.
 #[derive(Debug, Clone)]
 pub struct AttachmentUpload {
     pub filename: String,
     pub mime_type: Option<String>,
-    pub ciphertext: Vec<u8>,
+    pub bytes: Vec<u8>,
 }
 
 #[derive(Debug, Deserialize)]
 struct FileUploadResponse {
-    file_id: i64,
-    size_bytes: usize,
+    file_id: FileId,
 }
 
 #[async_trait]
@@ -657,8 +770,90 @@ pub trait ClientHandle: Send + Sync {
         attachment: AttachmentUpload,
     ) -> Result<()>;
     async fn download_file(&self, file_id: FileId) -> Result<Vec<u8>>;
+    /// The decrypted thumbnail of an image attachment, or `None` if its message carried none.
+    async fn download_thumbnail(&self, file_id: FileId) -> Result<Option<Vec<u8>>>;
     async fn create_invite(&self, guild_id: GuildId) -> Result<String>;
     async fn join_with_invite(&self, invite_code: &str) -> Result<()>;
+    /// Lists the caller's direct conversations and prepares their MLS groups.
+    async fn list_direct_channels(&self) -> Result<Vec<DirectChannelSummary>>;
+    /// Opens a conversation with the named users; reuses an existing one-to-one conversation.
+    async fn create_direct_channel(&self, usernames: Vec<String>) -> Result<DirectChannelSummary>;
+    async fn create_guild(&self, name: &str) -> Result<GuildSummary>;
+    async fn rename_guild(&self, guild_id: GuildId, name: &str) -> Result<GuildSummary>;
+    /// Stores this user's guild order; guilds left out keep their position.
+    async fn reorder_guilds(&self, guild_ids: Vec<GuildId>) -> Result<()>;
+    /// Deletes a guild; `confirm_name` must repeat the guild's current name.
+    async fn delete_guild(&self, guild_id: GuildId, confirm_name: &str) -> Result<()>;
+    async fn leave_guild(&self, guild_id: GuildId) -> Result<()>;
+    async fn transfer_ownership(
+        &self,
+        guild_id: GuildId,
+        new_owner_id: UserId,
+    ) -> Result<Vec<MemberSummary>>;
+    /// Kicks, bans, mutes or lifts a ban or mute on another member.
+    async fn moderate_member(
+        &self,
+        guild_id: GuildId,
+        target_user_id: UserId,
+        request: ModerationRequest,
+    ) -> Result<()>;
+    async fn list_invites(&self, guild_id: GuildId) -> Result<Vec<InviteSummary>>;
+    async fn delete_invite(&self, guild_id: GuildId, invite_code: &str) -> Result<()>;
+    /// Fetches one page of the guild's audit log, newest entries first.
+    async fn audit_log(
+        &self,
+        guild_id: GuildId,
+        filter: AuditLogFilter,
+    ) -> Result<Vec<AuditLogEntry>>;
+    /// Fetches the guild's attachment storage usage and quotas; needs `MANAGE_GUILD`.
+    async fn storage_usage(&self, guild_id: GuildId) -> Result<GuildStorageUsage>;
+    async fn create_channel(
+        &self,
+        guild_id: GuildId,
+        request: CreateChannelRequest,
+    ) -> Result<ChannelSummary>;
+    async fn update_channel(
+        &self,
+        channel_id: ChannelId,
+        request: UpdateChannelRequest,
+    ) -> Result<ChannelSummary>;
+    async fn reorder_channels(&self, guild_id: GuildId, channel_ids: Vec<ChannelId>) -> Result<()>;
+    async fn delete_channel(&self, channel_id: ChannelId) -> Result<()>;
+    /// Sets how long the server keeps a channel's messages; needs `MANAGE_CHANNELS`.
+    async fn set_channel_retention(
+        &self,
+        channel_id: ChannelId,
+        retention: RetentionPolicy,
+    ) -> Result<ChannelSummary>;
+    /// Sets the disappearing timer for messages this client sends to `channel_id`; `None`
+    /// turns it off.
+    async fn set_message_expiry(&self, channel_id: ChannelId, expires_in_seconds: Option<u64>);
+    /// Lists a channel's threads, newest first.
+    async fn list_threads(
+        &self,
+        channel_id: ChannelId,
+        include_archived: bool,
+    ) -> Result<Vec<ThreadSummary>>;
+    /// Starts a thread on a message in the selected guild's `channel_id`.
+    async fn create_thread(
+        &self,
+        channel_id: ChannelId,
+        parent_message_id: MessageId,
+        name: &str,
+    ) -> Result<ThreadSummary>;
+    async fn set_thread_archived(
+        &self,
+        thread_id: ThreadId,
+        archived: bool,
+    ) -> Result<ThreadSummary>;
+    async fn fetch_thread_messages(
+        &self,
+        thread_id: ThreadId,
+        limit: u32,
+        before: Option<MessageId>,
+    ) -> Result<Vec<MessagePayload>>;
+    /// Posts to a thread of the selected channel, encrypted with that channel's MLS group.
+    async fn send_thread_message(&self, thread_id: ThreadId, text: &str) -> Result<()>;
     async fn sender_directory(&self) -> HashMap<i64, String>;
     async fn connect_voice_session(&self, options: VoiceConnectOptions) -> Result<()>;
     async fn disconnect_voice_session(&self) -> Result<()>;
@@ -667,6 +862,7 @@ pub trait ClientHandle: Send + Sync {
 
 pub struct RealtimeClient<C: CryptoProvider + 'static> {
     http: Client,
+    ws_connector: Option<Connector>,
     _crypto: C,
     mls_session_manager: Arc<dyn MlsSessionManager>,
     livekit_control_plane: Arc<dyn LiveKitControlPlane>,
@@ -698,11 +894,19 @@ struct RealtimeClientState {
     welcome_sync_retry_after: HashMap<(GuildId, ChannelId), Instant>,
     bootstrap_request_last_sent: HashMap<(GuildId, ChannelId), Instant>,
     pending_outbound_plaintexts: HashMap<String, String>,
+    /// Keys for attachments seen in decrypted messages, so downloads can be opened.
+    attachment_keys: HashMap<FileId, AttachmentKey>,
+    /// Thumbnail file and key for each image attachment that has one.
+    thumbnails: HashMap<FileId, (FileId, AttachmentKey)>,
     voice_session_keys: HashMap<VoiceConnectionKey, CachedVoiceSessionKey>,
     processed_inbound_message_ids: HashSet<(ChannelId, MessageId)>,
     processed_inbound_message_order: VecDeque<(ChannelId, MessageId)>,
     inflight_bootstraps: HashSet<(GuildId, ChannelId)>,
     inflight_inbound_message_ids: HashSet<(ChannelId, MessageId)>,
+    key_transparency: KeyTransparencyState,
+    history_archive: HistoryArchive,
+    history_sharing_channels: HashSet<ChannelId>,
+    message_expiry: HashMap<ChannelId, u64>,
 }
 
 #[derive(Serialize)]
@@ -718,6 +922,10 @@ struct SendMessageHttpRequest {
     user_id: i64,
     guild_id: i64,
     channel_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_in_seconds: Option<u64>,
     ciphertext_b64: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     attachment: Option<AttachmentPayload>,
@@ -757,15 +965,51 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
         )
     }
 
+    /// Like [`Self::new_with_mls_session_manager`], for servers whose certificate is
+    /// trusted through a custom CA or a pin rather than the public roots.
+    pub fn new_with_server_trust(
+        crypto: C,
+        mls_session_manager: Arc<dyn MlsSessionManager>,
+        server_trust: &ServerTrust,
+    ) -> Result<Arc<Self>> {
+        Ok(Self::new_with_transport(
+            crypto,
+            mls_session_manager,
+            Arc::new(MissingLiveKitControlPlane),
+            Arc::new(MissingLiveKitConnector),
+            server_trust.http_client()?,
+            server_trust.ws_connector()?,
+        ))
+    }
+
     pub fn new_with_dependencies(
         crypto: C,
         mls_session_manager: Arc<dyn MlsSessionManager>,
         livekit_control_plane: Arc<dyn LiveKitControlPlane>,
         livekit_connector: Arc<dyn LiveKitConnectorProvider>,
+    ) -> Arc<Self> {
+        Self::new_with_transport(
+            crypto,
+            mls_session_manager,
+            livekit_control_plane,
+            livekit_connector,
+            Client::new(),
+            None,
+        )
+    }
+
+    fn new_with_transport(
+        crypto: C,
+        mls_session_manager: Arc<dyn MlsSessionManager>,
+        livekit_control_plane: Arc<dyn LiveKitControlPlane>,
+        livekit_connector: Arc<dyn LiveKitConnectorProvider>,
+        http: Client,
+        ws_connector: Option<Connector>,
     ) -> Arc<Self> {
         let (events, _) = broadcast::channel(1024);
         Arc::new(Self {
-            http: Client::new(),
+            http,
+            ws_connector,
             _crypto: crypto,
             mls_session_manager,
             livekit_control_plane,
@@ -785,11 +1029,17 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
                 welcome_sync_retry_after: HashMap::new(),
                 bootstrap_request_last_sent: HashMap::new(),
                 pending_outbound_plaintexts: HashMap::new(),
+                attachment_keys: HashMap::new(),
+                thumbnails: HashMap::new(),
                 voice_session_keys: HashMap::new(),
                 processed_inbound_message_ids: HashSet::new(),
                 processed_inbound_message_order: VecDeque::new(),
                 inflight_bootstraps: HashSet::new(),
                 inflight_inbound_message_ids: HashSet::new(),
+                key_transparency: KeyTransparencyState::default(),
+                history_archive: HistoryArchive::default(),
+                history_sharing_channels: HashSet::new(),
+                message_expiry: HashMap::new(),
             }),
             voice_connection: Mutex::new(None),
             voice_participants: RwLock::new(HashMap::new()),
@@ -1069,7 +1319,12 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
         }
     }
 
-    async fn spawn_ws_events(self: &Arc<Self>, server_url: &str, user_id: i64) -> Result<()> {
+    async fn spawn_ws_events(
+        self: &Arc<Self>,
+        server_url: &str,
+        user_id: i64,
+        device_id: i64,
+    ) -> Result<()> {
         let ws_url = if server_url.starts_with("https://") {
             server_url.replacen("https://", "wss://", 1)
         } else if server_url.starts_with("http://") {
@@ -1077,10 +1332,12 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
         } else {
             return Err(anyhow!("server_url must start with http:// or https://"));
         };
-        let ws_url = format!("{ws_url}/ws?user_id={user_id}");
-        let (ws_stream, _) = connect_async(&ws_url)
-            .await
-            .with_context(|| format!("failed to connect websocket: {ws_url}"))?;
+        // Naming the device lets the server close this socket if the device is revoked.
+        let ws_url = format!("{ws_url}/ws?user_id={user_id}&device_id={device_id}");
+        let (ws_stream, _) =
+            connect_async_tls_with_config(&ws_url, None, false, self.ws_connector.clone())
+                .await
+                .with_context(|| format!("failed to connect websocket: {ws_url}"))?;
         let (_, mut ws_reader) = ws_stream.split();
 
         let client = Arc::clone(self);
@@ -1219,6 +1476,80 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
                                     }
                                 });
                             } else {
+                                match &event {
+                                    ServerEvent::ChannelDeleted {
+                                        guild_id,
+                                        channel_id,
+                                    } => client.forget_channels(*guild_id, Some(*channel_id)).await,
+                                    ServerEvent::GuildDeleted { guild_id } => {
+                                        client.forget_channels(*guild_id, None).await
+                                    }
+                                    ServerEvent::GuildRolesUpdated { guild_id, .. } => {
+                                        let client_clone = Arc::clone(&client);
+                                        let guild_id = *guild_id;
+                                        tokio::spawn(async move {
+                                            client_clone
+                                                .reconcile_guild_channel_memberships(guild_id)
+                                                .await;
+                                        });
+                                    }
+                                    ServerEvent::UserLeft { guild_id, user_id }
+                                    | ServerEvent::UserKicked {
+                                        guild_id,
+                                        target_user_id: user_id,
+                                    }
+                                    | ServerEvent::UserBanned {
+                                        guild_id,
+                                        target_user_id: user_id,
+                                    } => client.handle_user_left(*guild_id, *user_id).await,
+                                    ServerEvent::MessagesExpired {
+                                        channel_id,
+                                        message_ids,
+                                        ..
+                                    } => client
+                                        .inner
+                                        .lock()
+                                        .await
+                                        .history_archive
+                                        .remove(*channel_id, message_ids),
+                                    ServerEvent::DirectChannelUpdated { channel } => {
+                                        let known = client
+                                            .inner
+                                            .lock()
+                                            .await
+                                            .channel_guilds
+                                            .contains_key(&channel.channel.channel_id);
+                                        if !known {
+                                            if let Err(err) =
+                                                client.open_direct_channel(channel).await
+                                            {
+                                                warn!(
+                                                    channel_id = channel.channel.channel_id.0,
+                                                    "dm: failed to open direct channel: {err}"
+                                                );
+                                            }
+                                        }
+                                    }
+                                    _ => {}
+                                }
+                                if let ServerEvent::ChannelUpdated { channel } = &event {
+                                    client.record_channel(channel).await;
+                                    let client_clone = Arc::clone(&client);
+                                    let (guild_id, channel_id) =
+                                        (channel.guild_id, channel.channel_id);
+                                    tokio::spawn(async move {
+                                        if let Err(err) = client_clone
+                                            .reconcile_channel_membership(guild_id, channel_id)
+                                            .await
+                                        {
+                                            warn!(
+                                                guild_id = guild_id.0,
+                                                channel_id = channel_id.0,
+                                                "mls: channel membership reconcile failed: {err}"
+                                            );
+                                        }
+                                    });
+                                }
                                 let _ = client.events.send(ClientEvent::Server(event));
                             }
                         }
@@ -1301,56 +1632,411 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
         if let Some(target_device_id) = target_device_id {
             request = request.query(&[("target_device_id", target_device_id)]);
         }
-        let response: KeyPackageResponse = request.send().await?.error_for_status()?.json().await?;
+        let response: KeyPackageResponse = send_with_backoff(request)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
 
         if response.user_id != user_id || response.guild_id != guild_id.0 {
             return Err(anyhow!("server returned mismatched key package metadata"));
         }
 
+        // Fail closed: a key package is only used once the log shows it belongs to the
+        // device the server says it does.
+        let device_id = response
+            .device_id
+            .ok_or(KeyTransparencyError::MissingDevice)?;
+        let proof = response
+            .transparency
+            .as_ref()
+            .ok_or(KeyTransparencyError::MissingProof)?;
         let key_package_bytes = STANDARD
-            .decode(response.key_package_b64)
+            .decode(&response.key_package_b64)
             .map_err(|e| anyhow!("invalid key package payload from server: {e}"))?;
-        Ok((key_package_bytes, response.device_id.map(|id| id.0)))
+        let key_package_identity = self
+            .mls_session_manager
+            .key_package_public_identity(&key_package_bytes)
+            .await?;
+        if key_package_identity != proof.leaf.device_public_identity {
+            return Err(KeyTransparencyError::IdentityMismatch {
+                user_id,
+                device_id: device_id.0,
+            }
+            .into());
+        }
+        self.verify_key_transparency_proof(&server_url, proof, UserId(user_id), device_id)
+            .await?;
+
+        Ok((key_package_bytes, Some(device_id.0)))
+    }
+
+    /// Largest key transparency tree head this client has verified so far.
+    pub async fn last_key_transparency_tree_head(&self) -> Option<SignedTreeHead> {
+        self.inner
+            .lock()
+            .await
+            .key_transparency
+            .last_tree_head()
+            .cloned()
+    }
+
+    async fn verify_key_transparency_proof(
+        &self,
+        server_url: &str,
+        proof: &KeyTransparencyProof,
+        user_id: UserId,
+        device_id: DeviceId,
+    ) -> Result<()> {
+        self.inner
+            .lock()
+            .await
+            .key_transparency
+            .verify_device_proof(proof, user_id, device_id)?;
+        self.observe_key_transparency_head(server_url, &proof.tree_head)
+            .await
+    }
+
+    /// Checks `head` against the last verified tree head and saves it with the MLS state when
+    /// it advances the log.
+    async fn observe_key_transparency_head(
+        &self,
+        server_url: &str,
+        head: &SignedTreeHead,
+    ) -> Result<()> {
+        let consistency_range = self
+            .inner
+            .lock()
+            .await
+            .key_transparency
+            .consistency_range(head);
+
+        let consistency = match consistency_range {
+            Some((first_size, second_size)) => Some(
+                self.http
+                    .get(format!("{server_url}/transparency/consistency"))
+                    .query(&[("first_size", first_size), ("second_size", second_size)])
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<ConsistencyProofResponse>()
+                    .await?,
+            ),
+            None => None,
+        };
+
+        let state_json = {
+            let mut guard = self.inner.lock().await;
+            let previous_size = guard
+                .key_transparency
+                .last_tree_head()
+                .map(|last| last.tree_size);
+            guard
+                .key_transparency
+                .observe_tree_head(head, consistency.as_ref())
+                .map_err(|error| {
+                    warn!(%error, tree_size = head.tree_size, "key transparency check failed");
+                    error
+                })?;
+            let advanced = previous_size.is_none_or(|size| size < head.tree_size);
+            advanced
+                .then(|| guard.key_transparency.to_json())
+                .transpose()?
+        };
+        if let Some(state_json) = state_json {
+            self.mls_session_manager
+                .save_key_transparency_state(&state_json)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Reloads the pinned log key and last verified tree head saved with the MLS state, then
+    /// checks that the server's current tree head is consistent with them.
+    async fn resume_key_transparency(&self, server_url: &str) -> Result<()> {
+        let state = match self
+            .mls_session_manager
+            .load_key_transparency_state()
+            .await?
+        {
+            Some(state_json) => KeyTransparencyState::from_json(&state_json)?,
+            None => KeyTransparencyState::default(),
+        };
+        let resumed = state.last_tree_head().is_some();
+        self.inner.lock().await.key_transparency = state;
+        if !resumed {
+            return Ok(());
+        }
+
+        let head: SignedTreeHead = self
+            .http
+            .get(format!("{server_url}/transparency/tree_head"))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        self.observe_key_transparency_head(server_url, &head).await
     }
 
     fn guild_id_from_invite(invite_code: &str) -> Option<GuildId> {
         let decoded = URL_SAFE_NO_PAD.decode(invite_code.as_bytes()).ok()?;
         let decoded_text = String::from_utf8(decoded).ok()?;
-        let guild_id = decoded_text.strip_prefix("guild:")?.parse::<i64>().ok()?;
+        let rest = decoded_text.strip_prefix("guild:")?;
+        let guild_id = rest.split(':').next()?.parse::<i64>().ok()?;
         Some(GuildId(guild_id))
     }
 
-    async fn upload_attachment(&self, attachment: AttachmentUpload) -> Result<AttachmentPayload> {
+    /// Encrypts `attachment` under a fresh key and uploads it one sealed chunk per request.
+    /// Failed chunks are retried after asking the server how much arrived, so a dropped
+    /// connection resumes instead of starting over.
+    async fn upload_attachment(
+        &self,
+        attachment: AttachmentUpload,
+    ) -> Result<(AttachmentPayload, AttachmentKey)> {
         let (server_url, user_id, guild_id, channel_id) = self.active_context().await?;
-        let response: FileUploadResponse = self
+        let key = AttachmentKey::generate();
+        let total_bytes = key.ciphertext_len(attachment.bytes.len());
+        let mime_type = attachment
+            .mime_type
+            .clone()
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        let request = self
             .http
-            .post(format!("{server_url}/files/upload"))
+            .post(format!("{server_url}/files/uploads"))
             .query(&[
                 ("user_id", user_id.to_string()),
                 ("guild_id", guild_id.0.to_string()),
                 ("channel_id", channel_id.0.to_string()),
                 ("filename", attachment.filename.clone()),
-                (
-                    "mime_type",
-                    attachment
-                        .mime_type
-                        .clone()
-                        .unwrap_or_else(|| "application/octet-stream".to_string()),
-                ),
+                ("mime_type", mime_type),
             ])
-            .body(attachment.ciphertext)
+            .json(&CreateUploadRequest { total_bytes });
+        let response = send_with_backoff(request).await?;
+        if response.status() == reqwest::StatusCode::PAYLOAD_TOO_LARGE {
+            // Quota rejections say which quota and how much is left; pass that on as is.
+            let error: shared::error::ApiError = response.json().await?;
+            return Err(anyhow!(error.message));
+        }
+        let session: UploadStatus = response.error_for_status()?.json().await?;
+
+        let result = self
+            .upload_attachment_chunks(&server_url, user_id, &session.upload_id, &key, &attachment)
+            .await;
+        let _ = self.events.send(ClientEvent::UploadFinished {
+            upload_id: session.upload_id.clone(),
+        });
+        let response = result?;
+        Ok((
+            AttachmentPayload {
+                file_id: response.file_id,
+                filename: attachment.filename,
+                size_bytes: attachment.bytes.len() as u64,
+                mime_type: attachment.mime_type,
+            },
+            key,
+        ))
+    }
+
+    async fn upload_attachment_chunks(
+        &self,
+        server_url: &str,
+        user_id: i64,
+        upload_id: &str,
+        key: &AttachmentKey,
+        attachment: &AttachmentUpload,
+    ) -> Result<FileUploadResponse> {
+        let upload_url = format!("{server_url}/files/uploads/{upload_id}");
+        let chunk_count = key.chunk_count(attachment.bytes.len());
+        let total_bytes = key.ciphertext_len(attachment.bytes.len());
+        let progress = |sent_bytes: u64| {
+            let _ = self.events.send(ClientEvent::UploadProgress {
+                upload_id: upload_id.to_string(),
+                filename: attachment.filename.clone(),
+                sent_bytes,
+                total_bytes,
+            });
+        };
+        progress(0);
+
+        let mut digest = Sha256::new();
+        let mut index = 0;
+        let mut failures = 0;
+        while index < chunk_count {
+            let sealed = key.seal_chunk(&attachment.bytes, index);
+            let offset = key.ciphertext_offset(index);
+            let sent = async {
+                self.http
+                    .put(&upload_url)
+                    .query(&[("user_id", user_id), ("offset", offset as i64)])
+                    .body(sealed.clone())
+                    .send()
+                    .await?
+                    .error_for_status()
+            }
+            .await;
+            match sent {
+                Ok(_) => {
+                    digest.update(&sealed);
+                    index += 1;
+                    failures = 0;
+                    progress(offset + sealed.len() as u64);
+                }
+                Err(err) => {
+                    failures += 1;
+                    if failures > UPLOAD_RETRY_ATTEMPTS {
+                        return Err(err).context("attachment upload failed");
+                    }
+                    warn!(
+                        upload_id,
+                        offset, failures, "upload: chunk failed, resuming: {err}"
+                    );
+                    tokio::time::sleep(UPLOAD_RETRY_BASE_DELAY * 2u32.pow(failures - 1)).await;
+                    // The chunk may have landed before the connection dropped.
+                    let status = async {
+                        self.http
+                            .get(&upload_url)
+                            .query(&[("user_id", user_id)])
+                            .send()
+                            .await?
+                            .error_for_status()?
+                            .json::<UploadStatus>()
+                            .await
+                    }
+                    .await;
+                    if let Ok(status) = status {
+                        while index < chunk_count
+                            && key.ciphertext_offset(index) < status.received_bytes
+                        {
+                            digest.update(key.seal_chunk(&attachment.bytes, index));
+                            index += 1;
+                        }
+                        progress(status.received_bytes);
+                    }
+                }
+            }
+        }
+
+        let sha256 = digest
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        Ok(self
+            .http
+            .post(format!("{upload_url}/complete"))
+            .query(&[("user_id", user_id)])
+            .json(&CompleteUploadRequest { sha256 })
             .send()
             .await?
             .error_for_status()?
             .json()
-            .await?;
-
-        Ok(AttachmentPayload {
-            file_id: FileId(response.file_id),
-            filename: attachment.filename,
-            size_bytes: response.size_bytes as u64,
-            mime_type: attachment.mime_type,
-        })
+            .await?)
+    }
+
+    /// Uploads `thumbnail` as a file of its own and links it to the attachment `file_id`, so
+    /// the server keeps and deletes the two together.
+    async fn upload_thumbnail(
+        &self,
+        file_id: &FileId,
+        thumbnail: Thumbnail,
+    ) -> Result<EnvelopePreview> {
+        let (uploaded, key) = self
+            .upload_attachment(AttachmentUpload {
+                filename: "thumbnail.jpg".to_string(),
+                mime_type: Some("image/jpeg".to_string()),
+                bytes: thumbnail.jpeg,
+            })
+            .await?;
+        let (server_url, user_id, _device_id) = self.session().await?;
+        self.http
+            .put(format!("{server_url}/files/{}/thumbnail", file_id.0))
+            .query(&[("user_id", user_id)])
+            .json(&LinkThumbnailRequest {
+                thumbnail_file_id: uploaded.file_id.clone(),
+            })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(EnvelopePreview {
+            width: thumbnail.width,
+            height: thumbnail.height,
+            blurhash: thumbnail.blurhash,
+            thumbnail: key.to_envelope(uploaded.file_id),
+        })
+    }
+
+    /// Keeps an attachment's key, and its thumbnail's if it has one, for later downloads.
+    async fn remember_attachment(&self, attachment: &EnvelopeAttachment, key: AttachmentKey) {
+        // A thumbnail key is only ever used for this attachment's thumbnail, so a bad one
+        // cannot affect other files.
+        let preview = attachment.preview.as_deref().and_then(|preview| {
+            match AttachmentKey::from_envelope(&preview.thumbnail) {
+                Ok(thumbnail_key) => Some((preview, thumbnail_key)),
+                Err(err) => {
+                    warn!(file_id = %attachment.file_id.0, "thumbnail: {err}");
+                    None
+                }
+            }
+        });
+        let mut inner = self.inner.lock().await;
+        inner
+            .attachment_keys
+            .insert(attachment.file_id.clone(), key);
+        let Some((preview, thumbnail_key)) = preview else {
+            return;
+        };
+        inner.thumbnails.insert(
+            attachment.file_id.clone(),
+            (preview.thumbnail.file_id.clone(), thumbnail_key),
+        );
+        drop(inner);
+        let _ = self
+            .events
+            .send(ClientEvent::AttachmentPreviewAvailable(AttachmentPreview {
+                file_id: attachment.file_id.clone(),
+                width: preview.width,
+                height: preview.height,
+                blurhash: preview.blurhash.clone(),
+            }));
+    }
+
+    async fn fetch_file(&self, file_id: &FileId) -> Result<Vec<u8>> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        Ok(self
+            .http
+            .get(format!("{server_url}/files/{}", file_id.0))
+            .query(&[("user_id", user_id)])
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec())
+    }
+
+    /// Sends a decrypted message to the UI, unwrapping its envelope and keeping any
+    /// attachment key for later downloads.
+    async fn publish_plaintext(&self, message: &MessagePayload, plaintext: &str) {
+        let envelope = MessageEnvelope::decode(plaintext);
+        if let Some(attachment) = &envelope.attachment {
+            // Only trust a key for the file the message actually carries.
+            let carried = message.attachment.as_ref().map(|payload| &payload.file_id);
+            match AttachmentKey::from_envelope(attachment) {
+                Ok(key) if carried == Some(&attachment.file_id) => {
+                    self.remember_attachment(attachment, key).await;
+                }
+                Ok(_) => warn!(
+                    message_id = message.message_id.0,
+                    "attachment key names a file the message does not carry"
+                ),
+                Err(err) => warn!(message_id = message.message_id.0, "{err}"),
+            }
+        }
+        let _ = self.events.send(ClientEvent::MessageDecrypted {
+            message: message.clone(),
+            plaintext: envelope.text,
+        });
     }
 
     async fn store_pending_welcome(
@@ -1519,7 +2205,7 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
         if let Some(target_user_id) = target_user_id {
             request = request.query(&[("target_user_id", target_user_id)]);
         }
-        request.send().await?.error_for_status()?;
+        send_with_backoff(request).await?.error_for_status()?;
         info!(
             guild_id = guild_id.0,
             channel_id = channel_id.0,
@@ -1681,9 +2367,280 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
             channel_id = channel_id.0,
             "mls: welcome consumed and channel initialized"
         );
+        // The exporter key only matches in the Welcome epoch, so import before any commit.
+        match self.import_shared_history(guild_id, channel_id).await {
+            Ok(imported) if imported > 0 => info!(
+                guild_id = guild_id.0,
+                channel_id = channel_id.0,
+                imported,
+                "mls: imported shared channel history"
+            ),
+            Ok(_) => {}
+            Err(err) => debug!(
+                guild_id = guild_id.0,
+                channel_id = channel_id.0,
+                "mls: shared channel history unavailable: {err}"
+            ),
+        }
         Ok(true)
     }
 
+    async fn share_history_with_new_member(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        target_user_id: i64,
+        target_device_id: Option<i64>,
+    ) -> Result<()> {
+        let entries = {
+            let guard = self.inner.lock().await;
+            if !guard.history_sharing_channels.contains(&channel_id) {
+                return Ok(());
+            }
+            guard.history_archive.entries(channel_id)
+        };
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let (server_url, current_user_id, _device_id) = self.session().await?;
+        let bundle = encode_history_bundle(guild_id, channel_id, target_user_id, entries)?;
+        let ciphertext = self
+            .mls_session_manager
+            .encrypt_application(channel_id, &bundle)
+            .await?;
+        let mut request = self.http.post(format!("{server_url}/mls/history")).query(&[
+            ("user_id", current_user_id),
+            ("guild_id", guild_id.0),
+            ("channel_id", channel_id.0),
+            ("target_user_id", target_user_id),
+        ]);
+        if let Some(target_device_id) = target_device_id {
+            request = request.query(&[("target_device_id", target_device_id)]);
+        }
+        request.body(ciphertext).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Fetches history bundles addressed to this device and merges the ones MLS authenticates
+    /// as sent by the member the server names. Returns the number of entries imported.
+    async fn import_shared_history(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<usize> {
+        let (server_url, user_id, device_id) = self.session().await?;
+        let response = self
+            .http
+            .get(format!("{server_url}/mls/history"))
+            .query(&[
+                ("user_id", user_id),
+                ("guild_id", guild_id.0),
+                ("channel_id", channel_id.0),
+                ("device_id", device_id),
+            ])
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(0);
+        }
+        let bundles: Vec<HistoryBundleResponse> = response.error_for_status()?.json().await?;
+        if bundles.is_empty() {
+            return Ok(0);
+        }
+
+        let mut imported = 0;
+        for bundle in bundles {
+            let opened = match STANDARD.decode(bundle.bundle_b64.as_bytes()) {
+                Ok(ciphertext) => self
+                    .mls_session_manager
+                    .decrypt_application_from_member(channel_id, &ciphertext)
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|(signed_by, plaintext)| {
+                        decode_history_bundle(
+                            &plaintext,
+                            guild_id,
+                            channel_id,
+                            user_id,
+                            bundle.sender_id,
+                            signed_by,
+                        )
+                        .map_err(anyhow::Error::from)
+                    }),
+                Err(err) => Err(HistoryShareError::Malformed(err.to_string()).into()),
+            };
+            match opened {
+                Ok(entries) => {
+                    imported += entries.len();
+                    self.inner.lock().await.history_archive.merge(
+                        channel_id,
+                        bundle.sender_id,
+                        entries,
+                    );
+                }
+                // Bundles sent for an earlier add of this user belong to another epoch.
+                Err(err) => debug!(
+                    guild_id = guild_id.0,
+                    channel_id = channel_id.0,
+                    sender_id = bundle.sender_id.0,
+                    "mls: skipping history bundle: {err}"
+                ),
+            }
+        }
+        Ok(imported)
+    }
+
+    async fn archived_plaintext(&self, message: &MessagePayload) -> Option<String> {
+        self.inner
+            .lock()
+            .await
+            .history_archive
+            .get(message.channel_id, message.message_id, message.sender_id)
+            .map(|entry| entry.plaintext.clone())
+    }
+
+    async fn archive_plaintext(&self, message: &MessagePayload, plaintext: &str) {
+        let mut guard = self.inner.lock().await;
+        guard.history_archive.purge_expired(Utc::now());
+        if message
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+        {
+            return;
+        }
+        guard.history_archive.record(
+            message.channel_id,
+            SharedHistoryEntry {
+                message_id: message.message_id,
+                sender_id: message.sender_id,
+                plaintext: plaintext.to_string(),
+                expires_at: message.expires_at,
+            },
+        );
+    }
+
+    async fn record_channel(&self, channel: &ChannelSummary) {
+        let mut guard = self.inner.lock().await;
+        guard
+            .channel_guilds
+            .insert(channel.channel_id, channel.guild_id);
+        if channel.history_sharing {
+            guard.history_sharing_channels.insert(channel.channel_id);
+        } else {
+            guard.history_sharing_channels.remove(&channel.channel_id);
+        }
+    }
+
+    /// Drops local routing, selection and MLS group state for channels this client can no
+    /// longer reach. Passing `None` forgets every channel of the guild.
+    async fn forget_channels(&self, guild_id: GuildId, channel_id: Option<ChannelId>) {
+        let forgotten = self.forget_channel_routing(guild_id, channel_id).await;
+        for forgotten_id in forgotten {
+            if let Err(err) = self
+                .mls_session_manager
+                .reset_channel_group_state(guild_id, forgotten_id)
+                .await
+            {
+                warn!(
+                    guild_id = guild_id.0,
+                    channel_id = forgotten_id.0,
+                    "mls: failed to reset group state for forgotten channel: {err}"
+                );
+            }
+        }
+    }
+
+    async fn forget_channel_routing(
+        &self,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+    ) -> Vec<ChannelId> {
+        let mut guard = self.inner.lock().await;
+        let forgotten: Vec<ChannelId> = guard
+            .channel_guilds
+            .iter()
+            .filter(|(id, guild)| **guild == guild_id && channel_id.is_none_or(|c| c == **id))
+            .map(|(id, _)| *id)
+            .collect();
+        for forgotten_id in &forgotten {
+            guard.channel_guilds.remove(forgotten_id);
+            guard.history_sharing_channels.remove(forgotten_id);
+            guard
+                .initialized_mls_channels
+                .remove(&(guild_id, *forgotten_id));
+        }
+        if guard
+            .selected_channel
+            .is_some_and(|selected| forgotten.contains(&selected))
+        {
+            guard.selected_channel = None;
+        }
+        if channel_id.is_none() && guard.selected_guild == Some(guild_id) {
+            guard.selected_guild = None;
+        }
+        forgotten
+    }
+
+    /// Joins the MLS group of a direct conversation the way joining a guild does: publish a
+    /// key package in the conversation's scope, then reconcile until a participant adds us.
+    async fn open_direct_channel(self: &Arc<Self>, channel: &DirectChannelSummary) -> Result<()> {
+        let guild_id = channel.channel.guild_id;
+        self.record_channel(&channel.channel).await;
+        self.upload_key_package_for_guild(guild_id).await?;
+        let client = Arc::clone(self);
+        tokio::spawn(async move {
+            for _ in 0..6 {
+                if let Err(err) = client.reconcile_mls_state_for_guild(guild_id).await {
+                    client.emit_mls_failure_event(
+                        MlsFailureCategory::MembershipFetch,
+                        guild_id,
+                        None,
+                        None,
+                        None,
+                        "open_direct_channel.reconcile_retry",
+                        &err,
+                    );
+                }
+                tokio::time::sleep(Duration::from_millis(350)).await;
+            }
+        });
+        Ok(())
+    }
+
+    /// Forgets the guild when this user left it or was removed; otherwise drops the departed member from
+    /// every initialized channel group of the guild.
+    async fn handle_user_left(self: &Arc<Self>, guild_id: GuildId, user_id: UserId) {
+        let (current_user_id, channel_ids) = {
+            let guard = self.inner.lock().await;
+            let channel_ids: Vec<ChannelId> = guard
+                .channel_guilds
+                .iter()
+                .filter(|(_, guild)| **guild == guild_id)
+                .map(|(id, _)| *id)
+                .collect();
+            (guard.user_id, channel_ids)
+        };
+        if current_user_id == Some(user_id.0) {
+            self.forget_channels(guild_id, None).await;
+            return;
+        }
+        for channel_id in channel_ids {
+            let client = Arc::clone(self);
+            tokio::spawn(async move {
+                if let Err(err) = client
+                    .reconcile_channel_membership(guild_id, channel_id)
+                    .await
+                {
+                    warn!(
+                        guild_id = guild_id.0,
+                        channel_id = channel_id.0,
+                        "mls: channel membership reconcile after leave failed: {err}"
+                    );
+                }
+            });
+        }
+    }
+
     async fn mark_welcome_sync_dirty(&self, guild_id: GuildId, channel_id: ChannelId) {
         self.inner
             .lock()
@@ -1843,6 +2800,21 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
         Ok(members)
     }
 
+    /// Members who can view `channel_id`; these are the members its MLS group should hold.
+    async fn fetch_channel_members(&self, channel_id: ChannelId) -> Result<Vec<MemberSummary>> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let members: Vec<MemberSummary> = self
+            .http
+            .get(format!("{server_url}/channels/{}/members", channel_id.0))
+            .query(&[("user_id", user_id)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(members)
+    }
+
     async fn maybe_initialize_local_group_if_leader(
         &self,
         guild_id: GuildId,
@@ -1854,7 +2826,7 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
         }
 
         let (_, current_user_id, _) = self.session().await?;
-        let members = self.fetch_members_for_guild(guild_id).await?;
+        let members = self.fetch_channel_members(channel_id).await?;
 
         let is_leader = members
             .iter()
@@ -1975,7 +2947,8 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
             .await
             .initialized_mls_channels
             .insert((guild_id, channel_id));
-        let members = self.fetch_members_for_guild(guild_id).await?;
+        let members = self.fetch_channel_members(channel_id).await?;
+        let viewer_ids: Vec<i64> = members.iter().map(|member| member.user_id.0).collect();
 
         for member in members {
             if target_user_id.is_some_and(|target| target != member.user_id.0) {
@@ -2163,7 +3136,7 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
             {
                 Ok(outcome) => outcome,
                 Err(err) => {
-                    if is_duplicate_member_add_error(&err) {
+                    if matches!(err, MlsError::DuplicateMember) {
                         info!(
                             guild_id = guild_id.0,
                             channel_id = channel_id.0,
@@ -2234,6 +3207,23 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
                 continue;
             }
 
+            if let Err(err) = self
+                .share_history_with_new_member(
+                    guild_id,
+                    channel_id,
+                    member.user_id.0,
+                    target_key_package_device_id,
+                )
+                .await
+            {
+                warn!(
+                    guild_id = guild_id.0,
+                    channel_id = channel_id.0,
+                    target_user_id = member.user_id.0,
+                    "mls: failed to share channel history with new member: {err}"
+                );
+            }
+
             if let Err(err) = self
                 .store_pending_welcome(
                     guild_id,
@@ -2271,48 +3261,151 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
                 .insert((guild_id, channel_id, member.user_id.0));
         }
 
+        if target_user_id.is_none() {
+            self.remove_channel_non_viewers(guild_id, channel_id, current_user_id, &viewer_ids)
+                .await;
+        }
+
         Ok(())
     }
 
-    async fn maybe_bootstrap_existing_members_if_leader(
+    /// Removes group members who can no longer view the channel, so later epochs are sealed
+    /// against them, and forgets that they were added in case they regain access.
+    async fn remove_channel_non_viewers(
         &self,
         guild_id: GuildId,
         channel_id: ChannelId,
         current_user_id: i64,
-        target_user_id: Option<i64>,
-        target_device_id: Option<i64>,
-    ) -> Result<bool> {
-        if !self.try_begin_bootstrap(guild_id, channel_id).await {
-            info!(
-                guild_id = guild_id.0,
-                channel_id = channel_id.0,
-                "mls: bootstrap already in progress; skipping duplicate trigger"
-            );
-            return Ok(false);
-        }
-
-        let result = async {
-            let members = self
-                .fetch_members_for_guild(guild_id)
-                .await
-                .with_context(|| {
-                    format!("failed to fetch guild members for guild {}", guild_id.0)
-                })?;
-
-            let is_leader = members
-                .iter()
-                .map(|member| member.user_id.0)
-                .min()
-                .is_some_and(|leader_user_id| leader_user_id == current_user_id);
-            if target_user_id.is_none()
-                && self.is_mls_channel_initialized(guild_id, channel_id).await
-            {
-                // Already initialized and this is not a targeted repair request.
-                return Ok(true);
-            }
-            if is_leader {
-                self.maybe_add_existing_members_to_channel_group(
-                    guild_id,
+        viewer_ids: &[i64],
+    ) {
+        let mut retained = viewer_ids.to_vec();
+        retained.push(current_user_id);
+        let commit_bytes = match self
+            .mls_session_manager
+            .retain_members(channel_id, &retained)
+            .await
+        {
+            Ok(Some(commit_bytes)) => commit_bytes,
+            Ok(None) => return,
+            Err(err) => {
+                warn!(
+                    guild_id = guild_id.0,
+                    channel_id = channel_id.0,
+                    "mls: failed to remove members who lost channel access: {err}"
+                );
+                return;
+            }
+        };
+        info!(
+            guild_id = guild_id.0,
+            channel_id = channel_id.0,
+            "mls: removed members who lost channel access"
+        );
+        self.inner
+            .lock()
+            .await
+            .attempted_channel_member_additions
+            .retain(|(g, c, user_id)| {
+                *g != guild_id || *c != channel_id || retained.contains(user_id)
+            });
+        if let Err(err) = self
+            .post_ciphertext_message(guild_id, channel_id, STANDARD.encode(commit_bytes), None)
+            .await
+        {
+            let _ = self.events.send(ClientEvent::Error(format!(
+                "failed to post MLS remove-member commit in guild {} channel {}: {err}",
+                guild_id.0, channel_id.0
+            )));
+        }
+    }
+
+    /// Brings an initialized channel group in line with who can view the channel after its
+    /// overwrites change. Only the elected leader commits the adds and removals.
+    async fn reconcile_channel_membership(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<()> {
+        if !self.is_mls_channel_initialized(guild_id, channel_id).await {
+            return Ok(());
+        }
+        let (_, current_user_id, _) = self.session().await?;
+        let members = self.fetch_channel_members(channel_id).await?;
+        let is_leader = members
+            .iter()
+            .map(|member| member.user_id.0)
+            .min()
+            .is_some_and(|leader_user_id| leader_user_id == current_user_id);
+        if !is_leader {
+            return Ok(());
+        }
+        self.maybe_add_existing_members_to_channel_group(guild_id, channel_id, None, None)
+            .await
+    }
+
+    /// Reconciles every known channel of a guild after its roles change. A role edit can grant
+    /// or revoke VIEW_CHANNEL without any channel's overwrites changing.
+    async fn reconcile_guild_channel_memberships(&self, guild_id: GuildId) {
+        let channel_ids: Vec<ChannelId> = self
+            .inner
+            .lock()
+            .await
+            .channel_guilds
+            .iter()
+            .filter(|(_, mapped_guild_id)| **mapped_guild_id == guild_id)
+            .map(|(channel_id, _)| *channel_id)
+            .collect();
+        for channel_id in channel_ids {
+            if let Err(err) = self
+                .reconcile_channel_membership(guild_id, channel_id)
+                .await
+            {
+                warn!(
+                    guild_id = guild_id.0,
+                    channel_id = channel_id.0,
+                    "mls: channel membership reconcile after role update failed: {err}"
+                );
+            }
+        }
+    }
+
+    async fn maybe_bootstrap_existing_members_if_leader(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        current_user_id: i64,
+        target_user_id: Option<i64>,
+        target_device_id: Option<i64>,
+    ) -> Result<bool> {
+        if !self.try_begin_bootstrap(guild_id, channel_id).await {
+            info!(
+                guild_id = guild_id.0,
+                channel_id = channel_id.0,
+                "mls: bootstrap already in progress; skipping duplicate trigger"
+            );
+            return Ok(false);
+        }
+
+        let result = async {
+            let members = self
+                .fetch_channel_members(channel_id)
+                .await
+                .with_context(|| format!("failed to fetch members of channel {}", channel_id.0))?;
+
+            let is_leader = members
+                .iter()
+                .map(|member| member.user_id.0)
+                .min()
+                .is_some_and(|leader_user_id| leader_user_id == current_user_id);
+            if target_user_id.is_none()
+                && self.is_mls_channel_initialized(guild_id, channel_id).await
+            {
+                // Already initialized and this is not a targeted repair request.
+                return Ok(true);
+            }
+            if is_leader {
+                self.maybe_add_existing_members_to_channel_group(
+                    guild_id,
                     channel_id,
                     target_user_id,
                     target_device_id,
@@ -2435,11 +3528,13 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
 
         // Self-sent messages: emit cached plaintext and mark processed.
         if message.sender_id.0 == user_id {
-            if let Some(plaintext) = pending_plaintext {
-                let _ = self.events.send(ClientEvent::MessageDecrypted {
-                    message: message.clone(),
-                    plaintext,
-                });
+            let plaintext = match pending_plaintext {
+                Some(plaintext) => Some(plaintext),
+                None => self.archived_plaintext(message).await,
+            };
+            if let Some(plaintext) = plaintext {
+                self.archive_plaintext(message, &plaintext).await;
+                self.publish_plaintext(message, &plaintext).await;
             }
             self.mark_message_processed(msg_key).await;
             return Ok(());
@@ -2464,6 +3559,13 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
             }
         }
 
+        // Backlog already seen or shared by an existing member cannot be decrypted again.
+        if let Some(plaintext) = self.archived_plaintext(message).await {
+            self.publish_plaintext(message, &plaintext).await;
+            self.mark_message_processed(msg_key).await;
+            return Ok(());
+        }
+
         let ciphertext = match STANDARD.decode(message.ciphertext_b64.as_bytes()) {
             Ok(c) => c,
             Err(e) => {
@@ -2478,14 +3580,14 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
             }
         };
 
-        let plaintext_bytes = match self
+        let inbound = match self
             .mls_session_manager
             .decrypt_application(message.channel_id, &ciphertext)
             .await
         {
-            Ok(bytes) => bytes,
+            Ok(inbound) => inbound,
 
-            Err(err) if is_wrong_epoch_error(&err) => {
+            Err(MlsError::WrongEpoch) => {
                 warn!(
                     guild_id = guild_id.0,
                     channel_id = message.channel_id.0,
@@ -2543,31 +3645,22 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
                 }
                 DecryptFailureKind::Unexpected => {
                     self.mark_message_processed(msg_key).await;
-                    return Err(err);
+                    return Err(err.into());
                 }
             },
         };
 
-        // Empty plaintext usually means commit/proposal/no-op. However, attachment-only
-        // chat messages intentionally carry empty text and must still be emitted.
-        if plaintext_bytes.is_empty() {
-            if message.attachment.is_some() {
-                let _ = self.events.send(ClientEvent::MessageDecrypted {
-                    message: message.clone(),
-                    plaintext: String::new(),
-                });
+        let plaintext_bytes = match inbound {
+            MlsInbound::Application(bytes) => bytes,
+            MlsInbound::Commit | MlsInbound::Proposal => {
                 self.mark_message_processed(msg_key).await;
                 return Ok(());
             }
-            self.mark_message_processed(msg_key).await;
-            return Ok(());
-        }
+        };
 
         let plaintext = String::from_utf8_lossy(&plaintext_bytes).to_string();
-        let _ = self.events.send(ClientEvent::MessageDecrypted {
-            message: message.clone(),
-            plaintext,
-        });
+        self.archive_plaintext(message, &plaintext).await;
+        self.publish_plaintext(message, &plaintext).await;
 
         self.mark_message_processed(msg_key).await;
 
@@ -2700,6 +3793,7 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
     async fn send_message_with_attachment_impl(
         &self,
         text: &str,
+        thread_id: Option<ThreadId>,
         attachment: Option<AttachmentPayload>,
     ) -> Result<()> {
         let (_server_url, user_id, guild_id, channel_id) = self.active_context().await?;
@@ -2774,10 +3868,19 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
             .mls_session_manager
             .encrypt_application(channel_id, plaintext_bytes)
             .await?;
+        let expires_in_seconds = self
+            .inner
+            .lock()
+            .await
+            .message_expiry
+            .get(&channel_id)
+            .copied();
         let payload = SendMessageHttpRequest {
             user_id,
             guild_id: guild_id.0,
             channel_id: channel_id.0,
+            thread_id: thread_id.map(|thread_id| thread_id.0),
+            expires_in_seconds,
             ciphertext_b64: STANDARD.encode(ciphertext),
             attachment,
         };
@@ -2800,7 +3903,11 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
 
         let websocket_active = { self.inner.lock().await.ws_started };
         if !websocket_active {
-            if let Err(err) = self.fetch_messages_impl(channel_id, 1, None).await {
+            let refreshed = match thread_id {
+                Some(thread_id) => self.fetch_thread_messages_impl(thread_id, 1, None).await,
+                None => self.fetch_messages_impl(channel_id, 1, None).await,
+            };
+            if let Err(err) = refreshed {
                 let _ = self.events.send(ClientEvent::Error(format!(
                     "message sent but local refresh failed without websocket: {err}"
                 )));
@@ -2822,6 +3929,8 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
             user_id,
             guild_id: guild_id.0,
             channel_id: channel_id.0,
+            thread_id: None,
+            expires_in_seconds: None,
             ciphertext_b64,
             attachment,
         };
@@ -2907,15 +4016,41 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
             .error_for_status()?
             .json()
             .await?;
+        self.emit_fetched_messages(&messages).await;
+        Ok(messages)
+    }
+
+    async fn fetch_thread_messages_impl(
+        &self,
+        thread_id: ThreadId,
+        limit: u32,
+        before: Option<MessageId>,
+    ) -> Result<Vec<MessagePayload>> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let messages: Vec<MessagePayload> = self
+            .http
+            .get(format!("{server_url}/threads/{}/messages", thread_id.0))
+            .query(&ListMessagesQuery {
+                user_id,
+                limit: limit.clamp(1, 100),
+                before: before.map(|id| id.0),
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        self.emit_fetched_messages(&messages).await;
+        Ok(messages)
+    }
 
-        for message in &messages {
+    async fn emit_fetched_messages(&self, messages: &[MessagePayload]) {
+        for message in messages {
             self.record_sender_username(message).await;
             if let Err(err) = self.emit_decrypted_message(message).await {
                 let _ = self.events.send(ClientEvent::Error(err.to_string()));
             }
         }
-
-        Ok(messages)
     }
 
     async fn is_mls_channel_initialized(&self, guild_id: GuildId, channel_id: ChannelId) -> bool {
@@ -2928,12 +4063,13 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
 
     async fn post_send_message_payload(&self, payload: SendMessageHttpRequest) -> Result<()> {
         let (server_url, _user_id, _) = self.session().await?;
-        self.http
-            .post(format!("{server_url}/messages"))
-            .json(&payload)
-            .send()
-            .await?
-            .error_for_status()?;
+        send_with_backoff(
+            self.http
+                .post(format!("{server_url}/messages"))
+                .json(&payload),
+        )
+        .await?
+        .error_for_status()?;
         Ok(())
     }
 
@@ -3036,97 +4172,291 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
         }
         Ok(())
     }
-}
-
-fn build_livekit_hkdf_info(guild_id: GuildId, channel_id: ChannelId) -> Vec<u8> {
-    let mut info = Vec::with_capacity(LIVEKIT_E2EE_INFO_PREFIX.len() + 1 + 16);
-    info.extend_from_slice(LIVEKIT_E2EE_INFO_PREFIX);
-    info.push(0);
-    info.extend_from_slice(&guild_id.0.to_be_bytes());
-    info.extend_from_slice(&channel_id.0.to_be_bytes());
-    info
-}
 
-fn zeroize_voice_session_cache(state: &mut RealtimeClientState) {
-    for cached in state.voice_session_keys.values_mut() {
-        cached.key.zeroize();
+    /// Encrypts the local MLS identity and group states under `passphrase` and stores the
+    /// blob on the server, replacing any earlier backup for this user.
+    pub async fn upload_mls_backup(&self, passphrase: &str) -> Result<()> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let backup = self
+            .mls_session_manager
+            .export_encrypted_backup(passphrase)
+            .await?;
+        self.http
+            .put(format!("{server_url}/mls/backup"))
+            .query(&[("user_id", user_id)])
+            .body(backup)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
     }
-    state.voice_session_keys.clear();
-}
 
-fn map_export_error(
-    guild_id: GuildId,
-    channel_id: ChannelId,
-    source: anyhow::Error,
-) -> LiveKitE2eeKeyError {
-    let msg = source.to_string();
-    if is_missing_mls_group_error(&msg) {
-        LiveKitE2eeKeyError::MissingMlsGroup {
-            guild_id: guild_id.0,
-            channel_id: channel_id.0,
-        }
-    } else {
-        LiveKitE2eeKeyError::ExportFailure {
-            guild_id: guild_id.0,
-            channel_id: channel_id.0,
-            source,
-        }
+    /// Logs in after restoring the server-held MLS backup into the local store, so the device
+    /// registers and publishes key packages for the restored identity. Returns the number of
+    /// restored channel groups.
+    pub async fn login_with_mls_backup(
+        self: &Arc<Self>,
+        server_url: &str,
+        username: &str,
+        passphrase: &str,
+    ) -> Result<usize> {
+        self.login_session(server_url, username, Some(passphrase))
+            .await
     }
-}
 
-fn is_missing_mls_group_error(message: &str) -> bool {
-    message.contains("no active MLS group")
-        || message.contains("group not initialized")
-        || message.contains("MLS group not opened")
-        || message.contains("MLS session missing")
-}
+    async fn login_session(
+        self: &Arc<Self>,
+        server_url: &str,
+        username: &str,
+        restore_passphrase: Option<&str>,
+    ) -> Result<usize> {
+        let res = send_with_backoff(self.http.post(format!("{server_url}/login")).json(
+            &LoginRequest {
+                username: username.to_string(),
+            },
+        ))
+        .await?
+        .error_for_status()?;
+        let body: LoginResponse = res.json().await?;
 
-impl<C: CryptoProvider + 'static> RealtimeClient<C> {
-    async fn force_mls_resync_for_channel(&self, guild_id: GuildId, channel_id: ChannelId) {
         {
             let mut guard = self.inner.lock().await;
-            guard
-                .initialized_mls_channels
-                .remove(&(guild_id, channel_id));
+            guard.server_url = Some(server_url.to_string());
+            guard.user_id = Some(body.user_id);
+            guard.device_id = None;
+            guard.selected_guild = None;
+            guard.selected_channel = None;
+            guard.ws_started = false;
+            guard.channel_guilds.clear();
+            guard.sender_directory.clear();
+            guard.pending_outbound_plaintexts.clear();
+            guard.initialized_mls_channels.clear();
+            guard.inflight_welcome_syncs.clear();
+            guard.bootstrap_request_last_sent.clear();
+            guard.attempted_channel_member_additions.clear();
+            guard.processed_inbound_message_ids.clear();
+            guard.processed_inbound_message_order.clear();
+            guard.inflight_bootstraps.clear();
+            guard.inflight_inbound_message_ids.clear();
+
+            zeroize_voice_session_cache(&mut guard);
         }
-        self.mark_welcome_sync_dirty(guild_id, channel_id).await;
-        let _ = self
-            .maybe_join_from_pending_welcome_with_retry(guild_id, channel_id)
-            .await;
-    }
 
-    async fn prewarm_mls_for_guild_channels(
-        &self,
-        guild_id: GuildId,
-        channels: &[ChannelSummary],
-    ) -> Result<()> {
-        let (_, user_id, _) = self.session().await?;
+        // Restoring swaps in the backed-up identity, so it has to happen before that identity
+        // is registered with the server and published in key packages.
+        let restored = match restore_passphrase {
+            Some(passphrase) => self
+                .restore_mls_backup(server_url, body.user_id, passphrase)
+                .await
+                .context("MLS backup restore failed")?,
+            None => 0,
+        };
 
-        for channel in channels {
-            if channel.kind != shared::domain::ChannelKind::Text {
-                continue;
-            }
+        self.resume_key_transparency(server_url).await?;
 
-            let channel_id = channel.channel_id;
+        let device_public_identity = self.mls_session_manager.device_public_identity().await?;
+        let registered_device: RegisteredDeviceResponse = self
+            .http
+            .post(format!("{server_url}/devices/register"))
+            .query(&[("user_id", body.user_id)])
+            .json(&RegisterDeviceRequest {
+                device_name: "desktop".to_string(),
+                device_public_identity,
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
 
-            // 1) Try local restore first (cheap, no network churn)
-            match self
-                .try_restore_mls_channel_from_local_state(guild_id, channel_id)
-                .await
-            {
-                Ok(true) => continue,
-                Ok(false) => {}
-                Err(err) => {
-                    self.emit_mls_failure_event(
-                        MlsFailureCategory::MembershipFetch,
-                        guild_id,
-                        Some(channel_id),
-                        Some(user_id),
-                        None,
-                        "prewarm_mls_for_guild_channels.restore_local_state",
-                        &err,
-                    );
-                }
+        {
+            let mut guard = self.inner.lock().await;
+            guard.device_id = Some(registered_device.device_id);
+        }
+
+        if let Err(err) = self
+            .spawn_ws_events(server_url, body.user_id, registered_device.device_id)
+            .await
+        {
+            let mut guard = self.inner.lock().await;
+            guard.server_url = None;
+            guard.user_id = None;
+            guard.device_id = None;
+            guard.ws_started = false;
+            guard.selected_guild = None;
+            guard.selected_channel = None;
+            guard.channel_guilds.clear();
+            guard.sender_directory.clear();
+            guard.pending_outbound_plaintexts.clear();
+            guard.initialized_mls_channels.clear();
+            guard.inflight_welcome_syncs.clear();
+            guard.bootstrap_request_last_sent.clear();
+            guard.attempted_channel_member_additions.clear();
+            guard.processed_inbound_message_ids.clear();
+            guard.processed_inbound_message_order.clear();
+            guard.inflight_bootstraps.clear();
+            guard.inflight_inbound_message_ids.clear();
+            zeroize_voice_session_cache(&mut guard);
+            return Err(err);
+        }
+
+        {
+            let mut guard = self.inner.lock().await;
+            guard.ws_started = true;
+        }
+
+        let guilds: Vec<GuildSummary> = self
+            .http
+            .get(format!("{server_url}/guilds"))
+            .query(&[("user_id", body.user_id)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        for guild in guilds {
+            self.upload_key_package_for_guild(guild.guild_id).await?;
+        }
+
+        Ok(restored)
+    }
+
+    async fn restore_mls_backup(
+        &self,
+        server_url: &str,
+        user_id: i64,
+        passphrase: &str,
+    ) -> Result<usize> {
+        let backup = self
+            .http
+            .get(format!("{server_url}/mls/backup"))
+            .query(&[("user_id", user_id)])
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let restored = self
+            .mls_session_manager
+            .restore_encrypted_backup(passphrase, &backup)
+            .await?;
+        {
+            let mut guard = self.inner.lock().await;
+            guard.initialized_mls_channels.clear();
+            zeroize_voice_session_cache(&mut guard);
+        }
+        Ok(restored)
+    }
+
+    /// Opts a channel in or out of sharing recent history with members added later. Only
+    /// moderators and owners may change this; the server rejects other callers.
+    pub async fn set_channel_history_sharing(
+        &self,
+        channel_id: ChannelId,
+        enabled: bool,
+    ) -> Result<()> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let channel: ChannelSummary = self
+            .http
+            .post(format!(
+                "{server_url}/channels/{}/history_sharing",
+                channel_id.0
+            ))
+            .query(&[
+                ("user_id", user_id.to_string()),
+                ("enabled", enabled.to_string()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        self.record_channel(&channel).await;
+        Ok(())
+    }
+}
+
+fn build_livekit_hkdf_info(guild_id: GuildId, channel_id: ChannelId) -> Vec<u8> {
+    let mut info = Vec::with_capacity(LIVEKIT_E2EE_INFO_PREFIX.len() + 1 + 16);
+    info.extend_from_slice(LIVEKIT_E2EE_INFO_PREFIX);
+    info.push(0);
+    info.extend_from_slice(&guild_id.0.to_be_bytes());
+    info.extend_from_slice(&channel_id.0.to_be_bytes());
+    info
+}
+
+fn zeroize_voice_session_cache(state: &mut RealtimeClientState) {
+    for cached in state.voice_session_keys.values_mut() {
+        cached.key.zeroize();
+    }
+    state.voice_session_keys.clear();
+}
+
+fn map_export_error(
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    source: MlsError,
+) -> LiveKitE2eeKeyError {
+    match source {
+        MlsError::NotInitialized | MlsError::Unavailable => LiveKitE2eeKeyError::MissingMlsGroup {
+            guild_id: guild_id.0,
+            channel_id: channel_id.0,
+        },
+        source => LiveKitE2eeKeyError::ExportFailure {
+            guild_id: guild_id.0,
+            channel_id: channel_id.0,
+            source: source.into(),
+        },
+    }
+}
+
+impl<C: CryptoProvider + 'static> RealtimeClient<C> {
+    async fn force_mls_resync_for_channel(&self, guild_id: GuildId, channel_id: ChannelId) {
+        {
+            let mut guard = self.inner.lock().await;
+            guard
+                .initialized_mls_channels
+                .remove(&(guild_id, channel_id));
+        }
+        self.mark_welcome_sync_dirty(guild_id, channel_id).await;
+        let _ = self
+            .maybe_join_from_pending_welcome_with_retry(guild_id, channel_id)
+            .await;
+    }
+
+    async fn prewarm_mls_for_guild_channels(
+        &self,
+        guild_id: GuildId,
+        channels: &[ChannelSummary],
+    ) -> Result<()> {
+        let (_, user_id, _) = self.session().await?;
+
+        for channel in channels {
+            if channel.kind == shared::domain::ChannelKind::Voice {
+                continue;
+            }
+
+            let channel_id = channel.channel_id;
+
+            // 1) Try local restore first (cheap, no network churn)
+            match self
+                .try_restore_mls_channel_from_local_state(guild_id, channel_id)
+                .await
+            {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(err) => {
+                    self.emit_mls_failure_event(
+                        MlsFailureCategory::MembershipFetch,
+                        guild_id,
+                        Some(channel_id),
+                        Some(user_id),
+                        None,
+                        "prewarm_mls_for_guild_channels.restore_local_state",
+                        &err,
+                    );
+                }
             }
 
             // 2) Try to consume pending welcome (joiners)
@@ -3238,6 +4568,11 @@ impl<C: CryptoProvider + 'static> RealtimeClient<C> {
                 guard
                     .channel_guilds
                     .insert(channel.channel_id, channel.guild_id);
+                if channel.history_sharing {
+                    guard.history_sharing_channels.insert(channel.channel_id);
+                } else {
+                    guard.history_sharing_channels.remove(&channel.channel_id);
+                }
             }
         }
 
@@ -3254,102 +4589,9 @@ impl<C: CryptoProvider + 'static> ClientHandle for Arc<RealtimeClient<C>> {
         username: &str,
         _password_or_invite: &str,
     ) -> Result<()> {
-        let res = self
-            .http
-            .post(format!("{server_url}/login"))
-            .json(&LoginRequest {
-                username: username.to_string(),
-            })
-            .send()
-            .await?
-            .error_for_status()?;
-        let body: LoginResponse = res.json().await?;
-
-        {
-            let mut guard = self.inner.lock().await;
-            guard.server_url = Some(server_url.to_string());
-            guard.user_id = Some(body.user_id);
-            guard.device_id = None;
-            guard.selected_guild = None;
-            guard.selected_channel = None;
-            guard.ws_started = false;
-            guard.channel_guilds.clear();
-            guard.sender_directory.clear();
-            guard.pending_outbound_plaintexts.clear();
-            guard.initialized_mls_channels.clear();
-            guard.inflight_welcome_syncs.clear();
-            guard.bootstrap_request_last_sent.clear();
-            guard.attempted_channel_member_additions.clear();
-            guard.processed_inbound_message_ids.clear();
-            guard.processed_inbound_message_order.clear();
-            guard.inflight_bootstraps.clear();
-            guard.inflight_inbound_message_ids.clear();
-
-            zeroize_voice_session_cache(&mut guard);
-        }
-
-        if let Err(err) = self.spawn_ws_events(server_url, body.user_id).await {
-            let mut guard = self.inner.lock().await;
-            guard.server_url = None;
-            guard.user_id = None;
-            guard.device_id = None;
-            guard.ws_started = false;
-            guard.selected_guild = None;
-            guard.selected_channel = None;
-            guard.channel_guilds.clear();
-            guard.sender_directory.clear();
-            guard.pending_outbound_plaintexts.clear();
-            guard.initialized_mls_channels.clear();
-            guard.inflight_welcome_syncs.clear();
-            guard.bootstrap_request_last_sent.clear();
-            guard.attempted_channel_member_additions.clear();
-            guard.processed_inbound_message_ids.clear();
-            guard.processed_inbound_message_order.clear();
-            guard.inflight_bootstraps.clear();
-            guard.inflight_inbound_message_ids.clear();
-            zeroize_voice_session_cache(&mut guard);
-            return Err(err);
-        }
-
-        {
-            let mut guard = self.inner.lock().await;
-            guard.ws_started = true;
-        }
-
-        let registered_device: RegisteredDeviceResponse = self
-            .http
-            .post(format!("{server_url}/devices/register"))
-            .query(&[("user_id", body.user_id)])
-            .json(&RegisterDeviceRequest {
-                device_name: "desktop".to_string(),
-                device_public_identity: format!("user:{}", body.user_id),
-            })
-            .send()
-            .await?
-            .error_for_status()?
-            .json()
-            .await?;
-
-        {
-            let mut guard = self.inner.lock().await;
-            guard.device_id = Some(registered_device.device_id);
-        }
-
-        let guilds: Vec<GuildSummary> = self
-            .http
-            .get(format!("{server_url}/guilds"))
-            .query(&[("user_id", body.user_id)])
-            .send()
-            .await?
-            .error_for_status()?
-            .json()
-            .await?;
-
-        for guild in guilds {
-            self.upload_key_package_for_guild(guild.guild_id).await?;
-        }
-
-        Ok(())
+        self.login_session(server_url, username, None)
+            .await
+            .map(|_| ())
     }
 
     async fn list_guilds(&self) -> Result<()> {
@@ -3395,7 +4637,12 @@ impl<C: CryptoProvider + 'static> ClientHandle for Arc<RealtimeClient<C>> {
                 guard
                     .channel_guilds
                     .insert(channel.channel_id, channel.guild_id);
-            }
+                if channel.history_sharing {
+                    guard.history_sharing_channels.insert(channel.channel_id);
+                } else {
+                    guard.history_sharing_channels.remove(&channel.channel_id);
+                }
+            }
         }
 
         for channel in &channels {
@@ -3521,7 +4768,8 @@ impl<C: CryptoProvider + 'static> ClientHandle for Arc<RealtimeClient<C>> {
     }
 
     async fn send_message(&self, text: &str) -> Result<()> {
-        self.send_message_with_attachment_impl(text, None).await
+        self.send_message_with_attachment_impl(text, None, None)
+            .await
     }
 
     async fn send_message_with_attachment(
@@ -3532,50 +4780,335 @@ impl<C: CryptoProvider + 'static> ClientHandle for Arc<RealtimeClient<C>> {
         let (_server_url, user_id, guild_id, channel_id) = self.active_context().await?;
         self.ensure_channel_ready_for_send(guild_id, channel_id, user_id)
             .await?;
-        let uploaded = self.upload_attachment(attachment).await?;
-        self.send_message_with_attachment_impl(text, Some(uploaded))
+        // Decoding a large image is slow, so keep it off the async workers.
+        let (attachment, thumbnail) = tokio::task::spawn_blocking(move || {
+            let thumbnail = thumbnails::generate(&attachment.bytes);
+            (attachment, thumbnail)
+        })
+        .await?;
+        let (uploaded, key) = self.upload_attachment(attachment).await?;
+        let mut carried = key.to_envelope(uploaded.file_id.clone());
+        if let Some(thumbnail) = thumbnail {
+            // A missing thumbnail only costs the preview, so the message still goes out.
+            match self.upload_thumbnail(&uploaded.file_id, thumbnail).await {
+                Ok(preview) => carried.preview = Some(Box::new(preview)),
+                Err(err) => warn!(file_id = %uploaded.file_id.0, "thumbnail upload failed: {err}"),
+            }
+        }
+        self.remember_attachment(&carried, key).await;
+        // The file key rides inside the MLS plaintext, so only channel members can open it.
+        let envelope = MessageEnvelope {
+            text: text.to_string(),
+            attachment: Some(carried),
+        };
+        self.send_message_with_attachment_impl(&envelope.encode(), None, Some(uploaded))
             .await
     }
 
     async fn download_file(&self, file_id: FileId) -> Result<Vec<u8>> {
+        let bytes = self.fetch_file(&file_id).await?;
+        let key = self
+            .inner
+            .lock()
+            .await
+            .attachment_keys
+            .get(&file_id)
+            .cloned();
+        match key {
+            Some(key) => Ok(key.open(&bytes)?),
+            // Files uploaded before attachment encryption are stored as sent.
+            None => Ok(bytes),
+        }
+    }
+
+    async fn download_thumbnail(&self, file_id: FileId) -> Result<Option<Vec<u8>>> {
+        let thumbnail = self.inner.lock().await.thumbnails.get(&file_id).cloned();
+        let Some((thumbnail_id, key)) = thumbnail else {
+            return Ok(None);
+        };
+        let bytes = self.fetch_file(&thumbnail_id).await?;
+        Ok(Some(key.open(&bytes)?))
+    }
+
+    async fn create_invite(&self, guild_id: GuildId) -> Result<String> {
         let (server_url, user_id, _device_id) = self.session().await?;
-        let bytes = self
+        let response: InviteResponse = self
             .http
-            .get(format!("{server_url}/files/{}", file_id.0))
+            .post(format!("{server_url}/guilds/{}/invites", guild_id.0))
             .query(&[("user_id", user_id)])
             .send()
             .await?
             .error_for_status()?
-            .bytes()
+            .json()
             .await?;
-        Ok(bytes.to_vec())
+        Ok(response.invite_code)
     }
 
-    async fn create_invite(&self, guild_id: GuildId) -> Result<String> {
+    async fn create_guild(&self, name: &str) -> Result<GuildSummary> {
         let (server_url, user_id, _device_id) = self.session().await?;
-        let response: InviteResponse = self
+        let guild: GuildSummary = self
             .http
-            .post(format!("{server_url}/guilds/{}/invites", guild_id.0))
+            .post(format!("{server_url}/guilds"))
             .query(&[("user_id", user_id)])
+            .json(&CreateGuildRequest {
+                name: name.to_string(),
+            })
             .send()
             .await?
             .error_for_status()?
             .json()
             .await?;
-        Ok(response.invite_code)
+        self.upload_key_package_for_guild(guild.guild_id).await?;
+        Ok(guild)
     }
 
-    async fn join_with_invite(&self, invite_code: &str) -> Result<()> {
+    async fn rename_guild(&self, guild_id: GuildId, name: &str) -> Result<GuildSummary> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let guild: GuildSummary = self
+            .http
+            .patch(format!("{server_url}/guilds/{}", guild_id.0))
+            .query(&[("user_id", user_id)])
+            .json(&UpdateGuildRequest {
+                name: name.to_string(),
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(guild)
+    }
+
+    async fn reorder_guilds(&self, guild_ids: Vec<GuildId>) -> Result<()> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let guilds: Vec<GuildSummary> = self
+            .http
+            .put(format!("{server_url}/guilds/order"))
+            .query(&[("user_id", user_id)])
+            .json(&ReorderGuildsRequest { guild_ids })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        for guild in guilds {
+            let _ = self
+                .events
+                .send(ClientEvent::Server(ServerEvent::GuildUpdated { guild }));
+        }
+        Ok(())
+    }
+
+    async fn delete_guild(&self, guild_id: GuildId, confirm_name: &str) -> Result<()> {
         let (server_url, user_id, _device_id) = self.session().await?;
         self.http
-            .post(format!("{server_url}/guilds/join"))
-            .json(&JoinGuildRequest {
-                user_id,
-                invite_code: invite_code.to_string(),
+            .delete(format!("{server_url}/guilds/{}", guild_id.0))
+            .query(&[("user_id", user_id)])
+            .json(&DeleteGuildRequest {
+                confirm_name: confirm_name.to_string(),
             })
             .send()
             .await?
             .error_for_status()?;
+        self.forget_channels(guild_id, None).await;
+        Ok(())
+    }
+
+    async fn leave_guild(&self, guild_id: GuildId) -> Result<()> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        self.http
+            .post(format!("{server_url}/guilds/{}/leave", guild_id.0))
+            .query(&[("user_id", user_id)])
+            .send()
+            .await?
+            .error_for_status()?;
+        self.forget_channels(guild_id, None).await;
+        Ok(())
+    }
+
+    async fn transfer_ownership(
+        &self,
+        guild_id: GuildId,
+        new_owner_id: UserId,
+    ) -> Result<Vec<MemberSummary>> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let members = self
+            .http
+            .put(format!("{server_url}/guilds/{}/owner", guild_id.0))
+            .query(&[("user_id", user_id)])
+            .json(&TransferOwnershipRequest { new_owner_id })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(members)
+    }
+
+    async fn moderate_member(
+        &self,
+        guild_id: GuildId,
+        target_user_id: UserId,
+        request: ModerationRequest,
+    ) -> Result<()> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        self.http
+            .post(format!(
+                "{server_url}/guilds/{}/members/{}/moderation",
+                guild_id.0, target_user_id.0
+            ))
+            .query(&[("user_id", user_id)])
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn list_invites(&self, guild_id: GuildId) -> Result<Vec<InviteSummary>> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let invites = self
+            .http
+            .get(format!("{server_url}/guilds/{}/invites", guild_id.0))
+            .query(&[("user_id", user_id)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(invites)
+    }
+
+    async fn delete_invite(&self, guild_id: GuildId, invite_code: &str) -> Result<()> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        self.http
+            .delete(format!(
+                "{server_url}/guilds/{}/invites/{invite_code}",
+                guild_id.0
+            ))
+            .query(&[("user_id", user_id)])
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn audit_log(
+        &self,
+        guild_id: GuildId,
+        filter: AuditLogFilter,
+    ) -> Result<Vec<AuditLogEntry>> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let entries = self
+            .http
+            .get(format!("{server_url}/guilds/{}/audit_log", guild_id.0))
+            .query(&[("user_id", user_id)])
+            .query(&filter)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(entries)
+    }
+
+    async fn storage_usage(&self, guild_id: GuildId) -> Result<GuildStorageUsage> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let usage = self
+            .http
+            .get(format!("{server_url}/guilds/{}/storage", guild_id.0))
+            .query(&[("user_id", user_id)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(usage)
+    }
+
+    async fn create_channel(
+        &self,
+        guild_id: GuildId,
+        request: CreateChannelRequest,
+    ) -> Result<ChannelSummary> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let channel: ChannelSummary = self
+            .http
+            .post(format!("{server_url}/guilds/{}/channels", guild_id.0))
+            .query(&[("user_id", user_id)])
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        self.record_channel(&channel).await;
+        Ok(channel)
+    }
+
+    async fn update_channel(
+        &self,
+        channel_id: ChannelId,
+        request: UpdateChannelRequest,
+    ) -> Result<ChannelSummary> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let channel: ChannelSummary = self
+            .http
+            .patch(format!("{server_url}/channels/{}", channel_id.0))
+            .query(&[("user_id", user_id)])
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        self.record_channel(&channel).await;
+        Ok(channel)
+    }
+
+    async fn reorder_channels(&self, guild_id: GuildId, channel_ids: Vec<ChannelId>) -> Result<()> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        self.http
+            .put(format!("{server_url}/guilds/{}/channels/order", guild_id.0))
+            .query(&[("user_id", user_id)])
+            .json(&ReorderChannelsRequest { channel_ids })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn delete_channel(&self, channel_id: ChannelId) -> Result<()> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let guild_id = self
+            .inner
+            .lock()
+            .await
+            .channel_guilds
+            .get(&channel_id)
+            .copied();
+        self.http
+            .delete(format!("{server_url}/channels/{}", channel_id.0))
+            .query(&[("user_id", user_id)])
+            .send()
+            .await?
+            .error_for_status()?;
+        if let Some(guild_id) = guild_id {
+            self.forget_channels(guild_id, Some(channel_id)).await;
+        }
+        Ok(())
+    }
+
+    async fn join_with_invite(&self, invite_code: &str) -> Result<()> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        send_with_backoff(self.http.post(format!("{server_url}/guilds/join")).json(
+            &JoinGuildRequest {
+                user_id,
+                invite_code: invite_code.to_string(),
+            },
+        ))
+        .await?
+        .error_for_status()?;
 
         if let Some(guild_id) = RealtimeClient::<C>::guild_id_from_invite(invite_code) {
             self.upload_key_package_for_guild(guild_id).await?;
@@ -3612,6 +5145,155 @@ impl<C: CryptoProvider + 'static> ClientHandle for Arc<RealtimeClient<C>> {
         Ok(())
     }
 
+    async fn list_direct_channels(&self) -> Result<Vec<DirectChannelSummary>> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let channels: Vec<DirectChannelSummary> = self
+            .http
+            .get(format!("{server_url}/dms"))
+            .query(&[("user_id", user_id)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        for direct in &channels {
+            let guild_id = direct.channel.guild_id;
+            self.record_channel(&direct.channel).await;
+            self.upload_key_package_for_guild(guild_id).await?;
+            if let Err(err) = self
+                .prewarm_mls_for_guild_channels(guild_id, std::slice::from_ref(&direct.channel))
+                .await
+            {
+                let _ = self.events.send(ClientEvent::Error(format!(
+                    "failed to prewarm MLS state for direct channel {}: {err}",
+                    direct.channel.channel_id.0
+                )));
+            }
+        }
+        Ok(channels)
+    }
+
+    async fn create_direct_channel(&self, usernames: Vec<String>) -> Result<DirectChannelSummary> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let channel: DirectChannelSummary = self
+            .http
+            .post(format!("{server_url}/dms"))
+            .query(&[("user_id", user_id)])
+            .json(&CreateDirectChannelRequest { usernames })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        self.open_direct_channel(&channel).await?;
+        Ok(channel)
+    }
+
+    async fn set_channel_retention(
+        &self,
+        channel_id: ChannelId,
+        retention: RetentionPolicy,
+    ) -> Result<ChannelSummary> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let channel: ChannelSummary = self
+            .http
+            .put(format!("{server_url}/channels/{}/retention", channel_id.0))
+            .query(&[("user_id", user_id)])
+            .json(&retention)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        self.record_channel(&channel).await;
+        Ok(channel)
+    }
+
+    async fn set_message_expiry(&self, channel_id: ChannelId, expires_in_seconds: Option<u64>) {
+        let mut guard = self.inner.lock().await;
+        match expires_in_seconds {
+            Some(seconds) => guard.message_expiry.insert(channel_id, seconds),
+            None => guard.message_expiry.remove(&channel_id),
+        };
+    }
+
+    async fn list_threads(
+        &self,
+        channel_id: ChannelId,
+        include_archived: bool,
+    ) -> Result<Vec<ThreadSummary>> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let threads = self
+            .http
+            .get(format!("{server_url}/channels/{}/threads", channel_id.0))
+            .query(&[("user_id", user_id)])
+            .query(&[("include_archived", include_archived)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(threads)
+    }
+
+    async fn create_thread(
+        &self,
+        channel_id: ChannelId,
+        parent_message_id: MessageId,
+        name: &str,
+    ) -> Result<ThreadSummary> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let thread = self
+            .http
+            .post(format!("{server_url}/channels/{}/threads", channel_id.0))
+            .query(&[("user_id", user_id)])
+            .json(&CreateThreadRequest {
+                parent_message_id,
+                name: name.to_string(),
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(thread)
+    }
+
+    async fn set_thread_archived(
+        &self,
+        thread_id: ThreadId,
+        archived: bool,
+    ) -> Result<ThreadSummary> {
+        let (server_url, user_id, _device_id) = self.session().await?;
+        let thread = self
+            .http
+            .put(format!("{server_url}/threads/{}/archived", thread_id.0))
+            .query(&[("user_id", user_id)])
+            .json(&ArchiveThreadRequest { archived })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(thread)
+    }
+
+    async fn fetch_thread_messages(
+        &self,
+        thread_id: ThreadId,
+        limit: u32,
+        before: Option<MessageId>,
+    ) -> Result<Vec<MessagePayload>> {
+        self.fetch_thread_messages_impl(thread_id, limit, before)
+            .await
+    }
+
+    async fn send_thread_message(&self, thread_id: ThreadId, text: &str) -> Result<()> {
+        self.send_message_with_attachment_impl(text, Some(thread_id), None)
+            .await
+    }
+
     async fn sender_directory(&self) -> HashMap<i64, String> {
         let guard = self.inner.lock().await;
         guard.sender_directory.clone()
@@ -0,0 +1,770 @@
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use mls::{MlsStore, PersistedGroupSnapshot, ProviderChanges, ProviderEntry};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use shared::domain::{ChannelId, GuildId};
+use storage::{Storage, StoredMlsAtRestKey, StoredMlsBlob, StoredMlsBlobKind};
+use thiserror::Error;
+use zeroize::Zeroize;
+
+use crate::mls_backup::{derive_key, MlsBackupError, MlsBackupKdfParams};
+
+const SEALED_PREFIX: &[u8] = b"proto-rtc:mls-at-rest:v1\0";
+const WRAPPED_KEY_VERSION: u8 = 1;
+const WRAPPED_KEY_AAD: &[u8] = b"proto-rtc:mls-at-rest-key:v1";
+const KEY_SOURCE_PASSPHRASE: &str = "passphrase";
+const KEY_SOURCE_KEY_FILE: &str = "key_file";
+
+#[derive(Debug, Error)]
+pub enum MlsAtRestError {
+    #[error("local MLS database is encrypted; unlock it with its {key_source}")]
+    Locked { key_source: String },
+    #[error("local MLS database is encrypted with a {expected}, not a {provided}")]
+    KeySourceMismatch { expected: String, provided: String },
+    #[error("wrong passphrase or key file for the local MLS database")]
+    WrongKey,
+    #[error("local MLS database is not encrypted at rest")]
+    NotEncrypted,
+    #[error("unusable MLS key file '{path}': {reason}")]
+    KeyFile { path: String, reason: String },
+    #[error("malformed encrypted MLS state: {0}")]
+    Malformed(String),
+    #[error("MLS at-rest key derivation failed: {0}")]
+    KeyDerivation(String),
+}
+
+/// Secret that unlocks the local MLS database.
+#[derive(Clone)]
+pub enum MlsAtRestKeySource {
+    /// Stretched with Argon2id using the parameters stored next to the wrapped key.
+    Passphrase(String),
+    /// File holding 32 random bytes; created with owner-only permissions when missing.
+    KeyFile(PathBuf),
+}
+
+impl MlsAtRestKeySource {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Passphrase(_) => KEY_SOURCE_PASSPHRASE,
+            Self::KeyFile(_) => KEY_SOURCE_KEY_FILE,
+        }
+    }
+}
+
+impl fmt::Debug for MlsAtRestKeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passphrase(_) => f.write_str("Passphrase(<redacted>)"),
+            Self::KeyFile(path) => f.debug_tuple("KeyFile").field(path).finish(),
+        }
+    }
+}
+
+/// Data key wrapped under the key-encryption key derived from a [`MlsAtRestKeySource`].
+#[derive(Debug, Serialize, Deserialize)]
+struct WrappedDataKeyV1 {
+    version: u8,
+    kdf: Option<MlsBackupKdfParams>,
+    salt_b64: String,
+    nonce_b64: String,
+    wrapped_key_b64: String,
+}
+
+/// Random data key sealing every identity, group and pending join blob in the database.
+pub(crate) struct MlsAtRestCipher {
+    key: [u8; 32],
+}
+
+impl Drop for MlsAtRestCipher {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl MlsAtRestCipher {
+    fn generate() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Self { key }
+    }
+
+    fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, MlsAtRestError> {
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&self.key))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| MlsAtRestError::Malformed("encryption failed".to_string()))?;
+
+        let mut out = Vec::with_capacity(SEALED_PREFIX.len() + nonce.len() + ciphertext.len());
+        out.extend_from_slice(SEALED_PREFIX);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    fn open(&self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, MlsAtRestError> {
+        let body = sealed
+            .strip_prefix(SEALED_PREFIX)
+            .ok_or_else(|| MlsAtRestError::Malformed("row is not sealed".to_string()))?;
+        if body.len() < 12 {
+            return Err(MlsAtRestError::Malformed(
+                "sealed row is truncated".to_string(),
+            ));
+        }
+        let (nonce, ciphertext) = body.split_at(12);
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| MlsAtRestError::Malformed("sealed row failed authentication".to_string()))
+    }
+
+    fn seal_row(&self, row: &mut StoredMlsBlob) -> Result<(), MlsAtRestError> {
+        let (primary_aad, key_material_aad) = row_aads(row);
+        row.primary_blob = self.seal(&primary_aad, &row.primary_blob)?;
+        if seals_key_material(row.kind) {
+            row.key_material_blob = self.seal(&key_material_aad, &row.key_material_blob)?;
+        }
+        Ok(())
+    }
+
+    fn open_row(&self, row: &mut StoredMlsBlob) -> Result<(), MlsAtRestError> {
+        let (primary_aad, key_material_aad) = row_aads(row);
+        row.primary_blob = self.open(&primary_aad, &row.primary_blob)?;
+        if seals_key_material(row.kind) {
+            row.key_material_blob = self.open(&key_material_aad, &row.key_material_blob)?;
+        }
+        Ok(())
+    }
+
+    fn wrap(&self, source: &MlsAtRestKeySource) -> Result<StoredMlsAtRestKey, MlsAtRestError> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let kdf =
+            matches!(source, MlsAtRestKeySource::Passphrase(_)).then(MlsBackupKdfParams::default);
+        let mut kek = key_encryption_key(source, &salt, kdf)?;
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        let wrapped = ChaCha20Poly1305::new(Key::from_slice(&kek))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &self.key,
+                    aad: WRAPPED_KEY_AAD,
+                },
+            )
+            .map_err(|_| MlsAtRestError::Malformed("key wrapping failed".to_string()));
+        kek.zeroize();
+
+        let wrapped_key = serde_json::to_vec(&WrappedDataKeyV1 {
+            version: WRAPPED_KEY_VERSION,
+            kdf,
+            salt_b64: STANDARD.encode(salt),
+            nonce_b64: STANDARD.encode(nonce),
+            wrapped_key_b64: STANDARD.encode(wrapped?),
+        })
+        .map_err(|e| MlsAtRestError::Malformed(e.to_string()))?;
+        Ok(StoredMlsAtRestKey {
+            key_source: source.kind().to_string(),
+            wrapped_key,
+        })
+    }
+
+    fn unwrap(
+        record: &StoredMlsAtRestKey,
+        source: &MlsAtRestKeySource,
+    ) -> Result<Self, MlsAtRestError> {
+        if record.key_source != source.kind() {
+            return Err(MlsAtRestError::KeySourceMismatch {
+                expected: key_source_label(&record.key_source).to_string(),
+                provided: key_source_label(source.kind()).to_string(),
+            });
+        }
+        let envelope: WrappedDataKeyV1 = serde_json::from_slice(&record.wrapped_key)
+            .map_err(|e| MlsAtRestError::Malformed(e.to_string()))?;
+        if envelope.version != WRAPPED_KEY_VERSION {
+            return Err(MlsAtRestError::Malformed(format!(
+                "unsupported wrapped key version {}",
+                envelope.version
+            )));
+        }
+        let salt = decode_field(&envelope.salt_b64, "salt")?;
+        let nonce = decode_field(&envelope.nonce_b64, "nonce")?;
+        if nonce.len() != 12 {
+            return Err(MlsAtRestError::Malformed(
+                "nonce must be 12 bytes".to_string(),
+            ));
+        }
+        let wrapped = decode_field(&envelope.wrapped_key_b64, "wrapped key")?;
+
+        let mut kek = key_encryption_key(source, &salt, envelope.kdf)?;
+        let unwrapped = ChaCha20Poly1305::new(Key::from_slice(&kek)).decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &wrapped,
+                aad: WRAPPED_KEY_AAD,
+            },
+        );
+        kek.zeroize();
+        let mut unwrapped = unwrapped.map_err(|_| MlsAtRestError::WrongKey)?;
+        let key: [u8; 32] = unwrapped
+            .as_slice()
+            .try_into()
+            .map_err(|_| MlsAtRestError::Malformed("data key must be 32 bytes".to_string()))?;
+        unwrapped.zeroize();
+        Ok(Self { key })
+    }
+}
+
+/// Fails with [`MlsAtRestError::Locked`] when the database has been encrypted at rest.
+pub(crate) async fn ensure_not_encrypted(storage: &Storage) -> Result<()> {
+    match storage.load_mls_at_rest_key().await? {
+        Some(record) => Err(MlsAtRestError::Locked {
+            key_source: key_source_label(&record.key_source).to_string(),
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Unwraps the data key of an encrypted database, or encrypts a plaintext database in place
+/// under a fresh data key.
+pub(crate) async fn unlock_or_enroll(
+    storage: &Storage,
+    source: &MlsAtRestKeySource,
+) -> Result<MlsAtRestCipher> {
+    if let Some(record) = storage.load_mls_at_rest_key().await? {
+        return Ok(MlsAtRestCipher::unwrap(&record, source)?);
+    }
+
+    let cipher = MlsAtRestCipher::generate();
+    let record = cipher.wrap(source)?;
+    let mut rows = storage.list_mls_secret_blobs().await?;
+    for row in &mut rows {
+        cipher.seal_row(row)?;
+    }
+    storage.rewrite_mls_secret_blobs(&rows, &record).await?;
+    Ok(cipher)
+}
+
+/// Re-encrypts every row under a new data key wrapped by `new_source`.
+pub(crate) async fn rotate(
+    storage: &Storage,
+    current: &MlsAtRestCipher,
+    new_source: &MlsAtRestKeySource,
+) -> Result<MlsAtRestCipher> {
+    let next = MlsAtRestCipher::generate();
+    let record = next.wrap(new_source)?;
+    let mut rows = storage.list_mls_secret_blobs().await?;
+    for row in &mut rows {
+        current.open_row(row)?;
+        next.seal_row(row)?;
+    }
+    storage.rewrite_mls_secret_blobs(&rows, &record).await?;
+    Ok(next)
+}
+
+/// [`MlsStore`] over [`Storage`] that seals blobs with the at-rest data key when one is set.
+#[derive(Clone)]
+pub(crate) struct ClientMlsStore {
+    storage: Storage,
+    cipher: Arc<RwLock<Option<Arc<MlsAtRestCipher>>>>,
+}
+
+impl ClientMlsStore {
+    pub(crate) fn new(storage: Storage, cipher: Option<MlsAtRestCipher>) -> Self {
+        Self {
+            storage,
+            cipher: Arc::new(RwLock::new(cipher.map(Arc::new))),
+        }
+    }
+
+    pub(crate) fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    pub(crate) fn cipher(&self) -> Option<Arc<MlsAtRestCipher>> {
+        self.cipher.read().expect("at-rest cipher lock").clone()
+    }
+
+    pub(crate) fn replace_cipher(&self, cipher: MlsAtRestCipher) {
+        *self.cipher.write().expect("at-rest cipher lock") = Some(Arc::new(cipher));
+    }
+
+    /// Seals the identity and snapshots as [`MlsStore`] would, then swaps them in for the
+    /// device's current state in one transaction.
+    pub(crate) async fn replace_device_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        identity_bytes: &[u8],
+        groups: Vec<(GuildId, ChannelId, PersistedGroupSnapshot)>,
+    ) -> Result<()> {
+        let identity_bytes = match self.cipher() {
+            Some(cipher) => cipher.seal(&identity_aad(user_id, device_id), identity_bytes)?,
+            None => identity_bytes.to_vec(),
+        };
+        let groups = groups
+            .into_iter()
+            .map(|(guild_id, channel_id, snapshot)| {
+                let aad = blob_aad(
+                    StoredMlsBlobKind::GroupState,
+                    user_id,
+                    device_id,
+                    guild_id,
+                    channel_id,
+                );
+                Ok((guild_id, channel_id, self.seal_snapshot(aad, snapshot)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.storage
+            .replace_mls_state_for_device(user_id, device_id, &identity_bytes, groups)
+            .await
+    }
+
+    fn seal_snapshot(
+        &self,
+        aad_base: Vec<u8>,
+        snapshot: PersistedGroupSnapshot,
+    ) -> Result<PersistedGroupSnapshot> {
+        let Some(cipher) = self.cipher() else {
+            return Ok(snapshot);
+        };
+        Ok(PersistedGroupSnapshot {
+            schema_version: snapshot.schema_version,
+            group_state_blob: cipher.seal(
+                &field_aad(&aad_base, b"group_state"),
+                &snapshot.group_state_blob,
+            )?,
+            key_material_blob: cipher.seal(
+                &field_aad(&aad_base, b"key_material"),
+                &snapshot.key_material_blob,
+            )?,
+        })
+    }
+
+    fn open_snapshot(
+        &self,
+        aad_base: Vec<u8>,
+        snapshot: PersistedGroupSnapshot,
+    ) -> Result<PersistedGroupSnapshot> {
+        let Some(cipher) = self.cipher() else {
+            return Ok(snapshot);
+        };
+        Ok(PersistedGroupSnapshot {
+            schema_version: snapshot.schema_version,
+            group_state_blob: cipher.open(
+                &field_aad(&aad_base, b"group_state"),
+                &snapshot.group_state_blob,
+            )?,
+            key_material_blob: cipher.open(
+                &field_aad(&aad_base, b"key_material"),
+                &snapshot.key_material_blob,
+            )?,
+        })
+    }
+}
+
+#[async_trait]
+impl MlsStore for ClientMlsStore {
+    async fn save_identity_keys(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        identity_bytes: &[u8],
+    ) -> Result<()> {
+        let Some(cipher) = self.cipher() else {
+            return self
+                .storage
+                .save_identity_keys(user_id, device_id, identity_bytes)
+                .await;
+        };
+        let aad = identity_aad(user_id, device_id);
+        let sealed = cipher.seal(&aad, identity_bytes)?;
+        self.storage
+            .save_identity_keys(user_id, device_id, &sealed)
+            .await
+    }
+
+    async fn load_identity_keys(&self, user_id: i64, device_id: &str) -> Result<Option<Vec<u8>>> {
+        let stored = self.storage.load_identity_keys(user_id, device_id).await?;
+        match (stored, self.cipher()) {
+            (Some(sealed), Some(cipher)) => Ok(Some(
+                cipher.open(&identity_aad(user_id, device_id), &sealed)?,
+            )),
+            (stored, _) => Ok(stored),
+        }
+    }
+
+    async fn save_group_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        snapshot: PersistedGroupSnapshot,
+    ) -> Result<()> {
+        let snapshot = self.seal_snapshot(
+            blob_aad(
+                StoredMlsBlobKind::GroupState,
+                user_id,
+                device_id,
+                guild_id,
+                channel_id,
+            ),
+            snapshot,
+        )?;
+        self.storage
+            .save_group_state(user_id, device_id, guild_id, channel_id, snapshot)
+            .await
+    }
+
+    async fn load_group_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<Option<PersistedGroupSnapshot>> {
+        let Some(snapshot) = self
+            .storage
+            .load_group_state(user_id, device_id, guild_id, channel_id)
+            .await?
+        else {
+            return Ok(None);
+        };
+        self.open_snapshot(
+            blob_aad(
+                StoredMlsBlobKind::GroupState,
+                user_id,
+                device_id,
+                guild_id,
+                channel_id,
+            ),
+            snapshot,
+        )
+        .map(Some)
+    }
+
+    async fn save_pending_join_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        snapshot: PersistedGroupSnapshot,
+    ) -> Result<()> {
+        let snapshot = self.seal_snapshot(
+            blob_aad(
+                StoredMlsBlobKind::PendingJoin,
+                user_id,
+                device_id,
+                guild_id,
+                ChannelId(0),
+            ),
+            snapshot,
+        )?;
+        self.storage
+            .save_pending_join_state(user_id, device_id, guild_id, snapshot)
+            .await
+    }
+
+    async fn load_pending_join_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+    ) -> Result<Option<PersistedGroupSnapshot>> {
+        let Some(snapshot) = self
+            .storage
+            .load_pending_join_state(user_id, device_id, guild_id)
+            .await?
+        else {
+            return Ok(None);
+        };
+        self.open_snapshot(
+            blob_aad(
+                StoredMlsBlobKind::PendingJoin,
+                user_id,
+                device_id,
+                guild_id,
+                ChannelId(0),
+            ),
+            snapshot,
+        )
+        .map(Some)
+    }
+
+    async fn clear_pending_join_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+    ) -> Result<()> {
+        self.storage
+            .clear_pending_join_state(user_id, device_id, guild_id)
+            .await
+    }
+
+    async fn load_group_provider_entries(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<Vec<ProviderEntry>> {
+        let entries = self
+            .storage
+            .load_group_provider_entries(user_id, device_id, guild_id, channel_id)
+            .await?;
+        let Some(cipher) = self.cipher() else {
+            return Ok(entries);
+        };
+        let base = blob_aad(
+            StoredMlsBlobKind::GroupProviderEntry,
+            user_id,
+            device_id,
+            guild_id,
+            channel_id,
+        );
+        entries
+            .into_iter()
+            .map(|entry| {
+                let value = cipher.open(&provider_entry_aad(&base, &entry.key), &entry.value)?;
+                Ok(ProviderEntry {
+                    key: entry.key,
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    async fn save_group_state_incremental(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        snapshot: PersistedGroupSnapshot,
+        changes: &ProviderChanges,
+    ) -> Result<()> {
+        let snapshot = self.seal_snapshot(
+            blob_aad(
+                StoredMlsBlobKind::GroupState,
+                user_id,
+                device_id,
+                guild_id,
+                channel_id,
+            ),
+            snapshot,
+        )?;
+        let Some(cipher) = self.cipher() else {
+            return self
+                .storage
+                .save_group_state_incremental(
+                    user_id, device_id, guild_id, channel_id, snapshot, changes,
+                )
+                .await;
+        };
+
+        let base = blob_aad(
+            StoredMlsBlobKind::GroupProviderEntry,
+            user_id,
+            device_id,
+            guild_id,
+            channel_id,
+        );
+        let upserts = changes
+            .upserts
+            .iter()
+            .map(|entry| {
+                Ok(ProviderEntry {
+                    key: entry.key.clone(),
+                    value: cipher.seal(&provider_entry_aad(&base, &entry.key), &entry.value)?,
+                })
+            })
+            .collect::<Result<Vec<_>, MlsAtRestError>>()?;
+        let sealed_changes = ProviderChanges {
+            replace_all: changes.replace_all,
+            upserts,
+            deletes: changes.deletes.clone(),
+        };
+        self.storage
+            .save_group_state_incremental(
+                user_id,
+                device_id,
+                guild_id,
+                channel_id,
+                snapshot,
+                &sealed_changes,
+            )
+            .await
+    }
+}
+
+/// Binds a sealed blob to its row so ciphertexts cannot be swapped between rows or columns.
+fn blob_aad(
+    kind: StoredMlsBlobKind,
+    user_id: i64,
+    device_id: &str,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(SEALED_PREFIX.len() + 29 + device_id.len());
+    out.extend_from_slice(SEALED_PREFIX);
+    out.push(match kind {
+        StoredMlsBlobKind::Identity => 0,
+        StoredMlsBlobKind::GroupState => 1,
+        StoredMlsBlobKind::PendingJoin => 2,
+        StoredMlsBlobKind::GroupProviderEntry => 3,
+    });
+    out.extend_from_slice(&user_id.to_be_bytes());
+    out.extend_from_slice(&(device_id.len() as u32).to_be_bytes());
+    out.extend_from_slice(device_id.as_bytes());
+    out.extend_from_slice(&guild_id.0.to_be_bytes());
+    out.extend_from_slice(&channel_id.0.to_be_bytes());
+    out
+}
+
+fn identity_aad(user_id: i64, device_id: &str) -> Vec<u8> {
+    field_aad(
+        &blob_aad(
+            StoredMlsBlobKind::Identity,
+            user_id,
+            device_id,
+            GuildId(0),
+            ChannelId(0),
+        ),
+        b"identity",
+    )
+}
+
+fn field_aad(base: &[u8], field: &[u8]) -> Vec<u8> {
+    [base, field].concat()
+}
+
+/// Provider entry values are sealed individually; the plaintext entry key is bound as AAD.
+fn provider_entry_aad(base: &[u8], entry_key: &[u8]) -> Vec<u8> {
+    [base, b"provider_entry".as_slice(), entry_key].concat()
+}
+
+/// Identity rows have no key material and provider entry rows keep their lookup key in clear.
+fn seals_key_material(kind: StoredMlsBlobKind) -> bool {
+    !matches!(
+        kind,
+        StoredMlsBlobKind::Identity | StoredMlsBlobKind::GroupProviderEntry
+    )
+}
+
+fn row_aads(row: &StoredMlsBlob) -> (Vec<u8>, Vec<u8>) {
+    if row.kind == StoredMlsBlobKind::Identity {
+        return (identity_aad(row.user_id, &row.device_id), Vec::new());
+    }
+    let base = blob_aad(
+        row.kind,
+        row.user_id,
+        &row.device_id,
+        row.guild_id,
+        row.channel_id,
+    );
+    if row.kind == StoredMlsBlobKind::GroupProviderEntry {
+        return (
+            provider_entry_aad(&base, &row.key_material_blob),
+            Vec::new(),
+        );
+    }
+    (
+        field_aad(&base, b"group_state"),
+        field_aad(&base, b"key_material"),
+    )
+}
+
+fn key_encryption_key(
+    source: &MlsAtRestKeySource,
+    salt: &[u8],
+    kdf: Option<MlsBackupKdfParams>,
+) -> Result<[u8; 32], MlsAtRestError> {
+    match source {
+        MlsAtRestKeySource::Passphrase(passphrase) => {
+            let params = kdf.ok_or_else(|| {
+                MlsAtRestError::Malformed("passphrase key is missing KDF parameters".to_string())
+            })?;
+            derive_key(passphrase, salt, params).map_err(|err| match err {
+                MlsBackupError::KeyDerivation(reason) => MlsAtRestError::KeyDerivation(reason),
+                other => MlsAtRestError::KeyDerivation(other.to_string()),
+            })
+        }
+        MlsAtRestKeySource::KeyFile(path) => load_or_create_key_file(path),
+    }
+}
+
+fn load_or_create_key_file(path: &Path) -> Result<[u8; 32], MlsAtRestError> {
+    let key_file_error = |reason: String| MlsAtRestError::KeyFile {
+        path: path.display().to_string(),
+        reason,
+    };
+
+    if !path.exists() {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(path)
+            .map_err(|e| key_file_error(e.to_string()))?;
+        file.write_all(&key)
+            .and_then(|()| file.sync_all())
+            .map_err(|e| key_file_error(e.to_string()))?;
+        return Ok(key);
+    }
+
+    let mut bytes = fs::read(path).map_err(|e| key_file_error(e.to_string()))?;
+    let key = bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| key_file_error(format!("expected 32 bytes, found {}", bytes.len())));
+    bytes.zeroize();
+    key
+}
+
+fn key_source_label(kind: &str) -> &str {
+    match kind {
+        KEY_SOURCE_PASSPHRASE => "passphrase",
+        KEY_SOURCE_KEY_FILE => "key file",
+        other => other,
+    }
+}
+
+fn decode_field(value: &str, field: &str) -> Result<Vec<u8>, MlsAtRestError> {
+    STANDARD
+        .decode(value)
+        .map_err(|e| MlsAtRestError::Malformed(format!("invalid {field}: {e}")))
+}
@@ -0,0 +1,163 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::Zeroize;
+
+const BACKUP_VERSION: u8 = 1;
+const BACKUP_AAD: &[u8] = b"proto-rtc:mls-state-backup:v1";
+
+#[derive(Debug, Error)]
+pub enum MlsBackupError {
+    #[error("wrong backup passphrase or corrupted backup")]
+    Decryption,
+    #[error("unsupported MLS backup version {0}")]
+    UnsupportedVersion(u8),
+    #[error("malformed MLS backup: {0}")]
+    Malformed(String),
+    #[error("backup key derivation failed: {0}")]
+    KeyDerivation(String),
+    #[error("backup key derivation parameters are out of range: {0:?}")]
+    KdfParamsOutOfRange(MlsBackupKdfParams),
+}
+
+/// Argon2id cost parameters, stored in the backup so restores can re-derive the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MlsBackupKdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for MlsBackupKdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+impl MlsBackupKdfParams {
+    const MEMORY_KIB: std::ops::RangeInclusive<u32> = 1024..=256 * 1024;
+    const ITERATIONS: std::ops::RangeInclusive<u32> = 1..=10;
+    const PARALLELISM: std::ops::RangeInclusive<u32> = 1..=4;
+
+    /// Parameters come back from the server with the blob, so anything far from the
+    /// defaults is refused before Argon2 allocates or spends time on it.
+    fn ensure_in_range(self) -> Result<Self, MlsBackupError> {
+        if Self::MEMORY_KIB.contains(&self.memory_kib)
+            && Self::ITERATIONS.contains(&self.iterations)
+            && Self::PARALLELISM.contains(&self.parallelism)
+        {
+            Ok(self)
+        } else {
+            Err(MlsBackupError::KdfParamsOutOfRange(self))
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedMlsBackupV1 {
+    version: u8,
+    kdf: MlsBackupKdfParams,
+    salt_b64: String,
+    nonce_b64: String,
+    ciphertext_b64: String,
+}
+
+/// Encrypts `plaintext` under a key derived from `passphrase`, returning the opaque blob
+/// that is uploaded to the server.
+pub(crate) fn seal_backup(
+    plaintext: &[u8],
+    passphrase: &str,
+    params: MlsBackupKdfParams,
+) -> Result<Vec<u8>, MlsBackupError> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut key = derive_key(passphrase, &salt, params)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    key.zeroize();
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: BACKUP_AAD,
+            },
+        )
+        .map_err(|_| MlsBackupError::Malformed("encryption failed".to_string()))?;
+
+    serde_json::to_vec(&EncryptedMlsBackupV1 {
+        version: BACKUP_VERSION,
+        kdf: params,
+        salt_b64: STANDARD.encode(salt),
+        nonce_b64: STANDARD.encode(nonce),
+        ciphertext_b64: STANDARD.encode(ciphertext),
+    })
+    .map_err(|e| MlsBackupError::Malformed(e.to_string()))
+}
+
+pub(crate) fn open_backup(blob: &[u8], passphrase: &str) -> Result<Vec<u8>, MlsBackupError> {
+    let envelope: EncryptedMlsBackupV1 =
+        serde_json::from_slice(blob).map_err(|e| MlsBackupError::Malformed(e.to_string()))?;
+    if envelope.version != BACKUP_VERSION {
+        return Err(MlsBackupError::UnsupportedVersion(envelope.version));
+    }
+    let salt = decode_field(&envelope.salt_b64, "salt")?;
+    let nonce = decode_field(&envelope.nonce_b64, "nonce")?;
+    if nonce.len() != 12 {
+        return Err(MlsBackupError::Malformed(
+            "nonce must be 12 bytes".to_string(),
+        ));
+    }
+    let ciphertext = decode_field(&envelope.ciphertext_b64, "ciphertext")?;
+
+    let mut key = derive_key(passphrase, &salt, envelope.kdf)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    key.zeroize();
+    cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &ciphertext,
+                aad: BACKUP_AAD,
+            },
+        )
+        .map_err(|_| MlsBackupError::Decryption)
+}
+
+pub(crate) fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    params: MlsBackupKdfParams,
+) -> Result<[u8; 32], MlsBackupError> {
+    let params = params.ensure_in_range()?;
+    let argon2_params = Params::new(
+        params.memory_kib,
+        params.iterations,
+        params.parallelism,
+        Some(32),
+    )
+    .map_err(|e| MlsBackupError::KeyDerivation(e.to_string()))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, argon2_params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| MlsBackupError::KeyDerivation(e.to_string()))?;
+    Ok(key)
+}
+
+fn decode_field(value: &str, field: &str) -> Result<Vec<u8>, MlsBackupError> {
+    STANDARD
+        .decode(value)
+        .map_err(|e| MlsBackupError::Malformed(format!("invalid {field}: {e}")))
+}
@@ -1,14 +1,26 @@
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
 
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
-use mls::{MlsGroupHandle, MlsIdentity, MlsStore};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use mls::{
+    load_portable_group_state, MlsError, MlsGroupHandle, MlsIdentity, MlsInbound, MlsResult,
+    MlsStore, PersistedGroupSnapshot,
+};
 use serde::{Deserialize, Serialize};
 use shared::domain::{ChannelId, GuildId};
 use storage::Storage;
 use tokio::sync::Mutex;
 
-use crate::{MlsAddMemberOutcome, MlsSessionManager};
+use crate::{
+    mls_at_rest::{self, ClientMlsStore, MlsAtRestCipher, MlsAtRestError, MlsAtRestKeySource},
+    mls_backup::{open_backup, seal_backup, MlsBackupKdfParams},
+    MlsAddMemberOutcome, MlsSessionManager,
+};
 
 type SessionKey = (GuildId, ChannelId);
 
@@ -20,28 +32,80 @@ struct PortableGroupStateV1 {
     key_material_blob: Vec<u8>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct MlsStateBackupV1 {
+    version: u8,
+    user_id: i64,
+    device_id: String,
+    identity_b64: String,
+    groups: Vec<BackedUpGroupState>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackedUpGroupState {
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    schema_version: i32,
+    group_state_blob_b64: String,
+    key_material_blob_b64: String,
+}
+
 pub struct DurableMlsSessionManager {
-    store: Storage,
+    store: ClientMlsStore,
     user_id: i64,
     device_id: String,
-    sessions: Mutex<HashMap<SessionKey, MlsGroupHandle<Storage>>>,
+    sessions: Mutex<HashMap<SessionKey, MlsGroupHandle<ClientMlsStore>>>,
     channel_index: Mutex<HashMap<ChannelId, GuildId>>,
 }
 
 impl DurableMlsSessionManager {
+    /// Opens an unencrypted MLS database. Fails with [`MlsAtRestError::Locked`] if the
+    /// database has been encrypted at rest; use [`Self::initialize_encrypted`] for those.
     pub async fn initialize(
         database_url: &str,
         user_id: i64,
         device_id: impl Into<String>,
     ) -> Result<Arc<Self>> {
+        let storage = Self::open_storage(database_url).await?;
+        mls_at_rest::ensure_not_encrypted(&storage).await?;
+        Self::from_store(ClientMlsStore::new(storage, None), user_id, device_id).await
+    }
+
+    /// Unlocks an encrypted MLS database with `key_source`. A database that is not yet
+    /// encrypted (including one written by [`Self::initialize`]) is encrypted in place first.
+    pub async fn initialize_encrypted(
+        database_url: &str,
+        user_id: i64,
+        device_id: impl Into<String>,
+        key_source: &MlsAtRestKeySource,
+    ) -> Result<Arc<Self>> {
+        let storage = Self::open_storage(database_url).await?;
+        let cipher = mls_at_rest::unlock_or_enroll(&storage, key_source)
+            .await
+            .with_context(|| format!("failed to unlock MLS storage at '{database_url}'"))?;
+        Self::from_store(
+            ClientMlsStore::new(storage, Some(cipher)),
+            user_id,
+            device_id,
+        )
+        .await
+    }
+
+    async fn open_storage(database_url: &str) -> Result<Storage> {
         Self::ensure_sqlite_parent_dirs(database_url).with_context(|| {
             format!("failed to prepare parent directories for '{database_url}'")
         })?;
 
-        let store = Storage::new(database_url)
+        Storage::new(database_url)
             .await
-            .with_context(|| format!("failed to initialize MLS storage at '{database_url}'"))?;
+            .with_context(|| format!("failed to initialize MLS storage at '{database_url}'"))
+    }
 
+    async fn from_store(
+        store: ClientMlsStore,
+        user_id: i64,
+        device_id: impl Into<String>,
+    ) -> Result<Arc<Self>> {
         let manager = Arc::new(Self {
             store,
             user_id,
@@ -106,19 +170,14 @@ impl DurableMlsSessionManager {
         Ok(identity)
     }
 
-    async fn key_for_channel(&self, channel_id: ChannelId) -> Result<SessionKey> {
+    async fn key_for_channel(&self, channel_id: ChannelId) -> MlsResult<SessionKey> {
         let guild_id = self
             .channel_index
             .lock()
             .await
             .get(&channel_id)
             .copied()
-            .ok_or_else(|| {
-                anyhow!(
-                    "MLS group not opened for channel {}; channel state is uninitialized for this session",
-                    channel_id.0
-                )
-            })?;
+            .ok_or(MlsError::NotInitialized)?;
         Ok((guild_id, channel_id))
     }
 
@@ -126,6 +185,21 @@ impl DurableMlsSessionManager {
         Self::sqlite_url_from_path(&base_dir.join("mls_client_state.sqlite3"))
     }
 
+    pub fn is_encrypted_at_rest(&self) -> bool {
+        self.store.cipher().is_some()
+    }
+
+    /// Re-encrypts all local MLS state under a fresh data key unlocked by `new_key_source`.
+    /// Sessions stay open; writes are blocked until the rotation commits.
+    pub async fn rotate_at_rest_key(&self, new_key_source: &MlsAtRestKeySource) -> Result<()> {
+        let _sessions = self.sessions.lock().await;
+        let current: Arc<MlsAtRestCipher> =
+            self.store.cipher().ok_or(MlsAtRestError::NotEncrypted)?;
+        let next = mls_at_rest::rotate(self.store.storage(), &current, new_key_source).await?;
+        self.store.replace_cipher(next);
+        Ok(())
+    }
+
     pub async fn reset_channel_group_state(
         &self,
         guild_id: GuildId,
@@ -141,6 +215,7 @@ impl DurableMlsSessionManager {
         }
 
         self.store
+            .storage()
             .clear_mls_group_state(self.user_id, &self.device_id, guild_id, channel_id)
             .await
     }
@@ -156,10 +231,54 @@ impl DurableMlsSessionManager {
         }
 
         self.store
+            .storage()
             .clear_all_mls_group_states_for_device(self.user_id, &self.device_id)
             .await
     }
 
+    pub async fn export_encrypted_backup_with_params(
+        &self,
+        passphrase: &str,
+        params: MlsBackupKdfParams,
+    ) -> Result<Vec<u8>> {
+        let identity = self.load_or_create_identity().await?;
+        let mut groups = Vec::new();
+        for (guild_id, channel_id) in self
+            .store
+            .storage()
+            .list_group_states_for_device(self.user_id, &self.device_id)
+            .await?
+        {
+            let Some(snapshot) = load_portable_group_state(
+                &self.store,
+                self.user_id,
+                &self.device_id,
+                guild_id,
+                channel_id,
+            )
+            .await?
+            else {
+                continue;
+            };
+            groups.push(BackedUpGroupState {
+                guild_id,
+                channel_id,
+                schema_version: snapshot.schema_version,
+                group_state_blob_b64: STANDARD.encode(snapshot.group_state_blob),
+                key_material_blob_b64: STANDARD.encode(snapshot.key_material_blob),
+            });
+        }
+
+        let plaintext = serde_json::to_vec(&MlsStateBackupV1 {
+            version: 1,
+            user_id: self.user_id,
+            device_id: self.device_id.clone(),
+            identity_b64: STANDARD.encode(identity.to_bytes()?),
+            groups,
+        })?;
+        Ok(seal_backup(&plaintext, passphrase, params)?)
+    }
+
     pub async fn reset_all_mls_state_for_device(&self) -> Result<(u64, u64)> {
         {
             let mut sessions = self.sessions.lock().await;
@@ -171,6 +290,7 @@ impl DurableMlsSessionManager {
         }
 
         self.store
+            .storage()
             .clear_all_mls_state_for_device(self.user_id, &self.device_id)
             .await
     }
@@ -178,7 +298,7 @@ impl DurableMlsSessionManager {
 
 #[async_trait]
 impl MlsSessionManager for DurableMlsSessionManager {
-    async fn key_package_bytes(&self, guild_id: GuildId) -> Result<Vec<u8>> {
+    async fn key_package_bytes(&self, guild_id: GuildId) -> MlsResult<Vec<u8>> {
         let mut sessions = self.sessions.lock().await;
         if let Some(existing_handle) =
             sessions
@@ -191,7 +311,10 @@ impl MlsSessionManager for DurableMlsSessionManager {
         }
         drop(sessions);
 
-        let identity = self.load_or_create_identity().await?;
+        let identity = self
+            .load_or_create_identity()
+            .await
+            .map_err(MlsError::Storage)?;
         let mut handle = MlsGroupHandle::new(
             self.store.clone(),
             self.user_id,
@@ -205,6 +328,13 @@ impl MlsSessionManager for DurableMlsSessionManager {
         handle.key_package_bytes().await
     }
 
+    async fn device_public_identity(&self) -> MlsResult<String> {
+        self.load_or_create_identity()
+            .await
+            .map_err(MlsError::Storage)?
+            .public_fingerprint()
+    }
+
     async fn has_persisted_group_state(
         &self,
         guild_id: GuildId,
@@ -217,16 +347,19 @@ impl MlsSessionManager for DurableMlsSessionManager {
             .is_some())
     }
 
-    async fn open_or_create_group(&self, guild_id: GuildId, channel_id: ChannelId) -> Result<()> {
+    async fn open_or_create_group(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> MlsResult<()> {
         {
             let mut index = self.channel_index.lock().await;
             if let Some(existing_guild) = index.get(&channel_id) {
                 if *existing_guild != guild_id {
-                    return Err(anyhow!(
-                        "channel {} is already bound to guild {} in this MLS session",
-                        channel_id.0,
-                        existing_guild.0
-                    ));
+                    return Err(MlsError::ChannelGuildMismatch {
+                        channel_id: channel_id.0,
+                        guild_id: existing_guild.0,
+                    });
                 }
             }
             index.insert(channel_id, guild_id);
@@ -241,7 +374,10 @@ impl MlsSessionManager for DurableMlsSessionManager {
             }
         }
 
-        let identity = self.load_or_create_identity().await?;
+        let identity = self
+            .load_or_create_identity()
+            .await
+            .map_err(MlsError::Storage)?;
         let mut handle = MlsGroupHandle::new(
             self.store.clone(),
             self.user_id,
@@ -262,53 +398,51 @@ impl MlsSessionManager for DurableMlsSessionManager {
         &self,
         channel_id: ChannelId,
         plaintext: &[u8],
-    ) -> Result<Vec<u8>> {
+    ) -> MlsResult<Vec<u8>> {
         let key = self.key_for_channel(channel_id).await?;
         let mut sessions = self.sessions.lock().await;
-        let handle = sessions.get_mut(&key).ok_or_else(|| {
-            anyhow!(
-                "MLS session missing for guild {} channel {}",
-                key.0 .0,
-                key.1 .0
-            )
-        })?;
-        handle.encrypt_application(plaintext)
+        let handle = sessions.get_mut(&key).ok_or(MlsError::NotInitialized)?;
+        handle.encrypt_application(plaintext).await
     }
 
     async fn decrypt_application(
         &self,
         channel_id: ChannelId,
         ciphertext: &[u8],
-    ) -> Result<Vec<u8>> {
+    ) -> MlsResult<MlsInbound> {
         let key = self.key_for_channel(channel_id).await?;
         let mut sessions = self.sessions.lock().await;
-        let handle = sessions.get_mut(&key).ok_or_else(|| {
-            anyhow!(
-                "MLS session missing for guild {} channel {}",
-                key.0 .0,
-                key.1 .0
-            )
-        })?;
+        let handle = sessions.get_mut(&key).ok_or(MlsError::NotInitialized)?;
         handle.decrypt_application(ciphertext).await
     }
 
+    async fn decrypt_application_from_member(
+        &self,
+        channel_id: ChannelId,
+        ciphertext: &[u8],
+    ) -> MlsResult<(i64, Vec<u8>)> {
+        let key = self.key_for_channel(channel_id).await?;
+        let mut sessions = self.sessions.lock().await;
+        let handle = sessions.get_mut(&key).ok_or(MlsError::NotInitialized)?;
+        let (identity, plaintext) = handle.decrypt_application_with_sender(ciphertext).await?;
+        let sender_id = identity_user_id(&identity).ok_or_else(|| {
+            MlsError::Malformed("sender credential is not a user identity".to_string())
+        })?;
+        Ok((sender_id, plaintext))
+    }
+
     async fn add_member(
         &self,
         channel_id: ChannelId,
         key_package_bytes: &[u8],
-    ) -> Result<MlsAddMemberOutcome> {
+    ) -> MlsResult<MlsAddMemberOutcome> {
         let key = self.key_for_channel(channel_id).await?;
         let mut sessions = self.sessions.lock().await;
-        let handle = sessions.get_mut(&key).ok_or_else(|| {
-            anyhow!(
-                "MLS session missing for guild {} channel {}",
-                key.0 .0,
-                key.1 .0
-            )
-        })?;
+        let handle = sessions.get_mut(&key).ok_or(MlsError::NotInitialized)?;
         let (commit, welcome) = handle.add_member(key_package_bytes).await?;
-        let welcome_bytes =
-            welcome.ok_or_else(|| anyhow!("MLS add_member did not return a welcome"))?;
+        let welcome_bytes = welcome.ok_or_else(|| {
+            MlsError::Protocol("MLS add_member did not return a welcome".to_string())
+        })?;
         Ok(MlsAddMemberOutcome {
             commit_bytes: commit,
             welcome_bytes,
@@ -319,25 +453,40 @@ impl MlsSessionManager for DurableMlsSessionManager {
         &self,
         channel_id: ChannelId,
         key_package_bytes: &[u8],
-    ) -> Result<bool> {
+    ) -> MlsResult<bool> {
         let key = self.key_for_channel(channel_id).await?;
         let mut sessions = self.sessions.lock().await;
-        let handle = sessions.get_mut(&key).ok_or_else(|| {
-            anyhow!(
-                "MLS session missing for guild {} channel {}",
-                key.0 .0,
-                key.1 .0
-            )
-        })?;
+        let handle = sessions.get_mut(&key).ok_or(MlsError::NotInitialized)?;
         handle.group_contains_key_package_identity(key_package_bytes)
     }
 
+    async fn retain_members(
+        &self,
+        channel_id: ChannelId,
+        user_ids: &[i64],
+    ) -> MlsResult<Option<Vec<u8>>> {
+        let key = self.key_for_channel(channel_id).await?;
+        let mut sessions = self.sessions.lock().await;
+        let handle = sessions.get_mut(&key).ok_or(MlsError::NotInitialized)?;
+        let departed: Vec<Vec<u8>> = handle
+            .member_identities()?
+            .into_iter()
+            .filter(|identity| {
+                identity_user_id(identity).is_some_and(|user_id| !user_ids.contains(&user_id))
+            })
+            .collect();
+        if departed.is_empty() {
+            return Ok(None);
+        }
+        handle.remove_members(&departed).await
+    }
+
     async fn join_from_welcome(
         &self,
         guild_id: GuildId,
         channel_id: ChannelId,
         welcome_bytes: &[u8],
-    ) -> Result<()> {
+    ) -> MlsResult<()> {
         {
             let mut index = self.channel_index.lock().await;
             index.insert(channel_id, guild_id);
@@ -354,7 +503,10 @@ impl MlsSessionManager for DurableMlsSessionManager {
             }
         }
 
-        let identity = self.load_or_create_identity().await?;
+        let identity = self
+            .load_or_create_identity()
+            .await
+            .map_err(MlsError::Storage)?;
         let mut handle = MlsGroupHandle::open_for_join(
             self.store.clone(),
             self.user_id,
@@ -377,16 +529,10 @@ impl MlsSessionManager for DurableMlsSessionManager {
         channel_id: ChannelId,
         label: &str,
         len: usize,
-    ) -> Result<Vec<u8>> {
+    ) -> MlsResult<Vec<u8>> {
         let key = self.key_for_channel(channel_id).await?;
         let mut sessions = self.sessions.lock().await;
-        let handle = sessions.get_mut(&key).ok_or_else(|| {
-            anyhow!(
-                "MLS session missing for guild {} channel {}",
-                key.0 .0,
-                key.1 .0
-            )
-        })?;
+        let handle = sessions.get_mut(&key).ok_or(MlsError::NotInitialized)?;
         handle.export_secret(label, len)
     }
 
@@ -403,17 +549,21 @@ impl MlsSessionManager for DurableMlsSessionManager {
         guild_id: GuildId,
         channel_id: ChannelId,
     ) -> Result<Vec<u8>> {
-        let snapshot = self
-            .store
-            .load_group_state(self.user_id, &self.device_id, guild_id, channel_id)
-            .await?
-            .ok_or_else(|| {
-                anyhow!(
-                    "group state not found for guild {} channel {}",
-                    guild_id.0,
-                    channel_id.0
-                )
-            })?;
+        let snapshot = load_portable_group_state(
+            &self.store,
+            self.user_id,
+            &self.device_id,
+            guild_id,
+            channel_id,
+        )
+        .await?
+        .ok_or_else(|| {
+            anyhow!(
+                "group state not found for guild {} channel {}",
+                guild_id.0,
+                channel_id.0
+            )
+        })?;
         Ok(serde_json::to_vec(&PortableGroupStateV1 {
             version: 1,
             schema_version: snapshot.schema_version,
@@ -454,8 +604,109 @@ impl MlsSessionManager for DurableMlsSessionManager {
             let mut sessions = self.sessions.lock().await;
             sessions.remove(&(guild_id, channel_id));
         }
-        self.open_or_create_group(guild_id, channel_id).await
+        Ok(self.open_or_create_group(guild_id, channel_id).await?)
     }
+
+    async fn export_encrypted_backup(&self, passphrase: &str) -> Result<Vec<u8>> {
+        self.export_encrypted_backup_with_params(passphrase, MlsBackupKdfParams::default())
+            .await
+    }
+
+    async fn load_key_transparency_state(&self) -> Result<Option<String>> {
+        self.store
+            .storage()
+            .load_key_transparency_state(self.user_id, &self.device_id)
+            .await
+    }
+
+    async fn save_key_transparency_state(&self, state_json: &str) -> Result<()> {
+        self.store
+            .storage()
+            .save_key_transparency_state(self.user_id, &self.device_id, state_json)
+            .await
+    }
+
+    async fn restore_encrypted_backup(&self, passphrase: &str, backup: &[u8]) -> Result<usize> {
+        let plaintext = open_backup(backup, passphrase)?;
+        let decoded: MlsStateBackupV1 = serde_json::from_slice(&plaintext)?;
+        if decoded.version != 1 {
+            return Err(anyhow!(
+                "unsupported MLS state backup version {}",
+                decoded.version
+            ));
+        }
+        if decoded.user_id != self.user_id {
+            return Err(anyhow!(
+                "MLS state backup belongs to user {}, not user {}",
+                decoded.user_id,
+                self.user_id
+            ));
+        }
+        let identity_bytes = STANDARD
+            .decode(&decoded.identity_b64)
+            .map_err(|e| anyhow!("invalid identity in MLS state backup: {e}"))?;
+        let identity = MlsIdentity::from_bytes(&identity_bytes)
+            .context("MLS state backup contains an unreadable identity")?;
+        if identity_user_id(identity.credential_name()) != Some(self.user_id) {
+            return Err(anyhow!(
+                "MLS state backup identity does not belong to user {}",
+                self.user_id
+            ));
+        }
+
+        // Decode every group before touching local state; a bad backup must not wipe it.
+        let mut groups = Vec::with_capacity(decoded.groups.len());
+        let mut seen = HashSet::new();
+        for group in decoded.groups {
+            if !seen.insert((group.guild_id, group.channel_id)) {
+                return Err(anyhow!(
+                    "MLS state backup lists guild {} channel {} twice",
+                    group.guild_id.0,
+                    group.channel_id.0
+                ));
+            }
+            let group_state_blob = STANDARD
+                .decode(&group.group_state_blob_b64)
+                .map_err(|e| anyhow!("invalid group state in MLS state backup: {e}"))?;
+            let key_material_blob = STANDARD
+                .decode(&group.key_material_blob_b64)
+                .map_err(|e| anyhow!("invalid key material in MLS state backup: {e}"))?;
+            groups.push((
+                group.guild_id,
+                group.channel_id,
+                PersistedGroupSnapshot {
+                    schema_version: group.schema_version,
+                    group_state_blob,
+                    key_material_blob,
+                },
+            ));
+        }
+
+        let restored = groups.len();
+        self.store
+            .replace_device_state(self.user_id, &self.device_id, &identity_bytes, groups)
+            .await?;
+        {
+            let mut sessions = self.sessions.lock().await;
+            sessions.clear();
+        }
+        {
+            let mut index = self.channel_index.lock().await;
+            index.clear();
+        }
+        Ok(restored)
+    }
+}
+
+/// Parses the user id out of a `user:{user_id}:{device_id}` credential identity.
+fn identity_user_id(identity: &[u8]) -> Option<i64> {
+    std::str::from_utf8(identity)
+        .ok()?
+        .strip_prefix("user:")?
+        .split(':')
+        .next()?
+        .parse()
+        .ok()
 }
 
 #[cfg(test)]
@@ -1,10 +1,12 @@
 use super::*;
 use axum::{
-    extract::{Query, State},
+    body::Bytes,
+    extract::{Path, Query, State},
     http::StatusCode,
-    routing::post,
+    routing::{get, post},
     Json, Router,
 };
+use shared::protocol::KeyTransparencyLeaf;
 use tokio::{net::TcpListener, sync::oneshot};
 
 #[derive(Clone)]
@@ -25,6 +27,17 @@ struct UploadCallRecord {
 struct MessageAndUploadServerState {
     message_tx: Arc<Mutex<Option<oneshot::Sender<SendMessageHttpRequest>>>>,
     upload_tx: Arc<Mutex<Option<oneshot::Sender<UploadCallRecord>>>>,
+    upload: Arc<Mutex<FakeUpload>>,
+}
+
+/// The fake session stores every chunk but drops the response to the first one, so the
+/// client has to ask for the status and resume.
+#[derive(Default)]
+struct FakeUpload {
+    total_bytes: u64,
+    received: Vec<u8>,
+    chunk_puts: usize,
+    completed_sha256: Option<String>,
 }
 
 struct TestMlsSessionManager {
@@ -32,13 +45,15 @@ struct TestMlsSessionManager {
     decrypt_plaintext: Vec<u8>,
     add_member_commit: Vec<u8>,
     add_member_welcome: Vec<u8>,
-    fail_with: Option<String>,
+    fail_with: Option<fn() -> MlsError>,
     exported_secret: Vec<u8>,
     joined_welcomes: Arc<Mutex<Vec<Vec<u8>>>>,
     decrypted_ciphertexts: Arc<Mutex<Vec<Vec<u8>>>>,
     has_persisted_group_state: bool,
     open_or_create_calls: Arc<Mutex<u32>>,
     exported_group_state: Vec<u8>,
+    group_member_user_ids: Arc<Mutex<Vec<i64>>>,
+    key_transparency_state: Arc<Mutex<Option<String>>>,
 }
 
 impl TestMlsSessionManager {
@@ -55,22 +70,26 @@ impl TestMlsSessionManager {
             has_persisted_group_state: false,
             open_or_create_calls: Arc::new(Mutex::new(0)),
             exported_group_state: b"group-state".to_vec(),
+            group_member_user_ids: Arc::new(Mutex::new(Vec::new())),
+            key_transparency_state: Arc::new(Mutex::new(None)),
         }
     }
 
-    fn failing(err: impl Into<String>) -> Self {
+    fn failing(err: fn() -> MlsError) -> Self {
         Self {
             encrypt_ciphertext: Vec::new(),
             decrypt_plaintext: Vec::new(),
             add_member_commit: Vec::new(),
             add_member_welcome: Vec::new(),
-            fail_with: Some(err.into()),
+            fail_with: Some(err),
             exported_secret: Vec::new(),
             joined_welcomes: Arc::new(Mutex::new(Vec::new())),
             decrypted_ciphertexts: Arc::new(Mutex::new(Vec::new())),
             has_persisted_group_state: false,
             open_or_create_calls: Arc::new(Mutex::new(0)),
             exported_group_state: Vec::new(),
+            group_member_user_ids: Arc::new(Mutex::new(Vec::new())),
+            key_transparency_state: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -87,27 +106,43 @@ impl TestMlsSessionManager {
 
 #[async_trait]
 impl MlsSessionManager for TestMlsSessionManager {
-    async fn key_package_bytes(&self, _guild_id: GuildId) -> Result<Vec<u8>> {
+    async fn key_package_bytes(&self, _guild_id: GuildId) -> Result<Vec<u8>, MlsError> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err());
         }
         Ok(b"test-key-package".to_vec())
     }
 
+    async fn device_public_identity(&self) -> Result<String, MlsError> {
+        Ok("test-device-identity".to_string())
+    }
+
+    /// Test key packages are just the identity string their leaf logs.
+    async fn key_package_public_identity(
+        &self,
+        key_package_bytes: &[u8],
+    ) -> Result<String, MlsError> {
+        Ok(String::from_utf8_lossy(key_package_bytes).into_owned())
+    }
+
     async fn has_persisted_group_state(
         &self,
         _guild_id: GuildId,
         _channel_id: ChannelId,
     ) -> Result<bool> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err().into());
         }
         Ok(self.has_persisted_group_state)
     }
 
-    async fn open_or_create_group(&self, _guild_id: GuildId, _channel_id: ChannelId) -> Result<()> {
+    async fn open_or_create_group(
+        &self,
+        _guild_id: GuildId,
+        _channel_id: ChannelId,
+    ) -> Result<(), MlsError> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err());
         }
 
         let mut calls = self.open_or_create_calls.lock().await;
@@ -121,7 +156,7 @@ impl MlsSessionManager for TestMlsSessionManager {
         _channel_id: ChannelId,
     ) -> Result<bool> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err().into());
         }
 
         // Default test behavior: nothing to reset.
@@ -133,12 +168,14 @@ impl MlsSessionManager for TestMlsSessionManager {
         &self,
         _channel_id: ChannelId,
         plaintext: &[u8],
-    ) -> Result<Vec<u8>> {
+    ) -> Result<Vec<u8>, MlsError> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err());
         }
         if plaintext.is_empty() {
-            return Err(anyhow!("plaintext must not be empty"));
+            return Err(MlsError::Protocol(
+                "plaintext must not be empty".to_string(),
+            ));
         }
         Ok(self.encrypt_ciphertext.clone())
     }
@@ -147,9 +184,9 @@ impl MlsSessionManager for TestMlsSessionManager {
         &self,
         _channel_id: ChannelId,
         ciphertext: &[u8],
-    ) -> Result<Vec<u8>> {
+    ) -> Result<MlsInbound, MlsError> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err());
         }
 
         self.decrypted_ciphertexts
@@ -157,21 +194,28 @@ impl MlsSessionManager for TestMlsSessionManager {
             .await
             .push(ciphertext.to_vec());
 
-        // Simulate non-application messages (e.g. commit/proposal) by returning empty plaintext.
         if ciphertext == self.add_member_commit.as_slice() {
-            return Ok(Vec::new());
+            return Ok(MlsInbound::Commit);
         }
 
-        Ok(self.decrypt_plaintext.clone())
+        Ok(MlsInbound::Application(self.decrypt_plaintext.clone()))
+    }
+
+    async fn decrypt_application_from_member(
+        &self,
+        _channel_id: ChannelId,
+        _ciphertext: &[u8],
+    ) -> Result<(i64, Vec<u8>), MlsError> {
+        Err(MlsError::Unavailable)
     }
 
     async fn add_member(
         &self,
         _channel_id: ChannelId,
         _key_package_bytes: &[u8],
-    ) -> Result<MlsAddMemberOutcome> {
+    ) -> Result<MlsAddMemberOutcome, MlsError> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err());
         }
 
         Ok(MlsAddMemberOutcome {
@@ -180,13 +224,24 @@ impl MlsSessionManager for TestMlsSessionManager {
         })
     }
 
+    async fn retain_members(
+        &self,
+        _channel_id: ChannelId,
+        user_ids: &[i64],
+    ) -> Result<Option<Vec<u8>>, MlsError> {
+        let mut members = self.group_member_user_ids.lock().await;
+        let before = members.len();
+        members.retain(|user_id| user_ids.contains(user_id));
+        Ok((members.len() != before).then(|| b"remove-commit-generated".to_vec()))
+    }
+
     async fn group_contains_key_package_identity(
         &self,
         _channel_id: ChannelId,
         _key_package_bytes: &[u8],
-    ) -> Result<bool> {
+    ) -> Result<bool, MlsError> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err());
         }
         Ok(false)
     }
@@ -196,9 +251,9 @@ impl MlsSessionManager for TestMlsSessionManager {
         _guild_id: GuildId,
         _channel_id: ChannelId,
         welcome_bytes: &[u8],
-    ) -> Result<()> {
+    ) -> Result<(), MlsError> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err());
         }
 
         self.joined_welcomes
@@ -214,9 +269,9 @@ impl MlsSessionManager for TestMlsSessionManager {
         _channel_id: ChannelId,
         _label: &str,
         _len: usize,
-    ) -> Result<Vec<u8>> {
+    ) -> Result<Vec<u8>, MlsError> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err());
         }
         Ok(self.exported_secret.clone())
     }
@@ -227,7 +282,7 @@ impl MlsSessionManager for TestMlsSessionManager {
         _channel_id: ChannelId,
     ) -> Result<Vec<u8>> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err().into());
         }
         Ok(self.exported_group_state.clone())
     }
@@ -239,10 +294,19 @@ impl MlsSessionManager for TestMlsSessionManager {
         _state_blob: &[u8],
     ) -> Result<()> {
         if let Some(err) = &self.fail_with {
-            return Err(anyhow!(err.clone()));
+            return Err(err().into());
         }
         Ok(())
     }
+
+    async fn load_key_transparency_state(&self) -> Result<Option<String>> {
+        Ok(self.key_transparency_state.lock().await.clone())
+    }
+
+    async fn save_key_transparency_state(&self, state_json: &str) -> Result<()> {
+        *self.key_transparency_state.lock().await = Some(state_json.to_string());
+        Ok(())
+    }
 }
 
 async fn handle_send_message(
@@ -263,10 +327,15 @@ struct UploadQuery {
     mime_type: String,
 }
 
+#[derive(Deserialize)]
+struct ChunkQuery {
+    offset: u64,
+}
+
 #[derive(Serialize)]
 struct UploadResponse {
-    file_id: i64,
-    size_bytes: usize,
+    file_id: String,
+    size_bytes: u64,
 }
 
 async fn handle_send_message_with_upload_state(
@@ -278,10 +347,20 @@ async fn handle_send_message_with_upload_state(
     }
 }
 
-async fn handle_upload(
+fn fake_upload_status(upload: &FakeUpload) -> UploadStatus {
+    UploadStatus {
+        upload_id: "upload-1".to_string(),
+        total_bytes: upload.total_bytes,
+        received_bytes: upload.received.len() as u64,
+        max_chunk_bytes: 8 * 1024 * 1024,
+    }
+}
+
+async fn handle_create_upload(
     State(state): State<MessageAndUploadServerState>,
     Query(q): Query<UploadQuery>,
-) -> Json<UploadResponse> {
+    Json(request): Json<CreateUploadRequest>,
+) -> Json<UploadStatus> {
     if let Some(tx) = state.upload_tx.lock().await.take() {
         let _ = tx.send(UploadCallRecord {
             user_id: q.user_id,
@@ -291,9 +370,46 @@ async fn handle_upload(
             mime_type: q.mime_type,
         });
     }
+    let mut upload = state.upload.lock().await;
+    upload.total_bytes = request.total_bytes;
+    Json(fake_upload_status(&upload))
+}
+
+async fn handle_upload_status(
+    State(state): State<MessageAndUploadServerState>,
+    Path(_upload_id): Path<String>,
+) -> Json<UploadStatus> {
+    Json(fake_upload_status(&*state.upload.lock().await))
+}
+
+async fn handle_upload_chunk(
+    State(state): State<MessageAndUploadServerState>,
+    Path(_upload_id): Path<String>,
+    Query(q): Query<ChunkQuery>,
+    body: Bytes,
+) -> std::result::Result<Json<UploadStatus>, StatusCode> {
+    let mut upload = state.upload.lock().await;
+    if q.offset != upload.received.len() as u64 {
+        return Err(StatusCode::CONFLICT);
+    }
+    upload.received.extend_from_slice(&body);
+    upload.chunk_puts += 1;
+    if upload.chunk_puts == 1 {
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+    Ok(Json(fake_upload_status(&upload)))
+}
+
+async fn handle_complete_upload(
+    State(state): State<MessageAndUploadServerState>,
+    Path(_upload_id): Path<String>,
+    Json(request): Json<CompleteUploadRequest>,
+) -> Json<UploadResponse> {
+    let mut upload = state.upload.lock().await;
+    upload.completed_sha256 = Some(request.sha256);
     Json(UploadResponse {
-        file_id: 77,
-        size_bytes: 20,
+        file_id: "5f0c9a".to_string(),
+        size_bytes: upload.received.len() as u64,
     })
 }
 
@@ -318,6 +434,7 @@ async fn spawn_message_and_upload_server() -> Result<(
     String,
     oneshot::Receiver<SendMessageHttpRequest>,
     oneshot::Receiver<UploadCallRecord>,
+    Arc<Mutex<FakeUpload>>,
 )> {
     std::env::set_var("NO_PROXY", "127.0.0.1,localhost");
     let listener = TcpListener::bind("127.0.0.1:0").await?;
@@ -328,18 +445,28 @@ async fn spawn_message_and_upload_server() -> Result<(
     let state = MessageAndUploadServerState {
         message_tx: Arc::new(Mutex::new(Some(message_tx))),
         upload_tx: Arc::new(Mutex::new(Some(upload_tx))),
+        upload: Arc::new(Mutex::new(FakeUpload::default())),
     };
+    let upload = state.upload.clone();
 
     let app = Router::new()
         .route("/messages", post(handle_send_message_with_upload_state))
-        .route("/files/upload", post(handle_upload))
+        .route("/files/uploads", post(handle_create_upload))
+        .route(
+            "/files/uploads/:upload_id",
+            get(handle_upload_status).put(handle_upload_chunk),
+        )
+        .route(
+            "/files/uploads/:upload_id/complete",
+            post(handle_complete_upload),
+        )
         .with_state(state);
 
     tokio::spawn(async move {
         let _ = axum::serve(listener, app).await;
     });
 
-    Ok((format!("http://{addr}"), message_rx, upload_rx))
+    Ok((format!("http://{addr}"), message_rx, upload_rx, upload))
 }
 
 #[tokio::test]
@@ -379,12 +506,66 @@ async fn send_message_uses_mls_ciphertext_payload() {
     );
 }
 
+#[tokio::test]
+async fn rate_limited_send_waits_for_retry_after_then_retries() {
+    std::env::set_var("NO_PROXY", "127.0.0.1,localhost");
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    let attempts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let server_attempts = Arc::clone(&attempts);
+    let app = Router::new().route(
+        "/messages",
+        post(move |Json(_): Json<SendMessageHttpRequest>| {
+            let attempts = Arc::clone(&server_attempts);
+            async move {
+                if attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                    let error = shared::error::ApiError::new(
+                        shared::error::ErrorCode::RateLimited,
+                        "too many messages; retry later",
+                    )
+                    .with_retry_after(1);
+                    (StatusCode::TOO_MANY_REQUESTS, Json(Some(error)))
+                } else {
+                    (StatusCode::OK, Json(None))
+                }
+            }
+        }),
+    );
+    tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+
+    let client = RealtimeClient::new_with_mls_session_manager(
+        PassthroughCrypto,
+        Arc::new(TestMlsSessionManager::ok(
+            b"mls-ciphertext".to_vec(),
+            Vec::new(),
+        )),
+    );
+    {
+        let mut inner = client.inner.lock().await;
+        inner.server_url = Some(format!("http://{addr}"));
+        inner.user_id = Some(7);
+        inner.device_id = Some(1);
+        inner.selected_guild = Some(GuildId(11));
+        inner.selected_channel = Some(ChannelId(13));
+        inner
+            .initialized_mls_channels
+            .insert((GuildId(11), ChannelId(13)));
+    }
+
+    let started = Instant::now();
+    client.send_message("hello").await.expect("send");
+    assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 2);
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
+
 #[tokio::test]
 async fn send_message_requires_active_mls_state() {
     let (server_url, _payload_rx) = spawn_message_server().await.expect("spawn server");
     let client = RealtimeClient::new_with_mls_session_manager(
         PassthroughCrypto,
-        Arc::new(TestMlsSessionManager::failing("group not initialized")),
+        Arc::new(TestMlsSessionManager::failing(|| MlsError::NotInitialized)),
     );
 
     {
@@ -439,7 +620,7 @@ async fn send_message_restores_persisted_mls_state_automatically() {
 
 #[tokio::test]
 async fn send_message_with_attachment_uploads_then_posts_message_with_attachment_payload() {
-    let (server_url, message_rx, upload_rx) = spawn_message_and_upload_server()
+    let (server_url, message_rx, upload_rx, upload_state) = spawn_message_and_upload_server()
         .await
         .expect("spawn server");
     let client = RealtimeClient::new_with_mls_session_manager(
@@ -468,7 +649,7 @@ async fn send_message_with_attachment_uploads_then_posts_message_with_attachment
             AttachmentUpload {
                 filename: "example.txt".to_string(),
                 mime_type: Some("text/plain".to_string()),
-                ciphertext: b"encrypted-file-bytes".to_vec(),
+                bytes: b"plaintext-file-bytes".to_vec(),
             },
         )
         .await
@@ -487,10 +668,148 @@ async fn send_message_with_attachment_uploads_then_posts_message_with_attachment
         STANDARD.encode("mls-ciphertext-with-attachment".as_bytes())
     );
     let attachment = message.attachment.expect("attachment in message payload");
-    assert_eq!(attachment.file_id, FileId(77));
+    assert_eq!(attachment.file_id, FileId("5f0c9a".to_string()));
     assert_eq!(attachment.filename, "example.txt");
     assert_eq!(attachment.size_bytes, 20);
     assert_eq!(attachment.mime_type.as_deref(), Some("text/plain"));
+
+    let upload_state = upload_state.lock().await;
+    assert_ne!(upload_state.received, b"plaintext-file-bytes");
+    let expected_sha256: String = Sha256::digest(&upload_state.received)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    assert_eq!(upload_state.completed_sha256, Some(expected_sha256));
+    let key = client
+        .inner
+        .lock()
+        .await
+        .attachment_keys
+        .get(&FileId("5f0c9a".to_string()))
+        .cloned()
+        .expect("attachment key kept for downloads");
+    assert_eq!(
+        key.open(&upload_state.received).expect("open"),
+        b"plaintext-file-bytes"
+    );
+}
+
+#[tokio::test]
+async fn attachment_upload_resumes_after_a_dropped_chunk_response() {
+    let (server_url, _message_rx, _upload_rx, upload_state) = spawn_message_and_upload_server()
+        .await
+        .expect("spawn server");
+    let client = RealtimeClient::new(PassthroughCrypto);
+    let mut events = client.subscribe_events();
+    {
+        let mut inner = client.inner.lock().await;
+        inner.server_url = Some(server_url);
+        inner.user_id = Some(7);
+        inner.selected_guild = Some(GuildId(11));
+        inner.selected_channel = Some(ChannelId(13));
+    }
+
+    let plaintext: Vec<u8> = (0..3 * 1024 * 1024 + 5).map(|i| i as u8).collect();
+    let (payload, key) = client
+        .upload_attachment(AttachmentUpload {
+            filename: "large.bin".to_string(),
+            mime_type: None,
+            bytes: plaintext.clone(),
+        })
+        .await
+        .expect("upload");
+
+    assert_eq!(payload.size_bytes, plaintext.len() as u64);
+    let upload_state = upload_state.lock().await;
+    // Four chunks, each stored once even though the first response was lost.
+    assert_eq!(upload_state.chunk_puts, 4);
+    assert_eq!(key.open(&upload_state.received).expect("open"), plaintext);
+
+    let mut last_progress = None;
+    let mut finished = false;
+    while let Ok(event) = events.try_recv() {
+        match event {
+            ClientEvent::UploadProgress {
+                sent_bytes,
+                total_bytes,
+                ..
+            } => last_progress = Some((sent_bytes, total_bytes)),
+            ClientEvent::UploadFinished { .. } => finished = true,
+            _ => {}
+        }
+    }
+    let total = key.ciphertext_len(plaintext.len());
+    assert_eq!(last_progress, Some((total, total)));
+    assert!(finished);
+}
+
+#[test]
+fn attachment_chunks_reject_truncation_and_envelopes_round_trip() {
+    let key = AttachmentKey::generate();
+    let plaintext = vec![9u8; 2 * 1024 * 1024 + 1];
+    let ciphertext: Vec<u8> = (0..key.chunk_count(plaintext.len()))
+        .flat_map(|index| key.seal_chunk(&plaintext, index))
+        .collect();
+    assert_eq!(ciphertext.len() as u64, key.ciphertext_len(plaintext.len()));
+    assert_eq!(key.open(&ciphertext).expect("open"), plaintext);
+    let truncated = &ciphertext[..key.ciphertext_offset(2) as usize];
+    assert_eq!(
+        key.open(truncated),
+        Err(AttachmentCryptoError::Decryption(1))
+    );
+
+    let envelope = MessageEnvelope {
+        text: "caption".to_string(),
+        attachment: Some(key.to_envelope(FileId("0a1b2c".to_string()))),
+    };
+    let decoded = MessageEnvelope::decode(&envelope.encode());
+    assert_eq!(decoded, envelope);
+    let reopened = AttachmentKey::from_envelope(decoded.attachment.as_ref().expect("key"))
+        .expect("key decodes");
+    assert_eq!(reopened.open(&ciphertext).expect("open"), plaintext);
+    assert_eq!(MessageEnvelope::decode("plain text").text, "plain text");
+}
+
+#[test]
+fn image_thumbnails_are_small_jpegs_carried_in_the_envelope() {
+    let image = image::RgbImage::from_fn(1200, 600, |x, y| {
+        image::Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+    });
+    let mut png = std::io::Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, image::ImageFormat::Png)
+        .expect("png");
+    assert!(thumbnails::generate(b"not an image").is_none());
+    let thumbnail = thumbnails::generate(png.get_ref()).expect("thumbnail");
+    assert_eq!((thumbnail.width, thumbnail.height), (1200, 600));
+    let decoded = image::load_from_memory(&thumbnail.jpeg).expect("jpeg");
+    assert_eq!((decoded.width(), decoded.height()), (320, 160));
+
+    let key = AttachmentKey::generate();
+    let thumbnail_key = AttachmentKey::generate();
+    let mut attachment = key.to_envelope(FileId("0a1b2c".to_string()));
+    attachment.preview = Some(Box::new(EnvelopePreview {
+        width: thumbnail.width,
+        height: thumbnail.height,
+        blurhash: thumbnail.blurhash.clone(),
+        thumbnail: thumbnail_key.to_envelope(FileId("3d4e5f".to_string())),
+    }));
+    let envelope = MessageEnvelope {
+        text: String::new(),
+        attachment: Some(attachment),
+    };
+    assert_eq!(MessageEnvelope::decode(&envelope.encode()), envelope);
+
+    let preview = AttachmentPreview {
+        file_id: FileId("0a1b2c".to_string()),
+        width: thumbnail.width,
+        height: thumbnail.height,
+        blurhash: thumbnail.blurhash,
+    };
+    assert_eq!(
+        preview.placeholder_rgba(8, 4).map(|rgba| rgba.len()),
+        Some(8 * 4 * 4)
+    );
 }
 
 fn sample_message() -> MessagePayload {
@@ -501,23 +820,57 @@ fn sample_message() -> MessagePayload {
         sender_username: Some("alice".to_string()),
         ciphertext_b64: STANDARD.encode(b"cipher"),
         attachment: None,
+        thread_id: None,
+        expires_at: None,
         sent_at: "2024-01-01T00:00:00Z".parse().expect("timestamp"),
     }
 }
 
 #[test]
-fn classify_missing_mls_group_errors_from_all_backends() {
-    assert!(is_missing_mls_group_error(
-        "no active MLS group available for channel 9"
-    ));
-    assert!(is_missing_mls_group_error(
-        "MLS group not opened for channel 9"
-    ));
-    assert!(is_missing_mls_group_error(
-        "MLS session missing for guild 1 channel 9"
-    ));
-    assert!(is_missing_mls_group_error("MLS group not initialized"));
-    assert!(!is_missing_mls_group_error("failed to connect to server"));
+fn classify_decrypt_failure_skips_only_expired_secrets() {
+    assert_eq!(
+        classify_decrypt_failure(&MlsError::Decryption(DecryptionFailure::Expired)),
+        DecryptFailureKind::ExpectedHistoricalGap
+    );
+}
+
+#[test]
+fn classify_decrypt_failure_resyncs_on_drift_and_authentication_failures() {
+    for err in [
+        MlsError::WrongEpoch,
+        MlsError::SecretReuse,
+        MlsError::GenerationOutOfBounds,
+        MlsError::Decryption(DecryptionFailure::Aead),
+        MlsError::Decryption(DecryptionFailure::UnknownSender),
+    ] {
+        assert_eq!(
+            classify_decrypt_failure(&err),
+            DecryptFailureKind::EpochDriftResync,
+            "{err:?}"
+        );
+    }
+}
+
+#[test]
+fn classify_decrypt_failure_flags_malformed_ciphertext() {
+    assert_eq!(
+        classify_decrypt_failure(&MlsError::Malformed("trailing data".to_string())),
+        DecryptFailureKind::MalformedCiphertext
+    );
+}
+
+#[test]
+fn classify_decrypt_failure_surfaces_internal_errors() {
+    for err in [
+        MlsError::Decryption(DecryptionFailure::Internal("ratchet too long".to_string())),
+        MlsError::Protocol("merge failed".to_string()),
+    ] {
+        assert_eq!(
+            classify_decrypt_failure(&err),
+            DecryptFailureKind::Unexpected,
+            "{err:?}"
+        );
+    }
 }
 
 #[tokio::test]
@@ -626,8 +979,11 @@ async fn suppresses_non_application_messages_after_decrypt() {
     }
     let mut rx = client.subscribe_events();
 
+    let mut message = sample_message();
+    message.ciphertext_b64 = STANDARD.encode(b"commit-generated");
+
     client
-        .emit_decrypted_message(&sample_message())
+        .emit_decrypted_message(&message)
         .await
         .expect("decrypt should still succeed");
 
@@ -652,7 +1008,7 @@ async fn emits_attachment_only_message_when_plaintext_is_empty() {
 
     let mut message = sample_message();
     message.attachment = Some(AttachmentPayload {
-        file_id: FileId(77),
+        file_id: FileId("5f0c9a".to_string()),
         filename: "example.txt".to_string(),
         size_bytes: 20,
         mime_type: Some("text/plain".to_string()),
@@ -671,7 +1027,7 @@ async fn emits_attachment_only_message_when_plaintext_is_empty() {
             plaintext,
         } => {
             let attachment = emitted.attachment.expect("attachment payload");
-            assert_eq!(attachment.file_id, FileId(77));
+            assert_eq!(attachment.file_id, FileId("5f0c9a".to_string()));
             assert_eq!(attachment.filename, "example.txt");
             assert_eq!(attachment.size_bytes, 20);
             assert_eq!(attachment.mime_type.as_deref(), Some("text/plain"));
@@ -681,6 +1037,205 @@ async fn emits_attachment_only_message_when_plaintext_is_empty() {
     }
 }
 
+#[test]
+fn history_bundle_decodes_only_for_addressed_member_from_its_signer() {
+    let entries = vec![SharedHistoryEntry {
+        message_id: MessageId(7),
+        sender_id: shared::domain::UserId(5),
+        plaintext: "before you joined".to_string(),
+        expires_at: None,
+    }];
+    let bundle =
+        encode_history_bundle(GuildId(11), ChannelId(3), 42, entries.clone()).expect("encode");
+    let sender = shared::domain::UserId(5);
+
+    assert_eq!(
+        decode_history_bundle(&bundle, GuildId(11), ChannelId(3), 42, sender, 5).expect("decode"),
+        entries
+    );
+    assert!(matches!(
+        decode_history_bundle(&bundle, GuildId(11), ChannelId(3), 43, sender, 5),
+        Err(HistoryShareError::WrongRecipient)
+    ));
+    assert!(matches!(
+        decode_history_bundle(&bundle, GuildId(11), ChannelId(4), 42, sender, 5),
+        Err(HistoryShareError::WrongRecipient)
+    ));
+    assert!(matches!(
+        decode_history_bundle(&bundle, GuildId(11), ChannelId(3), 42, sender, 6),
+        Err(HistoryShareError::SenderMismatch {
+            claimed: 5,
+            signed_by: 6
+        })
+    ));
+}
+
+#[test]
+fn history_archive_matches_shared_entries_to_their_sender() {
+    let entry = |sender: i64, plaintext: &str| SharedHistoryEntry {
+        message_id: MessageId(7),
+        sender_id: shared::domain::UserId(sender),
+        plaintext: plaintext.to_string(),
+        expires_at: None,
+    };
+    let mut archive = HistoryArchive::default();
+    // Member 6 shares first and attributes a forged plaintext to member 5.
+    archive.merge(
+        ChannelId(3),
+        shared::domain::UserId(6),
+        vec![entry(5, "forged"), entry(6, "misattributed")],
+    );
+    assert_eq!(
+        archive
+            .get(ChannelId(3), MessageId(7), shared::domain::UserId(6))
+            .map(|entry| entry.plaintext.as_str()),
+        Some("misattributed")
+    );
+
+    // The author's own copy replaces the forged one, and a later forgery cannot undo that.
+    archive.merge(
+        ChannelId(3),
+        shared::domain::UserId(5),
+        vec![entry(5, "honest")],
+    );
+    archive.merge(
+        ChannelId(3),
+        shared::domain::UserId(8),
+        vec![entry(5, "forged again")],
+    );
+    assert_eq!(
+        archive
+            .get(ChannelId(3), MessageId(7), shared::domain::UserId(5))
+            .map(|entry| entry.plaintext.as_str()),
+        Some("honest")
+    );
+
+    // A local decrypt wins over every shared copy.
+    archive.record(ChannelId(3), entry(5, "decrypted"));
+    archive.merge(
+        ChannelId(3),
+        shared::domain::UserId(5),
+        vec![entry(5, "shared later")],
+    );
+    assert!(archive
+        .get(ChannelId(3), MessageId(7), shared::domain::UserId(6))
+        .is_none());
+    assert_eq!(
+        archive
+            .get(ChannelId(3), MessageId(7), shared::domain::UserId(5))
+            .map(|entry| entry.plaintext.as_str()),
+        Some("decrypted")
+    );
+}
+
+#[test]
+fn history_archive_forgets_expired_and_server_purged_plaintext() {
+    let now = Utc::now();
+    let entry = |id: i64, expires_at| SharedHistoryEntry {
+        message_id: MessageId(id),
+        sender_id: shared::domain::UserId(5),
+        plaintext: format!("message {id}"),
+        expires_at,
+    };
+    let mut archive = HistoryArchive::default();
+    archive.record(ChannelId(3), entry(1, None));
+    archive.record(
+        ChannelId(3),
+        entry(2, Some(now - chrono::Duration::seconds(1))),
+    );
+    archive.record(
+        ChannelId(3),
+        entry(3, Some(now + chrono::Duration::hours(1))),
+    );
+
+    assert!(archive
+        .get(ChannelId(3), MessageId(2), shared::domain::UserId(5))
+        .is_none());
+    assert_eq!(archive.entries(ChannelId(3)).len(), 2);
+    archive.remove(ChannelId(3), &[MessageId(1)]);
+    archive.purge_expired(now + chrono::Duration::hours(2));
+    assert!(archive.entries(ChannelId(3)).is_empty());
+}
+
+#[tokio::test]
+async fn emit_decrypted_message_renders_shared_backlog_without_mls_decrypt() {
+    let mls = TestMlsSessionManager::ok(Vec::new(), b"unexpected".to_vec());
+    let decrypt_inputs = mls.decrypted_ciphertexts.clone();
+    let client = RealtimeClient::new_with_mls_session_manager(PassthroughCrypto, Arc::new(mls));
+    {
+        let mut inner = client.inner.lock().await;
+        inner.user_id = Some(99);
+        inner.device_id = Some(1);
+        inner.channel_guilds.insert(ChannelId(3), GuildId(11));
+        inner
+            .initialized_mls_channels
+            .insert((GuildId(11), ChannelId(3)));
+        inner.history_archive.merge(
+            ChannelId(3),
+            shared::domain::UserId(5),
+            vec![SharedHistoryEntry {
+                message_id: MessageId(7),
+                sender_id: shared::domain::UserId(5),
+                plaintext: "before you joined".to_string(),
+                expires_at: None,
+            }],
+        );
+    }
+    let mut rx = client.subscribe_events();
+
+    client
+        .emit_decrypted_message(&sample_message())
+        .await
+        .expect("archived backlog should render");
+
+    match rx.recv().await.expect("event") {
+        ClientEvent::MessageDecrypted { plaintext, .. } => {
+            assert_eq!(plaintext, "before you joined")
+        }
+        other => panic!("unexpected event: {other:?}"),
+    }
+    assert!(decrypt_inputs.lock().await.is_empty());
+}
+
+#[tokio::test]
+async fn emit_decrypted_message_ignores_shared_entry_for_another_sender() {
+    let mls = TestMlsSessionManager::ok(Vec::new(), b"from mls".to_vec());
+    let decrypt_inputs = mls.decrypted_ciphertexts.clone();
+    let client = RealtimeClient::new_with_mls_session_manager(PassthroughCrypto, Arc::new(mls));
+    {
+        let mut inner = client.inner.lock().await;
+        inner.user_id = Some(99);
+        inner.device_id = Some(1);
+        inner.channel_guilds.insert(ChannelId(3), GuildId(11));
+        inner
+            .initialized_mls_channels
+            .insert((GuildId(11), ChannelId(3)));
+        // The server says message 7 is from user 5; this copy claims user 6 wrote it.
+        inner.history_archive.merge(
+            ChannelId(3),
+            shared::domain::UserId(6),
+            vec![SharedHistoryEntry {
+                message_id: MessageId(7),
+                sender_id: shared::domain::UserId(6),
+                plaintext: "forged".to_string(),
+                expires_at: None,
+            }],
+        );
+    }
+    let mut rx = client.subscribe_events();
+
+    client
+        .emit_decrypted_message(&sample_message())
+        .await
+        .expect("message should decrypt through MLS");
+
+    match rx.recv().await.expect("event") {
+        ClientEvent::MessageDecrypted { plaintext, .. } => assert_eq!(plaintext, "from mls"),
+        other => panic!("unexpected event: {other:?}"),
+    }
+    assert_eq!(decrypt_inputs.lock().await.len(), 1);
+}
+
 #[tokio::test]
 async fn emit_decrypted_message_skips_when_uninitialized_and_no_welcome() {
     let manager = TestMlsSessionManager::ok(Vec::new(), b"hello".to_vec());
@@ -768,6 +1323,8 @@ async fn emit_decrypted_message_auto_joins_from_welcome_when_uninitialized() {
         sender_username: Some("adder".to_string()),
         ciphertext_b64: STANDARD.encode(b"ciphertext-from-a"),
         attachment: None,
+        thread_id: None,
+        expires_at: None,
         sent_at: "2024-01-01T00:00:00Z".parse().expect("timestamp"),
     };
 
@@ -799,6 +1356,7 @@ struct OnboardingServerState {
     fail_member_fetch: Arc<Mutex<bool>>,
     fail_key_package_fetch: Arc<Mutex<bool>>,
     bootstrap_requests: Arc<Mutex<Vec<BootstrapRequestRecord>>>,
+    ws_events: Arc<Mutex<Vec<ServerEvent>>>,
 }
 
 async fn onboarding_list_members(
@@ -813,6 +1371,7 @@ async fn onboarding_list_members(
         username: "adder".to_string(),
         role: shared::domain::Role::Owner,
         muted: false,
+        role_ids: Vec::new(),
     }];
     if *state.include_target_member.lock().await {
         members.push(MemberSummary {
@@ -821,6 +1380,7 @@ async fn onboarding_list_members(
             username: "target".to_string(),
             role: shared::domain::Role::Member,
             muted: false,
+            role_ids: Vec::new(),
         });
     }
     Ok(Json(members))
@@ -845,14 +1405,67 @@ async fn onboarding_fetch_key_package(
         guild_id: 11,
         user_id: requested_user_id,
         device_id: Some(shared::domain::DeviceId(42)),
-        key_package_b64: STANDARD.encode(if requested_user_id == 42 {
-            b"target-kp".as_slice()
-        } else {
-            b"adder-kp".as_slice()
-        }),
+        key_package_b64: STANDARD.encode(format!("user:{requested_user_id}")),
+        transparency: Some(test_transparency_proof(requested_user_id, 42)),
     }))
 }
 
+const TEST_TRANSPARENCY_LOG_KEY: [u8; 32] = [9; 32];
+
+fn test_transparency_leaf(user_id: i64, device_id: i64) -> KeyTransparencyLeaf {
+    KeyTransparencyLeaf {
+        leaf_index: (user_id - 1) as u64,
+        user_id: UserId(user_id),
+        device_id: DeviceId(device_id),
+        action: shared::domain::KeyTransparencyAction::Register,
+        device_public_identity: format!("user:{user_id}"),
+        logged_at: chrono::DateTime::from_timestamp_millis(1_700_000_000_000).expect("timestamp"),
+    }
+}
+
+fn test_tree_head(tree_size: u64, root: &[u8; 32]) -> SignedTreeHead {
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&TEST_TRANSPARENCY_LOG_KEY);
+    let timestamp = chrono::DateTime::from_timestamp_millis(1_700_000_000_000).expect("timestamp");
+    let payload = shared::transparency::tree_head_signing_payload(
+        tree_size,
+        root,
+        timestamp.timestamp_millis(),
+    );
+    SignedTreeHead {
+        tree_size,
+        root_hash_b64: STANDARD.encode(root),
+        timestamp,
+        log_public_key_b64: STANDARD.encode(signing_key.verifying_key().as_bytes()),
+        signature_b64: STANDARD
+            .encode(ed25519_dalek::Signer::sign(&signing_key, &payload).to_bytes()),
+    }
+}
+
+/// Proof against a fixed log holding one device registration per user id `1..=64`, all on
+/// `device_id`.
+fn test_transparency_proof(user_id: i64, device_id: i64) -> KeyTransparencyProof {
+    let log: Vec<KeyTransparencyLeaf> = (1..=64)
+        .map(|id| test_transparency_leaf(id, device_id))
+        .collect();
+    test_transparency_proof_from_log(&log, (user_id - 1) as usize)
+}
+
+fn test_transparency_proof_from_log(
+    log: &[KeyTransparencyLeaf],
+    index: usize,
+) -> KeyTransparencyProof {
+    let leaves: Vec<[u8; 32]> = log.iter().map(KeyTransparencyLeaf::leaf_hash).collect();
+    let path = shared::transparency::inclusion_path(&leaves, index).expect("leaf in test log");
+    KeyTransparencyProof {
+        leaf: log[index].clone(),
+        audit_path_b64: path.iter().map(|node| STANDARD.encode(node)).collect(),
+        tree_head: test_tree_head(
+            leaves.len() as u64,
+            &shared::transparency::root_hash(&leaves),
+        ),
+    }
+}
+
 #[derive(Deserialize)]
 struct StoreWelcomeQuery {
     user_id: i64,
@@ -953,6 +1566,27 @@ async fn onboarding_send_message(
     StatusCode::NO_CONTENT
 }
 
+/// Sends the queued events to the connecting client, then keeps the socket open.
+async fn onboarding_ws(
+    State(state): State<OnboardingServerState>,
+    ws: axum::extract::ws::WebSocketUpgrade,
+) -> axum::response::Response {
+    let events = std::mem::take(&mut *state.ws_events.lock().await);
+    ws.on_upgrade(move |mut socket| async move {
+        for event in events {
+            let text = serde_json::to_string(&event).expect("serialize event");
+            if socket
+                .send(axum::extract::ws::Message::Text(text))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        while socket.recv().await.is_some() {}
+    })
+}
+
 async fn onboarding_messages_for_channel(
     State(state): State<OnboardingServerState>,
 ) -> Json<Vec<MessagePayload>> {
@@ -970,6 +1604,8 @@ async fn onboarding_messages_for_channel(
         sender_username: Some("adder".to_string()),
         ciphertext_b64,
         attachment: None,
+        thread_id: None,
+        expires_at: None,
         sent_at: "2024-01-01T00:00:00Z".parse().expect("timestamp"),
     }])
 }
@@ -989,12 +1625,17 @@ async fn spawn_onboarding_server() -> Result<(String, OnboardingServerState)> {
         fail_member_fetch: Arc::new(Mutex::new(false)),
         fail_key_package_fetch: Arc::new(Mutex::new(false)),
         bootstrap_requests: Arc::new(Mutex::new(Vec::new())),
+        ws_events: Arc::new(Mutex::new(Vec::new())),
     };
     let app = Router::new()
         .route(
             "/guilds/11/members",
             axum::routing::get(onboarding_list_members),
         )
+        .route(
+            "/channels/13/members",
+            axum::routing::get(onboarding_list_members),
+        )
         .route(
             "/mls/key_packages",
             axum::routing::get(onboarding_fetch_key_package),
@@ -1013,6 +1654,7 @@ async fn spawn_onboarding_server() -> Result<(String, OnboardingServerState)> {
             axum::routing::post(onboarding_bootstrap_request),
         )
         .route("/messages", axum::routing::post(onboarding_send_message))
+        .route("/ws", axum::routing::get(onboarding_ws))
         .with_state(state.clone());
     tokio::spawn(async move {
         let _ = axum::serve(listener, app).await;
@@ -1139,6 +1781,496 @@ async fn added_member_retries_welcome_sync_until_payload_is_ready() {
     assert!(fetches >= 3);
 }
 
+#[derive(Clone)]
+struct TransparencyServerState {
+    log: Arc<Mutex<Vec<KeyTransparencyLeaf>>>,
+    omit_proof: Arc<Mutex<bool>>,
+    omit_device: Arc<Mutex<bool>>,
+    swapped_key_package: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+#[derive(Deserialize)]
+struct TransparencyConsistencyQuery {
+    first_size: u64,
+    second_size: u64,
+}
+
+async fn transparency_fetch_key_package(
+    State(state): State<TransparencyServerState>,
+    Query(q): Query<FetchKeyPackageQuery>,
+) -> Json<KeyPackageResponse> {
+    let requested_user_id = q.target_user_id.unwrap_or(q.user_id);
+    let log = state.log.lock().await.clone();
+    let transparency = if *state.omit_proof.lock().await {
+        None
+    } else {
+        Some(test_transparency_proof_from_log(
+            &log,
+            (requested_user_id - 1) as usize,
+        ))
+    };
+    let key_package = state
+        .swapped_key_package
+        .lock()
+        .await
+        .clone()
+        .unwrap_or_else(|| format!("user:{requested_user_id}").into_bytes());
+    Json(KeyPackageResponse {
+        key_package_id: 1,
+        guild_id: 11,
+        user_id: requested_user_id,
+        device_id: (!*state.omit_device.lock().await).then_some(DeviceId(42)),
+        key_package_b64: STANDARD.encode(key_package),
+        transparency,
+    })
+}
+
+async fn transparency_tree_head(
+    State(state): State<TransparencyServerState>,
+) -> Json<SignedTreeHead> {
+    let leaves: Vec<[u8; 32]> = state
+        .log
+        .lock()
+        .await
+        .iter()
+        .map(KeyTransparencyLeaf::leaf_hash)
+        .collect();
+    Json(test_tree_head(
+        leaves.len() as u64,
+        &shared::transparency::root_hash(&leaves),
+    ))
+}
+
+async fn transparency_consistency(
+    State(state): State<TransparencyServerState>,
+    Query(q): Query<TransparencyConsistencyQuery>,
+) -> Json<ConsistencyProofResponse> {
+    let leaves: Vec<[u8; 32]> = state
+        .log
+        .lock()
+        .await
+        .iter()
+        .take(q.second_size as usize)
+        .map(KeyTransparencyLeaf::leaf_hash)
+        .collect();
+    let proof = shared::transparency::consistency_proof(&leaves, q.first_size as usize)
+        .expect("consistency proof");
+    Json(ConsistencyProofResponse {
+        first_size: q.first_size,
+        second_size: q.second_size,
+        proof_b64: proof.iter().map(|node| STANDARD.encode(node)).collect(),
+    })
+}
+
+async fn spawn_transparency_server(
+    initial_users: i64,
+) -> Result<(String, TransparencyServerState)> {
+    std::env::set_var("NO_PROXY", "127.0.0.1,localhost");
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let state = TransparencyServerState {
+        log: Arc::new(Mutex::new(
+            (1..=initial_users)
+                .map(|id| test_transparency_leaf(id, 42))
+                .collect(),
+        )),
+        omit_proof: Arc::new(Mutex::new(false)),
+        omit_device: Arc::new(Mutex::new(false)),
+        swapped_key_package: Arc::new(Mutex::new(None)),
+    };
+    let app = Router::new()
+        .route(
+            "/mls/key_packages",
+            axum::routing::get(transparency_fetch_key_package),
+        )
+        .route(
+            "/transparency/tree_head",
+            axum::routing::get(transparency_tree_head),
+        )
+        .route(
+            "/transparency/consistency",
+            axum::routing::get(transparency_consistency),
+        )
+        .with_state(state.clone());
+    tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+    Ok((format!("http://{addr}"), state))
+}
+
+async fn transparency_test_client(server_url: String) -> Arc<RealtimeClient<PassthroughCrypto>> {
+    let client = RealtimeClient::new_with_mls_session_manager(
+        PassthroughCrypto,
+        Arc::new(TestMlsSessionManager::ok(Vec::new(), Vec::new())),
+    );
+    {
+        let mut inner = client.inner.lock().await;
+        inner.server_url = Some(server_url);
+        inner.user_id = Some(1);
+        inner.device_id = Some(1);
+    }
+    client
+}
+
+#[tokio::test]
+async fn fetch_key_package_tracks_growing_log_and_rejects_split_view() {
+    let (server_url, server_state) = spawn_transparency_server(4).await.expect("spawn server");
+    let client = transparency_test_client(server_url).await;
+
+    client
+        .fetch_key_package(2, GuildId(11), Some(42))
+        .await
+        .expect("first fetch verifies");
+    assert_eq!(
+        client
+            .last_key_transparency_tree_head()
+            .await
+            .map(|head| head.tree_size),
+        Some(4)
+    );
+
+    server_state
+        .log
+        .lock()
+        .await
+        .extend((5..=6).map(|id| test_transparency_leaf(id, 42)));
+    client
+        .fetch_key_package(5, GuildId(11), Some(42))
+        .await
+        .expect("appended log is consistent");
+    assert_eq!(
+        client
+            .last_key_transparency_tree_head()
+            .await
+            .map(|head| head.tree_size),
+        Some(6)
+    );
+
+    server_state.log.lock().await[0].device_public_identity = "swapped".to_string();
+    let err = client
+        .fetch_key_package(3, GuildId(11), Some(42))
+        .await
+        .expect_err("rewritten log must be rejected");
+    assert!(matches!(
+        err.downcast_ref::<KeyTransparencyError>(),
+        Some(KeyTransparencyError::SplitView { .. })
+    ));
+}
+
+#[tokio::test]
+async fn persisted_tree_head_is_checked_against_the_log_after_restart() {
+    let (server_url, server_state) = spawn_transparency_server(4).await.expect("spawn server");
+    let mls = TestMlsSessionManager::ok(Vec::new(), Vec::new());
+    let persisted = mls.key_transparency_state.clone();
+    let client = RealtimeClient::new_with_mls_session_manager(PassthroughCrypto, Arc::new(mls));
+    {
+        let mut inner = client.inner.lock().await;
+        inner.server_url = Some(server_url.clone());
+        inner.user_id = Some(1);
+        inner.device_id = Some(1);
+    }
+    client
+        .fetch_key_package(2, GuildId(11), Some(42))
+        .await
+        .expect("first fetch pins the log");
+
+    let restarted_client = || {
+        let mut mls = TestMlsSessionManager::ok(Vec::new(), Vec::new());
+        mls.key_transparency_state = persisted.clone();
+        RealtimeClient::new_with_mls_session_manager(PassthroughCrypto, Arc::new(mls))
+    };
+
+    server_state
+        .log
+        .lock()
+        .await
+        .extend((5..=6).map(|id| test_transparency_leaf(id, 42)));
+    let restarted = restarted_client();
+    restarted
+        .resume_key_transparency(&server_url)
+        .await
+        .expect("appended log is consistent with the stored head");
+    assert_eq!(
+        restarted
+            .last_key_transparency_tree_head()
+            .await
+            .map(|head| head.tree_size),
+        Some(6)
+    );
+
+    server_state.log.lock().await[0].device_public_identity = "swapped".to_string();
+    let err = restarted_client()
+        .resume_key_transparency(&server_url)
+        .await
+        .expect_err("rewritten log must be rejected after a restart");
+    assert!(matches!(
+        err.downcast_ref::<KeyTransparencyError>(),
+        Some(KeyTransparencyError::SplitView { .. })
+    ));
+}
+
+#[tokio::test]
+async fn fetch_key_package_requires_transparency_proof_for_devices() {
+    let (server_url, server_state) = spawn_transparency_server(2).await.expect("spawn server");
+    *server_state.omit_proof.lock().await = true;
+    let client = transparency_test_client(server_url).await;
+
+    let err = client
+        .fetch_key_package(2, GuildId(11), Some(42))
+        .await
+        .expect_err("missing proof must be rejected");
+    assert!(matches!(
+        err.downcast_ref::<KeyTransparencyError>(),
+        Some(KeyTransparencyError::MissingProof)
+    ));
+}
+
+#[tokio::test]
+async fn fetch_key_package_rejects_key_package_without_device() {
+    let (server_url, server_state) = spawn_transparency_server(2).await.expect("spawn server");
+    *server_state.omit_device.lock().await = true;
+    let client = transparency_test_client(server_url).await;
+
+    let err = client
+        .fetch_key_package(2, GuildId(11), Some(42))
+        .await
+        .expect_err("key package without a device must be rejected");
+    assert!(matches!(
+        err.downcast_ref::<KeyTransparencyError>(),
+        Some(KeyTransparencyError::MissingDevice)
+    ));
+}
+
+#[tokio::test]
+async fn fetch_key_package_rejects_key_package_from_another_identity() {
+    let (server_url, server_state) = spawn_transparency_server(2).await.expect("spawn server");
+    *server_state.swapped_key_package.lock().await = Some(b"server-chosen".to_vec());
+    let client = transparency_test_client(server_url).await;
+
+    let err = client
+        .fetch_key_package(2, GuildId(11), Some(42))
+        .await
+        .expect_err("key package signed by an unlogged identity must be rejected");
+    assert!(matches!(
+        err.downcast_ref::<KeyTransparencyError>(),
+        Some(KeyTransparencyError::IdentityMismatch {
+            user_id: 2,
+            device_id: 42
+        })
+    ));
+}
+
+/// Uploading device id and key package bytes.
+type UploadedKeyPackage = (i64, Vec<u8>);
+
+/// Registers devices in a key transparency log and serves the latest key package each user
+/// uploaded, with an inclusion proof for the device that uploaded it.
+#[derive(Clone, Default)]
+struct RegistryServerState {
+    log: Arc<Mutex<Vec<KeyTransparencyLeaf>>>,
+    key_packages: Arc<Mutex<HashMap<i64, UploadedKeyPackage>>>,
+    backup: Arc<Mutex<Vec<u8>>>,
+}
+
+#[derive(Deserialize)]
+struct RegistryUserQuery {
+    user_id: i64,
+}
+
+#[derive(Deserialize)]
+struct RegistryUploadKeyPackageQuery {
+    user_id: i64,
+    device_id: i64,
+}
+
+async fn registry_login(Json(req): Json<serde_json::Value>) -> Json<serde_json::Value> {
+    let user_id = if req["username"] == "alice" { 1 } else { 2 };
+    Json(serde_json::json!({ "user_id": user_id }))
+}
+
+async fn registry_register_device(
+    State(state): State<RegistryServerState>,
+    Query(q): Query<RegistryUserQuery>,
+    Json(req): Json<serde_json::Value>,
+) -> Json<serde_json::Value> {
+    let mut log = state.log.lock().await;
+    let leaf_index = log.len() as u64;
+    let device_id = leaf_index as i64 + 1;
+    log.push(KeyTransparencyLeaf {
+        leaf_index,
+        user_id: UserId(q.user_id),
+        device_id: DeviceId(device_id),
+        action: shared::domain::KeyTransparencyAction::Register,
+        device_public_identity: req["device_public_identity"]
+            .as_str()
+            .expect("device identity")
+            .to_string(),
+        logged_at: chrono::DateTime::from_timestamp_millis(1_700_000_000_000).expect("timestamp"),
+    });
+    Json(serde_json::json!({ "device_id": device_id }))
+}
+
+async fn registry_ws(ws: axum::extract::ws::WebSocketUpgrade) -> axum::response::Response {
+    ws.on_upgrade(|mut socket| async move { while socket.recv().await.is_some() {} })
+}
+
+async fn registry_guilds() -> Json<Vec<GuildSummary>> {
+    Json(vec![GuildSummary {
+        guild_id: GuildId(11),
+        name: "guild".to_string(),
+    }])
+}
+
+async fn registry_upload_key_package(
+    State(state): State<RegistryServerState>,
+    Query(q): Query<RegistryUploadKeyPackageQuery>,
+    body: Bytes,
+) -> Json<UploadKeyPackageResponse> {
+    state
+        .key_packages
+        .lock()
+        .await
+        .insert(q.user_id, (q.device_id, body.to_vec()));
+    Json(UploadKeyPackageResponse { key_package_id: 1 })
+}
+
+async fn registry_fetch_key_package(
+    State(state): State<RegistryServerState>,
+    Query(q): Query<FetchKeyPackageQuery>,
+) -> Result<Json<KeyPackageResponse>, StatusCode> {
+    let requested_user_id = q.target_user_id.unwrap_or(q.user_id);
+    let (device_id, key_package) = state
+        .key_packages
+        .lock()
+        .await
+        .get(&requested_user_id)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let log = state.log.lock().await.clone();
+    let index = log
+        .iter()
+        .position(|leaf| leaf.device_id.0 == device_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(KeyPackageResponse {
+        key_package_id: 1,
+        guild_id: 11,
+        user_id: requested_user_id,
+        device_id: Some(DeviceId(device_id)),
+        key_package_b64: STANDARD.encode(key_package),
+        transparency: Some(test_transparency_proof_from_log(&log, index)),
+    }))
+}
+
+async fn registry_backup(State(state): State<RegistryServerState>) -> Vec<u8> {
+    state.backup.lock().await.clone()
+}
+
+async fn spawn_registry_server() -> Result<(String, RegistryServerState)> {
+    std::env::set_var("NO_PROXY", "127.0.0.1,localhost");
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let state = RegistryServerState::default();
+    let app = Router::new()
+        .route("/login", post(registry_login))
+        .route("/devices/register", post(registry_register_device))
+        .route("/ws", get(registry_ws))
+        .route("/guilds", get(registry_guilds))
+        .route(
+            "/mls/key_packages",
+            post(registry_upload_key_package).get(registry_fetch_key_package),
+        )
+        .route("/mls/backup", get(registry_backup))
+        .with_state(state.clone());
+    tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+    Ok((format!("http://{addr}"), state))
+}
+
+fn temp_mls_database_url(label: &str) -> String {
+    let unique = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("clock")
+        .as_nanos();
+    format!(
+        "sqlite://{}",
+        std::env::temp_dir()
+            .join(format!("proto_rtc_mls_{label}_{unique}.sqlite3"))
+            .display()
+    )
+}
+
+#[tokio::test]
+async fn login_with_backup_publishes_the_restored_identity_to_peers() {
+    let (server_url, server_state) = spawn_registry_server().await.expect("spawn server");
+    let params = MlsBackupKdfParams {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    let old_device =
+        DurableMlsSessionManager::initialize(&temp_mls_database_url("old_laptop"), 1, "old")
+            .await
+            .expect("old device manager");
+    old_device
+        .open_or_create_group(GuildId(11), ChannelId(12))
+        .await
+        .expect("old device group");
+    *server_state.backup.lock().await = old_device
+        .export_encrypted_backup_with_params("correct horse", params)
+        .await
+        .expect("backup");
+
+    let new_device =
+        DurableMlsSessionManager::initialize(&temp_mls_database_url("new_laptop"), 1, "new")
+            .await
+            .expect("new device manager");
+    let alice = RealtimeClient::new_with_mls_session_manager(PassthroughCrypto, new_device.clone());
+    let restored = alice
+        .login_with_mls_backup(&server_url, "alice", "correct horse")
+        .await
+        .expect("login with backup");
+    assert_eq!(restored, 1);
+
+    let restored_identity = old_device
+        .device_public_identity()
+        .await
+        .expect("restored identity");
+    let log = server_state.log.lock().await.clone();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].device_public_identity, restored_identity);
+
+    let bob_mls = DurableMlsSessionManager::initialize(&temp_mls_database_url("bob"), 2, "bob")
+        .await
+        .expect("bob manager");
+    let bob = RealtimeClient::new_with_mls_session_manager(PassthroughCrypto, bob_mls.clone());
+    {
+        let mut inner = bob.inner.lock().await;
+        inner.server_url = Some(server_url);
+        inner.user_id = Some(2);
+        inner.device_id = Some(2);
+    }
+    let (key_package, device_id) = bob
+        .fetch_key_package(1, GuildId(11), None)
+        .await
+        .expect("restored key package verifies against the log");
+    assert_eq!(device_id, Some(1));
+
+    bob_mls
+        .open_or_create_group(GuildId(11), ChannelId(13))
+        .await
+        .expect("bob group");
+    let outcome = bob_mls
+        .add_member(ChannelId(13), &key_package)
+        .await
+        .expect("add restored device");
+    new_device
+        .join_from_welcome(GuildId(11), ChannelId(13), &outcome.welcome_bytes)
+        .await
+        .expect("restored device joins with its published key package");
+}
+
 #[tokio::test]
 async fn derive_livekit_e2ee_key_is_deterministic_for_same_connection() {
     let client = RealtimeClient::new_with_mls_session_manager(
@@ -1185,7 +2317,7 @@ async fn derive_livekit_e2ee_key_is_domain_separated_by_channel() {
 async fn derive_livekit_e2ee_key_surfaces_missing_group_failure() {
     let client = RealtimeClient::new_with_mls_session_manager(
         PassthroughCrypto,
-        Arc::new(TestMlsSessionManager::failing("group not initialized")),
+        Arc::new(TestMlsSessionManager::failing(|| MlsError::NotInitialized)),
     );
 
     let err = client
@@ -1209,7 +2341,9 @@ async fn derive_livekit_e2ee_key_surfaces_missing_group_failure() {
 async fn derive_livekit_e2ee_key_surfaces_export_failure() {
     let client = RealtimeClient::new_with_mls_session_manager(
         PassthroughCrypto,
-        Arc::new(TestMlsSessionManager::failing("backend export failed")),
+        Arc::new(TestMlsSessionManager::failing(|| {
+            MlsError::Protocol("backend export failed".to_string())
+        })),
     );
 
     let err = client
@@ -1348,6 +2482,58 @@ async fn onboarding_flow_encrypts_fetches_once_and_renders_plaintext() {
     assert_eq!(second_welcome_fetch.status(), StatusCode::NOT_FOUND);
 }
 
+#[tokio::test]
+async fn role_update_removes_members_who_lost_channel_view_from_group() {
+    let (server_url, server_state) = spawn_onboarding_server().await.expect("spawn server");
+    // The role edit took VIEW_CHANNEL away from user 42, so the channel no longer lists them.
+    *server_state.include_target_member.lock().await = false;
+    server_state
+        .ws_events
+        .lock()
+        .await
+        .push(ServerEvent::GuildRolesUpdated {
+            guild_id: GuildId(11),
+            roles: Vec::new(),
+        });
+
+    let leader_mls = TestMlsSessionManager::ok(Vec::new(), Vec::new());
+    let group_member_user_ids = leader_mls.group_member_user_ids.clone();
+    *group_member_user_ids.lock().await = vec![7, 42];
+    let leader =
+        RealtimeClient::new_with_mls_session_manager(PassthroughCrypto, Arc::new(leader_mls));
+    {
+        let mut inner = leader.inner.lock().await;
+        inner.server_url = Some(server_url.clone());
+        inner.user_id = Some(7);
+        inner.device_id = Some(1);
+        inner.channel_guilds.insert(ChannelId(13), GuildId(11));
+        inner
+            .initialized_mls_channels
+            .insert((GuildId(11), ChannelId(13)));
+    }
+
+    leader
+        .spawn_ws_events(&server_url, 7, 1)
+        .await
+        .expect("connect websocket");
+
+    let remove_commit_b64 = STANDARD.encode(b"remove-commit-generated");
+    let mut posted = false;
+    for _ in 0..40 {
+        posted = server_state
+            .stored_ciphertexts
+            .lock()
+            .await
+            .contains(&remove_commit_b64);
+        if posted {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert!(posted, "role update should post a remove-member commit");
+    assert_eq!(*group_member_user_ids.lock().await, vec![7]);
+}
+
 #[tokio::test]
 async fn moderator_retries_member_bootstrap_after_new_member_joins() {
     let (server_url, server_state) = spawn_onboarding_server().await.expect("spawn server");
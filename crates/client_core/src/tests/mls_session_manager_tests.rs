@@ -1,5 +1,7 @@
 use super::*;
+use crate::{MlsAtRestError, MlsAtRestKeySource, MlsBackupError};
 use std::time::{SystemTime, UNIX_EPOCH};
+use storage::StoredMlsBlobKind;
 
 #[tokio::test]
 async fn identity_persists_across_manager_restart_and_supports_commit_and_decrypt() {
@@ -69,7 +71,7 @@ async fn identity_persists_across_manager_restart_and_supports_commit_and_decryp
         .decrypt_application(channel_id, &charlie_add.commit_bytes)
         .await
         .expect("merge commit after restart");
-    assert!(commit_merge_result.is_empty());
+    assert_eq!(commit_merge_result, MlsInbound::Commit);
 
     let bob_ciphertext = bob_after_restart
         .encrypt_application(channel_id, b"bob after restart")
@@ -79,7 +81,367 @@ async fn identity_persists_across_manager_restart_and_supports_commit_and_decryp
         .decrypt_application(channel_id, &bob_ciphertext)
         .await
         .expect("alice decrypts bob message");
-    assert_eq!(alice_plaintext, b"bob after restart");
+    assert_eq!(
+        alice_plaintext,
+        MlsInbound::Application(b"bob after restart".to_vec())
+    );
 
     let _ = std::fs::remove_file(&db_path);
 }
+
+#[tokio::test]
+async fn key_transparency_state_persists_across_manager_restart() {
+    let unique = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock")
+        .as_nanos();
+    let db_path = std::env::temp_dir().join(format!("proto_rtc_mls_kt_{unique}.sqlite3"));
+    let database_url = format!("sqlite://{}", db_path.display());
+
+    let manager = DurableMlsSessionManager::initialize(&database_url, 1, "device-alice")
+        .await
+        .expect("manager");
+    assert!(manager
+        .load_key_transparency_state()
+        .await
+        .expect("load")
+        .is_none());
+    manager
+        .save_key_transparency_state("{\"pinned\":1}")
+        .await
+        .expect("save");
+    drop(manager);
+
+    let reopened = DurableMlsSessionManager::initialize(&database_url, 1, "device-alice")
+        .await
+        .expect("reopened manager");
+    assert_eq!(
+        reopened.load_key_transparency_state().await.expect("load"),
+        Some("{\"pinned\":1}".to_string())
+    );
+}
+
+#[tokio::test]
+async fn encrypted_backup_restores_identity_and_groups_on_fresh_device() {
+    let unique = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock")
+        .as_nanos();
+    let source_url = format!(
+        "sqlite://{}",
+        std::env::temp_dir()
+            .join(format!("proto_rtc_mls_backup_src_{unique}.sqlite3"))
+            .display()
+    );
+    let restored_url = format!(
+        "sqlite://{}",
+        std::env::temp_dir()
+            .join(format!("proto_rtc_mls_backup_dst_{unique}.sqlite3"))
+            .display()
+    );
+    let guild_id = GuildId(41);
+    let channel_id = ChannelId(42);
+    let params = MlsBackupKdfParams {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    let original = DurableMlsSessionManager::initialize(&source_url, 7, "old-laptop")
+        .await
+        .expect("source manager");
+    original
+        .open_or_create_group(guild_id, channel_id)
+        .await
+        .expect("group");
+    let expected_secret = original
+        .export_secret(channel_id, "backup-test", 32)
+        .await
+        .expect("secret");
+    let backup = original
+        .export_encrypted_backup_with_params("correct horse", params)
+        .await
+        .expect("backup");
+
+    let restored = DurableMlsSessionManager::initialize(&restored_url, 7, "new-laptop")
+        .await
+        .expect("restored manager");
+    let err = restored
+        .restore_encrypted_backup("wrong horse", &backup)
+        .await
+        .expect_err("wrong passphrase");
+    assert!(matches!(
+        err.downcast_ref::<MlsBackupError>(),
+        Some(MlsBackupError::Decryption)
+    ));
+
+    let other_user = DurableMlsSessionManager::initialize(&restored_url, 8, "new-laptop")
+        .await
+        .expect("other user manager");
+    assert!(other_user
+        .restore_encrypted_backup("correct horse", &backup)
+        .await
+        .is_err());
+
+    let count = restored
+        .restore_encrypted_backup("correct horse", &backup)
+        .await
+        .expect("restore");
+    assert_eq!(count, 1);
+    assert!(restored
+        .has_persisted_group_state(guild_id, channel_id)
+        .await
+        .expect("state check"));
+    restored
+        .open_or_create_group(guild_id, channel_id)
+        .await
+        .expect("reopen restored group");
+    let restored_secret = restored
+        .export_secret(channel_id, "backup-test", 32)
+        .await
+        .expect("restored secret");
+    assert_eq!(restored_secret, expected_secret);
+}
+
+#[tokio::test]
+async fn rejected_backup_restore_leaves_local_state_untouched() {
+    let unique = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock")
+        .as_nanos();
+    let database_url = format!(
+        "sqlite://{}",
+        std::env::temp_dir()
+            .join(format!("proto_rtc_mls_backup_reject_{unique}.sqlite3"))
+            .display()
+    );
+    let params = MlsBackupKdfParams {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+    let guild_id = GuildId(51);
+    let channel_id = ChannelId(52);
+
+    let manager = DurableMlsSessionManager::initialize(&database_url, 7, "laptop")
+        .await
+        .expect("manager");
+    manager
+        .open_or_create_group(guild_id, channel_id)
+        .await
+        .expect("group");
+    let identity_before = manager.load_or_create_identity().await.expect("identity");
+    let backup = manager
+        .export_encrypted_backup_with_params("correct horse", params)
+        .await
+        .expect("backup");
+
+    let mut decoded: MlsStateBackupV1 =
+        serde_json::from_slice(&open_backup(&backup, "correct horse").expect("open backup"))
+            .expect("decode backup");
+    decoded.identity_b64 = STANDARD.encode(
+        MlsIdentity::new_with_name(b"user:7:other".to_vec())
+            .expect("replacement identity")
+            .to_bytes()
+            .expect("identity bytes"),
+    );
+    decoded.groups.push(BackedUpGroupState {
+        guild_id: GuildId(53),
+        channel_id: ChannelId(54),
+        schema_version: 1,
+        group_state_blob_b64: "not base64!".to_string(),
+        key_material_blob_b64: String::new(),
+    });
+    let truncated = seal_backup(
+        &serde_json::to_vec(&decoded).expect("encode backup"),
+        "correct horse",
+        params,
+    )
+    .expect("seal backup");
+    manager
+        .restore_encrypted_backup("correct horse", &truncated)
+        .await
+        .expect_err("corrupt group must be rejected");
+
+    decoded.groups.pop();
+    decoded.identity_b64 = STANDARD.encode(
+        MlsIdentity::new_with_name(b"user:8:laptop".to_vec())
+            .expect("foreign identity")
+            .to_bytes()
+            .expect("identity bytes"),
+    );
+    let foreign = seal_backup(
+        &serde_json::to_vec(&decoded).expect("encode backup"),
+        "correct horse",
+        params,
+    )
+    .expect("seal backup");
+    manager
+        .restore_encrypted_backup("correct horse", &foreign)
+        .await
+        .expect_err("another user's identity must be rejected");
+
+    assert!(manager
+        .has_persisted_group_state(guild_id, channel_id)
+        .await
+        .expect("state check"));
+    assert_eq!(
+        manager
+            .load_or_create_identity()
+            .await
+            .expect("identity")
+            .public_fingerprint()
+            .expect("fingerprint"),
+        identity_before.public_fingerprint().expect("fingerprint")
+    );
+}
+
+#[tokio::test]
+async fn backup_with_hostile_kdf_params_is_rejected_before_derivation() {
+    let params = MlsBackupKdfParams {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+    let mut envelope: serde_json::Value =
+        serde_json::from_slice(&seal_backup(b"{}", "correct horse", params).expect("seal"))
+            .expect("envelope");
+    envelope["kdf"]["memory_kib"] = serde_json::json!(u32::MAX);
+
+    let err = open_backup(
+        &serde_json::to_vec(&envelope).expect("encode envelope"),
+        "correct horse",
+    )
+    .expect_err("hostile parameters must be rejected");
+    assert!(matches!(err, MlsBackupError::KdfParamsOutOfRange(_)));
+}
+
+#[tokio::test]
+async fn at_rest_encryption_migrates_plaintext_db_and_survives_key_rotation() {
+    let unique = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock")
+        .as_nanos();
+    let dir = std::env::temp_dir().join(format!("proto_rtc_mls_at_rest_{unique}"));
+    std::fs::create_dir_all(&dir).expect("temp dir");
+    let database_url = format!("sqlite://{}", dir.join("mls.sqlite3").display());
+    let key_file = MlsAtRestKeySource::KeyFile(dir.join("mls_state.key"));
+    let guild_id = GuildId(61);
+    let channel_id = ChannelId(62);
+
+    let plaintext = DurableMlsSessionManager::initialize(&database_url, 3, "desktop")
+        .await
+        .expect("plaintext manager");
+    plaintext
+        .open_or_create_group(guild_id, channel_id)
+        .await
+        .expect("group");
+    let expected_secret = plaintext
+        .export_secret(channel_id, "at-rest-test", 32)
+        .await
+        .expect("secret");
+    let plaintext_identity = plaintext
+        .store
+        .storage()
+        .load_identity_keys(3, "desktop")
+        .await
+        .expect("identity")
+        .expect("identity row");
+    drop(plaintext);
+
+    let encrypted =
+        DurableMlsSessionManager::initialize_encrypted(&database_url, 3, "desktop", &key_file)
+            .await
+            .expect("migrate to encrypted");
+    assert!(encrypted.is_encrypted_at_rest());
+    let raw_rows = encrypted
+        .store
+        .storage()
+        .list_mls_secret_blobs()
+        .await
+        .expect("raw rows");
+    let count_kind = |kind| raw_rows.iter().filter(|row| row.kind == kind).count();
+    assert_eq!(count_kind(StoredMlsBlobKind::Identity), 1);
+    assert_eq!(count_kind(StoredMlsBlobKind::GroupState), 1);
+    assert!(count_kind(StoredMlsBlobKind::GroupProviderEntry) > 0);
+    for row in &raw_rows {
+        assert!(row.primary_blob.starts_with(b"proto-rtc:mls-at-rest:v1\0"));
+        assert_ne!(row.primary_blob, plaintext_identity);
+    }
+    drop(encrypted);
+
+    let err = DurableMlsSessionManager::initialize(&database_url, 3, "desktop")
+        .await
+        .err()
+        .expect("plaintext open of encrypted db");
+    assert!(matches!(
+        err.downcast_ref::<MlsAtRestError>(),
+        Some(MlsAtRestError::Locked { .. })
+    ));
+
+    let reopened =
+        DurableMlsSessionManager::initialize_encrypted(&database_url, 3, "desktop", &key_file)
+            .await
+            .expect("unlock with key file");
+    reopened
+        .open_or_create_group(guild_id, channel_id)
+        .await
+        .expect("reopen group");
+    assert_eq!(
+        reopened
+            .export_secret(channel_id, "at-rest-test", 32)
+            .await
+            .expect("secret"),
+        expected_secret
+    );
+    reopened
+        .rotate_at_rest_key(&MlsAtRestKeySource::Passphrase(
+            "new local secret".to_string(),
+        ))
+        .await
+        .expect("rotate");
+    drop(reopened);
+
+    let err =
+        DurableMlsSessionManager::initialize_encrypted(&database_url, 3, "desktop", &key_file)
+            .await
+            .err()
+            .expect("old key source rejected");
+    assert!(matches!(
+        err.downcast_ref::<MlsAtRestError>(),
+        Some(MlsAtRestError::KeySourceMismatch { .. })
+    ));
+    let err = DurableMlsSessionManager::initialize_encrypted(
+        &database_url,
+        3,
+        "desktop",
+        &MlsAtRestKeySource::Passphrase("guess".to_string()),
+    )
+    .await
+    .err()
+    .expect("wrong passphrase rejected");
+    assert!(matches!(
+        err.downcast_ref::<MlsAtRestError>(),
+        Some(MlsAtRestError::WrongKey)
+    ));
+
+    let rotated = DurableMlsSessionManager::initialize_encrypted(
+        &database_url,
+        3,
+        "desktop",
+        &MlsAtRestKeySource::Passphrase("new local secret".to_string()),
+    )
+    .await
+    .expect("unlock rotated db");
+    rotated
+        .open_or_create_group(guild_id, channel_id)
+        .await
+        .expect("reopen rotated group");
+    assert_eq!(
+        rotated
+            .export_secret(channel_id, "at-rest-test", 32)
+            .await
+            .expect("secret"),
+        expected_secret
+    );
+}
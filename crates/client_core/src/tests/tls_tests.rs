@@ -0,0 +1,96 @@
+use super::ServerTrust;
+
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{routing::get, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{pki_types::PrivateKeyDer, ServerConfig};
+use sha2::{Digest, Sha256};
+
+/// Serves `/healthz` over TLS with a fresh self-signed certificate for `localhost`.
+async fn self_signed_server() -> (SocketAddr, rcgen::CertifiedKey) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+        .expect("generate certificate");
+    let key = PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into());
+    let config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("protocol versions")
+            .with_no_client_auth()
+            .with_single_cert(vec![certified.cert.der().clone()], key)
+            .expect("server config");
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    let app = Router::new().route("/healthz", get(|| async { "ok" }));
+    tokio::spawn(async move {
+        let _ = axum_server::from_tcp_rustls(listener, RustlsConfig::from_config(Arc::new(config)))
+            .serve(app.into_make_service())
+            .await;
+    });
+    (addr, certified)
+}
+
+#[tokio::test]
+async fn pinned_certificate_is_accepted_and_any_other_is_refused() {
+    std::env::set_var("NO_PROXY", "127.0.0.1,localhost");
+    let (addr, certified) = self_signed_server().await;
+    let fingerprint = hex::encode(Sha256::digest(certified.cert.der()));
+
+    // Pins ignore the name, so a certificate for `localhost` works by IP.
+    let pinned = ServerTrust::pinned_sha256(&fingerprint).expect("pin");
+    let body = pinned
+        .http_client()
+        .expect("client")
+        .get(format!("https://{addr}/healthz"))
+        .send()
+        .await
+        .expect("pinned request")
+        .text()
+        .await
+        .expect("body");
+    assert_eq!(body, "ok");
+
+    let wrong = ServerTrust::pinned_sha256(&"00".repeat(32)).expect("pin");
+    assert!(wrong
+        .http_client()
+        .expect("client")
+        .get(format!("https://{addr}/healthz"))
+        .send()
+        .await
+        .is_err());
+    assert!(ServerTrust::default()
+        .http_client()
+        .expect("client")
+        .get(format!("https://{addr}/healthz"))
+        .send()
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn custom_ca_is_trusted_for_its_names() {
+    std::env::set_var("NO_PROXY", "127.0.0.1,localhost");
+    let (addr, certified) = self_signed_server().await;
+    let trust = ServerTrust::custom_ca_pem(certified.cert.pem().as_bytes()).expect("ca");
+    let client = trust.http_client().expect("client");
+
+    let response = client
+        .get(format!("https://localhost:{}/healthz", addr.port()))
+        .send()
+        .await
+        .expect("trusted request");
+    assert!(response.status().is_success());
+}
+
+#[test]
+fn fingerprints_parse_with_or_without_separators() {
+    let plain = "ab".repeat(32);
+    let separated = vec!["AB"; 32].join(":");
+    assert_eq!(
+        ServerTrust::pinned_sha256(&plain).expect("plain"),
+        ServerTrust::pinned_sha256(&separated).expect("separated")
+    );
+    assert!(ServerTrust::pinned_sha256("abcd").is_err());
+    assert!(ServerTrust::custom_ca_pem(b"not a certificate").is_err());
+}
@@ -0,0 +1,80 @@
+use std::io::Cursor;
+
+use image::{codecs::jpeg::JpegEncoder, DynamicImage};
+use shared::domain::FileId;
+
+/// Longest edge of an uploaded thumbnail, in pixels.
+const THUMBNAIL_MAX_EDGE: u32 = 320;
+const THUMBNAIL_JPEG_QUALITY: u8 = 75;
+/// The placeholder is computed from a tiny copy of the image; more pixels add nothing to a
+/// 4x3-component hash.
+const BLURHASH_SOURCE_EDGE: u32 = 32;
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// What the sender recorded about an image attachment, so it can be laid out and previewed
+/// before the original is downloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentPreview {
+    pub file_id: FileId,
+    /// Size of the original image.
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String,
+}
+
+impl AttachmentPreview {
+    /// Renders the blurhash as unpremultiplied RGBA at `width` x `height`. Returns `None`
+    /// for a malformed hash.
+    pub fn placeholder_rgba(&self, width: u32, height: u32) -> Option<Vec<u8>> {
+        blurhash::decode(&self.blurhash, width, height, 1.0).ok()
+    }
+}
+
+/// A thumbnail made at upload time; `jpeg` is encrypted and uploaded like any attachment.
+pub(crate) struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String,
+    pub jpeg: Vec<u8>,
+}
+
+/// Makes a thumbnail for `bytes` if they decode as an image. Anything else gets none.
+pub(crate) fn generate(bytes: &[u8]) -> Option<Thumbnail> {
+    let image = image::load_from_memory(bytes).ok()?;
+    let (width, height) = (image.width(), image.height());
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let small = image
+        .thumbnail(BLURHASH_SOURCE_EDGE, BLURHASH_SOURCE_EDGE)
+        .to_rgba8();
+    let blurhash = blurhash::encode(
+        BLURHASH_COMPONENTS.0,
+        BLURHASH_COMPONENTS.1,
+        small.width(),
+        small.height(),
+        small.as_raw(),
+    )
+    .ok()?;
+
+    // JPEG has no alpha channel; transparent areas come out black.
+    let thumbnail = DynamicImage::ImageRgb8(
+        image
+            .thumbnail(THUMBNAIL_MAX_EDGE, THUMBNAIL_MAX_EDGE)
+            .to_rgb8(),
+    );
+    let mut jpeg = Cursor::new(Vec::new());
+    thumbnail
+        .write_with_encoder(JpegEncoder::new_with_quality(
+            &mut jpeg,
+            THUMBNAIL_JPEG_QUALITY,
+        ))
+        .ok()?;
+    Some(Thumbnail {
+        width,
+        height,
+        blurhash,
+        jpeg: jpeg.into_inner(),
+    })
+}
@@ -0,0 +1,188 @@
+//! Which server certificates the client accepts. Public servers need nothing; a server
+//! with a private CA or a self-signed development certificate is trusted by CA file or by
+//! pinning the SHA-256 of its certificate.
+
+use std::{fmt, fs, io::BufReader, path::Path, sync::Arc};
+
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::Client;
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{
+        verify_tls12_signature, verify_tls13_signature, CryptoProvider, WebPkiSupportedAlgorithms,
+    },
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use tokio_tungstenite::Connector;
+
+/// Pins the server certificate by SHA-256, as printed by the server at startup.
+pub const TLS_CERT_SHA256_ENV: &str = "CLIENT_TLS_CERT_SHA256";
+/// PEM file of CA certificates to trust instead of the public roots.
+pub const TLS_CA_FILE_ENV: &str = "CLIENT_TLS_CA_FILE";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ServerTrust {
+    /// The bundled public web roots.
+    #[default]
+    PublicRoots,
+    /// Only certificates issued by these CAs, checked against the server name as usual.
+    CustomCa(Vec<CertificateDer<'static>>),
+    /// Exactly the certificate with this SHA-256, whatever name it was issued for.
+    PinnedSha256([u8; 32]),
+}
+
+impl ServerTrust {
+    pub fn custom_ca_pem(pem: &[u8]) -> Result<Self> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(pem))
+            .collect::<Result<Vec<_>, _>>()
+            .context("CA file is not PEM")?;
+        if certs.is_empty() {
+            bail!("CA file contains no certificates");
+        }
+        Ok(Self::CustomCa(certs))
+    }
+
+    pub fn custom_ca_file(path: &Path) -> Result<Self> {
+        let pem = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        Self::custom_ca_pem(&pem).with_context(|| path.display().to_string())
+    }
+
+    /// Accepts hex with or without `:` separators.
+    pub fn pinned_sha256(fingerprint: &str) -> Result<Self> {
+        let digits: String = fingerprint.trim().chars().filter(|c| *c != ':').collect();
+        let bytes = hex::decode(&digits).context("certificate fingerprint is not hex")?;
+        let pin = bytes
+            .try_into()
+            .map_err(|_| anyhow!("certificate fingerprint must be 32 bytes of SHA-256"))?;
+        Ok(Self::PinnedSha256(pin))
+    }
+
+    /// Reads [`TLS_CERT_SHA256_ENV`], then [`TLS_CA_FILE_ENV`]; neither set means the
+    /// public roots.
+    pub fn from_env() -> Result<Self> {
+        if let Some(pin) = env_value(TLS_CERT_SHA256_ENV) {
+            return Self::pinned_sha256(&pin).context(TLS_CERT_SHA256_ENV);
+        }
+        if let Some(path) = env_value(TLS_CA_FILE_ENV) {
+            return Self::custom_ca_file(Path::new(&path)).context(TLS_CA_FILE_ENV);
+        }
+        Ok(Self::PublicRoots)
+    }
+
+    /// HTTP client for `https://` server URLs under this trust.
+    pub fn http_client(&self) -> Result<Client> {
+        match self.client_config()? {
+            Some(config) => Client::builder()
+                .use_preconfigured_tls(config)
+                .build()
+                .context("failed to build https client"),
+            None => Ok(Client::new()),
+        }
+    }
+
+    /// Connector for `wss://` under this trust; `None` keeps tungstenite's default.
+    pub(crate) fn ws_connector(&self) -> Result<Option<Connector>> {
+        Ok(self
+            .client_config()?
+            .map(|config| Connector::Rustls(Arc::new(config))))
+    }
+
+    fn client_config(&self) -> Result<Option<ClientConfig>> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()?;
+        let config = match self {
+            Self::PublicRoots => return Ok(None),
+            Self::CustomCa(certs) => {
+                let mut roots = RootCertStore::empty();
+                for cert in certs {
+                    roots.add(cert.clone()).context("invalid CA certificate")?;
+                }
+                builder.with_root_certificates(roots).with_no_client_auth()
+            }
+            Self::PinnedSha256(pin) => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+                    pin: *pin,
+                    provider,
+                }))
+                .with_no_client_auth(),
+        };
+        Ok(Some(config))
+    }
+}
+
+fn env_value(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Accepts the one certificate whose SHA-256 matches, still requiring the handshake to
+/// be signed by its key.
+struct PinnedCertVerifier {
+    pin: [u8; 32],
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedCertVerifier {
+    fn algorithms(&self) -> &WebPkiSupportedAlgorithms {
+        &self.provider.signature_verification_algorithms
+    }
+}
+
+impl fmt::Debug for PinnedCertVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PinnedCertVerifier")
+            .field("pin", &hex::encode(self.pin))
+            .finish()
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Sha256::digest(end_entity.as_ref()).as_slice() == self.pin {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, self.algorithms())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, self.algorithms())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms().supported_schemes()
+    }
+}
+
+#[cfg(test)]
+#[path = "tests/tls_tests.rs"]
+mod tests;
@@ -0,0 +1,111 @@
+use std::fmt::Display;
+
+use openmls::framing::errors::{MessageDecryptionError, SecretTreeError};
+use openmls::group::{ProcessMessageError, ValidationError, WelcomeError};
+use thiserror::Error;
+
+pub type MlsResult<T> = std::result::Result<T, MlsError>;
+
+#[derive(Debug, Error)]
+pub enum MlsError {
+    #[error("MLS group not initialized")]
+    NotInitialized,
+    #[error("MLS backend unavailable")]
+    Unavailable,
+    #[error("MLS message is for a different epoch than the local group")]
+    WrongEpoch,
+    #[error("MLS message secret was already used or deleted to preserve forward secrecy")]
+    SecretReuse,
+    #[error("MLS message generation is outside the retained ratchet window")]
+    GenerationOutOfBounds,
+    #[error("failed to decrypt MLS message: {0}")]
+    Decryption(DecryptionFailure),
+    #[error("malformed MLS input: {0}")]
+    Malformed(String),
+    #[error("member is already in the MLS group")]
+    DuplicateMember,
+    #[error("an MLS group for this channel already exists locally")]
+    GroupAlreadyExists,
+    #[error("no pending MLS join state; the key package was not generated by this device")]
+    MissingPendingJoinState,
+    #[error("cannot create an MLS group while the handle is in join-only mode")]
+    JoinOnly,
+    #[error("channel {channel_id} is already bound to guild {guild_id} in this MLS session")]
+    ChannelGuildMismatch { channel_id: i64, guild_id: i64 },
+    #[error("invalid persisted MLS snapshot: {0}")]
+    Snapshot(String),
+    #[error("MLS storage error: {0:#}")]
+    Storage(anyhow::Error),
+    #[error("MLS operation failed: {0}")]
+    Protocol(String),
+}
+
+/// Why a private message that reached decryption could not be opened.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DecryptionFailure {
+    /// The AEAD tag did not verify: the message was tampered with, or the local key
+    /// schedule no longer matches the sender's.
+    #[error("AEAD authentication failed")]
+    Aead,
+    /// The sender's leaf is not in the local ratchet tree.
+    #[error("sender is not in the local ratchet tree")]
+    UnknownSender,
+    /// The generation is older than the retained ratchet window; its secret is gone.
+    #[error("message is older than the retained ratchet window")]
+    Expired,
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl MlsError {
+    pub(crate) fn protocol(err: impl Display) -> Self {
+        Self::Protocol(err.to_string())
+    }
+
+    pub(crate) fn malformed(err: impl Display) -> Self {
+        Self::Malformed(err.to_string())
+    }
+}
+
+pub(crate) fn map_process_error<E: Display>(err: ProcessMessageError<E>) -> MlsError {
+    match err {
+        ProcessMessageError::ValidationError(ValidationError::WrongEpoch) => MlsError::WrongEpoch,
+        ProcessMessageError::ValidationError(ValidationError::UnableToDecrypt(err)) => {
+            map_decryption_error(err)
+        }
+        ProcessMessageError::ValidationError(ValidationError::WrongGroupId)
+        | ProcessMessageError::IncompatibleWireFormat => MlsError::malformed(err),
+        other => MlsError::protocol(other),
+    }
+}
+
+fn map_decryption_error(err: MessageDecryptionError) -> MlsError {
+    match err {
+        MessageDecryptionError::SecretTreeError(SecretTreeError::SecretReuseError) => {
+            MlsError::SecretReuse
+        }
+        MessageDecryptionError::GenerationOutOfBound
+        | MessageDecryptionError::SecretTreeError(SecretTreeError::TooDistantInTheFuture) => {
+            MlsError::GenerationOutOfBounds
+        }
+        MessageDecryptionError::SecretTreeError(SecretTreeError::TooDistantInThePast) => {
+            MlsError::Decryption(DecryptionFailure::Expired)
+        }
+        MessageDecryptionError::AeadError => MlsError::Decryption(DecryptionFailure::Aead),
+        MessageDecryptionError::SecretTreeError(SecretTreeError::IndexOutOfBounds) => {
+            MlsError::Decryption(DecryptionFailure::UnknownSender)
+        }
+        MessageDecryptionError::MalformedContent | MessageDecryptionError::WrongWireFormat => {
+            MlsError::malformed(err)
+        }
+        other => MlsError::Decryption(DecryptionFailure::Internal(other.to_string())),
+    }
+}
+
+pub(crate) fn map_welcome_error<E: Display>(err: WelcomeError<E>) -> MlsError {
+    match err {
+        WelcomeError::GroupAlreadyExists => MlsError::GroupAlreadyExists,
+        WelcomeError::NoMatchingKeyPackage => MlsError::MissingPendingJoinState,
+        other => MlsError::Protocol(format!("failed to stage welcome: {other}")),
+    }
+}
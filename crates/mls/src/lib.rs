@@ -1,15 +1,31 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::Result;
 use async_trait::async_trait;
 use openmls::prelude::*;
 use openmls_basic_credential::SignatureKeyPair;
-use openmls_rust_crypto::{MemoryStorage, RustCrypto};
+use openmls_rust_crypto::RustCrypto;
 use openmls_traits::OpenMlsProvider;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use shared::domain::{ChannelId, GuildId};
 use tls_codec::{Deserialize as TlsDeserializeTrait, Serialize as TlsSerializeTrait};
 
+mod error;
+mod provider_storage;
+
+use error::{map_process_error, map_welcome_error};
+pub use error::{DecryptionFailure, MlsError, MlsResult};
+pub use provider_storage::{
+    IncrementalStorage, ProviderChanges, ProviderEntry, ProviderStorageError,
+};
+
 const CIPHERSUITE: Ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
-const MLS_SNAPSHOT_SCHEMA_VERSION: i32 = 2; // bumped
+/// Self-contained snapshot: provider entries are serialized into `key_material_blob`.
+/// Used for pending joins, exports and backups, and by group rows written before v3.
+const MLS_SNAPSHOT_SCHEMA_VERSION: i32 = 2;
+/// Group rows whose provider entries live in the store's incremental entry table.
+const MLS_GROUP_STATE_SCHEMA_VERSION: i32 = 3;
+const CREDENTIAL_FINGERPRINT_DOMAIN: &[u8] = b"proto-rtc:mls-credential:v1";
+const CREDENTIAL_FINGERPRINT_PREFIX: &str = "mls-sha256:";
 
 #[derive(Debug, Clone)]
 pub struct PersistedGroupSnapshot {
@@ -18,6 +34,17 @@ pub struct PersistedGroupSnapshot {
     pub key_material_blob: Vec<u8>,
 }
 
+/// Outcome of processing one inbound MLS message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MlsInbound {
+    /// Decrypted application payload, which may legitimately be empty.
+    Application(Vec<u8>),
+    /// A commit was merged and the group advanced to a new epoch.
+    Commit,
+    /// A proposal was stored for a later commit.
+    Proposal,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct MlsSnapshotV2 {
     /// None => pending join provider state only (no group yet)
@@ -87,6 +114,19 @@ impl MlsIdentity {
         Ok(self.key_package(provider)?.tls_serialize_detached()?)
     }
 
+    /// The name the credential was created with, e.g. `user:{user_id}:{device_id}`.
+    pub fn credential_name(&self) -> &[u8] {
+        self.credential_with_key.credential.serialized_content()
+    }
+
+    /// What this device registers as its public identity; see [`credential_fingerprint`].
+    pub fn public_fingerprint(&self) -> MlsResult<String> {
+        credential_fingerprint(
+            &self.credential_with_key.credential,
+            self.credential_with_key.signature_key.as_slice(),
+        )
+    }
+
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
         Ok(serde_json::to_vec(&SerializedIdentityV1Ref {
             credential_with_key: &self.credential_with_key,
@@ -103,6 +143,47 @@ impl MlsIdentity {
     }
 }
 
+/// SHA-256 over a credential and its signature key, as logged for the device in key
+/// transparency. Two key packages share a fingerprint only if they were signed by the
+/// same device identity.
+pub fn credential_fingerprint(credential: &Credential, signature_key: &[u8]) -> MlsResult<String> {
+    let credential_bytes = credential
+        .tls_serialize_detached()
+        .map_err(MlsError::malformed)?;
+    let mut hasher = Sha256::new();
+    hasher.update(CREDENTIAL_FINGERPRINT_DOMAIN);
+    hasher.update((credential_bytes.len() as u32).to_be_bytes());
+    hasher.update(&credential_bytes);
+    hasher.update(signature_key);
+    Ok(format!(
+        "{CREDENTIAL_FINGERPRINT_PREFIX}{}",
+        hex::encode(hasher.finalize())
+    ))
+}
+
+/// Validates a serialized key package and returns the fingerprint of its leaf credential.
+pub fn key_package_fingerprint(key_package_bytes: &[u8]) -> MlsResult<String> {
+    let key_package = validate_key_package(&RustCrypto::default(), key_package_bytes)?;
+    let leaf = key_package.leaf_node();
+    credential_fingerprint(leaf.credential(), leaf.signature_key().as_slice())
+}
+
+fn validate_key_package(
+    crypto: &impl openmls_traits::crypto::OpenMlsCrypto,
+    key_package_bytes: &[u8],
+) -> MlsResult<KeyPackage> {
+    let mut bytes = key_package_bytes;
+    let key_package_in = <KeyPackageIn as TlsDeserializeTrait>::tls_deserialize(&mut bytes)
+        .map_err(MlsError::malformed)?;
+    if !bytes.is_empty() {
+        return Err(MlsError::malformed("key package bytes had trailing data"));
+    }
+
+    key_package_in
+        .validate(crypto, ProtocolVersion::default())
+        .map_err(|e| MlsError::Malformed(format!("invalid key package bytes: {e}")))
+}
+
 #[async_trait]
 pub trait MlsStore: Send + Sync {
     async fn save_identity_keys(
@@ -154,18 +235,69 @@ pub trait MlsStore: Send + Sync {
         device_id: &str,
         guild_id: GuildId,
     ) -> Result<()>;
+
+    /// Provider entries of a group row saved with schema v3.
+    async fn load_group_provider_entries(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<Vec<ProviderEntry>>;
+
+    /// Writes the group row and applies `changes` to its provider entries atomically.
+    async fn save_group_state_incremental(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        snapshot: PersistedGroupSnapshot,
+        changes: &ProviderChanges,
+    ) -> Result<()>;
+}
+
+/// Loads a group as a self-contained schema v2 snapshot, materializing the provider entries
+/// of v3 rows. Suitable for exports and backups that are restored with `save_group_state`.
+pub async fn load_portable_group_state<S: MlsStore>(
+    store: &S,
+    user_id: i64,
+    device_id: &str,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> MlsResult<Option<PersistedGroupSnapshot>> {
+    let Some(snapshot) = store
+        .load_group_state(user_id, device_id, guild_id, channel_id)
+        .await
+        .map_err(MlsError::Storage)?
+    else {
+        return Ok(None);
+    };
+    if snapshot.schema_version != MLS_GROUP_STATE_SCHEMA_VERSION {
+        return Ok(Some(snapshot));
+    }
+
+    let entries = store
+        .load_group_provider_entries(user_id, device_id, guild_id, channel_id)
+        .await
+        .map_err(MlsError::Storage)?;
+    Ok(Some(PersistedGroupSnapshot {
+        schema_version: MLS_SNAPSHOT_SCHEMA_VERSION,
+        group_state_blob: snapshot.group_state_blob,
+        key_material_blob: serialize_entries(entries)?,
+    }))
 }
 
 #[derive(Default, Debug)]
 pub struct PersistentOpenMlsProvider {
     crypto: RustCrypto,
-    storage: MemoryStorage,
+    storage: IncrementalStorage,
 }
 
 impl OpenMlsProvider for PersistentOpenMlsProvider {
     type CryptoProvider = RustCrypto;
     type RandProvider = RustCrypto;
-    type StorageProvider = MemoryStorage;
+    type StorageProvider = IncrementalStorage;
 
     fn storage(&self) -> &Self::StorageProvider {
         &self.storage
@@ -192,6 +324,9 @@ pub struct MlsGroupHandle<S: MlsStore> {
     /// true when this handle was created specifically to consume a Welcome using
     /// previously persisted pending key material.
     join_only_mode: bool,
+    /// true once the store's provider entries for this channel match `provider`, so
+    /// persisting only needs the entries changed since.
+    entries_persisted: bool,
 }
 
 impl<S: MlsStore> MlsGroupHandle<S> {
@@ -202,7 +337,7 @@ impl<S: MlsStore> MlsGroupHandle<S> {
         guild_id: GuildId,
         channel_id: ChannelId,
         identity: MlsIdentity,
-    ) -> Result<Self> {
+    ) -> MlsResult<Self> {
         let mut handle = Self {
             store,
             user_id,
@@ -213,6 +348,7 @@ impl<S: MlsStore> MlsGroupHandle<S> {
             identity,
             group: None,
             join_only_mode: false,
+            entries_persisted: false,
         };
         handle.load_group_if_exists().await?;
         Ok(handle)
@@ -228,7 +364,7 @@ impl<S: MlsStore> MlsGroupHandle<S> {
         guild_id: GuildId,
         channel_id: ChannelId,
         identity: MlsIdentity,
-    ) -> Result<Self> {
+    ) -> MlsResult<Self> {
         let mut handle = Self {
             store,
             user_id,
@@ -239,23 +375,16 @@ impl<S: MlsStore> MlsGroupHandle<S> {
             identity,
             group: None,
             join_only_mode: true,
+            entries_persisted: false,
         };
 
         // Load pending key package/provider state. This is required to process Welcome.
-        handle
-            .load_pending_join_provider_state()
-            .await
-            .with_context(|| {
-                format!(
-                    "failed to load pending MLS join state for user={} device={} guild={}",
-                    handle.user_id, handle.device_id, handle.guild_id.0
-                )
-            })?;
+        handle.load_pending_join_provider_state().await?;
 
         Ok(handle)
     }
 
-    pub async fn load_or_create_group(&mut self) -> Result<()> {
+    pub async fn load_or_create_group(&mut self) -> MlsResult<()> {
         if self.group.is_none() {
             self.create_group(self.channel_id).await?;
         }
@@ -264,19 +393,18 @@ impl<S: MlsStore> MlsGroupHandle<S> {
 
     /// Generate a KeyPackage and persist the provider key material required to later
     /// consume a Welcome in a different process/handle.
-    pub async fn key_package_bytes(&mut self) -> Result<Vec<u8>> {
-        let key_package = self.identity.key_package_bytes(&self.provider)?;
+    pub async fn key_package_bytes(&mut self) -> MlsResult<Vec<u8>> {
+        let key_package = self
+            .identity
+            .key_package_bytes(&self.provider)
+            .map_err(MlsError::protocol)?;
         self.persist_pending_join_provider_state().await?;
         Ok(key_package)
     }
 
-    pub async fn create_group(&mut self, channel_id: ChannelId) -> Result<()> {
+    pub async fn create_group(&mut self, channel_id: ChannelId) -> MlsResult<()> {
         if self.join_only_mode {
-            return Err(anyhow!(
-                "cannot create group while handle is in join-only mode for guild={} channel={}",
-                self.guild_id.0,
-                self.channel_id.0
-            ));
+            return Err(MlsError::JoinOnly);
         }
 
         let group_id = GroupId::from_slice(&channel_id.0.to_le_bytes());
@@ -291,197 +419,330 @@ impl<S: MlsStore> MlsGroupHandle<S> {
             &config,
             group_id,
             self.identity.credential_with_key.clone(),
-        )?;
+        )
+        .map_err(|e| match e {
+            NewGroupError::GroupAlreadyExists => MlsError::GroupAlreadyExists,
+            other => MlsError::protocol(other),
+        })?;
 
         self.group = Some(group);
         self.persist_group().await
     }
 
-    pub async fn join_group_from_welcome(&mut self, welcome_bytes: &[u8]) -> Result<()> {
+    pub async fn join_group_from_welcome(&mut self, welcome_bytes: &[u8]) -> MlsResult<()> {
         let mut bytes = welcome_bytes;
-        let welcome_message = MlsMessageIn::tls_deserialize(&mut bytes)?;
+        let welcome_message =
+            MlsMessageIn::tls_deserialize(&mut bytes).map_err(MlsError::malformed)?;
         if !bytes.is_empty() {
-            return Err(anyhow!("welcome bytes had trailing data"));
+            return Err(MlsError::malformed("welcome bytes had trailing data"));
         }
 
         let welcome = match welcome_message.extract() {
             MlsMessageBodyIn::Welcome(w) => w,
-            _ => return Err(anyhow!("welcome bytes did not contain a Welcome message")),
+            _ => {
+                return Err(MlsError::malformed(
+                    "welcome bytes did not contain a Welcome message",
+                ))
+            }
         };
 
         let config = MlsGroupJoinConfig::builder()
             .use_ratchet_tree_extension(true)
             .build();
 
+        // A locally created group with no other members is only a placeholder for this
+        // channel; the Welcome replaces it instead of failing with GroupAlreadyExists.
+        if let Some(mut placeholder) = self.group.take() {
+            if placeholder.members().count() > 1 {
+                self.group = Some(placeholder);
+                return Err(MlsError::GroupAlreadyExists);
+            }
+            placeholder
+                .delete(self.provider.storage())
+                .map_err(MlsError::protocol)?;
+        }
+
         let staged = StagedWelcome::new_from_welcome(&self.provider, &config, welcome, None)
-            .map_err(|e| anyhow!("failed to stage welcome: {e}"))?;
+            .map_err(map_welcome_error)?;
 
-        let group = staged
-            .into_group(&self.provider)
-            .map_err(|e| anyhow!("failed to finalize welcome into group: {e}"))?;
+        let group = staged.into_group(&self.provider).map_err(|e| {
+            MlsError::Protocol(format!("failed to finalize welcome into group: {e}"))
+        })?;
 
         self.group = Some(group);
 
-        // Now that join succeeded, clear pending join state and persist actual group snapshot.
+        // Persist the group before dropping the pending join state, so a crash in between
+        // leaves one of the two on disk.
+        self.persist_group().await?;
         self.store
             .clear_pending_join_state(self.user_id, &self.device_id, self.guild_id)
-            .await?;
-        self.persist_group().await
+            .await
+            .map_err(MlsError::Storage)
     }
 
     pub async fn add_member(
         &mut self,
         key_package_bytes: &[u8],
-    ) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
-        let mut bytes = key_package_bytes;
-        let key_package_in = <KeyPackageIn as TlsDeserializeTrait>::tls_deserialize(&mut bytes)?;
-        if !bytes.is_empty() {
-            return Err(anyhow!("key package bytes had trailing data"));
-        }
+    ) -> MlsResult<(Vec<u8>, Option<Vec<u8>>)> {
+        let key_package = self.validate_key_package(key_package_bytes)?;
 
         let provider = &self.provider;
-        let key_package = key_package_in
-            .validate(provider.crypto(), ProtocolVersion::default())
-            .map_err(|e| anyhow!("invalid key package bytes: {e}"))?;
-
         let signer = &self.identity.signer;
-        let group = self
-            .group
-            .as_mut()
-            .ok_or_else(|| anyhow!("MLS group not initialized"))?;
+        let group = self.group.as_mut().ok_or(MlsError::NotInitialized)?;
 
-        let (commit, welcome, _group_info) = group.add_members(provider, signer, &[key_package])?;
+        let target_signature_key = key_package.leaf_node().signature_key().as_slice();
+        if group
+            .members()
+            .any(|member| member.signature_key.as_slice() == target_signature_key)
+        {
+            return Err(MlsError::DuplicateMember);
+        }
 
-        let commit_bytes = commit.tls_serialize_detached()?;
-        let welcome_bytes = Some(welcome.tls_serialize_detached()?);
+        let (commit, welcome, _group_info) = group
+            .add_members(provider, signer, &[key_package])
+            .map_err(MlsError::protocol)?;
+
+        let commit_bytes = commit
+            .tls_serialize_detached()
+            .map_err(MlsError::protocol)?;
+        let welcome_bytes = Some(
+            welcome
+                .tls_serialize_detached()
+                .map_err(MlsError::protocol)?,
+        );
 
-        group.merge_pending_commit(provider)?;
+        group
+            .merge_pending_commit(provider)
+            .map_err(MlsError::protocol)?;
         self.persist_group().await?;
 
         Ok((commit_bytes, welcome_bytes))
     }
 
-    pub fn group_contains_key_package_identity(&self, key_package_bytes: &[u8]) -> Result<bool> {
-        let mut bytes = key_package_bytes;
-        let key_package_in = <KeyPackageIn as TlsDeserializeTrait>::tls_deserialize(&mut bytes)?;
-        if !bytes.is_empty() {
-            return Err(anyhow!("key package bytes had trailing data"));
-        }
-
-        let key_package = key_package_in
-            .validate(self.provider.crypto(), ProtocolVersion::default())
-            .map_err(|e| anyhow!("invalid key package bytes: {e}"))?;
-
+    pub fn group_contains_key_package_identity(&self, key_package_bytes: &[u8]) -> MlsResult<bool> {
+        let key_package = self.validate_key_package(key_package_bytes)?;
         let target_signature_key = key_package.leaf_node().signature_key().as_slice();
 
-        let group = self
-            .group
-            .as_ref()
-            .ok_or_else(|| anyhow!("MLS group not initialized"))?;
+        let group = self.group.as_ref().ok_or(MlsError::NotInitialized)?;
 
         Ok(group
             .members()
             .any(|member| member.signature_key.as_slice() == target_signature_key))
     }
 
-    pub fn encrypt_application(&mut self, plaintext_bytes: &[u8]) -> Result<Vec<u8>> {
+    /// Basic-credential identities of every current group member, including this one.
+    pub fn member_identities(&self) -> MlsResult<Vec<Vec<u8>>> {
+        let group = self.group.as_ref().ok_or(MlsError::NotInitialized)?;
+        Ok(group
+            .members()
+            .filter_map(|member| BasicCredential::try_from(member.credential).ok())
+            .map(|credential| credential.identity().to_vec())
+            .collect())
+    }
+
+    /// Removes the members whose credential identity is listed. Returns the commit to fan
+    /// out, or `None` when none of them are in the group. This member is never removed.
+    pub async fn remove_members(&mut self, identities: &[Vec<u8>]) -> MlsResult<Option<Vec<u8>>> {
         let provider = &self.provider;
         let signer = &self.identity.signer;
-        let group = self
-            .group
-            .as_mut()
-            .ok_or_else(|| anyhow!("MLS group not initialized"))?;
-
-        let msg = group.create_message(provider, signer, plaintext_bytes)?;
-        Ok(msg.tls_serialize_detached()?)
-    }
-
-    pub async fn decrypt_application(&mut self, ciphertext_bytes: &[u8]) -> Result<Vec<u8>> {
-        let mut ciphertext_bytes = ciphertext_bytes;
-        let message_in = match MlsMessageIn::tls_deserialize(&mut ciphertext_bytes) {
-            Ok(m) => m,
-            Err(e) => {
-                let s = e.to_string();
-                if s.contains("SecretReuseError") || s.contains("requested secret was deleted") {
-                    return Ok(Vec::new());
-                }
-                return Err(anyhow!("failed to deserialize MLS message: {e}"));
-            }
-        };
+        let own_signature_key = self.identity.credential_with_key.signature_key.as_slice();
+        let group = self.group.as_mut().ok_or(MlsError::NotInitialized)?;
 
-        let protocol_message: ProtocolMessage = match message_in.try_into_protocol_message() {
-            Ok(p) => p,
-            Err(_) => return Err(anyhow!("ciphertext did not contain a protocol message")),
-        };
+        let leaf_indices: Vec<LeafNodeIndex> = group
+            .members()
+            .filter(|member| member.signature_key.as_slice() != own_signature_key)
+            .filter(|member| {
+                BasicCredential::try_from(member.credential.clone()).is_ok_and(|credential| {
+                    identities
+                        .iter()
+                        .any(|identity| identity.as_slice() == credential.identity())
+                })
+            })
+            .map(|member| member.index)
+            .collect();
+        if leaf_indices.is_empty() {
+            return Ok(None);
+        }
+
+        let (commit, _welcome, _group_info) = group
+            .remove_members(provider, signer, &leaf_indices)
+            .map_err(MlsError::protocol)?;
+        let commit_bytes = commit
+            .tls_serialize_detached()
+            .map_err(MlsError::protocol)?;
+        group
+            .merge_pending_commit(provider)
+            .map_err(MlsError::protocol)?;
+        self.persist_group().await?;
+
+        Ok(Some(commit_bytes))
+    }
 
+    /// Encrypts one application message. The advanced sender ratchet is persisted before the
+    /// ciphertext is returned, so a restart never encrypts under the same generation twice.
+    pub async fn encrypt_application(&mut self, plaintext_bytes: &[u8]) -> MlsResult<Vec<u8>> {
         let provider = &self.provider;
-        let group = self
-            .group
-            .as_mut()
-            .ok_or_else(|| anyhow!("MLS group not initialized"))?;
-
-        let processed = match group.process_message(provider, protocol_message) {
-            Ok(p) => p,
-            Err(e) => {
-                let s = e.to_string();
-                // Replay / FS-pruned secrets can happen in history fetch paths and are safe to skip.
-                if s.contains("SecretReuseError") || s.contains("requested secret was deleted") {
-                    return Ok(Vec::new());
-                }
-                return Err(anyhow!("failed to process MLS message: {e}"));
-            }
-        };
+        let signer = &self.identity.signer;
+        let group = self.group.as_mut().ok_or(MlsError::NotInitialized)?;
+
+        let ciphertext = group
+            .create_message(provider, signer, plaintext_bytes)
+            .map_err(MlsError::protocol)?
+            .tls_serialize_detached()
+            .map_err(MlsError::protocol)?;
+        self.persist_group().await?;
+        Ok(ciphertext)
+    }
+
+    /// Processes one inbound MLS message. Commits are merged, and both commits and consumed
+    /// application secrets are persisted before returning.
+    pub async fn decrypt_application(&mut self, ciphertext_bytes: &[u8]) -> MlsResult<MlsInbound> {
+        let protocol_message = parse_protocol_message(ciphertext_bytes)?;
+        Ok(self.process_inbound(protocol_message).await?.0)
+    }
+
+    /// Decrypts an application message and returns the basic-credential identity of the
+    /// member that signed it with the plaintext. Commits and proposals are rejected
+    /// unprocessed, so this never changes the epoch.
+    pub async fn decrypt_application_with_sender(
+        &mut self,
+        ciphertext_bytes: &[u8],
+    ) -> MlsResult<(Vec<u8>, Vec<u8>)> {
+        let protocol_message = parse_protocol_message(ciphertext_bytes)?;
+        if protocol_message.content_type() != ContentType::Application {
+            return Err(MlsError::malformed("expected an MLS application message"));
+        }
+        match self.process_inbound(protocol_message).await? {
+            (MlsInbound::Application(plaintext), sender) => Ok((sender, plaintext)),
+            _ => Err(MlsError::malformed("expected an MLS application message")),
+        }
+    }
+
+    async fn process_inbound(
+        &mut self,
+        protocol_message: ProtocolMessage,
+    ) -> MlsResult<(MlsInbound, Vec<u8>)> {
+        let provider = &self.provider;
+        let group = self.group.as_mut().ok_or(MlsError::NotInitialized)?;
 
-        match processed.into_content() {
-            ProcessedMessageContent::ApplicationMessage(app_msg) => Ok(app_msg.into_bytes()),
+        let processed = group
+            .process_message(provider, protocol_message)
+            .map_err(map_process_error)?;
+        let sender = BasicCredential::try_from(processed.credential().clone())
+            .map(|credential| credential.identity().to_vec())
+            .unwrap_or_default();
+
+        let inbound = match processed.into_content() {
+            ProcessedMessageContent::ApplicationMessage(app_msg) => {
+                self.persist_group().await?;
+                MlsInbound::Application(app_msg.into_bytes())
+            }
             ProcessedMessageContent::StagedCommitMessage(staged_commit) => {
-                group.merge_staged_commit(provider, *staged_commit)?;
+                group
+                    .merge_staged_commit(provider, *staged_commit)
+                    .map_err(MlsError::protocol)?;
                 self.persist_group().await?;
-                Ok(Vec::new())
+                MlsInbound::Commit
             }
-            _ => Ok(Vec::new()),
-        }
+            ProcessedMessageContent::ProposalMessage(_)
+            | ProcessedMessageContent::ExternalJoinProposalMessage(_) => MlsInbound::Proposal,
+        };
+        Ok((inbound, sender))
+    }
+
+    pub fn export_secret(&self, label: &str, len: usize) -> MlsResult<Vec<u8>> {
+        let group = self.group.as_ref().ok_or(MlsError::NotInitialized)?;
+
+        group
+            .export_secret(self.provider.crypto(), label, &[], len)
+            .map_err(MlsError::protocol)
+    }
+
+    fn validate_key_package(&self, key_package_bytes: &[u8]) -> MlsResult<KeyPackage> {
+        validate_key_package(self.provider.crypto(), key_package_bytes)
     }
 
-    pub fn export_secret(&self, label: &str, len: usize) -> Result<Vec<u8>> {
-        let group = self
-            .group
-            .as_ref()
-            .ok_or_else(|| anyhow!("MLS group not initialized"))?;
+    /// Flushes the provider changes of the last operation. If the store rejects them, the
+    /// in-memory state is rolled back to what the store holds, as if the process had crashed
+    /// before the write, so memory never runs ahead of disk.
+    async fn persist_group(&mut self) -> MlsResult<()> {
+        if let Err(err) = self.write_group().await {
+            self.rollback_to_persisted().await;
+            return Err(err);
+        }
+        Ok(())
+    }
 
-        Ok(group.export_secret(self.provider.crypto(), label, &[], len)?)
+    async fn rollback_to_persisted(&mut self) {
+        self.group = None;
+        self.provider.storage().load(Vec::new());
+        self.entries_persisted = false;
+        // If even the reload fails the handle stays without a group, and callers see
+        // `NotInitialized` until they rejoin.
+        let _ = self.load_group_if_exists().await;
     }
 
-    async fn persist_group(&self) -> Result<()> {
+    async fn write_group(&mut self) -> MlsResult<()> {
         let Some(group) = &self.group else {
             return Ok(());
         };
 
         let group_state_blob = serde_json::to_vec(&MlsSnapshotV2 {
             group_id: Some(group.group_id().as_slice().to_vec()),
-        })?;
-
-        let key_material_blob = self.serialize_provider_state()?;
+        })
+        .map_err(|e| MlsError::Snapshot(e.to_string()))?;
+
+        let storage = self.provider.storage();
+        let changes = if self.entries_persisted {
+            storage.pending_changes()
+        } else {
+            ProviderChanges {
+                replace_all: true,
+                upserts: storage.entries(),
+                deletes: Vec::new(),
+            }
+        };
 
         self.store
-            .save_group_state(
+            .save_group_state_incremental(
                 self.user_id,
                 &self.device_id,
                 self.guild_id,
                 self.channel_id,
                 PersistedGroupSnapshot {
-                    schema_version: MLS_SNAPSHOT_SCHEMA_VERSION,
+                    schema_version: MLS_GROUP_STATE_SCHEMA_VERSION,
                     group_state_blob,
-                    key_material_blob,
+                    key_material_blob: Vec::new(),
                 },
+                &changes,
             )
-            .await?;
+            .await
+            .map_err(MlsError::Storage)?;
 
+        storage.mark_persisted(&changes);
+        self.entries_persisted = true;
         Ok(())
     }
 
-    async fn persist_pending_join_provider_state(&self) -> Result<()> {
-        let group_state_blob = serde_json::to_vec(&MlsSnapshotV2 { group_id: None })?;
+    /// Self-contained schema v2 snapshot of this group, as written before incremental
+    /// persistence. Used for portable exports and to compare persistence costs.
+    pub fn full_snapshot(&self) -> MlsResult<PersistedGroupSnapshot> {
+        let group = self.group.as_ref().ok_or(MlsError::NotInitialized)?;
+        let group_state_blob = serde_json::to_vec(&MlsSnapshotV2 {
+            group_id: Some(group.group_id().as_slice().to_vec()),
+        })
+        .map_err(|e| MlsError::Snapshot(e.to_string()))?;
+
+        Ok(PersistedGroupSnapshot {
+            schema_version: MLS_SNAPSHOT_SCHEMA_VERSION,
+            group_state_blob,
+            key_material_blob: self.serialize_provider_state()?,
+        })
+    }
+
+    async fn persist_pending_join_provider_state(&self) -> MlsResult<()> {
+        let group_state_blob = serde_json::to_vec(&MlsSnapshotV2 { group_id: None })
+            .map_err(|e| MlsError::Snapshot(e.to_string()))?;
         let key_material_blob = self.serialize_provider_state()?;
 
         self.store
@@ -495,12 +756,11 @@ impl<S: MlsStore> MlsGroupHandle<S> {
                     key_material_blob,
                 },
             )
-            .await?;
-
-        Ok(())
+            .await
+            .map_err(MlsError::Storage)
     }
 
-    async fn load_group_if_exists(&mut self) -> Result<()> {
+    async fn load_group_if_exists(&mut self) -> MlsResult<()> {
         let Some(snapshot) = self
             .store
             .load_group_state(
@@ -509,7 +769,8 @@ impl<S: MlsStore> MlsGroupHandle<S> {
                 self.guild_id,
                 self.channel_id,
             )
-            .await?
+            .await
+            .map_err(MlsError::Storage)?
         else {
             return Ok(());
         };
@@ -517,15 +778,14 @@ impl<S: MlsStore> MlsGroupHandle<S> {
         self.restore_from_snapshot(snapshot, true).await
     }
 
-    async fn load_pending_join_provider_state(&mut self) -> Result<()> {
+    async fn load_pending_join_provider_state(&mut self) -> MlsResult<()> {
         let Some(snapshot) = self
             .store
             .load_pending_join_state(self.user_id, &self.device_id, self.guild_id)
-            .await?
+            .await
+            .map_err(MlsError::Storage)?
         else {
-            return Err(anyhow!(
-                "no pending join state found (key package may not have been generated/persisted yet)"
-            ));
+            return Err(MlsError::MissingPendingJoinState);
         };
 
         self.restore_from_snapshot(snapshot, false).await
@@ -535,48 +795,65 @@ impl<S: MlsStore> MlsGroupHandle<S> {
         &mut self,
         snapshot: PersistedGroupSnapshot,
         expect_group: bool,
-    ) -> Result<()> {
-        if snapshot.schema_version != MLS_SNAPSHOT_SCHEMA_VERSION {
-            return Err(anyhow!(
-                "unsupported MLS snapshot schema version: {}",
-                snapshot.schema_version
-            ));
-        }
-
-        let parsed_state: MlsSnapshotV2 = serde_json::from_slice(&snapshot.group_state_blob)?;
-        let parsed_provider_state: SerializedProviderState =
-            serde_json::from_slice(&snapshot.key_material_blob)?;
+    ) -> MlsResult<()> {
+        let migrate_to_incremental = match (expect_group, snapshot.schema_version) {
+            (true, MLS_GROUP_STATE_SCHEMA_VERSION) => false,
+            (_, MLS_SNAPSHOT_SCHEMA_VERSION) => expect_group,
+            (_, other) => {
+                return Err(MlsError::Snapshot(format!(
+                    "unsupported schema version {other}"
+                )))
+            }
+        };
 
-        {
-            let mut values = self.provider.storage().values.write().unwrap();
-            values.clear();
-            values.extend(
-                parsed_provider_state
-                    .values
-                    .into_iter()
-                    .map(|entry| (entry.key, entry.value)),
-            );
-        }
+        let parsed_state: MlsSnapshotV2 = serde_json::from_slice(&snapshot.group_state_blob)
+            .map_err(|e| MlsError::Snapshot(e.to_string()))?;
+        let entries = if snapshot.schema_version == MLS_GROUP_STATE_SCHEMA_VERSION {
+            self.store
+                .load_group_provider_entries(
+                    self.user_id,
+                    &self.device_id,
+                    self.guild_id,
+                    self.channel_id,
+                )
+                .await
+                .map_err(MlsError::Storage)?
+        } else {
+            let parsed_provider_state: SerializedProviderState =
+                serde_json::from_slice(&snapshot.key_material_blob)
+                    .map_err(|e| MlsError::Snapshot(e.to_string()))?;
+            parsed_provider_state
+                .values
+                .into_iter()
+                .map(|entry| ProviderEntry {
+                    key: entry.key,
+                    value: entry.value,
+                })
+                .collect()
+        };
+        self.provider.storage().load(entries);
+        self.entries_persisted = expect_group && !migrate_to_incremental;
 
         match (expect_group, parsed_state.group_id) {
             (true, Some(group_id_bytes)) => {
                 let group_id = GroupId::from_slice(&group_id_bytes);
-                self.group = MlsGroup::load(self.provider.storage(), &group_id)?;
+                self.group = MlsGroup::load(self.provider.storage(), &group_id)
+                    .map_err(|e| MlsError::Snapshot(e.to_string()))?;
 
                 if self.group.is_none() {
-                    return Err(anyhow!(
-                        "persisted MLS snapshot did not contain a loadable group"
+                    return Err(MlsError::Snapshot(
+                        "persisted snapshot did not contain a loadable group".to_string(),
                     ));
                 }
             }
             (true, None) => {
-                return Err(anyhow!(
-                    "expected group snapshot but found pending-join snapshot"
+                return Err(MlsError::Snapshot(
+                    "expected group snapshot but found pending-join snapshot".to_string(),
                 ));
             }
             (false, Some(_)) => {
-                return Err(anyhow!(
-                    "expected pending-join snapshot but found group snapshot"
+                return Err(MlsError::Snapshot(
+                    "expected pending-join snapshot but found group snapshot".to_string(),
                 ));
             }
             (false, None) => {
@@ -585,31 +862,50 @@ impl<S: MlsStore> MlsGroupHandle<S> {
             }
         }
 
+        if migrate_to_incremental {
+            self.write_group().await?;
+        }
+
         Ok(())
     }
 
-    fn serialize_provider_state(&self) -> Result<Vec<u8>> {
-        let values = self
-            .provider
-            .storage()
-            .values
-            .read()
-            .unwrap()
-            .iter()
-            .map(|(key, value)| SerializedProviderValue {
-                key: key.clone(),
-                value: value.clone(),
-            })
-            .collect();
-
-        Ok(serde_json::to_vec(&SerializedProviderState { values })?)
+    fn serialize_provider_state(&self) -> MlsResult<Vec<u8>> {
+        serialize_entries(self.provider.storage().entries())
     }
 }
 
+fn parse_protocol_message(ciphertext_bytes: &[u8]) -> MlsResult<ProtocolMessage> {
+    let mut ciphertext_bytes = ciphertext_bytes;
+    let message_in = MlsMessageIn::tls_deserialize(&mut ciphertext_bytes)
+        .map_err(|e| MlsError::Malformed(format!("failed to deserialize MLS message: {e}")))?;
+    message_in
+        .try_into_protocol_message()
+        .map_err(|_| MlsError::malformed("ciphertext did not contain a protocol message"))
+}
+
+fn serialize_entries(mut entries: Vec<ProviderEntry>) -> MlsResult<Vec<u8>> {
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    let values = entries
+        .into_iter()
+        .map(|entry| SerializedProviderValue {
+            key: entry.key,
+            value: entry.value,
+        })
+        .collect();
+    serde_json::to_vec(&SerializedProviderState { values })
+        .map_err(|e| MlsError::Snapshot(e.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{collections::HashMap, sync::Arc};
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
     use tokio::sync::Mutex;
 
     type IdentityKey = (i64, String);
@@ -622,6 +918,10 @@ mod tests {
         identities: BlobMap<IdentityKey, Vec<u8>>,
         groups: BlobMap<GroupKey, PersistedGroupSnapshot>,
         pending_joins: BlobMap<PendingJoinKey, PersistedGroupSnapshot>,
+        provider_entries: BlobMap<GroupKey, HashMap<Vec<u8>, Vec<u8>>>,
+        last_changes: Arc<Mutex<Option<ProviderChanges>>>,
+        /// Makes group writes fail, standing in for a crash before the flush.
+        fail_group_writes: Arc<AtomicBool>,
     }
 
     #[async_trait]
@@ -722,6 +1022,61 @@ mod tests {
                 .remove(&(user_id, device_id.to_string(), guild_id.0));
             Ok(())
         }
+
+        async fn load_group_provider_entries(
+            &self,
+            user_id: i64,
+            device_id: &str,
+            guild_id: GuildId,
+            channel_id: ChannelId,
+        ) -> Result<Vec<ProviderEntry>> {
+            Ok(self
+                .provider_entries
+                .lock()
+                .await
+                .get(&(user_id, device_id.to_string(), guild_id.0, channel_id.0))
+                .map(|entries| {
+                    entries
+                        .iter()
+                        .map(|(key, value)| ProviderEntry {
+                            key: key.clone(),
+                            value: value.clone(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default())
+        }
+
+        async fn save_group_state_incremental(
+            &self,
+            user_id: i64,
+            device_id: &str,
+            guild_id: GuildId,
+            channel_id: ChannelId,
+            snapshot: PersistedGroupSnapshot,
+            changes: &ProviderChanges,
+        ) -> Result<()> {
+            if self.fail_group_writes.load(Ordering::SeqCst) {
+                anyhow::bail!("simulated crash before the group write");
+            }
+            let key = (user_id, device_id.to_string(), guild_id.0, channel_id.0);
+            {
+                let mut all_entries = self.provider_entries.lock().await;
+                let entries = all_entries.entry(key.clone()).or_default();
+                if changes.replace_all {
+                    entries.clear();
+                }
+                for deleted in &changes.deletes {
+                    entries.remove(deleted);
+                }
+                for entry in &changes.upserts {
+                    entries.insert(entry.key.clone(), entry.value.clone());
+                }
+            }
+            self.groups.lock().await.insert(key, snapshot);
+            *self.last_changes.lock().await = Some(changes.clone());
+            Ok(())
+        }
     }
 
     #[test]
@@ -745,6 +1100,23 @@ mod tests {
         assert_eq!(decoded.to_bytes().expect("serialize decoded"), encoded);
     }
 
+    #[test]
+    fn key_package_fingerprint_matches_only_its_own_identity() {
+        let provider = PersistentOpenMlsProvider::default();
+        let alice = MlsIdentity::new_with_name(b"alice".to_vec()).expect("alice identity");
+        let impostor = MlsIdentity::new_with_name(b"alice".to_vec()).expect("impostor identity");
+        let key_package = alice.key_package_bytes(&provider).expect("key package");
+
+        let fingerprint = key_package_fingerprint(&key_package).expect("fingerprint");
+        assert!(fingerprint.starts_with(CREDENTIAL_FINGERPRINT_PREFIX));
+        assert_eq!(fingerprint, alice.public_fingerprint().expect("alice"));
+        assert_ne!(
+            fingerprint,
+            impostor.public_fingerprint().expect("impostor")
+        );
+        assert!(key_package_fingerprint(b"not a key package").is_err());
+    }
+
     #[tokio::test]
     async fn two_members_exchange_application_message() {
         let guild_id = GuildId(1);
@@ -805,6 +1177,7 @@ mod tests {
 
         let ciphertext = alice
             .encrypt_application(b"hello bob")
+            .await
             .expect("encrypt app message");
 
         let plaintext = bob
@@ -812,7 +1185,69 @@ mod tests {
             .await
             .expect("decrypt app message");
 
-        assert_eq!(plaintext, b"hello bob");
+        assert_eq!(plaintext, MlsInbound::Application(b"hello bob".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn removed_member_cannot_read_later_messages() {
+        let guild_id = GuildId(1);
+        let channel_id = ChannelId(10);
+        let mut alice = MlsGroupHandle::new(
+            MemoryStore::default(),
+            1,
+            "device-alice",
+            guild_id,
+            channel_id,
+            MlsIdentity::new_with_name(b"alice".to_vec()).expect("alice identity"),
+        )
+        .await
+        .expect("alice handle");
+        let mut bob = MlsGroupHandle::new(
+            MemoryStore::default(),
+            2,
+            "device-bob",
+            guild_id,
+            channel_id,
+            MlsIdentity::new_with_name(b"bob".to_vec()).expect("bob identity"),
+        )
+        .await
+        .expect("bob handle");
+
+        let bob_kp = bob.key_package_bytes().await.expect("bob key package");
+        alice.create_group(channel_id).await.expect("create group");
+        let (_commit, welcome) = alice.add_member(&bob_kp).await.expect("add member");
+        bob.join_group_from_welcome(&welcome.expect("welcome"))
+            .await
+            .expect("bob joins");
+
+        assert_eq!(
+            alice
+                .remove_members(&[b"carol".to_vec(), b"alice".to_vec()])
+                .await
+                .expect("no-op removal"),
+            None
+        );
+        let commit = alice
+            .remove_members(&[b"bob".to_vec()])
+            .await
+            .expect("remove bob")
+            .expect("removal commit");
+        assert_eq!(
+            alice.member_identities().expect("identities"),
+            vec![b"alice".to_vec()]
+        );
+        assert_eq!(
+            bob.decrypt_application(&commit)
+                .await
+                .expect("bob sees commit"),
+            MlsInbound::Commit
+        );
+
+        let ciphertext = alice
+            .encrypt_application(b"after removal")
+            .await
+            .expect("encrypt");
+        assert!(bob.decrypt_application(&ciphertext).await.is_err());
     }
 
     #[tokio::test]
@@ -877,6 +1312,7 @@ mod tests {
         // Bob never processes Alice's commit that advanced the epoch.
         let ct = alice
             .encrypt_application(b"hello after epoch advance")
+            .await
             .expect("alice encrypt");
         let err = bob
             .decrypt_application(&ct)
@@ -884,12 +1320,86 @@ mod tests {
             .expect_err("bob should fail when missing commit epoch");
 
         assert!(
-            err.to_string().contains("Wrong Epoch")
-                || err
-                    .to_string()
-                    .contains("Message epoch differs from the group's epoch"),
-            "unexpected error: {err:#}"
+            matches!(err, MlsError::WrongEpoch),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn decrypt_application_reports_commits_and_empty_payloads_distinctly() {
+        let guild_id = GuildId(1);
+        let channel_id = ChannelId(55);
+
+        let store = MemoryStore::default();
+
+        let mut alice = MlsGroupHandle::new(
+            store.clone(),
+            1,
+            "device-alice",
+            guild_id,
+            channel_id,
+            MlsIdentity::new_with_name(b"alice".to_vec()).expect("alice identity"),
+        )
+        .await
+        .expect("alice handle");
+        alice
+            .create_group(channel_id)
+            .await
+            .expect("create alice group");
+
+        let mut bob = MlsGroupHandle::new(
+            store.clone(),
+            2,
+            "device-bob",
+            guild_id,
+            channel_id,
+            MlsIdentity::new_with_name(b"bob".to_vec()).expect("bob identity"),
+        )
+        .await
+        .expect("bob handle");
+        let bob_kp = bob.key_package_bytes().await.expect("bob key package");
+        let (_commit_ab, welcome_ab) = alice.add_member(&bob_kp).await.expect("add bob");
+        bob.join_group_from_welcome(&welcome_ab.expect("welcome bob"))
+            .await
+            .expect("bob joins");
+
+        let err = alice
+            .add_member(&bob_kp)
+            .await
+            .expect_err("re-adding bob should fail");
+        assert!(
+            matches!(err, MlsError::DuplicateMember),
+            "unexpected error: {err}"
         );
+
+        let mut charlie = MlsGroupHandle::new(
+            store.clone(),
+            3,
+            "device-charlie",
+            guild_id,
+            channel_id,
+            MlsIdentity::new_with_name(b"charlie".to_vec()).expect("charlie identity"),
+        )
+        .await
+        .expect("charlie handle");
+        let charlie_kp = charlie
+            .key_package_bytes()
+            .await
+            .expect("charlie key package");
+        let (commit_ac, _welcome_ac) = alice.add_member(&charlie_kp).await.expect("add charlie");
+
+        let inbound = bob
+            .decrypt_application(&commit_ac)
+            .await
+            .expect("bob processes commit");
+        assert_eq!(inbound, MlsInbound::Commit);
+
+        let ct = alice
+            .encrypt_application(b"")
+            .await
+            .expect("alice encrypt empty");
+        let inbound = bob.decrypt_application(&ct).await.expect("bob decrypt");
+        assert_eq!(inbound, MlsInbound::Application(Vec::new()));
     }
 
     #[tokio::test]
@@ -954,11 +1464,278 @@ mod tests {
 
         let ct = alice
             .encrypt_application(b"hello after reopen")
+            .await
             .expect("alice encrypt");
         let pt = bob_join
             .decrypt_application(&ct)
             .await
             .expect("bob decrypt");
-        assert_eq!(pt, b"hello after reopen");
+        assert_eq!(pt, MlsInbound::Application(b"hello after reopen".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn commits_persist_only_changed_provider_entries() {
+        let guild_id = GuildId(1);
+        let channel_id = ChannelId(5);
+        let store = MemoryStore::default();
+
+        let mut alice = MlsGroupHandle::new(
+            store.clone(),
+            1,
+            "device-alice",
+            guild_id,
+            channel_id,
+            MlsIdentity::new_with_name(b"alice".to_vec()).expect("alice identity"),
+        )
+        .await
+        .expect("alice handle");
+        alice
+            .create_group(channel_id)
+            .await
+            .expect("create alice group");
+        let initial = store.last_changes.lock().await.clone().expect("changes");
+        assert!(initial.replace_all);
+
+        let bob_store = MemoryStore::default();
+        let mut bob = MlsGroupHandle::new(
+            bob_store,
+            2,
+            "device-bob",
+            guild_id,
+            channel_id,
+            MlsIdentity::new_with_name(b"bob".to_vec()).expect("bob identity"),
+        )
+        .await
+        .expect("bob handle");
+        let bob_kp = bob.key_package_bytes().await.expect("bob key package");
+        alice.add_member(&bob_kp).await.expect("alice add bob");
+
+        let after_commit = store.last_changes.lock().await.clone().expect("changes");
+        assert!(!after_commit.replace_all);
+        let stored_entries = store
+            .load_group_provider_entries(1, "device-alice", guild_id, channel_id)
+            .await
+            .expect("entries");
+        assert!(after_commit.upserts.len() < stored_entries.len());
+
+        let snapshot = store
+            .load_group_state(1, "device-alice", guild_id, channel_id)
+            .await
+            .expect("load")
+            .expect("snapshot");
+        assert_eq!(snapshot.schema_version, MLS_GROUP_STATE_SCHEMA_VERSION);
+        assert!(snapshot.key_material_blob.is_empty());
+
+        let mut stored: Vec<_> = stored_entries;
+        stored.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(stored, alice.provider.storage().entries());
+    }
+
+    #[tokio::test]
+    async fn full_snapshot_rows_migrate_to_incremental_entries_on_load() {
+        let guild_id = GuildId(1);
+        let channel_id = ChannelId(6);
+        let store = MemoryStore::default();
+
+        let mut alice = MlsGroupHandle::new(
+            store.clone(),
+            1,
+            "device-alice",
+            guild_id,
+            channel_id,
+            MlsIdentity::new_with_name(b"alice".to_vec()).expect("alice identity"),
+        )
+        .await
+        .expect("alice handle");
+        alice
+            .create_group(channel_id)
+            .await
+            .expect("create alice group");
+        let full = alice.full_snapshot().expect("full snapshot");
+        drop(alice);
+
+        let legacy_store = MemoryStore::default();
+        legacy_store
+            .save_group_state(1, "device-alice", guild_id, channel_id, full.clone())
+            .await
+            .expect("save legacy row");
+
+        let reopened = MlsGroupHandle::new(
+            legacy_store.clone(),
+            1,
+            "device-alice",
+            guild_id,
+            channel_id,
+            MlsIdentity::new_with_name(b"alice".to_vec()).expect("alice identity"),
+        )
+        .await
+        .expect("reopen from legacy row");
+        assert!(reopened.group.is_some());
+
+        let migrated = legacy_store
+            .load_group_state(1, "device-alice", guild_id, channel_id)
+            .await
+            .expect("load")
+            .expect("snapshot");
+        assert_eq!(migrated.schema_version, MLS_GROUP_STATE_SCHEMA_VERSION);
+
+        let portable =
+            load_portable_group_state(&legacy_store, 1, "device-alice", guild_id, channel_id)
+                .await
+                .expect("portable")
+                .expect("portable snapshot");
+        assert_eq!(portable.schema_version, MLS_SNAPSHOT_SCHEMA_VERSION);
+        assert_eq!(portable.key_material_blob, full.key_material_blob);
+    }
+
+    async fn joined_pair(
+        channel_id: ChannelId,
+    ) -> (
+        MlsGroupHandle<MemoryStore>,
+        MlsGroupHandle<MemoryStore>,
+        MemoryStore,
+        MemoryStore,
+    ) {
+        let guild_id = GuildId(1);
+        let alice_store = MemoryStore::default();
+        let bob_store = MemoryStore::default();
+        let mut alice = MlsGroupHandle::new(
+            alice_store.clone(),
+            1,
+            "device-alice",
+            guild_id,
+            channel_id,
+            MlsIdentity::new_with_name(b"alice".to_vec()).expect("alice identity"),
+        )
+        .await
+        .expect("alice handle");
+        let mut bob = MlsGroupHandle::new(
+            bob_store.clone(),
+            2,
+            "device-bob",
+            guild_id,
+            channel_id,
+            MlsIdentity::new_with_name(b"bob".to_vec()).expect("bob identity"),
+        )
+        .await
+        .expect("bob handle");
+        let bob_kp = bob.key_package_bytes().await.expect("bob key package");
+        alice.create_group(channel_id).await.expect("create group");
+        let (_commit, welcome) = alice.add_member(&bob_kp).await.expect("add bob");
+        bob.join_group_from_welcome(&welcome.expect("welcome"))
+            .await
+            .expect("bob joins");
+        (alice, bob, alice_store, bob_store)
+    }
+
+    /// Opens the handle a restarted process would, from the store and the saved identity.
+    async fn reopen(
+        store: &MemoryStore,
+        user_id: i64,
+        device_id: &str,
+        identity: &MlsIdentity,
+    ) -> MlsGroupHandle<MemoryStore> {
+        let identity = MlsIdentity::from_bytes(&identity.to_bytes().expect("identity bytes"))
+            .expect("identity");
+        MlsGroupHandle::new(
+            store.clone(),
+            user_id,
+            device_id,
+            GuildId(1),
+            ChannelId(8),
+            identity,
+        )
+        .await
+        .expect("reopen handle")
+    }
+
+    #[tokio::test]
+    async fn restarted_sender_never_reuses_a_ratchet_generation() {
+        let (mut alice, mut bob, alice_store, _) = joined_pair(ChannelId(8)).await;
+
+        let first = alice
+            .encrypt_application(b"before restart")
+            .await
+            .expect("first message");
+        let identity = alice.identity;
+        let mut alice = reopen(&alice_store, 1, "device-alice", &identity).await;
+        let second = alice
+            .encrypt_application(b"after restart")
+            .await
+            .expect("second message");
+
+        assert_eq!(
+            bob.decrypt_application(&first).await.expect("first"),
+            MlsInbound::Application(b"before restart".to_vec())
+        );
+        assert_eq!(
+            bob.decrypt_application(&second).await.expect("second"),
+            MlsInbound::Application(b"after restart".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn application_sender_is_the_signing_member_and_commits_are_refused() {
+        let (mut alice, mut bob, _, _) = joined_pair(ChannelId(8)).await;
+        let message = alice
+            .encrypt_application(b"from alice")
+            .await
+            .expect("encrypt");
+        assert_eq!(
+            bob.decrypt_application_with_sender(&message)
+                .await
+                .expect("decrypt"),
+            (b"alice".to_vec(), b"from alice".to_vec())
+        );
+
+        let carol = MlsIdentity::new_with_name(b"carol".to_vec()).expect("carol identity");
+        let carol_kp = carol
+            .key_package_bytes(&PersistentOpenMlsProvider::default())
+            .expect("carol key package");
+        let (commit, _welcome) = alice.add_member(&carol_kp).await.expect("add carol");
+        assert!(matches!(
+            bob.decrypt_application_with_sender(&commit).await,
+            Err(MlsError::Malformed(_))
+        ));
+        assert_eq!(bob.member_identities().expect("members").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_flush_rolls_back_to_the_stored_state_and_replay_recovers() {
+        let (mut alice, mut bob, _, bob_store) = joined_pair(ChannelId(8)).await;
+        let carol = MlsIdentity::new_with_name(b"carol".to_vec()).expect("carol identity");
+        let carol_kp = carol
+            .key_package_bytes(&PersistentOpenMlsProvider::default())
+            .expect("carol key package");
+        let (commit, _welcome) = alice.add_member(&carol_kp).await.expect("add carol");
+        let message = alice
+            .encrypt_application(b"after carol")
+            .await
+            .expect("encrypt");
+
+        bob_store.fail_group_writes.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            bob.decrypt_application(&commit).await,
+            Err(MlsError::Storage(_))
+        ));
+        assert_eq!(bob.member_identities().expect("members").len(), 2);
+        bob_store.fail_group_writes.store(false, Ordering::SeqCst);
+
+        // The rolled-back handle and a handle reopened after a crash both replay the commit.
+        let mut restarted = reopen(&bob_store, 2, "device-bob", &bob.identity).await;
+        for handle in [&mut bob, &mut restarted] {
+            assert_eq!(
+                handle
+                    .decrypt_application(&commit)
+                    .await
+                    .expect("replay commit"),
+                MlsInbound::Commit
+            );
+            assert_eq!(handle.member_identities().expect("members").len(), 3);
+        }
+        assert_eq!(
+            bob.decrypt_application(&message).await.expect("decrypt"),
+            MlsInbound::Application(b"after carol".to_vec())
+        );
     }
 }
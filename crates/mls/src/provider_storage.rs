@@ -0,0 +1,810 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+use openmls_traits::storage::{traits, StorageProvider, CURRENT_VERSION};
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+/// One OpenMLS provider value under its storage key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderEntry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+/// Provider entries written or deleted since a group was last persisted.
+#[derive(Debug, Clone, Default)]
+pub struct ProviderChanges {
+    /// Drop every stored entry of the group before applying `upserts`.
+    pub replace_all: bool,
+    pub upserts: Vec<ProviderEntry>,
+    pub deletes: Vec<Vec<u8>>,
+}
+
+impl ProviderChanges {
+    pub fn is_empty(&self) -> bool {
+        !self.replace_all && self.upserts.is_empty() && self.deletes.is_empty()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ProviderStorageError {
+    #[error("failed to encode or decode provider entry: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    values: HashMap<Vec<u8>, Vec<u8>>,
+    dirty: HashSet<Vec<u8>>,
+}
+
+/// OpenMLS storage that tracks which entries changed since the last persist, so a group
+/// commit only rewrites the rows it touched instead of the whole provider state.
+///
+/// OpenMLS writes here synchronously and the changes reach SQLite afterwards, in one
+/// transaction per operation. `MlsGroupHandle` flushes before an operation returns any
+/// output (a commit, Welcome, ciphertext or plaintext), and rolls this state back to the
+/// stored one if the flush fails. A crash inside that window therefore loses only the
+/// operation in flight: the store still holds the previous consistent state, nothing derived
+/// from the lost state has left the process, and replaying the same inbound message or
+/// sending again recovers.
+///
+/// Keys and values use the same layout as `openmls_memory_storage`, which keeps the
+/// schema v2 snapshots loadable entry by entry.
+#[derive(Debug, Default)]
+pub struct IncrementalStorage {
+    entries: RwLock<Entries>,
+}
+
+impl IncrementalStorage {
+    /// Replaces all entries with persisted ones; nothing is dirty afterwards.
+    pub(crate) fn load(&self, entries: impl IntoIterator<Item = ProviderEntry>) {
+        let mut state = self.entries.write().unwrap();
+        state.values = entries
+            .into_iter()
+            .map(|entry| (entry.key, entry.value))
+            .collect();
+        state.dirty.clear();
+    }
+
+    /// Every entry, sorted by key.
+    pub(crate) fn entries(&self) -> Vec<ProviderEntry> {
+        let state = self.entries.read().unwrap();
+        let mut entries: Vec<_> = state
+            .values
+            .iter()
+            .map(|(key, value)| ProviderEntry {
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        entries
+    }
+
+    /// Entries changed since the last [`Self::mark_persisted`], sorted by key.
+    pub(crate) fn pending_changes(&self) -> ProviderChanges {
+        let state = self.entries.read().unwrap();
+        let mut dirty: Vec<_> = state.dirty.iter().cloned().collect();
+        dirty.sort();
+
+        let mut changes = ProviderChanges::default();
+        for key in dirty {
+            match state.values.get(&key) {
+                Some(value) => changes.upserts.push(ProviderEntry {
+                    key,
+                    value: value.clone(),
+                }),
+                None => changes.deletes.push(key),
+            }
+        }
+        changes
+    }
+
+    pub(crate) fn mark_persisted(&self, changes: &ProviderChanges) {
+        let mut state = self.entries.write().unwrap();
+        if changes.replace_all {
+            state.dirty.clear();
+            return;
+        }
+        for entry in &changes.upserts {
+            state.dirty.remove(&entry.key);
+        }
+        for key in &changes.deletes {
+            state.dirty.remove(key);
+        }
+    }
+
+    fn write(&self, label: &[u8], key: &[u8], value: Vec<u8>) -> Result<(), ProviderStorageError> {
+        let storage_key = storage_key(label, key);
+        let mut state = self.entries.write().unwrap();
+        state.dirty.insert(storage_key.clone());
+        state.values.insert(storage_key, value);
+        Ok(())
+    }
+
+    fn read<V: DeserializeOwned>(
+        &self,
+        label: &[u8],
+        key: &[u8],
+    ) -> Result<Option<V>, ProviderStorageError> {
+        let state = self.entries.read().unwrap();
+        state
+            .values
+            .get(&storage_key(label, key))
+            .map(|value| serde_json::from_slice(value))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    fn delete(&self, label: &[u8], key: &[u8]) -> Result<(), ProviderStorageError> {
+        let storage_key = storage_key(label, key);
+        let mut state = self.entries.write().unwrap();
+        if state.values.remove(&storage_key).is_some() {
+            state.dirty.insert(storage_key);
+        }
+        Ok(())
+    }
+
+    fn read_list<V: DeserializeOwned>(
+        &self,
+        label: &[u8],
+        key: &[u8],
+    ) -> Result<Vec<V>, ProviderStorageError> {
+        let Some(items) = self.read::<Vec<Vec<u8>>>(label, key)? else {
+            return Ok(Vec::new());
+        };
+        items
+            .iter()
+            .map(|item| serde_json::from_slice(item))
+            .collect::<Result<_, _>>()
+            .map_err(Into::into)
+    }
+
+    fn update_list(
+        &self,
+        label: &[u8],
+        key: &[u8],
+        update: impl FnOnce(&mut Vec<Vec<u8>>),
+    ) -> Result<(), ProviderStorageError> {
+        let mut items: Vec<Vec<u8>> = self.read(label, key)?.unwrap_or_default();
+        update(&mut items);
+        self.write(label, key, serde_json::to_vec(&items)?)
+    }
+}
+
+const KEY_PACKAGE_LABEL: &[u8] = b"KeyPackage";
+const PSK_LABEL: &[u8] = b"Psk";
+const ENCRYPTION_KEY_PAIR_LABEL: &[u8] = b"EncryptionKeyPair";
+const SIGNATURE_KEY_PAIR_LABEL: &[u8] = b"SignatureKeyPair";
+const EPOCH_KEY_PAIRS_LABEL: &[u8] = b"EpochKeyPairs";
+const TREE_LABEL: &[u8] = b"Tree";
+const GROUP_CONTEXT_LABEL: &[u8] = b"GroupContext";
+const INTERIM_TRANSCRIPT_HASH_LABEL: &[u8] = b"InterimTranscriptHash";
+const CONFIRMATION_TAG_LABEL: &[u8] = b"ConfirmationTag";
+const JOIN_CONFIG_LABEL: &[u8] = b"MlsGroupJoinConfig";
+const OWN_LEAF_NODES_LABEL: &[u8] = b"OwnLeafNodes";
+const GROUP_STATE_LABEL: &[u8] = b"GroupState";
+const QUEUED_PROPOSAL_LABEL: &[u8] = b"QueuedProposal";
+const PROPOSAL_QUEUE_REFS_LABEL: &[u8] = b"ProposalQueueRefs";
+const OWN_LEAF_NODE_INDEX_LABEL: &[u8] = b"OwnLeafNodeIndex";
+const EPOCH_SECRETS_LABEL: &[u8] = b"EpochSecrets";
+const RESUMPTION_PSK_STORE_LABEL: &[u8] = b"ResumptionPsk";
+const MESSAGE_SECRETS_LABEL: &[u8] = b"MessageSecrets";
+
+fn storage_key(label: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(label.len() + key.len() + 2);
+    out.extend_from_slice(label);
+    out.extend_from_slice(key);
+    out.extend_from_slice(&CURRENT_VERSION.to_be_bytes());
+    out
+}
+
+fn json(value: &impl Serialize) -> Result<Vec<u8>, ProviderStorageError> {
+    Ok(serde_json::to_vec(value)?)
+}
+
+fn epoch_key_pairs_id(
+    group_id: &impl traits::GroupId<CURRENT_VERSION>,
+    epoch: &impl traits::EpochKey<CURRENT_VERSION>,
+    leaf_index: u32,
+) -> Result<Vec<u8>, ProviderStorageError> {
+    let mut key = json(group_id)?;
+    key.extend_from_slice(&json(epoch)?);
+    key.extend_from_slice(&json(&leaf_index)?);
+    Ok(key)
+}
+
+impl StorageProvider<CURRENT_VERSION> for IncrementalStorage {
+    type Error = ProviderStorageError;
+
+    fn write_mls_join_config<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        MlsGroupJoinConfig: traits::MlsGroupJoinConfig<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        config: &MlsGroupJoinConfig,
+    ) -> Result<(), Self::Error> {
+        self.write(JOIN_CONFIG_LABEL, &json(group_id)?, json(config)?)
+    }
+
+    fn append_own_leaf_node<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        LeafNode: traits::LeafNode<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        leaf_node: &LeafNode,
+    ) -> Result<(), Self::Error> {
+        let item = json(leaf_node)?;
+        self.update_list(OWN_LEAF_NODES_LABEL, &json(group_id)?, |items| {
+            items.push(item)
+        })
+    }
+
+    fn queue_proposal<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        ProposalRef: traits::ProposalRef<CURRENT_VERSION>,
+        QueuedProposal: traits::QueuedProposal<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        proposal_ref: &ProposalRef,
+        proposal: &QueuedProposal,
+    ) -> Result<(), Self::Error> {
+        self.write(
+            QUEUED_PROPOSAL_LABEL,
+            &json(&(group_id, proposal_ref))?,
+            json(proposal)?,
+        )?;
+        let item = json(proposal_ref)?;
+        self.update_list(PROPOSAL_QUEUE_REFS_LABEL, &json(group_id)?, |items| {
+            items.push(item)
+        })
+    }
+
+    fn write_tree<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        TreeSync: traits::TreeSync<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        tree: &TreeSync,
+    ) -> Result<(), Self::Error> {
+        self.write(TREE_LABEL, &json(group_id)?, json(tree)?)
+    }
+
+    fn write_interim_transcript_hash<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        InterimTranscriptHash: traits::InterimTranscriptHash<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        interim_transcript_hash: &InterimTranscriptHash,
+    ) -> Result<(), Self::Error> {
+        self.write(
+            INTERIM_TRANSCRIPT_HASH_LABEL,
+            &json(group_id)?,
+            json(interim_transcript_hash)?,
+        )
+    }
+
+    fn write_context<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        GroupContext: traits::GroupContext<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        group_context: &GroupContext,
+    ) -> Result<(), Self::Error> {
+        self.write(GROUP_CONTEXT_LABEL, &json(group_id)?, json(group_context)?)
+    }
+
+    fn write_confirmation_tag<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        ConfirmationTag: traits::ConfirmationTag<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        confirmation_tag: &ConfirmationTag,
+    ) -> Result<(), Self::Error> {
+        self.write(
+            CONFIRMATION_TAG_LABEL,
+            &json(group_id)?,
+            json(confirmation_tag)?,
+        )
+    }
+
+    fn write_group_state<
+        GroupState: traits::GroupState<CURRENT_VERSION>,
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        group_state: &GroupState,
+    ) -> Result<(), Self::Error> {
+        self.write(GROUP_STATE_LABEL, &json(group_id)?, json(group_state)?)
+    }
+
+    fn write_message_secrets<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        MessageSecrets: traits::MessageSecrets<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        message_secrets: &MessageSecrets,
+    ) -> Result<(), Self::Error> {
+        self.write(
+            MESSAGE_SECRETS_LABEL,
+            &json(group_id)?,
+            json(message_secrets)?,
+        )
+    }
+
+    fn write_resumption_psk_store<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        ResumptionPskStore: traits::ResumptionPskStore<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        resumption_psk_store: &ResumptionPskStore,
+    ) -> Result<(), Self::Error> {
+        self.write(
+            RESUMPTION_PSK_STORE_LABEL,
+            &json(group_id)?,
+            json(resumption_psk_store)?,
+        )
+    }
+
+    fn write_own_leaf_index<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        LeafNodeIndex: traits::LeafNodeIndex<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        own_leaf_index: &LeafNodeIndex,
+    ) -> Result<(), Self::Error> {
+        self.write(
+            OWN_LEAF_NODE_INDEX_LABEL,
+            &json(group_id)?,
+            json(own_leaf_index)?,
+        )
+    }
+
+    fn write_group_epoch_secrets<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        GroupEpochSecrets: traits::GroupEpochSecrets<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        group_epoch_secrets: &GroupEpochSecrets,
+    ) -> Result<(), Self::Error> {
+        self.write(
+            EPOCH_SECRETS_LABEL,
+            &json(group_id)?,
+            json(group_epoch_secrets)?,
+        )
+    }
+
+    fn write_signature_key_pair<
+        SignaturePublicKey: traits::SignaturePublicKey<CURRENT_VERSION>,
+        SignatureKeyPair: traits::SignatureKeyPair<CURRENT_VERSION>,
+    >(
+        &self,
+        public_key: &SignaturePublicKey,
+        signature_key_pair: &SignatureKeyPair,
+    ) -> Result<(), Self::Error> {
+        self.write(
+            SIGNATURE_KEY_PAIR_LABEL,
+            &json(public_key)?,
+            json(signature_key_pair)?,
+        )
+    }
+
+    fn write_encryption_key_pair<
+        EncryptionKey: traits::EncryptionKey<CURRENT_VERSION>,
+        HpkeKeyPair: traits::HpkeKeyPair<CURRENT_VERSION>,
+    >(
+        &self,
+        public_key: &EncryptionKey,
+        key_pair: &HpkeKeyPair,
+    ) -> Result<(), Self::Error> {
+        self.write(
+            ENCRYPTION_KEY_PAIR_LABEL,
+            &json(public_key)?,
+            json(key_pair)?,
+        )
+    }
+
+    fn write_encryption_epoch_key_pairs<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        EpochKey: traits::EpochKey<CURRENT_VERSION>,
+        HpkeKeyPair: traits::HpkeKeyPair<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        epoch: &EpochKey,
+        leaf_index: u32,
+        key_pairs: &[HpkeKeyPair],
+    ) -> Result<(), Self::Error> {
+        self.write(
+            EPOCH_KEY_PAIRS_LABEL,
+            &epoch_key_pairs_id(group_id, epoch, leaf_index)?,
+            json(&key_pairs)?,
+        )
+    }
+
+    fn write_key_package<
+        HashReference: traits::HashReference<CURRENT_VERSION>,
+        KeyPackage: traits::KeyPackage<CURRENT_VERSION>,
+    >(
+        &self,
+        hash_ref: &HashReference,
+        key_package: &KeyPackage,
+    ) -> Result<(), Self::Error> {
+        self.write(KEY_PACKAGE_LABEL, &json(hash_ref)?, json(key_package)?)
+    }
+
+    fn write_psk<
+        PskId: traits::PskId<CURRENT_VERSION>,
+        PskBundle: traits::PskBundle<CURRENT_VERSION>,
+    >(
+        &self,
+        psk_id: &PskId,
+        psk: &PskBundle,
+    ) -> Result<(), Self::Error> {
+        self.write(PSK_LABEL, &json(psk_id)?, json(psk)?)
+    }
+
+    fn mls_group_join_config<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        MlsGroupJoinConfig: traits::MlsGroupJoinConfig<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Option<MlsGroupJoinConfig>, Self::Error> {
+        self.read(JOIN_CONFIG_LABEL, &json(group_id)?)
+    }
+
+    fn own_leaf_nodes<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        LeafNode: traits::LeafNode<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Vec<LeafNode>, Self::Error> {
+        self.read_list(OWN_LEAF_NODES_LABEL, &json(group_id)?)
+    }
+
+    fn queued_proposal_refs<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        ProposalRef: traits::ProposalRef<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Vec<ProposalRef>, Self::Error> {
+        self.read_list(PROPOSAL_QUEUE_REFS_LABEL, &json(group_id)?)
+    }
+
+    fn queued_proposals<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        ProposalRef: traits::ProposalRef<CURRENT_VERSION>,
+        QueuedProposal: traits::QueuedProposal<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Vec<(ProposalRef, QueuedProposal)>, Self::Error> {
+        let refs: Vec<ProposalRef> = self.read_list(PROPOSAL_QUEUE_REFS_LABEL, &json(group_id)?)?;
+        let mut proposals = Vec::with_capacity(refs.len());
+        for proposal_ref in refs {
+            if let Some(proposal) =
+                self.read(QUEUED_PROPOSAL_LABEL, &json(&(group_id, &proposal_ref))?)?
+            {
+                proposals.push((proposal_ref, proposal));
+            }
+        }
+        Ok(proposals)
+    }
+
+    fn tree<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        TreeSync: traits::TreeSync<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Option<TreeSync>, Self::Error> {
+        self.read(TREE_LABEL, &json(group_id)?)
+    }
+
+    fn group_context<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        GroupContext: traits::GroupContext<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Option<GroupContext>, Self::Error> {
+        self.read(GROUP_CONTEXT_LABEL, &json(group_id)?)
+    }
+
+    fn interim_transcript_hash<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        InterimTranscriptHash: traits::InterimTranscriptHash<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Option<InterimTranscriptHash>, Self::Error> {
+        self.read(INTERIM_TRANSCRIPT_HASH_LABEL, &json(group_id)?)
+    }
+
+    fn confirmation_tag<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        ConfirmationTag: traits::ConfirmationTag<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Option<ConfirmationTag>, Self::Error> {
+        self.read(CONFIRMATION_TAG_LABEL, &json(group_id)?)
+    }
+
+    fn group_state<
+        GroupState: traits::GroupState<CURRENT_VERSION>,
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Option<GroupState>, Self::Error> {
+        self.read(GROUP_STATE_LABEL, &json(group_id)?)
+    }
+
+    fn message_secrets<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        MessageSecrets: traits::MessageSecrets<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Option<MessageSecrets>, Self::Error> {
+        self.read(MESSAGE_SECRETS_LABEL, &json(group_id)?)
+    }
+
+    fn resumption_psk_store<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        ResumptionPskStore: traits::ResumptionPskStore<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Option<ResumptionPskStore>, Self::Error> {
+        self.read(RESUMPTION_PSK_STORE_LABEL, &json(group_id)?)
+    }
+
+    fn own_leaf_index<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        LeafNodeIndex: traits::LeafNodeIndex<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Option<LeafNodeIndex>, Self::Error> {
+        self.read(OWN_LEAF_NODE_INDEX_LABEL, &json(group_id)?)
+    }
+
+    fn group_epoch_secrets<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        GroupEpochSecrets: traits::GroupEpochSecrets<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Option<GroupEpochSecrets>, Self::Error> {
+        self.read(EPOCH_SECRETS_LABEL, &json(group_id)?)
+    }
+
+    fn signature_key_pair<
+        SignaturePublicKey: traits::SignaturePublicKey<CURRENT_VERSION>,
+        SignatureKeyPair: traits::SignatureKeyPair<CURRENT_VERSION>,
+    >(
+        &self,
+        public_key: &SignaturePublicKey,
+    ) -> Result<Option<SignatureKeyPair>, Self::Error> {
+        self.read(SIGNATURE_KEY_PAIR_LABEL, &json(public_key)?)
+    }
+
+    fn encryption_key_pair<
+        HpkeKeyPair: traits::HpkeKeyPair<CURRENT_VERSION>,
+        EncryptionKey: traits::EncryptionKey<CURRENT_VERSION>,
+    >(
+        &self,
+        public_key: &EncryptionKey,
+    ) -> Result<Option<HpkeKeyPair>, Self::Error> {
+        self.read(ENCRYPTION_KEY_PAIR_LABEL, &json(public_key)?)
+    }
+
+    fn encryption_epoch_key_pairs<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        EpochKey: traits::EpochKey<CURRENT_VERSION>,
+        HpkeKeyPair: traits::HpkeKeyPair<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        epoch: &EpochKey,
+        leaf_index: u32,
+    ) -> Result<Vec<HpkeKeyPair>, Self::Error> {
+        Ok(self
+            .read(
+                EPOCH_KEY_PAIRS_LABEL,
+                &epoch_key_pairs_id(group_id, epoch, leaf_index)?,
+            )?
+            .unwrap_or_default())
+    }
+
+    fn key_package<
+        KeyPackageRef: traits::HashReference<CURRENT_VERSION>,
+        KeyPackage: traits::KeyPackage<CURRENT_VERSION>,
+    >(
+        &self,
+        hash_ref: &KeyPackageRef,
+    ) -> Result<Option<KeyPackage>, Self::Error> {
+        self.read(KEY_PACKAGE_LABEL, &json(hash_ref)?)
+    }
+
+    fn psk<PskBundle: traits::PskBundle<CURRENT_VERSION>, PskId: traits::PskId<CURRENT_VERSION>>(
+        &self,
+        psk_id: &PskId,
+    ) -> Result<Option<PskBundle>, Self::Error> {
+        self.read(PSK_LABEL, &json(psk_id)?)
+    }
+
+    fn remove_proposal<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        ProposalRef: traits::ProposalRef<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        proposal_ref: &ProposalRef,
+    ) -> Result<(), Self::Error> {
+        let item = json(proposal_ref)?;
+        self.update_list(PROPOSAL_QUEUE_REFS_LABEL, &json(group_id)?, |items| {
+            if let Some(pos) = items.iter().position(|stored| *stored == item) {
+                items.remove(pos);
+            }
+        })?;
+        self.delete(QUEUED_PROPOSAL_LABEL, &json(&(group_id, proposal_ref))?)
+    }
+
+    fn delete_own_leaf_nodes<GroupId: traits::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        self.delete(OWN_LEAF_NODES_LABEL, &json(group_id)?)
+    }
+
+    fn delete_group_config<GroupId: traits::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        self.delete(JOIN_CONFIG_LABEL, &json(group_id)?)
+    }
+
+    fn delete_tree<GroupId: traits::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        self.delete(TREE_LABEL, &json(group_id)?)
+    }
+
+    fn delete_confirmation_tag<GroupId: traits::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        self.delete(CONFIRMATION_TAG_LABEL, &json(group_id)?)
+    }
+
+    fn delete_group_state<GroupId: traits::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        self.delete(GROUP_STATE_LABEL, &json(group_id)?)
+    }
+
+    fn delete_context<GroupId: traits::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        self.delete(GROUP_CONTEXT_LABEL, &json(group_id)?)
+    }
+
+    fn delete_interim_transcript_hash<GroupId: traits::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        self.delete(INTERIM_TRANSCRIPT_HASH_LABEL, &json(group_id)?)
+    }
+
+    fn delete_message_secrets<GroupId: traits::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        self.delete(MESSAGE_SECRETS_LABEL, &json(group_id)?)
+    }
+
+    fn delete_all_resumption_psk_secrets<GroupId: traits::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        self.delete(RESUMPTION_PSK_STORE_LABEL, &json(group_id)?)
+    }
+
+    fn delete_own_leaf_index<GroupId: traits::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        self.delete(OWN_LEAF_NODE_INDEX_LABEL, &json(group_id)?)
+    }
+
+    fn delete_group_epoch_secrets<GroupId: traits::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        self.delete(EPOCH_SECRETS_LABEL, &json(group_id)?)
+    }
+
+    fn clear_proposal_queue<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        ProposalRef: traits::ProposalRef<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<(), Self::Error> {
+        let refs: Vec<ProposalRef> = self.read_list(PROPOSAL_QUEUE_REFS_LABEL, &json(group_id)?)?;
+        for proposal_ref in refs {
+            self.delete(QUEUED_PROPOSAL_LABEL, &json(&(group_id, proposal_ref))?)?;
+        }
+        self.delete(PROPOSAL_QUEUE_REFS_LABEL, &json(group_id)?)
+    }
+
+    fn delete_signature_key_pair<
+        SignaturePublicKey: traits::SignaturePublicKey<CURRENT_VERSION>,
+    >(
+        &self,
+        public_key: &SignaturePublicKey,
+    ) -> Result<(), Self::Error> {
+        self.delete(SIGNATURE_KEY_PAIR_LABEL, &json(public_key)?)
+    }
+
+    fn delete_encryption_key_pair<EncryptionKey: traits::EncryptionKey<CURRENT_VERSION>>(
+        &self,
+        public_key: &EncryptionKey,
+    ) -> Result<(), Self::Error> {
+        self.delete(ENCRYPTION_KEY_PAIR_LABEL, &json(public_key)?)
+    }
+
+    fn delete_encryption_epoch_key_pairs<
+        GroupId: traits::GroupId<CURRENT_VERSION>,
+        EpochKey: traits::EpochKey<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        epoch: &EpochKey,
+        leaf_index: u32,
+    ) -> Result<(), Self::Error> {
+        self.delete(
+            EPOCH_KEY_PAIRS_LABEL,
+            &epoch_key_pairs_id(group_id, epoch, leaf_index)?,
+        )
+    }
+
+    fn delete_key_package<KeyPackageRef: traits::HashReference<CURRENT_VERSION>>(
+        &self,
+        hash_ref: &KeyPackageRef,
+    ) -> Result<(), Self::Error> {
+        self.delete(KEY_PACKAGE_LABEL, &json(hash_ref)?)
+    }
+
+    fn delete_psk<PskKey: traits::PskId<CURRENT_VERSION>>(
+        &self,
+        psk_id: &PskKey,
+    ) -> Result<(), Self::Error> {
+        self.delete(PSK_LABEL, &json(psk_id)?)
+    }
+}
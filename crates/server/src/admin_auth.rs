@@ -0,0 +1,69 @@
+//! Bearer-token check for the `/admin` routes. The API is off unless `admin_token` is set,
+//! and answers `404` while it is.
+
+use std::{fmt, sync::Arc};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use sha2::{Digest, Sha256};
+use shared::error::{ApiError, ErrorCode};
+
+use crate::app_state::AppState;
+
+/// The configured token, kept only as its SHA-256. Presented tokens are hashed before
+/// comparing, so the comparison time says nothing about the token itself.
+#[derive(Clone)]
+pub struct AdminToken([u8; 32]);
+
+impl AdminToken {
+    pub fn new(token: &str) -> Self {
+        Self(Sha256::digest(token.trim().as_bytes()).into())
+    }
+
+    fn matches(&self, presented: &str) -> bool {
+        Sha256::digest(presented.trim().as_bytes()).as_slice() == self.0
+    }
+}
+
+impl fmt::Debug for AdminToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AdminToken(..)")
+    }
+}
+
+/// Middleware for the `/admin` routes: `Authorization: Bearer <admin_token>`.
+pub async fn require_token(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = &state.admin_token else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(ErrorCode::NotFound, "admin API is disabled")),
+        )
+            .into_response();
+    };
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(presented) if token.matches(presented) => next.run(request).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            Json(ApiError::new(
+                ErrorCode::Unauthorized,
+                "missing or invalid admin token",
+            )),
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,172 @@
+//! Server-wide administration behind the admin token. Nothing here checks guild
+//! membership: the token holder operates the server, not a guild.
+
+use shared::{
+    domain::{DeviceId, FileId, GuildId, LinkedDeviceSummary, MessageId, UserId},
+    error::{ApiError, ErrorCode},
+    protocol::{
+        AdminGuildSummary, AdminUserSummary, MlsQueueSummary, ServerEvent, ServerStorageStats,
+        SuspendUserRequest,
+    },
+};
+
+use super::{internal, ApiContext};
+
+const DEFAULT_USER_PAGE: u32 = 100;
+const MAX_USER_PAGE: u32 = 1000;
+const MAX_SUSPENSION_REASON_CHARS: usize = 500;
+
+/// Lists accounts oldest first, optionally only those whose username contains `search`.
+pub async fn list_users(
+    ctx: &ApiContext,
+    search: Option<&str>,
+    limit: Option<u32>,
+) -> Result<Vec<AdminUserSummary>, ApiError> {
+    let search = search.map(str::trim).filter(|search| !search.is_empty());
+    let limit = limit.unwrap_or(DEFAULT_USER_PAGE).clamp(1, MAX_USER_PAGE);
+    ctx.storage
+        .list_users(search, limit)
+        .await
+        .map_err(internal)
+}
+
+pub async fn user(ctx: &ApiContext, user_id: UserId) -> Result<AdminUserSummary, ApiError> {
+    ctx.storage
+        .admin_user(user_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(user_not_found)
+}
+
+/// Suspends an account: it can no longer log in, open a WebSocket or act in any guild.
+/// Its memberships, devices and messages are kept for when it is unsuspended.
+pub async fn suspend_user(
+    ctx: &ApiContext,
+    user_id: UserId,
+    request: SuspendUserRequest,
+) -> Result<AdminUserSummary, ApiError> {
+    let reason = request
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty());
+    if reason.is_some_and(|reason| reason.chars().count() > MAX_SUSPENSION_REASON_CHARS) {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            format!("reason must be at most {MAX_SUSPENSION_REASON_CHARS} characters"),
+        ));
+    }
+    if !ctx
+        .storage
+        .suspend_user(user_id, reason)
+        .await
+        .map_err(internal)?
+    {
+        return Err(user_not_found());
+    }
+    user(ctx, user_id).await
+}
+
+pub async fn unsuspend_user(
+    ctx: &ApiContext,
+    user_id: UserId,
+) -> Result<AdminUserSummary, ApiError> {
+    if !ctx
+        .storage
+        .unsuspend_user(user_id)
+        .await
+        .map_err(internal)?
+    {
+        return Err(user_not_found());
+    }
+    user(ctx, user_id).await
+}
+
+/// Every guild and direct conversation on the server.
+pub async fn list_guilds(ctx: &ApiContext) -> Result<Vec<AdminGuildSummary>, ApiError> {
+    ctx.storage.list_all_guilds().await.map_err(internal)
+}
+
+/// Deletes a guild without the owner's name confirmation, returning the members it had.
+pub async fn delete_guild(ctx: &ApiContext, guild_id: GuildId) -> Result<Vec<UserId>, ApiError> {
+    let members = super::guild_member_ids(ctx, guild_id).await?;
+    if !ctx.storage.delete_guild(guild_id).await.map_err(internal)? {
+        return Err(ApiError::new(ErrorCode::NotFound, "guild not found"));
+    }
+    Ok(members)
+}
+
+/// Deletes a message, and any thread anchored to it, returning the event that tells
+/// clients to drop them.
+pub async fn delete_message(
+    ctx: &ApiContext,
+    message_id: MessageId,
+) -> Result<ServerEvent, ApiError> {
+    let (guild_id, channel_id, message_ids) = ctx
+        .storage
+        .delete_message(message_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "message not found"))?;
+    Ok(ServerEvent::MessagesExpired {
+        guild_id,
+        channel_id,
+        message_ids,
+    })
+}
+
+pub async fn delete_file(ctx: &ApiContext, file_id: &FileId) -> Result<(), ApiError> {
+    if !ctx.storage.delete_file(file_id).await.map_err(internal)? {
+        return Err(ApiError::new(ErrorCode::NotFound, "file not found"));
+    }
+    Ok(())
+}
+
+pub async fn list_devices(
+    ctx: &ApiContext,
+    user_id: UserId,
+) -> Result<Vec<LinkedDeviceSummary>, ApiError> {
+    user(ctx, user_id).await?;
+    ctx.storage
+        .list_devices_for_user(user_id)
+        .await
+        .map_err(internal)
+}
+
+pub async fn revoke_device(
+    ctx: &ApiContext,
+    user_id: UserId,
+    device_id: DeviceId,
+) -> Result<(), ApiError> {
+    if !ctx
+        .storage
+        .revoke_device(user_id, device_id)
+        .await
+        .map_err(internal)?
+    {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            "active device not found",
+        ));
+    }
+    Ok(())
+}
+
+/// Server-wide totals, then the guilds that have Welcomes or key packages queued.
+pub async fn mls_queue(ctx: &ApiContext) -> Result<MlsQueueSummary, ApiError> {
+    let depths = ctx.storage.mls_queue_depths().await.map_err(internal)?;
+    let guilds = ctx.storage.mls_queue_by_guild().await.map_err(internal)?;
+    Ok(MlsQueueSummary {
+        pending_welcomes: depths.pending_welcomes,
+        key_packages: depths.key_packages,
+        guilds,
+    })
+}
+
+pub async fn storage_stats(ctx: &ApiContext) -> Result<ServerStorageStats, ApiError> {
+    ctx.storage.storage_stats().await.map_err(internal)
+}
+
+fn user_not_found() -> ApiError {
+    ApiError::new(ErrorCode::NotFound, "user not found")
+}
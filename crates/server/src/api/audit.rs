@@ -0,0 +1,46 @@
+use shared::{
+    domain::{GuildId, Permissions, UserId},
+    error::ApiError,
+    protocol::{AuditLogEntry, AuditLogFilter},
+};
+use storage::NewAuditEntry;
+
+use super::{internal, permissions, ApiContext};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 100;
+
+/// Appends an entry to the guild's audit log. Called after the change it describes has
+/// been stored, so a failure here surfaces as an internal error on an applied change.
+pub async fn record(ctx: &ApiContext, entry: NewAuditEntry) -> Result<(), ApiError> {
+    ctx.storage
+        .append_audit_entry(&entry)
+        .await
+        .map_err(internal)?;
+    Ok(())
+}
+
+/// Lists the guild's audit log newest first. Requires `VIEW_AUDIT_LOG`.
+pub async fn list_audit_log(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    filter: AuditLogFilter,
+) -> Result<Vec<AuditLogEntry>, ApiError> {
+    permissions::resolve(ctx, user_id, guild_id, None)
+        .await?
+        .require(Permissions::VIEW_AUDIT_LOG, "view the audit log")?;
+    let filter = AuditLogFilter {
+        limit: Some(
+            filter
+                .limit
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .clamp(1, MAX_PAGE_SIZE),
+        ),
+        ..filter
+    };
+    ctx.storage
+        .list_audit_entries(guild_id, &filter)
+        .await
+        .map_err(internal)
+}
@@ -0,0 +1,102 @@
+use shared::{
+    domain::UserId,
+    error::{ApiError, ErrorCode},
+    protocol::{CreateDirectChannelRequest, DirectChannelSummary, DirectParticipant},
+};
+use storage::StoredChannel;
+
+use super::{channel_summary_from, internal, load_channel, ApiContext};
+
+/// Largest group conversation, the creator included.
+const MAX_PARTICIPANTS: usize = 10;
+
+/// Direct conversations the caller takes part in, most recently opened first.
+pub async fn list_direct_channels(
+    ctx: &ApiContext,
+    user_id: UserId,
+) -> Result<Vec<DirectChannelSummary>, ApiError> {
+    let channels = ctx
+        .storage
+        .list_direct_channels_for_user(user_id)
+        .await
+        .map_err(internal)?;
+    let mut summaries = Vec::with_capacity(channels.len());
+    for channel in channels {
+        summaries.push(direct_channel_summary(ctx, channel).await?);
+    }
+    Ok(summaries)
+}
+
+/// Opens a conversation with the named users, or returns the existing one-to-one
+/// conversation with a single named user.
+pub async fn create_direct_channel(
+    ctx: &ApiContext,
+    user_id: UserId,
+    request: CreateDirectChannelRequest,
+) -> Result<DirectChannelSummary, ApiError> {
+    let mut participant_ids = Vec::with_capacity(request.usernames.len());
+    for username in &request.usernames {
+        let participant_id = ctx
+            .storage
+            .user_id_for_username(username.trim())
+            .await
+            .map_err(internal)?
+            .ok_or_else(|| {
+                ApiError::new(ErrorCode::NotFound, format!("user '{username}' not found"))
+            })?;
+        if participant_id != user_id && !participant_ids.contains(&participant_id) {
+            participant_ids.push(participant_id);
+        }
+    }
+    if participant_ids.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "name at least one other user",
+        ));
+    }
+    if participant_ids.len() + 1 > MAX_PARTICIPANTS {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            format!("conversations are limited to {MAX_PARTICIPANTS} participants"),
+        ));
+    }
+
+    let existing = if participant_ids.len() == 1 {
+        ctx.storage
+            .find_direct_channel(&[user_id, participant_ids[0]])
+            .await
+            .map_err(internal)?
+    } else {
+        None
+    };
+    let channel_id = match existing {
+        Some(channel_id) => channel_id,
+        None => ctx
+            .storage
+            .create_direct_channel(user_id, &participant_ids)
+            .await
+            .map_err(internal)?,
+    };
+    direct_channel_summary(ctx, load_channel(ctx, channel_id).await?).await
+}
+
+async fn direct_channel_summary(
+    ctx: &ApiContext,
+    channel: StoredChannel,
+) -> Result<DirectChannelSummary, ApiError> {
+    let participants = ctx
+        .storage
+        .list_members_for_guild(channel.guild_id)
+        .await
+        .map_err(internal)?
+        .into_iter()
+        .map(|member| DirectParticipant {
+            user_id: member.user_id,
+            username: member.username,
+        })
+        .collect();
+    Ok(DirectChannelSummary {
+        channel: channel_summary_from(channel, &[]),
+        participants,
+    })
+}
@@ -1,20 +1,72 @@
+use crate::key_transparency::KeyTransparencyLog;
 use crate::livekit::{mint_token, room_name_for_voice_channel, LiveKitConfig};
-use base64::{engine::general_purpose::STANDARD, Engine as _};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine as _,
+};
 use chrono::{DateTime, Utc};
+use rand_core::{OsRng, RngCore};
 use serde::{Deserialize, Serialize};
 use shared::{
-    domain::{ChannelId, ChannelKind, DeviceId, GuildId, Role, UserId},
+    domain::{
+        AuditAction, ChannelId, ChannelKind, DeviceId, GuildId, ModerationAction, OverwriteTarget,
+        PermissionOverwrite, Permissions, Role, RoleId, ThreadId, UserId,
+    },
     error::{ApiError, ErrorCode},
     protocol::{
-        AttachmentPayload, ChannelSummary, GuildSummary, MemberSummary, MessagePayload, ServerEvent,
+        AttachmentPayload, ChannelSummary, ConsistencyProofResponse, CreateChannelRequest,
+        CreateGuildRequest, CreateRoleRequest, DeleteGuildRequest, GuildSummary,
+        HistoryBundleResponse, InclusionProofResponse, InviteSummary, KeyTransparencyProof,
+        MemberSummary, MessagePayload, ModerationRequest, ReorderChannelsRequest,
+        ReorderGuildsRequest, RoleSummary, ServerEvent, SignedTreeHead, TransferOwnershipRequest,
+        UpdateChannelRequest, UpdateGuildRequest, UpdateRoleRequest,
     },
+    transparency,
 };
-use storage::{Storage, StoredAttachment};
+use std::collections::HashMap;
+use storage::{
+    NewAuditEntry, Storage, StoredAttachment, StoredChannel, StoredGuildRole, StoredMember,
+    StoredMessage,
+};
+
+pub mod admin;
+pub mod audit;
+pub mod direct;
+pub mod permissions;
+pub mod retention;
+pub mod threads;
+pub mod uploads;
+
+use permissions::ResolvedMember;
 
 #[derive(Clone)]
 pub struct ApiContext {
     pub storage: Storage,
     pub livekit: LiveKitConfig,
+    pub transparency: KeyTransparencyLog,
+    pub limits: Limits,
+}
+
+/// Request size limits from the server settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Body size of a single-request upload.
+    pub max_attachment_bytes: usize,
+    /// Size a resumable upload may declare.
+    pub max_upload_bytes: u64,
+    pub max_thumbnail_bytes: u64,
+    pub max_mls_backup_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_attachment_bytes: 8 * 1024 * 1024,
+            max_upload_bytes: uploads::MAX_UPLOAD_BYTES,
+            max_thumbnail_bytes: uploads::MAX_THUMBNAIL_BYTES,
+            max_mls_backup_bytes: 16 * 1024 * 1024,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -29,147 +81,1475 @@ pub struct MlsKeyPackageQuery {
     pub target_device_id: Option<i64>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct MlsWelcomeQuery {
-    pub user_id: i64,
-    pub guild_id: i64,
-    pub channel_id: i64,
-    #[serde(default)]
-    pub device_id: Option<i64>,
-    #[serde(default)]
-    pub target_user_id: Option<i64>,
-    #[serde(default)]
-    pub target_device_id: Option<i64>,
-}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MlsWelcomeQuery {
+    pub user_id: i64,
+    pub guild_id: i64,
+    pub channel_id: i64,
+    #[serde(default)]
+    pub device_id: Option<i64>,
+    #[serde(default)]
+    pub target_user_id: Option<i64>,
+    #[serde(default)]
+    pub target_device_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UploadKeyPackageResponse {
+    pub key_package_id: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct KeyPackageResponse {
+    pub key_package_id: i64,
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub device_id: Option<DeviceId>,
+    pub key_package_b64: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transparency: Option<KeyTransparencyProof>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MlsWelcomeResponse {
+    pub user_id: i64,
+    pub guild_id: i64,
+    pub channel_id: i64,
+    pub target_device_id: Option<DeviceId>,
+    pub welcome_b64: String,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+pub fn mls_key_packages_route() -> &'static str {
+    "/mls/key_packages"
+}
+
+pub fn mls_welcome_route() -> &'static str {
+    "/mls/welcome"
+}
+
+pub fn mls_bootstrap_request_route() -> &'static str {
+    "/mls/bootstrap/request"
+}
+
+pub fn mls_welcome_recovery_route() -> &'static str {
+    "/mls/welcome/recovery"
+}
+
+pub fn mls_backup_route() -> &'static str {
+    "/mls/backup"
+}
+
+pub fn mls_history_route() -> &'static str {
+    "/mls/history"
+}
+
+pub fn key_transparency_tree_head_route() -> &'static str {
+    "/transparency/tree_head"
+}
+
+pub fn key_transparency_inclusion_route() -> &'static str {
+    "/transparency/inclusion"
+}
+
+pub fn key_transparency_consistency_route() -> &'static str {
+    "/transparency/consistency"
+}
+
+pub async fn key_transparency_tree_head(ctx: &ApiContext) -> Result<SignedTreeHead, ApiError> {
+    let tree_size = ctx
+        .storage
+        .key_transparency_tree_size()
+        .await
+        .map_err(internal)?;
+    let leaves = ctx
+        .storage
+        .list_key_transparency_leaf_hashes(tree_size)
+        .await
+        .map_err(internal)?;
+    ctx.transparency
+        .sign_tree_head(tree_size, &transparency::root_hash(&leaves))
+        .map_err(internal)
+}
+
+pub async fn key_transparency_inclusion_proof(
+    ctx: &ApiContext,
+    leaf_index: u64,
+    tree_size: Option<u64>,
+) -> Result<InclusionProofResponse, ApiError> {
+    let tree_size = resolve_tree_size(ctx, tree_size).await?;
+    if leaf_index >= tree_size {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "leaf index is outside the requested tree",
+        ));
+    }
+    let leaf = ctx
+        .storage
+        .load_key_transparency_leaf(leaf_index)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "log entry not found"))?;
+    let leaves = ctx
+        .storage
+        .list_key_transparency_leaf_hashes(tree_size)
+        .await
+        .map_err(internal)?;
+    let path = transparency::inclusion_path(&leaves, leaf_index as usize)
+        .ok_or_else(|| ApiError::new(ErrorCode::Internal, "log entry missing from tree"))?;
+    Ok(InclusionProofResponse {
+        leaf,
+        tree_size,
+        audit_path_b64: path.iter().map(|node| STANDARD.encode(node)).collect(),
+    })
+}
+
+pub async fn key_transparency_consistency_proof(
+    ctx: &ApiContext,
+    first_size: u64,
+    second_size: Option<u64>,
+) -> Result<ConsistencyProofResponse, ApiError> {
+    let second_size = resolve_tree_size(ctx, second_size).await?;
+    if first_size > second_size {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "first tree size must not exceed second tree size",
+        ));
+    }
+    let leaves = ctx
+        .storage
+        .list_key_transparency_leaf_hashes(second_size)
+        .await
+        .map_err(internal)?;
+    let proof = transparency::consistency_proof(&leaves, first_size as usize)
+        .ok_or_else(|| ApiError::new(ErrorCode::Internal, "log shorter than requested"))?;
+    Ok(ConsistencyProofResponse {
+        first_size,
+        second_size,
+        proof_b64: proof.iter().map(|node| STANDARD.encode(node)).collect(),
+    })
+}
+
+/// Inclusion proof for the latest log entry of a device, against a fresh signed tree head.
+pub async fn key_transparency_proof_for_device(
+    ctx: &ApiContext,
+    user_id: UserId,
+    device_id: DeviceId,
+) -> Result<Option<KeyTransparencyProof>, ApiError> {
+    let Some(leaf) = ctx
+        .storage
+        .latest_key_transparency_leaf_for_device(user_id, device_id)
+        .await
+        .map_err(internal)?
+    else {
+        return Ok(None);
+    };
+    let tree_size = ctx
+        .storage
+        .key_transparency_tree_size()
+        .await
+        .map_err(internal)?;
+    let leaves = ctx
+        .storage
+        .list_key_transparency_leaf_hashes(tree_size)
+        .await
+        .map_err(internal)?;
+    let path = transparency::inclusion_path(&leaves, leaf.leaf_index as usize)
+        .ok_or_else(|| ApiError::new(ErrorCode::Internal, "log entry missing from tree"))?;
+    let tree_head = ctx
+        .transparency
+        .sign_tree_head(tree_size, &transparency::root_hash(&leaves))
+        .map_err(internal)?;
+    Ok(Some(KeyTransparencyProof {
+        leaf,
+        audit_path_b64: path.iter().map(|node| STANDARD.encode(node)).collect(),
+        tree_head,
+    }))
+}
+
+async fn resolve_tree_size(ctx: &ApiContext, requested: Option<u64>) -> Result<u64, ApiError> {
+    let current = ctx
+        .storage
+        .key_transparency_tree_size()
+        .await
+        .map_err(internal)?;
+    match requested {
+        Some(size) if size > current => Err(ApiError::new(
+            ErrorCode::Validation,
+            "requested tree size exceeds the log",
+        )),
+        Some(size) => Ok(size),
+        None => Ok(current),
+    }
+}
+
+pub async fn list_guilds(ctx: &ApiContext, user_id: UserId) -> Result<Vec<GuildSummary>, ApiError> {
+    let guilds = ctx
+        .storage
+        .list_guilds_for_user(user_id)
+        .await
+        .map_err(internal)?;
+    Ok(guilds
+        .into_iter()
+        .map(|(guild_id, name)| GuildSummary { guild_id, name })
+        .collect())
+}
+
+/// Creates a guild owned by the caller with a default `general` text channel.
+pub async fn create_guild(
+    ctx: &ApiContext,
+    user_id: UserId,
+    request: CreateGuildRequest,
+) -> Result<GuildSummary, ApiError> {
+    let name = validate_name(&request.name, "guild")?;
+    let guild_id = ctx
+        .storage
+        .create_guild(name, user_id)
+        .await
+        .map_err(internal)?;
+    ctx.storage
+        .create_channel(guild_id, "general", ChannelKind::Text)
+        .await
+        .map_err(internal)?;
+    Ok(GuildSummary {
+        guild_id,
+        name: name.to_string(),
+    })
+}
+
+pub async fn rename_guild(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    request: UpdateGuildRequest,
+) -> Result<GuildSummary, ApiError> {
+    ensure_active_membership(ctx, guild_id, user_id)
+        .await?
+        .require(Permissions::MANAGE_GUILD, "manage guild")?;
+    let name = validate_name(&request.name, "guild")?;
+    ctx.storage
+        .rename_guild(guild_id, name)
+        .await
+        .map_err(internal)?;
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            details: Some(name.to_string()),
+            ..NewAuditEntry::new(guild_id, user_id, AuditAction::GuildRenamed)
+        },
+    )
+    .await?;
+    Ok(GuildSummary {
+        guild_id,
+        name: name.to_string(),
+    })
+}
+
+/// Stores the caller's guild order and returns their guilds in it.
+pub async fn reorder_guilds(
+    ctx: &ApiContext,
+    user_id: UserId,
+    request: ReorderGuildsRequest,
+) -> Result<Vec<GuildSummary>, ApiError> {
+    ctx.storage
+        .reorder_guilds(user_id, &request.guild_ids)
+        .await
+        .map_err(internal)?;
+    list_guilds(ctx, user_id).await
+}
+
+/// Deletes a guild and everything in it. Only the owner may do this, and only after
+/// repeating the guild's name. Returns the members it had, who are the ones to notify.
+pub async fn delete_guild(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    request: DeleteGuildRequest,
+) -> Result<Vec<UserId>, ApiError> {
+    let member = ensure_active_membership(ctx, guild_id, user_id).await?;
+    if member.role != Role::Owner {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "only the owner can delete a guild",
+        ));
+    }
+    let name = ctx
+        .storage
+        .guild_name(guild_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "guild not found"))?;
+    if request.confirm_name.trim() != name {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "confirmation does not match the guild name",
+        ));
+    }
+    let members = guild_member_ids(ctx, guild_id).await?;
+    ctx.storage.delete_guild(guild_id).await.map_err(internal)?;
+    Ok(members)
+}
+
+/// Active members of a guild, for telling them about a change that removes what would
+/// otherwise scope the event.
+pub(crate) async fn guild_member_ids(
+    ctx: &ApiContext,
+    guild_id: GuildId,
+) -> Result<Vec<UserId>, ApiError> {
+    Ok(ctx
+        .storage
+        .list_members_for_guild(guild_id)
+        .await
+        .map_err(internal)?
+        .into_iter()
+        .map(|member| member.user_id)
+        .collect())
+}
+
+/// Removes the caller from a guild and returns the remaining members. The owner has to
+/// transfer ownership first.
+pub async fn leave_guild(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+) -> Result<Vec<MemberSummary>, ApiError> {
+    let member = ensure_active_membership(ctx, guild_id, user_id).await?;
+    if member.role == Role::Owner {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "transfer ownership before leaving the guild",
+        ));
+    }
+    ctx.storage
+        .remove_membership(guild_id, user_id)
+        .await
+        .map_err(internal)?;
+    let members = ctx
+        .storage
+        .list_members_for_guild(guild_id)
+        .await
+        .map_err(internal)?;
+    Ok(members
+        .into_iter()
+        .map(|member| member_summary(guild_id, member))
+        .collect())
+}
+
+/// Hands the guild to another active member. The previous owner stays on as a moderator.
+pub async fn transfer_ownership(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    request: TransferOwnershipRequest,
+) -> Result<Vec<MemberSummary>, ApiError> {
+    let member = ensure_active_membership(ctx, guild_id, user_id).await?;
+    if member.role != Role::Owner {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "only the owner can transfer ownership",
+        ));
+    }
+    let new_owner = request.new_owner_id;
+    if new_owner == user_id {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "you already own this guild",
+        ));
+    }
+    match ctx
+        .storage
+        .membership_status(guild_id, new_owner)
+        .await
+        .map_err(internal)?
+    {
+        Some((_, false, _)) => {}
+        _ => {
+            return Err(ApiError::new(
+                ErrorCode::Validation,
+                "new owner must be an active member of the guild",
+            ))
+        }
+    }
+    ctx.storage
+        .transfer_guild_ownership(guild_id, user_id, new_owner)
+        .await
+        .map_err(internal)?;
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_user_id: Some(new_owner),
+            ..NewAuditEntry::new(guild_id, user_id, AuditAction::OwnershipTransferred)
+        },
+    )
+    .await?;
+    list_members(ctx, new_owner, guild_id).await
+}
+
+/// Creates a channel at the end of the guild's channel list. Requires `MANAGE_CHANNELS`.
+pub async fn create_channel(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    request: CreateChannelRequest,
+) -> Result<ChannelSummary, ApiError> {
+    ensure_active_membership(ctx, guild_id, user_id)
+        .await?
+        .require(Permissions::MANAGE_CHANNELS, "manage channels")?;
+    if request.kind == ChannelKind::Direct {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "direct channels cannot be created in a guild",
+        ));
+    }
+    let name = validate_name(&request.name, "channel")?;
+    let topic = validate_topic(request.topic.as_deref())?;
+    let category = validate_category(request.category.as_deref())?;
+    let channel_id = ctx
+        .storage
+        .create_channel(guild_id, name, request.kind)
+        .await
+        .map_err(internal)?;
+    if topic.is_some() || category.is_some() {
+        ctx.storage
+            .update_channel(channel_id, name, topic, category)
+            .await
+            .map_err(internal)?;
+    }
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_channel_id: Some(channel_id),
+            details: Some(name.to_string()),
+            ..NewAuditEntry::new(guild_id, user_id, AuditAction::ChannelCreated)
+        },
+    )
+    .await?;
+    channel_summary(ctx, channel_id).await
+}
+
+/// Renames a channel or changes its topic or category. Requires `MANAGE_CHANNELS` in it.
+pub async fn update_channel(
+    ctx: &ApiContext,
+    user_id: UserId,
+    channel_id: ChannelId,
+    request: UpdateChannelRequest,
+) -> Result<ChannelSummary, ApiError> {
+    let existing = load_channel(ctx, channel_id).await?;
+    permissions::resolve(ctx, user_id, existing.guild_id, Some(channel_id))
+        .await?
+        .require(Permissions::MANAGE_CHANNELS, "manage channels")?;
+    let name = match request.name.as_deref() {
+        Some(name) => validate_name(name, "channel")?,
+        None => existing.name.as_str(),
+    };
+    let topic = match request.topic.as_deref() {
+        Some(topic) => validate_topic(Some(topic))?,
+        None => existing.topic.as_deref(),
+    };
+    let category = match request.category.as_deref() {
+        Some(category) => validate_category(Some(category))?,
+        None => existing.category.as_deref(),
+    };
+    ctx.storage
+        .update_channel(channel_id, name, topic, category)
+        .await
+        .map_err(internal)?;
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_channel_id: Some(channel_id),
+            details: Some(name.to_string()),
+            ..NewAuditEntry::new(existing.guild_id, user_id, AuditAction::ChannelUpdated)
+        },
+    )
+    .await?;
+    channel_summary(ctx, channel_id).await
+}
+
+/// Reorders the guild's channels and returns the ones the caller can view.
+pub async fn reorder_channels(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    request: ReorderChannelsRequest,
+) -> Result<Vec<ChannelSummary>, ApiError> {
+    ensure_active_membership(ctx, guild_id, user_id)
+        .await?
+        .require(Permissions::MANAGE_CHANNELS, "manage channels")?;
+    ctx.storage
+        .reorder_channels(guild_id, &request.channel_ids)
+        .await
+        .map_err(internal)?;
+    list_channels(ctx, user_id, guild_id).await
+}
+
+/// Deletes a channel and its history. Requires `MANAGE_CHANNELS` in the channel and
+/// returns the guild it belonged to with the members who could view it.
+pub async fn delete_channel(
+    ctx: &ApiContext,
+    user_id: UserId,
+    channel_id: ChannelId,
+) -> Result<(GuildId, Vec<UserId>), ApiError> {
+    let existing = load_channel(ctx, channel_id).await?;
+    let guild_id = existing.guild_id;
+    permissions::resolve(ctx, user_id, guild_id, Some(channel_id))
+        .await?
+        .require(Permissions::MANAGE_CHANNELS, "manage channels")?;
+    let viewers = channel_viewers(ctx, guild_id, channel_id)
+        .await?
+        .into_iter()
+        .map(|member| member.user_id)
+        .collect();
+    ctx.storage
+        .delete_channel(channel_id)
+        .await
+        .map_err(internal)?;
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_channel_id: Some(channel_id),
+            details: Some(existing.name),
+            ..NewAuditEntry::new(guild_id, user_id, AuditAction::ChannelDeleted)
+        },
+    )
+    .await?;
+    Ok((guild_id, viewers))
+}
+
+/// Trims a topic; an empty topic means none. Topics are capped at 1024 characters.
+fn validate_topic(topic: Option<&str>) -> Result<Option<&str>, ApiError> {
+    let topic = topic.map(str::trim).filter(|topic| !topic.is_empty());
+    if topic.is_some_and(|topic| topic.chars().count() > 1024) {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "channel topic must be at most 1024 characters",
+        ));
+    }
+    Ok(topic)
+}
+
+fn validate_category(category: Option<&str>) -> Result<Option<&str>, ApiError> {
+    match category
+        .map(str::trim)
+        .filter(|category| !category.is_empty())
+    {
+        Some(category) => validate_name(category, "category").map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Lists the guild's channels the caller can view. A channel is reported as private when
+/// its `@everyone` overwrite denies `VIEW_CHANNEL`.
+pub async fn list_channels(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+) -> Result<Vec<ChannelSummary>, ApiError> {
+    let member = ensure_active_membership(ctx, guild_id, user_id).await?;
+    let channels = ctx
+        .storage
+        .list_channels_for_guild(guild_id)
+        .await
+        .map_err(internal)?;
+    let overwrites = guild_overwrites_by_channel(ctx, guild_id).await?;
+    Ok(channels
+        .into_iter()
+        .filter_map(|channel| {
+            let overwrites = overwrites
+                .get(&channel.channel_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            member
+                .channel_permissions(overwrites)
+                .contains(Permissions::VIEW_CHANNEL)
+                .then(|| channel_summary_from(channel, overwrites))
+        })
+        .collect())
+}
+
+/// Lists the guild members who can view `channel_id`. Clients use this to decide who
+/// belongs in the channel's MLS group.
+pub async fn list_channel_members(
+    ctx: &ApiContext,
+    user_id: UserId,
+    channel_id: ChannelId,
+) -> Result<Vec<MemberSummary>, ApiError> {
+    let guild_id = guild_for_channel(ctx, channel_id).await?;
+    ensure_active_membership_in_channel(ctx, user_id, guild_id, channel_id).await?;
+    Ok(channel_viewers(ctx, guild_id, channel_id)
+        .await?
+        .into_iter()
+        .map(|member| member_summary(guild_id, member))
+        .collect())
+}
+
+/// Active members whose roles and the channel's overwrites let them view it.
+async fn channel_viewers(
+    ctx: &ApiContext,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Result<Vec<StoredMember>, ApiError> {
+    let members = ctx
+        .storage
+        .list_members_for_guild(guild_id)
+        .await
+        .map_err(internal)?;
+    let roles = ctx
+        .storage
+        .list_guild_roles(guild_id)
+        .await
+        .map_err(internal)?;
+    let overwrites = ctx
+        .storage
+        .list_channel_overwrites(channel_id)
+        .await
+        .map_err(internal)?;
+
+    Ok(members
+        .into_iter()
+        .filter(|member| {
+            let member_roles: Vec<StoredGuildRole> = roles
+                .iter()
+                .filter(|role| member.role_ids.contains(&role.role_id))
+                .cloned()
+                .collect();
+            ResolvedMember::new(member.user_id, member.role, member.muted, &member_roles)
+                .channel_permissions(&overwrites)
+                .contains(Permissions::VIEW_CHANNEL)
+        })
+        .collect())
+}
+
+pub async fn list_channel_overwrites(
+    ctx: &ApiContext,
+    user_id: UserId,
+    channel_id: ChannelId,
+) -> Result<Vec<PermissionOverwrite>, ApiError> {
+    let guild_id = guild_for_channel(ctx, channel_id).await?;
+    ensure_active_membership_in_channel(ctx, user_id, guild_id, channel_id).await?;
+    ctx.storage
+        .list_channel_overwrites(channel_id)
+        .await
+        .map_err(internal)
+}
+
+/// Replaces the overwrite for one target on a channel; an overwrite with nothing allowed
+/// or denied removes it. Requires `MANAGE_CHANNELS` in the channel, and the caller may
+/// only allow or deny permissions they hold there.
+pub async fn set_channel_overwrite(
+    ctx: &ApiContext,
+    user_id: UserId,
+    channel_id: ChannelId,
+    overwrite: PermissionOverwrite,
+) -> Result<ChannelSummary, ApiError> {
+    let guild_id = guild_for_channel(ctx, channel_id).await?;
+    let actor = permissions::resolve(ctx, user_id, guild_id, Some(channel_id)).await?;
+    actor.require(Permissions::MANAGE_CHANNELS, "manage channels")?;
+    let overwrite = PermissionOverwrite {
+        target: overwrite.target,
+        allow: Permissions::from_bits_truncate(overwrite.allow.0),
+        deny: Permissions::from_bits_truncate(overwrite.deny.0),
+    };
+    if !actor
+        .permissions
+        .contains(overwrite.allow.union(overwrite.deny))
+    {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "cannot overwrite permissions you do not have",
+        ));
+    }
+    match overwrite.target {
+        OverwriteTarget::Everyone => {}
+        OverwriteTarget::Role(role_id) => {
+            load_role(ctx, guild_id, role_id).await?;
+        }
+        OverwriteTarget::Member(target_user_id) => {
+            if ctx
+                .storage
+                .membership_status(guild_id, target_user_id)
+                .await
+                .map_err(internal)?
+                .is_none()
+            {
+                return Err(ApiError::new(ErrorCode::NotFound, "member not found"));
+            }
+        }
+    }
+
+    if overwrite.allow.is_empty() && overwrite.deny.is_empty() {
+        ctx.storage
+            .delete_channel_overwrite(channel_id, overwrite.target)
+            .await
+            .map_err(internal)?;
+    } else {
+        ctx.storage
+            .upsert_channel_overwrite(channel_id, &overwrite)
+            .await
+            .map_err(internal)?;
+    }
+    let (target_user_id, target_role_id, target) = match overwrite.target {
+        OverwriteTarget::Everyone => (None, None, "everyone".to_string()),
+        OverwriteTarget::Role(role_id) => (None, Some(role_id), format!("role {}", role_id.0)),
+        OverwriteTarget::Member(member_id) => {
+            (Some(member_id), None, format!("member {}", member_id.0))
+        }
+    };
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_user_id,
+            target_channel_id: Some(channel_id),
+            target_role_id,
+            details: Some(format!(
+                "{target}: allow {:#x}, deny {:#x}",
+                overwrite.allow.0, overwrite.deny.0
+            )),
+            ..NewAuditEntry::new(guild_id, user_id, AuditAction::ChannelOverwriteUpdated)
+        },
+    )
+    .await?;
+    channel_summary(ctx, channel_id).await
+}
+
+/// Builds the summary for one channel regardless of whether the caller can still view it.
+pub async fn channel_summary(
+    ctx: &ApiContext,
+    channel_id: ChannelId,
+) -> Result<ChannelSummary, ApiError> {
+    let channel = load_channel(ctx, channel_id).await?;
+    let overwrites = ctx
+        .storage
+        .list_channel_overwrites(channel_id)
+        .await
+        .map_err(internal)?;
+    Ok(channel_summary_from(channel, &overwrites))
+}
+
+fn channel_summary_from(
+    channel: StoredChannel,
+    overwrites: &[PermissionOverwrite],
+) -> ChannelSummary {
+    ChannelSummary {
+        channel_id: channel.channel_id,
+        guild_id: channel.guild_id,
+        kind: channel.kind,
+        name: channel.name,
+        history_sharing: channel.history_sharing,
+        private: is_private(overwrites),
+        topic: channel.topic,
+        category: channel.category,
+        position: channel.position,
+        retention: channel.retention,
+    }
+}
+
+async fn load_channel(ctx: &ApiContext, channel_id: ChannelId) -> Result<StoredChannel, ApiError> {
+    ctx.storage
+        .channel(channel_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "channel not found"))
+}
+
+async fn guild_overwrites_by_channel(
+    ctx: &ApiContext,
+    guild_id: GuildId,
+) -> Result<HashMap<ChannelId, Vec<PermissionOverwrite>>, ApiError> {
+    let mut by_channel: HashMap<ChannelId, Vec<PermissionOverwrite>> = HashMap::new();
+    for (channel_id, overwrite) in ctx
+        .storage
+        .list_guild_channel_overwrites(guild_id)
+        .await
+        .map_err(internal)?
+    {
+        by_channel.entry(channel_id).or_default().push(overwrite);
+    }
+    Ok(by_channel)
+}
+
+fn is_private(overwrites: &[PermissionOverwrite]) -> bool {
+    overwrites.iter().any(|overwrite| {
+        overwrite.target == OverwriteTarget::Everyone
+            && overwrite.deny.contains(Permissions::VIEW_CHANNEL)
+    })
+}
+
+async fn guild_for_channel(ctx: &ApiContext, channel_id: ChannelId) -> Result<GuildId, ApiError> {
+    ctx.storage
+        .guild_for_channel(channel_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "channel not found"))
+}
+
+/// Turns history sharing for a channel on or off. Requires `MANAGE_CHANNELS`.
+pub async fn set_channel_history_sharing(
+    ctx: &ApiContext,
+    user_id: UserId,
+    channel_id: ChannelId,
+    enabled: bool,
+) -> Result<ChannelSummary, ApiError> {
+    let guild_id = ctx
+        .storage
+        .guild_for_channel(channel_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "channel not found"))?;
+    ensure_active_membership(ctx, guild_id, user_id)
+        .await?
+        .require(Permissions::MANAGE_CHANNELS, "change history sharing")?;
+    ctx.storage
+        .set_channel_history_sharing(channel_id, enabled)
+        .await
+        .map_err(internal)?;
+
+    channel_summary(ctx, channel_id).await
+}
+
+/// Stores an opaque history bundle from `sender_id` for a member of a history-sharing channel.
+pub async fn store_history_bundle(
+    ctx: &ApiContext,
+    sender_id: UserId,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    target_user_id: UserId,
+    target_device_id: Option<DeviceId>,
+    bundle_bytes: &[u8],
+) -> Result<(), ApiError> {
+    if bundle_bytes.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "history bundle cannot be empty",
+        ));
+    }
+    ensure_active_membership_in_channel(ctx, sender_id, guild_id, channel_id).await?;
+    ensure_active_membership_in_channel(ctx, target_user_id, guild_id, channel_id).await?;
+    if !ctx
+        .storage
+        .channel_history_sharing(channel_id)
+        .await
+        .map_err(internal)?
+    {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "history sharing is disabled for this channel",
+        ));
+    }
+    ctx.storage
+        .insert_history_bundle(
+            guild_id,
+            channel_id,
+            sender_id,
+            target_user_id,
+            target_device_id,
+            bundle_bytes,
+        )
+        .await
+        .map_err(internal)?;
+    Ok(())
+}
+
+pub async fn list_history_bundles(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    device_id: Option<DeviceId>,
+) -> Result<Vec<HistoryBundleResponse>, ApiError> {
+    ensure_active_membership_in_channel(ctx, user_id, guild_id, channel_id).await?;
+    let bundles = ctx
+        .storage
+        .list_history_bundles(guild_id, channel_id, user_id, device_id)
+        .await
+        .map_err(internal)?;
+    Ok(bundles
+        .into_iter()
+        .map(|bundle| HistoryBundleResponse {
+            guild_id,
+            channel_id,
+            sender_id: bundle.sender_id,
+            target_device_id: bundle.target_device_id,
+            bundle_b64: STANDARD.encode(bundle.bundle_bytes),
+            created_at: bundle.created_at,
+        })
+        .collect())
+}
+
+pub async fn list_members(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+) -> Result<Vec<MemberSummary>, ApiError> {
+    ensure_active_membership(ctx, guild_id, user_id).await?;
+    let members = ctx
+        .storage
+        .list_members_for_guild(guild_id)
+        .await
+        .map_err(internal)?;
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct UploadKeyPackageResponse {
-    pub key_package_id: i64,
+    Ok(members
+        .into_iter()
+        .map(|member| member_summary(guild_id, member))
+        .collect())
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct KeyPackageResponse {
-    pub key_package_id: i64,
-    pub guild_id: i64,
-    pub user_id: i64,
-    pub device_id: Option<DeviceId>,
-    pub key_package_b64: String,
+fn member_summary(guild_id: GuildId, member: StoredMember) -> MemberSummary {
+    MemberSummary {
+        guild_id,
+        user_id: member.user_id,
+        username: member.username,
+        role: member.role,
+        muted: member.muted,
+        role_ids: member.role_ids,
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct MlsWelcomeResponse {
-    pub user_id: i64,
-    pub guild_id: i64,
-    pub channel_id: i64,
-    pub target_device_id: Option<DeviceId>,
-    pub welcome_b64: String,
-    pub consumed_at: Option<DateTime<Utc>>,
+/// Kicks, bans, mutes or lifts a ban or mute. Each action needs its matching permission
+/// and, except for unbanning, an actor who outranks the target.
+pub async fn moderate_member(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    target_user_id: UserId,
+    request: ModerationRequest,
+) -> Result<(), ApiError> {
+    let actor = ensure_active_membership(ctx, guild_id, user_id).await?;
+    let (needed, what, audit_action) = match request.action {
+        ModerationAction::Kick => (
+            Permissions::KICK_MEMBERS,
+            "kick members",
+            AuditAction::MemberKicked,
+        ),
+        ModerationAction::Ban => (
+            Permissions::BAN_MEMBERS,
+            "ban members",
+            AuditAction::MemberBanned,
+        ),
+        ModerationAction::Unban => (
+            Permissions::BAN_MEMBERS,
+            "ban members",
+            AuditAction::MemberUnbanned,
+        ),
+        ModerationAction::Mute => (
+            Permissions::MUTE_MEMBERS,
+            "mute members",
+            AuditAction::MemberMuted,
+        ),
+        ModerationAction::Unmute => (
+            Permissions::MUTE_MEMBERS,
+            "mute members",
+            AuditAction::MemberUnmuted,
+        ),
+    };
+    actor.require(needed, what)?;
+    if target_user_id == user_id {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "cannot moderate yourself",
+        ));
+    }
+    let reason = validate_reason(request.reason.as_deref())?;
+
+    let membership = ctx
+        .storage
+        .membership_status(guild_id, target_user_id)
+        .await
+        .map_err(internal)?;
+    match (request.action, membership) {
+        (ModerationAction::Unban, _) => {}
+        (ModerationAction::Ban, None) => {}
+        (_, Some((_, false, _))) => {
+            let target = ensure_active_membership(ctx, guild_id, target_user_id).await?;
+            if !actor.outranks_member(&target) {
+                return Err(ApiError::new(
+                    ErrorCode::Forbidden,
+                    "cannot moderate a member at or above your rank",
+                ));
+            }
+        }
+        (ModerationAction::Ban, Some((_, true, _))) => {
+            return Err(ApiError::new(
+                ErrorCode::Validation,
+                "member is already banned",
+            ));
+        }
+        _ => return Err(ApiError::new(ErrorCode::NotFound, "member not found")),
+    }
+
+    match request.action {
+        ModerationAction::Kick => {
+            ctx.storage
+                .remove_membership(guild_id, target_user_id)
+                .await
+                .map_err(internal)?;
+        }
+        ModerationAction::Ban => {
+            ctx.storage
+                .ban_member(guild_id, target_user_id)
+                .await
+                .map_err(internal)?;
+        }
+        ModerationAction::Unban => {
+            if !ctx
+                .storage
+                .unban_member(guild_id, target_user_id)
+                .await
+                .map_err(internal)?
+            {
+                return Err(ApiError::new(ErrorCode::NotFound, "ban not found"));
+            }
+        }
+        ModerationAction::Mute | ModerationAction::Unmute => {
+            ctx.storage
+                .set_member_muted(
+                    guild_id,
+                    target_user_id,
+                    request.action == ModerationAction::Mute,
+                )
+                .await
+                .map_err(internal)?;
+        }
+    }
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_user_id: Some(target_user_id),
+            reason: reason.map(str::to_string),
+            ..NewAuditEntry::new(guild_id, user_id, audit_action)
+        },
+    )
+    .await
 }
 
-pub fn mls_key_packages_route() -> &'static str {
-    "/mls/key_packages"
+/// Trims a moderation reason; an empty reason means none. Capped at 512 characters.
+fn validate_reason(reason: Option<&str>) -> Result<Option<&str>, ApiError> {
+    let reason = reason.map(str::trim).filter(|reason| !reason.is_empty());
+    if reason.is_some_and(|reason| reason.chars().count() > 512) {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "reason must be at most 512 characters",
+        ));
+    }
+    Ok(reason)
 }
 
-pub fn mls_welcome_route() -> &'static str {
-    "/mls/welcome"
+/// Creates an invite. The code encodes the guild id so clients can tell which guild they
+/// joined, plus a random secret so invites can be revoked one by one.
+pub async fn create_invite(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+) -> Result<InviteSummary, ApiError> {
+    ensure_active_membership(ctx, guild_id, user_id)
+        .await?
+        .require(Permissions::CREATE_INVITES, "create invites")?;
+    let mut secret = [0u8; 12];
+    OsRng.fill_bytes(&mut secret);
+    let payload = format!("guild:{}:{}", guild_id.0, URL_SAFE_NO_PAD.encode(secret));
+    let code = URL_SAFE_NO_PAD.encode(payload.as_bytes());
+    let invite = ctx
+        .storage
+        .create_invite(guild_id, user_id, &code)
+        .await
+        .map_err(internal)?;
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            details: Some(code),
+            ..NewAuditEntry::new(guild_id, user_id, AuditAction::InviteCreated)
+        },
+    )
+    .await?;
+    Ok(invite)
 }
 
-pub fn mls_bootstrap_request_route() -> &'static str {
-    "/mls/bootstrap/request"
+/// Lists the guild's outstanding invites. Requires `MANAGE_GUILD`.
+pub async fn list_invites(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+) -> Result<Vec<InviteSummary>, ApiError> {
+    ensure_active_membership(ctx, guild_id, user_id)
+        .await?
+        .require(Permissions::MANAGE_GUILD, "manage guild")?;
+    ctx.storage.list_invites(guild_id).await.map_err(internal)
 }
 
-pub fn mls_welcome_recovery_route() -> &'static str {
-    "/mls/welcome/recovery"
+/// Revokes an invite. Its creator may always do this; anyone else needs `MANAGE_GUILD`.
+pub async fn delete_invite(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    code: &str,
+) -> Result<(), ApiError> {
+    let actor = ensure_active_membership(ctx, guild_id, user_id).await?;
+    let invite = ctx
+        .storage
+        .invite(code)
+        .await
+        .map_err(internal)?
+        .filter(|invite| invite.guild_id == guild_id)
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "invite not found"))?;
+    if invite.creator_id != user_id {
+        actor.require(Permissions::MANAGE_GUILD, "manage guild")?;
+    }
+    ctx.storage.delete_invite(code).await.map_err(internal)?;
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_user_id: Some(invite.creator_id),
+            details: Some(invite.invite_code),
+            ..NewAuditEntry::new(guild_id, user_id, AuditAction::InviteDeleted)
+        },
+    )
+    .await
 }
 
-pub async fn list_guilds(ctx: &ApiContext, user_id: UserId) -> Result<Vec<GuildSummary>, ApiError> {
-    let guilds = ctx
+/// Adds the caller to the invite's guild and returns it. Existing members keep their role;
+/// banned users are turned away.
+pub async fn join_with_invite(
+    ctx: &ApiContext,
+    user_id: UserId,
+    code: &str,
+) -> Result<GuildId, ApiError> {
+    let invite = ctx
         .storage
-        .list_guilds_for_user(user_id)
+        .invite(code)
         .await
-        .map_err(internal)?;
-    Ok(guilds
-        .into_iter()
-        .map(|(guild_id, name)| GuildSummary { guild_id, name })
-        .collect())
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(ErrorCode::Validation, "invalid invite code"))?;
+    match ctx
+        .storage
+        .membership_status(invite.guild_id, user_id)
+        .await
+        .map_err(internal)?
+    {
+        Some((_, true, _)) => Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "user is banned from this guild",
+        )),
+        Some(_) => Ok(invite.guild_id),
+        None => {
+            ctx.storage
+                .add_membership(invite.guild_id, user_id, Role::Member, false, false)
+                .await
+                .map_err(internal)?;
+            Ok(invite.guild_id)
+        }
+    }
 }
 
-pub async fn list_channels(
+pub async fn list_roles(
     ctx: &ApiContext,
     user_id: UserId,
     guild_id: GuildId,
-) -> Result<Vec<ChannelSummary>, ApiError> {
+) -> Result<Vec<RoleSummary>, ApiError> {
     ensure_active_membership(ctx, guild_id, user_id).await?;
-    let channels = ctx
+    let roles = ctx
         .storage
-        .list_channels_for_guild(guild_id)
+        .list_guild_roles(guild_id)
         .await
         .map_err(internal)?;
-    Ok(channels
-        .into_iter()
-        .map(|(channel_id, name, kind)| ChannelSummary {
-            channel_id,
+    Ok(roles.into_iter().map(role_summary).collect())
+}
+
+/// Creates a guild role. The caller needs `MANAGE_ROLES`, may only grant permissions they
+/// hold themselves, and may only place the role below their own highest role.
+pub async fn create_role(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    request: CreateRoleRequest,
+) -> Result<RoleSummary, ApiError> {
+    let actor = ensure_active_membership(ctx, guild_id, user_id).await?;
+    actor.require(Permissions::MANAGE_ROLES, "manage roles")?;
+    let name = validate_name(&request.name, "role")?;
+    ensure_can_place_role(&actor, request.position, request.permissions)?;
+
+    let role_id = ctx
+        .storage
+        .create_guild_role(
             guild_id,
-            kind,
             name,
-        })
-        .collect())
+            request.color,
+            request.position,
+            Permissions::from_bits_truncate(request.permissions.0),
+        )
+        .await
+        .map_err(internal)?;
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_role_id: Some(role_id),
+            details: Some(name.to_string()),
+            ..NewAuditEntry::new(guild_id, user_id, AuditAction::RoleCreated)
+        },
+    )
+    .await?;
+    load_role(ctx, guild_id, role_id).await.map(role_summary)
 }
 
-pub async fn list_members(
+pub async fn update_role(
     ctx: &ApiContext,
     user_id: UserId,
     guild_id: GuildId,
-) -> Result<Vec<MemberSummary>, ApiError> {
-    ensure_active_membership(ctx, guild_id, user_id).await?;
-    let members = ctx
-        .storage
-        .list_members_for_guild(guild_id)
+    role_id: RoleId,
+    request: UpdateRoleRequest,
+) -> Result<RoleSummary, ApiError> {
+    let actor = ensure_active_membership(ctx, guild_id, user_id).await?;
+    actor.require(Permissions::MANAGE_ROLES, "manage roles")?;
+    let existing = load_role(ctx, guild_id, role_id).await?;
+    ensure_can_place_role(&actor, existing.position, Permissions::NONE)?;
+
+    let name = match request.name.as_deref() {
+        Some(name) => validate_name(name, "role")?,
+        None => existing.name.as_str(),
+    };
+    let position = request.position.unwrap_or(existing.position);
+    let permissions = request.permissions.unwrap_or(existing.permissions);
+    // Only newly granted bits need to be held by the caller.
+    ensure_can_place_role(
+        &actor,
+        position,
+        permissions.difference(existing.permissions),
+    )?;
+
+    ctx.storage
+        .update_guild_role(
+            role_id,
+            name,
+            request.color.unwrap_or(existing.color),
+            position,
+            Permissions::from_bits_truncate(permissions.0),
+        )
         .await
         .map_err(internal)?;
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_role_id: Some(role_id),
+            details: Some(name.to_string()),
+            ..NewAuditEntry::new(guild_id, user_id, AuditAction::RoleUpdated)
+        },
+    )
+    .await?;
+    load_role(ctx, guild_id, role_id).await.map(role_summary)
+}
 
-    Ok(members
-        .into_iter()
-        .map(|member| MemberSummary {
-            guild_id,
-            user_id: member.user_id,
-            username: member.username,
-            role: member.role,
-            muted: member.muted,
-        })
-        .collect())
+pub async fn delete_role(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    role_id: RoleId,
+) -> Result<(), ApiError> {
+    let actor = ensure_active_membership(ctx, guild_id, user_id).await?;
+    actor.require(Permissions::MANAGE_ROLES, "manage roles")?;
+    let existing = load_role(ctx, guild_id, role_id).await?;
+    ensure_can_place_role(&actor, existing.position, Permissions::NONE)?;
+    ctx.storage
+        .delete_guild_role(role_id)
+        .await
+        .map_err(internal)?;
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_role_id: Some(role_id),
+            details: Some(existing.name),
+            ..NewAuditEntry::new(guild_id, user_id, AuditAction::RoleDeleted)
+        },
+    )
+    .await?;
+    Ok(())
 }
 
-pub async fn send_message(
+/// Grants or revokes a custom role for `target_user_id`. Follows the same hierarchy rules
+/// as editing the role itself, and the caller must also outrank the target unless they are
+/// changing their own roles.
+pub async fn set_member_role(
     ctx: &ApiContext,
     user_id: UserId,
     guild_id: GuildId,
-    channel_id: ChannelId,
-    ciphertext_b64: &str,
-    attachment: Option<AttachmentPayload>,
-) -> Result<ServerEvent, ApiError> {
-    let (_, _, muted) = ensure_active_membership(ctx, guild_id, user_id).await?;
-    let actual_guild_id = ctx
+    target_user_id: UserId,
+    role_id: RoleId,
+    assigned: bool,
+) -> Result<(), ApiError> {
+    let actor = ensure_active_membership(ctx, guild_id, user_id).await?;
+    actor.require(Permissions::MANAGE_ROLES, "manage roles")?;
+    let role = load_role(ctx, guild_id, role_id).await?;
+    ensure_can_place_role(&actor, role.position, role.permissions)?;
+    let Some((target_role, _, target_muted)) = ctx
         .storage
-        .guild_for_channel(channel_id)
+        .membership_status(guild_id, target_user_id)
         .await
         .map_err(internal)?
-        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "channel not found"))?;
-    if actual_guild_id != guild_id {
+    else {
+        return Err(ApiError::new(ErrorCode::NotFound, "member not found"));
+    };
+    if target_user_id != user_id {
+        let target_roles = ctx
+            .storage
+            .list_member_roles(guild_id, target_user_id)
+            .await
+            .map_err(internal)?;
+        let target = ResolvedMember::new(target_user_id, target_role, target_muted, &target_roles);
+        if !actor.outranks_member(&target) {
+            return Err(ApiError::new(
+                ErrorCode::Forbidden,
+                "cannot change roles of a member at or above your rank",
+            ));
+        }
+    }
+
+    let action = if assigned {
+        ctx.storage
+            .assign_member_role(guild_id, target_user_id, role_id)
+            .await
+            .map_err(internal)?;
+        AuditAction::MemberRoleAdded
+    } else {
+        ctx.storage
+            .unassign_member_role(guild_id, target_user_id, role_id)
+            .await
+            .map_err(internal)?;
+        AuditAction::MemberRoleRemoved
+    };
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_user_id: Some(target_user_id),
+            target_role_id: Some(role_id),
+            details: Some(role.name),
+            ..NewAuditEntry::new(guild_id, user_id, action)
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+async fn load_role(
+    ctx: &ApiContext,
+    guild_id: GuildId,
+    role_id: RoleId,
+) -> Result<StoredGuildRole, ApiError> {
+    ctx.storage
+        .guild_role(role_id)
+        .await
+        .map_err(internal)?
+        .filter(|role| role.guild_id == guild_id)
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "role not found"))
+}
+
+fn ensure_can_place_role(
+    actor: &ResolvedMember,
+    position: i64,
+    granted: Permissions,
+) -> Result<(), ApiError> {
+    if !actor.outranks_role(position) {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "role must be below your highest role",
+        ));
+    }
+    if !actor.permissions.contains(granted) {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "cannot grant permissions you do not have",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_name<'a>(name: &'a str, what: &str) -> Result<&'a str, ApiError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 64 {
         return Err(ApiError::new(
             ErrorCode::Validation,
-            "channel does not belong to guild",
+            format!("{what} name must be 1-64 characters"),
         ));
     }
-    if muted {
-        return Err(ApiError::new(ErrorCode::Forbidden, "user is muted"));
+    Ok(name)
+}
+
+fn role_summary(role: StoredGuildRole) -> RoleSummary {
+    RoleSummary {
+        role_id: role.role_id,
+        guild_id: role.guild_id,
+        name: role.name,
+        color: role.color,
+        position: role.position,
+        permissions: role.permissions,
+    }
+}
+
+/// Where a new message goes and how long it lives. The default posts to the channel
+/// itself and never expires.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessageOptions {
+    pub thread_id: Option<ThreadId>,
+    pub expires_in_seconds: Option<u64>,
+}
+
+/// Posts a message to the channel, or to one of its threads when `options.thread_id` is set.
+/// Archived threads reject new messages until they are unarchived.
+pub async fn send_message(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    options: MessageOptions,
+    ciphertext_b64: &str,
+    attachment: Option<AttachmentPayload>,
+) -> Result<ServerEvent, ApiError> {
+    let MessageOptions {
+        thread_id,
+        expires_in_seconds,
+    } = options;
+    let expires_at = retention::message_expiry(expires_in_seconds)?;
+    let member = permissions::resolve(ctx, user_id, guild_id, Some(channel_id)).await?;
+    member.require(Permissions::SEND_MESSAGES, "send messages")?;
+    if attachment.is_some() {
+        member.require(Permissions::ATTACH_FILES, "attach files")?;
+    }
+    if let Some(thread_id) = thread_id {
+        let thread = threads::load_thread(ctx, thread_id).await?;
+        if thread.channel_id != channel_id {
+            return Err(ApiError::new(
+                ErrorCode::Validation,
+                "thread does not belong to channel",
+            ));
+        }
+        if thread.archived {
+            return Err(ApiError::new(ErrorCode::Validation, "thread is archived"));
+        }
     }
     let ciphertext = STANDARD
         .decode(ciphertext_b64)
         .map_err(|_| ApiError::new(ErrorCode::Validation, "invalid base64 ciphertext"))?;
 
+    if let Some(attachment) = &attachment {
+        let file = ctx
+            .storage
+            .load_file_metadata(&attachment.file_id)
+            .await
+            .map_err(internal)?
+            .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "attachment not found"))?;
+        if file.channel_id != channel_id {
+            return Err(ApiError::new(
+                ErrorCode::Validation,
+                "attachment does not belong to channel",
+            ));
+        }
+        if file.thumbnail_of.is_some() {
+            return Err(ApiError::new(
+                ErrorCode::Validation,
+                "a thumbnail cannot be attached on its own",
+            ));
+        }
+    }
+
     let stored_attachment = attachment.as_ref().map(|attachment| StoredAttachment {
-        file_id: attachment.file_id,
+        file_id: attachment.file_id.clone(),
         filename: attachment.filename.clone(),
         size_bytes: attachment.size_bytes,
         mime_type: attachment.mime_type.clone(),
@@ -177,7 +1557,14 @@ pub async fn send_message(
 
     let message_id = ctx
         .storage
-        .insert_message_ciphertext(channel_id, user_id, &ciphertext, stored_attachment.as_ref())
+        .insert_thread_message_ciphertext(
+            channel_id,
+            thread_id,
+            user_id,
+            &ciphertext,
+            stored_attachment.as_ref(),
+            expires_at,
+        )
         .await
         .map_err(internal)?;
     let sender_username = ctx
@@ -193,7 +1580,9 @@ pub async fn send_message(
             sender_username,
             ciphertext_b64: ciphertext_b64.to_string(),
             attachment,
+            thread_id,
             sent_at: Utc::now(),
+            expires_at,
         },
     })
 }
@@ -211,14 +1600,20 @@ pub async fn list_messages(
         .await
         .map_err(internal)?
         .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "channel not found"))?;
-    ensure_active_membership(ctx, guild_id, user_id).await?;
+    ensure_active_membership_in_channel(ctx, user_id, guild_id, channel_id).await?;
 
     let messages = ctx
         .storage
         .list_channel_messages(channel_id, limit, before)
         .await
         .map_err(internal)?;
+    message_payloads(ctx, messages).await
+}
 
+async fn message_payloads(
+    ctx: &ApiContext,
+    messages: Vec<StoredMessage>,
+) -> Result<Vec<MessagePayload>, ApiError> {
     let mut username_cache: std::collections::HashMap<UserId, Option<String>> =
         std::collections::HashMap::new();
     let mut payloads = Vec::with_capacity(messages.len());
@@ -247,13 +1642,17 @@ pub async fn list_messages(
                 size_bytes: attachment.size_bytes,
                 mime_type: attachment.mime_type,
             }),
+            thread_id: message.thread_id,
             sent_at: message.created_at,
+            expires_at: message.expires_at,
         });
     }
 
     Ok(payloads)
 }
 
+/// Mints a LiveKit token for a voice channel. Publish grants are narrowed to what the
+/// member's `SPEAK` and `STREAM` permissions allow rather than rejected outright.
 pub async fn request_livekit_token(
     ctx: &ApiContext,
     user_id: UserId,
@@ -262,18 +1661,10 @@ pub async fn request_livekit_token(
     can_publish_mic: bool,
     can_publish_screen: bool,
 ) -> Result<ServerEvent, ApiError> {
-    ensure_active_membership(ctx, guild_id, user_id).await?;
-    let channels = ctx
-        .storage
-        .list_channels_for_guild(guild_id)
-        .await
-        .map_err(internal)?;
-    let is_voice = channels
-        .into_iter()
-        .find(|(id, _, _)| *id == channel_id)
-        .map(|(_, _, kind)| kind == ChannelKind::Voice)
-        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "channel not found"))?;
-    if !is_voice {
+    let granted = permissions::compute(ctx, user_id, guild_id, Some(channel_id)).await?;
+    let can_publish_mic = can_publish_mic && granted.contains(Permissions::SPEAK);
+    let can_publish_screen = can_publish_screen && granted.contains(Permissions::STREAM);
+    if load_channel(ctx, channel_id).await?.kind != ChannelKind::Voice {
         return Err(ApiError::new(
             ErrorCode::Validation,
             "livekit token only valid for voice channels",
@@ -304,19 +1695,7 @@ pub async fn ensure_active_membership_in_channel(
     guild_id: GuildId,
     channel_id: ChannelId,
 ) -> Result<(), ApiError> {
-    ensure_active_membership(ctx, guild_id, user_id).await?;
-    let actual_guild_id = ctx
-        .storage
-        .guild_for_channel(channel_id)
-        .await
-        .map_err(internal)?
-        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "channel not found"))?;
-    if actual_guild_id != guild_id {
-        return Err(ApiError::new(
-            ErrorCode::Validation,
-            "channel does not belong to guild",
-        ));
-    }
+    permissions::resolve(ctx, user_id, guild_id, Some(channel_id)).await?;
     Ok(())
 }
 
@@ -333,19 +1712,8 @@ async fn ensure_active_membership(
     ctx: &ApiContext,
     guild_id: GuildId,
     user_id: UserId,
-) -> Result<(Role, bool, bool), ApiError> {
-    let membership = ctx
-        .storage
-        .membership_status(guild_id, user_id)
-        .await
-        .map_err(internal)?;
-    let Some((role, banned, muted)) = membership else {
-        return Err(ApiError::new(ErrorCode::Forbidden, "user is not a member"));
-    };
-    if banned {
-        return Err(ApiError::new(ErrorCode::Forbidden, "user is banned"));
-    }
-    Ok((role, banned, muted))
+) -> Result<ResolvedMember, ApiError> {
+    permissions::resolve(ctx, user_id, guild_id, None).await
 }
 
 fn internal(err: anyhow::Error) -> ApiError {
@@ -0,0 +1,247 @@
+use shared::{
+    domain::{
+        ChannelId, GuildId, OverwriteTarget, PermissionOverwrite, Permissions, Role, RoleId, UserId,
+    },
+    error::{ApiError, ErrorCode},
+};
+use storage::StoredGuildRole;
+
+use super::{internal, ApiContext};
+
+/// Permissions a guild mute takes away from anyone but the owner.
+const MUTED_STRIPPED: Permissions =
+    Permissions(Permissions::SEND_MESSAGES.0 | Permissions::ATTACH_FILES.0 | Permissions::SPEAK.0);
+
+/// The role rank moderators hold when none of their custom roles is higher, so a moderator
+/// granted `MANAGE_ROLES` can manage roles at position 0 and below.
+const MOD_ROLE_RANK: i64 = 1;
+
+/// A member's standing in a guild after banned/muted state, custom roles and, when a
+/// channel is given, that channel's overwrites are applied.
+#[derive(Debug, Clone)]
+pub struct ResolvedMember {
+    pub user_id: UserId,
+    pub role: Role,
+    pub muted: bool,
+    pub permissions: Permissions,
+    /// Highest position among the member's custom roles; `None` when they hold none.
+    pub top_role_position: Option<i64>,
+    role_ids: Vec<RoleId>,
+    /// Guild permissions before the mute and any channel overwrites are applied.
+    base: Permissions,
+}
+
+impl ResolvedMember {
+    pub fn new(user_id: UserId, role: Role, muted: bool, roles: &[StoredGuildRole]) -> Self {
+        let mut base = match role {
+            Role::Owner => Permissions::ALL,
+            Role::Mod => Permissions::MOD_DEFAULT,
+            Role::Member => Permissions::MEMBER_DEFAULT,
+        };
+        for custom_role in roles {
+            base |= custom_role.permissions;
+        }
+        let mut member = Self {
+            user_id,
+            role,
+            muted,
+            permissions: base,
+            top_role_position: roles.iter().map(|custom_role| custom_role.position).max(),
+            role_ids: roles
+                .iter()
+                .map(|custom_role| custom_role.role_id)
+                .collect(),
+            base,
+        };
+        member.permissions = member.apply_mute(base);
+        member
+    }
+
+    /// A direct conversation participant: a fixed permission set, no roles, no mute.
+    pub fn direct(user_id: UserId) -> Self {
+        Self {
+            user_id,
+            role: Role::Member,
+            muted: false,
+            permissions: Permissions::DIRECT_DEFAULT,
+            top_role_position: None,
+            role_ids: Vec::new(),
+            base: Permissions::DIRECT_DEFAULT,
+        }
+    }
+
+    /// Applies a channel's overwrites in order: everyone, then the member's roles combined,
+    /// then the member. Owners are never restricted.
+    pub fn channel_permissions(&self, overwrites: &[PermissionOverwrite]) -> Permissions {
+        if self.role == Role::Owner {
+            return Permissions::ALL;
+        }
+        let mut permissions = self.base;
+        let apply = |permissions: Permissions, allow: Permissions, deny: Permissions| {
+            permissions.difference(deny).union(allow)
+        };
+        if let Some(everyone) = overwrites
+            .iter()
+            .find(|overwrite| overwrite.target == OverwriteTarget::Everyone)
+        {
+            permissions = apply(permissions, everyone.allow, everyone.deny);
+        }
+        let (role_allow, role_deny) = overwrites
+            .iter()
+            .filter(|overwrite| match overwrite.target {
+                OverwriteTarget::Role(role_id) => self.role_ids.contains(&role_id),
+                _ => false,
+            })
+            .fold(
+                (Permissions::NONE, Permissions::NONE),
+                |(allow, deny), overwrite| (allow | overwrite.allow, deny | overwrite.deny),
+            );
+        permissions = apply(permissions, role_allow, role_deny);
+        if let Some(member) = overwrites
+            .iter()
+            .find(|overwrite| overwrite.target == OverwriteTarget::Member(self.user_id))
+        {
+            permissions = apply(permissions, member.allow, member.deny);
+        }
+        self.apply_mute(permissions)
+    }
+
+    pub fn require(&self, needed: Permissions, action: &str) -> Result<(), ApiError> {
+        if self.permissions.contains(needed) {
+            return Ok(());
+        }
+        if self.muted && MUTED_STRIPPED.contains(needed) {
+            return Err(ApiError::new(ErrorCode::Forbidden, "user is muted"));
+        }
+        Err(ApiError::new(
+            ErrorCode::Forbidden,
+            format!("missing permission to {action}"),
+        ))
+    }
+
+    /// Owners outrank every role; everyone else may only manage roles strictly below their
+    /// rank, which is their highest custom role or, for moderators, at least
+    /// [`MOD_ROLE_RANK`].
+    pub fn outranks_role(&self, position: i64) -> bool {
+        self.role == Role::Owner || self.role_rank().is_some_and(|rank| position < rank)
+    }
+
+    fn role_rank(&self) -> Option<i64> {
+        match self.role {
+            Role::Mod => Some(
+                self.top_role_position
+                    .map_or(MOD_ROLE_RANK, |top_position| {
+                        top_position.max(MOD_ROLE_RANK)
+                    }),
+            ),
+            _ => self.top_role_position,
+        }
+    }
+
+    /// Whether this member may moderate `other`: owners outrank everyone, moderators outrank
+    /// plain members, and within a tier the higher custom role wins.
+    pub fn outranks_member(&self, other: &ResolvedMember) -> bool {
+        let tier = |role: Role| match role {
+            Role::Owner => 2,
+            Role::Mod => 1,
+            Role::Member => 0,
+        };
+        match tier(self.role).cmp(&tier(other.role)) {
+            std::cmp::Ordering::Greater => true,
+            std::cmp::Ordering::Less => false,
+            std::cmp::Ordering::Equal => {
+                self.role != Role::Owner && self.top_role_position > other.top_role_position
+            }
+        }
+    }
+
+    fn apply_mute(&self, permissions: Permissions) -> Permissions {
+        if self.muted && self.role != Role::Owner {
+            permissions.difference(MUTED_STRIPPED)
+        } else {
+            permissions
+        }
+    }
+}
+
+/// Effective permissions of `user_id` in `guild_id`, optionally scoped to a channel that
+/// must belong to the guild. Non-members, banned members and suspended accounts are
+/// rejected outright.
+pub async fn compute(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    channel_id: Option<ChannelId>,
+) -> Result<Permissions, ApiError> {
+    Ok(resolve(ctx, user_id, guild_id, channel_id)
+        .await?
+        .permissions)
+}
+
+/// Like [`compute`], but keeps the member details role management needs. A channel the
+/// member cannot view is rejected as if they were not in the guild.
+pub async fn resolve(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    channel_id: Option<ChannelId>,
+) -> Result<ResolvedMember, ApiError> {
+    let membership = ctx
+        .storage
+        .membership_status(guild_id, user_id)
+        .await
+        .map_err(internal)?;
+    let Some((role, banned, muted)) = membership else {
+        return Err(ApiError::new(ErrorCode::Forbidden, "user is not a member"));
+    };
+    if banned {
+        return Err(ApiError::new(ErrorCode::Forbidden, "user is banned"));
+    }
+    if ctx
+        .storage
+        .user_is_suspended(user_id)
+        .await
+        .map_err(internal)?
+    {
+        return Err(ApiError::new(ErrorCode::Forbidden, "account is suspended"));
+    }
+    let mut member = if ctx
+        .storage
+        .guild_is_direct(guild_id)
+        .await
+        .map_err(internal)?
+    {
+        ResolvedMember::direct(user_id)
+    } else {
+        let roles = ctx
+            .storage
+            .list_member_roles(guild_id, user_id)
+            .await
+            .map_err(internal)?;
+        ResolvedMember::new(user_id, role, muted, &roles)
+    };
+
+    if let Some(channel_id) = channel_id {
+        let actual_guild_id = ctx
+            .storage
+            .guild_for_channel(channel_id)
+            .await
+            .map_err(internal)?
+            .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "channel not found"))?;
+        if actual_guild_id != guild_id {
+            return Err(ApiError::new(
+                ErrorCode::Validation,
+                "channel does not belong to guild",
+            ));
+        }
+        let overwrites = ctx
+            .storage
+            .list_channel_overwrites(channel_id)
+            .await
+            .map_err(internal)?;
+        member.permissions = member.channel_permissions(&overwrites);
+        member.require(Permissions::VIEW_CHANNEL, "view channel")?;
+    }
+
+    Ok(member)
+}
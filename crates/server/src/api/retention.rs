@@ -0,0 +1,102 @@
+use chrono::{DateTime, Duration, Utc};
+use shared::{
+    domain::{AuditAction, ChannelId, Permissions, UserId},
+    error::{ApiError, ErrorCode},
+    protocol::{ChannelSummary, RetentionPolicy, ServerEvent},
+};
+use storage::{NewAuditEntry, RetentionPurge};
+
+use super::{audit, channel_summary, internal, load_channel, permissions, ApiContext};
+
+/// Longest per-message timer a sender may set.
+const MAX_MESSAGE_TTL_SECONDS: u64 = 30 * 24 * 60 * 60;
+/// Shortest channel max age; anything lower would race clients still rendering the message.
+const MIN_RETENTION_AGE_SECONDS: u64 = 60;
+
+/// Sets how long the channel keeps messages. Requires `MANAGE_CHANNELS`; the next purge
+/// applies the new limits to existing history.
+pub async fn set_channel_retention(
+    ctx: &ApiContext,
+    user_id: UserId,
+    channel_id: ChannelId,
+    retention: RetentionPolicy,
+) -> Result<ChannelSummary, ApiError> {
+    let existing = load_channel(ctx, channel_id).await?;
+    permissions::resolve(ctx, user_id, existing.guild_id, Some(channel_id))
+        .await?
+        .require(Permissions::MANAGE_CHANNELS, "change retention")?;
+    if retention
+        .max_age_seconds
+        .is_some_and(|seconds| seconds < MIN_RETENTION_AGE_SECONDS || seconds > i64::MAX as u64)
+    {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            format!("max age must be at least {MIN_RETENTION_AGE_SECONDS} seconds"),
+        ));
+    }
+    if retention
+        .max_messages
+        .is_some_and(|count| count == 0 || count > i64::MAX as u64)
+    {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "max messages must be at least 1",
+        ));
+    }
+    ctx.storage
+        .set_channel_retention(channel_id, retention)
+        .await
+        .map_err(internal)?;
+    audit::record(
+        ctx,
+        NewAuditEntry {
+            target_channel_id: Some(channel_id),
+            details: Some(describe(retention)),
+            ..NewAuditEntry::new(existing.guild_id, user_id, AuditAction::ChannelUpdated)
+        },
+    )
+    .await?;
+    channel_summary(ctx, channel_id).await
+}
+
+/// Runs one retention purge and returns the events that tell clients what disappeared.
+pub async fn purge_expired(ctx: &ApiContext) -> anyhow::Result<(RetentionPurge, Vec<ServerEvent>)> {
+    let purge = ctx.storage.purge_expired(Utc::now()).await?;
+    let events = purge
+        .messages
+        .iter()
+        .map(
+            |(guild_id, channel_id, message_ids)| ServerEvent::MessagesExpired {
+                guild_id: *guild_id,
+                channel_id: *channel_id,
+                message_ids: message_ids.clone(),
+            },
+        )
+        .collect();
+    Ok((purge, events))
+}
+
+/// Turns a sender's timer into an absolute expiry, rejecting zero and overlong timers.
+pub(super) fn message_expiry(
+    expires_in_seconds: Option<u64>,
+) -> Result<Option<DateTime<Utc>>, ApiError> {
+    let Some(seconds) = expires_in_seconds else {
+        return Ok(None);
+    };
+    if seconds == 0 || seconds > MAX_MESSAGE_TTL_SECONDS {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            format!("message timers must be 1-{MAX_MESSAGE_TTL_SECONDS} seconds"),
+        ));
+    }
+    Ok(Some(Utc::now() + Duration::seconds(seconds as i64)))
+}
+
+fn describe(retention: RetentionPolicy) -> String {
+    match (retention.max_age_seconds, retention.max_messages) {
+        (None, None) => "retention: unlimited".to_string(),
+        (Some(age), None) => format!("retention: {age}s"),
+        (None, Some(count)) => format!("retention: {count} messages"),
+        (Some(age), Some(count)) => format!("retention: {age}s, {count} messages"),
+    }
+}
@@ -1,4 +1,8 @@
 use super::*;
+use shared::{
+    domain::{FileId, Role},
+    protocol::{AuditLogFilter, CreateDirectChannelRequest, CreateThreadRequest, RetentionPolicy},
+};
 
 async fn setup() -> (ApiContext, UserId, GuildId, ChannelId) {
     let storage = Storage::new("sqlite::memory:").await.expect("db");
@@ -8,6 +12,9 @@ async fn setup() -> (ApiContext, UserId, GuildId, ChannelId) {
         .create_channel(guild, "voice", ChannelKind::Voice)
         .await
         .expect("channel");
+    let transparency = KeyTransparencyLog::load_or_create(&storage)
+        .await
+        .expect("transparency log");
     (
         ApiContext {
             storage,
@@ -16,6 +23,8 @@ async fn setup() -> (ApiContext, UserId, GuildId, ChannelId) {
                 api_secret: "s".into(),
                 ttl_seconds: 60,
             },
+            transparency,
+            limits: Limits::default(),
         },
         user,
         guild,
@@ -43,9 +52,17 @@ async fn muted_user_cannot_send_messages() {
         .add_membership(guild, user, Role::Member, false, true)
         .await
         .expect("membership");
-    let err = send_message(&ctx, user, guild, channel, "b2theA==", None)
-        .await
-        .expect_err("should fail");
+    let err = send_message(
+        &ctx,
+        user,
+        guild,
+        channel,
+        MessageOptions::default(),
+        "b2theA==",
+        None,
+    )
+    .await
+    .expect_err("should fail");
     assert!(matches!(err.code, ErrorCode::Forbidden));
 }
 
@@ -83,9 +100,10 @@ async fn send_and_list_message_with_attachment() {
         user,
         guild,
         channel,
+        MessageOptions::default(),
         "aGVsbG8=",
         Some(AttachmentPayload {
-            file_id,
+            file_id: file_id.clone(),
             filename: "blob.bin".to_string(),
             size_bytes: 10,
             mime_type: Some("application/octet-stream".to_string()),
@@ -141,8 +159,1153 @@ async fn send_message_rejects_channel_from_another_guild() {
         .await
         .expect("guild");
 
-    let err = send_message(&ctx, user, other_guild, channel, "aGVsbG8=", None)
+    let err = send_message(
+        &ctx,
+        user,
+        other_guild,
+        channel,
+        MessageOptions::default(),
+        "aGVsbG8=",
+        None,
+    )
+    .await
+    .expect_err("should fail");
+    assert!(matches!(err.code, ErrorCode::Validation));
+}
+
+#[tokio::test]
+async fn custom_roles_grant_permissions_within_hierarchy() {
+    let (ctx, owner, guild, _) = setup().await;
+    let bob = ctx.storage.create_user("bob").await.expect("user");
+    ctx.storage
+        .add_membership(guild, bob, Role::Member, false, false)
         .await
-        .expect_err("should fail");
+        .expect("membership");
+    let role_request = |name: &str, position, permissions| CreateRoleRequest {
+        name: name.to_string(),
+        color: 0,
+        position,
+        permissions,
+    };
+
+    let err = create_role(
+        &ctx,
+        bob,
+        guild,
+        role_request("sneaky", 1, Permissions::NONE),
+    )
+    .await
+    .expect_err("members cannot manage roles");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+
+    let managers = create_role(
+        &ctx,
+        owner,
+        guild,
+        role_request(
+            "managers",
+            5,
+            Permissions::MANAGE_ROLES | Permissions::MUTE_MEMBERS,
+        ),
+    )
+    .await
+    .expect("owner creates role");
+    set_member_role(&ctx, owner, guild, bob, managers.role_id, true)
+        .await
+        .expect("assign");
+    let granted = permissions::compute(&ctx, bob, guild, None)
+        .await
+        .expect("compute");
+    assert!(granted.contains(Permissions::MANAGE_ROLES | Permissions::SEND_MESSAGES));
+    assert!(!granted.contains(Permissions::BAN_MEMBERS));
+
+    create_role(
+        &ctx,
+        bob,
+        guild,
+        role_request("helpers", 1, Permissions::MUTE_MEMBERS),
+    )
+    .await
+    .expect("lower role with held permissions");
+    for request in [
+        role_request("peers", 5, Permissions::NONE),
+        role_request("banners", 1, Permissions::BAN_MEMBERS),
+    ] {
+        let err = create_role(&ctx, bob, guild, request)
+            .await
+            .expect_err("hierarchy violation");
+        assert!(matches!(err.code, ErrorCode::Forbidden));
+    }
+    let err = delete_role(&ctx, bob, guild, managers.role_id)
+        .await
+        .expect_err("cannot delete own top role");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+
+    let members = list_members(&ctx, owner, guild).await.expect("members");
+    let bob_member = members.iter().find(|m| m.user_id == bob).expect("bob");
+    assert_eq!(bob_member.role_ids, vec![managers.role_id]);
+}
+
+#[tokio::test]
+async fn moderators_rank_above_unranked_roles_and_role_changes_respect_member_rank() {
+    let (ctx, owner, guild, _) = setup().await;
+    let dave = ctx.storage.create_user("dave").await.expect("user");
+    let bob = ctx.storage.create_user("bob").await.expect("user");
+    ctx.storage
+        .add_membership(guild, dave, Role::Mod, false, false)
+        .await
+        .expect("membership");
+    ctx.storage
+        .add_membership(guild, bob, Role::Member, false, false)
+        .await
+        .expect("membership");
+    let role_request = |name: &str, position, permissions| CreateRoleRequest {
+        name: name.to_string(),
+        color: 0,
+        position,
+        permissions,
+    };
+
+    let role_managers = create_role(
+        &ctx,
+        owner,
+        guild,
+        role_request("role managers", 0, Permissions::MANAGE_ROLES),
+    )
+    .await
+    .expect("owner creates role");
+    set_member_role(&ctx, owner, guild, dave, role_managers.role_id, true)
+        .await
+        .expect("assign to moderator");
+
+    // A moderator whose only custom role sits at position 0 still manages unranked roles.
+    let greeters = create_role(
+        &ctx,
+        dave,
+        guild,
+        role_request("greeters", 0, Permissions::NONE),
+    )
+    .await
+    .expect("moderator creates unranked role");
+    let err = create_role(
+        &ctx,
+        dave,
+        guild,
+        role_request("ranked", 1, Permissions::NONE),
+    )
+    .await
+    .expect_err("role above the moderator rank");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    set_member_role(&ctx, dave, guild, bob, greeters.role_id, true)
+        .await
+        .expect("moderator assigns to member");
+
+    // A member with a higher custom role still cannot change the roles of those above them.
+    let veterans = create_role(
+        &ctx,
+        owner,
+        guild,
+        role_request("veterans", 10, Permissions::MANAGE_ROLES),
+    )
+    .await
+    .expect("owner creates role");
+    set_member_role(&ctx, owner, guild, bob, veterans.role_id, true)
+        .await
+        .expect("assign to member");
+    for (target, role_id, assigned) in [
+        (dave, role_managers.role_id, false),
+        (owner, greeters.role_id, true),
+    ] {
+        let err = set_member_role(&ctx, bob, guild, target, role_id, assigned)
+            .await
+            .expect_err("target at or above the caller's rank");
+        assert!(matches!(err.code, ErrorCode::Forbidden));
+    }
+    set_member_role(&ctx, bob, guild, bob, greeters.role_id, false)
+        .await
+        .expect("members may drop their own lower roles");
+
+    let members = list_members(&ctx, owner, guild).await.expect("members");
+    let dave_member = members.iter().find(|m| m.user_id == dave).expect("dave");
+    assert_eq!(dave_member.role_ids, vec![role_managers.role_id]);
+}
+
+#[tokio::test]
+async fn muted_member_loses_send_and_speak_but_keeps_custom_grants() {
+    let (ctx, owner, guild, _) = setup().await;
+    let bob = ctx.storage.create_user("bob").await.expect("user");
+    ctx.storage
+        .add_membership(guild, bob, Role::Member, false, true)
+        .await
+        .expect("membership");
+    let role = create_role(
+        &ctx,
+        owner,
+        guild,
+        CreateRoleRequest {
+            name: "streamers".to_string(),
+            color: 0x3366ff,
+            position: 1,
+            permissions: Permissions::STREAM | Permissions::SPEAK,
+        },
+    )
+    .await
+    .expect("role");
+    set_member_role(&ctx, owner, guild, bob, role.role_id, true)
+        .await
+        .expect("assign");
+
+    let granted = permissions::compute(&ctx, bob, guild, None)
+        .await
+        .expect("compute");
+    assert!(granted.contains(Permissions::STREAM));
+    assert!(!granted.contains(Permissions::SPEAK));
+    assert!(!granted.contains(Permissions::SEND_MESSAGES));
+}
+
+#[tokio::test]
+async fn private_channel_is_hidden_from_members_without_view_overwrite() {
+    let (ctx, owner, guild, channel) = setup().await;
+    let bob = ctx.storage.create_user("bob").await.expect("user");
+    let carol = ctx.storage.create_user("carol").await.expect("user");
+    for member in [bob, carol] {
+        ctx.storage
+            .add_membership(guild, member, Role::Member, false, false)
+            .await
+            .expect("membership");
+    }
+    let staff = create_role(
+        &ctx,
+        owner,
+        guild,
+        CreateRoleRequest {
+            name: "staff".to_string(),
+            color: 0,
+            position: 1,
+            permissions: Permissions::NONE,
+        },
+    )
+    .await
+    .expect("role");
+    set_member_role(&ctx, owner, guild, carol, staff.role_id, true)
+        .await
+        .expect("assign");
+
+    for overwrite in [
+        PermissionOverwrite {
+            target: OverwriteTarget::Everyone,
+            allow: Permissions::NONE,
+            deny: Permissions::VIEW_CHANNEL,
+        },
+        PermissionOverwrite {
+            target: OverwriteTarget::Role(staff.role_id),
+            allow: Permissions::VIEW_CHANNEL,
+            deny: Permissions::NONE,
+        },
+    ] {
+        let summary = set_channel_overwrite(&ctx, owner, channel, overwrite)
+            .await
+            .expect("overwrite");
+        assert!(summary.private);
+    }
+    let err = set_channel_overwrite(
+        &ctx,
+        bob,
+        channel,
+        PermissionOverwrite {
+            target: OverwriteTarget::Member(bob),
+            allow: Permissions::VIEW_CHANNEL,
+            deny: Permissions::NONE,
+        },
+    )
+    .await
+    .expect_err("bob cannot manage the channel");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+
+    assert!(list_channels(&ctx, bob, guild)
+        .await
+        .expect("bob channels")
+        .is_empty());
+    let err = list_messages(&ctx, bob, channel, 10, None)
+        .await
+        .expect_err("bob cannot read");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    assert_eq!(
+        list_channels(&ctx, carol, guild)
+            .await
+            .expect("carol channels")
+            .len(),
+        1
+    );
+    let viewers: Vec<UserId> = list_channel_members(&ctx, carol, channel)
+        .await
+        .expect("viewers")
+        .into_iter()
+        .map(|member| member.user_id)
+        .collect();
+    assert!(viewers.contains(&owner) && viewers.contains(&carol));
+    assert!(!viewers.contains(&bob));
+
+    delete_role(&ctx, owner, guild, staff.role_id)
+        .await
+        .expect("delete role");
+    let err = list_messages(&ctx, carol, channel, 10, None)
+        .await
+        .expect_err("carol loses access with the role");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+
+    let (_, viewers) = delete_channel(&ctx, owner, channel)
+        .await
+        .expect("delete channel");
+    assert_eq!(viewers, vec![owner]);
+}
+
+#[tokio::test]
+async fn guild_and_channel_management_requires_permissions() {
+    let (ctx, owner, _, _) = setup().await;
+    let bob = ctx.storage.create_user("bob").await.expect("user");
+
+    let guild = create_guild(
+        &ctx,
+        owner,
+        CreateGuildRequest {
+            name: "  studio ".to_string(),
+        },
+    )
+    .await
+    .expect("create guild");
+    assert_eq!(guild.name, "studio");
+    ctx.storage
+        .add_membership(guild.guild_id, bob, Role::Member, false, false)
+        .await
+        .expect("membership");
+    let general = list_channels(&ctx, owner, guild.guild_id)
+        .await
+        .expect("channels");
+    assert_eq!(general.len(), 1);
+
+    let err = rename_guild(
+        &ctx,
+        bob,
+        guild.guild_id,
+        UpdateGuildRequest {
+            name: "mine".to_string(),
+        },
+    )
+    .await
+    .expect_err("bob cannot rename");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let renamed = rename_guild(
+        &ctx,
+        owner,
+        guild.guild_id,
+        UpdateGuildRequest {
+            name: "records".to_string(),
+        },
+    )
+    .await
+    .expect("rename");
+    assert_eq!(renamed.name, "records");
+
+    let voice = create_channel(
+        &ctx,
+        owner,
+        guild.guild_id,
+        CreateChannelRequest {
+            name: "booth".to_string(),
+            kind: ChannelKind::Voice,
+            topic: Some("live takes".to_string()),
+            category: Some("recording".to_string()),
+        },
+    )
+    .await
+    .expect("create channel");
+    assert_eq!(voice.position, 1);
+    assert_eq!(voice.category.as_deref(), Some("recording"));
+    let err = create_channel(
+        &ctx,
+        bob,
+        guild.guild_id,
+        CreateChannelRequest {
+            name: "spam".to_string(),
+            kind: ChannelKind::Text,
+            topic: None,
+            category: None,
+        },
+    )
+    .await
+    .expect_err("bob cannot create channels");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+
+    let updated = update_channel(
+        &ctx,
+        owner,
+        voice.channel_id,
+        UpdateChannelRequest {
+            topic: Some(String::new()),
+            ..UpdateChannelRequest::default()
+        },
+    )
+    .await
+    .expect("clear topic");
+    assert_eq!(updated.name, "booth");
+    assert_eq!(updated.topic, None);
+    assert_eq!(updated.category.as_deref(), Some("recording"));
+
+    let reordered = reorder_channels(
+        &ctx,
+        owner,
+        guild.guild_id,
+        ReorderChannelsRequest {
+            channel_ids: vec![voice.channel_id, general[0].channel_id],
+        },
+    )
+    .await
+    .expect("reorder");
+    assert_eq!(reordered[0].channel_id, voice.channel_id);
+    assert_eq!(reordered[0].position, 0);
+
+    let err = delete_channel(&ctx, bob, voice.channel_id)
+        .await
+        .expect_err("bob cannot delete channels");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let (deleted_from, viewers) = delete_channel(&ctx, owner, voice.channel_id)
+        .await
+        .expect("delete channel");
+    assert_eq!(deleted_from, guild.guild_id);
+    assert!(viewers.contains(&owner) && viewers.contains(&bob));
+
+    let confirm = || DeleteGuildRequest {
+        confirm_name: "records".to_string(),
+    };
+    let err = delete_guild(&ctx, bob, guild.guild_id, confirm())
+        .await
+        .expect_err("only the owner deletes");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let members = delete_guild(&ctx, owner, guild.guild_id, confirm())
+        .await
+        .expect("delete guild");
+    assert!(members.contains(&owner) && members.contains(&bob));
+    assert!(list_guilds(&ctx, owner)
+        .await
+        .expect("guilds")
+        .iter()
+        .all(|g| g.guild_id != guild.guild_id));
+}
+
+#[tokio::test]
+async fn owner_transfers_guild_before_leaving_and_confirms_deletion() {
+    let (ctx, owner, guild, channel) = setup().await;
+    let bob = ctx.storage.create_user("bob").await.expect("user");
+    ctx.storage
+        .add_membership(guild, bob, Role::Member, false, false)
+        .await
+        .expect("membership");
+
+    let err = leave_guild(&ctx, owner, guild)
+        .await
+        .expect_err("owner must transfer first");
+    assert!(matches!(err.code, ErrorCode::Validation));
+    let err = transfer_ownership(
+        &ctx,
+        bob,
+        guild,
+        TransferOwnershipRequest {
+            new_owner_id: owner,
+        },
+    )
+    .await
+    .expect_err("only the owner transfers");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+
+    let members = transfer_ownership(
+        &ctx,
+        owner,
+        guild,
+        TransferOwnershipRequest { new_owner_id: bob },
+    )
+    .await
+    .expect("transfer");
+    let role_of = |user_id: UserId| {
+        members
+            .iter()
+            .find(|m| m.user_id == user_id)
+            .map(|m| m.role)
+    };
+    assert_eq!(role_of(bob), Some(Role::Owner));
+    assert_eq!(role_of(owner), Some(Role::Mod));
+
+    let remaining = leave_guild(&ctx, owner, guild).await.expect("leave");
+    assert_eq!(
+        remaining.iter().map(|m| m.user_id).collect::<Vec<_>>(),
+        vec![bob]
+    );
+    let err = list_messages(&ctx, owner, channel, 10, None)
+        .await
+        .expect_err("former member cannot read");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+
+    let err = delete_guild(
+        &ctx,
+        bob,
+        guild,
+        DeleteGuildRequest {
+            confirm_name: "not the guild".to_string(),
+        },
+    )
+    .await
+    .expect_err("confirmation must match");
+    assert!(matches!(err.code, ErrorCode::Validation));
+    delete_guild(
+        &ctx,
+        bob,
+        guild,
+        DeleteGuildRequest {
+            confirm_name: "guild".to_string(),
+        },
+    )
+    .await
+    .expect("delete");
+    assert!(ctx
+        .storage
+        .channel(channel)
+        .await
+        .expect("lookup")
+        .is_none());
+}
+
+#[tokio::test]
+async fn moderation_respects_rank_and_is_recorded_in_audit_log() {
+    let (ctx, owner, guild, _) = setup().await;
+    let bob = ctx.storage.create_user("bob").await.expect("user");
+    let carol = ctx.storage.create_user("carol").await.expect("user");
+    ctx.storage
+        .add_membership(guild, bob, Role::Mod, false, false)
+        .await
+        .expect("membership");
+    ctx.storage
+        .add_membership(guild, carol, Role::Member, false, false)
+        .await
+        .expect("membership");
+    let moderation = |action, reason: Option<&str>| ModerationRequest {
+        action,
+        reason: reason.map(str::to_string),
+    };
+
+    let err = moderate_member(
+        &ctx,
+        carol,
+        guild,
+        bob,
+        moderation(ModerationAction::Kick, None),
+    )
+    .await
+    .expect_err("members cannot kick");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let err = moderate_member(
+        &ctx,
+        bob,
+        guild,
+        owner,
+        moderation(ModerationAction::Ban, None),
+    )
+    .await
+    .expect_err("owner cannot be banned");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+
+    moderate_member(
+        &ctx,
+        bob,
+        guild,
+        carol,
+        moderation(ModerationAction::Mute, Some("  spam  ")),
+    )
+    .await
+    .expect("mute");
+    moderate_member(
+        &ctx,
+        bob,
+        guild,
+        carol,
+        moderation(ModerationAction::Kick, None),
+    )
+    .await
+    .expect("kick");
+    assert_eq!(
+        ctx.storage
+            .membership_status(guild, carol)
+            .await
+            .expect("status"),
+        None
+    );
+
+    let invite = create_invite(&ctx, owner, guild).await.expect("invite");
+    assert_eq!(
+        join_with_invite(&ctx, carol, &invite.invite_code)
+            .await
+            .expect("join"),
+        guild
+    );
+    join_with_invite(&ctx, bob, &invite.invite_code)
+        .await
+        .expect("rejoin");
+    assert_eq!(
+        ctx.storage
+            .membership_status(guild, bob)
+            .await
+            .expect("status")
+            .map(|(role, _, _)| role),
+        Some(Role::Mod)
+    );
+    moderate_member(
+        &ctx,
+        owner,
+        guild,
+        carol,
+        moderation(ModerationAction::Ban, None),
+    )
+    .await
+    .expect("ban");
+    let err = join_with_invite(&ctx, carol, &invite.invite_code)
+        .await
+        .expect_err("banned users cannot rejoin");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    moderate_member(
+        &ctx,
+        owner,
+        guild,
+        carol,
+        moderation(ModerationAction::Unban, None),
+    )
+    .await
+    .expect("unban");
+    let err = moderate_member(
+        &ctx,
+        owner,
+        guild,
+        carol,
+        moderation(ModerationAction::Unban, None),
+    )
+    .await
+    .expect_err("nothing to unban");
+    assert!(matches!(err.code, ErrorCode::NotFound));
+
+    let err = audit::list_audit_log(&ctx, carol, guild, AuditLogFilter::default())
+        .await
+        .expect_err("non-members cannot read the audit log");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let entries = audit::list_audit_log(&ctx, bob, guild, AuditLogFilter::default())
+        .await
+        .expect("audit log");
+    assert_eq!(
+        entries.iter().map(|e| e.action).collect::<Vec<_>>(),
+        vec![
+            AuditAction::MemberUnbanned,
+            AuditAction::MemberBanned,
+            AuditAction::InviteCreated,
+            AuditAction::MemberKicked,
+            AuditAction::MemberMuted,
+        ]
+    );
+    let mutes = audit::list_audit_log(
+        &ctx,
+        bob,
+        guild,
+        AuditLogFilter {
+            action: Some(AuditAction::MemberMuted),
+            ..AuditLogFilter::default()
+        },
+    )
+    .await
+    .expect("filtered");
+    assert_eq!(mutes.len(), 1);
+    assert_eq!(mutes[0].actor_id, bob);
+    assert_eq!(mutes[0].target_user_id, Some(carol));
+    assert_eq!(mutes[0].reason.as_deref(), Some("spam"));
+}
+
+#[tokio::test]
+async fn direct_channels_are_limited_to_participants() {
+    let (ctx, alice, guild, _) = setup().await;
+    let bob = ctx.storage.create_user("bob").await.expect("user");
+    let carol = ctx.storage.create_user("carol").await.expect("user");
+    let request = |usernames: &[&str]| CreateDirectChannelRequest {
+        usernames: usernames.iter().map(|name| name.to_string()).collect(),
+    };
+
+    let err = direct::create_direct_channel(&ctx, alice, request(&["alice"]))
+        .await
+        .expect_err("needs someone else");
+    assert!(matches!(err.code, ErrorCode::Validation));
+    let err = direct::create_direct_channel(&ctx, alice, request(&["nobody"]))
+        .await
+        .expect_err("unknown user");
+    assert!(matches!(err.code, ErrorCode::NotFound));
+
+    let direct = direct::create_direct_channel(&ctx, alice, request(&["bob"]))
+        .await
+        .expect("open");
+    assert_eq!(direct.channel.kind, ChannelKind::Direct);
+    assert_eq!(
+        direct
+            .participants
+            .iter()
+            .map(|participant| participant.username.as_str())
+            .collect::<Vec<_>>(),
+        vec!["alice", "bob"]
+    );
+    let again = direct::create_direct_channel(&ctx, bob, request(&[" alice "]))
+        .await
+        .expect("reuse");
+    assert_eq!(again.channel.channel_id, direct.channel.channel_id);
+
+    let scope = direct.channel.guild_id;
+    let channel_id = direct.channel.channel_id;
+    send_message(
+        &ctx,
+        bob,
+        scope,
+        channel_id,
+        MessageOptions::default(),
+        "b2theA==",
+        None,
+    )
+    .await
+    .expect("participant sends");
+    assert_eq!(
+        list_messages(&ctx, alice, channel_id, 10, None)
+            .await
+            .expect("participant reads")
+            .len(),
+        1
+    );
+    let err = list_messages(&ctx, carol, channel_id, 10, None)
+        .await
+        .expect_err("outsider cannot read");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let err = create_invite(&ctx, alice, scope)
+        .await
+        .expect_err("no invites into a conversation");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let err = create_channel(
+        &ctx,
+        alice,
+        guild,
+        CreateChannelRequest {
+            name: "sneaky".to_string(),
+            kind: ChannelKind::Direct,
+            topic: None,
+            category: None,
+        },
+    )
+    .await
+    .expect_err("direct channels stay out of guilds");
+    assert!(matches!(err.code, ErrorCode::Validation));
+
+    assert!(list_guilds(&ctx, bob)
+        .await
+        .expect("guilds")
+        .iter()
+        .all(|summary| summary.guild_id != scope));
+    assert_eq!(
+        direct::list_direct_channels(&ctx, bob)
+            .await
+            .expect("list")
+            .len(),
+        1
+    );
+    assert!(direct::list_direct_channels(&ctx, carol)
+        .await
+        .expect("list")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn threads_keep_their_messages_and_reject_posts_once_archived() {
+    let (ctx, alice, guild, _) = setup().await;
+    let bob = ctx.storage.create_user("bob").await.expect("bob");
+    let carol = ctx.storage.create_user("carol").await.expect("carol");
+    for user in [bob, carol] {
+        ctx.storage
+            .add_membership(guild, user, Role::Member, false, false)
+            .await
+            .expect("membership");
+    }
+    let channel = ctx
+        .storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+    let ServerEvent::MessageReceived { message: parent } = send_message(
+        &ctx,
+        bob,
+        guild,
+        channel,
+        MessageOptions::default(),
+        "cGFyZW50",
+        None,
+    )
+    .await
+    .expect("parent") else {
+        panic!("expected message event");
+    };
+
+    let thread = threads::create_thread(
+        &ctx,
+        bob,
+        channel,
+        CreateThreadRequest {
+            parent_message_id: parent.message_id,
+            name: " follow-up ".to_string(),
+        },
+    )
+    .await
+    .expect("thread");
+    assert_eq!(thread.name, "follow-up");
+    let err = threads::create_thread(
+        &ctx,
+        carol,
+        channel,
+        CreateThreadRequest {
+            parent_message_id: parent.message_id,
+            name: "again".to_string(),
+        },
+    )
+    .await
+    .expect_err("one thread per message");
+    assert!(matches!(err.code, ErrorCode::Validation));
+
+    let ServerEvent::MessageReceived { message: reply } = send_message(
+        &ctx,
+        carol,
+        guild,
+        channel,
+        MessageOptions {
+            thread_id: Some(thread.thread_id),
+            ..MessageOptions::default()
+        },
+        "cmVwbHk=",
+        None,
+    )
+    .await
+    .expect("reply") else {
+        panic!("expected message event");
+    };
+    assert_eq!(reply.thread_id, Some(thread.thread_id));
+    let err = threads::create_thread(
+        &ctx,
+        carol,
+        channel,
+        CreateThreadRequest {
+            parent_message_id: reply.message_id,
+            name: "nested".to_string(),
+        },
+    )
+    .await
+    .expect_err("no threads from thread messages");
+    assert!(matches!(err.code, ErrorCode::Validation));
+    assert_eq!(
+        list_messages(&ctx, alice, channel, 10, None)
+            .await
+            .expect("channel history")
+            .len(),
+        1
+    );
+    assert_eq!(
+        threads::list_thread_messages(&ctx, alice, thread.thread_id, 10, None)
+            .await
+            .expect("thread history")
+            .iter()
+            .map(|message| message.message_id)
+            .collect::<Vec<_>>(),
+        vec![reply.message_id]
+    );
+
+    let err = threads::set_thread_archived(&ctx, carol, thread.thread_id, true)
+        .await
+        .expect_err("only the creator or a moderator archives");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let archived = threads::set_thread_archived(&ctx, alice, thread.thread_id, true)
+        .await
+        .expect("owner archives");
+    assert!(archived.archived);
+    let err = send_message(
+        &ctx,
+        carol,
+        guild,
+        channel,
+        MessageOptions {
+            thread_id: Some(thread.thread_id),
+            ..MessageOptions::default()
+        },
+        "bGF0ZQ==",
+        None,
+    )
+    .await
+    .expect_err("archived threads are read-only");
+    assert!(matches!(err.code, ErrorCode::Validation));
+    assert!(threads::list_threads(&ctx, carol, channel, false)
+        .await
+        .expect("open threads")
+        .is_empty());
+    assert!(
+        !threads::set_thread_archived(&ctx, bob, thread.thread_id, false)
+            .await
+            .expect("creator unarchives")
+            .archived
+    );
+}
+
+#[tokio::test]
+async fn retention_needs_manage_channels_and_timers_are_bounded() {
+    let (ctx, alice, guild, _) = setup().await;
+    let bob = ctx.storage.create_user("bob").await.expect("bob");
+    ctx.storage
+        .add_membership(guild, bob, Role::Member, false, false)
+        .await
+        .expect("membership");
+    let channel = ctx
+        .storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+    let policy = RetentionPolicy {
+        max_age_seconds: Some(3600),
+        max_messages: Some(100),
+    };
+
+    let err = retention::set_channel_retention(&ctx, bob, channel, policy)
+        .await
+        .expect_err("members cannot change retention");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let err = retention::set_channel_retention(
+        &ctx,
+        alice,
+        channel,
+        RetentionPolicy {
+            max_age_seconds: Some(5),
+            max_messages: None,
+        },
+    )
+    .await
+    .expect_err("max age too short");
+    assert!(matches!(err.code, ErrorCode::Validation));
+    let summary = retention::set_channel_retention(&ctx, alice, channel, policy)
+        .await
+        .expect("owner sets retention");
+    assert_eq!(summary.retention, policy);
+
+    let err = send_message(
+        &ctx,
+        bob,
+        guild,
+        channel,
+        MessageOptions {
+            expires_in_seconds: Some(0),
+            ..MessageOptions::default()
+        },
+        "aGVsbG8=",
+        None,
+    )
+    .await
+    .expect_err("zero timer");
+    assert!(matches!(err.code, ErrorCode::Validation));
+    let ServerEvent::MessageReceived { message } = send_message(
+        &ctx,
+        bob,
+        guild,
+        channel,
+        MessageOptions {
+            expires_in_seconds: Some(300),
+            ..MessageOptions::default()
+        },
+        "aGVsbG8=",
+        None,
+    )
+    .await
+    .expect("timed message") else {
+        panic!("expected message event");
+    };
+    assert!(message.expires_at.is_some_and(|at| at > message.sent_at));
+    let (purge, events) = retention::purge_expired(&ctx).await.expect("purge");
+    assert!(purge.messages.is_empty());
+    assert!(events.is_empty());
+}
+
+#[tokio::test]
+async fn uploads_over_quota_are_rejected_and_usage_needs_manage_guild() {
+    let (ctx, alice, guild, _) = setup().await;
+    let ctx = ApiContext {
+        storage: ctx
+            .storage
+            .clone()
+            .with_storage_quotas(storage::StorageQuotas {
+                guild_bytes: Some(100),
+                user_bytes: None,
+            }),
+        ..ctx
+    };
+    let bob = ctx.storage.create_user("bob").await.expect("bob");
+    ctx.storage
+        .add_membership(guild, bob, Role::Member, false, false)
+        .await
+        .expect("membership");
+    let channel = ctx
+        .storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+
+    let opened = uploads::create_upload(&ctx, bob, guild, channel, None, None, 80)
+        .await
+        .expect("fits the quota");
+    let err = uploads::create_upload(&ctx, alice, guild, channel, None, None, 30)
+        .await
+        .expect_err("open uploads reserve their size");
+    assert!(matches!(err.code, ErrorCode::QuotaExceeded));
+    assert!(err.message.contains("guild storage quota"));
+
+    let err = uploads::guild_storage_usage(&ctx, bob, guild)
+        .await
+        .expect_err("members cannot see storage usage");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let usage = uploads::guild_storage_usage(&ctx, alice, guild)
+        .await
+        .expect("owner sees storage usage");
+    assert_eq!(usage.guild.reserved_bytes, opened.total_bytes);
+    assert_eq!(usage.guild.quota_bytes, Some(100));
+    assert_eq!(usage.user.quota_bytes, None);
+    assert_eq!(usage.user.used_bytes + usage.user.reserved_bytes, 0);
+}
+
+#[tokio::test]
+async fn files_are_scoped_to_their_channel_and_have_opaque_ids() {
+    let (ctx, alice, guild, _) = setup().await;
+    let bob = ctx.storage.create_user("bob").await.expect("bob");
+    ctx.storage
+        .add_membership(guild, bob, Role::Member, false, false)
+        .await
+        .expect("membership");
+    let general = ctx
+        .storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("general");
+    let staff = ctx
+        .storage
+        .create_channel(guild, "staff", ChannelKind::Text)
+        .await
+        .expect("staff");
+    set_channel_overwrite(
+        &ctx,
+        alice,
+        staff,
+        PermissionOverwrite {
+            target: OverwriteTarget::Everyone,
+            allow: Permissions::NONE,
+            deny: Permissions::VIEW_CHANNEL,
+        },
+    )
+    .await
+    .expect("overwrite");
+    let file_id = ctx
+        .storage
+        .store_file_ciphertext(alice, guild, staff, b"ciphertext", None, Some("a.bin"))
+        .await
+        .expect("file");
+    assert_eq!(file_id.0.len(), 32);
+    assert!(file_id.0.chars().all(|c| c.is_ascii_hexdigit()));
+
+    let file = uploads::authorize_download(&ctx, alice, &file_id)
+        .await
+        .expect("owner views staff");
+    assert_eq!(file.channel_id, staff);
+    let err = uploads::authorize_download(&ctx, bob, &file_id)
+        .await
+        .expect_err("bob cannot view staff");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let err = uploads::authorize_download(&ctx, bob, &FileId("1".to_string()))
+        .await
+        .expect_err("sequential ids resolve to nothing");
+    assert!(matches!(err.code, ErrorCode::NotFound));
+
+    let err = send_message(
+        &ctx,
+        alice,
+        guild,
+        general,
+        MessageOptions::default(),
+        "aGVsbG8=",
+        Some(AttachmentPayload {
+            file_id,
+            filename: "a.bin".to_string(),
+            size_bytes: 10,
+            mime_type: None,
+        }),
+    )
+    .await
+    .expect_err("another channel's file");
+    assert!(matches!(err.code, ErrorCode::Validation));
+}
+
+#[tokio::test]
+async fn thumbnails_link_to_the_callers_own_attachment_only() {
+    let (ctx, alice, guild, _) = setup().await;
+    let bob = ctx.storage.create_user("bob").await.expect("bob");
+    ctx.storage
+        .add_membership(guild, bob, Role::Member, false, false)
+        .await
+        .expect("membership");
+    let channel = ctx
+        .storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+    let store = |user: UserId, bytes: Vec<u8>| {
+        let storage = ctx.storage.clone();
+        async move {
+            storage
+                .store_file_ciphertext(user, guild, channel, &bytes, None, None)
+                .await
+                .expect("file")
+        }
+    };
+    let photo = store(alice, b"photo".to_vec()).await;
+    let thumbnail = store(alice, b"thumb".to_vec()).await;
+    let bobs = store(bob, b"bob's thumb".to_vec()).await;
+    let oversized = store(alice, vec![0; uploads::MAX_THUMBNAIL_BYTES as usize + 1]).await;
+
+    let err = uploads::link_thumbnail(&ctx, bob, &photo, &bobs)
+        .await
+        .expect_err("not bob's attachment");
+    assert!(matches!(err.code, ErrorCode::Forbidden));
+    let err = uploads::link_thumbnail(&ctx, alice, &photo, &oversized)
+        .await
+        .expect_err("too large");
+    assert!(matches!(err.code, ErrorCode::Validation));
+    uploads::link_thumbnail(&ctx, alice, &photo, &thumbnail)
+        .await
+        .expect("link");
+    let err = uploads::link_thumbnail(&ctx, alice, &photo, &thumbnail)
+        .await
+        .expect_err("already linked");
+    assert!(matches!(err.code, ErrorCode::Conflict));
+
+    let err = send_message(
+        &ctx,
+        alice,
+        guild,
+        channel,
+        MessageOptions::default(),
+        "aGVsbG8=",
+        Some(AttachmentPayload {
+            file_id: thumbnail,
+            filename: "thumb.jpg".to_string(),
+            size_bytes: 5,
+            mime_type: None,
+        }),
+    )
+    .await
+    .expect_err("thumbnails are not attachments");
     assert!(matches!(err.code, ErrorCode::Validation));
 }
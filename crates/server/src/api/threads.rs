@@ -0,0 +1,130 @@
+use shared::{
+    domain::{ChannelId, ChannelKind, Permissions, ThreadId, UserId},
+    error::{ApiError, ErrorCode},
+    protocol::{CreateThreadRequest, MessagePayload, ThreadSummary},
+};
+
+use super::{
+    ensure_active_membership_in_channel, internal, load_channel, message_payloads, permissions,
+    validate_name, ApiContext,
+};
+
+/// Starts a thread on a message in `channel_id`. Each message anchors at most one thread,
+/// and messages that are themselves in a thread cannot anchor another.
+pub async fn create_thread(
+    ctx: &ApiContext,
+    user_id: UserId,
+    channel_id: ChannelId,
+    request: CreateThreadRequest,
+) -> Result<ThreadSummary, ApiError> {
+    let channel = load_channel(ctx, channel_id).await?;
+    let member =
+        permissions::resolve(ctx, user_id, channel.guild_id, Some(channel.channel_id)).await?;
+    member.require(Permissions::SEND_MESSAGES, "start threads")?;
+    if channel.kind == ChannelKind::Voice {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "threads require a text channel",
+        ));
+    }
+    let name = validate_name(&request.name, "thread")?;
+    match ctx
+        .storage
+        .message_location(request.parent_message_id)
+        .await
+        .map_err(internal)?
+    {
+        Some((parent_channel_id, None)) if parent_channel_id == channel_id => {}
+        Some((parent_channel_id, Some(_))) if parent_channel_id == channel_id => {
+            return Err(ApiError::new(
+                ErrorCode::Validation,
+                "threads cannot be started from a thread message",
+            ));
+        }
+        _ => return Err(ApiError::new(ErrorCode::NotFound, "message not found")),
+    }
+    if ctx
+        .storage
+        .thread_for_message(request.parent_message_id)
+        .await
+        .map_err(internal)?
+        .is_some()
+    {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "message already has a thread",
+        ));
+    }
+
+    let thread_id = ctx
+        .storage
+        .create_thread(channel_id, request.parent_message_id, user_id, name)
+        .await
+        .map_err(internal)?;
+    load_thread(ctx, thread_id).await
+}
+
+/// Threads of a channel, newest first.
+pub async fn list_threads(
+    ctx: &ApiContext,
+    user_id: UserId,
+    channel_id: ChannelId,
+    include_archived: bool,
+) -> Result<Vec<ThreadSummary>, ApiError> {
+    let channel = load_channel(ctx, channel_id).await?;
+    ensure_active_membership_in_channel(ctx, user_id, channel.guild_id, channel_id).await?;
+    ctx.storage
+        .list_threads(channel_id, include_archived)
+        .await
+        .map_err(internal)
+}
+
+/// Archives or unarchives a thread. The creator may always do this; others need
+/// `MANAGE_MESSAGES`.
+pub async fn set_thread_archived(
+    ctx: &ApiContext,
+    user_id: UserId,
+    thread_id: ThreadId,
+    archived: bool,
+) -> Result<ThreadSummary, ApiError> {
+    let thread = load_thread(ctx, thread_id).await?;
+    let member =
+        permissions::resolve(ctx, user_id, thread.guild_id, Some(thread.channel_id)).await?;
+    if thread.creator_id != user_id {
+        member.require(Permissions::MANAGE_MESSAGES, "archive threads")?;
+    }
+    ctx.storage
+        .set_thread_archived(thread_id, archived)
+        .await
+        .map_err(internal)?;
+    load_thread(ctx, thread_id).await
+}
+
+/// Messages posted in the thread, oldest first. Pages back with `before` like channel history.
+pub async fn list_thread_messages(
+    ctx: &ApiContext,
+    user_id: UserId,
+    thread_id: ThreadId,
+    limit: u32,
+    before: Option<i64>,
+) -> Result<Vec<MessagePayload>, ApiError> {
+    let thread = load_thread(ctx, thread_id).await?;
+    ensure_active_membership_in_channel(ctx, user_id, thread.guild_id, thread.channel_id).await?;
+    let messages = ctx
+        .storage
+        .list_thread_messages(thread_id, limit, before)
+        .await
+        .map_err(internal)?;
+    message_payloads(ctx, messages).await
+}
+
+pub(super) async fn load_thread(
+    ctx: &ApiContext,
+    thread_id: ThreadId,
+) -> Result<ThreadSummary, ApiError> {
+    ctx.storage
+        .thread(thread_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "thread not found"))
+}
@@ -0,0 +1,323 @@
+use shared::{
+    domain::{ChannelId, FileId, GuildId, Permissions, UserId},
+    error::{ApiError, ErrorCode},
+    protocol::{GuildStorageUsage, StorageUsageSummary, UploadStatus},
+};
+use storage::{FileMetadata, QuotaExceeded, StorageUsage, UploadSession};
+
+use super::{internal, permissions, ApiContext};
+
+/// Largest body accepted for one chunk of a resumable upload.
+pub const MAX_UPLOAD_CHUNK_BYTES: usize = 8 * 1024 * 1024;
+/// Default for the largest attachment a resumable upload may declare.
+pub const MAX_UPLOAD_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+/// Default for the largest file that can be linked as a thumbnail.
+pub const MAX_THUMBNAIL_BYTES: u64 = 256 * 1024;
+const MAX_FILENAME_BYTES: usize = 180;
+
+/// Checks that `user_id` may attach files in the channel and that `filename` is a plain
+/// name. Shared by single-request uploads and upload sessions.
+pub async fn authorize_upload(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    filename: Option<&str>,
+) -> Result<(), ApiError> {
+    if let Some(name) = filename {
+        if name.len() > MAX_FILENAME_BYTES {
+            return Err(ApiError::new(ErrorCode::Validation, "filename is too long"));
+        }
+        if name.contains('/') || name.contains('\\') {
+            return Err(ApiError::new(
+                ErrorCode::Validation,
+                "filename must not contain path separators",
+            ));
+        }
+    }
+
+    permissions::resolve(ctx, user_id, guild_id, Some(channel_id))
+        .await?
+        .require(Permissions::ATTACH_FILES, "attach files")?;
+
+    let channel_guild = ctx
+        .storage
+        .guild_for_channel(channel_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "channel not found"))?;
+    if channel_guild != guild_id {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "channel does not belong to guild",
+        ));
+    }
+    Ok(())
+}
+
+/// Opens a resumable upload of `total_bytes` of ciphertext into the channel.
+pub async fn create_upload(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    filename: Option<&str>,
+    mime_type: Option<&str>,
+    total_bytes: u64,
+) -> Result<UploadStatus, ApiError> {
+    if total_bytes == 0 {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "attachment body cannot be empty",
+        ));
+    }
+    let max_upload_bytes = ctx.limits.max_upload_bytes;
+    if total_bytes > max_upload_bytes {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            format!("attachment exceeds {max_upload_bytes} bytes"),
+        ));
+    }
+    authorize_upload(ctx, user_id, guild_id, channel_id, filename).await?;
+    let session = ctx
+        .storage
+        .create_upload_session(
+            user_id,
+            guild_id,
+            channel_id,
+            total_bytes,
+            mime_type,
+            filename,
+        )
+        .await
+        .map_err(storage_error)?;
+    Ok(status(&session))
+}
+
+/// Reports how much of the caller's upload has arrived, so a client can resume after a
+/// dropped connection.
+pub async fn upload_status(
+    ctx: &ApiContext,
+    user_id: UserId,
+    upload_id: &str,
+) -> Result<UploadStatus, ApiError> {
+    Ok(status(&load_own_session(ctx, user_id, upload_id).await?))
+}
+
+/// Stores the next chunk. `offset` must equal the bytes received so far; anything else is
+/// a conflict, and the client should ask for the status and resume from there.
+pub async fn append_upload_chunk(
+    ctx: &ApiContext,
+    user_id: UserId,
+    upload_id: &str,
+    offset: u64,
+    bytes: &[u8],
+) -> Result<UploadStatus, ApiError> {
+    if bytes.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "chunk cannot be empty",
+        ));
+    }
+    if bytes.len() > MAX_UPLOAD_CHUNK_BYTES {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            format!("chunk exceeds {MAX_UPLOAD_CHUNK_BYTES} bytes"),
+        ));
+    }
+    let session = load_own_session(ctx, user_id, upload_id).await?;
+    let received = ctx
+        .storage
+        .append_upload_chunk(upload_id, offset, bytes)
+        .await
+        .map_err(internal)?;
+    match received {
+        Some(received_bytes) => Ok(UploadStatus {
+            received_bytes,
+            ..status(&session)
+        }),
+        None => {
+            let current = load_own_session(ctx, user_id, upload_id).await?;
+            let message = if offset == current.received_bytes {
+                format!(
+                    "chunk would exceed the declared {} bytes",
+                    current.total_bytes
+                )
+            } else {
+                format!("expected a chunk at offset {}", current.received_bytes)
+            };
+            Err(ApiError::new(ErrorCode::Conflict, message))
+        }
+    }
+}
+
+/// Turns a fully received upload into a file once `sha256` matches the stored bytes. The
+/// caller must still be allowed to attach files in the channel.
+pub async fn complete_upload(
+    ctx: &ApiContext,
+    user_id: UserId,
+    upload_id: &str,
+    sha256: &str,
+) -> Result<(FileId, u64), ApiError> {
+    let session = load_own_session(ctx, user_id, upload_id).await?;
+    if session.received_bytes != session.total_bytes {
+        return Err(ApiError::new(
+            ErrorCode::Conflict,
+            format!(
+                "upload is missing {} bytes",
+                session.total_bytes - session.received_bytes
+            ),
+        ));
+    }
+    authorize_upload(
+        ctx,
+        user_id,
+        session.guild_id,
+        session.channel_id,
+        session.filename.as_deref(),
+    )
+    .await?;
+    let received = ctx
+        .storage
+        .upload_sha256(upload_id)
+        .await
+        .map_err(internal)?;
+    if !received.eq_ignore_ascii_case(sha256.trim()) {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "sha256 does not match the uploaded bytes",
+        ));
+    }
+    let file_id = ctx
+        .storage
+        .complete_upload(upload_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "upload not found"))?;
+    Ok((file_id, session.total_bytes))
+}
+
+/// Loads a file for `user_id`, who must be able to view the channel it was uploaded to.
+pub async fn authorize_download(
+    ctx: &ApiContext,
+    user_id: UserId,
+    file_id: &FileId,
+) -> Result<FileMetadata, ApiError> {
+    let file = ctx
+        .storage
+        .load_file_metadata(file_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "file not found"))?;
+    permissions::resolve(ctx, user_id, file.guild_id, Some(file.channel_id)).await?;
+    Ok(file)
+}
+
+/// Links `thumbnail` to the attachment `parent` so the two are kept and deleted together.
+/// Both must be the caller's own uploads to the same channel.
+pub async fn link_thumbnail(
+    ctx: &ApiContext,
+    user_id: UserId,
+    parent: &FileId,
+    thumbnail: &FileId,
+) -> Result<(), ApiError> {
+    let parent_file = authorize_download(ctx, user_id, parent).await?;
+    let thumbnail_file = authorize_download(ctx, user_id, thumbnail).await?;
+    if parent_file.uploader_id != user_id || thumbnail_file.uploader_id != user_id {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "only the uploader can link a thumbnail",
+        ));
+    }
+    if parent_file.channel_id != thumbnail_file.channel_id {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            "thumbnail must be uploaded to the same channel",
+        ));
+    }
+    let max_thumbnail_bytes = ctx.limits.max_thumbnail_bytes;
+    if thumbnail_file.size_bytes > max_thumbnail_bytes {
+        return Err(ApiError::new(
+            ErrorCode::Validation,
+            format!("thumbnail exceeds {max_thumbnail_bytes} bytes"),
+        ));
+    }
+    let linked = ctx
+        .storage
+        .link_thumbnail(parent, thumbnail)
+        .await
+        .map_err(internal)?;
+    if !linked {
+        return Err(ApiError::new(
+            ErrorCode::Conflict,
+            "file already has a thumbnail or cannot be one",
+        ));
+    }
+    Ok(())
+}
+
+/// Reports the guild's attachment storage and the caller's own. Requires `MANAGE_GUILD`.
+pub async fn guild_storage_usage(
+    ctx: &ApiContext,
+    user_id: UserId,
+    guild_id: GuildId,
+) -> Result<GuildStorageUsage, ApiError> {
+    permissions::resolve(ctx, user_id, guild_id, None)
+        .await?
+        .require(Permissions::MANAGE_GUILD, "manage guild")?;
+    let quotas = ctx.storage.storage_quotas();
+    let guild = ctx
+        .storage
+        .guild_storage_usage(guild_id)
+        .await
+        .map_err(internal)?;
+    let user = ctx
+        .storage
+        .user_storage_usage(user_id)
+        .await
+        .map_err(internal)?;
+    Ok(GuildStorageUsage {
+        guild_id,
+        guild: usage_summary(guild, quotas.guild_bytes),
+        user: usage_summary(user, quotas.user_bytes),
+    })
+}
+
+/// Maps a storage failure to an API error, keeping quota rejections distinguishable.
+pub fn storage_error(error: anyhow::Error) -> ApiError {
+    match error.downcast_ref::<QuotaExceeded>() {
+        Some(exceeded) => ApiError::new(ErrorCode::QuotaExceeded, exceeded.to_string()),
+        None => internal(error),
+    }
+}
+
+fn usage_summary(usage: StorageUsage, quota_bytes: Option<u64>) -> StorageUsageSummary {
+    StorageUsageSummary {
+        used_bytes: usage.used_bytes,
+        reserved_bytes: usage.reserved_bytes,
+        quota_bytes,
+    }
+}
+
+/// Sessions belong to their uploader; other users get the same answer as for a missing id.
+async fn load_own_session(
+    ctx: &ApiContext,
+    user_id: UserId,
+    upload_id: &str,
+) -> Result<UploadSession, ApiError> {
+    ctx.storage
+        .load_upload_session(upload_id)
+        .await
+        .map_err(internal)?
+        .filter(|session| session.uploader_id == user_id)
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "upload not found"))
+}
+
+fn status(session: &UploadSession) -> UploadStatus {
+    UploadStatus {
+        upload_id: session.upload_id.clone(),
+        total_bytes: session.total_bytes,
+        received_bytes: session.received_bytes,
+        max_chunk_bytes: MAX_UPLOAD_CHUNK_BYTES as u64,
+    }
+}
@@ -1,9 +1,86 @@
-use crate::api::ApiContext;
-use shared::protocol::ServerEvent;
+use std::sync::Arc;
+
+use crate::{admin_auth::AdminToken, api::ApiContext, metrics::Metrics, rate_limit::RateLimiter};
+use shared::{
+    domain::{DeviceId, UserId},
+    error::{ApiError, ErrorCode},
+    protocol::ServerEvent,
+};
 use tokio::sync::broadcast;
 
 #[derive(Clone)]
 pub(crate) struct AppState {
     pub(crate) api: ApiContext,
-    pub(crate) events: broadcast::Sender<ServerEvent>,
+    pub(crate) events: broadcast::Sender<Dispatch>,
+    pub(crate) revocations: broadcast::Sender<Revocation>,
+    pub(crate) rate_limiter: RateLimiter,
+    pub(crate) metrics: Metrics,
+    pub(crate) admin_token: Option<AdminToken>,
+}
+
+impl AppState {
+    /// Queues an event for the WebSocket connections allowed to see it. Nobody listening is
+    /// not an error.
+    pub(crate) fn publish(&self, dispatch: impl Into<Dispatch>) {
+        let _ = self.events.send(dispatch.into());
+    }
+
+    /// Closes the live WebSocket connections `revocation` applies to.
+    pub(crate) fn revoke(&self, revocation: Revocation) {
+        let _ = self.revocations.send(revocation);
+    }
+}
+
+/// An event on its way to WebSocket connections. Most are scoped by the recipient's current
+/// permissions; deletions carry their `audience` instead, captured before the memberships
+/// and overwrites that would have scoped them were removed.
+#[derive(Debug, Clone)]
+pub(crate) struct Dispatch {
+    pub(crate) event: ServerEvent,
+    pub(crate) audience: Option<Arc<[UserId]>>,
+}
+
+impl Dispatch {
+    pub(crate) fn to(audience: Vec<UserId>, event: ServerEvent) -> Self {
+        Self {
+            event,
+            audience: Some(audience.into()),
+        }
+    }
+}
+
+impl From<ServerEvent> for Dispatch {
+    fn from(event: ServerEvent) -> Self {
+        Self {
+            event,
+            audience: None,
+        }
+    }
+}
+
+/// Access taken away after a WebSocket connected, which the connection has to end on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Revocation {
+    UserSuspended(UserId),
+    /// Also ends the user's connections that did not name a device, since any of them may
+    /// be the revoked one.
+    DeviceRevoked(UserId, DeviceId),
+}
+
+impl Revocation {
+    pub(crate) fn closes(self, user_id: UserId, device_id: Option<DeviceId>) -> bool {
+        match self {
+            Self::UserSuspended(suspended) => suspended == user_id,
+            Self::DeviceRevoked(owner, revoked) => {
+                owner == user_id && device_id.is_none_or(|device_id| device_id == revoked)
+            }
+        }
+    }
+
+    pub(crate) fn reason(self) -> ApiError {
+        match self {
+            Self::UserSuspended(_) => ApiError::new(ErrorCode::Forbidden, "account is suspended"),
+            Self::DeviceRevoked(..) => ApiError::new(ErrorCode::Forbidden, "device is not active"),
+        }
+    }
 }
@@ -1,14 +1,32 @@
 use std::{
-    collections::HashMap,
+    fmt::Display,
     fs,
+    net::SocketAddr,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use anyhow::Context;
-use serde::Deserialize;
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use storage::{BlobStoreConfig, MaintenancePolicy, S3Config, StorageQuotas};
+use tracing_subscriber::EnvFilter;
 
-#[derive(Debug, Deserialize)]
+use crate::api::Limits;
+use crate::rate_limit::{RateLimit, RateLimits};
+use crate::tls::{self, TlsFiles};
+
+/// Read when no `--config` is given and the file exists.
+pub const DEFAULT_CONFIG_PATH: &str = "server.toml";
+/// Where `tls_self_signed` keeps its certificate when no TLS paths are set.
+const DEFAULT_SELF_SIGNED_DIR: &str = "./data/tls";
+const MIN_ADMIN_TOKEN_CHARS: usize = 16;
+
+/// Server settings. `server.toml` uses the field names as keys; unknown keys and values of
+/// the wrong type are rejected.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Settings {
+    #[serde(rename = "bind_addr")]
     pub server_bind: String,
     pub database_url: String,
     pub server_public_url: Option<String>,
@@ -16,85 +34,645 @@ pub struct Settings {
     pub livekit_api_secret: String,
     pub livekit_url: Option<String>,
     pub livekit_ttl_seconds: i64,
+    pub retention_purge_interval_seconds: u64,
+    pub maintenance_interval_seconds: u64,
+    pub consumed_welcome_ttl_seconds: i64,
+    pub key_package_ttl_seconds: i64,
+    pub device_link_ttl_seconds: i64,
+    pub orphan_file_ttl_seconds: i64,
+    pub upload_session_ttl_seconds: i64,
+    pub maintenance_compact: bool,
+    /// `database`, `filesystem` or `s3`.
+    pub blob_store: String,
+    pub blob_dir: String,
+    pub s3_endpoint: Option<String>,
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_prefix: String,
+    pub s3_access_key_id: Option<String>,
+    pub s3_secret_access_key: Option<String>,
+    /// Attachment bytes per guild; 0 disables the quota.
+    pub guild_storage_quota_bytes: u64,
+    /// Attachment bytes per uploader across all guilds; 0 disables the quota.
+    pub user_storage_quota_bytes: u64,
+    /// Body size of a single-request upload.
+    pub max_attachment_bytes: usize,
+    /// Size a resumable upload may declare.
+    pub max_upload_bytes: u64,
+    pub max_thumbnail_bytes: u64,
+    pub max_mls_backup_bytes: usize,
+    /// Token-bucket limits per route class, applied per user and per address. A
+    /// `_per_minute` of 0 disables that class's limit.
+    pub rate_limit_login_per_minute: u32,
+    pub rate_limit_login_burst: u32,
+    pub rate_limit_send_message_per_minute: u32,
+    pub rate_limit_send_message_burst: u32,
+    pub rate_limit_upload_per_minute: u32,
+    pub rate_limit_upload_burst: u32,
+    pub rate_limit_invite_join_per_minute: u32,
+    pub rate_limit_invite_join_burst: u32,
+    pub rate_limit_key_package_fetch_per_minute: u32,
+    pub rate_limit_key_package_fetch_burst: u32,
+    pub rate_limit_bootstrap_request_per_minute: u32,
+    pub rate_limit_bootstrap_request_burst: u32,
+    pub rate_limit_ws_frames_per_minute: u32,
+    pub rate_limit_ws_frames_burst: u32,
+    /// PEM certificate chain and private key. With both set the server speaks HTTPS, and
+    /// edits to either file are picked up while running.
+    pub tls_cert_path: Option<PathBuf>,
+    pub tls_key_path: Option<PathBuf>,
+    /// Serve HTTPS with a self-signed certificate, written to the TLS paths (or
+    /// `./data/tls/`) on first start. For development only.
+    pub tls_self_signed: bool,
+    /// Bearer token for the `/admin` API, at least 16 characters. Unset disables it.
+    pub admin_token: Option<String>,
+    /// `tracing` filter directives, such as `info` or `server=debug,sqlx=warn`.
+    pub log_level: String,
+    pub log_format: LogFormat,
 }
 
-impl Default for Settings {
-    fn default() -> Self {
-        Self {
-            server_bind: "127.0.0.1:8443".into(),
-            database_url: "sqlite://./data/server.db".into(),
-            server_public_url: None,
-            livekit_api_key: "devkey".into(),
-            livekit_api_secret: "devsecret".into(),
-            livekit_url: None,
-            livekit_ttl_seconds: 3600,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => bail!("unknown log format {other:?}; use text or json"),
         }
     }
 }
 
-pub fn load_settings() -> Settings {
-    let mut settings = Settings::default();
+/// TOML keys and environment variables for every setting, in that order. The variables
+/// override the file.
+const SETTING_KEYS: &[(&str, &str)] = &[
+    ("bind_addr", "APP__BIND_ADDR"),
+    ("database_url", "APP__DATABASE_URL"),
+    ("server_public_url", "APP__SERVER_PUBLIC_URL"),
+    ("livekit_api_key", "APP__LIVEKIT_API_KEY"),
+    ("livekit_api_secret", "APP__LIVEKIT_API_SECRET"),
+    ("livekit_url", "APP__LIVEKIT_URL"),
+    ("livekit_ttl_seconds", "APP__LIVEKIT_TTL_SECONDS"),
+    (
+        "retention_purge_interval_seconds",
+        "APP__RETENTION_PURGE_INTERVAL_SECONDS",
+    ),
+    (
+        "maintenance_interval_seconds",
+        "APP__MAINTENANCE_INTERVAL_SECONDS",
+    ),
+    (
+        "consumed_welcome_ttl_seconds",
+        "APP__CONSUMED_WELCOME_TTL_SECONDS",
+    ),
+    ("key_package_ttl_seconds", "APP__KEY_PACKAGE_TTL_SECONDS"),
+    ("device_link_ttl_seconds", "APP__DEVICE_LINK_TTL_SECONDS"),
+    ("orphan_file_ttl_seconds", "APP__ORPHAN_FILE_TTL_SECONDS"),
+    (
+        "upload_session_ttl_seconds",
+        "APP__UPLOAD_SESSION_TTL_SECONDS",
+    ),
+    ("maintenance_compact", "APP__MAINTENANCE_COMPACT"),
+    ("blob_store", "APP__BLOB_STORE"),
+    ("blob_dir", "APP__BLOB_DIR"),
+    ("s3_endpoint", "APP__S3_ENDPOINT"),
+    ("s3_bucket", "APP__S3_BUCKET"),
+    ("s3_region", "APP__S3_REGION"),
+    ("s3_prefix", "APP__S3_PREFIX"),
+    ("s3_access_key_id", "APP__S3_ACCESS_KEY_ID"),
+    ("s3_secret_access_key", "APP__S3_SECRET_ACCESS_KEY"),
+    (
+        "guild_storage_quota_bytes",
+        "APP__GUILD_STORAGE_QUOTA_BYTES",
+    ),
+    ("user_storage_quota_bytes", "APP__USER_STORAGE_QUOTA_BYTES"),
+    (
+        "rate_limit_login_per_minute",
+        "APP__RATE_LIMIT_LOGIN_PER_MINUTE",
+    ),
+    ("rate_limit_login_burst", "APP__RATE_LIMIT_LOGIN_BURST"),
+    (
+        "rate_limit_send_message_per_minute",
+        "APP__RATE_LIMIT_SEND_MESSAGE_PER_MINUTE",
+    ),
+    (
+        "rate_limit_send_message_burst",
+        "APP__RATE_LIMIT_SEND_MESSAGE_BURST",
+    ),
+    (
+        "rate_limit_upload_per_minute",
+        "APP__RATE_LIMIT_UPLOAD_PER_MINUTE",
+    ),
+    ("rate_limit_upload_burst", "APP__RATE_LIMIT_UPLOAD_BURST"),
+    (
+        "rate_limit_invite_join_per_minute",
+        "APP__RATE_LIMIT_INVITE_JOIN_PER_MINUTE",
+    ),
+    (
+        "rate_limit_invite_join_burst",
+        "APP__RATE_LIMIT_INVITE_JOIN_BURST",
+    ),
+    (
+        "rate_limit_key_package_fetch_per_minute",
+        "APP__RATE_LIMIT_KEY_PACKAGE_FETCH_PER_MINUTE",
+    ),
+    (
+        "rate_limit_key_package_fetch_burst",
+        "APP__RATE_LIMIT_KEY_PACKAGE_FETCH_BURST",
+    ),
+    (
+        "rate_limit_bootstrap_request_per_minute",
+        "APP__RATE_LIMIT_BOOTSTRAP_REQUEST_PER_MINUTE",
+    ),
+    (
+        "rate_limit_bootstrap_request_burst",
+        "APP__RATE_LIMIT_BOOTSTRAP_REQUEST_BURST",
+    ),
+    (
+        "rate_limit_ws_frames_per_minute",
+        "APP__RATE_LIMIT_WS_FRAMES_PER_MINUTE",
+    ),
+    (
+        "rate_limit_ws_frames_burst",
+        "APP__RATE_LIMIT_WS_FRAMES_BURST",
+    ),
+    ("max_attachment_bytes", "APP__MAX_ATTACHMENT_BYTES"),
+    ("max_upload_bytes", "APP__MAX_UPLOAD_BYTES"),
+    ("max_thumbnail_bytes", "APP__MAX_THUMBNAIL_BYTES"),
+    ("max_mls_backup_bytes", "APP__MAX_MLS_BACKUP_BYTES"),
+    ("tls_cert_path", "APP__TLS_CERT_PATH"),
+    ("tls_key_path", "APP__TLS_KEY_PATH"),
+    ("tls_self_signed", "APP__TLS_SELF_SIGNED"),
+    ("admin_token", "APP__ADMIN_TOKEN"),
+    ("log_level", "APP__LOG_LEVEL"),
+    ("log_format", "APP__LOG_FORMAT"),
+];
 
-    if let Ok(raw) = fs::read_to_string("server.toml") {
-        if let Ok(file_cfg) = toml::from_str::<HashMap<String, String>>(&raw) {
-            if let Some(v) = file_cfg.get("bind_addr") {
-                settings.server_bind = v.clone();
-            }
-            if let Some(v) = file_cfg.get("database_url") {
-                settings.database_url = v.clone();
-            }
-            if let Some(v) = file_cfg.get("server_public_url") {
-                settings.server_public_url = Some(v.clone());
-            }
+/// Older variable names, applied before the `APP__` ones.
+const LEGACY_ENV_KEYS: &[(&str, &str)] = &[
+    ("bind_addr", "SERVER_BIND"),
+    ("database_url", "DATABASE_URL"),
+    ("server_public_url", "SERVER_PUBLIC_URL"),
+    ("livekit_api_key", "LIVEKIT_API_KEY"),
+    ("livekit_api_secret", "LIVEKIT_API_SECRET"),
+    ("livekit_url", "LIVEKIT_URL"),
+];
+
+/// Settings a running server picks up on `SIGHUP`, besides every `rate_limit_*` key.
+const RELOADABLE_KEYS: &[&str] = &[
+    "log_level",
+    "consumed_welcome_ttl_seconds",
+    "key_package_ttl_seconds",
+    "device_link_ttl_seconds",
+    "orphan_file_ttl_seconds",
+    "upload_session_ttl_seconds",
+    "maintenance_compact",
+];
+
+impl Settings {
+    pub fn maintenance_policy(&self) -> MaintenancePolicy {
+        MaintenancePolicy {
+            consumed_welcome_ttl: chrono::Duration::seconds(self.consumed_welcome_ttl_seconds),
+            key_package_ttl: chrono::Duration::seconds(self.key_package_ttl_seconds),
+            device_link_ttl: chrono::Duration::seconds(self.device_link_ttl_seconds),
+            orphan_file_ttl: chrono::Duration::seconds(self.orphan_file_ttl_seconds),
+            upload_session_ttl: chrono::Duration::seconds(self.upload_session_ttl_seconds),
+            compact: self.maintenance_compact,
         }
     }
 
-    if let Ok(v) = std::env::var("SERVER_BIND") {
-        settings.server_bind = v;
-    }
-    if let Ok(v) = std::env::var("APP__BIND_ADDR") {
-        settings.server_bind = v;
+    pub fn storage_quotas(&self) -> StorageQuotas {
+        let limit = |bytes: u64| (bytes > 0).then_some(bytes);
+        StorageQuotas {
+            guild_bytes: limit(self.guild_storage_quota_bytes),
+            user_bytes: limit(self.user_storage_quota_bytes),
+        }
     }
 
-    if let Ok(v) = std::env::var("DATABASE_URL") {
-        settings.database_url = v;
+    pub fn rate_limits(&self) -> RateLimits {
+        RateLimits {
+            login: RateLimit::new(
+                self.rate_limit_login_per_minute,
+                self.rate_limit_login_burst,
+            ),
+            send_message: RateLimit::new(
+                self.rate_limit_send_message_per_minute,
+                self.rate_limit_send_message_burst,
+            ),
+            upload: RateLimit::new(
+                self.rate_limit_upload_per_minute,
+                self.rate_limit_upload_burst,
+            ),
+            invite_join: RateLimit::new(
+                self.rate_limit_invite_join_per_minute,
+                self.rate_limit_invite_join_burst,
+            ),
+            key_package_fetch: RateLimit::new(
+                self.rate_limit_key_package_fetch_per_minute,
+                self.rate_limit_key_package_fetch_burst,
+            ),
+            bootstrap_request: RateLimit::new(
+                self.rate_limit_bootstrap_request_per_minute,
+                self.rate_limit_bootstrap_request_burst,
+            ),
+            ws_frames: RateLimit::new(
+                self.rate_limit_ws_frames_per_minute,
+                self.rate_limit_ws_frames_burst,
+            ),
+        }
     }
-    if let Ok(v) = std::env::var("APP__DATABASE_URL") {
-        settings.database_url = v;
+
+    /// Where attachment ciphertext is stored, validated but not yet connected.
+    pub fn blob_store_config(&self) -> anyhow::Result<BlobStoreConfig> {
+        match self.blob_store.trim().to_ascii_lowercase().as_str() {
+            "" | "database" => Ok(BlobStoreConfig::Database),
+            "filesystem" => Ok(BlobStoreConfig::Filesystem(PathBuf::from(&self.blob_dir))),
+            "s3" => {
+                let required = |value: &Option<String>, key: &str| {
+                    value
+                        .clone()
+                        .filter(|v| !v.trim().is_empty())
+                        .with_context(|| format!("blob_store = \"s3\" requires {key}"))
+                };
+                Ok(BlobStoreConfig::S3(S3Config {
+                    endpoint: required(&self.s3_endpoint, "s3_endpoint")?,
+                    bucket: required(&self.s3_bucket, "s3_bucket")?,
+                    region: self.s3_region.clone(),
+                    prefix: self.s3_prefix.clone(),
+                    access_key_id: required(&self.s3_access_key_id, "s3_access_key_id")?,
+                    secret_access_key: required(
+                        &self.s3_secret_access_key,
+                        "s3_secret_access_key",
+                    )?,
+                }))
+            }
+            other => bail!("unknown blob_store {other:?}; use database, filesystem or s3"),
+        }
     }
 
-    if let Ok(v) = std::env::var("SERVER_PUBLIC_URL") {
-        settings.server_public_url = Some(v);
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_attachment_bytes: self.max_attachment_bytes,
+            max_upload_bytes: self.max_upload_bytes,
+            max_thumbnail_bytes: self.max_thumbnail_bytes,
+            max_mls_backup_bytes: self.max_mls_backup_bytes,
+        }
     }
 
-    if let Ok(v) = std::env::var("LIVEKIT_API_KEY") {
-        settings.livekit_api_key = v;
+    /// Certificate and key to serve HTTPS with, if HTTPS is on.
+    pub fn tls_files(&self) -> Option<TlsFiles> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => Some(TlsFiles {
+                cert_path: cert_path.clone(),
+                key_path: key_path.clone(),
+            }),
+            (None, None) if self.tls_self_signed => Some(TlsFiles {
+                cert_path: PathBuf::from(DEFAULT_SELF_SIGNED_DIR).join("server.crt"),
+                key_path: PathBuf::from(DEFAULT_SELF_SIGNED_DIR).join("server.key"),
+            }),
+            _ => None,
+        }
     }
-    if let Ok(v) = std::env::var("APP__LIVEKIT_API_KEY") {
-        settings.livekit_api_key = v;
+
+    /// Names a self-signed certificate is valid for: loopback plus the bind address and
+    /// public URL host when they name something more specific.
+    pub fn self_signed_names(&self) -> Vec<String> {
+        let mut names = vec![
+            "localhost".to_string(),
+            "127.0.0.1".to_string(),
+            "::1".to_string(),
+        ];
+        if let Ok(addr) = self.server_bind.trim().parse::<SocketAddr>() {
+            if !addr.ip().is_unspecified() {
+                names.push(addr.ip().to_string());
+            }
+        }
+        if let Some(host) = self
+            .server_public_url
+            .as_deref()
+            .and_then(|url| url::Url::parse(url).ok())
+            .and_then(|url| {
+                url.host_str()
+                    .map(|host| host.trim_matches(['[', ']']).to_string())
+            })
+        {
+            names.push(host);
+        }
+        names.sort();
+        names.dedup();
+        names
     }
 
-    if let Ok(v) = std::env::var("LIVEKIT_API_SECRET") {
-        settings.livekit_api_secret = v;
+    /// Checks values that parse but cannot work, reporting every problem at once.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+        if let Err(error) = self.server_bind.trim().parse::<SocketAddr>() {
+            problems.push(format!("bind_addr {:?}: {error}", self.server_bind));
+        }
+        for (key, value) in [
+            ("server_public_url", &self.server_public_url),
+            ("livekit_url", &self.livekit_url),
+            ("s3_endpoint", &self.s3_endpoint),
+        ] {
+            if let Some(Err(error)) = value.as_deref().map(url::Url::parse) {
+                problems.push(format!("{key} {value:?}: {error}"));
+            }
+        }
+        for (key, value) in [
+            ("livekit_ttl_seconds", self.livekit_ttl_seconds),
+            (
+                "retention_purge_interval_seconds",
+                self.retention_purge_interval_seconds as i64,
+            ),
+            (
+                "maintenance_interval_seconds",
+                self.maintenance_interval_seconds as i64,
+            ),
+            ("max_attachment_bytes", self.max_attachment_bytes as i64),
+            ("max_upload_bytes", self.max_upload_bytes as i64),
+            ("max_thumbnail_bytes", self.max_thumbnail_bytes as i64),
+            ("max_mls_backup_bytes", self.max_mls_backup_bytes as i64),
+        ] {
+            if value <= 0 {
+                problems.push(format!("{key} must be greater than 0"));
+            }
+        }
+        for (key, value) in [
+            (
+                "consumed_welcome_ttl_seconds",
+                self.consumed_welcome_ttl_seconds,
+            ),
+            ("key_package_ttl_seconds", self.key_package_ttl_seconds),
+            ("device_link_ttl_seconds", self.device_link_ttl_seconds),
+            ("orphan_file_ttl_seconds", self.orphan_file_ttl_seconds),
+            (
+                "upload_session_ttl_seconds",
+                self.upload_session_ttl_seconds,
+            ),
+        ] {
+            if value < 0 {
+                problems.push(format!("{key} must not be negative"));
+            }
+        }
+        if let Err(error) = self.blob_store_config() {
+            problems.push(error.to_string());
+        }
+        if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+            problems.push("tls_cert_path and tls_key_path must be set together".to_string());
+        } else if let Some(files) = self.tls_files() {
+            // A self-signed pair that does not exist yet is written at startup.
+            let pending =
+                self.tls_self_signed && !(files.cert_path.exists() || files.key_path.exists());
+            if !pending {
+                if let Err(error) = tls::server_config(&files) {
+                    problems.push(format!("{error:#}"));
+                }
+            }
+        }
+        if let Some(token) = &self.admin_token {
+            if token.trim().chars().count() < MIN_ADMIN_TOKEN_CHARS {
+                problems.push(format!(
+                    "admin_token must be at least {MIN_ADMIN_TOKEN_CHARS} characters"
+                ));
+            }
+        }
+        if let Err(error) = EnvFilter::try_new(&self.log_level) {
+            problems.push(format!("log_level {:?}: {error}", self.log_level));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            bail!("invalid settings:\n  {}", problems.join("\n  "))
+        }
     }
-    if let Ok(v) = std::env::var("APP__LIVEKIT_API_SECRET") {
-        settings.livekit_api_secret = v;
+
+    /// Keys that differ between `self` and `next` but only take effect after a restart.
+    pub fn restart_required(&self, next: &Settings) -> Vec<String> {
+        let (Ok(toml::Value::Table(current)), Ok(toml::Value::Table(next))) =
+            (toml::Value::try_from(self), toml::Value::try_from(next))
+        else {
+            return Vec::new();
+        };
+        let mut keys: Vec<String> = current
+            .keys()
+            .chain(next.keys())
+            .filter(|key| current.get(*key) != next.get(*key))
+            .filter(|key| {
+                !key.starts_with("rate_limit_") && !RELOADABLE_KEYS.contains(&key.as_str())
+            })
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
     }
 
-    if let Ok(v) = std::env::var("LIVEKIT_URL") {
-        settings.livekit_url = Some(v);
+    fn apply_value(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        match key {
+            "bind_addr" => self.server_bind = value.trim().to_string(),
+            "database_url" => self.database_url = value.to_string(),
+            "server_public_url" => self.server_public_url = optional(value),
+            "livekit_api_key" => self.livekit_api_key = value.to_string(),
+            "livekit_api_secret" => self.livekit_api_secret = value.to_string(),
+            "livekit_url" => self.livekit_url = optional(value),
+            "livekit_ttl_seconds" => parse_into(value, &mut self.livekit_ttl_seconds)?,
+            "retention_purge_interval_seconds" => {
+                parse_into(value, &mut self.retention_purge_interval_seconds)?
+            }
+            "maintenance_interval_seconds" => {
+                parse_into(value, &mut self.maintenance_interval_seconds)?
+            }
+            "consumed_welcome_ttl_seconds" => {
+                parse_into(value, &mut self.consumed_welcome_ttl_seconds)?
+            }
+            "key_package_ttl_seconds" => parse_into(value, &mut self.key_package_ttl_seconds)?,
+            "device_link_ttl_seconds" => parse_into(value, &mut self.device_link_ttl_seconds)?,
+            "orphan_file_ttl_seconds" => parse_into(value, &mut self.orphan_file_ttl_seconds)?,
+            "upload_session_ttl_seconds" => {
+                parse_into(value, &mut self.upload_session_ttl_seconds)?
+            }
+            "maintenance_compact" => parse_into(value, &mut self.maintenance_compact)?,
+            "blob_store" => self.blob_store = value.to_string(),
+            "blob_dir" => self.blob_dir = value.to_string(),
+            "s3_endpoint" => self.s3_endpoint = optional(value),
+            "s3_bucket" => self.s3_bucket = optional(value),
+            "s3_region" => self.s3_region = value.to_string(),
+            "s3_prefix" => self.s3_prefix = value.to_string(),
+            "s3_access_key_id" => self.s3_access_key_id = optional(value),
+            "s3_secret_access_key" => self.s3_secret_access_key = optional(value),
+            "guild_storage_quota_bytes" => parse_into(value, &mut self.guild_storage_quota_bytes)?,
+            "user_storage_quota_bytes" => parse_into(value, &mut self.user_storage_quota_bytes)?,
+            "rate_limit_login_per_minute" => {
+                parse_into(value, &mut self.rate_limit_login_per_minute)?
+            }
+            "rate_limit_login_burst" => parse_into(value, &mut self.rate_limit_login_burst)?,
+            "rate_limit_send_message_per_minute" => {
+                parse_into(value, &mut self.rate_limit_send_message_per_minute)?
+            }
+            "rate_limit_send_message_burst" => {
+                parse_into(value, &mut self.rate_limit_send_message_burst)?
+            }
+            "rate_limit_upload_per_minute" => {
+                parse_into(value, &mut self.rate_limit_upload_per_minute)?
+            }
+            "rate_limit_upload_burst" => parse_into(value, &mut self.rate_limit_upload_burst)?,
+            "rate_limit_invite_join_per_minute" => {
+                parse_into(value, &mut self.rate_limit_invite_join_per_minute)?
+            }
+            "rate_limit_invite_join_burst" => {
+                parse_into(value, &mut self.rate_limit_invite_join_burst)?
+            }
+            "rate_limit_key_package_fetch_per_minute" => {
+                parse_into(value, &mut self.rate_limit_key_package_fetch_per_minute)?
+            }
+            "rate_limit_key_package_fetch_burst" => {
+                parse_into(value, &mut self.rate_limit_key_package_fetch_burst)?
+            }
+            "rate_limit_bootstrap_request_per_minute" => {
+                parse_into(value, &mut self.rate_limit_bootstrap_request_per_minute)?
+            }
+            "rate_limit_bootstrap_request_burst" => {
+                parse_into(value, &mut self.rate_limit_bootstrap_request_burst)?
+            }
+            "rate_limit_ws_frames_per_minute" => {
+                parse_into(value, &mut self.rate_limit_ws_frames_per_minute)?
+            }
+            "rate_limit_ws_frames_burst" => {
+                parse_into(value, &mut self.rate_limit_ws_frames_burst)?
+            }
+            "max_attachment_bytes" => parse_into(value, &mut self.max_attachment_bytes)?,
+            "max_upload_bytes" => parse_into(value, &mut self.max_upload_bytes)?,
+            "max_thumbnail_bytes" => parse_into(value, &mut self.max_thumbnail_bytes)?,
+            "max_mls_backup_bytes" => parse_into(value, &mut self.max_mls_backup_bytes)?,
+            "tls_cert_path" => self.tls_cert_path = optional(value).map(PathBuf::from),
+            "tls_key_path" => self.tls_key_path = optional(value).map(PathBuf::from),
+            "tls_self_signed" => parse_into(value, &mut self.tls_self_signed)?,
+            "admin_token" => self.admin_token = optional(value),
+            "log_level" => self.log_level = value.to_string(),
+            "log_format" => parse_into(value, &mut self.log_format)?,
+            _ => bail!("unknown setting {key}"),
+        }
+        Ok(())
     }
-    if let Ok(v) = std::env::var("APP__LIVEKIT_URL") {
-        settings.livekit_url = Some(v);
+}
+
+/// Overwrites `target` when `value` parses; a malformed value keeps the previous setting
+/// and is reported.
+fn parse_into<T>(value: &str, target: &mut T) -> anyhow::Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    *target = value
+        .trim()
+        .parse()
+        .map_err(|error| anyhow!("{value:?} is not valid: {error}"))?;
+    Ok(())
+}
+
+/// An empty value unsets an optional setting.
+fn optional(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let defaults = MaintenancePolicy::default();
+        let limits = Limits::default();
+        Self {
+            server_bind: "127.0.0.1:8443".into(),
+            database_url: "sqlite://./data/server.db".into(),
+            server_public_url: None,
+            livekit_api_key: "devkey".into(),
+            livekit_api_secret: "devsecret".into(),
+            livekit_url: None,
+            livekit_ttl_seconds: 3600,
+            retention_purge_interval_seconds: 60,
+            maintenance_interval_seconds: 3600,
+            consumed_welcome_ttl_seconds: defaults.consumed_welcome_ttl.num_seconds(),
+            key_package_ttl_seconds: defaults.key_package_ttl.num_seconds(),
+            device_link_ttl_seconds: defaults.device_link_ttl.num_seconds(),
+            orphan_file_ttl_seconds: defaults.orphan_file_ttl.num_seconds(),
+            upload_session_ttl_seconds: defaults.upload_session_ttl.num_seconds(),
+            maintenance_compact: defaults.compact,
+            blob_store: "database".into(),
+            blob_dir: "./data/blobs".into(),
+            s3_endpoint: None,
+            s3_bucket: None,
+            s3_region: "us-east-1".into(),
+            s3_prefix: String::new(),
+            s3_access_key_id: None,
+            s3_secret_access_key: None,
+            guild_storage_quota_bytes: 10 * 1024 * 1024 * 1024,
+            user_storage_quota_bytes: 5 * 1024 * 1024 * 1024,
+            max_attachment_bytes: limits.max_attachment_bytes,
+            max_upload_bytes: limits.max_upload_bytes,
+            max_thumbnail_bytes: limits.max_thumbnail_bytes,
+            max_mls_backup_bytes: limits.max_mls_backup_bytes,
+            rate_limit_login_per_minute: 10,
+            rate_limit_login_burst: 5,
+            rate_limit_send_message_per_minute: 120,
+            rate_limit_send_message_burst: 20,
+            rate_limit_upload_per_minute: 30,
+            rate_limit_upload_burst: 10,
+            rate_limit_invite_join_per_minute: 10,
+            rate_limit_invite_join_burst: 5,
+            rate_limit_key_package_fetch_per_minute: 120,
+            rate_limit_key_package_fetch_burst: 30,
+            rate_limit_bootstrap_request_per_minute: 30,
+            rate_limit_bootstrap_request_burst: 10,
+            rate_limit_ws_frames_per_minute: 600,
+            rate_limit_ws_frames_burst: 60,
+            tls_cert_path: None,
+            tls_key_path: None,
+            tls_self_signed: false,
+            admin_token: None,
+            log_level: "info".into(),
+            log_format: LogFormat::Text,
+        }
     }
+}
+
+/// The file to read when none is given: `server.toml` in the working directory, if present.
+pub fn default_config_path() -> Option<PathBuf> {
+    let path = PathBuf::from(DEFAULT_CONFIG_PATH);
+    path.is_file().then_some(path)
+}
 
-    if let Ok(v) = std::env::var("APP__LIVEKIT_TTL_SECONDS") {
-        if let Ok(parsed) = v.parse::<i64>() {
-            settings.livekit_ttl_seconds = parsed;
+/// Reads `path` (if any), applies environment overrides and validates the result.
+pub fn load_settings(path: Option<&Path>) -> anyhow::Result<Settings> {
+    let mut settings = match path {
+        Some(path) => {
+            let raw = fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            parse_settings(&raw).with_context(|| format!("invalid {}", path.display()))?
+        }
+        None => Settings::default(),
+    };
+
+    for (key, env_key) in LEGACY_ENV_KEYS.iter().chain(SETTING_KEYS) {
+        if let Ok(v) = std::env::var(env_key) {
+            settings
+                .apply_value(key, &v)
+                .with_context(|| format!("invalid {env_key}"))?;
         }
     }
 
-    settings
+    settings.validate()?;
+    Ok(settings)
+}
+
+fn parse_settings(raw: &str) -> anyhow::Result<Settings> {
+    Ok(toml::from_str(raw)?)
 }
 
 pub fn prepare_database_url(raw_database_url: &str) -> anyhow::Result<String> {
@@ -0,0 +1,54 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signer, SigningKey};
+use rand_core::{OsRng, RngCore};
+use shared::{
+    protocol::SignedTreeHead,
+    transparency::{tree_head_signing_payload, TreeHash},
+};
+use storage::Storage;
+
+/// Signs tree heads for the append-only device identity log kept in storage.
+#[derive(Clone)]
+pub struct KeyTransparencyLog {
+    signing_key: SigningKey,
+}
+
+impl KeyTransparencyLog {
+    /// Loads the persisted log key (generating it on first start) and logs any devices
+    /// registered before the log existed.
+    pub async fn load_or_create(storage: &Storage) -> Result<Self> {
+        let mut candidate = [0u8; 32];
+        OsRng.fill_bytes(&mut candidate);
+        let secret_key = storage
+            .load_or_store_key_transparency_signing_key(&candidate)
+            .await?;
+        let secret_key: [u8; 32] = secret_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow!("stored key transparency signing key must be 32 bytes"))?;
+        storage.backfill_key_transparency_log().await?;
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&secret_key),
+        })
+    }
+
+    pub fn public_key_b64(&self) -> String {
+        STANDARD.encode(self.signing_key.verifying_key().as_bytes())
+    }
+
+    pub fn sign_tree_head(&self, tree_size: u64, root_hash: &TreeHash) -> Result<SignedTreeHead> {
+        let timestamp = DateTime::from_timestamp_millis(Utc::now().timestamp_millis())
+            .ok_or_else(|| anyhow!("clock out of range"))?;
+        let payload = tree_head_signing_payload(tree_size, root_hash, timestamp.timestamp_millis());
+        let signature = self.signing_key.sign(&payload);
+        Ok(SignedTreeHead {
+            tree_size,
+            root_hash_b64: STANDARD.encode(root_hash),
+            timestamp,
+            log_public_key_b64: self.public_key_b64(),
+            signature_b64: STANDARD.encode(signature.to_bytes()),
+        })
+    }
+}
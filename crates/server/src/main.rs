@@ -1,49 +1,92 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
 use crate::api::{
-    ensure_active_membership_in_channel, ensure_active_membership_in_guild, list_channels,
-    list_guilds, list_members, list_messages, mls_bootstrap_request_route, mls_key_packages_route,
-    mls_welcome_recovery_route, mls_welcome_route, request_livekit_token, send_message, ApiContext,
-    KeyPackageResponse, MlsKeyPackageQuery, MlsWelcomeQuery, MlsWelcomeResponse,
-    UploadKeyPackageResponse,
+    admin,
+    audit::list_audit_log,
+    channel_summary, create_channel, create_guild, create_invite, create_role, delete_channel,
+    delete_guild, delete_invite, delete_role,
+    direct::{create_direct_channel, list_direct_channels},
+    ensure_active_membership_in_channel, ensure_active_membership_in_guild, join_with_invite,
+    key_transparency_consistency_proof, key_transparency_consistency_route,
+    key_transparency_inclusion_proof, key_transparency_inclusion_route,
+    key_transparency_proof_for_device, key_transparency_tree_head,
+    key_transparency_tree_head_route, leave_guild, list_channel_members, list_channel_overwrites,
+    list_channels, list_guilds, list_history_bundles, list_invites, list_members, list_messages,
+    list_roles, mls_backup_route, mls_bootstrap_request_route, mls_history_route,
+    mls_key_packages_route, mls_welcome_recovery_route, mls_welcome_route, moderate_member,
+    permissions, rename_guild, reorder_channels, reorder_guilds, request_livekit_token,
+    retention::{purge_expired, set_channel_retention},
+    send_message, set_channel_history_sharing, set_channel_overwrite, set_member_role,
+    store_history_bundle,
+    threads::{create_thread, list_thread_messages, list_threads, set_thread_archived},
+    transfer_ownership, update_channel, update_role,
+    uploads::{
+        append_upload_chunk, authorize_download, authorize_upload, complete_upload, create_upload,
+        guild_storage_usage, link_thumbnail, storage_error, upload_status, MAX_UPLOAD_CHUNK_BYTES,
+    },
+    ApiContext, KeyPackageResponse, MessageOptions, MlsKeyPackageQuery, MlsWelcomeQuery,
+    MlsWelcomeResponse, UploadKeyPackageResponse,
 };
+use crate::key_transparency::KeyTransparencyLog;
 use crate::livekit::LiveKitConfig;
 use axum::{
-    body::Bytes,
-    extract::{Path, Query, State, WebSocketUpgrade},
+    body::{Body, Bytes},
+    extract::{ConnectInfo, DefaultBodyLimit, Path, Query, State, WebSocketUpgrade},
     http::{header, HeaderMap, HeaderValue, StatusCode},
-    response::IntoResponse,
-    routing::{get, post},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, patch, post, put},
     Json, Router,
 };
-use base64::{
-    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
-    Engine as _,
-};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use shared::{
-    domain::{ChannelId, ChannelKind, DeviceId, FileId, GuildId, UserId},
+    domain::{
+        AuditAction, ChannelId, ChannelKind, DeviceId, FileId, GuildId, MessageId,
+        ModerationAction, PermissionOverwrite, RoleId, ThreadId, UserId,
+    },
     error::{ApiError, ErrorCode},
     protocol::{
-        AttachmentPayload, DeviceLinkBundleFetchRequest, DeviceLinkBundleUploadRequest,
-        DeviceLinkStartResponse, MlsBootstrapReason, ServerEvent,
+        AdminGuildSummary, AdminUserSummary, ArchiveThreadRequest, AttachmentPayload,
+        AuditLogEntry, AuditLogFilter, ChannelSummary, CompleteUploadRequest,
+        ConsistencyProofResponse, CreateChannelRequest, CreateDirectChannelRequest,
+        CreateGuildRequest, CreateRoleRequest, CreateThreadRequest, CreateUploadRequest,
+        DeleteGuildRequest, DeviceLinkBundleFetchRequest, DeviceLinkBundleUploadRequest,
+        DeviceLinkStartResponse, DirectChannelSummary, GuildStorageUsage, GuildSummary,
+        HistoryBundleResponse, InclusionProofResponse, InviteSummary, LinkThumbnailRequest,
+        MlsBootstrapReason, MlsQueueSummary, ModerationRequest, ReorderChannelsRequest,
+        ReorderGuildsRequest, RetentionPolicy, RoleSummary, ServerEvent, ServerStorageStats,
+        SignedTreeHead, SuspendUserRequest, ThreadSummary, TransferOwnershipRequest,
+        UpdateChannelRequest, UpdateGuildRequest, UpdateRoleRequest, UploadStatus,
     },
 };
 use storage::Storage;
-use tokio::sync::broadcast;
-use tracing::{error, info};
+use tokio::sync::{broadcast, watch};
+use tracing::{error, info, warn};
 
+mod admin_auth;
 mod api;
 mod app_state;
 mod config;
+mod key_transparency;
 mod livekit;
+mod maintenance;
+mod metrics;
+mod rate_limit;
+mod reload;
 mod router;
 mod routes;
+mod tls;
 mod ws;
 
-use app_state::AppState;
-use config::{load_settings, prepare_database_url};
+use admin_auth::AdminToken;
+use app_state::{AppState, Dispatch, Revocation};
+use axum_server::tls_rustls::RustlsConfig;
+use clap::{Parser, Subcommand};
+use config::{default_config_path, load_settings, prepare_database_url};
+use metrics::{Metrics, StoredKind};
+use rate_limit::{RateLimiter, RouteClass};
 
 #[derive(Debug, Deserialize)]
 struct LoginRequest {
@@ -72,6 +115,18 @@ struct DeviceRegisterQuery {
     user_id: i64,
 }
 
+#[derive(Debug, Deserialize)]
+struct InclusionProofQuery {
+    leaf_index: u64,
+    tree_size: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsistencyProofQuery {
+    first_size: u64,
+    second_size: Option<u64>,
+}
+
 #[derive(Debug, Deserialize)]
 struct DeviceLinkStartQuery {
     user_id: i64,
@@ -106,6 +161,8 @@ struct FileUploadQuery {
 #[derive(Debug, Deserialize)]
 struct WsQuery {
     user_id: i64,
+    /// Ties the connection to a device so revoking it closes the connection.
+    device_id: Option<i64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -113,11 +170,23 @@ struct UserQuery {
     user_id: i64,
 }
 
+#[derive(Debug, Deserialize)]
+struct AdminUserSearchQuery {
+    search: Option<String>,
+    limit: Option<u32>,
+}
+
 #[derive(Debug, Deserialize)]
 struct FileDownloadQuery {
     user_id: i64,
 }
 
+#[derive(Debug, Deserialize)]
+struct UploadChunkQuery {
+    user_id: i64,
+    offset: u64,
+}
+
 #[derive(Debug, Deserialize)]
 struct LiveKitTokenQuery {
     user_id: i64,
@@ -142,9 +211,14 @@ struct JoinGuildRequest {
     invite_code: String,
 }
 
-#[derive(Debug, Serialize)]
-struct InviteResponse {
-    invite_code: String,
+#[derive(Debug, Deserialize)]
+struct AuditLogQuery {
+    user_id: i64,
+    before: Option<i64>,
+    limit: Option<u32>,
+    action: Option<String>,
+    actor_id: Option<i64>,
+    target_user_id: Option<i64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -152,11 +226,22 @@ struct SendMessageRequest {
     user_id: i64,
     guild_id: i64,
     channel_id: i64,
+    #[serde(default)]
+    thread_id: Option<i64>,
+    #[serde(default)]
+    expires_in_seconds: Option<u64>,
     ciphertext_b64: String,
     #[serde(default)]
     attachment: Option<AttachmentPayload>,
 }
 
+#[derive(Debug, Deserialize)]
+struct ListThreadsQuery {
+    user_id: i64,
+    #[serde(default)]
+    include_archived: bool,
+}
+
 #[derive(Debug, Deserialize)]
 struct StorePendingWelcomeQuery {
     user_id: i64,
@@ -180,6 +265,22 @@ struct MlsBootstrapRequestQuery {
     reason: MlsBootstrapReason,
 }
 
+#[derive(Debug, Deserialize)]
+struct ChannelHistorySharingQuery {
+    user_id: i64,
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoreHistoryBundleQuery {
+    user_id: i64,
+    guild_id: i64,
+    channel_id: i64,
+    target_user_id: i64,
+    #[serde(default)]
+    target_device_id: Option<i64>,
+}
+
 #[derive(Debug, Deserialize)]
 struct RecoveryWelcomeQuery {
     user_id: i64,
@@ -190,21 +291,40 @@ struct RecoveryWelcomeQuery {
     target_device_id: Option<i64>,
 }
 
-const MAX_ATTACHMENT_BYTES: usize = 8 * 1024 * 1024;
-const MAX_FILENAME_BYTES: usize = 180;
+/// Bytes read from storage per body frame while streaming a download.
+const DOWNLOAD_READ_BYTES: u64 = 1024 * 1024;
+
+#[derive(Parser, Debug)]
+struct Cli {
+    /// Settings file. Defaults to `server.toml` in the working directory, if present.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Loads and validates the settings, including environment overrides, then exits.
+    CheckConfig,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt().with_env_filter("info").init();
-
-    info!("loading server settings");
-    let settings = load_settings();
-    info!(bind_addr = %settings.server_bind, "server settings parsed");
-    if settings.server_bind.trim().is_empty() {
-        error!("SERVER_BIND / bind_addr must not be empty");
-        anyhow::bail!("SERVER_BIND / bind_addr must not be empty");
+    let cli = Cli::parse();
+    let config_path = cli.config.or_else(default_config_path);
+    let settings = load_settings(config_path.as_deref())?;
+    if let Some(Command::CheckConfig) = cli.command {
+        match &config_path {
+            Some(path) => println!("{}: ok", path.display()),
+            None => println!("no config file; defaults and environment: ok"),
+        }
+        return Ok(());
     }
 
+    let log_filter = reload::init_logging(&settings)?;
+    info!(config = ?config_path, bind_addr = %settings.server_bind, "server settings loaded");
+
     let database_url = prepare_database_url(&settings.database_url).map_err(|error| {
         error!(raw_database_url = %settings.database_url, %error, "database url preparation failed");
         error
@@ -227,28 +347,86 @@ async fn main() -> anyhow::Result<()> {
     })?;
     info!("database health check passed");
 
+    let blob_store_config = settings.blob_store_config().map_err(|error| {
+        error!(%error, "invalid blob store settings");
+        error
+    })?;
+    let blobs = blob_store_config.build().map_err(|error| {
+        error!(?blob_store_config, %error, "blob store initialization failed");
+        error
+    })?;
+    let storage = storage.with_blob_store(blobs);
+    info!(?blob_store_config, "attachment blob store ready");
+    let quotas = settings.storage_quotas();
+    let storage = storage.with_storage_quotas(quotas);
+    info!(?quotas, "attachment storage quotas");
+
+    let transparency = KeyTransparencyLog::load_or_create(&storage)
+        .await
+        .map_err(|error| {
+            error!(%error, "key transparency log initialization failed");
+            error
+        })?;
+    info!(log_public_key = %transparency.public_key_b64(), "key transparency log ready");
+
+    let (maintenance_tx, maintenance_rx) = watch::channel(settings.maintenance_policy());
+    let rate_limits = settings.rate_limits();
+    info!(?rate_limits, "rate limits");
     let api = ApiContext {
         storage,
         livekit: LiveKitConfig {
-            api_key: settings.livekit_api_key,
-            api_secret: settings.livekit_api_secret,
+            api_key: settings.livekit_api_key.clone(),
+            api_secret: settings.livekit_api_secret.clone(),
             ttl_seconds: settings.livekit_ttl_seconds,
         },
+        transparency,
+        limits: settings.limits(),
     };
     let (events, _) = broadcast::channel(256);
-
-    let state = AppState { api, events };
-    let app = build_router(Arc::new(state));
+    let (revocations, _) = broadcast::channel(64);
+
+    let state = Arc::new(AppState {
+        api,
+        events,
+        revocations,
+        rate_limiter: RateLimiter::new(rate_limits),
+        metrics: Metrics::new(),
+        admin_token: settings.admin_token.as_deref().map(AdminToken::new),
+    });
+    tokio::spawn(run_retention_purge(
+        Arc::clone(&state),
+        std::time::Duration::from_secs(settings.retention_purge_interval_seconds.max(1)),
+    ));
+    tokio::spawn(maintenance::run_scheduled(
+        state.api.storage.clone(),
+        maintenance_rx,
+        std::time::Duration::from_secs(settings.maintenance_interval_seconds.max(1)),
+    ));
+    tokio::spawn(reload::on_sighup(
+        reload::Reloadable {
+            config_path,
+            log_filter,
+            rate_limiter: state.rate_limiter.clone(),
+            maintenance: maintenance_tx,
+        },
+        settings.clone(),
+    ));
+    let app = build_router(state);
 
     let routes = [
         "/healthz",
+        "/readyz",
+        "/metrics",
         "/messages",
         "/channels/:channel_id/messages",
         "/files/upload",
+        "/files/uploads",
         "/files/:file_id",
         mls_key_packages_route(),
         mls_welcome_route(),
         mls_welcome_recovery_route(),
+        mls_backup_route(),
+        key_transparency_tree_head_route(),
     ];
     for route in routes {
         info!(%route, "route registered");
@@ -260,13 +438,51 @@ async fn main() -> anyhow::Result<()> {
         error
     })?;
 
+    let tls_files = match settings.tls_files() {
+        Some(files) => {
+            if settings.tls_self_signed
+                && tls::ensure_self_signed(&files, settings.self_signed_names())?
+            {
+                warn!(
+                    cert = %files.cert_path.display(),
+                    "generated a self-signed certificate; clients must pin it or trust it as a CA"
+                );
+            }
+            let config = tls::server_config(&files).map_err(|error| {
+                error!(error = %format!("{error:#}"), "failed to load tls certificate");
+                error
+            })?;
+            Some((files, RustlsConfig::from_config(config)))
+        }
+        None => None,
+    };
+
     let listener = tokio::net::TcpListener::bind(addr).await.map_err(|error| {
         error!(%addr, %error, "failed to bind tcp listener");
         error
     })?;
-    info!(%addr, "server listening");
 
-    axum::serve(listener, app).await.map_err(|error| {
+    // Peer addresses key the per-address rate limits.
+    let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+    let served = match tls_files {
+        Some((files, config)) => {
+            info!(
+                %addr,
+                cert = %files.cert_path.display(),
+                fingerprint = %tls::leaf_fingerprint(&files)?,
+                "server listening (https, h2 and http/1.1)"
+            );
+            tokio::spawn(tls::reload_on_change(config.clone(), files));
+            axum_server::from_tcp_rustls(listener.into_std()?, config)
+                .serve(make_service)
+                .await
+        }
+        None => {
+            info!(%addr, "server listening");
+            axum::serve(listener, make_service).await
+        }
+    };
+    served.map_err(|error| {
         error!(%addr, %error, "server terminated unexpectedly");
         error
     })?;
@@ -274,42 +490,209 @@ async fn main() -> anyhow::Result<()> {
 }
 
 fn build_router(state: Arc<AppState>) -> Router {
+    let limits = state.api.limits;
+    let limit = |class: RouteClass| {
+        middleware::from_fn_with_state((Arc::clone(&state), class), rate_limit::enforce)
+    };
     Router::new()
         .route("/healthz", get(healthz))
-        .route("/login", post(login))
-        .route("/guilds", get(http_list_guilds))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(http_metrics))
+        .route("/login", post(login).layer(limit(RouteClass::Login)))
+        .route("/guilds", get(http_list_guilds).post(http_create_guild))
+        .route("/guilds/order", put(http_reorder_guilds))
+        .route(
+            "/dms",
+            get(http_list_direct_channels).post(http_create_direct_channel),
+        )
+        .route(
+            "/guilds/:guild_id",
+            patch(http_rename_guild).delete(http_delete_guild),
+        )
+        .route("/guilds/:guild_id/leave", post(http_leave_guild))
+        .route("/guilds/:guild_id/owner", put(http_transfer_ownership))
         .route("/devices/register", post(register_device))
         .route("/devices/me", get(get_my_device))
+        .route("/devices/revoke", post(revoke_device))
         .route("/devices/link/start", post(start_device_link))
         .route("/devices/link/bundle", post(upload_device_link_bundle))
         .route("/devices/link/bundle/fetch", post(fetch_device_link_bundle))
         .route("/devices/link/complete", post(complete_device_link))
         .route("/users/:user_id/devices", get(list_user_devices))
-        .route("/guilds/:guild_id/channels", get(http_list_channels))
+        .route(
+            "/guilds/:guild_id/channels",
+            get(http_list_channels).post(http_create_channel),
+        )
+        .route(
+            "/guilds/:guild_id/channels/order",
+            put(http_reorder_channels),
+        )
         .route("/guilds/:guild_id/members", get(http_list_members))
+        .route(
+            "/guilds/:guild_id/roles",
+            get(http_list_roles).post(http_create_role),
+        )
+        .route(
+            "/guilds/:guild_id/roles/:role_id",
+            patch(http_update_role).delete(http_delete_role),
+        )
+        .route(
+            "/guilds/:guild_id/members/:member_id/roles/:role_id",
+            put(http_assign_member_role).delete(http_unassign_member_role),
+        )
+        .route(
+            "/channels/:channel_id",
+            patch(http_update_channel).delete(http_delete_channel),
+        )
         .route("/channels/:channel_id/messages", get(http_list_messages))
-        .route("/guilds/:guild_id/invites", post(http_create_invite))
-        .route("/guilds/join", post(http_join_guild))
-        .route("/messages", post(http_send_message))
+        .route(
+            "/channels/:channel_id/threads",
+            get(http_list_threads).post(http_create_thread),
+        )
+        .route(
+            "/threads/:thread_id/archived",
+            put(http_set_thread_archived),
+        )
+        .route(
+            "/threads/:thread_id/messages",
+            get(http_list_thread_messages),
+        )
+        .route(
+            "/channels/:channel_id/history_sharing",
+            post(http_set_channel_history_sharing),
+        )
+        .route(
+            "/channels/:channel_id/retention",
+            put(http_set_channel_retention),
+        )
+        .route(
+            "/channels/:channel_id/overwrites",
+            get(http_list_channel_overwrites).put(http_set_channel_overwrite),
+        )
+        .route(
+            "/channels/:channel_id/members",
+            get(http_list_channel_members),
+        )
+        .route(
+            "/guilds/:guild_id/members/:member_id/moderation",
+            post(http_moderate_member),
+        )
+        .route("/guilds/:guild_id/audit_log", get(http_list_audit_log))
+        .route("/guilds/:guild_id/storage", get(http_guild_storage_usage))
+        .route(
+            "/guilds/:guild_id/invites",
+            get(http_list_invites).post(http_create_invite),
+        )
+        .route(
+            "/guilds/:guild_id/invites/:invite_code",
+            delete(http_delete_invite),
+        )
+        .route(
+            "/guilds/join",
+            post(http_join_guild).layer(limit(RouteClass::InviteJoin)),
+        )
+        .route(
+            "/messages",
+            post(http_send_message).layer(limit(RouteClass::SendMessage)),
+        )
         .route("/livekit/token", post(http_request_livekit_token))
-        .route("/files/upload", post(upload_file))
+        .route(
+            "/files/upload",
+            post(upload_file)
+                .layer(DefaultBodyLimit::max(limits.max_attachment_bytes))
+                .layer(limit(RouteClass::Upload)),
+        )
+        .route(
+            "/files/uploads",
+            post(http_create_upload).layer(limit(RouteClass::Upload)),
+        )
+        .route(
+            "/files/uploads/:upload_id",
+            get(http_upload_status)
+                .put(http_append_upload_chunk)
+                .layer(DefaultBodyLimit::max(MAX_UPLOAD_CHUNK_BYTES)),
+        )
+        .route(
+            "/files/uploads/:upload_id/complete",
+            post(http_complete_upload),
+        )
         .route("/files/:file_id", get(download_file))
+        .route("/files/:file_id/thumbnail", put(http_link_thumbnail))
         .route(mls_key_packages_route(), post(upload_key_package))
-        .route(mls_key_packages_route(), get(fetch_key_package))
+        .route(
+            mls_key_packages_route(),
+            get(fetch_key_package).layer(limit(RouteClass::KeyPackageFetch)),
+        )
         .route(mls_welcome_route(), post(store_pending_welcome))
         .route(mls_welcome_route(), get(fetch_pending_welcome))
         .route(mls_welcome_recovery_route(), post(issue_recovery_welcome))
-        .route(mls_bootstrap_request_route(), post(request_mls_bootstrap))
+        .route(
+            mls_bootstrap_request_route(),
+            post(request_mls_bootstrap).layer(limit(RouteClass::BootstrapRequest)),
+        )
+        .route(mls_history_route(), post(http_store_history_bundle))
+        .route(mls_history_route(), get(http_list_history_bundles))
+        .route(
+            mls_backup_route(),
+            put(upload_mls_backup)
+                .get(download_mls_backup)
+                .layer(DefaultBodyLimit::max(limits.max_mls_backup_bytes)),
+        )
+        .route(
+            key_transparency_tree_head_route(),
+            get(http_key_transparency_tree_head),
+        )
+        .route(
+            key_transparency_inclusion_route(),
+            get(http_key_transparency_inclusion_proof),
+        )
+        .route(
+            key_transparency_consistency_route(),
+            get(http_key_transparency_consistency_proof),
+        )
         .route("/ws", get(ws_handler))
+        .nest("/admin", admin_router(Arc::clone(&state)))
+        .layer(middleware::from_fn_with_state(
+            Arc::clone(&state),
+            metrics::track,
+        ))
         .with_state(state)
 }
 
+/// Server administration, all behind [`admin_auth::require_token`].
+fn admin_router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/users", get(http_admin_list_users))
+        .route("/users/:user_id", get(http_admin_user))
+        .route(
+            "/users/:user_id/suspension",
+            put(http_admin_suspend_user).delete(http_admin_unsuspend_user),
+        )
+        .route("/users/:user_id/devices", get(http_admin_list_devices))
+        .route(
+            "/users/:user_id/devices/:device_id",
+            delete(http_admin_revoke_device),
+        )
+        .route("/guilds", get(http_admin_list_guilds))
+        .route("/guilds/:guild_id", delete(http_admin_delete_guild))
+        .route("/messages/:message_id", delete(http_admin_delete_message))
+        .route("/files/:file_id", delete(http_admin_delete_file))
+        .route("/mls/queues", get(http_admin_mls_queue))
+        .route("/storage", get(http_admin_storage_stats))
+        .route_layer(middleware::from_fn_with_state(
+            state,
+            admin_auth::require_token,
+        ))
+}
+
 fn api_error_status(error: &ApiError) -> StatusCode {
     match error.code {
         ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
         ErrorCode::Forbidden => StatusCode::FORBIDDEN,
         ErrorCode::NotFound => StatusCode::NOT_FOUND,
         ErrorCode::Validation => StatusCode::BAD_REQUEST,
+        ErrorCode::Conflict => StatusCode::CONFLICT,
+        ErrorCode::QuotaExceeded => StatusCode::PAYLOAD_TOO_LARGE,
         ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
         ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
     }
@@ -330,6 +713,204 @@ async fn healthz(
     Ok("ok")
 }
 
+#[derive(Debug, Serialize)]
+struct ReadinessReport {
+    ready: bool,
+    checks: Vec<ReadinessCheck>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReadinessCheck {
+    name: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Unlike `/healthz`, also fails while migrations are missing or the blob store cannot
+/// take writes, so a load balancer keeps traffic away until the instance can serve it.
+async fn readyz(State(state): State<Arc<AppState>>) -> (StatusCode, Json<ReadinessReport>) {
+    let storage = &state.api.storage;
+    let checks = vec![
+        ("database", storage.health_check().await),
+        ("migrations", storage.check_migrations().await),
+        ("blob_store", storage.check_blob_store().await),
+    ];
+    let checks: Vec<ReadinessCheck> = checks
+        .into_iter()
+        .map(|(name, result)| ReadinessCheck {
+            name,
+            ok: result.is_ok(),
+            error: result.err().map(|error| format!("{error:#}")),
+        })
+        .collect();
+    let ready = checks.iter().all(|check| check.ok);
+    if !ready {
+        warn!(?checks, "readiness check failed");
+    }
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(ReadinessReport { ready, checks }))
+}
+
+async fn http_metrics(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiError>)> {
+    let text = state
+        .metrics
+        .render(&state.api.storage)
+        .await
+        .map_err(|error| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiError::new(
+                    ErrorCode::Internal,
+                    format!("metrics collection failed: {error}"),
+                )),
+            )
+        })?;
+    Ok(([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], text))
+}
+
+async fn http_admin_list_users(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<AdminUserSearchQuery>,
+) -> Result<Json<Vec<AdminUserSummary>>, (StatusCode, Json<ApiError>)> {
+    let users = admin::list_users(&state.api, q.search.as_deref(), q.limit)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(users))
+}
+
+async fn http_admin_user(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<i64>,
+) -> Result<Json<AdminUserSummary>, (StatusCode, Json<ApiError>)> {
+    let user = admin::user(&state.api, UserId(user_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(user))
+}
+
+async fn http_admin_suspend_user(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<i64>,
+    Json(req): Json<SuspendUserRequest>,
+) -> Result<Json<AdminUserSummary>, (StatusCode, Json<ApiError>)> {
+    let user = admin::suspend_user(&state.api, UserId(user_id), req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(user_id, "admin: user suspended");
+    state.revoke(Revocation::UserSuspended(UserId(user_id)));
+    Ok(Json(user))
+}
+
+async fn http_admin_unsuspend_user(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<i64>,
+) -> Result<Json<AdminUserSummary>, (StatusCode, Json<ApiError>)> {
+    let user = admin::unsuspend_user(&state.api, UserId(user_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(user_id, "admin: user unsuspended");
+    Ok(Json(user))
+}
+
+async fn http_admin_list_devices(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<i64>,
+) -> Result<Json<Vec<shared::domain::LinkedDeviceSummary>>, (StatusCode, Json<ApiError>)> {
+    let devices = admin::list_devices(&state.api, UserId(user_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(devices))
+}
+
+async fn http_admin_revoke_device(
+    State(state): State<Arc<AppState>>,
+    Path((user_id, device_id)): Path<(i64, i64)>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    admin::revoke_device(&state.api, UserId(user_id), DeviceId(device_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(user_id, device_id, "admin: device revoked");
+    state.revoke(Revocation::DeviceRevoked(
+        UserId(user_id),
+        DeviceId(device_id),
+    ));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn http_admin_list_guilds(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<AdminGuildSummary>>, (StatusCode, Json<ApiError>)> {
+    let guilds = admin::list_guilds(&state.api)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(guilds))
+}
+
+async fn http_admin_delete_guild(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    let members = admin::delete_guild(&state.api, GuildId(guild_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(guild_id, "admin: guild deleted");
+    state.publish(Dispatch::to(
+        members,
+        ServerEvent::GuildDeleted {
+            guild_id: GuildId(guild_id),
+        },
+    ));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn http_admin_delete_message(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    let event = admin::delete_message(&state.api, MessageId(message_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(message_id, "admin: message deleted");
+    state.publish(event);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn http_admin_delete_file(
+    State(state): State<Arc<AppState>>,
+    Path(file_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    admin::delete_file(&state.api, &FileId(file_id.clone()))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(%file_id, "admin: file deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn http_admin_mls_queue(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<MlsQueueSummary>, (StatusCode, Json<ApiError>)> {
+    let queue = admin::mls_queue(&state.api)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(queue))
+}
+
+async fn http_admin_storage_stats(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ServerStorageStats>, (StatusCode, Json<ApiError>)> {
+    let stats = admin::storage_stats(&state.api)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(stats))
+}
+
 async fn login(
     State(state): State<Arc<AppState>>,
     Json(req): Json<LoginRequest>,
@@ -345,6 +926,7 @@ async fn login(
                 Json(ApiError::new(ErrorCode::Validation, e.to_string())),
             )
         })?;
+    reject_suspended(&state, user_id).await?;
 
     let guilds = state
         .api
@@ -449,6 +1031,39 @@ async fn get_my_device(
     }))
 }
 
+async fn revoke_device(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<DeviceQuery>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    let revoked = state
+        .api
+        .storage
+        .revoke_device(UserId(q.user_id), DeviceId(q.device_id))
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiError::new(ErrorCode::Internal, e.to_string())),
+            )
+        })?;
+
+    if !revoked {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(
+                ErrorCode::NotFound,
+                "active device not found",
+            )),
+        ));
+    }
+    state.revoke(Revocation::DeviceRevoked(
+        UserId(q.user_id),
+        DeviceId(q.device_id),
+    ));
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 async fn start_device_link(
     State(state): State<Arc<AppState>>,
     Query(q): Query<DeviceLinkStartQuery>,
@@ -736,12 +1351,13 @@ async fn upload_file(
         ));
     }
 
-    if body.len() > MAX_ATTACHMENT_BYTES {
+    let max_attachment_bytes = state.api.limits.max_attachment_bytes;
+    if body.len() > max_attachment_bytes {
         return Err((
             StatusCode::PAYLOAD_TOO_LARGE,
             Json(ApiError::new(
                 ErrorCode::Validation,
-                format!("attachment exceeds {} bytes", MAX_ATTACHMENT_BYTES),
+                format!("attachment exceeds {max_attachment_bytes} bytes"),
             )),
         ));
     }
@@ -751,113 +1367,220 @@ async fn upload_file(
         .as_deref()
         .map(str::trim)
         .filter(|name| !name.is_empty());
-    if let Some(name) = filename {
-        if name.len() > MAX_FILENAME_BYTES {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(ApiError::new(ErrorCode::Validation, "filename is too long")),
-            ));
-        }
-        if name.contains('/') || name.contains('\\') {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(ApiError::new(
-                    ErrorCode::Validation,
-                    "filename must not contain path separators",
-                )),
-            ));
-        }
-    }
-
-    ensure_active_membership_in_channel(
+    authorize_upload(
         &state.api,
         UserId(q.user_id),
         GuildId(q.guild_id),
         ChannelId(q.channel_id),
+        filename,
     )
     .await
     .map_err(|error| (api_error_status(&error), Json(error)))?;
 
-    let channel_guild = state
+    let file_id = state
         .api
         .storage
-        .guild_for_channel(ChannelId(q.channel_id))
+        .store_file_ciphertext(
+            UserId(q.user_id),
+            GuildId(q.guild_id),
+            ChannelId(q.channel_id),
+            &body,
+            q.mime_type
+                .as_deref()
+                .filter(|mime| !mime.trim().is_empty()),
+            filename,
+        )
         .await
         .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiError::new(ErrorCode::Internal, e.to_string())),
-            )
-        })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ApiError::new(ErrorCode::NotFound, "channel not found")),
-            )
+            let error = storage_error(e);
+            (api_error_status(&error), Json(error))
         })?;
-    if channel_guild != GuildId(q.guild_id) {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ApiError::new(
-                ErrorCode::Validation,
-                "channel does not belong to guild",
-            )),
-        ));
-    }
+    state
+        .metrics
+        .bytes_stored(StoredKind::File, body.len() as u64);
+    state.publish(ServerEvent::FileStored {
+        file_id: file_id.clone(),
+    });
+    Ok(Json(
+        serde_json::json!({ "file_id": file_id.0, "size_bytes": body.len() }),
+    ))
+}
 
-    let file_id = state
-        .api
-        .storage
-        .store_file_ciphertext(
-            UserId(q.user_id),
-            GuildId(q.guild_id),
-            ChannelId(q.channel_id),
-            &body,
-            q.mime_type
-                .as_deref()
-                .filter(|mime| !mime.trim().is_empty()),
-            filename,
-        )
+async fn http_create_upload(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<FileUploadQuery>,
+    Json(req): Json<CreateUploadRequest>,
+) -> Result<Json<UploadStatus>, (StatusCode, Json<ApiError>)> {
+    let status = create_upload(
+        &state.api,
+        UserId(q.user_id),
+        GuildId(q.guild_id),
+        ChannelId(q.channel_id),
+        q.filename
+            .as_deref()
+            .map(str::trim)
+            .filter(|name| !name.is_empty()),
+        q.mime_type
+            .as_deref()
+            .filter(|mime| !mime.trim().is_empty()),
+        req.total_bytes,
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(
+        upload_id = %status.upload_id,
+        total_bytes = status.total_bytes,
+        "files: upload session opened"
+    );
+    Ok(Json(status))
+}
+
+async fn http_upload_status(
+    State(state): State<Arc<AppState>>,
+    Path(upload_id): Path<String>,
+    Query(q): Query<UserQuery>,
+) -> Result<Json<UploadStatus>, (StatusCode, Json<ApiError>)> {
+    upload_status(&state.api, UserId(q.user_id), &upload_id)
         .await
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiError::new(ErrorCode::Internal, e.to_string())),
-            )
-        })?;
-    let _ = state.events.send(ServerEvent::FileStored { file_id });
+        .map(Json)
+        .map_err(|error| (api_error_status(&error), Json(error)))
+}
+
+async fn http_append_upload_chunk(
+    State(state): State<Arc<AppState>>,
+    Path(upload_id): Path<String>,
+    Query(q): Query<UploadChunkQuery>,
+    body: Bytes,
+) -> Result<Json<UploadStatus>, (StatusCode, Json<ApiError>)> {
+    append_upload_chunk(&state.api, UserId(q.user_id), &upload_id, q.offset, &body)
+        .await
+        .map(Json)
+        .map_err(|error| (api_error_status(&error), Json(error)))
+}
+
+async fn http_complete_upload(
+    State(state): State<Arc<AppState>>,
+    Path(upload_id): Path<String>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<CompleteUploadRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ApiError>)> {
+    let (file_id, size_bytes) =
+        complete_upload(&state.api, UserId(q.user_id), &upload_id, &req.sha256)
+            .await
+            .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(%upload_id, file_id = %file_id.0, size_bytes, "files: upload completed");
+    state.metrics.bytes_stored(StoredKind::File, size_bytes);
+    state.publish(ServerEvent::FileStored {
+        file_id: file_id.clone(),
+    });
     Ok(Json(
-        serde_json::json!({ "file_id": file_id.0, "size_bytes": body.len() }),
+        serde_json::json!({ "file_id": file_id.0, "size_bytes": size_bytes }),
     ))
 }
 
+async fn http_link_thumbnail(
+    State(state): State<Arc<AppState>>,
+    Path(file_id): Path<String>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<LinkThumbnailRequest>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    link_thumbnail(
+        &state.api,
+        UserId(q.user_id),
+        &FileId(file_id),
+        &req.thumbnail_file_id,
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The part of a file a download asks for through its `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    /// Bytes `start..end`.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parses a single `bytes=` range against a file of `size` bytes. Multiple ranges and
+/// malformed headers are ignored, so the whole file is sent, as RFC 9110 allows.
+fn byte_range(header: Option<&str>, size: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|value| value.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    let Some((first, last)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let (first, last) = (first.trim(), last.trim());
+    let (start, end) = if first.is_empty() {
+        // `bytes=-n` asks for the last `n` bytes.
+        match last.parse::<u64>() {
+            Ok(suffix) => (size.saturating_sub(suffix), size),
+            Err(_) => return ByteRange::Full,
+        }
+    } else {
+        let Ok(start) = first.parse::<u64>() else {
+            return ByteRange::Full;
+        };
+        let end = if last.is_empty() {
+            size
+        } else {
+            match last.parse::<u64>() {
+                Ok(last) if last >= start => last.saturating_add(1).min(size),
+                _ => return ByteRange::Full,
+            }
+        };
+        (start, end)
+    };
+    if start < end {
+        ByteRange::Partial(start, end)
+    } else {
+        ByteRange::Unsatisfiable
+    }
+}
+
 async fn download_file(
     State(state): State<Arc<AppState>>,
-    Path(file_id): Path<i64>,
+    Path(file_id): Path<String>,
     Query(q): Query<FileDownloadQuery>,
-) -> Result<impl IntoResponse, (StatusCode, Json<ApiError>)> {
-    let file = state
-        .api
-        .storage
-        .load_file(FileId(file_id))
-        .await
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiError::new(ErrorCode::Internal, e.to_string())),
-            )
-        })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ApiError::new(ErrorCode::NotFound, "file not found")),
-            )
-        })?;
-    ensure_active_membership_in_guild(&state.api, UserId(q.user_id), file.guild_id)
+    request_headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ApiError>)> {
+    let file = authorize_download(&state.api, UserId(q.user_id), &FileId(file_id))
         .await
         .map_err(|error| (api_error_status(&error), Json(error)))?;
 
+    let size = file.size_bytes;
+    let range = byte_range(
+        request_headers
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok()),
+        size,
+    );
     let mut headers = HeaderMap::new();
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    let (status, start, end) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, size),
+        ByteRange::Partial(start, end) => {
+            headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes {start}-{}/{size}", end - 1))
+                    .expect("numeric header value"),
+            );
+            (StatusCode::PARTIAL_CONTENT, start, end)
+        }
+        ByteRange::Unsatisfiable => {
+            headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{size}")).expect("numeric header value"),
+            );
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+        }
+    };
     let content_type = file
         .mime_type
         .as_deref()
@@ -867,13 +1590,32 @@ async fn download_file(
         HeaderValue::from_str(content_type)
             .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
     );
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(end - start));
     if let Some(filename) = file.filename {
         if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename=\"{filename}\"")) {
             headers.insert(header::CONTENT_DISPOSITION, value);
         }
     }
 
-    Ok((StatusCode::OK, headers, file.ciphertext))
+    // Read the ciphertext a slice at a time so large files never sit in memory whole.
+    let storage = state.api.storage.clone();
+    let file_id = file.file_id;
+    let body = futures::stream::try_unfold(start, move |offset| {
+        let storage = storage.clone();
+        let file_id = file_id.clone();
+        async move {
+            if offset >= end {
+                return Ok(None);
+            }
+            let next = end.min(offset + DOWNLOAD_READ_BYTES);
+            let bytes = storage
+                .read_file_range(&file_id, offset, next)
+                .await
+                .map_err(std::io::Error::other)?;
+            Ok::<_, std::io::Error>(Some((Bytes::from(bytes), next)))
+        }
+    });
+    Ok((status, headers, Body::from_stream(body)).into_response())
 }
 
 async fn upload_key_package(
@@ -916,9 +1658,7 @@ async fn upload_key_package(
         })?;
 
     if let Ok(members) = list_members(&state.api, user_id, guild_id).await {
-        let _ = state
-            .events
-            .send(ServerEvent::GuildMembersUpdated { guild_id, members });
+        state.publish(ServerEvent::GuildMembersUpdated { guild_id, members });
     }
 
     info!(
@@ -963,15 +1703,114 @@ async fn fetch_key_package(
             )
         })?;
 
+    let transparency = match key_package_device_id {
+        Some(device_id) => {
+            key_transparency_proof_for_device(&state.api, UserId(target_user_id), device_id)
+                .await
+                .map_err(|error| (api_error_status(&error), Json(error)))?
+        }
+        None => None,
+    };
+
     Ok(Json(KeyPackageResponse {
         key_package_id,
         guild_id: q.guild_id,
         user_id: target_user_id,
         device_id: key_package_device_id,
         key_package_b64: STANDARD.encode(key_package_bytes),
+        transparency,
     }))
 }
 
+async fn http_key_transparency_tree_head(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<SignedTreeHead>, (StatusCode, Json<ApiError>)> {
+    key_transparency_tree_head(&state.api)
+        .await
+        .map(Json)
+        .map_err(|error| (api_error_status(&error), Json(error)))
+}
+
+async fn http_key_transparency_inclusion_proof(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<InclusionProofQuery>,
+) -> Result<Json<InclusionProofResponse>, (StatusCode, Json<ApiError>)> {
+    key_transparency_inclusion_proof(&state.api, q.leaf_index, q.tree_size)
+        .await
+        .map(Json)
+        .map_err(|error| (api_error_status(&error), Json(error)))
+}
+
+async fn http_key_transparency_consistency_proof(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<ConsistencyProofQuery>,
+) -> Result<Json<ConsistencyProofResponse>, (StatusCode, Json<ApiError>)> {
+    key_transparency_consistency_proof(&state.api, q.first_size, q.second_size)
+        .await
+        .map(Json)
+        .map_err(|error| (api_error_status(&error), Json(error)))
+}
+
+async fn upload_mls_backup(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<UserQuery>,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    if body.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::new(
+                ErrorCode::Validation,
+                "backup body cannot be empty",
+            )),
+        ));
+    }
+
+    state
+        .api
+        .storage
+        .store_mls_backup(UserId(q.user_id), &body)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiError::new(ErrorCode::Internal, e.to_string())),
+            )
+        })?;
+    info!(
+        user_id = q.user_id,
+        backup_size = body.len(),
+        "mls: encrypted state backup stored"
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn download_mls_backup(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<UserQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiError>)> {
+    let backup = state
+        .api
+        .storage
+        .load_mls_backup(UserId(q.user_id))
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiError::new(ErrorCode::Internal, e.to_string())),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiError::new(ErrorCode::NotFound, "no backup stored")),
+            )
+        })?;
+
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], backup))
+}
+
 async fn fetch_pending_welcome(
     State(state): State<Arc<AppState>>,
     Query(q): Query<MlsWelcomeQuery>,
@@ -1095,7 +1934,7 @@ async fn store_pending_welcome(
             )
         })?;
 
-    let _ = state.events.send(ServerEvent::MlsWelcomeAvailable {
+    state.publish(ServerEvent::MlsWelcomeAvailable {
         guild_id: GuildId(q.guild_id),
         channel_id: ChannelId(q.channel_id),
         target_user_id: UserId(q.target_user_id),
@@ -1136,7 +1975,7 @@ async fn request_mls_bootstrap(
         .map_err(|error| (api_error_status(&error), Json(error)))?;
     }
 
-    let _ = state.events.send(ServerEvent::MlsBootstrapRequested {
+    state.publish(ServerEvent::MlsBootstrapRequested {
         guild_id: GuildId(q.guild_id),
         channel_id: ChannelId(q.channel_id),
         requesting_user_id: UserId(q.user_id),
@@ -1223,7 +2062,7 @@ async fn issue_recovery_welcome(
             )
         })?;
 
-    let _ = state.events.send(ServerEvent::MlsWelcomeAvailable {
+    state.publish(ServerEvent::MlsWelcomeAvailable {
         guild_id: GuildId(q.guild_id),
         channel_id: ChannelId(q.channel_id),
         target_user_id: UserId(q.target_user_id),
@@ -1244,45 +2083,210 @@ async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
     Query(q): Query<WsQuery>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| ws_connection(state, socket, UserId(q.user_id)))
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+) -> Response {
+    let user_id = UserId(q.user_id);
+    let device_id = q.device_id.map(DeviceId);
+    // Subscribed before the check so a revocation cannot slip in between.
+    let revocations = state.revocations.subscribe();
+    match current_revocation(&state, user_id, device_id).await {
+        Ok(None) => {}
+        Ok(Some(revocation)) => {
+            return (StatusCode::FORBIDDEN, Json(revocation.reason())).into_response();
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiError::new(ErrorCode::Internal, e.to_string())),
+            )
+                .into_response();
+        }
+    }
+    let ip = connect_info.map(|ConnectInfo(addr)| addr.ip());
+    let connection = WsConnection {
+        user_id,
+        device_id,
+        ip,
+        revocations,
+    };
+    ws.on_upgrade(move |socket| ws_connection(state, socket, connection))
+}
+
+/// Whether a WebSocket for `user_id`, and `device_id` when it named one, may no longer stay
+/// open, as recorded in storage.
+async fn current_revocation(
+    state: &AppState,
+    user_id: UserId,
+    device_id: Option<DeviceId>,
+) -> anyhow::Result<Option<Revocation>> {
+    if state.api.storage.user_is_suspended(user_id).await? {
+        return Ok(Some(Revocation::UserSuspended(user_id)));
+    }
+    if let Some(device_id) = device_id {
+        let active = state
+            .api
+            .storage
+            .get_device(user_id, device_id)
+            .await?
+            .is_some_and(|device| !device.is_revoked);
+        if !active {
+            return Ok(Some(Revocation::DeviceRevoked(user_id, device_id)));
+        }
+    }
+    Ok(None)
+}
+
+/// Login checks this itself, and the WebSocket through [`current_revocation`]; guild
+/// routes get it from [`permissions::resolve`].
+async fn reject_suspended(
+    state: &AppState,
+    user_id: UserId,
+) -> Result<(), (StatusCode, Json<ApiError>)> {
+    let suspended = state
+        .api
+        .storage
+        .user_is_suspended(user_id)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiError::new(ErrorCode::Internal, e.to_string())),
+            )
+        })?;
+    if suspended {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new(ErrorCode::Forbidden, "account is suspended")),
+        ));
+    }
+    Ok(())
+}
+
+struct WsConnection {
+    user_id: UserId,
+    device_id: Option<DeviceId>,
+    ip: Option<std::net::IpAddr>,
+    revocations: tokio::sync::broadcast::Receiver<Revocation>,
 }
 
 async fn ws_connection(
     state: Arc<AppState>,
     socket: axum::extract::ws::WebSocket,
-    user_id: UserId,
+    connection: WsConnection,
 ) {
     use axum::extract::ws::Message;
     use futures::{SinkExt, StreamExt};
 
+    let WsConnection {
+        user_id,
+        device_id,
+        ip,
+        mut revocations,
+    } = connection;
     let (mut sender, mut receiver) = socket.split();
     let mut events_rx = state.events.subscribe();
+    state.metrics.ws_connected();
+    let (close_tx, mut close_rx) = tokio::sync::oneshot::channel::<ApiError>();
 
     let send_state = Arc::clone(&state);
-    let send_task = tokio::spawn(async move {
-        loop {
-            let event = match events_rx.recv().await {
-                Ok(event) => event,
-                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
-                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+    let mut send_task = tokio::spawn(async move {
+        let close_reason = loop {
+            let dispatch = tokio::select! {
+                reason = &mut close_rx => break reason.ok(),
+                revocation = revocations.recv() => {
+                    let revoked = match revocation {
+                        Ok(revocation) => {
+                            revocation.closes(user_id, device_id).then_some(revocation)
+                        }
+                        // A missed revocation may have been this one; storage has the answer.
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                            current_revocation(&send_state, user_id, device_id)
+                                .await
+                                .ok()
+                                .flatten()
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+                    };
+                    match revoked {
+                        Some(revocation) => {
+                            info!(
+                                user_id = user_id.0,
+                                ?revocation,
+                                "ws: access revoked; closing"
+                            );
+                            break Some(revocation.reason());
+                        }
+                        None => continue,
+                    }
+                }
+                event = events_rx.recv() => match event {
+                    Ok(dispatch) => dispatch,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        send_state.metrics.events_lagged(skipped);
+                        continue;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+                },
             };
-            if !is_event_visible_to_user(&send_state, user_id, &event).await {
+            if !is_dispatch_visible_to_user(&send_state, user_id, &dispatch).await {
                 continue;
             }
-            let text = match serde_json::to_string(&event) {
+            let text = match serde_json::to_string(&dispatch.event) {
                 Ok(v) => v,
                 Err(_) => continue,
             };
             if sender.send(Message::Text(text)).await.is_err() {
-                break;
+                return;
+            }
+            send_state.metrics.event_delivered();
+        };
+        if let Some(error) = close_reason {
+            if let Ok(text) = serde_json::to_string(&ServerEvent::Error(error)) {
+                let _ = sender.send(Message::Text(text)).await;
             }
         }
+        let _ = sender.send(Message::Close(None)).await;
     });
 
-    while let Some(Ok(_msg)) = receiver.next().await {}
+    loop {
+        let frame = tokio::select! {
+            // The sender closed the socket; stop reading from it too.
+            _ = &mut send_task => break,
+            frame = receiver.next() => frame,
+        };
+        let Some(Ok(_msg)) = frame else {
+            break;
+        };
+        if let Err(limited) = state
+            .rate_limiter
+            .check(RouteClass::WsFrame, Some(user_id.0), ip)
+        {
+            warn!(
+                user_id = user_id.0,
+                "ws: inbound frame rate exceeded; closing"
+            );
+            let _ = close_tx.send(limited.to_api_error());
+            // Let the sender deliver the error and close frame before giving up on it.
+            let _ = tokio::time::timeout(std::time::Duration::from_secs(1), &mut send_task).await;
+            break;
+        }
+    }
 
     send_task.abort();
+    state.metrics.ws_disconnected();
+}
+
+/// Deliveries with an audience go to exactly those users; everything else is scoped by
+/// [`is_event_visible_to_user`].
+async fn is_dispatch_visible_to_user(
+    state: &Arc<AppState>,
+    user_id: UserId,
+    dispatch: &Dispatch,
+) -> bool {
+    match &dispatch.audience {
+        Some(audience) => audience.contains(&user_id),
+        None => is_event_visible_to_user(state, user_id, &dispatch.event).await,
+    }
 }
 
 async fn is_event_visible_to_user(
@@ -1291,16 +2295,35 @@ async fn is_event_visible_to_user(
     event: &ServerEvent,
 ) -> bool {
     let is_member = |guild_id: GuildId| async move {
-        matches!(
-            state.api.storage.membership_status(guild_id, user_id).await,
-            Ok(Some((_role, banned, _muted))) if !banned
-        )
+        permissions::compute(&state.api, user_id, guild_id, None)
+            .await
+            .is_ok()
+    };
+    let can_view = |guild_id: GuildId, channel_id: ChannelId| async move {
+        permissions::compute(&state.api, user_id, guild_id, Some(channel_id))
+            .await
+            .is_ok()
     };
 
     match event {
         ServerEvent::GuildUpdated { guild } => is_member(guild.guild_id).await,
-        ServerEvent::ChannelUpdated { channel } => is_member(channel.guild_id).await,
-        ServerEvent::GuildMembersUpdated { guild_id, .. } => is_member(*guild_id).await,
+        ServerEvent::DirectChannelUpdated { channel } => channel
+            .participants
+            .iter()
+            .any(|participant| participant.user_id == user_id),
+        ServerEvent::ChannelUpdated { channel } => {
+            can_view(channel.guild_id, channel.channel_id).await
+        }
+        ServerEvent::ThreadUpdated { thread } => can_view(thread.guild_id, thread.channel_id).await,
+        ServerEvent::MessagesExpired {
+            guild_id,
+            channel_id,
+            ..
+        } => can_view(*guild_id, *channel_id).await,
+        ServerEvent::GuildMembersUpdated { guild_id, .. }
+        | ServerEvent::GuildRolesUpdated { guild_id, .. } => is_member(*guild_id).await,
+        // Whoever could see these is gone with them, so they are only sent with an audience.
+        ServerEvent::GuildDeleted { .. } | ServerEvent::ChannelDeleted { .. } => false,
         ServerEvent::MessageReceived { message } => {
             let guild_id = match state
                 .api
@@ -1311,7 +2334,7 @@ async fn is_event_visible_to_user(
                 Ok(Some(guild_id)) => guild_id,
                 _ => return false,
             };
-            is_member(guild_id).await
+            can_view(guild_id, message.channel_id).await
         }
         ServerEvent::UserKicked {
             guild_id,
@@ -1324,15 +2347,34 @@ async fn is_event_visible_to_user(
         | ServerEvent::UserMuted {
             guild_id,
             target_user_id,
+        }
+        | ServerEvent::UserLeft {
+            guild_id,
+            user_id: target_user_id,
         } => *target_user_id == user_id || is_member(*guild_id).await,
-        ServerEvent::LiveKitTokenIssued { guild_id, .. }
-        | ServerEvent::MlsBootstrapRequested { guild_id, .. } => is_member(*guild_id).await,
+        ServerEvent::LiveKitTokenIssued {
+            guild_id,
+            channel_id,
+            ..
+        }
+        | ServerEvent::MlsBootstrapRequested {
+            guild_id,
+            channel_id,
+            ..
+        } => can_view(*guild_id, *channel_id).await,
         ServerEvent::MlsWelcomeAvailable {
             guild_id,
+            channel_id,
             target_user_id,
             ..
-        } => *target_user_id == user_id && is_member(*guild_id).await,
-        ServerEvent::FileStored { .. } | ServerEvent::Error(_) => true,
+        } => *target_user_id == user_id && can_view(*guild_id, *channel_id).await,
+        ServerEvent::FileStored { file_id } => {
+            match state.api.storage.load_file_metadata(file_id).await {
+                Ok(Some(file)) => can_view(file.guild_id, file.channel_id).await,
+                _ => false,
+            }
+        }
+        ServerEvent::Error(_) => true,
     }
 }
 
@@ -1346,35 +2388,370 @@ async fn http_list_guilds(
     Ok(Json(guilds))
 }
 
-async fn http_list_channels(
+async fn http_create_guild(
     State(state): State<Arc<AppState>>,
-    Path(guild_id): Path<i64>,
     Query(q): Query<UserQuery>,
-) -> Result<Json<Vec<shared::protocol::ChannelSummary>>, (StatusCode, Json<ApiError>)> {
-    let channels = list_channels(&state.api, UserId(q.user_id), GuildId(guild_id))
+    Json(req): Json<CreateGuildRequest>,
+) -> Result<Json<GuildSummary>, (StatusCode, Json<ApiError>)> {
+    let guild = create_guild(&state.api, UserId(q.user_id), req)
         .await
         .map_err(|error| (api_error_status(&error), Json(error)))?;
-    Ok(Json(channels))
+    info!(guild_id = guild.guild_id.0, "guild: created");
+    state.publish(ServerEvent::GuildUpdated {
+        guild: guild.clone(),
+    });
+    Ok(Json(guild))
 }
 
-async fn http_list_members(
+async fn http_rename_guild(
     State(state): State<Arc<AppState>>,
     Path(guild_id): Path<i64>,
     Query(q): Query<UserQuery>,
-) -> Result<Json<Vec<shared::protocol::MemberSummary>>, (StatusCode, Json<ApiError>)> {
-    let members = list_members(&state.api, UserId(q.user_id), GuildId(guild_id))
+    Json(req): Json<UpdateGuildRequest>,
+) -> Result<Json<GuildSummary>, (StatusCode, Json<ApiError>)> {
+    let guild = rename_guild(&state.api, UserId(q.user_id), GuildId(guild_id), req)
         .await
         .map_err(|error| (api_error_status(&error), Json(error)))?;
-    Ok(Json(members))
+    state.publish(ServerEvent::GuildUpdated {
+        guild: guild.clone(),
+    });
+    Ok(Json(guild))
 }
 
-async fn http_list_messages(
+async fn http_reorder_guilds(
     State(state): State<Arc<AppState>>,
-    Path(channel_id): Path<i64>,
-    Query(q): Query<ListMessagesQuery>,
-) -> Result<Json<Vec<shared::protocol::MessagePayload>>, (StatusCode, Json<ApiError>)> {
-    let limit = q.limit.unwrap_or(100).clamp(1, 100);
-    let messages = list_messages(
+    Query(q): Query<UserQuery>,
+    Json(req): Json<ReorderGuildsRequest>,
+) -> Result<Json<Vec<GuildSummary>>, (StatusCode, Json<ApiError>)> {
+    let guilds = reorder_guilds(&state.api, UserId(q.user_id), req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(guilds))
+}
+
+async fn http_delete_guild(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<DeleteGuildRequest>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    let members = delete_guild(&state.api, UserId(q.user_id), GuildId(guild_id), req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(guild_id, "guild: deleted");
+    state.publish(Dispatch::to(
+        members,
+        ServerEvent::GuildDeleted {
+            guild_id: GuildId(guild_id),
+        },
+    ));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn http_list_direct_channels(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<UserQuery>,
+) -> Result<Json<Vec<DirectChannelSummary>>, (StatusCode, Json<ApiError>)> {
+    let channels = list_direct_channels(&state.api, UserId(q.user_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(channels))
+}
+
+async fn http_create_direct_channel(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<CreateDirectChannelRequest>,
+) -> Result<Json<DirectChannelSummary>, (StatusCode, Json<ApiError>)> {
+    let user_id = UserId(q.user_id);
+    let channel = create_direct_channel(&state.api, user_id, req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(
+        user_id = user_id.0,
+        channel_id = channel.channel.channel_id.0,
+        participants = channel.participants.len(),
+        "dm: conversation opened"
+    );
+    state.publish(ServerEvent::DirectChannelUpdated {
+        channel: channel.clone(),
+    });
+    Ok(Json(channel))
+}
+
+async fn http_leave_guild(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    let user_id = UserId(q.user_id);
+    let guild_id = GuildId(guild_id);
+    let members = leave_guild(&state.api, user_id, guild_id)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(
+        guild_id = guild_id.0,
+        user_id = user_id.0,
+        "guild: member left"
+    );
+    state.publish(ServerEvent::UserLeft { guild_id, user_id });
+    state.publish(ServerEvent::GuildMembersUpdated { guild_id, members });
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn http_transfer_ownership(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<TransferOwnershipRequest>,
+) -> Result<Json<Vec<shared::protocol::MemberSummary>>, (StatusCode, Json<ApiError>)> {
+    let guild_id = GuildId(guild_id);
+    let new_owner_id = req.new_owner_id;
+    let members = transfer_ownership(&state.api, UserId(q.user_id), guild_id, req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(
+        guild_id = guild_id.0,
+        new_owner_id = new_owner_id.0,
+        "guild: ownership transferred"
+    );
+    state.publish(ServerEvent::GuildMembersUpdated {
+        guild_id,
+        members: members.clone(),
+    });
+    Ok(Json(members))
+}
+
+async fn http_create_channel(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<CreateChannelRequest>,
+) -> Result<Json<ChannelSummary>, (StatusCode, Json<ApiError>)> {
+    let channel = create_channel(&state.api, UserId(q.user_id), GuildId(guild_id), req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(
+        guild_id,
+        channel_id = channel.channel_id.0,
+        "channel: created"
+    );
+    state.publish(ServerEvent::ChannelUpdated {
+        channel: channel.clone(),
+    });
+    Ok(Json(channel))
+}
+
+async fn http_update_channel(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<UpdateChannelRequest>,
+) -> Result<Json<ChannelSummary>, (StatusCode, Json<ApiError>)> {
+    let channel = update_channel(&state.api, UserId(q.user_id), ChannelId(channel_id), req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    state.publish(ServerEvent::ChannelUpdated {
+        channel: channel.clone(),
+    });
+    Ok(Json(channel))
+}
+
+async fn http_reorder_channels(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<ReorderChannelsRequest>,
+) -> Result<Json<Vec<ChannelSummary>>, (StatusCode, Json<ApiError>)> {
+    let channels = reorder_channels(&state.api, UserId(q.user_id), GuildId(guild_id), req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    // Re-announce every channel, not just the caller's visible ones; delivery is still
+    // filtered per recipient.
+    for channel in channels_in_guild(&state, GuildId(guild_id)).await {
+        state.publish(ServerEvent::ChannelUpdated { channel });
+    }
+    Ok(Json(channels))
+}
+
+async fn http_delete_channel(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    let (guild_id, viewers) = delete_channel(&state.api, UserId(q.user_id), ChannelId(channel_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(guild_id = guild_id.0, channel_id, "channel: deleted");
+    state.publish(Dispatch::to(
+        viewers,
+        ServerEvent::ChannelDeleted {
+            guild_id,
+            channel_id: ChannelId(channel_id),
+        },
+    ));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn channels_in_guild(state: &Arc<AppState>, guild_id: GuildId) -> Vec<ChannelSummary> {
+    let Ok(channels) = state.api.storage.list_channels_for_guild(guild_id).await else {
+        return Vec::new();
+    };
+    let mut summaries = Vec::with_capacity(channels.len());
+    for channel in channels {
+        if let Ok(summary) = channel_summary(&state.api, channel.channel_id).await {
+            summaries.push(summary);
+        }
+    }
+    summaries
+}
+
+async fn http_list_channels(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+) -> Result<Json<Vec<shared::protocol::ChannelSummary>>, (StatusCode, Json<ApiError>)> {
+    let channels = list_channels(&state.api, UserId(q.user_id), GuildId(guild_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(channels))
+}
+
+async fn http_list_members(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+) -> Result<Json<Vec<shared::protocol::MemberSummary>>, (StatusCode, Json<ApiError>)> {
+    let members = list_members(&state.api, UserId(q.user_id), GuildId(guild_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(members))
+}
+
+async fn http_list_roles(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+) -> Result<Json<Vec<RoleSummary>>, (StatusCode, Json<ApiError>)> {
+    let roles = list_roles(&state.api, UserId(q.user_id), GuildId(guild_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(roles))
+}
+
+async fn http_create_role(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<CreateRoleRequest>,
+) -> Result<Json<RoleSummary>, (StatusCode, Json<ApiError>)> {
+    let role = create_role(&state.api, UserId(q.user_id), GuildId(guild_id), req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(guild_id, role_id = role.role_id.0, "guild: role created");
+    broadcast_guild_roles(&state, UserId(q.user_id), GuildId(guild_id)).await;
+    Ok(Json(role))
+}
+
+async fn http_update_role(
+    State(state): State<Arc<AppState>>,
+    Path((guild_id, role_id)): Path<(i64, i64)>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<UpdateRoleRequest>,
+) -> Result<Json<RoleSummary>, (StatusCode, Json<ApiError>)> {
+    let role = update_role(
+        &state.api,
+        UserId(q.user_id),
+        GuildId(guild_id),
+        RoleId(role_id),
+        req,
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    broadcast_guild_roles(&state, UserId(q.user_id), GuildId(guild_id)).await;
+    Ok(Json(role))
+}
+
+async fn http_delete_role(
+    State(state): State<Arc<AppState>>,
+    Path((guild_id, role_id)): Path<(i64, i64)>,
+    Query(q): Query<UserQuery>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    delete_role(
+        &state.api,
+        UserId(q.user_id),
+        GuildId(guild_id),
+        RoleId(role_id),
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(guild_id, role_id, "guild: role deleted");
+    broadcast_guild_roles(&state, UserId(q.user_id), GuildId(guild_id)).await;
+    broadcast_guild_members(&state, UserId(q.user_id), GuildId(guild_id)).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn http_assign_member_role(
+    State(state): State<Arc<AppState>>,
+    Path((guild_id, member_id, role_id)): Path<(i64, i64, i64)>,
+    Query(q): Query<UserQuery>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    update_member_role(&state, q.user_id, guild_id, member_id, role_id, true).await
+}
+
+async fn http_unassign_member_role(
+    State(state): State<Arc<AppState>>,
+    Path((guild_id, member_id, role_id)): Path<(i64, i64, i64)>,
+    Query(q): Query<UserQuery>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    update_member_role(&state, q.user_id, guild_id, member_id, role_id, false).await
+}
+
+async fn update_member_role(
+    state: &Arc<AppState>,
+    user_id: i64,
+    guild_id: i64,
+    member_id: i64,
+    role_id: i64,
+    assigned: bool,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    set_member_role(
+        &state.api,
+        UserId(user_id),
+        GuildId(guild_id),
+        UserId(member_id),
+        RoleId(role_id),
+        assigned,
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(
+        guild_id,
+        member_id, role_id, assigned, "guild: member role updated"
+    );
+    broadcast_guild_members(state, UserId(user_id), GuildId(guild_id)).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn broadcast_guild_roles(state: &Arc<AppState>, user_id: UserId, guild_id: GuildId) {
+    if let Ok(roles) = list_roles(&state.api, user_id, guild_id).await {
+        state.publish(ServerEvent::GuildRolesUpdated { guild_id, roles });
+    }
+}
+
+async fn broadcast_guild_members(state: &Arc<AppState>, user_id: UserId, guild_id: GuildId) {
+    if let Ok(members) = list_members(&state.api, user_id, guild_id).await {
+        state.publish(ServerEvent::GuildMembersUpdated { guild_id, members });
+    }
+}
+
+async fn http_list_messages(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<i64>,
+    Query(q): Query<ListMessagesQuery>,
+) -> Result<Json<Vec<shared::protocol::MessagePayload>>, (StatusCode, Json<ApiError>)> {
+    let limit = q.limit.unwrap_or(100).clamp(1, 100);
+    let messages = list_messages(
         &state.api,
         UserId(q.user_id),
         ChannelId(channel_id),
@@ -1386,99 +2763,375 @@ async fn http_list_messages(
     Ok(Json(messages))
 }
 
+async fn http_list_threads(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<i64>,
+    Query(q): Query<ListThreadsQuery>,
+) -> Result<Json<Vec<ThreadSummary>>, (StatusCode, Json<ApiError>)> {
+    let threads = list_threads(
+        &state.api,
+        UserId(q.user_id),
+        ChannelId(channel_id),
+        q.include_archived,
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(threads))
+}
+
+async fn http_create_thread(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<CreateThreadRequest>,
+) -> Result<Json<ThreadSummary>, (StatusCode, Json<ApiError>)> {
+    let thread = create_thread(&state.api, UserId(q.user_id), ChannelId(channel_id), req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    state.publish(ServerEvent::ThreadUpdated {
+        thread: thread.clone(),
+    });
+    Ok(Json(thread))
+}
+
+async fn http_set_thread_archived(
+    State(state): State<Arc<AppState>>,
+    Path(thread_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<ArchiveThreadRequest>,
+) -> Result<Json<ThreadSummary>, (StatusCode, Json<ApiError>)> {
+    let thread = set_thread_archived(
+        &state.api,
+        UserId(q.user_id),
+        ThreadId(thread_id),
+        req.archived,
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    state.publish(ServerEvent::ThreadUpdated {
+        thread: thread.clone(),
+    });
+    Ok(Json(thread))
+}
+
+async fn http_list_thread_messages(
+    State(state): State<Arc<AppState>>,
+    Path(thread_id): Path<i64>,
+    Query(q): Query<ListMessagesQuery>,
+) -> Result<Json<Vec<shared::protocol::MessagePayload>>, (StatusCode, Json<ApiError>)> {
+    let limit = q.limit.unwrap_or(100).clamp(1, 100);
+    let messages = list_thread_messages(
+        &state.api,
+        UserId(q.user_id),
+        ThreadId(thread_id),
+        limit,
+        q.before,
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(messages))
+}
+
+async fn http_set_channel_history_sharing(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<i64>,
+    Query(q): Query<ChannelHistorySharingQuery>,
+) -> Result<Json<shared::protocol::ChannelSummary>, (StatusCode, Json<ApiError>)> {
+    let channel = set_channel_history_sharing(
+        &state.api,
+        UserId(q.user_id),
+        ChannelId(channel_id),
+        q.enabled,
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(
+        channel_id,
+        enabled = q.enabled,
+        "channel: history sharing updated"
+    );
+    state.publish(ServerEvent::ChannelUpdated {
+        channel: channel.clone(),
+    });
+    Ok(Json(channel))
+}
+
+async fn http_set_channel_retention(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<RetentionPolicy>,
+) -> Result<Json<ChannelSummary>, (StatusCode, Json<ApiError>)> {
+    let channel = set_channel_retention(&state.api, UserId(q.user_id), ChannelId(channel_id), req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(
+        channel_id,
+        max_age_seconds = ?req.max_age_seconds,
+        max_messages = ?req.max_messages,
+        "channel: retention updated"
+    );
+    state.publish(ServerEvent::ChannelUpdated {
+        channel: channel.clone(),
+    });
+    Ok(Json(channel))
+}
+
+/// Purges expired messages every `interval` and tells viewers which ones are gone.
+async fn run_retention_purge(state: Arc<AppState>, interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match purge_expired(&state.api).await {
+            Ok((purge, events)) => {
+                let messages: usize = purge.messages.iter().map(|(_, _, ids)| ids.len()).sum();
+                if messages > 0 || purge.files > 0 || purge.welcomes > 0 {
+                    info!(
+                        messages,
+                        files = purge.files,
+                        welcomes = purge.welcomes,
+                        "retention: purge complete"
+                    );
+                }
+                for event in events {
+                    state.publish(event);
+                }
+            }
+            Err(error) => error!(%error, "retention: purge failed"),
+        }
+    }
+}
+
+async fn http_list_channel_overwrites(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+) -> Result<Json<Vec<PermissionOverwrite>>, (StatusCode, Json<ApiError>)> {
+    let overwrites = list_channel_overwrites(&state.api, UserId(q.user_id), ChannelId(channel_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(overwrites))
+}
+
+async fn http_set_channel_overwrite(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+    Json(overwrite): Json<PermissionOverwrite>,
+) -> Result<Json<shared::protocol::ChannelSummary>, (StatusCode, Json<ApiError>)> {
+    let channel = set_channel_overwrite(
+        &state.api,
+        UserId(q.user_id),
+        ChannelId(channel_id),
+        overwrite,
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(channel_id, "channel: permission overwrite updated");
+    // Viewers reconcile the channel's MLS group against the new member list on this event.
+    state.publish(ServerEvent::ChannelUpdated {
+        channel: channel.clone(),
+    });
+    Ok(Json(channel))
+}
+
+async fn http_list_channel_members(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+) -> Result<Json<Vec<shared::protocol::MemberSummary>>, (StatusCode, Json<ApiError>)> {
+    let members = list_channel_members(&state.api, UserId(q.user_id), ChannelId(channel_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(members))
+}
+
+async fn http_store_history_bundle(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<StoreHistoryBundleQuery>,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    store_history_bundle(
+        &state.api,
+        UserId(q.user_id),
+        GuildId(q.guild_id),
+        ChannelId(q.channel_id),
+        UserId(q.target_user_id),
+        q.target_device_id.map(DeviceId),
+        &body,
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(
+        guild_id = q.guild_id,
+        channel_id = q.channel_id,
+        target_user_id = q.target_user_id,
+        bundle_size = body.len(),
+        "mls: history bundle stored"
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn http_list_history_bundles(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<MlsWelcomeQuery>,
+) -> Result<Json<Vec<HistoryBundleResponse>>, (StatusCode, Json<ApiError>)> {
+    let bundles = list_history_bundles(
+        &state.api,
+        UserId(q.user_id),
+        GuildId(q.guild_id),
+        ChannelId(q.channel_id),
+        q.device_id.map(DeviceId),
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(bundles))
+}
+
 async fn http_create_invite(
     State(state): State<Arc<AppState>>,
     Path(guild_id): Path<i64>,
     Query(q): Query<UserQuery>,
-) -> Result<Json<InviteResponse>, (StatusCode, Json<ApiError>)> {
-    let guild_id = GuildId(guild_id);
-    let user_id = UserId(q.user_id);
-    state
-        .api
-        .storage
-        .membership_status(guild_id, user_id)
+) -> Result<Json<InviteSummary>, (StatusCode, Json<ApiError>)> {
+    let invite = create_invite(&state.api, UserId(q.user_id), GuildId(guild_id))
         .await
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiError::new(ErrorCode::Internal, e.to_string())),
-            )
-        })?
-        .ok_or_else(|| {
-            (
-                StatusCode::FORBIDDEN,
-                Json(ApiError::new(ErrorCode::Forbidden, "user is not a member")),
-            )
-        })?;
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(invite))
+}
 
-    let payload = format!("guild:{}", guild_id.0);
-    let invite_code = URL_SAFE_NO_PAD.encode(payload.as_bytes());
-    Ok(Json(InviteResponse { invite_code }))
+async fn http_list_invites(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+) -> Result<Json<Vec<InviteSummary>>, (StatusCode, Json<ApiError>)> {
+    let invites = list_invites(&state.api, UserId(q.user_id), GuildId(guild_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(invites))
+}
+
+async fn http_guild_storage_usage(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<UserQuery>,
+) -> Result<Json<GuildStorageUsage>, (StatusCode, Json<ApiError>)> {
+    let usage = guild_storage_usage(&state.api, UserId(q.user_id), GuildId(guild_id))
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(usage))
+}
+
+async fn http_delete_invite(
+    State(state): State<Arc<AppState>>,
+    Path((guild_id, invite_code)): Path<(i64, String)>,
+    Query(q): Query<UserQuery>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    delete_invite(
+        &state.api,
+        UserId(q.user_id),
+        GuildId(guild_id),
+        &invite_code,
+    )
+    .await
+    .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
 async fn http_join_guild(
     State(state): State<Arc<AppState>>,
     Json(req): Json<JoinGuildRequest>,
 ) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
-    let decoded = URL_SAFE_NO_PAD
-        .decode(req.invite_code.as_bytes())
-        .map_err(|_| {
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ApiError::new(ErrorCode::Validation, "invalid invite code")),
-            )
-        })?;
-    let decoded_text = String::from_utf8(decoded).map_err(|_| {
-        (
-            StatusCode::BAD_REQUEST,
-            Json(ApiError::new(ErrorCode::Validation, "invalid invite code")),
-        )
-    })?;
-
-    let guild_id = decoded_text
-        .strip_prefix("guild:")
-        .and_then(|id| id.parse::<i64>().ok())
-        .map(GuildId)
-        .ok_or_else(|| {
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ApiError::new(ErrorCode::Validation, "invalid invite code")),
-            )
-        })?;
-
     let joining_user_id = UserId(req.user_id);
+    let guild_id = join_with_invite(&state.api, joining_user_id, &req.invite_code)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
     info!(
         guild_id = guild_id.0,
         user_id = joining_user_id.0,
         "guild: join with invite"
     );
-    state
-        .api
-        .storage
-        .add_membership(
-            guild_id,
-            joining_user_id,
-            shared::domain::Role::Member,
-            false,
-            false,
-        )
-        .await
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiError::new(ErrorCode::Internal, e.to_string())),
-            )
-        })?;
 
     if let Ok(members) = list_members(&state.api, joining_user_id, guild_id).await {
-        let _ = state
-            .events
-            .send(ServerEvent::GuildMembersUpdated { guild_id, members });
+        state.publish(ServerEvent::GuildMembersUpdated { guild_id, members });
     }
 
     Ok(StatusCode::NO_CONTENT)
 }
 
+async fn http_moderate_member(
+    State(state): State<Arc<AppState>>,
+    Path((guild_id, member_id)): Path<(i64, i64)>,
+    Query(q): Query<UserQuery>,
+    Json(req): Json<ModerationRequest>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    let user_id = UserId(q.user_id);
+    let guild_id = GuildId(guild_id);
+    let target_user_id = UserId(member_id);
+    let action = req.action;
+    moderate_member(&state.api, user_id, guild_id, target_user_id, req)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    info!(
+        guild_id = guild_id.0,
+        user_id = user_id.0,
+        target_user_id = target_user_id.0,
+        ?action,
+        "guild: member moderated"
+    );
+    let event = match action {
+        ModerationAction::Kick => Some(ServerEvent::UserKicked {
+            guild_id,
+            target_user_id,
+        }),
+        ModerationAction::Ban => Some(ServerEvent::UserBanned {
+            guild_id,
+            target_user_id,
+        }),
+        ModerationAction::Mute => Some(ServerEvent::UserMuted {
+            guild_id,
+            target_user_id,
+        }),
+        ModerationAction::Unban | ModerationAction::Unmute => None,
+    };
+    if let Some(event) = event {
+        state.publish(event);
+    }
+    if let Ok(members) = list_members(&state.api, user_id, guild_id).await {
+        state.publish(ServerEvent::GuildMembersUpdated { guild_id, members });
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn http_list_audit_log(
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<i64>,
+    Query(q): Query<AuditLogQuery>,
+) -> Result<Json<Vec<AuditLogEntry>>, (StatusCode, Json<ApiError>)> {
+    let action = match q.action.as_deref() {
+        Some(action) => Some(AuditAction::parse(action).ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::new(
+                    ErrorCode::Validation,
+                    format!("unknown audit action '{action}'"),
+                )),
+            )
+        })?),
+        None => None,
+    };
+    let filter = AuditLogFilter {
+        before: q.before,
+        limit: q.limit,
+        action,
+        actor_id: q.actor_id,
+        target_user_id: q.target_user_id,
+    };
+    let entries = list_audit_log(&state.api, UserId(q.user_id), GuildId(guild_id), filter)
+        .await
+        .map_err(|error| (api_error_status(&error), Json(error)))?;
+    Ok(Json(entries))
+}
+
 async fn http_request_livekit_token(
     State(state): State<Arc<AppState>>,
     Query(q): Query<LiveKitTokenQuery>,
@@ -1493,6 +3146,7 @@ async fn http_request_livekit_token(
     )
     .await
     .map_err(|error| (api_error_status(&error), Json(error)))?;
+    state.metrics.livekit_token_minted();
     Ok(Json(event))
 }
 
@@ -1505,12 +3159,20 @@ async fn http_send_message(
         UserId(req.user_id),
         GuildId(req.guild_id),
         ChannelId(req.channel_id),
+        MessageOptions {
+            thread_id: req.thread_id.map(ThreadId),
+            expires_in_seconds: req.expires_in_seconds,
+        },
         &req.ciphertext_b64,
         req.attachment,
     )
     .await
     .map_err(|error| (api_error_status(&error), Json(error)))?;
-    let _ = state.events.send(event.clone());
+    state.metrics.bytes_stored(
+        StoredKind::Message,
+        metrics::base64_decoded_len(&req.ciphertext_b64),
+    );
+    state.publish(event.clone());
     Ok(Json(event))
 }
 
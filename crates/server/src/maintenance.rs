@@ -0,0 +1,36 @@
+//! Scheduled cleanup of rows the protocol leaves behind: consumed Welcomes, superseded key
+//! packages, spent device link tokens and uploads no message references.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use storage::{MaintenancePolicy, Storage};
+use tokio::sync::watch;
+use tracing::{error, info};
+
+/// Runs maintenance every `interval`, starting one interval after startup so a restart
+/// loop cannot keep the database busy vacuuming. Each run uses the latest `policy`.
+pub async fn run_scheduled(
+    storage: Storage,
+    policy: watch::Receiver<MaintenancePolicy>,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        ticker.tick().await;
+        let policy = *policy.borrow();
+        match storage.run_maintenance(policy, Utc::now()).await {
+            Ok(report) => info!(
+                welcomes = report.welcomes,
+                key_packages = report.key_packages,
+                device_link_tokens = report.device_link_tokens,
+                device_link_bundles = report.device_link_bundles,
+                files = report.files,
+                upload_sessions = report.upload_sessions,
+                reclaimed_bytes = report.reclaimed_bytes,
+                "maintenance: run complete"
+            ),
+            Err(error) => error!(%error, "maintenance: run failed"),
+        }
+    }
+}
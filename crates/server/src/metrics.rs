@@ -0,0 +1,235 @@
+//! Prometheus metrics for `/metrics`. Counters are bumped where the work happens; MLS queue
+//! depths are read from storage on each scrape so they never drift from the database.
+
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use prometheus_client::{
+    encoding::{text::encode, EncodeLabelSet},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use storage::Storage;
+
+use crate::app_state::AppState;
+
+/// What `/metrics` answers with; the encoder writes OpenMetrics text.
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, EncodeLabelSet)]
+struct HttpLabels {
+    route: String,
+    method: String,
+    status: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, EncodeLabelSet)]
+struct RouteLabels {
+    route: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, EncodeLabelSet)]
+struct KindLabels {
+    kind: &'static str,
+}
+
+/// What a stored-bytes sample counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoredKind {
+    /// Message ciphertext.
+    Message,
+    /// Attachment ciphertext, from single-request and resumable uploads.
+    File,
+}
+
+impl StoredKind {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Message => "message",
+            Self::File => "file",
+        }
+    }
+}
+
+type DurationFamily = Family<RouteLabels, Histogram, fn() -> Histogram>;
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+    http_requests: Family<HttpLabels, Counter>,
+    http_request_duration: DurationFamily,
+    ws_connections_opened: Counter,
+    ws_connections_active: Gauge,
+    events_delivered: Counter,
+    events_lagged: Counter,
+    stored_bytes: Family<KindLabels, Counter>,
+    mls_pending_welcomes: Gauge,
+    mls_key_packages: Gauge,
+    livekit_tokens: Counter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let mut registry = Registry::with_prefix("proto_rtc");
+        let http_requests = Family::<HttpLabels, Counter>::default();
+        registry.register(
+            "http_requests",
+            "HTTP requests by matched route, method and status",
+            http_requests.clone(),
+        );
+        let http_request_duration: DurationFamily =
+            Family::new_with_constructor(|| Histogram::new(exponential_buckets(0.001, 2.0, 14)));
+        registry.register(
+            "http_request_duration_seconds",
+            "HTTP request latency by matched route",
+            http_request_duration.clone(),
+        );
+        let ws_connections_opened = Counter::default();
+        registry.register(
+            "ws_connections_opened",
+            "WebSocket connections accepted",
+            ws_connections_opened.clone(),
+        );
+        let ws_connections_active = Gauge::default();
+        registry.register(
+            "ws_connections_active",
+            "WebSocket connections currently open",
+            ws_connections_active.clone(),
+        );
+        let events_delivered = Counter::default();
+        registry.register(
+            "events_delivered",
+            "Server events fanned out to WebSocket clients",
+            events_delivered.clone(),
+        );
+        let events_lagged = Counter::default();
+        registry.register(
+            "events_lagged",
+            "Server events dropped because a WebSocket client fell behind the broadcast buffer",
+            events_lagged.clone(),
+        );
+        let stored_bytes = Family::<KindLabels, Counter>::default();
+        registry.register(
+            "stored_bytes",
+            "Ciphertext bytes accepted for storage by kind",
+            stored_bytes.clone(),
+        );
+        let mls_pending_welcomes = Gauge::default();
+        registry.register(
+            "mls_pending_welcomes",
+            "MLS Welcomes waiting for their target device",
+            mls_pending_welcomes.clone(),
+        );
+        let mls_key_packages = Gauge::default();
+        registry.register(
+            "mls_key_packages",
+            "MLS key packages held for devices that are not revoked",
+            mls_key_packages.clone(),
+        );
+        let livekit_tokens = Counter::default();
+        registry.register(
+            "livekit_tokens_minted",
+            "LiveKit access tokens issued",
+            livekit_tokens.clone(),
+        );
+        Self {
+            registry: Arc::new(registry),
+            http_requests,
+            http_request_duration,
+            ws_connections_opened,
+            ws_connections_active,
+            events_delivered,
+            events_lagged,
+            stored_bytes,
+            mls_pending_welcomes,
+            mls_key_packages,
+            livekit_tokens,
+        }
+    }
+
+    fn record_http(&self, route: &str, method: &str, status: u16, started: Instant) {
+        self.http_requests
+            .get_or_create(&HttpLabels {
+                route: route.to_string(),
+                method: method.to_string(),
+                status,
+            })
+            .inc();
+        self.http_request_duration
+            .get_or_create(&RouteLabels {
+                route: route.to_string(),
+            })
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    pub fn ws_connected(&self) {
+        self.ws_connections_opened.inc();
+        self.ws_connections_active.inc();
+    }
+
+    pub fn ws_disconnected(&self) {
+        self.ws_connections_active.dec();
+    }
+
+    pub fn event_delivered(&self) {
+        self.events_delivered.inc();
+    }
+
+    pub fn events_lagged(&self, skipped: u64) {
+        self.events_lagged.inc_by(skipped);
+    }
+
+    pub fn bytes_stored(&self, kind: StoredKind, bytes: u64) {
+        self.stored_bytes
+            .get_or_create(&KindLabels { kind: kind.label() })
+            .inc_by(bytes);
+    }
+
+    pub fn livekit_token_minted(&self) {
+        self.livekit_tokens.inc();
+    }
+
+    /// Refreshes the storage-backed gauges and encodes every metric.
+    pub async fn render(&self, storage: &Storage) -> anyhow::Result<String> {
+        let depths = storage.mls_queue_depths().await?;
+        self.mls_pending_welcomes
+            .set(i64::try_from(depths.pending_welcomes).unwrap_or(i64::MAX));
+        self.mls_key_packages
+            .set(i64::try_from(depths.key_packages).unwrap_or(i64::MAX));
+        let mut text = String::new();
+        encode(&mut text, &self.registry)?;
+        Ok(text)
+    }
+}
+
+/// Counts each request under its route pattern, so ids in paths do not multiply series.
+/// Requests that match no route share the `unmatched` label.
+pub async fn track(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().as_str().to_string();
+    let response = next.run(request).await;
+    state
+        .metrics
+        .record_http(&route, &method, response.status().as_u16(), started);
+    response
+}
+
+/// Decoded size of padded standard base64 that already passed validation.
+pub fn base64_decoded_len(encoded: &str) -> u64 {
+    let padding = encoded.bytes().rev().take_while(|b| *b == b'=').count();
+    ((encoded.len() / 4) * 3).saturating_sub(padding) as u64
+}
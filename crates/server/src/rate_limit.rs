@@ -0,0 +1,261 @@
+//! Token-bucket rate limiting for abuse-prone routes and inbound WS frames. Each request
+//! takes a token from the caller's bucket for the route class, keyed by user and by peer
+//! address; either bucket running dry rejects it with `429`.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use shared::error::{ApiError, ErrorCode};
+
+use crate::app_state::AppState;
+
+/// Buckets idle long enough to have refilled are dropped once the table grows past this.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Routes that share a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteClass {
+    Login,
+    SendMessage,
+    Upload,
+    InviteJoin,
+    KeyPackageFetch,
+    BootstrapRequest,
+    WsFrame,
+}
+
+impl RouteClass {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Login => "login attempts",
+            Self::SendMessage => "messages",
+            Self::Upload => "uploads",
+            Self::InviteJoin => "invite joins",
+            Self::KeyPackageFetch => "key package fetches",
+            Self::BootstrapRequest => "bootstrap requests",
+            Self::WsFrame => "websocket frames",
+        }
+    }
+}
+
+/// A bucket holding up to `burst` tokens that refills at `per_minute`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// `None` when `per_minute` is 0, which disables the limit.
+    pub fn new(per_minute: u32, burst: u32) -> Option<Self> {
+        (per_minute > 0).then_some(Self {
+            per_minute,
+            burst: burst.max(1),
+        })
+    }
+
+    fn tokens_per_second(self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+/// The limit for each route class; `None` leaves a class unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RateLimits {
+    pub login: Option<RateLimit>,
+    pub send_message: Option<RateLimit>,
+    pub upload: Option<RateLimit>,
+    pub invite_join: Option<RateLimit>,
+    pub key_package_fetch: Option<RateLimit>,
+    pub bootstrap_request: Option<RateLimit>,
+    pub ws_frames: Option<RateLimit>,
+}
+
+impl RateLimits {
+    fn get(&self, class: RouteClass) -> Option<RateLimit> {
+        match class {
+            RouteClass::Login => self.login,
+            RouteClass::SendMessage => self.send_message,
+            RouteClass::Upload => self.upload,
+            RouteClass::InviteJoin => self.invite_join,
+            RouteClass::KeyPackageFetch => self.key_package_fetch,
+            RouteClass::BootstrapRequest => self.bootstrap_request,
+            RouteClass::WsFrame => self.ws_frames,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum BucketKey {
+    User(i64),
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Rejection from [`RateLimiter::check`]: the class that ran out and when a token frees up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimited {
+    pub class: RouteClass,
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    /// Whole seconds to wait, never zero.
+    pub fn retry_after_seconds(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil().max(1.0) as u64
+    }
+
+    pub fn to_api_error(self) -> ApiError {
+        ApiError::new(
+            ErrorCode::RateLimited,
+            format!("too many {}; retry later", self.class.describe()),
+        )
+        .with_retry_after(self.retry_after_seconds())
+    }
+}
+
+impl IntoResponse for RateLimited {
+    fn into_response(self) -> Response {
+        let retry_after = HeaderValue::from(self.retry_after_seconds());
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after)],
+            Json(self.to_api_error()),
+        )
+            .into_response()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limits: Arc<RwLock<RateLimits>>,
+    buckets: Arc<Mutex<HashMap<(RouteClass, BucketKey), Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits: Arc::new(RwLock::new(limits)),
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Replaces the limits, as on a config reload. Buckets keep their tokens, capped at the
+    /// new burst on their next use.
+    pub fn set_limits(&self, limits: RateLimits) {
+        *self.limits.write().expect("rate limiter lock poisoned") = limits;
+    }
+
+    /// Takes one token for `class` from the user's bucket and the address's bucket. Nothing
+    /// is taken unless both have one, so a rejected request does not drain either.
+    pub fn check(
+        &self,
+        class: RouteClass,
+        user_id: Option<i64>,
+        ip: Option<IpAddr>,
+    ) -> Result<(), RateLimited> {
+        self.check_at(class, user_id, ip, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        class: RouteClass,
+        user_id: Option<i64>,
+        ip: Option<IpAddr>,
+        now: Instant,
+    ) -> Result<(), RateLimited> {
+        let limits = *self.limits.read().expect("rate limiter lock poisoned");
+        let Some(limit) = limits.get(class) else {
+            return Ok(());
+        };
+        let keys: Vec<BucketKey> = user_id
+            .map(BucketKey::User)
+            .into_iter()
+            .chain(ip.map(BucketKey::Ip))
+            .collect();
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|(class, _), bucket| {
+                limits
+                    .get(*class)
+                    .is_some_and(|limit| refilled(bucket, limit, now) < f64::from(limit.burst))
+            });
+        }
+
+        let mut wait = Duration::ZERO;
+        for key in &keys {
+            let bucket = buckets.entry((class, *key)).or_insert(Bucket {
+                tokens: f64::from(limit.burst),
+                updated: now,
+            });
+            bucket.tokens = refilled(bucket, limit, now);
+            bucket.updated = now;
+            if bucket.tokens < 1.0 {
+                let missing = (1.0 - bucket.tokens) / limit.tokens_per_second();
+                wait = wait.max(Duration::from_secs_f64(missing));
+            }
+        }
+        if wait > Duration::ZERO {
+            return Err(RateLimited {
+                class,
+                retry_after: wait,
+            });
+        }
+        for key in &keys {
+            if let Some(bucket) = buckets.get_mut(&(class, *key)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn refilled(bucket: &Bucket, limit: RateLimit, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    (bucket.tokens + elapsed * limit.tokens_per_second()).min(f64::from(limit.burst))
+}
+
+/// Middleware for one route class. The user is the `user_id` query parameter, and the
+/// address is the TCP peer when the server was started with connect info.
+pub async fn enforce(
+    State((state, class)): State<(Arc<AppState>, RouteClass)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let user_id = request.uri().query().and_then(query_user_id);
+    match state.rate_limiter.check(class, user_id, ip) {
+        Ok(()) => next.run(request).await,
+        Err(limited) => limited.into_response(),
+    }
+}
+
+fn query_user_id(query: &str) -> Option<i64> {
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("user_id="))
+        .and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+#[path = "tests/rate_limit_tests.rs"]
+mod tests;
@@ -0,0 +1,89 @@
+//! Logging setup and the `SIGHUP` handler that re-reads the settings. Only the log filter,
+//! rate limits and maintenance TTLs change in a running server; anything else is reported
+//! as needing a restart.
+
+use std::path::PathBuf;
+
+use storage::MaintenancePolicy;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+use crate::config::{load_settings, LogFormat, Settings};
+use crate::rate_limit::RateLimiter;
+
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Installs the global subscriber. The returned handle swaps its filter on reload.
+pub fn init_logging(settings: &Settings) -> anyhow::Result<LogFilterHandle> {
+    let (filter, handle) = reload::Layer::new(EnvFilter::try_new(&settings.log_level)?);
+    let registry = tracing_subscriber::registry().with(filter);
+    match settings.log_format {
+        LogFormat::Text => registry.with(fmt::layer()).try_init()?,
+        LogFormat::Json => registry.with(fmt::layer().json()).try_init()?,
+    }
+    Ok(handle)
+}
+
+/// The parts of a running server a reload can reach.
+pub struct Reloadable {
+    pub config_path: Option<PathBuf>,
+    pub log_filter: LogFilterHandle,
+    pub rate_limiter: RateLimiter,
+    pub maintenance: watch::Sender<MaintenancePolicy>,
+}
+
+impl Reloadable {
+    fn apply(&self, startup: &Settings, next: &Settings) {
+        match EnvFilter::try_new(&next.log_level) {
+            Ok(filter) => {
+                if let Err(error) = self.log_filter.reload(filter) {
+                    error!(%error, "config reload: log filter not replaced");
+                }
+            }
+            Err(error) => error!(%error, "config reload: invalid log_level"),
+        }
+        self.rate_limiter.set_limits(next.rate_limits());
+        self.maintenance.send_replace(next.maintenance_policy());
+
+        let pending = startup.restart_required(next);
+        if !pending.is_empty() {
+            warn!(keys = ?pending, "config reload: these settings take effect after a restart");
+        }
+        info!(
+            log_level = %next.log_level,
+            rate_limits = ?next.rate_limits(),
+            maintenance = ?next.maintenance_policy(),
+            "config reloaded"
+        );
+    }
+}
+
+/// Re-reads the settings on every `SIGHUP`. A file that fails to load or validate is
+/// logged and leaves the running settings alone.
+#[cfg(unix)]
+pub async fn on_sighup(reloadable: Reloadable, startup: Settings) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(error) => {
+            error!(%error, "config reload: cannot listen for SIGHUP");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        info!(config = ?reloadable.config_path, "config reload: SIGHUP received");
+        match load_settings(reloadable.config_path.as_deref()) {
+            Ok(next) => reloadable.apply(&startup, &next),
+            Err(error) => {
+                error!(error = %format!("{error:#}"), "config reload failed; keeping the current settings")
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn on_sighup(_reloadable: Reloadable, _startup: Settings) {}
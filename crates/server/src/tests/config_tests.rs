@@ -1,4 +1,4 @@
-use super::{normalize_database_url, prepare_database_url};
+use super::{normalize_database_url, parse_settings, prepare_database_url, LogFormat, Settings};
 
 use std::{
     env, fs,
@@ -75,3 +75,211 @@ async fn prepared_database_url_creates_openable_sqlite_file() {
 
     fs::remove_dir_all(temp_root).expect("cleanup");
 }
+
+#[test]
+fn maintenance_values_override_defaults_and_skip_malformed_input() {
+    let mut settings = Settings::default();
+    settings
+        .apply_value("orphan_file_ttl_seconds", " 600 ")
+        .expect("ttl");
+    settings
+        .apply_value("maintenance_compact", "false")
+        .expect("compact");
+    let error = settings
+        .apply_value("key_package_ttl_seconds", "soon")
+        .expect_err("malformed ttl");
+    assert!(error.to_string().contains("\"soon\""));
+    settings
+        .apply_value("upload_session_ttl_seconds", "3600")
+        .expect("ttl");
+
+    let policy = settings.maintenance_policy();
+    assert_eq!(policy.orphan_file_ttl, chrono::Duration::minutes(10));
+    assert_eq!(policy.upload_session_ttl, chrono::Duration::hours(1));
+    assert!(!policy.compact);
+    assert_eq!(
+        policy.key_package_ttl,
+        storage::MaintenancePolicy::default().key_package_ttl
+    );
+}
+
+#[test]
+fn storage_quotas_default_on_and_zero_disables_them() {
+    let mut settings = Settings::default();
+    let defaults = settings.storage_quotas();
+    assert!(defaults.guild_bytes.is_some() && defaults.user_bytes.is_some());
+
+    settings
+        .apply_value("guild_storage_quota_bytes", "0")
+        .expect("setting");
+    settings
+        .apply_value("user_storage_quota_bytes", "1048576")
+        .expect("setting");
+    assert_eq!(
+        settings.storage_quotas(),
+        storage::StorageQuotas {
+            guild_bytes: None,
+            user_bytes: Some(1024 * 1024),
+        }
+    );
+}
+
+#[test]
+fn s3_blob_store_requires_bucket_and_credentials() {
+    let mut settings = Settings::default();
+    assert_eq!(
+        settings.blob_store_config().expect("default"),
+        storage::BlobStoreConfig::Database
+    );
+
+    settings.apply_value("blob_store", "s3").expect("setting");
+    settings
+        .apply_value("s3_endpoint", "http://127.0.0.1:9000")
+        .expect("setting");
+    settings
+        .apply_value("s3_access_key_id", "minio")
+        .expect("setting");
+    settings
+        .apply_value("s3_secret_access_key", "minio-secret")
+        .expect("setting");
+    let err = settings.blob_store_config().expect_err("bucket missing");
+    assert!(err.to_string().contains("s3_bucket"));
+
+    settings
+        .apply_value("s3_bucket", "attachments")
+        .expect("setting");
+    let storage::BlobStoreConfig::S3(config) = settings.blob_store_config().expect("s3") else {
+        panic!("expected an S3 config");
+    };
+    assert_eq!(config.bucket, "attachments");
+    assert_eq!(config.region, "us-east-1");
+}
+
+#[test]
+fn rate_limits_default_on_and_zero_per_minute_disables_a_class() {
+    let mut settings = Settings::default();
+    let defaults = settings.rate_limits();
+    assert!(defaults.login.is_some());
+    assert!(defaults.ws_frames.is_some());
+
+    settings
+        .apply_value("rate_limit_login_per_minute", "0")
+        .expect("setting");
+    settings
+        .apply_value("rate_limit_upload_per_minute", "6")
+        .expect("setting");
+    settings
+        .apply_value("rate_limit_upload_burst", "2")
+        .expect("setting");
+    let limits = settings.rate_limits();
+    assert_eq!(limits.login, None);
+    assert_eq!(limits.upload, crate::rate_limit::RateLimit::new(6, 2));
+    assert_eq!(limits.send_message, defaults.send_message);
+}
+
+#[test]
+fn config_file_is_typed_and_rejects_unknown_keys() {
+    let settings = parse_settings(
+        r#"
+bind_addr = "0.0.0.0:9000"
+livekit_ttl_seconds = 600
+max_attachment_bytes = 1048576
+rate_limit_login_per_minute = 0
+log_format = "json"
+"#,
+    )
+    .expect("valid file");
+    assert_eq!(settings.server_bind, "0.0.0.0:9000");
+    assert_eq!(settings.livekit_ttl_seconds, 600);
+    assert_eq!(settings.limits().max_attachment_bytes, 1024 * 1024);
+    assert_eq!(settings.rate_limits().login, None);
+    assert_eq!(settings.log_format, LogFormat::Json);
+    assert_eq!(settings.database_url, Settings::default().database_url);
+
+    let unknown = parse_settings("bind_adr = \"0.0.0.0:9000\"").expect_err("typo");
+    assert!(unknown.to_string().contains("unknown field `bind_adr`"));
+    let mistyped = parse_settings("livekit_ttl_seconds = \"600\"").expect_err("string");
+    assert!(mistyped.to_string().contains("livekit_ttl_seconds"));
+}
+
+#[test]
+fn validation_reports_every_problem() {
+    Settings::default().validate().expect("defaults are valid");
+
+    let mut settings = Settings::default();
+    settings
+        .apply_value("bind_addr", "localhost")
+        .expect("bind");
+    settings
+        .apply_value("max_upload_bytes", "0")
+        .expect("limit");
+    settings
+        .apply_value("log_level", "server=loud")
+        .expect("level");
+    settings
+        .apply_value("tls_cert_path", "/etc/server/cert.pem")
+        .expect("cert");
+    settings.apply_value("admin_token", "short").expect("token");
+    let message = format!("{:#}", settings.validate().expect_err("invalid"));
+    assert!(!message.contains("short"), "token echoed in {message}");
+    for key in [
+        "bind_addr",
+        "max_upload_bytes",
+        "log_level",
+        "tls_key_path",
+        "admin_token",
+    ] {
+        assert!(message.contains(key), "{key} missing from {message}");
+    }
+}
+
+#[test]
+fn tls_settings_resolve_to_files_and_must_load() {
+    assert_eq!(Settings::default().tls_files(), None);
+
+    let mut settings = Settings::default();
+    settings
+        .apply_value("tls_self_signed", "true")
+        .expect("self signed");
+    let files = settings.tls_files().expect("self-signed files");
+    assert!(files.cert_path.ends_with("tls/server.crt"));
+    assert!(settings
+        .self_signed_names()
+        .contains(&"localhost".to_string()));
+
+    let mut settings = Settings::default();
+    settings
+        .apply_value("tls_cert_path", "/nonexistent/server.crt")
+        .expect("cert");
+    settings
+        .apply_value("tls_key_path", "/nonexistent/server.key")
+        .expect("key");
+    let message = format!("{:#}", settings.validate().expect_err("missing files"));
+    assert!(message.contains("/nonexistent/server.crt"), "{message}");
+
+    // Self-signed mode writes missing files at startup instead.
+    settings
+        .apply_value("tls_self_signed", "true")
+        .expect("self signed");
+    settings.validate().expect("generated at startup");
+}
+
+#[test]
+fn only_some_settings_reload_without_a_restart() {
+    let startup = Settings::default();
+    let mut next = startup.clone();
+    next.apply_value("log_level", "debug").expect("level");
+    next.apply_value("rate_limit_upload_burst", "3")
+        .expect("burst");
+    next.apply_value("orphan_file_ttl_seconds", "60")
+        .expect("ttl");
+    assert!(startup.restart_required(&next).is_empty());
+
+    next.apply_value("bind_addr", "0.0.0.0:9000").expect("bind");
+    next.apply_value("livekit_url", "ws://10.0.0.2:7880")
+        .expect("url");
+    assert_eq!(
+        startup.restart_required(&next),
+        vec!["bind_addr".to_string(), "livekit_url".to_string()]
+    );
+}
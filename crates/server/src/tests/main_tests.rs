@@ -1,8 +1,16 @@
 use super::*;
 use axum::{body, body::Body, http::Request};
+use rate_limit::{RateLimit, RateLimits};
+use shared::domain::{OverwriteTarget, Permissions, Role};
 use tower::ServiceExt;
 
+const TEST_ADMIN_TOKEN: &str = "test-admin-token-0123456789";
+
 async fn test_app() -> (Router, Storage, i64, i64, i64) {
+    test_app_with_limits(RateLimits::default()).await
+}
+
+async fn test_app_with_limits(limits: RateLimits) -> (Router, Storage, i64, i64, i64) {
     let storage = Storage::new("sqlite::memory:").await.expect("db");
     let user = storage.create_user("alice").await.expect("user");
     let guild = storage.create_guild("general", user).await.expect("guild");
@@ -10,7 +18,18 @@ async fn test_app() -> (Router, Storage, i64, i64, i64) {
         .create_channel(guild, "general", ChannelKind::Text)
         .await
         .expect("channel");
+    let app = router_for(storage.clone(), limits).await;
+    (app, storage, user.0, guild.0, channel.0)
+}
 
+async fn router_for(storage: Storage, limits: RateLimits) -> Router {
+    build_router(state_for(storage, limits).await)
+}
+
+async fn state_for(storage: Storage, limits: RateLimits) -> Arc<AppState> {
+    let transparency = KeyTransparencyLog::load_or_create(&storage)
+        .await
+        .expect("transparency log");
     let api = ApiContext {
         storage,
         livekit: LiveKitConfig {
@@ -18,13 +37,19 @@ async fn test_app() -> (Router, Storage, i64, i64, i64) {
             api_secret: "s".to_string(),
             ttl_seconds: 60,
         },
+        transparency,
+        limits: crate::api::Limits::default(),
     };
     let (events, _) = broadcast::channel(32);
-    let app = build_router(Arc::new(AppState {
-        api: api.clone(),
+    let (revocations, _) = broadcast::channel(8);
+    Arc::new(AppState {
+        api,
         events,
-    }));
-    (app, api.storage, user.0, guild.0, channel.0)
+        revocations,
+        rate_limiter: RateLimiter::new(limits),
+        metrics: crate::metrics::Metrics::new(),
+        admin_token: Some(AdminToken::new(TEST_ADMIN_TOKEN)),
+    })
 }
 
 #[tokio::test]
@@ -42,6 +67,447 @@ async fn healthz_reports_ok_when_storage_is_ready() {
     assert_eq!(body.as_ref(), b"ok");
 }
 
+#[tokio::test]
+async fn readyz_reports_each_check_and_fails_while_the_blob_store_is_down() {
+    let (app, storage, _user_id, _guild_id, _channel_id) = test_app().await;
+    let readyz = || {
+        Request::get("/readyz")
+            .body(Body::empty())
+            .expect("request")
+    };
+
+    let response = app.clone().oneshot(readyz()).await.expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let report: serde_json::Value = serde_json::from_slice(&body).expect("json");
+    assert_eq!(report["ready"], true);
+    let names: Vec<&str> = report["checks"]
+        .as_array()
+        .expect("checks")
+        .iter()
+        .filter_map(|check| check["name"].as_str())
+        .collect();
+    assert_eq!(names, ["database", "migrations", "blob_store"]);
+
+    // A blob root that is a plain file cannot take writes.
+    let blocked = std::env::temp_dir().join(format!(
+        "proto_rtc_readyz_{}",
+        Utc::now().timestamp_nanos_opt().expect("clock")
+    ));
+    std::fs::write(&blocked, b"file").expect("blocker");
+    let storage = storage.with_blob_store(Some(Arc::new(storage::FilesystemBlobStore::new(
+        blocked.clone(),
+    ))));
+    let app = router_for(storage, RateLimits::default()).await;
+    let response = app.oneshot(readyz()).await.expect("response");
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let report: serde_json::Value = serde_json::from_slice(&body).expect("json");
+    assert_eq!(report["ready"], false);
+    assert_eq!(report["checks"][0]["ok"], true);
+    assert_eq!(report["checks"][2]["ok"], false);
+    assert!(report["checks"][2]["error"]
+        .as_str()
+        .expect("error")
+        .contains("blob store write failed"));
+    std::fs::remove_file(blocked).expect("cleanup");
+}
+
+#[tokio::test]
+async fn admin_routes_require_the_bearer_token() {
+    let (app, _storage, user_id, _guild_id, _channel_id) = test_app().await;
+    for authorization in [None, Some("Bearer wrong-token-0123456789".to_string())] {
+        let mut request = Request::get("/admin/users");
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).expect("request"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    let request = Request::get("/admin/users?search=ali")
+        .header("authorization", format!("Bearer {TEST_ADMIN_TOKEN}"))
+        .body(Body::empty())
+        .expect("request");
+    let response = app.oneshot(request).await.expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let users: Vec<AdminUserSummary> = serde_json::from_slice(&body).expect("json");
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].user_id, UserId(user_id));
+    assert_eq!(users[0].guild_count, 1);
+}
+
+#[tokio::test]
+async fn suspended_users_cannot_log_in_or_use_their_guilds() {
+    let (app, _storage, user_id, _guild_id, channel_id) = test_app().await;
+    let admin = |request: axum::http::request::Builder, body: Body| {
+        request
+            .header("authorization", format!("Bearer {TEST_ADMIN_TOKEN}"))
+            .header("content-type", "application/json")
+            .body(body)
+            .expect("request")
+    };
+    let login = || {
+        Request::post("/login")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"username":"alice"}"#))
+            .expect("request")
+    };
+    let messages = || {
+        Request::get(format!(
+            "/channels/{channel_id}/messages?user_id={user_id}&limit=10"
+        ))
+        .body(Body::empty())
+        .expect("request")
+    };
+
+    let suspend = admin(
+        Request::put(format!("/admin/users/{user_id}/suspension")),
+        Body::from(r#"{"reason":"spam"}"#),
+    );
+    let response = app.clone().oneshot(suspend).await.expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let user: AdminUserSummary = serde_json::from_slice(&body).expect("json");
+    assert!(user.suspended_at.is_some());
+    assert_eq!(user.suspension_reason.as_deref(), Some("spam"));
+
+    let response = app.clone().oneshot(login()).await.expect("response");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app.clone().oneshot(messages()).await.expect("response");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let unsuspend = admin(
+        Request::delete(format!("/admin/users/{user_id}/suspension")),
+        Body::empty(),
+    );
+    let response = app.clone().oneshot(unsuspend).await.expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.clone().oneshot(login()).await.expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.oneshot(messages()).await.expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn deletions_reach_only_the_users_who_could_see_what_was_deleted() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let owner = storage.create_user("alice").await.expect("user");
+    let bob = storage.create_user("bob").await.expect("user");
+    let carol = storage.create_user("carol").await.expect("user");
+    let mallory = storage.create_user("mallory").await.expect("user");
+    let guild = storage.create_guild("general", owner).await.expect("guild");
+    let other_guild = storage
+        .create_guild("elsewhere", mallory)
+        .await
+        .expect("guild");
+    for member in [bob, carol] {
+        storage
+            .add_membership(guild, member, Role::Member, false, false)
+            .await
+            .expect("membership");
+    }
+    let secret = storage
+        .create_channel(guild, "secret", ChannelKind::Text)
+        .await
+        .expect("channel");
+    storage
+        .upsert_channel_overwrite(
+            secret,
+            &PermissionOverwrite {
+                target: OverwriteTarget::Member(carol),
+                allow: Permissions::NONE,
+                deny: Permissions::VIEW_CHANNEL,
+            },
+        )
+        .await
+        .expect("overwrite");
+    let state = state_for(storage, RateLimits::default()).await;
+    let app = build_router(Arc::clone(&state));
+    let mut events = state.events.subscribe();
+    let delete = |request: Request<Body>| {
+        let app = app.clone();
+        async move { app.oneshot(request).await.expect("response").status() }
+    };
+    let reaches = |dispatch: Dispatch| {
+        let state = Arc::clone(&state);
+        async move {
+            let mut reached = Vec::new();
+            for user in [owner, bob, carol, mallory] {
+                if is_dispatch_visible_to_user(&state, user, &dispatch).await {
+                    reached.push(user);
+                }
+            }
+            reached
+        }
+    };
+
+    let status = delete(
+        Request::delete(format!("/channels/{}?user_id={}", secret.0, owner.0))
+            .body(Body::empty())
+            .expect("request"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let dispatch = events.recv().await.expect("dispatch");
+    assert!(matches!(dispatch.event, ServerEvent::ChannelDeleted { .. }));
+    assert_eq!(reaches(dispatch).await, vec![owner, bob]);
+
+    let status = delete(
+        Request::delete(format!("/guilds/{}?user_id={}", guild.0, owner.0))
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"confirm_name":"general"}"#))
+            .expect("request"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let dispatch = events.recv().await.expect("dispatch");
+    assert!(matches!(dispatch.event, ServerEvent::GuildDeleted { .. }));
+    assert_eq!(reaches(dispatch).await, vec![owner, bob, carol]);
+
+    let status = delete(
+        Request::delete(format!("/admin/guilds/{}", other_guild.0))
+            .header("authorization", format!("Bearer {TEST_ADMIN_TOKEN}"))
+            .body(Body::empty())
+            .expect("request"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let dispatch = events.recv().await.expect("dispatch");
+    assert!(matches!(dispatch.event, ServerEvent::GuildDeleted { .. }));
+    assert_eq!(reaches(dispatch).await, vec![mallory]);
+}
+
+/// Serves `state` on a local port, for tests that need a real WebSocket.
+async fn serve(state: Arc<AppState>) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    let app = build_router(state).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.expect("serve") });
+    addr
+}
+
+/// The error a closing WebSocket sends first, followed by its close frame.
+async fn close_reason<S>(socket: &mut S) -> String
+where
+    S: futures::Stream<
+            Item = Result<
+                tokio_tungstenite::tungstenite::Message,
+                tokio_tungstenite::tungstenite::Error,
+            >,
+        > + Unpin,
+{
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let mut frames = Vec::new();
+    for _ in 0..2 {
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next())
+            .await
+            .expect("socket closes in time")
+            .expect("frame")
+            .expect("valid frame");
+        frames.push(frame);
+    }
+    let [Message::Text(text), Message::Close(_)] = frames.as_slice() else {
+        panic!("expected a close reason and a close frame, got {frames:?}");
+    };
+    match serde_json::from_str(text).expect("event") {
+        ServerEvent::Error(error) => error.message,
+        other => panic!("unexpected event: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn suspension_and_device_revocation_close_live_websockets() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let user = storage.create_user("alice").await.expect("user");
+    let laptop = storage
+        .register_device(user, "laptop", "laptop-identity")
+        .await
+        .expect("device")
+        .device_id;
+    let phone = storage
+        .register_device(user, "phone", "phone-identity")
+        .await
+        .expect("device")
+        .device_id;
+    let state = state_for(storage, RateLimits::default()).await;
+    let addr = serve(Arc::clone(&state)).await;
+    let app = build_router(state);
+    let ws_url = |device_id: DeviceId| {
+        format!(
+            "ws://{addr}/ws?user_id={}&device_id={}",
+            user.0, device_id.0
+        )
+    };
+    let admin = |request: axum::http::request::Builder, body: Body| {
+        request
+            .header("authorization", format!("Bearer {TEST_ADMIN_TOKEN}"))
+            .header("content-type", "application/json")
+            .body(body)
+            .expect("request")
+    };
+    let (mut laptop_socket, _) = tokio_tungstenite::connect_async(ws_url(laptop))
+        .await
+        .expect("laptop connects");
+    let (mut phone_socket, _) = tokio_tungstenite::connect_async(ws_url(phone))
+        .await
+        .expect("phone connects");
+
+    let revoke = admin(
+        Request::delete(format!("/admin/users/{}/devices/{}", user.0, laptop.0)),
+        Body::empty(),
+    );
+    let response = app.clone().oneshot(revoke).await.expect("response");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        close_reason(&mut laptop_socket).await,
+        "device is not active"
+    );
+    assert!(tokio_tungstenite::connect_async(ws_url(laptop))
+        .await
+        .is_err());
+
+    // The phone was left open by the revocation and is closed by the suspension.
+    let suspend = admin(
+        Request::put(format!("/admin/users/{}/suspension", user.0)),
+        Body::from(r#"{"reason":"spam"}"#),
+    );
+    let response = app.oneshot(suspend).await.expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        close_reason(&mut phone_socket).await,
+        "account is suspended"
+    );
+}
+
+#[tokio::test]
+async fn metrics_count_requests_by_route_and_stored_bytes() {
+    let (app, storage, user_id, guild_id, channel_id) = test_app().await;
+    storage
+        .insert_pending_welcome(
+            GuildId(guild_id),
+            ChannelId(channel_id),
+            UserId(user_id),
+            None,
+            b"welcome",
+        )
+        .await
+        .expect("welcome");
+
+    for _ in 0..2 {
+        let request = Request::get(format!(
+            "/channels/{channel_id}/messages?user_id={user_id}&limit=10"
+        ))
+        .body(Body::empty())
+        .expect("request");
+        let response = app.clone().oneshot(request).await.expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let send = Request::post("/messages")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::json!({
+                "user_id": user_id,
+                "guild_id": guild_id,
+                "channel_id": channel_id,
+                "ciphertext_b64": "aGVsbG8=",
+            })
+            .to_string(),
+        ))
+        .expect("request");
+    assert_eq!(
+        app.clone().oneshot(send).await.expect("response").status(),
+        StatusCode::OK
+    );
+    let missing = Request::get("/no-such-route")
+        .body(Body::empty())
+        .expect("request");
+    app.clone().oneshot(missing).await.expect("response");
+
+    let request = Request::get("/metrics")
+        .body(Body::empty())
+        .expect("request");
+    let response = app.oneshot(request).await.expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[header::CONTENT_TYPE]
+        .to_str()
+        .expect("content type")
+        .starts_with("application/openmetrics-text"));
+    let body = body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let text = String::from_utf8(body.to_vec()).expect("utf8");
+    for line in [
+        r#"proto_rtc_http_requests_total{route="/channels/:channel_id/messages",method="GET",status="200"} 2"#,
+        r#"proto_rtc_http_requests_total{route="unmatched",method="GET",status="404"} 1"#,
+        r#"proto_rtc_stored_bytes_total{kind="message"} 5"#,
+        "proto_rtc_mls_pending_welcomes 1",
+        "proto_rtc_ws_connections_active 0",
+    ] {
+        assert!(
+            text.lines().any(|l| l == line),
+            "{line} missing from\n{text}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn rate_limited_routes_answer_429_with_retry_after() {
+    let (app, _storage, _user_id, _guild_id, _channel_id) = test_app_with_limits(RateLimits {
+        login: RateLimit::new(1, 1),
+        ..RateLimits::default()
+    })
+    .await;
+    let login = || {
+        let mut request = Request::post("/login")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                serde_json::json!({ "username": "route-user" }).to_string(),
+            ))
+            .expect("request");
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+        request
+    };
+
+    let first = app.clone().oneshot(login()).await.expect("response");
+    assert_eq!(first.status(), StatusCode::OK);
+
+    let second = app.clone().oneshot(login()).await.expect("response");
+    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+    let header = second
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .expect("retry-after header");
+    let body = body::to_bytes(second.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let error: ApiError = serde_json::from_slice(&body).expect("json");
+    assert!(matches!(error.code, ErrorCode::RateLimited));
+    assert_eq!(error.retry_after, Some(header));
+    assert!(header >= 1);
+}
+
 #[tokio::test]
 async fn login_and_guild_channel_list_routes_work() {
     let (app, _storage, _user_id, _guild_id, _channel_id) = test_app().await;
@@ -91,8 +557,15 @@ async fn file_upload_and_download_requires_membership() {
         .expect("request");
     let response = app.clone().oneshot(upload).await.expect("upload response");
     assert_eq!(response.status(), StatusCode::OK);
+    let body = body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let file_id = serde_json::from_slice::<serde_json::Value>(&body).expect("json")["file_id"]
+        .as_str()
+        .expect("file id")
+        .to_string();
 
-    let authorized_download = Request::get(format!("/files/1?user_id={user_id}"))
+    let authorized_download = Request::get(format!("/files/{file_id}?user_id={user_id}"))
         .body(Body::empty())
         .expect("request");
     let authorized = app
@@ -116,7 +589,7 @@ async fn file_upload_and_download_requires_membership() {
         .expect("unauthorized upload response");
     assert_eq!(unauthorized_upload_response.status(), StatusCode::FORBIDDEN);
 
-    let unauthorized_download = Request::get(format!("/files/1?user_id={}", outsider.0))
+    let unauthorized_download = Request::get(format!("/files/{file_id}?user_id={}", outsider.0))
         .body(Body::empty())
         .expect("request");
     let response = app
@@ -126,6 +599,105 @@ async fn file_upload_and_download_requires_membership() {
     assert_eq!(response.status(), StatusCode::FORBIDDEN);
 }
 
+#[tokio::test]
+async fn chunked_upload_resumes_at_the_received_offset_and_serves_ranges() {
+    let (app, storage, user_id, guild_id, channel_id) = test_app().await;
+    let send = |request: Request<Body>| {
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.expect("response");
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body");
+            (status, headers, body)
+        }
+    };
+    let put_chunk = |upload_id: &str, user_id: i64, offset: u64, bytes: &'static [u8]| {
+        Request::put(format!(
+            "/files/uploads/{upload_id}?user_id={user_id}&offset={offset}"
+        ))
+        .body(Body::from(bytes))
+        .expect("request")
+    };
+
+    let (status, _, body) = send(
+        Request::post(format!(
+            "/files/uploads?user_id={user_id}&guild_id={guild_id}&channel_id={channel_id}&filename=clip.bin"
+        ))
+        .header("content-type", "application/json")
+        .body(Body::from(r#"{"total_bytes":10}"#))
+        .expect("request"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let opened: UploadStatus = serde_json::from_slice(&body).expect("status");
+    let upload_id = opened.upload_id.clone();
+    assert_eq!(opened.received_bytes, 0);
+
+    let (status, _, _) = send(put_chunk(&upload_id, user_id, 0, b"0123")).await;
+    assert_eq!(status, StatusCode::OK);
+    // A retried chunk after a dropped response lands on a stale offset.
+    let (status, _, _) = send(put_chunk(&upload_id, user_id, 0, b"0123")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let outsider = storage.create_user("outsider-upload").await.expect("user");
+    let (status, _, _) = send(put_chunk(&upload_id, outsider.0, 4, b"456789")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _, body) = send(
+        Request::get(format!("/files/uploads/{upload_id}?user_id={user_id}"))
+            .body(Body::empty())
+            .expect("request"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let resumed: UploadStatus = serde_json::from_slice(&body).expect("status");
+    assert_eq!(resumed.received_bytes, 4);
+    let (status, _, _) = send(put_chunk(&upload_id, user_id, 4, b"456789")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let complete = |sha256: String| {
+        Request::post(format!(
+            "/files/uploads/{upload_id}/complete?user_id={user_id}"
+        ))
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::json!({ "sha256": sha256 }).to_string(),
+        ))
+        .expect("request")
+    };
+    let (status, _, _) = send(complete(storage::blob_key(b"tampered"))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _, body) = send(complete(storage::blob_key(b"0123456789"))).await;
+    assert_eq!(status, StatusCode::OK);
+    let file_id = serde_json::from_slice::<serde_json::Value>(&body).expect("json")["file_id"]
+        .as_str()
+        .expect("file id")
+        .to_string();
+
+    let download = |range: Option<&str>| {
+        let mut request = Request::get(format!("/files/{file_id}?user_id={user_id}"));
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range);
+        }
+        request.body(Body::empty()).expect("request")
+    };
+    let (status, headers, body) = send(download(None)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(body.as_ref(), b"0123456789");
+    let (status, headers, body) = send(download(Some("bytes=2-5"))).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(headers[header::CONTENT_RANGE], "bytes 2-5/10");
+    assert_eq!(body.as_ref(), b"2345");
+    let (_, _, body) = send(download(Some("bytes=-3"))).await;
+    assert_eq!(body.as_ref(), b"789");
+    let (status, headers, _) = send(download(Some("bytes=10-"))).await;
+    assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(headers[header::CONTENT_RANGE], "bytes */10");
+}
+
 #[tokio::test]
 async fn livekit_token_route_mints_token_for_voice_channel() {
     let (app, storage, user_id, guild_id, _channel_id) = test_app().await;
@@ -338,6 +910,104 @@ async fn store_pending_welcome_allows_target_to_fetch_and_join_later() {
     );
 }
 
+#[tokio::test]
+async fn history_bundles_require_channel_opt_in_by_moderator() {
+    let (app, storage, user_id, guild_id, channel_id) = test_app().await;
+    let target = storage.create_user("bob").await.expect("user");
+    storage
+        .add_membership(
+            GuildId(guild_id),
+            target,
+            shared::domain::Role::Member,
+            false,
+            false,
+        )
+        .await
+        .expect("membership");
+    let store_uri = format!(
+        "/mls/history?user_id={user_id}&guild_id={guild_id}&channel_id={channel_id}&target_user_id={}",
+        target.0
+    );
+
+    let disabled = app
+        .clone()
+        .oneshot(
+            Request::post(&store_uri)
+                .body(Body::from("sealed-history"))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(disabled.status(), StatusCode::FORBIDDEN);
+
+    let member_toggle = app
+        .clone()
+        .oneshot(
+            Request::post(format!(
+                "/channels/{channel_id}/history_sharing?user_id={}&enabled=true",
+                target.0
+            ))
+            .body(Body::empty())
+            .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(member_toggle.status(), StatusCode::FORBIDDEN);
+
+    let owner_toggle = app
+        .clone()
+        .oneshot(
+            Request::post(format!(
+                "/channels/{channel_id}/history_sharing?user_id={user_id}&enabled=true"
+            ))
+            .body(Body::empty())
+            .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(owner_toggle.status(), StatusCode::OK);
+    let body = body::to_bytes(owner_toggle.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let channel: shared::protocol::ChannelSummary = serde_json::from_slice(&body).expect("json");
+    assert!(channel.history_sharing);
+
+    let stored = app
+        .clone()
+        .oneshot(
+            Request::post(&store_uri)
+                .body(Body::from("sealed-history"))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(stored.status(), StatusCode::NO_CONTENT);
+
+    let fetched = app
+        .clone()
+        .oneshot(
+            Request::get(format!(
+                "/mls/history?user_id={}&guild_id={guild_id}&channel_id={channel_id}",
+                target.0
+            ))
+            .body(Body::empty())
+            .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(fetched.status(), StatusCode::OK);
+    let body = body::to_bytes(fetched.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let bundles: Vec<HistoryBundleResponse> = serde_json::from_slice(&body).expect("json");
+    assert_eq!(bundles.len(), 1);
+    assert_eq!(bundles[0].sender_id, UserId(user_id));
+    assert_eq!(
+        STANDARD.decode(&bundles[0].bundle_b64).expect("base64"),
+        b"sealed-history"
+    );
+}
+
 #[tokio::test]
 async fn fetch_pending_welcome_rejects_non_member_and_does_not_consume() {
     let (app, storage, user_id, guild_id, channel_id) = test_app().await;
@@ -377,3 +1047,164 @@ async fn fetch_pending_welcome_rejects_non_member_and_does_not_consume() {
         .expect("authorized response");
     assert_eq!(authorized_response.status(), StatusCode::OK);
 }
+
+#[tokio::test]
+async fn key_transparency_logs_devices_and_serves_verifiable_proofs() {
+    let (app, storage, user_id, guild_id, _channel_id) = test_app().await;
+    let first = storage
+        .register_device(UserId(user_id), "laptop", "user:laptop")
+        .await
+        .expect("device");
+    let head_request = Request::get("/transparency/tree_head")
+        .body(Body::empty())
+        .expect("request");
+    let head_response = app.clone().oneshot(head_request).await.expect("response");
+    assert_eq!(head_response.status(), StatusCode::OK);
+    let head_body = body::to_bytes(head_response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let first_head: SignedTreeHead = serde_json::from_slice(&head_body).expect("json");
+    assert_eq!(first_head.tree_size, 1);
+
+    storage
+        .register_device(UserId(user_id), "phone", "user:phone")
+        .await
+        .expect("device");
+    let revoke_request = Request::post(format!(
+        "/devices/revoke?user_id={user_id}&device_id={}",
+        first.device_id.0
+    ))
+    .body(Body::empty())
+    .expect("request");
+    let revoke_response = app.clone().oneshot(revoke_request).await.expect("response");
+    assert_eq!(revoke_response.status(), StatusCode::NO_CONTENT);
+
+    let upload = Request::post(format!(
+        "/mls/key_packages?user_id={user_id}&guild_id={guild_id}&device_id={}",
+        first.device_id.0
+    ))
+    .body(Body::from("kp"))
+    .expect("request");
+    let upload_response = app.clone().oneshot(upload).await.expect("response");
+    assert_eq!(upload_response.status(), StatusCode::OK);
+
+    let inclusion_request = Request::get("/transparency/inclusion?leaf_index=2")
+        .body(Body::empty())
+        .expect("request");
+    let inclusion_response = app
+        .clone()
+        .oneshot(inclusion_request)
+        .await
+        .expect("response");
+    assert_eq!(inclusion_response.status(), StatusCode::OK);
+    let inclusion_body = body::to_bytes(inclusion_response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let inclusion: InclusionProofResponse = serde_json::from_slice(&inclusion_body).expect("json");
+    assert_eq!(inclusion.tree_size, 3);
+    assert_eq!(
+        inclusion.leaf.action,
+        shared::domain::KeyTransparencyAction::Revoke
+    );
+    let leaves = storage
+        .list_key_transparency_leaf_hashes(3)
+        .await
+        .expect("leaves");
+    let path: Vec<[u8; 32]> = inclusion
+        .audit_path_b64
+        .iter()
+        .map(|node| {
+            STANDARD
+                .decode(node)
+                .expect("b64")
+                .try_into()
+                .expect("hash")
+        })
+        .collect();
+    assert!(shared::transparency::verify_inclusion(
+        &inclusion.leaf.leaf_hash(),
+        2,
+        3,
+        &path,
+        &shared::transparency::root_hash(&leaves),
+    ));
+
+    let consistency_request = Request::get("/transparency/consistency?first_size=1")
+        .body(Body::empty())
+        .expect("request");
+    let consistency_response = app
+        .clone()
+        .oneshot(consistency_request)
+        .await
+        .expect("response");
+    assert_eq!(consistency_response.status(), StatusCode::OK);
+    let consistency_body = body::to_bytes(consistency_response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let consistency: ConsistencyProofResponse =
+        serde_json::from_slice(&consistency_body).expect("json");
+    let proof: Vec<[u8; 32]> = consistency
+        .proof_b64
+        .iter()
+        .map(|node| {
+            STANDARD
+                .decode(node)
+                .expect("b64")
+                .try_into()
+                .expect("hash")
+        })
+        .collect();
+    let first_root: [u8; 32] = STANDARD
+        .decode(&first_head.root_hash_b64)
+        .expect("b64")
+        .try_into()
+        .expect("hash");
+    assert!(shared::transparency::verify_consistency(
+        1,
+        3,
+        &first_root,
+        &shared::transparency::root_hash(&leaves),
+        &proof,
+    ));
+
+    let out_of_range = Request::get("/transparency/consistency?first_size=4")
+        .body(Body::empty())
+        .expect("request");
+    let out_of_range_response = app.oneshot(out_of_range).await.expect("response");
+    assert_eq!(out_of_range_response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn mls_backup_upload_replaces_and_downloads_opaque_blob() {
+    let (app, _storage, user_id, _guild_id, _channel_id) = test_app().await;
+
+    let missing = Request::get(format!("/mls/backup?user_id={user_id}"))
+        .body(Body::empty())
+        .expect("request");
+    let missing_response = app.clone().oneshot(missing).await.expect("response");
+    assert_eq!(missing_response.status(), StatusCode::NOT_FOUND);
+
+    for blob in [&b"first-sealed-backup"[..], &b"second-sealed-backup"[..]] {
+        let upload = Request::put(format!("/mls/backup?user_id={user_id}"))
+            .body(Body::from(blob.to_vec()))
+            .expect("request");
+        let upload_response = app.clone().oneshot(upload).await.expect("response");
+        assert_eq!(upload_response.status(), StatusCode::NO_CONTENT);
+    }
+
+    let download = Request::get(format!("/mls/backup?user_id={user_id}"))
+        .body(Body::empty())
+        .expect("request");
+    let download_response = app.clone().oneshot(download).await.expect("response");
+    assert_eq!(download_response.status(), StatusCode::OK);
+    let body = body::to_bytes(download_response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    assert_eq!(body.as_ref(), b"second-sealed-backup");
+
+    let empty = Request::put(format!("/mls/backup?user_id={user_id}"))
+        .body(Body::empty())
+        .expect("request");
+    let empty_response = app.oneshot(empty).await.expect("response");
+    assert_eq!(empty_response.status(), StatusCode::BAD_REQUEST);
+}
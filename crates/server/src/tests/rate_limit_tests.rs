@@ -0,0 +1,59 @@
+use super::*;
+
+fn limiter(limit: RateLimit) -> RateLimiter {
+    RateLimiter::new(RateLimits {
+        login: Some(limit),
+        ..RateLimits::default()
+    })
+}
+
+#[test]
+fn buckets_allow_a_burst_then_refill_at_the_configured_rate() {
+    let limiter = limiter(RateLimit::new(60, 2).expect("limit"));
+    let start = Instant::now();
+    let ip = Some(IpAddr::from([10, 0, 0, 1]));
+
+    assert!(limiter.check_at(RouteClass::Login, None, ip, start).is_ok());
+    assert!(limiter.check_at(RouteClass::Login, None, ip, start).is_ok());
+    let limited = limiter
+        .check_at(RouteClass::Login, None, ip, start)
+        .expect_err("burst spent");
+    assert_eq!(limited.retry_after_seconds(), 1);
+    let error = limited.to_api_error();
+    assert!(matches!(error.code, ErrorCode::RateLimited));
+    assert_eq!(error.retry_after, Some(1));
+
+    let later = start + Duration::from_secs(1);
+    assert!(limiter.check_at(RouteClass::Login, None, ip, later).is_ok());
+    assert!(limiter
+        .check_at(RouteClass::Login, None, ip, later)
+        .is_err());
+    // Other classes are unlimited unless configured.
+    assert!(limiter
+        .check_at(RouteClass::SendMessage, None, ip, later)
+        .is_ok());
+}
+
+#[test]
+fn user_and_address_buckets_are_both_charged() {
+    let limiter = limiter(RateLimit::new(1, 1).expect("limit"));
+    let now = Instant::now();
+    let home = Some(IpAddr::from([10, 0, 0, 1]));
+    let cafe = Some(IpAddr::from([10, 0, 0, 2]));
+
+    assert!(limiter
+        .check_at(RouteClass::Login, Some(7), home, now)
+        .is_ok());
+    // Same user from another address, and another user from the same address.
+    assert!(limiter
+        .check_at(RouteClass::Login, Some(7), cafe, now)
+        .is_err());
+    assert!(limiter
+        .check_at(RouteClass::Login, Some(8), home, now)
+        .is_err());
+    // The rejections above took nothing, so a fresh user at a fresh address still passes.
+    assert!(limiter
+        .check_at(RouteClass::Login, Some(8), cafe, now)
+        .is_ok());
+    assert_eq!(RateLimit::new(0, 5), None);
+}
@@ -0,0 +1,89 @@
+use super::{ensure_self_signed, leaf_fingerprint, server_config, TlsFiles};
+
+use std::{
+    env, fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn temp_files(name: &str) -> TlsFiles {
+    let suffix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock")
+        .as_nanos();
+    let root = env::temp_dir().join(format!("proto_rtc_tls_{name}_{suffix}"));
+    TlsFiles {
+        cert_path: root.join("tls").join("server.crt"),
+        key_path: root.join("tls").join("server.key"),
+    }
+}
+
+#[test]
+fn self_signed_certificate_is_written_once_and_served_over_h2() {
+    let files = temp_files("self_signed");
+    let names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+
+    assert!(ensure_self_signed(&files, names.clone()).expect("generate"));
+    let fingerprint = leaf_fingerprint(&files).expect("fingerprint");
+    assert_eq!(fingerprint.len(), 64);
+
+    assert!(!ensure_self_signed(&files, names).expect("reuse"));
+    assert_eq!(leaf_fingerprint(&files).expect("fingerprint"), fingerprint);
+
+    let config = server_config(&files).expect("load");
+    assert_eq!(
+        config.alpn_protocols,
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    );
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&files.key_path)
+            .expect("key")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    fs::remove_dir_all(
+        files
+            .cert_path
+            .parent()
+            .and_then(|p| p.parent())
+            .expect("root"),
+    )
+    .expect("cleanup");
+}
+
+#[test]
+fn mismatched_or_missing_files_are_rejected() {
+    let first = temp_files("first");
+    let second = temp_files("second");
+    ensure_self_signed(&first, vec!["localhost".to_string()]).expect("first");
+    ensure_self_signed(&second, vec!["localhost".to_string()]).expect("second");
+
+    let mismatched = TlsFiles {
+        cert_path: first.cert_path.clone(),
+        key_path: second.key_path.clone(),
+    };
+    let error = format!("{:#}", server_config(&mismatched).expect_err("mismatch"));
+    assert!(error.contains("does not match"), "{error}");
+
+    let missing = TlsFiles {
+        cert_path: first.cert_path.with_file_name("absent.crt"),
+        key_path: first.key_path.clone(),
+    };
+    let error = format!("{:#}", server_config(&missing).expect_err("missing"));
+    assert!(error.contains("absent.crt"), "{error}");
+
+    for files in [first, second] {
+        fs::remove_dir_all(
+            files
+                .cert_path
+                .parent()
+                .and_then(|p| p.parent())
+                .expect("root"),
+        )
+        .expect("cleanup");
+    }
+}
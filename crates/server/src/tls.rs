@@ -0,0 +1,151 @@
+//! HTTPS termination. Certificates are read from PEM files and re-read when either file
+//! changes, so renewals need no restart. Development mode writes a self-signed certificate
+//! the first time it starts.
+
+use std::{
+    fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    ServerConfig,
+};
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+/// How often the certificate files are checked for changes.
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsFiles {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+/// Reads the certificate chain and key into a server config offering HTTP/2 and HTTP/1.1.
+pub fn server_config(files: &TlsFiles) -> anyhow::Result<Arc<ServerConfig>> {
+    let certs = read_certs(&files.cert_path)?;
+    let key = read_key(&files.key_path)?;
+    let mut config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .with_context(|| {
+                format!(
+                    "{} does not match {}",
+                    files.key_path.display(),
+                    files.cert_path.display()
+                )
+            })?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// SHA-256 of the leaf certificate, as clients pin it.
+pub fn leaf_fingerprint(files: &TlsFiles) -> anyhow::Result<String> {
+    let certs = read_certs(&files.cert_path)?;
+    Ok(hex::encode(Sha256::digest(certs[0].as_ref())))
+}
+
+/// Writes a self-signed certificate for `names` unless both files already exist, so the
+/// fingerprint clients pinned stays valid across restarts.
+pub fn ensure_self_signed(files: &TlsFiles, names: Vec<String>) -> anyhow::Result<bool> {
+    if files.cert_path.is_file() && files.key_path.is_file() {
+        return Ok(false);
+    }
+    let certified = rcgen::generate_simple_self_signed(names)?;
+    for path in [&files.cert_path, &files.key_path] {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+    }
+    fs::write(&files.cert_path, certified.cert.pem())
+        .with_context(|| format!("failed to write {}", files.cert_path.display()))?;
+    write_private(
+        &files.key_path,
+        certified.key_pair.serialize_pem().as_bytes(),
+    )
+    .with_context(|| format!("failed to write {}", files.key_path.display()))?;
+    Ok(true)
+}
+
+/// Swaps in the new certificate whenever either file's modification time changes. A pair
+/// that fails to load is logged and the current certificate stays in use.
+pub async fn reload_on_change(config: RustlsConfig, files: TlsFiles) {
+    let mut seen = modified(&files);
+    let mut ticker = tokio::time::interval(RELOAD_POLL_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let current = modified(&files);
+        if current == seen {
+            continue;
+        }
+        seen = current;
+        match server_config(&files) {
+            Ok(server_config) => {
+                config.reload_from_config(server_config);
+                info!(
+                    cert = %files.cert_path.display(),
+                    fingerprint = leaf_fingerprint(&files).unwrap_or_default(),
+                    "tls: certificate reloaded"
+                );
+            }
+            Err(error) => {
+                error!(error = %format!("{error:#}"), "tls: reload failed; keeping the current certificate")
+            }
+        }
+    }
+}
+
+fn modified(files: &TlsFiles) -> (Option<SystemTime>, Option<SystemTime>) {
+    let at = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    (at(&files.cert_path), at(&files.key_path))
+}
+
+fn read_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let raw = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(raw.as_slice()))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("{} is not a PEM certificate chain", path.display()))?;
+    if certs.is_empty() {
+        bail!("{} contains no certificates", path.display());
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let raw = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(raw.as_slice()))
+        .with_context(|| format!("{} is not a PEM private key", path.display()))?
+        .with_context(|| format!("{} contains no private key", path.display()))
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+#[path = "tests/tls_tests.rs"]
+mod tests;
@@ -12,13 +12,21 @@ id_newtype!(DeviceId);
 id_newtype!(GuildId);
 id_newtype!(ChannelId);
 id_newtype!(MessageId);
-id_newtype!(FileId);
+id_newtype!(RoleId);
+id_newtype!(ThreadId);
+
+/// Public identifier of a stored file. It is random rather than sequential, so knowing one
+/// file's id gives no way to find others.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FileId(pub String);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ChannelKind {
     Text,
     Voice,
+    /// A one-to-one or small group conversation outside any guild.
+    Direct,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -29,6 +37,197 @@ pub enum Role {
     Member,
 }
 
+/// Guild permission bitset. Bit positions are part of the wire format and must not change.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Permissions(pub u64);
+
+impl Permissions {
+    pub const NONE: Self = Self(0);
+    pub const MANAGE_CHANNELS: Self = Self(1 << 0);
+    pub const MANAGE_ROLES: Self = Self(1 << 1);
+    pub const KICK_MEMBERS: Self = Self(1 << 2);
+    pub const BAN_MEMBERS: Self = Self(1 << 3);
+    pub const MUTE_MEMBERS: Self = Self(1 << 4);
+    pub const CREATE_INVITES: Self = Self(1 << 5);
+    pub const SEND_MESSAGES: Self = Self(1 << 6);
+    pub const ATTACH_FILES: Self = Self(1 << 7);
+    pub const SPEAK: Self = Self(1 << 8);
+    pub const STREAM: Self = Self(1 << 9);
+    pub const MANAGE_MESSAGES: Self = Self(1 << 10);
+    pub const VIEW_CHANNEL: Self = Self(1 << 11);
+    pub const MANAGE_GUILD: Self = Self(1 << 12);
+    pub const VIEW_AUDIT_LOG: Self = Self(1 << 13);
+    pub const ALL: Self = Self((1 << 14) - 1);
+
+    /// Granted to every member before custom roles are applied.
+    pub const MEMBER_DEFAULT: Self = Self(
+        Self::VIEW_CHANNEL.0
+            | Self::CREATE_INVITES.0
+            | Self::SEND_MESSAGES.0
+            | Self::ATTACH_FILES.0
+            | Self::SPEAK.0
+            | Self::STREAM.0,
+    );
+    /// Everything a direct conversation participant may do; roles and overwrites do not
+    /// apply outside guilds.
+    pub const DIRECT_DEFAULT: Self =
+        Self(Self::VIEW_CHANNEL.0 | Self::SEND_MESSAGES.0 | Self::ATTACH_FILES.0);
+    /// Granted to the built-in moderator tier.
+    pub const MOD_DEFAULT: Self = Self(
+        Self::MEMBER_DEFAULT.0
+            | Self::MANAGE_CHANNELS.0
+            | Self::KICK_MEMBERS.0
+            | Self::BAN_MEMBERS.0
+            | Self::MUTE_MEMBERS.0
+            | Self::MANAGE_MESSAGES.0
+            | Self::VIEW_AUDIT_LOG.0,
+    );
+
+    /// Drops bits that do not name a known permission.
+    pub const fn from_bits_truncate(bits: u64) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for Permissions {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+impl std::ops::BitOrAssign for Permissions {
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = self.union(rhs);
+    }
+}
+
+/// Who a channel permission overwrite applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "id", rename_all = "snake_case")]
+pub enum OverwriteTarget {
+    /// Every member of the guild; this is how a channel is made private.
+    Everyone,
+    Role(RoleId),
+    Member(UserId),
+}
+
+/// Channel-level adjustment applied on top of guild permissions. `deny` is applied before
+/// `allow`, so a bit present in both ends up allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PermissionOverwrite {
+    pub target: OverwriteTarget,
+    #[serde(default)]
+    pub allow: Permissions,
+    #[serde(default)]
+    pub deny: Permissions,
+}
+
+/// Moderation actions a moderator can take against a guild member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    Kick,
+    Ban,
+    Unban,
+    Mute,
+    Unmute,
+}
+
+/// What an audit log entry records. Names are stored in the database and must not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    MemberKicked,
+    MemberBanned,
+    MemberUnbanned,
+    MemberMuted,
+    MemberUnmuted,
+    MemberRoleAdded,
+    MemberRoleRemoved,
+    RoleCreated,
+    RoleUpdated,
+    RoleDeleted,
+    ChannelCreated,
+    ChannelUpdated,
+    ChannelDeleted,
+    ChannelOverwriteUpdated,
+    InviteCreated,
+    InviteDeleted,
+    GuildRenamed,
+    OwnershipTransferred,
+}
+
+impl AuditAction {
+    pub const ALL: [Self; 18] = [
+        Self::MemberKicked,
+        Self::MemberBanned,
+        Self::MemberUnbanned,
+        Self::MemberMuted,
+        Self::MemberUnmuted,
+        Self::MemberRoleAdded,
+        Self::MemberRoleRemoved,
+        Self::RoleCreated,
+        Self::RoleUpdated,
+        Self::RoleDeleted,
+        Self::ChannelCreated,
+        Self::ChannelUpdated,
+        Self::ChannelDeleted,
+        Self::ChannelOverwriteUpdated,
+        Self::InviteCreated,
+        Self::InviteDeleted,
+        Self::GuildRenamed,
+        Self::OwnershipTransferred,
+    ];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::MemberKicked => "member_kicked",
+            Self::MemberBanned => "member_banned",
+            Self::MemberUnbanned => "member_unbanned",
+            Self::MemberMuted => "member_muted",
+            Self::MemberUnmuted => "member_unmuted",
+            Self::MemberRoleAdded => "member_role_added",
+            Self::MemberRoleRemoved => "member_role_removed",
+            Self::RoleCreated => "role_created",
+            Self::RoleUpdated => "role_updated",
+            Self::RoleDeleted => "role_deleted",
+            Self::ChannelCreated => "channel_created",
+            Self::ChannelUpdated => "channel_updated",
+            Self::ChannelDeleted => "channel_deleted",
+            Self::ChannelOverwriteUpdated => "channel_overwrite_updated",
+            Self::InviteCreated => "invite_created",
+            Self::InviteDeleted => "invite_deleted",
+            Self::GuildRenamed => "guild_renamed",
+            Self::OwnershipTransferred => "ownership_transferred",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.as_str() == value)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DeviceLinkState {
@@ -37,6 +236,13 @@ pub enum DeviceLinkState {
     Revoked,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyTransparencyAction {
+    Register,
+    Revoke,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceSummary {
     pub device_id: DeviceId,
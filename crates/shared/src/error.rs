@@ -8,6 +8,10 @@ pub enum ErrorCode {
     Forbidden,
     NotFound,
     Validation,
+    /// The request raced another change, such as a stale upload offset.
+    Conflict,
+    /// Storing the upload would exceed the guild's or the uploader's storage quota.
+    QuotaExceeded,
     RateLimited,
     Internal,
 }
@@ -16,6 +20,9 @@ pub enum ErrorCode {
 pub struct ApiError {
     pub code: ErrorCode,
     pub message: String,
+    /// Seconds to wait before retrying; set on `RateLimited` errors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
 }
 
 impl ApiError {
@@ -23,8 +30,14 @@ impl ApiError {
         Self {
             code,
             message: message.into(),
+            retry_after: None,
         }
     }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
 }
 
 #[derive(Debug, Error)]
@@ -45,9 +58,6 @@ impl ApiException {
 
 impl From<ApiException> for ApiError {
     fn from(value: ApiException) -> Self {
-        Self {
-            code: value.code,
-            message: value.message,
-        }
+        Self::new(value.code, value.message)
     }
 }
@@ -1,3 +1,4 @@
 pub mod domain;
 pub mod error;
 pub mod protocol;
+pub mod transparency;
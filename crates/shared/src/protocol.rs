@@ -2,7 +2,10 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    domain::{ChannelId, ChannelKind, DeviceId, FileId, GuildId, MessageId, Role, UserId},
+    domain::{
+        AuditAction, ChannelId, ChannelKind, DeviceId, FileId, GuildId, KeyTransparencyAction,
+        MessageId, ModerationAction, Permissions, Role, RoleId, ThreadId, UserId,
+    },
     error::ApiError,
 };
 
@@ -68,6 +71,194 @@ pub struct ChannelSummary {
     pub guild_id: GuildId,
     pub kind: ChannelKind,
     pub name: String,
+    /// Existing members share recent history with newly added members when set.
+    #[serde(default)]
+    pub history_sharing: bool,
+    /// Hidden from members unless a role or member overwrite grants `VIEW_CHANNEL`.
+    #[serde(default)]
+    pub private: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    /// Free-form grouping label; channels sharing a category are listed together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub position: i64,
+    #[serde(default, skip_serializing_if = "RetentionPolicy::is_unlimited")]
+    pub retention: RetentionPolicy,
+}
+
+/// How long a channel keeps its messages. The server purges messages older than
+/// `max_age_seconds` and all but the newest `max_messages`; unset limits do not apply.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_seconds: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_messages: Option<u64>,
+}
+
+impl RetentionPolicy {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age_seconds.is_none() && self.max_messages.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateGuildRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateGuildRequest {
+    pub name: String,
+}
+
+/// Deleting a guild is irreversible, so the caller must repeat its current name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteGuildRequest {
+    pub confirm_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferOwnershipRequest {
+    pub new_owner_id: UserId,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModerationRequest {
+    pub action: ModerationAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InviteSummary {
+    pub invite_code: String,
+    pub guild_id: GuildId,
+    pub creator_id: UserId,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One recorded guild change. Targets are set according to the action: members for
+/// moderation and role assignment, channels for channel edits, roles for role edits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditLogEntry {
+    pub entry_id: i64,
+    pub guild_id: GuildId,
+    pub actor_id: UserId,
+    #[serde(default)]
+    pub actor_username: Option<String>,
+    pub action: AuditAction,
+    #[serde(default)]
+    pub target_user_id: Option<UserId>,
+    #[serde(default)]
+    pub target_username: Option<String>,
+    #[serde(default)]
+    pub target_channel_id: Option<ChannelId>,
+    #[serde(default)]
+    pub target_role_id: Option<RoleId>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Human-readable specifics such as a new name or an invite code.
+    #[serde(default)]
+    pub details: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Audit log page selection. Entries come newest first; pass the last `entry_id` as
+/// `before` to fetch the next page.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditLogFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<AuditAction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_user_id: Option<i64>,
+}
+
+/// A direct conversation together with everyone taking part in it, the caller included.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectChannelSummary {
+    pub channel: ChannelSummary,
+    pub participants: Vec<DirectParticipant>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectParticipant {
+    pub user_id: UserId,
+    pub username: String,
+}
+
+/// Starts a conversation with the named users. A one-to-one request returns the existing
+/// conversation with that user when there is one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateDirectChannelRequest {
+    pub usernames: Vec<String>,
+}
+
+/// A side conversation branched off `parent_message_id`. Thread messages belong to
+/// `channel_id` and are encrypted with that channel's MLS group.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThreadSummary {
+    pub thread_id: ThreadId,
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub parent_message_id: MessageId,
+    pub creator_id: UserId,
+    pub name: String,
+    pub archived: bool,
+    pub message_count: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateThreadRequest {
+    pub parent_message_id: MessageId,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveThreadRequest {
+    pub archived: bool,
+}
+
+/// The caller's preferred guild order; guilds left out keep their current position.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReorderGuildsRequest {
+    pub guild_ids: Vec<GuildId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateChannelRequest {
+    pub name: String,
+    pub kind: ChannelKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+}
+
+/// Fields left as `None` are unchanged; an empty topic or category clears it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateChannelRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+}
+
+/// New channel order for a guild; channels left out keep their current position.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReorderChannelsRequest {
+    pub channel_ids: Vec<ChannelId>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,6 +274,50 @@ pub struct KeyPackageResponse {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub device_id: Option<DeviceId>,
     pub key_package_b64: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transparency: Option<KeyTransparencyProof>,
+}
+
+/// One entry of the append-only device identity log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyTransparencyLeaf {
+    pub leaf_index: u64,
+    pub user_id: UserId,
+    pub device_id: DeviceId,
+    pub action: KeyTransparencyAction,
+    pub device_public_identity: String,
+    pub logged_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedTreeHead {
+    pub tree_size: u64,
+    pub root_hash_b64: String,
+    pub timestamp: DateTime<Utc>,
+    pub log_public_key_b64: String,
+    pub signature_b64: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InclusionProofResponse {
+    pub leaf: KeyTransparencyLeaf,
+    pub tree_size: u64,
+    pub audit_path_b64: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsistencyProofResponse {
+    pub first_size: u64,
+    pub second_size: u64,
+    pub proof_b64: Vec<String>,
+}
+
+/// Inclusion proof for the device that owns a key package, against a signed tree head.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyTransparencyProof {
+    pub leaf: KeyTransparencyLeaf,
+    pub audit_path_b64: Vec<String>,
+    pub tree_head: SignedTreeHead,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -97,6 +332,19 @@ pub struct WelcomeResponse {
     pub consumed_at: Option<DateTime<Utc>>,
 }
 
+/// A history bundle sealed by an existing member for a newly added member. The bundle is
+/// opaque to the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryBundleResponse {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub sender_id: UserId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_device_id: Option<DeviceId>,
+    pub bundle_b64: String,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChannelStateRecord {
     pub guild_id: GuildId,
@@ -153,7 +401,13 @@ pub struct MessagePayload {
     pub ciphertext_b64: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub attachment: Option<AttachmentPayload>,
+    /// Set when the message was posted in a thread of `channel_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<ThreadId>,
     pub sent_at: DateTime<Utc>,
+    /// When the server will delete the message; clients drop their plaintext at the same time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -165,6 +419,119 @@ pub struct AttachmentPayload {
     pub mime_type: Option<String>,
 }
 
+/// Opens a resumable upload of `total_bytes` of attachment ciphertext.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateUploadRequest {
+    pub total_bytes: u64,
+}
+
+/// Where a resumable upload stands. Clients send the next chunk at `received_bytes`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadStatus {
+    pub upload_id: String,
+    pub total_bytes: u64,
+    pub received_bytes: u64,
+    /// Largest chunk body the server accepts.
+    pub max_chunk_bytes: u64,
+}
+
+/// Finishes an upload once every byte has arrived; `sha256` is the hex digest of the whole
+/// ciphertext and must match what the server received.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompleteUploadRequest {
+    pub sha256: String,
+}
+
+/// Marks an uploaded file as the thumbnail of the attachment in the request path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkThumbnailRequest {
+    pub thumbnail_file_id: FileId,
+}
+
+/// Attachment bytes counted against one quota. `reserved_bytes` are declared by uploads
+/// still in progress; `quota_bytes` is `None` when the server sets no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageUsageSummary {
+    pub used_bytes: u64,
+    pub reserved_bytes: u64,
+    pub quota_bytes: Option<u64>,
+}
+
+/// A guild's attachment storage, alongside the caller's own usage across all guilds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuildStorageUsage {
+    pub guild_id: GuildId,
+    pub guild: StorageUsageSummary,
+    pub user: StorageUsageSummary,
+}
+
+/// An account as the server admin API lists it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminUserSummary {
+    pub user_id: UserId,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub suspended_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub suspension_reason: Option<String>,
+    pub guild_count: u64,
+    /// Devices that have not been revoked.
+    pub device_count: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SuspendUserRequest {
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// A guild as the server admin API lists it. Direct conversations are included, flagged
+/// with `is_direct`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminGuildSummary {
+    pub guild_id: GuildId,
+    pub name: String,
+    pub owner_id: UserId,
+    pub is_direct: bool,
+    /// Members that are not banned.
+    pub member_count: u64,
+    pub channel_count: u64,
+    pub used_bytes: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// MLS material waiting to be picked up in one guild.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuildMlsQueue {
+    pub guild_id: GuildId,
+    pub pending_welcomes: u64,
+    pub key_packages: u64,
+}
+
+/// Server-wide MLS queue totals with the guilds that have anything queued.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MlsQueueSummary {
+    pub pending_welcomes: u64,
+    pub key_packages: u64,
+    pub guilds: Vec<GuildMlsQueue>,
+}
+
+/// Row counts and sizes across the whole server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerStorageStats {
+    pub users: u64,
+    pub suspended_users: u64,
+    pub guilds: u64,
+    pub channels: u64,
+    pub messages: u64,
+    pub files: u64,
+    /// Attachment ciphertext, wherever the blob store keeps it.
+    pub file_bytes: u64,
+    pub upload_sessions: u64,
+    pub database_bytes: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MemberSummary {
     pub guild_id: GuildId,
@@ -172,6 +539,43 @@ pub struct MemberSummary {
     pub username: String,
     pub role: Role,
     pub muted: bool,
+    /// Custom guild roles assigned to the member, on top of the built-in `role` tier.
+    #[serde(default)]
+    pub role_ids: Vec<RoleId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoleSummary {
+    pub role_id: RoleId,
+    pub guild_id: GuildId,
+    pub name: String,
+    pub color: u32,
+    /// Higher positions outrank lower ones when managing roles.
+    pub position: i64,
+    pub permissions: Permissions,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateRoleRequest {
+    pub name: String,
+    #[serde(default)]
+    pub color: u32,
+    #[serde(default)]
+    pub position: i64,
+    #[serde(default)]
+    pub permissions: Permissions,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateRoleRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Permissions>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -183,10 +587,21 @@ pub enum ServerEvent {
     ChannelUpdated {
         channel: ChannelSummary,
     },
+    GuildDeleted {
+        guild_id: GuildId,
+    },
+    ChannelDeleted {
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    },
     GuildMembersUpdated {
         guild_id: GuildId,
         members: Vec<MemberSummary>,
     },
+    GuildRolesUpdated {
+        guild_id: GuildId,
+        roles: Vec<RoleSummary>,
+    },
     MessageReceived {
         message: MessagePayload,
     },
@@ -202,6 +617,25 @@ pub enum ServerEvent {
         guild_id: GuildId,
         target_user_id: UserId,
     },
+    UserLeft {
+        guild_id: GuildId,
+        user_id: UserId,
+    },
+    /// A direct conversation was opened; only its participants receive this.
+    DirectChannelUpdated {
+        channel: DirectChannelSummary,
+    },
+    /// Messages were deleted by a per-message timer, the channel's retention policy or a
+    /// server admin.
+    MessagesExpired {
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+    },
+    /// A thread was created, archived or unarchived.
+    ThreadUpdated {
+        thread: ThreadSummary,
+    },
     LiveKitTokenIssued {
         guild_id: GuildId,
         channel_id: ChannelId,
@@ -0,0 +1,76 @@
+use super::*;
+
+fn leaves(count: usize) -> Vec<TreeHash> {
+    (0..count)
+        .map(|i| hash_leaf(format!("leaf-{i}").as_bytes()))
+        .collect()
+}
+
+#[test]
+fn inclusion_proofs_verify_for_every_leaf_and_size() {
+    for size in 1..=17 {
+        let tree = leaves(size);
+        let root = root_hash(&tree);
+        for index in 0..size {
+            let path = inclusion_path(&tree, index).expect("path");
+            assert!(
+                verify_inclusion(&tree[index], index as u64, size as u64, &path, &root),
+                "leaf {index} of {size}"
+            );
+        }
+    }
+}
+
+#[test]
+fn inclusion_proof_rejects_wrong_leaf_index_or_root() {
+    let tree = leaves(7);
+    let root = root_hash(&tree);
+    let path = inclusion_path(&tree, 3).expect("path");
+    assert!(!verify_inclusion(&tree[4], 3, 7, &path, &root));
+    assert!(!verify_inclusion(&tree[3], 2, 7, &path, &root));
+    assert!(!verify_inclusion(
+        &tree[3],
+        3,
+        7,
+        &path,
+        &root_hash(&tree[..6])
+    ));
+    assert!(inclusion_path(&tree, 7).is_none());
+}
+
+#[test]
+fn consistency_proofs_verify_for_every_prefix() {
+    for second in 1..=17 {
+        let tree = leaves(second);
+        let second_root = root_hash(&tree);
+        for first in 1..=second {
+            let first_root = root_hash(&tree[..first]);
+            let proof = consistency_proof(&tree, first).expect("proof");
+            assert!(
+                verify_consistency(
+                    first as u64,
+                    second as u64,
+                    &first_root,
+                    &second_root,
+                    &proof
+                ),
+                "{first} -> {second}"
+            );
+        }
+    }
+}
+
+#[test]
+fn consistency_proof_detects_rewritten_history() {
+    let original = leaves(6);
+    let mut forked = leaves(9);
+    forked[2] = hash_leaf(b"swapped device key");
+    let proof = consistency_proof(&forked, 6).expect("proof");
+    assert!(!verify_consistency(
+        6,
+        9,
+        &root_hash(&original),
+        &root_hash(&forked),
+        &proof
+    ));
+}
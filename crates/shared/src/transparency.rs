@@ -0,0 +1,234 @@
+//! Merkle tree primitives for the device key transparency log.
+//!
+//! Hashing and proof shapes follow RFC 9162 (Certificate Transparency v2): leaves are
+//! hashed as `SHA-256(0x00 || leaf)` and interior nodes as `SHA-256(0x01 || left || right)`.
+
+use sha2::{Digest, Sha256};
+
+use crate::{domain::KeyTransparencyAction, protocol::KeyTransparencyLeaf};
+
+pub type TreeHash = [u8; 32];
+
+const LEAF_DOMAIN: &[u8] = b"proto-rtc:kt-leaf:v1";
+const TREE_HEAD_DOMAIN: &[u8] = b"proto-rtc:kt-tree-head:v1";
+
+impl KeyTransparencyLeaf {
+    /// Canonical byte encoding hashed into the log. `leaf_index` is implied by position.
+    pub fn encode(&self) -> Vec<u8> {
+        let identity = self.device_public_identity.as_bytes();
+        let mut out = Vec::with_capacity(LEAF_DOMAIN.len() + 29 + identity.len());
+        out.extend_from_slice(LEAF_DOMAIN);
+        out.push(match self.action {
+            KeyTransparencyAction::Register => 0,
+            KeyTransparencyAction::Revoke => 1,
+        });
+        out.extend_from_slice(&self.user_id.0.to_be_bytes());
+        out.extend_from_slice(&self.device_id.0.to_be_bytes());
+        out.extend_from_slice(&self.logged_at.timestamp_millis().to_be_bytes());
+        out.extend_from_slice(&(identity.len() as u32).to_be_bytes());
+        out.extend_from_slice(identity);
+        out
+    }
+
+    pub fn leaf_hash(&self) -> TreeHash {
+        hash_leaf(&self.encode())
+    }
+}
+
+/// Bytes covered by the log signature over a tree head.
+pub fn tree_head_signing_payload(
+    tree_size: u64,
+    root_hash: &TreeHash,
+    timestamp_ms: i64,
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(TREE_HEAD_DOMAIN.len() + 48);
+    out.extend_from_slice(TREE_HEAD_DOMAIN);
+    out.extend_from_slice(&tree_size.to_be_bytes());
+    out.extend_from_slice(root_hash);
+    out.extend_from_slice(&timestamp_ms.to_be_bytes());
+    out
+}
+
+pub fn hash_leaf(data: &[u8]) -> TreeHash {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+pub fn hash_children(left: &TreeHash, right: &TreeHash) -> TreeHash {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Root hash of the tree built from `leaves` (already leaf-hashed).
+pub fn root_hash(leaves: &[TreeHash]) -> TreeHash {
+    match leaves.len() {
+        0 => Sha256::digest([]).into(),
+        1 => leaves[0],
+        n => {
+            let k = split_point(n);
+            hash_children(&root_hash(&leaves[..k]), &root_hash(&leaves[k..]))
+        }
+    }
+}
+
+/// Audit path proving `leaves[index]` is included in the tree over `leaves`.
+pub fn inclusion_path(leaves: &[TreeHash], index: usize) -> Option<Vec<TreeHash>> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut path = Vec::new();
+    collect_inclusion_path(leaves, index, &mut path);
+    Some(path)
+}
+
+fn collect_inclusion_path(leaves: &[TreeHash], index: usize, path: &mut Vec<TreeHash>) {
+    if leaves.len() <= 1 {
+        return;
+    }
+    let k = split_point(leaves.len());
+    if index < k {
+        collect_inclusion_path(&leaves[..k], index, path);
+        path.push(root_hash(&leaves[k..]));
+    } else {
+        collect_inclusion_path(&leaves[k..], index - k, path);
+        path.push(root_hash(&leaves[..k]));
+    }
+}
+
+/// Proof that the tree over the first `first_size` leaves is a prefix of the tree over `leaves`.
+pub fn consistency_proof(leaves: &[TreeHash], first_size: usize) -> Option<Vec<TreeHash>> {
+    if first_size > leaves.len() {
+        return None;
+    }
+    let mut proof = Vec::new();
+    if first_size > 0 {
+        collect_consistency_proof(leaves, first_size, true, &mut proof);
+    }
+    Some(proof)
+}
+
+fn collect_consistency_proof(
+    leaves: &[TreeHash],
+    first_size: usize,
+    is_original_subtree: bool,
+    proof: &mut Vec<TreeHash>,
+) {
+    if first_size == leaves.len() {
+        if !is_original_subtree {
+            proof.push(root_hash(leaves));
+        }
+        return;
+    }
+    let k = split_point(leaves.len());
+    if first_size <= k {
+        collect_consistency_proof(&leaves[..k], first_size, is_original_subtree, proof);
+        proof.push(root_hash(&leaves[k..]));
+    } else {
+        collect_consistency_proof(&leaves[k..], first_size - k, false, proof);
+        proof.push(root_hash(&leaves[..k]));
+    }
+}
+
+pub fn verify_inclusion(
+    leaf_hash: &TreeHash,
+    index: u64,
+    tree_size: u64,
+    path: &[TreeHash],
+    root: &TreeHash,
+) -> bool {
+    if index >= tree_size {
+        return false;
+    }
+    let mut fn_ = index;
+    let mut sn = tree_size - 1;
+    let mut computed = *leaf_hash;
+    for node in path {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            computed = hash_children(node, &computed);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            computed = hash_children(&computed, node);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    sn == 0 && computed == *root
+}
+
+pub fn verify_consistency(
+    first_size: u64,
+    second_size: u64,
+    first_root: &TreeHash,
+    second_root: &TreeHash,
+    proof: &[TreeHash],
+) -> bool {
+    if first_size > second_size {
+        return false;
+    }
+    if first_size == second_size {
+        return proof.is_empty() && first_root == second_root;
+    }
+    if first_size == 0 {
+        return proof.is_empty();
+    }
+
+    let mut nodes: Vec<TreeHash> = Vec::with_capacity(proof.len() + 1);
+    if first_size.is_power_of_two() {
+        nodes.push(*first_root);
+    }
+    nodes.extend_from_slice(proof);
+    let Some((seed, rest)) = nodes.split_first() else {
+        return false;
+    };
+
+    let mut fn_ = first_size - 1;
+    let mut sn = second_size - 1;
+    while fn_ & 1 == 1 {
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    let mut first_computed = *seed;
+    let mut second_computed = *seed;
+    for node in rest {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            first_computed = hash_children(node, &first_computed);
+            second_computed = hash_children(node, &second_computed);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            second_computed = hash_children(&second_computed, node);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    sn == 0 && first_computed == *first_root && second_computed == *second_root
+}
+
+/// Largest power of two strictly smaller than `n` (for `n > 1`).
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+#[cfg(test)]
+#[path = "tests/transparency_tests.rs"]
+mod tests;
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS key_transparency_log (
+  leaf_index INTEGER PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users(id),
+  device_id INTEGER NOT NULL REFERENCES user_devices(device_id),
+  action TEXT NOT NULL,
+  device_public_identity TEXT NOT NULL,
+  logged_at_ms INTEGER NOT NULL,
+  leaf_hash BLOB NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_key_transparency_log_device
+  ON key_transparency_log (user_id, device_id, leaf_index DESC);
+
+CREATE TABLE IF NOT EXISTS key_transparency_signing_key (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  secret_key BLOB NOT NULL,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS mls_state_backups (
+  user_id INTEGER PRIMARY KEY REFERENCES users(id),
+  backup_blob BLOB NOT NULL,
+  size_bytes INTEGER NOT NULL,
+  updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
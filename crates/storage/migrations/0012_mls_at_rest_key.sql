@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS mls_at_rest_key (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  key_source TEXT NOT NULL,
+  wrapped_key BLOB NOT NULL,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
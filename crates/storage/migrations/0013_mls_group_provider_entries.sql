@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS mls_group_provider_entries (
+  user_id INTEGER NOT NULL,
+  device_id TEXT NOT NULL,
+  guild_id INTEGER NOT NULL,
+  channel_id INTEGER NOT NULL,
+  entry_key BLOB NOT NULL,
+  entry_value BLOB NOT NULL,
+  updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, device_id, guild_id, channel_id, entry_key)
+);
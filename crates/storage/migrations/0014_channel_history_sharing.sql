@@ -0,0 +1,15 @@
+ALTER TABLE channels ADD COLUMN history_sharing INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS mls_history_bundles (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  guild_id INTEGER NOT NULL REFERENCES guilds(id),
+  channel_id INTEGER NOT NULL REFERENCES channels(id),
+  sender_user_id INTEGER NOT NULL REFERENCES users(id),
+  target_user_id INTEGER NOT NULL REFERENCES users(id),
+  target_device_id INTEGER,
+  bundle_bytes BLOB NOT NULL,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_mls_history_bundles_target
+  ON mls_history_bundles (guild_id, channel_id, target_user_id, id DESC);
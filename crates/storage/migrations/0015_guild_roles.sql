@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS guild_roles (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  guild_id INTEGER NOT NULL REFERENCES guilds(id),
+  name TEXT NOT NULL,
+  color INTEGER NOT NULL DEFAULT 0,
+  position INTEGER NOT NULL DEFAULT 0,
+  permissions INTEGER NOT NULL DEFAULT 0,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_guild_roles_guild ON guild_roles (guild_id, position DESC);
+
+CREATE TABLE IF NOT EXISTS member_roles (
+  guild_id INTEGER NOT NULL REFERENCES guilds(id),
+  user_id INTEGER NOT NULL REFERENCES users(id),
+  role_id INTEGER NOT NULL REFERENCES guild_roles(id) ON DELETE CASCADE,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (guild_id, user_id, role_id)
+);
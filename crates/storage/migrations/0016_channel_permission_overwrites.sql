@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS channel_permission_overwrites (
+  channel_id INTEGER NOT NULL REFERENCES channels(id),
+  target_kind TEXT NOT NULL,
+  target_id INTEGER NOT NULL DEFAULT 0,
+  allow_bits INTEGER NOT NULL DEFAULT 0,
+  deny_bits INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY (channel_id, target_kind, target_id)
+);
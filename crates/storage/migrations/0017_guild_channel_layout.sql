@@ -0,0 +1,6 @@
+ALTER TABLE channels ADD COLUMN topic TEXT;
+ALTER TABLE channels ADD COLUMN category TEXT;
+ALTER TABLE channels ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+-- Guild order is per member, so it lives on the membership row.
+ALTER TABLE memberships ADD COLUMN guild_position INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  guild_id INTEGER NOT NULL REFERENCES guilds(id),
+  actor_user_id INTEGER NOT NULL REFERENCES users(id),
+  action TEXT NOT NULL,
+  target_user_id INTEGER,
+  target_channel_id INTEGER,
+  target_role_id INTEGER,
+  reason TEXT,
+  details TEXT,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_guild ON audit_log (guild_id, id DESC);
+
+-- Invites are stored so they can be listed and revoked; the code is random.
+CREATE TABLE IF NOT EXISTS guild_invites (
+  code TEXT PRIMARY KEY,
+  guild_id INTEGER NOT NULL REFERENCES guilds(id),
+  creator_user_id INTEGER NOT NULL REFERENCES users(id),
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_guild_invites_guild ON guild_invites (guild_id);
@@ -0,0 +1,4 @@
+-- Direct conversations are channels of kind 'direct'. Each one is backed by a hidden
+-- scope row in `guilds` whose memberships are the participants, so key packages,
+-- welcomes and files, which are all keyed by guild, need no separate tables.
+ALTER TABLE guilds ADD COLUMN is_direct INTEGER NOT NULL DEFAULT 0;
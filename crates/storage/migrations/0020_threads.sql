@@ -0,0 +1,18 @@
+-- A thread branches off one message and lives inside that message's channel. Thread
+-- messages keep the parent `channel_id` and set `thread_id`, so they share the channel's
+-- MLS group and attachment scope.
+CREATE TABLE IF NOT EXISTS threads (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  channel_id INTEGER NOT NULL REFERENCES channels(id),
+  parent_message_id INTEGER NOT NULL UNIQUE REFERENCES messages(id),
+  creator_user_id INTEGER NOT NULL REFERENCES users(id),
+  name TEXT NOT NULL,
+  archived INTEGER NOT NULL DEFAULT 0,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_threads_channel ON threads (channel_id, id DESC);
+
+ALTER TABLE messages ADD COLUMN thread_id INTEGER REFERENCES threads(id);
+
+CREATE INDEX IF NOT EXISTS idx_messages_thread ON messages (thread_id, id);
@@ -0,0 +1,10 @@
+-- Per-channel retention limits; NULL means unlimited.
+ALTER TABLE channels ADD COLUMN retention_max_age_seconds INTEGER;
+ALTER TABLE channels ADD COLUMN retention_max_messages INTEGER;
+
+-- Per-message expiry, stored in the same format as CURRENT_TIMESTAMP so the purge can
+-- compare it directly.
+ALTER TABLE messages ADD COLUMN expires_at TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_messages_expires_at ON messages (expires_at)
+  WHERE expires_at IS NOT NULL;
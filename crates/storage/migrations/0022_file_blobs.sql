@@ -0,0 +1,6 @@
+-- Files whose ciphertext lives in a blob store keep an empty `ciphertext` and the
+-- content address of the blob here. Rows without a key still hold their bytes inline.
+ALTER TABLE files ADD COLUMN blob_key TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_files_blob_key ON files (blob_key) WHERE blob_key IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_files_inline ON files (id) WHERE blob_key IS NULL;
@@ -0,0 +1,30 @@
+-- Resumable uploads. A session accepts chunks in order until `received_bytes` reaches
+-- `total_bytes`; completing it turns the chunks into the ciphertext of a new file.
+CREATE TABLE IF NOT EXISTS upload_sessions (
+  id TEXT PRIMARY KEY,
+  uploader_user_id INTEGER NOT NULL REFERENCES users(id),
+  guild_id INTEGER NOT NULL REFERENCES guilds(id),
+  channel_id INTEGER NOT NULL REFERENCES channels(id),
+  mime_type TEXT,
+  filename TEXT,
+  total_bytes INTEGER NOT NULL,
+  received_bytes INTEGER NOT NULL DEFAULT 0,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Chunks stay keyed by their upload after completion; the file row points back through
+-- `files.upload_id`, so finishing an upload never copies ciphertext.
+CREATE TABLE IF NOT EXISTS file_chunks (
+  upload_id TEXT NOT NULL,
+  byte_offset INTEGER NOT NULL,
+  size_bytes INTEGER NOT NULL,
+  ciphertext BLOB NOT NULL DEFAULT x'',
+  blob_key TEXT,
+  PRIMARY KEY (upload_id, byte_offset)
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_chunks_blob_key ON file_chunks (blob_key) WHERE blob_key IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_upload_sessions_updated_at ON upload_sessions (updated_at);
+
+ALTER TABLE files ADD COLUMN upload_id TEXT;
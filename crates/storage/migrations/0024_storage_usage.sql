@@ -0,0 +1,21 @@
+-- Attachment bytes stored per guild and per uploader. Storage keeps these in step with
+-- `files` inside the same transactions that insert or delete file rows.
+CREATE TABLE IF NOT EXISTS guild_storage_usage (
+  guild_id INTEGER PRIMARY KEY REFERENCES guilds(id),
+  used_bytes INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS user_storage_usage (
+  user_id INTEGER PRIMARY KEY REFERENCES users(id),
+  used_bytes INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT INTO guild_storage_usage (guild_id, used_bytes)
+  SELECT guild_id, SUM(COALESCE(size_bytes, length(ciphertext))) FROM files GROUP BY guild_id;
+
+INSERT INTO user_storage_usage (user_id, used_bytes)
+  SELECT uploader_user_id, SUM(COALESCE(size_bytes, length(ciphertext))) FROM files GROUP BY uploader_user_id;
+
+-- Open upload sessions reserve their declared size against both quotas.
+CREATE INDEX IF NOT EXISTS idx_upload_sessions_guild_id ON upload_sessions (guild_id);
+CREATE INDEX IF NOT EXISTS idx_upload_sessions_uploader ON upload_sessions (uploader_user_id);
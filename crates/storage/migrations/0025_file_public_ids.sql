@@ -0,0 +1,7 @@
+-- Files are addressed on the API by a random public id instead of their row id, so ids
+-- cannot be enumerated. Row ids stay the internal key for messages and chunks.
+ALTER TABLE files ADD COLUMN public_id TEXT;
+
+UPDATE files SET public_id = lower(hex(randomblob(16))) WHERE public_id IS NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_files_public_id ON files (public_id);
@@ -0,0 +1,6 @@
+-- A thumbnail is a file of its own, linked to the attachment it previews. It lives as long
+-- as that attachment. There is no foreign key because parents are deleted first and their
+-- thumbnails are swept later in the same transaction.
+ALTER TABLE files ADD COLUMN thumbnail_of INTEGER;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_files_thumbnail_of ON files (thumbnail_of) WHERE thumbnail_of IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Server admins can suspend an account. A suspended user cannot log in or act in any
+-- guild; their data stays in place until the suspension is lifted or content is deleted.
+ALTER TABLE users ADD COLUMN suspended_at TEXT;
+ALTER TABLE users ADD COLUMN suspension_reason TEXT;
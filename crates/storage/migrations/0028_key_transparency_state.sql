@@ -0,0 +1,9 @@
+-- Client-side key transparency state (pinned log key and last verified tree head), kept
+-- with the device's MLS state so split-view detection survives restarts.
+CREATE TABLE IF NOT EXISTS key_transparency_state (
+  user_id INTEGER NOT NULL,
+  device_id TEXT NOT NULL,
+  state_json TEXT NOT NULL,
+  updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, device_id)
+);
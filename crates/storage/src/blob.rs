@@ -0,0 +1,398 @@
+//! Attachment ciphertext storage outside SQLite. Blobs are content-addressed: the key is
+//! the hex SHA-256 of the bytes, so identical uploads share one object and a key can be
+//! checked against what it returns.
+
+use std::{fmt, io::SeekFrom, path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, StatusCode};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Stores `bytes` and returns their key. Storing the same bytes twice is a no-op.
+    async fn put(&self, bytes: &[u8]) -> Result<String>;
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    /// Reads up to `len` bytes starting at `start`; fewer come back at the end of the blob.
+    async fn get_range(&self, key: &str, start: u64, len: u64) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .get(key)
+            .await?
+            .map(|bytes| slice_range(&bytes, start, len)))
+    }
+    /// Removes a blob; deleting a missing key succeeds.
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// Where attachment ciphertext lives. `Database` keeps it in `files.ciphertext`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum BlobStoreConfig {
+    Database,
+    Filesystem(PathBuf),
+    S3(S3Config),
+}
+
+impl BlobStoreConfig {
+    /// Builds the configured store; `None` means ciphertext stays in SQLite.
+    pub fn build(&self) -> Result<Option<Arc<dyn BlobStore>>> {
+        Ok(match self {
+            Self::Database => None,
+            Self::Filesystem(root) => Some(Arc::new(FilesystemBlobStore::new(root.clone()))),
+            Self::S3(config) => Some(Arc::new(S3BlobStore::new(config.clone())?)),
+        })
+    }
+}
+
+impl fmt::Debug for BlobStoreConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Database => f.write_str("Database"),
+            Self::Filesystem(root) => f.debug_tuple("Filesystem").field(root).finish(),
+            Self::S3(config) => f.debug_tuple("S3").field(config).finish(),
+        }
+    }
+}
+
+pub fn blob_key(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+fn slice_range(bytes: &[u8], start: u64, len: u64) -> Vec<u8> {
+    let start = usize::try_from(start)
+        .unwrap_or(usize::MAX)
+        .min(bytes.len());
+    let end = start
+        .saturating_add(usize::try_from(len).unwrap_or(usize::MAX))
+        .min(bytes.len());
+    bytes[start..end].to_vec()
+}
+
+fn validate_key(key: &str) -> Result<()> {
+    if key.len() == 64 && key.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        bail!("invalid blob key {key:?}")
+    }
+}
+
+/// Stores blobs as `root/ab/abcdef...`, fanned out by the first key byte.
+#[derive(Debug, Clone)]
+pub struct FilesystemBlobStore {
+    root: PathBuf,
+}
+
+impl FilesystemBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path_for(&self, key: &str) -> Result<PathBuf> {
+        validate_key(key)?;
+        Ok(self.root.join(&key[..2]).join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for FilesystemBlobStore {
+    async fn put(&self, bytes: &[u8]) -> Result<String> {
+        let key = blob_key(bytes);
+        let path = self.path_for(&key)?;
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(key);
+        }
+        let dir = path.parent().expect("blob path has a fan-out directory");
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("failed to create blob directory {}", dir.display()))?;
+        // Write to a unique temporary name first so readers never see a partial blob.
+        let temp = dir.join(format!(".{key}.{}", uuid::Uuid::new_v4()));
+        tokio::fs::write(&temp, bytes)
+            .await
+            .with_context(|| format!("failed to write blob {}", temp.display()))?;
+        tokio::fs::rename(&temp, &path)
+            .await
+            .with_context(|| format!("failed to move blob into place at {}", path.display()))?;
+        Ok(key)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path_for(key)?).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn get_range(&self, key: &str, start: u64, len: u64) -> Result<Option<Vec<u8>>> {
+        let mut file = match tokio::fs::File::open(self.path_for(key)?).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        file.seek(SeekFrom::Start(start)).await?;
+        let mut bytes = Vec::new();
+        file.take(len).read_to_end(&mut bytes).await?;
+        Ok(Some(bytes))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path_for(key)?).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Connection details for an S3-compatible bucket, addressed path-style
+/// (`{endpoint}/{bucket}/{prefix}{key}`) so MinIO and similar servers work unchanged.
+#[derive(Clone, PartialEq, Eq)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub prefix: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Config")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("prefix", &self.prefix)
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct S3BlobStore {
+    http: Client,
+    config: S3Config,
+    host: String,
+}
+
+impl S3BlobStore {
+    pub fn new(config: S3Config) -> Result<Self> {
+        let url = reqwest::Url::parse(&config.endpoint)
+            .with_context(|| format!("invalid S3 endpoint {}", config.endpoint))?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => bail!("S3 endpoint {} has no host", config.endpoint),
+        };
+        if config.bucket.is_empty() {
+            bail!("S3 bucket must not be empty");
+        }
+        Ok(Self {
+            http: Client::new(),
+            config,
+            host,
+        })
+    }
+
+    fn object_path(&self, key: &str) -> Result<String> {
+        validate_key(key)?;
+        Ok(uri_encode_path(&format!(
+            "/{}/{}{key}",
+            self.config.bucket, self.config.prefix
+        )))
+    }
+
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        body: Vec<u8>,
+        range: Option<&str>,
+    ) -> Result<reqwest::Response> {
+        let path = self.object_path(key)?;
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let range_header = range.map(|range| [("range", range)]);
+        let signed = sign_v4(
+            &SigningRequest {
+                method: method.as_str(),
+                host: &self.host,
+                path: &path,
+                headers: range_header.as_ref().map_or(&[][..], |header| &header[..]),
+                payload_hash: &payload_hash,
+            },
+            &self.config,
+            Utc::now(),
+        );
+        let url = format!("{}{path}", self.config.endpoint.trim_end_matches('/'));
+        let has_body = method == reqwest::Method::PUT;
+        let mut request = self
+            .http
+            .request(method, url)
+            .header("x-amz-date", signed.amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(reqwest::header::AUTHORIZATION, signed.authorization);
+        if has_body {
+            request = request.body(body);
+        }
+        if let Some(range) = range {
+            request = request.header(reqwest::header::RANGE, range);
+        }
+        Ok(request.send().await?)
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, bytes: &[u8]) -> Result<String> {
+        let key = blob_key(bytes);
+        let response = self
+            .send(reqwest::Method::PUT, &key, bytes.to_vec(), None)
+            .await?;
+        if !response.status().is_success() {
+            return Err(s3_error("put", &key, response).await);
+        }
+        Ok(key)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let response = self
+            .send(reqwest::Method::GET, key, Vec::new(), None)
+            .await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.bytes().await?.to_vec())),
+            _ => Err(s3_error("get", key, response).await),
+        }
+    }
+
+    async fn get_range(&self, key: &str, start: u64, len: u64) -> Result<Option<Vec<u8>>> {
+        if len == 0 {
+            return Ok(self.get(key).await?.map(|_| Vec::new()));
+        }
+        let range = format!("bytes={start}-{}", start.saturating_add(len - 1));
+        let response = self
+            .send(reqwest::Method::GET, key, Vec::new(), Some(&range))
+            .await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(Some(Vec::new())),
+            StatusCode::PARTIAL_CONTENT => Ok(Some(response.bytes().await?.to_vec())),
+            // Servers may ignore `Range` and send the whole object.
+            status if status.is_success() => {
+                Ok(Some(slice_range(&response.bytes().await?, start, len)))
+            }
+            _ => Err(s3_error("get", key, response).await),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let response = self
+            .send(reqwest::Method::DELETE, key, Vec::new(), None)
+            .await?;
+        if response.status().is_success() || response.status() == StatusCode::NOT_FOUND {
+            Ok(())
+        } else {
+            Err(s3_error("delete", key, response).await)
+        }
+    }
+}
+
+async fn s3_error(operation: &str, key: &str, response: reqwest::Response) -> anyhow::Error {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    anyhow!("S3 {operation} of blob {key} failed with {status}: {body}")
+}
+
+pub(crate) struct SigningRequest<'a> {
+    pub method: &'a str,
+    pub host: &'a str,
+    /// Already URI-encoded absolute path.
+    pub path: &'a str,
+    /// Extra headers to sign, lowercase names; `host`, `x-amz-content-sha256` and
+    /// `x-amz-date` are always signed.
+    pub headers: &'a [(&'a str, &'a str)],
+    pub payload_hash: &'a str,
+}
+
+pub(crate) struct SignedHeaders {
+    pub amz_date: String,
+    pub authorization: String,
+}
+
+/// AWS Signature Version 4 for a request without a query string.
+pub(crate) fn sign_v4(
+    request: &SigningRequest<'_>,
+    config: &S3Config,
+    now: DateTime<Utc>,
+) -> SignedHeaders {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let mut headers: Vec<(&str, &str)> = vec![
+        ("host", request.host),
+        ("x-amz-content-sha256", request.payload_hash),
+        ("x-amz-date", &amz_date),
+    ];
+    headers.extend_from_slice(request.headers);
+    headers.sort_unstable_by_key(|(name, _)| *name);
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n\n{canonical_headers}\n{signed_headers}\n{}",
+        request.method, request.path, request.payload_hash
+    );
+    let scope = format!("{date}/{}/s3/aws4_request", config.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut key = hmac_sha256(
+        format!("AWS4{}", config.secret_access_key).as_bytes(),
+        date.as_bytes(),
+    );
+    for part in [config.region.as_str(), "s3", "aws4_request"] {
+        key = hmac_sha256(&key, part.as_bytes());
+    }
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+    SignedHeaders {
+        authorization: format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope},SignedHeaders={signed_headers},Signature={signature}",
+            config.access_key_id
+        ),
+        amz_date,
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters and `/`, as SigV4 requires.
+fn uri_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+#[path = "tests/blob_tests.rs"]
+mod tests;
@@ -1,28 +1,113 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use mls::{MlsStore, PersistedGroupSnapshot};
+use mls::{MlsStore, PersistedGroupSnapshot, ProviderChanges, ProviderEntry};
 use sqlx::{
+    migrate::Migrator,
     sqlite::{SqliteConnectOptions, SqlitePoolOptions},
     Pool, Row, Sqlite,
 };
 use std::{
+    collections::BTreeSet,
     fs,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Arc,
 };
 
-use shared::domain::{
-    ChannelId, ChannelKind, DeviceId, DeviceLinkState, FileId, GuildId, LinkedDeviceSummary,
-    MessageId, Role, UserId,
+use sha2::{Digest, Sha256};
+use shared::{
+    domain::{
+        AuditAction, ChannelId, ChannelKind, DeviceId, DeviceLinkState, FileId, GuildId,
+        KeyTransparencyAction, LinkedDeviceSummary, MessageId, OverwriteTarget,
+        PermissionOverwrite, Permissions, Role, RoleId, ThreadId, UserId,
+    },
+    protocol::{
+        AdminGuildSummary, AdminUserSummary, AuditLogEntry, AuditLogFilter, GuildMlsQueue,
+        InviteSummary, KeyTransparencyLeaf, RetentionPolicy, ServerStorageStats, ThreadSummary,
+    },
+    transparency::TreeHash,
 };
 use uuid::Uuid;
 
+mod blob;
+
+pub use blob::{blob_key, BlobStore, BlobStoreConfig, FilesystemBlobStore, S3BlobStore, S3Config};
+
 #[derive(Clone)]
 pub struct Storage {
     pool: Pool<Sqlite>,
+    /// Where new attachment ciphertext goes; `None` keeps it in `files.ciphertext`.
+    blobs: Option<Arc<dyn BlobStore>>,
+    quotas: StorageQuotas,
+}
+
+/// Limits on attachment bytes, enforced when a file is stored or an upload session opens.
+/// `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StorageQuotas {
+    pub guild_bytes: Option<u64>,
+    /// Counted across every guild the user uploads to.
+    pub user_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaScope {
+    Guild,
+    User,
+}
+
+/// Returned inside the `anyhow::Error` of an upload that would go over a quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub scope: QuotaScope,
+    pub used_bytes: u64,
+    pub requested_bytes: u64,
+    pub quota_bytes: u64,
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scope = match self.scope {
+            QuotaScope::Guild => "guild",
+            QuotaScope::User => "user",
+        };
+        write!(
+            f,
+            "{scope} storage quota exceeded: {} of {} bytes used, {} more requested",
+            self.used_bytes, self.quota_bytes, self.requested_bytes
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Attachment bytes counted against a quota.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StorageUsage {
+    /// Bytes of stored files.
+    pub used_bytes: u64,
+    /// Bytes declared by upload sessions that have not completed yet.
+    pub reserved_bytes: u64,
 }
 
+impl StorageUsage {
+    pub fn total_bytes(&self) -> u64 {
+        self.used_bytes + self.reserved_bytes
+    }
+}
+
+/// MLS material waiting for devices to pick it up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MlsQueueDepths {
+    /// Welcomes not yet consumed by their target device.
+    pub pending_welcomes: u64,
+    /// Key packages of devices that have not been revoked.
+    pub key_packages: u64,
+}
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
 #[derive(Debug, Clone)]
 pub struct StoredMessage {
     pub message_id: MessageId,
@@ -30,7 +115,9 @@ pub struct StoredMessage {
     pub sender_id: UserId,
     pub ciphertext: Vec<u8>,
     pub attachment: Option<StoredAttachment>,
+    pub thread_id: Option<ThreadId>,
     pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone)]
@@ -41,6 +128,111 @@ pub struct StoredAttachment {
     pub mime_type: Option<String>,
 }
 
+/// A guild change to append to the audit log. Targets default to `None`.
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry {
+    pub guild_id: GuildId,
+    pub actor_id: UserId,
+    pub action: AuditAction,
+    pub target_user_id: Option<UserId>,
+    pub target_channel_id: Option<ChannelId>,
+    pub target_role_id: Option<RoleId>,
+    pub reason: Option<String>,
+    pub details: Option<String>,
+}
+
+impl NewAuditEntry {
+    pub fn new(guild_id: GuildId, actor_id: UserId, action: AuditAction) -> Self {
+        Self {
+            guild_id,
+            actor_id,
+            action,
+            target_user_id: None,
+            target_channel_id: None,
+            target_role_id: None,
+            reason: None,
+            details: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredChannel {
+    pub channel_id: ChannelId,
+    pub guild_id: GuildId,
+    pub name: String,
+    pub kind: ChannelKind,
+    pub topic: Option<String>,
+    pub category: Option<String>,
+    pub position: i64,
+    pub history_sharing: bool,
+    pub retention: RetentionPolicy,
+}
+
+/// What one retention purge removed. Message ids are grouped by channel and include thread
+/// messages whose parent was purged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPurge {
+    pub messages: Vec<(GuildId, ChannelId, Vec<MessageId>)>,
+    pub files: u64,
+    pub welcomes: u64,
+}
+
+/// How long each kind of leftover row is kept before maintenance deletes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenancePolicy {
+    /// Consumed Welcomes, counted from consumption. The newest one per recipient device
+    /// backs Welcome recovery until this runs out.
+    pub consumed_welcome_ttl: chrono::Duration,
+    /// Key packages superseded by a newer upload for the same guild, user and device, or
+    /// belonging to a revoked device.
+    pub key_package_ttl: chrono::Duration,
+    /// Device link tokens after they expire or are consumed, with their bundles.
+    pub device_link_ttl: chrono::Duration,
+    /// Uploads that no message references.
+    pub orphan_file_ttl: chrono::Duration,
+    /// Upload sessions that received no chunk for this long, with their chunks.
+    pub upload_session_ttl: chrono::Duration,
+    /// Run `VACUUM` afterwards to return freed pages to the filesystem.
+    pub compact: bool,
+}
+
+impl Default for MaintenancePolicy {
+    fn default() -> Self {
+        Self {
+            consumed_welcome_ttl: chrono::Duration::days(7),
+            key_package_ttl: chrono::Duration::days(30),
+            device_link_ttl: chrono::Duration::days(1),
+            orphan_file_ttl: chrono::Duration::days(1),
+            upload_session_ttl: chrono::Duration::days(1),
+            compact: true,
+        }
+    }
+}
+
+/// Rows deleted by one maintenance run and the bytes compaction gave back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    pub welcomes: u64,
+    pub key_packages: u64,
+    pub device_link_tokens: u64,
+    pub device_link_bundles: u64,
+    pub files: u64,
+    pub upload_sessions: u64,
+    pub reclaimed_bytes: u64,
+}
+
+impl MaintenanceReport {
+    pub fn rows(&self) -> u64 {
+        self.welcomes
+            + self.key_packages
+            + self.device_link_tokens
+            + self.device_link_bundles
+            + self.files
+            + self.upload_sessions
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct StoredFile {
     pub file_id: FileId,
@@ -52,6 +244,33 @@ pub struct StoredFile {
     pub size_bytes: u64,
 }
 
+/// A file's row without its ciphertext; read the bytes with [`Storage::read_file_range`].
+#[derive(Debug, Clone)]
+pub struct FileMetadata {
+    pub file_id: FileId,
+    pub uploader_id: UserId,
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub mime_type: Option<String>,
+    pub filename: Option<String>,
+    pub size_bytes: u64,
+    /// The attachment this file is the thumbnail of.
+    pub thumbnail_of: Option<FileId>,
+}
+
+/// A resumable upload that has not been completed yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadSession {
+    pub upload_id: String,
+    pub uploader_id: UserId,
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub mime_type: Option<String>,
+    pub filename: Option<String>,
+    pub total_bytes: u64,
+    pub received_bytes: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct StoredDeviceSummary {
     pub device_id: DeviceId,
@@ -73,12 +292,31 @@ pub struct ConsumedPendingWelcome {
     pub consumed_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Clone)]
+pub struct StoredHistoryBundle {
+    pub sender_id: UserId,
+    pub target_device_id: Option<DeviceId>,
+    pub bundle_bytes: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone)]
 pub struct StoredMember {
     pub user_id: UserId,
     pub username: String,
     pub role: Role,
     pub muted: bool,
+    pub role_ids: Vec<RoleId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredGuildRole {
+    pub role_id: RoleId,
+    pub guild_id: GuildId,
+    pub name: String,
+    pub color: u32,
+    pub position: i64,
+    pub permissions: Permissions,
 }
 
 #[derive(Debug, Clone)]
@@ -100,6 +338,36 @@ pub struct StoredDeviceLinkBundle {
     pub bundle_json: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoredMlsBlobKind {
+    Identity,
+    GroupState,
+    PendingJoin,
+    GroupProviderEntry,
+}
+
+/// Client-side MLS secret material from `mls_identity_keys`, `mls_group_states`,
+/// `mls_group_provider_entries` or `mls_pending_join_state`. Identity rows use
+/// `GuildId(0)`/`ChannelId(0)`, pending join rows use `ChannelId(0)`, and `key_material_blob`
+/// is empty for identities. Provider entry rows carry the entry value in `primary_blob` and
+/// the plaintext entry key in `key_material_blob`.
+#[derive(Debug, Clone)]
+pub struct StoredMlsBlob {
+    pub kind: StoredMlsBlobKind,
+    pub user_id: i64,
+    pub device_id: String,
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub primary_blob: Vec<u8>,
+    pub key_material_blob: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredMlsAtRestKey {
+    pub key_source: String,
+    pub wrapped_key: Vec<u8>,
+}
+
 impl Storage {
     pub async fn new(database_url: &str) -> Result<Self> {
         ensure_sqlite_parent_dir_exists(database_url)?;
@@ -109,8 +377,28 @@ impl Storage {
             .max_connections(5)
             .connect_with(connect_options)
             .await?;
-        sqlx::migrate!("./migrations").run(&pool).await?;
-        Ok(Self { pool })
+        MIGRATOR.run(&pool).await?;
+        Ok(Self {
+            pool,
+            blobs: None,
+            quotas: StorageQuotas::default(),
+        })
+    }
+
+    /// Stores new attachments in `blobs` instead of SQLite. Files already stored inline stay
+    /// readable; move them with [`Storage::migrate_files_to_blob_store`].
+    pub fn with_blob_store(mut self, blobs: Option<Arc<dyn BlobStore>>) -> Self {
+        self.blobs = blobs;
+        self
+    }
+
+    pub fn with_storage_quotas(mut self, quotas: StorageQuotas) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    pub fn storage_quotas(&self) -> StorageQuotas {
+        self.quotas
     }
 
     pub fn pool(&self) -> &Pool<Sqlite> {
@@ -125,6 +413,110 @@ impl Storage {
         Ok(())
     }
 
+    /// Fails unless every migration this build ships has been applied successfully.
+    pub async fn check_migrations(&self) -> Result<()> {
+        let applied: BTreeSet<i64> =
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 1")
+                .fetch_all(&self.pool)
+                .await
+                .context("failed to read applied migrations")?
+                .into_iter()
+                .collect();
+        let missing: Vec<String> = MIGRATOR
+            .iter()
+            .filter(|migration| !applied.contains(&migration.version))
+            .map(|migration| format!("{} ({})", migration.version, migration.description))
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!("migrations not applied: {}", missing.join(", ")));
+        }
+        Ok(())
+    }
+
+    /// Writes and reads back a probe blob. Succeeds without a blob store, since SQLite then
+    /// holds the ciphertext and [`Storage::health_check`] covers it.
+    pub async fn check_blob_store(&self) -> Result<()> {
+        const PROBE: &[u8] = b"blob store readiness probe";
+        let Some(blobs) = &self.blobs else {
+            return Ok(());
+        };
+        let key = blobs.put(PROBE).await.context("blob store write failed")?;
+        match blobs.get(&key).await.context("blob store read failed")? {
+            Some(bytes) if bytes == PROBE => Ok(()),
+            Some(_) => Err(anyhow!("blob store returned different bytes for {key}")),
+            None => Err(anyhow!("blob store lost {key} right after writing it")),
+        }
+    }
+
+    pub async fn mls_queue_depths(&self) -> Result<MlsQueueDepths> {
+        let pending_welcomes: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM pending_welcomes WHERE consumed_at IS NULL")
+                .fetch_one(&self.pool)
+                .await?;
+        let key_packages: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*)
+             FROM mls_key_packages kp
+             LEFT JOIN user_devices ud ON ud.device_id = kp.device_id
+             WHERE kp.device_id IS NULL OR COALESCE(ud.is_revoked, 0) = 0",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(MlsQueueDepths {
+            pending_welcomes: pending_welcomes.max(0) as u64,
+            key_packages: key_packages.max(0) as u64,
+        })
+    }
+
+    /// [`Storage::mls_queue_depths`] per guild, for guilds with anything queued.
+    pub async fn mls_queue_by_guild(&self) -> Result<Vec<GuildMlsQueue>> {
+        let rows: Vec<(i64, i64, i64)> = sqlx::query_as(
+            "SELECT guild_id, SUM(welcomes), SUM(key_packages)
+             FROM (SELECT guild_id, 1 AS welcomes, 0 AS key_packages
+                   FROM pending_welcomes WHERE consumed_at IS NULL
+                   UNION ALL
+                   SELECT kp.guild_id, 0, 1
+                   FROM mls_key_packages kp
+                   LEFT JOIN user_devices ud ON ud.device_id = kp.device_id
+                   WHERE kp.device_id IS NULL OR COALESCE(ud.is_revoked, 0) = 0)
+             GROUP BY guild_id
+             ORDER BY guild_id ASC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(guild_id, pending_welcomes, key_packages)| GuildMlsQueue {
+                guild_id: GuildId(guild_id),
+                pending_welcomes: pending_welcomes.max(0) as u64,
+                key_packages: key_packages.max(0) as u64,
+            })
+            .collect())
+    }
+
+    pub async fn storage_stats(&self) -> Result<ServerStorageStats> {
+        let count = |sql: &'static str| async move {
+            sqlx::query_scalar::<_, i64>(sql)
+                .fetch_one(&self.pool)
+                .await
+                .map(|count| count.max(0) as u64)
+        };
+        Ok(ServerStorageStats {
+            users: count("SELECT COUNT(*) FROM users").await?,
+            suspended_users: count("SELECT COUNT(*) FROM users WHERE suspended_at IS NOT NULL")
+                .await?,
+            guilds: count("SELECT COUNT(*) FROM guilds").await?,
+            channels: count("SELECT COUNT(*) FROM channels").await?,
+            messages: count("SELECT COUNT(*) FROM messages").await?,
+            files: count("SELECT COUNT(*) FROM files").await?,
+            file_bytes: count(
+                "SELECT COALESCE(SUM(COALESCE(size_bytes, length(ciphertext))), 0) FROM files",
+            )
+            .await?,
+            upload_sessions: count("SELECT COUNT(*) FROM upload_sessions").await?,
+            database_bytes: self.database_size_bytes().await?,
+        })
+    }
+
     async fn ensure_pending_join_table(&self) -> Result<()> {
         sqlx::query(
             r#"
@@ -202,6 +594,114 @@ impl Storage {
         Ok(row.map(|r| r.get::<String, _>(0)))
     }
 
+    pub async fn user_id_for_username(&self, username: &str) -> Result<Option<UserId>> {
+        Ok(
+            sqlx::query_scalar::<_, i64>("SELECT id FROM users WHERE username = ?")
+                .bind(username)
+                .fetch_optional(&self.pool)
+                .await?
+                .map(UserId),
+        )
+    }
+
+    /// Accounts whose username contains `search` (all accounts without one), oldest first.
+    pub async fn list_users(
+        &self,
+        search: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<AdminUserSummary>> {
+        let pattern = search.map(|search| {
+            let escaped = search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{escaped}%")
+        });
+        let rows = sqlx::query(&format!(
+            "{ADMIN_USER_SELECT}
+             WHERE ?1 IS NULL OR u.username LIKE ?1 ESCAPE '\\'
+             ORDER BY u.id ASC
+             LIMIT ?2"
+        ))
+        .bind(pattern)
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(admin_user_from_row).collect())
+    }
+
+    pub async fn admin_user(&self, user_id: UserId) -> Result<Option<AdminUserSummary>> {
+        let row = sqlx::query(&format!("{ADMIN_USER_SELECT} WHERE u.id = ?"))
+            .bind(user_id.0)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.as_ref().map(admin_user_from_row))
+    }
+
+    /// Suspends an account. Suspending again replaces the reason and keeps the original
+    /// time. Returns `false` when the user does not exist.
+    pub async fn suspend_user(&self, user_id: UserId, reason: Option<&str>) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE users
+             SET suspended_at = COALESCE(suspended_at, CURRENT_TIMESTAMP), suspension_reason = ?
+             WHERE id = ?",
+        )
+        .bind(reason)
+        .bind(user_id.0)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn unsuspend_user(&self, user_id: UserId) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE users SET suspended_at = NULL, suspension_reason = NULL WHERE id = ?",
+        )
+        .bind(user_id.0)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn user_is_suspended(&self, user_id: UserId) -> Result<bool> {
+        Ok(
+            sqlx::query_scalar::<_, bool>(
+                "SELECT suspended_at IS NOT NULL FROM users WHERE id = ?",
+            )
+            .bind(user_id.0)
+            .fetch_optional(&self.pool)
+            .await?
+            .unwrap_or(false),
+        )
+    }
+
+    /// Every guild, direct conversations included, with member and channel counts.
+    pub async fn list_all_guilds(&self) -> Result<Vec<AdminGuildSummary>> {
+        let rows = sqlx::query(
+            "SELECT g.id, g.name, g.owner_user_id, g.is_direct, g.created_at,
+                    (SELECT COUNT(*) FROM memberships m WHERE m.guild_id = g.id AND m.banned = 0),
+                    (SELECT COUNT(*) FROM channels c WHERE c.guild_id = g.id),
+                    COALESCE((SELECT used_bytes FROM guild_storage_usage s WHERE s.guild_id = g.id), 0)
+             FROM guilds g
+             ORDER BY g.id ASC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .iter()
+            .map(|row| AdminGuildSummary {
+                guild_id: GuildId(row.get::<i64, _>(0)),
+                name: row.get::<String, _>(1),
+                owner_id: UserId(row.get::<i64, _>(2)),
+                is_direct: row.get::<bool, _>(3),
+                created_at: row.get::<DateTime<Utc>, _>(4),
+                member_count: row.get::<i64, _>(5).max(0) as u64,
+                channel_count: row.get::<i64, _>(6).max(0) as u64,
+                used_bytes: row.get::<i64, _>(7).max(0) as u64,
+            })
+            .collect())
+    }
+
     pub async fn create_guild(&self, name: &str, owner_user_id: UserId) -> Result<GuildId> {
         let rec =
             sqlx::query("INSERT INTO guilds (name, owner_user_id) VALUES (?, ?) RETURNING id")
@@ -222,51 +722,466 @@ impl Storage {
         kind: ChannelKind,
     ) -> Result<ChannelId> {
         let rec = sqlx::query(
-            "INSERT INTO channels (guild_id, name, kind) VALUES (?, ?, ?) RETURNING id",
+            "INSERT INTO channels (guild_id, name, kind, position)
+             VALUES (?, ?, ?, (SELECT COALESCE(MAX(position) + 1, 0) FROM channels WHERE guild_id = ?))
+             RETURNING id",
         )
         .bind(guild_id.0)
         .bind(name)
-        .bind(match kind {
-            ChannelKind::Text => "text",
-            ChannelKind::Voice => "voice",
-        })
+        .bind(channel_kind_str(kind))
+        .bind(guild_id.0)
         .fetch_one(&self.pool)
         .await?;
         Ok(ChannelId(rec.get::<i64, _>(0)))
     }
 
-    pub async fn add_membership(
+    /// Updates a channel's name, topic and category. `None` clears topic or category.
+    pub async fn update_channel(
+        &self,
+        channel_id: ChannelId,
+        name: &str,
+        topic: Option<&str>,
+        category: Option<&str>,
+    ) -> Result<bool> {
+        let result =
+            sqlx::query("UPDATE channels SET name = ?, topic = ?, category = ? WHERE id = ?")
+                .bind(name)
+                .bind(topic)
+                .bind(category)
+                .bind(channel_id.0)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Gives the listed channels positions in list order. Channels of other guilds are
+    /// ignored; unlisted channels keep their position.
+    pub async fn reorder_channels(
         &self,
         guild_id: GuildId,
-        user_id: UserId,
-        role: Role,
-        banned: bool,
-        muted: bool,
+        channel_ids: &[ChannelId],
     ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (position, channel_id) in channel_ids.iter().enumerate() {
+            sqlx::query("UPDATE channels SET position = ? WHERE id = ? AND guild_id = ?")
+                .bind(position as i64)
+                .bind(channel_id.0)
+                .bind(guild_id.0)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Deletes a channel together with its messages, files, overwrites, pending welcomes
+    /// and history bundles.
+    pub async fn delete_channel(&self, channel_id: ChannelId) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let mut released = Vec::new();
+        let deleted = delete_channel_rows(&mut tx, channel_id, &mut released).await?;
+        tx.commit().await?;
+        self.release_blobs(released).await?;
+        Ok(deleted)
+    }
+
+    pub async fn rename_guild(&self, guild_id: GuildId, name: &str) -> Result<bool> {
+        let result = sqlx::query("UPDATE guilds SET name = ? WHERE id = ?")
+            .bind(name)
+            .bind(guild_id.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Stores `user_id`'s preferred guild order. Guilds they are not in are ignored.
+    pub async fn reorder_guilds(&self, user_id: UserId, guild_ids: &[GuildId]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (position, guild_id) in guild_ids.iter().enumerate() {
+            sqlx::query(
+                "UPDATE memberships SET guild_position = ? WHERE guild_id = ? AND user_id = ?",
+            )
+            .bind(position as i64)
+            .bind(guild_id.0)
+            .bind(user_id.0)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Deletes a guild and everything scoped to it: channels and their contents, roles,
+    /// memberships, key packages, invites and the audit log.
+    pub async fn delete_guild(&self, guild_id: GuildId) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let channel_ids: Vec<i64> =
+            sqlx::query_scalar("SELECT id FROM channels WHERE guild_id = ?")
+                .bind(guild_id.0)
+                .fetch_all(&mut *tx)
+                .await?;
+        let mut released = Vec::new();
+        for channel_id in channel_ids {
+            delete_channel_rows(&mut tx, ChannelId(channel_id), &mut released).await?;
+        }
+        for statement in [
+            "DELETE FROM audit_log WHERE guild_id = ?",
+            "DELETE FROM guild_invites WHERE guild_id = ?",
+            "DELETE FROM member_roles WHERE guild_id = ?",
+            "DELETE FROM guild_roles WHERE guild_id = ?",
+            "DELETE FROM mls_key_packages WHERE guild_id = ?",
+            "DELETE FROM memberships WHERE guild_id = ?",
+            "DELETE FROM guild_storage_usage WHERE guild_id = ?",
+        ] {
+            sqlx::query(statement)
+                .bind(guild_id.0)
+                .execute(&mut *tx)
+                .await?;
+        }
+        let result = sqlx::query("DELETE FROM guilds WHERE id = ?")
+            .bind(guild_id.0)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        self.release_blobs(released).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Removes `user_id` from a guild along with their role assignments, member overwrites,
+    /// key packages, pending welcomes and history bundles addressed to them.
+    pub async fn remove_membership(&self, guild_id: GuildId, user_id: UserId) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        delete_member_rows(&mut tx, guild_id, user_id).await?;
+        let result = sqlx::query("DELETE FROM memberships WHERE user_id = ? AND guild_id = ?")
+            .bind(user_id.0)
+            .bind(guild_id.0)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Bans `user_id` from a guild, dropping the same member-scoped rows as
+    /// [`Storage::remove_membership`]. The banned membership row stays so they cannot rejoin.
+    pub async fn ban_member(&self, guild_id: GuildId, user_id: UserId) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        delete_member_rows(&mut tx, guild_id, user_id).await?;
         sqlx::query(
             "INSERT INTO memberships (guild_id, user_id, role, banned, muted)
-             VALUES (?, ?, ?, ?, ?)
-             ON CONFLICT(guild_id, user_id) DO UPDATE SET role=excluded.role, banned=excluded.banned, muted=excluded.muted",
+             VALUES (?, ?, 'member', 1, 0)
+             ON CONFLICT(guild_id, user_id) DO UPDATE SET role = 'member', banned = 1, muted = 0",
         )
         .bind(guild_id.0)
         .bind(user_id.0)
-        .bind(match role { Role::Owner => "owner", Role::Mod => "mod", Role::Member => "member" })
-        .bind(banned)
-        .bind(muted)
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
+        tx.commit().await?;
         Ok(())
     }
 
-    pub async fn list_guilds_for_user(&self, user_id: UserId) -> Result<Vec<(GuildId, String)>> {
-        let rows = sqlx::query(
-            "SELECT g.id, g.name
-             FROM guilds g
-             INNER JOIN memberships m ON m.guild_id = g.id
-             WHERE m.user_id = ? AND m.banned = 0",
+    /// Lifts a ban by dropping the banned membership row; the user may join again.
+    pub async fn unban_member(&self, guild_id: GuildId, user_id: UserId) -> Result<bool> {
+        let result = sqlx::query(
+            "DELETE FROM memberships WHERE guild_id = ? AND user_id = ? AND banned = 1",
         )
+        .bind(guild_id.0)
         .bind(user_id.0)
-        .fetch_all(&self.pool)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn set_member_muted(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        muted: bool,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE memberships SET muted = ? WHERE guild_id = ? AND user_id = ? AND banned = 0",
+        )
+        .bind(muted)
+        .bind(guild_id.0)
+        .bind(user_id.0)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Makes `new_owner` the guild owner and demotes the previous owner to `Mod`.
+    pub async fn transfer_guild_ownership(
+        &self,
+        guild_id: GuildId,
+        previous_owner: UserId,
+        new_owner: UserId,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE guilds SET owner_user_id = ? WHERE id = ?")
+            .bind(new_owner.0)
+            .bind(guild_id.0)
+            .execute(&mut *tx)
+            .await?;
+        for (user_id, role) in [(previous_owner, "mod"), (new_owner, "owner")] {
+            sqlx::query("UPDATE memberships SET role = ? WHERE guild_id = ? AND user_id = ?")
+                .bind(role)
+                .bind(guild_id.0)
+                .bind(user_id.0)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Whether the guild row is the hidden scope of a direct conversation.
+    pub async fn guild_is_direct(&self, guild_id: GuildId) -> Result<bool> {
+        Ok(
+            sqlx::query_scalar::<_, bool>("SELECT is_direct FROM guilds WHERE id = ?")
+                .bind(guild_id.0)
+                .fetch_optional(&self.pool)
+                .await?
+                .unwrap_or(false),
+        )
+    }
+
+    /// Opens a direct conversation between `creator_id` and `participant_ids`: a hidden
+    /// scope whose memberships are the participants, holding a single `direct` channel.
+    pub async fn create_direct_channel(
+        &self,
+        creator_id: UserId,
+        participant_ids: &[UserId],
+    ) -> Result<ChannelId> {
+        let mut tx = self.pool.begin().await?;
+        let guild_id: i64 = sqlx::query_scalar(
+            "INSERT INTO guilds (name, owner_user_id, is_direct) VALUES ('direct', ?, 1)
+             RETURNING id",
+        )
+        .bind(creator_id.0)
+        .fetch_one(&mut *tx)
+        .await?;
+        let mut user_ids = vec![creator_id];
+        user_ids.extend_from_slice(participant_ids);
+        user_ids.sort_by_key(|user_id| user_id.0);
+        user_ids.dedup();
+        for user_id in user_ids {
+            sqlx::query(
+                "INSERT INTO memberships (guild_id, user_id, role, banned, muted)
+                 VALUES (?, ?, 'member', 0, 0)",
+            )
+            .bind(guild_id)
+            .bind(user_id.0)
+            .execute(&mut *tx)
+            .await?;
+        }
+        let channel_id: i64 = sqlx::query_scalar(
+            "INSERT INTO channels (guild_id, name, kind, position) VALUES (?, 'direct', ?, 0)
+             RETURNING id",
+        )
+        .bind(guild_id)
+        .bind(channel_kind_str(ChannelKind::Direct))
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(ChannelId(channel_id))
+    }
+
+    /// Finds the direct conversation whose participants are exactly `user_ids`.
+    pub async fn find_direct_channel(&self, user_ids: &[UserId]) -> Result<Option<ChannelId>> {
+        let mut user_ids: Vec<i64> = user_ids.iter().map(|user_id| user_id.0).collect();
+        user_ids.sort_unstable();
+        user_ids.dedup();
+        let ids_json = json_id_array(user_ids.iter().copied());
+        Ok(sqlx::query_scalar::<_, i64>(
+            "SELECT c.id
+             FROM channels c
+             INNER JOIN guilds g ON g.id = c.guild_id
+             WHERE g.is_direct = 1
+               AND (SELECT COUNT(*) FROM memberships m WHERE m.guild_id = g.id) = ?
+               AND (SELECT COUNT(*) FROM memberships m
+                    WHERE m.guild_id = g.id
+                      AND m.user_id IN (SELECT value FROM json_each(?))) = ?
+             ORDER BY c.id ASC
+             LIMIT 1",
+        )
+        .bind(user_ids.len() as i64)
+        .bind(ids_json)
+        .bind(user_ids.len() as i64)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(ChannelId))
+    }
+
+    /// Direct conversations the user takes part in, most recently opened first.
+    pub async fn list_direct_channels_for_user(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<StoredChannel>> {
+        let rows = sqlx::query(
+            "SELECT c.id, c.guild_id, c.name, c.kind, c.topic, c.category, c.position, c.history_sharing,
+                    c.retention_max_age_seconds, c.retention_max_messages
+             FROM channels c
+             INNER JOIN guilds g ON g.id = c.guild_id
+             INNER JOIN memberships m ON m.guild_id = g.id
+             WHERE g.is_direct = 1 AND m.user_id = ? AND m.banned = 0
+             ORDER BY c.id DESC",
+        )
+        .bind(user_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(stored_channel_from_row).collect())
+    }
+
+    pub async fn guild_name(&self, guild_id: GuildId) -> Result<Option<String>> {
+        Ok(sqlx::query_scalar("SELECT name FROM guilds WHERE id = ?")
+            .bind(guild_id.0)
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    pub async fn create_invite(
+        &self,
+        guild_id: GuildId,
+        creator_id: UserId,
+        code: &str,
+    ) -> Result<InviteSummary> {
+        let created_at: DateTime<Utc> = sqlx::query_scalar(
+            "INSERT INTO guild_invites (code, guild_id, creator_user_id) VALUES (?, ?, ?)
+             RETURNING created_at",
+        )
+        .bind(code)
+        .bind(guild_id.0)
+        .bind(creator_id.0)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(InviteSummary {
+            invite_code: code.to_string(),
+            guild_id,
+            creator_id,
+            created_at,
+        })
+    }
+
+    pub async fn invite(&self, code: &str) -> Result<Option<InviteSummary>> {
+        let row = sqlx::query(
+            "SELECT code, guild_id, creator_user_id, created_at FROM guild_invites WHERE code = ?",
+        )
+        .bind(code)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.as_ref().map(invite_from_row))
+    }
+
+    pub async fn list_invites(&self, guild_id: GuildId) -> Result<Vec<InviteSummary>> {
+        let rows = sqlx::query(
+            "SELECT code, guild_id, creator_user_id, created_at
+             FROM guild_invites
+             WHERE guild_id = ?
+             ORDER BY created_at DESC, code ASC",
+        )
+        .bind(guild_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(invite_from_row).collect())
+    }
+
+    pub async fn delete_invite(&self, code: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM guild_invites WHERE code = ?")
+            .bind(code)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn append_audit_entry(&self, entry: &NewAuditEntry) -> Result<i64> {
+        Ok(sqlx::query_scalar(
+            "INSERT INTO audit_log
+               (guild_id, actor_user_id, action, target_user_id, target_channel_id,
+                target_role_id, reason, details)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+             RETURNING id",
+        )
+        .bind(entry.guild_id.0)
+        .bind(entry.actor_id.0)
+        .bind(entry.action.as_str())
+        .bind(entry.target_user_id.map(|id| id.0))
+        .bind(entry.target_channel_id.map(|id| id.0))
+        .bind(entry.target_role_id.map(|id| id.0))
+        .bind(entry.reason.as_deref())
+        .bind(entry.details.as_deref())
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Returns a page of a guild's audit log, newest first. Unset filter fields match
+    /// everything; `limit` defaults to 50.
+    pub async fn list_audit_entries(
+        &self,
+        guild_id: GuildId,
+        filter: &AuditLogFilter,
+    ) -> Result<Vec<AuditLogEntry>> {
+        let rows = sqlx::query(
+            "SELECT a.id, a.guild_id, a.actor_user_id, actor.username, a.action,
+                    a.target_user_id, target.username, a.target_channel_id, a.target_role_id,
+                    a.reason, a.details, a.created_at
+             FROM audit_log a
+             LEFT JOIN users actor ON actor.id = a.actor_user_id
+             LEFT JOIN users target ON target.id = a.target_user_id
+             WHERE a.guild_id = ?
+               AND (? IS NULL OR a.id < ?)
+               AND (? IS NULL OR a.action = ?)
+               AND (? IS NULL OR a.actor_user_id = ?)
+               AND (? IS NULL OR a.target_user_id = ?)
+             ORDER BY a.id DESC
+             LIMIT ?",
+        )
+        .bind(guild_id.0)
+        .bind(filter.before)
+        .bind(filter.before)
+        .bind(filter.action.map(AuditAction::as_str))
+        .bind(filter.action.map(AuditAction::as_str))
+        .bind(filter.actor_id)
+        .bind(filter.actor_id)
+        .bind(filter.target_user_id)
+        .bind(filter.target_user_id)
+        .bind(filter.limit.unwrap_or(50))
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(audit_entry_from_row).collect()
+    }
+
+    pub async fn add_membership(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        role: Role,
+        banned: bool,
+        muted: bool,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO memberships (guild_id, user_id, role, banned, muted)
+             VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT(guild_id, user_id) DO UPDATE SET role=excluded.role, banned=excluded.banned, muted=excluded.muted",
+        )
+        .bind(guild_id.0)
+        .bind(user_id.0)
+        .bind(match role { Role::Owner => "owner", Role::Mod => "mod", Role::Member => "member" })
+        .bind(banned)
+        .bind(muted)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_guilds_for_user(&self, user_id: UserId) -> Result<Vec<(GuildId, String)>> {
+        let rows = sqlx::query(
+            "SELECT g.id, g.name
+             FROM guilds g
+             INNER JOIN memberships m ON m.guild_id = g.id
+             WHERE m.user_id = ? AND m.banned = 0 AND g.is_direct = 0
+             ORDER BY m.guild_position ASC, g.id ASC",
+        )
+        .bind(user_id.0)
+        .fetch_all(&self.pool)
         .await?;
         Ok(rows
             .into_iter()
@@ -274,23 +1189,288 @@ impl Storage {
             .collect())
     }
 
-    pub async fn list_channels_for_guild(
+    /// Channels of a guild in display order.
+    pub async fn list_channels_for_guild(&self, guild_id: GuildId) -> Result<Vec<StoredChannel>> {
+        let rows = sqlx::query(
+            "SELECT id, guild_id, name, kind, topic, category, position, history_sharing,
+                    retention_max_age_seconds, retention_max_messages
+             FROM channels
+             WHERE guild_id = ?
+             ORDER BY position ASC, id ASC",
+        )
+        .bind(guild_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(stored_channel_from_row).collect())
+    }
+
+    pub async fn channel(&self, channel_id: ChannelId) -> Result<Option<StoredChannel>> {
+        let row = sqlx::query(
+            "SELECT id, guild_id, name, kind, topic, category, position, history_sharing,
+                    retention_max_age_seconds, retention_max_messages
+             FROM channels
+             WHERE id = ?",
+        )
+        .bind(channel_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.as_ref().map(stored_channel_from_row))
+    }
+
+    pub async fn set_channel_history_sharing(
         &self,
-        guild_id: GuildId,
-    ) -> Result<Vec<(ChannelId, String, ChannelKind)>> {
-        let rows = sqlx::query("SELECT id, name, kind FROM channels WHERE guild_id = ?")
-            .bind(guild_id.0)
-            .fetch_all(&self.pool)
+        channel_id: ChannelId,
+        enabled: bool,
+    ) -> Result<()> {
+        sqlx::query("UPDATE channels SET history_sharing = ? WHERE id = ?")
+            .bind(enabled)
+            .bind(channel_id.0)
+            .execute(&self.pool)
             .await?;
+        Ok(())
+    }
+
+    pub async fn set_channel_retention(
+        &self,
+        channel_id: ChannelId,
+        retention: RetentionPolicy,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE channels SET retention_max_age_seconds = ?, retention_max_messages = ?
+             WHERE id = ?",
+        )
+        .bind(retention.max_age_seconds.map(|seconds| seconds as i64))
+        .bind(retention.max_messages.map(|count| count as i64))
+        .bind(channel_id.0)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes messages past their expiry or outside their channel's retention policy as of
+    /// `now`, together with threads anchored to them and attachments nothing else references.
+    /// Also drops consumed Welcomes, keeping the newest per recipient for recovery.
+    pub async fn purge_expired(&self, now: DateTime<Utc>) -> Result<RetentionPurge> {
+        let now = sql_timestamp(now);
+        let mut tx = self.pool.begin().await?;
+        let mut rows: Vec<(i64, i64, i64)> = sqlx::query_as(
+            "SELECT m.id, m.channel_id, c.guild_id
+             FROM messages m
+             INNER JOIN channels c ON c.id = m.channel_id
+             WHERE (m.expires_at IS NOT NULL AND m.expires_at <= ?1)
+                OR (c.retention_max_age_seconds IS NOT NULL
+                    AND m.created_at <= datetime(?1, '-' || c.retention_max_age_seconds || ' seconds'))
+             UNION
+             SELECT ranked.id, ranked.channel_id, ranked.guild_id
+             FROM (SELECT m.id, m.channel_id, c.guild_id, c.retention_max_messages AS max_messages,
+                          ROW_NUMBER() OVER (PARTITION BY m.channel_id ORDER BY m.id DESC) AS rank
+                   FROM messages m
+                   INNER JOIN channels c ON c.id = m.channel_id
+                   WHERE m.thread_id IS NULL AND c.retention_max_messages IS NOT NULL) ranked
+             WHERE ranked.rank > ranked.max_messages",
+        )
+        .bind(&now)
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut released = Vec::new();
+        let files = delete_message_rows(&mut tx, &mut rows, &mut released).await?;
+        let welcomes = sqlx::query(
+            "DELETE FROM pending_welcomes
+             WHERE consumed_at IS NOT NULL
+               AND id NOT IN (SELECT MAX(id) FROM pending_welcomes
+                              GROUP BY guild_id, channel_id, user_id, target_device_id)",
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+        self.release_blobs(released).await?;
+
+        Ok(RetentionPurge {
+            messages: group_by_channel(rows),
+            files,
+            welcomes,
+        })
+    }
+
+    /// Deletes a message regardless of retention, with any thread anchored to it and
+    /// attachments nothing else references. Returns the deleted ids, which share one
+    /// channel, or `None` when the message does not exist.
+    pub async fn delete_message(
+        &self,
+        message_id: MessageId,
+    ) -> Result<Option<(GuildId, ChannelId, Vec<MessageId>)>> {
+        let mut tx = self.pool.begin().await?;
+        let mut rows: Vec<(i64, i64, i64)> = sqlx::query_as(
+            "SELECT m.id, m.channel_id, c.guild_id
+             FROM messages m
+             INNER JOIN channels c ON c.id = m.channel_id
+             WHERE m.id = ?",
+        )
+        .bind(message_id.0)
+        .fetch_all(&mut *tx)
+        .await?;
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        let mut released = Vec::new();
+        delete_message_rows(&mut tx, &mut rows, &mut released).await?;
+        tx.commit().await?;
+        self.release_blobs(released).await?;
+        Ok(group_by_channel(rows).pop())
+    }
+
+    /// Deletes an attachment and its thumbnail even while messages still reference it;
+    /// those messages keep their text and lose the attachment.
+    pub async fn delete_file(&self, file_id: &FileId) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let Some(id) = sqlx::query_scalar::<_, i64>("SELECT id FROM files WHERE public_id = ?")
+            .bind(&file_id.0)
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(false);
+        };
+        sqlx::query("UPDATE messages SET attachment_file_id = NULL WHERE attachment_file_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let deleted_files: Vec<DeletedFile> = sqlx::query_as(
+            "DELETE FROM files WHERE id = ?
+             RETURNING blob_key, upload_id, guild_id, uploader_user_id,
+                       COALESCE(size_bytes, length(ciphertext))",
+        )
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut released = Vec::new();
+        release_file_rows(&mut tx, deleted_files, &mut released).await?;
+        tx.commit().await?;
+        self.release_blobs(released).await?;
+        Ok(true)
+    }
+
+    /// Deletes consumed Welcomes other than each recipient's newest, superseded key packages,
+    /// spent device link tokens, orphaned files and abandoned upload sessions older than
+    /// `policy` allows as of `now`, then optionally compacts.
+    pub async fn run_maintenance(
+        &self,
+        policy: MaintenancePolicy,
+        now: DateTime<Utc>,
+    ) -> Result<MaintenanceReport> {
+        let cutoff = |ttl: chrono::Duration| sql_timestamp(now - ttl);
+        let mut report = MaintenanceReport::default();
+        let mut tx = self.pool.begin().await?;
+        // The newest Welcome per recipient stays: recovery re-issues it.
+        report.welcomes = sqlx::query(
+            "DELETE FROM pending_welcomes
+             WHERE consumed_at IS NOT NULL AND consumed_at <= ?
+               AND id NOT IN (SELECT MAX(id) FROM pending_welcomes
+                              GROUP BY guild_id, channel_id, user_id, target_device_id)",
+        )
+        .bind(cutoff(policy.consumed_welcome_ttl))
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        report.key_packages = sqlx::query(
+            "DELETE FROM mls_key_packages
+             WHERE created_at <= ?
+               AND (id NOT IN (SELECT MAX(id) FROM mls_key_packages
+                               GROUP BY guild_id, user_id, device_id)
+                    OR device_id IN (SELECT device_id FROM user_devices WHERE is_revoked = 1))",
+        )
+        .bind(cutoff(policy.key_package_ttl))
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        // Token expiry is stored as an RFC 3339 timestamp; consumption uses CURRENT_TIMESTAMP.
+        let link_cutoff = now - policy.device_link_ttl;
+        let spent_tokens = "SELECT token_id FROM device_link_tokens
+                            WHERE expires_at <= ?1 OR (consumed_at IS NOT NULL AND consumed_at <= ?2)";
+        report.device_link_bundles = sqlx::query(&format!(
+            "DELETE FROM device_link_bundles
+             WHERE token_id IN ({spent_tokens})
+                OR (consumed_at IS NOT NULL AND consumed_at <= ?2)"
+        ))
+        .bind(link_cutoff)
+        .bind(sql_timestamp(link_cutoff))
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        report.device_link_tokens = sqlx::query(&format!(
+            "DELETE FROM device_link_tokens WHERE token_id IN ({spent_tokens})"
+        ))
+        .bind(link_cutoff)
+        .bind(sql_timestamp(link_cutoff))
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        let deleted_files: Vec<DeletedFile> = sqlx::query_as(
+            "DELETE FROM files
+             WHERE created_at <= ? AND thumbnail_of IS NULL
+               AND NOT EXISTS (SELECT 1 FROM messages m WHERE m.attachment_file_id = files.id)
+             RETURNING blob_key, upload_id, guild_id, uploader_user_id,
+                       COALESCE(size_bytes, length(ciphertext))",
+        )
+        .bind(cutoff(policy.orphan_file_ttl))
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut released = Vec::new();
+        report.files = deleted_files.len() as u64
+            + release_file_rows(&mut tx, deleted_files, &mut released).await?;
+        let stale_uploads: Vec<String> =
+            sqlx::query_scalar("DELETE FROM upload_sessions WHERE updated_at <= ? RETURNING id")
+                .bind(cutoff(policy.upload_session_ttl))
+                .fetch_all(&mut *tx)
+                .await?;
+        report.upload_sessions = stale_uploads.len() as u64;
+        delete_upload_chunks(&mut tx, stale_uploads, &mut released).await?;
+        tx.commit().await?;
+        self.release_blobs(released).await?;
+
+        if policy.compact {
+            report.reclaimed_bytes = self.compact().await?;
+        }
+        Ok(report)
+    }
+
+    /// Runs `VACUUM` and returns how many bytes the database file shrank by.
+    pub async fn compact(&self) -> Result<u64> {
+        let before = self.database_size_bytes().await?;
+        sqlx::query("VACUUM").execute(&self.pool).await?;
+        let after = self.database_size_bytes().await?;
+        Ok(before.saturating_sub(after))
+    }
+
+    async fn database_size_bytes(&self) -> Result<u64> {
+        let page_count: i64 = sqlx::query_scalar("PRAGMA page_count")
+            .fetch_one(&self.pool)
+            .await?;
+        let page_size: i64 = sqlx::query_scalar("PRAGMA page_size")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok((page_count * page_size) as u64)
+    }
+
+    pub async fn channel_history_sharing(&self, channel_id: ChannelId) -> Result<bool> {
+        let enabled =
+            sqlx::query_scalar::<_, bool>("SELECT history_sharing FROM channels WHERE id = ?")
+                .bind(channel_id.0)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(enabled.unwrap_or(false))
+    }
+
+    /// Channels of a guild that opted in to sharing history with newly added members.
+    pub async fn list_history_sharing_channels(&self, guild_id: GuildId) -> Result<Vec<ChannelId>> {
+        let rows =
+            sqlx::query("SELECT id FROM channels WHERE guild_id = ? AND history_sharing = 1")
+                .bind(guild_id.0)
+                .fetch_all(&self.pool)
+                .await?;
         Ok(rows
             .into_iter()
-            .map(|r| {
-                let kind = match r.get::<String, _>(2).as_str() {
-                    "voice" => ChannelKind::Voice,
-                    _ => ChannelKind::Text,
-                };
-                (ChannelId(r.get::<i64, _>(0)), r.get::<String, _>(1), kind)
-            })
+            .map(|r| ChannelId(r.get::<i64, _>(0)))
             .collect())
     }
 
@@ -328,6 +1508,17 @@ impl Storage {
         .fetch_all(&self.pool)
         .await?;
 
+        let assignments = sqlx::query(
+            "SELECT mr.user_id, mr.role_id
+             FROM member_roles mr
+             INNER JOIN guild_roles r ON r.id = mr.role_id
+             WHERE mr.guild_id = ?
+             ORDER BY r.position DESC, r.id ASC",
+        )
+        .bind(guild_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+
         Ok(rows
             .into_iter()
             .map(|r| {
@@ -336,33 +1527,275 @@ impl Storage {
                     "mod" => Role::Mod,
                     _ => Role::Member,
                 };
+                let user_id = UserId(r.get::<i64, _>(0));
                 StoredMember {
-                    user_id: UserId(r.get::<i64, _>(0)),
+                    user_id,
                     username: r.get::<String, _>(1),
                     role,
                     muted: r.get::<bool, _>(3),
+                    role_ids: assignments
+                        .iter()
+                        .filter(|a| a.get::<i64, _>(0) == user_id.0)
+                        .map(|a| RoleId(a.get::<i64, _>(1)))
+                        .collect(),
                 }
             })
             .collect())
     }
 
+    pub async fn create_guild_role(
+        &self,
+        guild_id: GuildId,
+        name: &str,
+        color: u32,
+        position: i64,
+        permissions: Permissions,
+    ) -> Result<RoleId> {
+        let rec = sqlx::query(
+            "INSERT INTO guild_roles (guild_id, name, color, position, permissions)
+             VALUES (?, ?, ?, ?, ?) RETURNING id",
+        )
+        .bind(guild_id.0)
+        .bind(name)
+        .bind(i64::from(color))
+        .bind(position)
+        .bind(permissions.0 as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(RoleId(rec.get::<i64, _>(0)))
+    }
+
+    pub async fn update_guild_role(
+        &self,
+        role_id: RoleId,
+        name: &str,
+        color: u32,
+        position: i64,
+        permissions: Permissions,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE guild_roles SET name = ?, color = ?, position = ?, permissions = ? WHERE id = ?",
+        )
+        .bind(name)
+        .bind(i64::from(color))
+        .bind(position)
+        .bind(permissions.0 as i64)
+        .bind(role_id.0)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes a role; its member assignments and channel overwrites go with it.
+    pub async fn delete_guild_role(&self, role_id: RoleId) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM channel_permission_overwrites WHERE target_kind = 'role' AND target_id = ?",
+        )
+        .bind(role_id.0)
+        .execute(&mut *tx)
+        .await?;
+        let result = sqlx::query("DELETE FROM guild_roles WHERE id = ?")
+            .bind(role_id.0)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn guild_role(&self, role_id: RoleId) -> Result<Option<StoredGuildRole>> {
+        let row = sqlx::query(
+            "SELECT id, guild_id, name, color, position, permissions FROM guild_roles WHERE id = ?",
+        )
+        .bind(role_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| stored_guild_role_from_row(&r)))
+    }
+
+    /// Roles of a guild, highest position first.
+    pub async fn list_guild_roles(&self, guild_id: GuildId) -> Result<Vec<StoredGuildRole>> {
+        let rows = sqlx::query(
+            "SELECT id, guild_id, name, color, position, permissions
+             FROM guild_roles
+             WHERE guild_id = ?
+             ORDER BY position DESC, id ASC",
+        )
+        .bind(guild_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(stored_guild_role_from_row).collect())
+    }
+
+    pub async fn assign_member_role(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO member_roles (guild_id, user_id, role_id) VALUES (?, ?, ?)
+             ON CONFLICT(guild_id, user_id, role_id) DO NOTHING",
+        )
+        .bind(guild_id.0)
+        .bind(user_id.0)
+        .bind(role_id.0)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn unassign_member_role(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "DELETE FROM member_roles WHERE guild_id = ? AND user_id = ? AND role_id = ?",
+        )
+        .bind(guild_id.0)
+        .bind(user_id.0)
+        .bind(role_id.0)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Inserts or replaces the overwrite for `overwrite.target` on a channel.
+    pub async fn upsert_channel_overwrite(
+        &self,
+        channel_id: ChannelId,
+        overwrite: &PermissionOverwrite,
+    ) -> Result<()> {
+        let (target_kind, target_id) = overwrite_target_to_row(overwrite.target);
+        sqlx::query(
+            "INSERT INTO channel_permission_overwrites (channel_id, target_kind, target_id, allow_bits, deny_bits)
+             VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT(channel_id, target_kind, target_id)
+             DO UPDATE SET allow_bits = excluded.allow_bits, deny_bits = excluded.deny_bits",
+        )
+        .bind(channel_id.0)
+        .bind(target_kind)
+        .bind(target_id)
+        .bind(overwrite.allow.0 as i64)
+        .bind(overwrite.deny.0 as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_channel_overwrite(
+        &self,
+        channel_id: ChannelId,
+        target: OverwriteTarget,
+    ) -> Result<bool> {
+        let (target_kind, target_id) = overwrite_target_to_row(target);
+        let result = sqlx::query(
+            "DELETE FROM channel_permission_overwrites
+             WHERE channel_id = ? AND target_kind = ? AND target_id = ?",
+        )
+        .bind(channel_id.0)
+        .bind(target_kind)
+        .bind(target_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn list_channel_overwrites(
+        &self,
+        channel_id: ChannelId,
+    ) -> Result<Vec<PermissionOverwrite>> {
+        let rows = sqlx::query(
+            "SELECT channel_id, target_kind, target_id, allow_bits, deny_bits
+             FROM channel_permission_overwrites
+             WHERE channel_id = ?",
+        )
+        .bind(channel_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .iter()
+            .filter_map(channel_overwrite_from_row)
+            .map(|(_, overwrite)| overwrite)
+            .collect())
+    }
+
+    /// Overwrites for every channel of a guild, for filtering channel lists in one query.
+    pub async fn list_guild_channel_overwrites(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<Vec<(ChannelId, PermissionOverwrite)>> {
+        let rows = sqlx::query(
+            "SELECT o.channel_id, o.target_kind, o.target_id, o.allow_bits, o.deny_bits
+             FROM channel_permission_overwrites o
+             INNER JOIN channels c ON c.id = o.channel_id
+             WHERE c.guild_id = ?",
+        )
+        .bind(guild_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().filter_map(channel_overwrite_from_row).collect())
+    }
+
+    /// Custom roles held by a member, highest position first.
+    pub async fn list_member_roles(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+    ) -> Result<Vec<StoredGuildRole>> {
+        let rows = sqlx::query(
+            "SELECT r.id, r.guild_id, r.name, r.color, r.position, r.permissions
+             FROM member_roles mr
+             INNER JOIN guild_roles r ON r.id = mr.role_id
+             WHERE mr.guild_id = ? AND mr.user_id = ?
+             ORDER BY r.position DESC, r.id ASC",
+        )
+        .bind(guild_id.0)
+        .bind(user_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(stored_guild_role_from_row).collect())
+    }
+
     pub async fn insert_message_ciphertext(
         &self,
         channel_id: ChannelId,
         sender_id: UserId,
         ciphertext: &[u8],
         attachment: Option<&StoredAttachment>,
+    ) -> Result<MessageId> {
+        self.insert_thread_message_ciphertext(
+            channel_id, None, sender_id, ciphertext, attachment, None,
+        )
+        .await
+    }
+
+    /// Stores a message posted in `thread_id`, or in the channel itself when `None`. Messages
+    /// with `expires_at` are hidden once it passes and removed by `purge_expired`.
+    pub async fn insert_thread_message_ciphertext(
+        &self,
+        channel_id: ChannelId,
+        thread_id: Option<ThreadId>,
+        sender_id: UserId,
+        ciphertext: &[u8],
+        attachment: Option<&StoredAttachment>,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<MessageId> {
         let rec = sqlx::query(
-            "INSERT INTO messages (channel_id, sender_user_id, ciphertext, attachment_file_id, attachment_filename, attachment_size_bytes, attachment_mime_type) VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING id",
+            "INSERT INTO messages (channel_id, thread_id, sender_user_id, ciphertext, attachment_file_id, attachment_filename, attachment_size_bytes, attachment_mime_type, expires_at)
+             VALUES (?, ?, ?, ?, (SELECT id FROM files WHERE public_id = ?), ?, ?, ?, ?) RETURNING id",
         )
         .bind(channel_id.0)
+        .bind(thread_id.map(|thread_id| thread_id.0))
         .bind(sender_id.0)
         .bind(ciphertext)
-        .bind(attachment.map(|a| a.file_id.0))
+        .bind(attachment.map(|a| a.file_id.0.as_str()))
         .bind(attachment.map(|a| a.filename.as_str()))
         .bind(attachment.map(|a| i64::try_from(a.size_bytes).unwrap_or(i64::MAX)))
         .bind(attachment.and_then(|a| a.mime_type.as_deref()))
+        .bind(expires_at.map(sql_timestamp))
         .fetch_one(&self.pool)
         .await?;
         Ok(MessageId(rec.get::<i64, _>(0)))
@@ -376,58 +1809,141 @@ impl Storage {
         Ok(row.map(|r| GuildId(r.get::<i64, _>(0))))
     }
 
+    /// Messages posted directly in the channel, oldest first. Thread messages are left out.
     pub async fn list_channel_messages(
         &self,
         channel_id: ChannelId,
         limit: u32,
         before: Option<i64>,
     ) -> Result<Vec<StoredMessage>> {
-        let mut rows = if let Some(before_id) = before {
-            sqlx::query(
-                "SELECT id, channel_id, sender_user_id, ciphertext, created_at, attachment_file_id, attachment_filename, attachment_size_bytes, attachment_mime_type
-                 FROM messages
-                 WHERE channel_id = ? AND id < ?
-                 ORDER BY id DESC
-                 LIMIT ?",
-            )
-            .bind(channel_id.0)
-            .bind(before_id)
-            .bind(limit)
-            .fetch_all(&self.pool)
-            .await?
-        } else {
-            sqlx::query(
-                "SELECT id, channel_id, sender_user_id, ciphertext, created_at, attachment_file_id, attachment_filename, attachment_size_bytes, attachment_mime_type
-                 FROM messages
-                 WHERE channel_id = ?
-                 ORDER BY id DESC
-                 LIMIT ?",
-            )
-            .bind(channel_id.0)
-            .bind(limit)
-            .fetch_all(&self.pool)
-            .await?
-        };
+        let mut rows = sqlx::query(
+            "SELECT id, channel_id, sender_user_id, ciphertext, created_at,
+                    (SELECT public_id FROM files WHERE files.id = messages.attachment_file_id),
+                    attachment_filename, attachment_size_bytes, attachment_mime_type, thread_id, expires_at
+             FROM messages
+             WHERE channel_id = ? AND thread_id IS NULL AND (? IS NULL OR id < ?)
+               AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+             ORDER BY id DESC
+             LIMIT ?",
+        )
+        .bind(channel_id.0)
+        .bind(before)
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.reverse();
+        Ok(rows.iter().map(stored_message_from_row).collect())
+    }
 
+    /// Messages posted in the thread, oldest first.
+    pub async fn list_thread_messages(
+        &self,
+        thread_id: ThreadId,
+        limit: u32,
+        before: Option<i64>,
+    ) -> Result<Vec<StoredMessage>> {
+        let mut rows = sqlx::query(
+            "SELECT id, channel_id, sender_user_id, ciphertext, created_at,
+                    (SELECT public_id FROM files WHERE files.id = messages.attachment_file_id),
+                    attachment_filename, attachment_size_bytes, attachment_mime_type, thread_id, expires_at
+             FROM messages
+             WHERE thread_id = ? AND (? IS NULL OR id < ?)
+               AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+             ORDER BY id DESC
+             LIMIT ?",
+        )
+        .bind(thread_id.0)
+        .bind(before)
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
         rows.reverse();
-        Ok(rows
-            .into_iter()
-            .map(|r| StoredMessage {
-                message_id: MessageId(r.get::<i64, _>(0)),
-                channel_id: ChannelId(r.get::<i64, _>(1)),
-                sender_id: UserId(r.get::<i64, _>(2)),
-                ciphertext: r.get::<Vec<u8>, _>(3),
-                attachment: r.get::<Option<i64>, _>(5).map(|file_id| StoredAttachment {
-                    file_id: FileId(file_id),
-                    filename: r
-                        .get::<Option<String>, _>(6)
-                        .unwrap_or_else(|| "attachment.bin".to_string()),
-                    size_bytes: r.get::<Option<i64>, _>(7).unwrap_or_default() as u64,
-                    mime_type: r.get::<Option<String>, _>(8),
-                }),
-                created_at: r.get::<DateTime<Utc>, _>(4),
-            })
-            .collect())
+        Ok(rows.iter().map(stored_message_from_row).collect())
+    }
+
+    /// The channel and thread a message was posted in.
+    pub async fn message_location(
+        &self,
+        message_id: MessageId,
+    ) -> Result<Option<(ChannelId, Option<ThreadId>)>> {
+        let row = sqlx::query("SELECT channel_id, thread_id FROM messages WHERE id = ?")
+            .bind(message_id.0)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| {
+            (
+                ChannelId(r.get::<i64, _>(0)),
+                r.get::<Option<i64>, _>(1).map(ThreadId),
+            )
+        }))
+    }
+
+    /// Creates a thread anchored to `parent_message_id`. Fails if the message already has one.
+    pub async fn create_thread(
+        &self,
+        channel_id: ChannelId,
+        parent_message_id: MessageId,
+        creator_id: UserId,
+        name: &str,
+    ) -> Result<ThreadId> {
+        let id: i64 = sqlx::query_scalar(
+            "INSERT INTO threads (channel_id, parent_message_id, creator_user_id, name)
+             VALUES (?, ?, ?, ?)
+             RETURNING id",
+        )
+        .bind(channel_id.0)
+        .bind(parent_message_id.0)
+        .bind(creator_id.0)
+        .bind(name)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(ThreadId(id))
+    }
+
+    pub async fn thread(&self, thread_id: ThreadId) -> Result<Option<ThreadSummary>> {
+        let row = sqlx::query(&format!("{THREAD_SELECT} WHERE t.id = ?"))
+            .bind(thread_id.0)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.as_ref().map(thread_from_row))
+    }
+
+    pub async fn thread_for_message(
+        &self,
+        parent_message_id: MessageId,
+    ) -> Result<Option<ThreadSummary>> {
+        let row = sqlx::query(&format!("{THREAD_SELECT} WHERE t.parent_message_id = ?"))
+            .bind(parent_message_id.0)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.as_ref().map(thread_from_row))
+    }
+
+    /// Threads of a channel, newest first. Archived threads are only included on request.
+    pub async fn list_threads(
+        &self,
+        channel_id: ChannelId,
+        include_archived: bool,
+    ) -> Result<Vec<ThreadSummary>> {
+        let rows = sqlx::query(&format!(
+            "{THREAD_SELECT} WHERE t.channel_id = ? AND (? OR t.archived = 0) ORDER BY t.id DESC"
+        ))
+        .bind(channel_id.0)
+        .bind(include_archived)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(thread_from_row).collect())
+    }
+
+    pub async fn set_thread_archived(&self, thread_id: ThreadId, archived: bool) -> Result<bool> {
+        let result = sqlx::query("UPDATE threads SET archived = ? WHERE id = ?")
+            .bind(archived)
+            .bind(thread_id.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
     }
 
     pub async fn store_file_ciphertext(
@@ -440,63 +1956,512 @@ impl Storage {
         filename: Option<&str>,
     ) -> Result<FileId> {
         let size_bytes = i64::try_from(ciphertext.len()).unwrap_or(i64::MAX);
-        let rec = sqlx::query(
-            "INSERT INTO files (uploader_user_id, guild_id, channel_id, ciphertext, mime_type, filename, size_bytes) VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING id",
+        let (inline, blob_key) = match &self.blobs {
+            Some(blobs) => (&[][..], Some(blobs.put(ciphertext).await?)),
+            None => (ciphertext, None),
+        };
+        let mut tx = self.pool.begin().await?;
+        if let Err(error) = check_storage_quotas(
+            &mut tx,
+            self.quotas,
+            uploader_id,
+            guild_id,
+            size_bytes as u64,
+        )
+        .await
+        {
+            tx.rollback().await?;
+            self.release_blobs(blob_key).await?;
+            return Err(error);
+        }
+        let file_id = new_file_id();
+        sqlx::query(
+            "INSERT INTO files (public_id, uploader_user_id, guild_id, channel_id, ciphertext, blob_key, mime_type, filename, size_bytes) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
         )
-         .bind(uploader_id.0)
+        .bind(&file_id.0)
+        .bind(uploader_id.0)
         .bind(guild_id.0)
         .bind(channel_id.0)
-        .bind(ciphertext)
+        .bind(inline)
+        .bind(&blob_key)
         .bind(mime)
         .bind(filename)
         .bind(size_bytes)
-        .fetch_one(&self.pool)
+        .execute(&mut *tx)
         .await?;
-        Ok(FileId(rec.get::<i64, _>(0)))
+        adjust_storage_usage(&mut tx, guild_id.0, uploader_id.0, size_bytes).await?;
+        tx.commit().await?;
+        Ok(file_id)
     }
 
-    pub async fn load_file(&self, file_id: FileId) -> Result<Option<StoredFile>> {
+    pub async fn load_file(&self, file_id: &FileId) -> Result<Option<StoredFile>> {
         let row = sqlx::query(
-            "SELECT id, guild_id, channel_id, ciphertext, mime_type, filename, size_bytes FROM files WHERE id = ?",
+            "SELECT public_id, guild_id, channel_id, ciphertext, mime_type, filename, size_bytes, blob_key, upload_id FROM files WHERE public_id = ?",
         )
-            .bind(file_id.0)
+            .bind(&file_id.0)
             .fetch_optional(&self.pool)
             .await?;
-        Ok(row.map(|r| StoredFile {
-            file_id: FileId(r.get::<i64, _>(0)),
+        let Some(r) = row else {
+            return Ok(None);
+        };
+        let size_bytes = r.get::<Option<i64>, _>(6).unwrap_or_default() as u64;
+        let ciphertext = match (r.get::<Option<String>, _>(7), r.get::<Option<String>, _>(8)) {
+            (_, Some(upload_id)) => self.read_upload_range(&upload_id, 0, size_bytes).await?,
+            (Some(key), None) => self
+                .blob_store_for(&key)?
+                .get(&key)
+                .await?
+                .ok_or_else(|| anyhow!("blob {key} for file {} is missing", file_id.0))?,
+            (None, None) => r.get::<Vec<u8>, _>(3),
+        };
+        Ok(Some(StoredFile {
+            file_id: FileId(r.get::<String, _>(0)),
+            guild_id: GuildId(r.get::<i64, _>(1)),
+            channel_id: ChannelId(r.get::<i64, _>(2)),
+            ciphertext,
+            mime_type: r.get::<Option<String>, _>(4),
+            filename: r.get::<Option<String>, _>(5),
+            size_bytes,
+        }))
+    }
+
+    pub async fn load_file_metadata(&self, file_id: &FileId) -> Result<Option<FileMetadata>> {
+        let row = sqlx::query(
+            "SELECT public_id, guild_id, channel_id, mime_type, filename, size_bytes, uploader_user_id,
+                    (SELECT p.public_id FROM files p WHERE p.id = files.thumbnail_of)
+             FROM files WHERE public_id = ?",
+        )
+        .bind(&file_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| FileMetadata {
+            file_id: FileId(r.get::<String, _>(0)),
+            uploader_id: UserId(r.get::<i64, _>(6)),
             guild_id: GuildId(r.get::<i64, _>(1)),
             channel_id: ChannelId(r.get::<i64, _>(2)),
-            ciphertext: r.get::<Vec<u8>, _>(3),
+            mime_type: r.get::<Option<String>, _>(3),
+            filename: r.get::<Option<String>, _>(4),
+            size_bytes: r.get::<Option<i64>, _>(5).unwrap_or_default() as u64,
+            thumbnail_of: r.get::<Option<String>, _>(7).map(FileId),
+        }))
+    }
+
+    /// Links `thumbnail` to the attachment it previews, so it is kept and deleted along with
+    /// it. Returns `false` if either file is missing, `thumbnail` is already linked or
+    /// attached to a message, `parent` is itself a thumbnail, or `parent` already has one.
+    pub async fn link_thumbnail(&self, parent: &FileId, thumbnail: &FileId) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE files SET thumbnail_of = p.id
+             FROM files p
+             WHERE p.public_id = ?1 AND p.thumbnail_of IS NULL AND p.id <> files.id
+               AND files.public_id = ?2 AND files.thumbnail_of IS NULL
+               AND NOT EXISTS (SELECT 1 FROM files t WHERE t.thumbnail_of = p.id)
+               AND NOT EXISTS (SELECT 1 FROM files t WHERE t.thumbnail_of = files.id)
+               AND NOT EXISTS (SELECT 1 FROM messages m WHERE m.attachment_file_id = files.id)",
+        )
+        .bind(&parent.0)
+        .bind(&thumbnail.0)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Reads ciphertext bytes `start..end` of a file, wherever they are stored. `end` is
+    /// clamped to the file size.
+    pub async fn read_file_range(&self, file_id: &FileId, start: u64, end: u64) -> Result<Vec<u8>> {
+        let (size_bytes, blob_key, upload_id): (Option<i64>, Option<String>, Option<String>) =
+            sqlx::query_as("SELECT size_bytes, blob_key, upload_id FROM files WHERE public_id = ?")
+                .bind(&file_id.0)
+                .fetch_optional(&self.pool)
+                .await?
+                .ok_or_else(|| anyhow!("file {} not found", file_id.0))?;
+        let end = end.min(size_bytes.unwrap_or_default() as u64);
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        match (blob_key, upload_id) {
+            (_, Some(upload_id)) => self.read_upload_range(&upload_id, start, end).await,
+            (Some(key), None) => self
+                .blob_store_for(&key)?
+                .get_range(&key, start, end - start)
+                .await?
+                .ok_or_else(|| anyhow!("blob {key} for file {} is missing", file_id.0)),
+            (None, None) => Ok(sqlx::query_scalar(
+                "SELECT substr(ciphertext, ? + 1, ?) FROM files WHERE public_id = ?",
+            )
+            .bind(start as i64)
+            .bind((end - start) as i64)
+            .bind(&file_id.0)
+            .fetch_one(&self.pool)
+            .await?),
+        }
+    }
+
+    /// Opens a resumable upload that will accept `total_bytes` of ciphertext. The declared
+    /// size counts against the quotas until the session completes or expires.
+    pub async fn create_upload_session(
+        &self,
+        uploader_id: UserId,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        total_bytes: u64,
+        mime: Option<&str>,
+        filename: Option<&str>,
+    ) -> Result<UploadSession> {
+        let upload_id = Uuid::new_v4().to_string();
+        let mut tx = self.pool.begin().await?;
+        check_storage_quotas(&mut tx, self.quotas, uploader_id, guild_id, total_bytes).await?;
+        sqlx::query(
+            "INSERT INTO upload_sessions (id, uploader_user_id, guild_id, channel_id, mime_type, filename, total_bytes)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&upload_id)
+        .bind(uploader_id.0)
+        .bind(guild_id.0)
+        .bind(channel_id.0)
+        .bind(mime)
+        .bind(filename)
+        .bind(i64::try_from(total_bytes)?)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(UploadSession {
+            upload_id,
+            uploader_id,
+            guild_id,
+            channel_id,
+            mime_type: mime.map(str::to_string),
+            filename: filename.map(str::to_string),
+            total_bytes,
+            received_bytes: 0,
+        })
+    }
+
+    pub async fn load_upload_session(&self, upload_id: &str) -> Result<Option<UploadSession>> {
+        let row = sqlx::query(
+            "SELECT id, uploader_user_id, guild_id, channel_id, mime_type, filename, total_bytes, received_bytes
+             FROM upload_sessions WHERE id = ?",
+        )
+        .bind(upload_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| UploadSession {
+            upload_id: r.get::<String, _>(0),
+            uploader_id: UserId(r.get::<i64, _>(1)),
+            guild_id: GuildId(r.get::<i64, _>(2)),
+            channel_id: ChannelId(r.get::<i64, _>(3)),
             mime_type: r.get::<Option<String>, _>(4),
             filename: r.get::<Option<String>, _>(5),
-            size_bytes: r.get::<Option<i64>, _>(6).unwrap_or_default() as u64,
+            total_bytes: r.get::<i64, _>(6) as u64,
+            received_bytes: r.get::<i64, _>(7) as u64,
         }))
     }
 
+    /// Appends `bytes` at `offset`, which must equal the bytes received so far. Returns the
+    /// new received count, or `None` if the offset is stale, the chunk would overrun the
+    /// declared size, or the session is gone.
+    pub async fn append_upload_chunk(
+        &self,
+        upload_id: &str,
+        offset: u64,
+        bytes: &[u8],
+    ) -> Result<Option<u64>> {
+        let size_bytes = i64::try_from(bytes.len())?;
+        let (inline, blob_key) = match &self.blobs {
+            Some(blobs) => (&[][..], Some(blobs.put(bytes).await?)),
+            None => (bytes, None),
+        };
+        let mut tx = self.pool.begin().await?;
+        let received: Option<i64> = sqlx::query_scalar(
+            "UPDATE upload_sessions
+             SET received_bytes = received_bytes + ?1, updated_at = CURRENT_TIMESTAMP
+             WHERE id = ?2 AND received_bytes = ?3 AND received_bytes + ?1 <= total_bytes
+             RETURNING received_bytes",
+        )
+        .bind(size_bytes)
+        .bind(upload_id)
+        .bind(i64::try_from(offset)?)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(received) = received else {
+            tx.rollback().await?;
+            self.release_blobs(blob_key).await?;
+            return Ok(None);
+        };
+        sqlx::query(
+            "INSERT INTO file_chunks (upload_id, byte_offset, size_bytes, ciphertext, blob_key)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(upload_id)
+        .bind(i64::try_from(offset)?)
+        .bind(size_bytes)
+        .bind(inline)
+        .bind(blob_key)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Some(received as u64))
+    }
+
+    /// Hex SHA-256 of everything an upload has received, read one chunk at a time.
+    pub async fn upload_sha256(&self, upload_id: &str) -> Result<String> {
+        let chunks: Vec<(i64, i64)> = sqlx::query_as(
+            "SELECT byte_offset, size_bytes FROM file_chunks WHERE upload_id = ? ORDER BY byte_offset",
+        )
+        .bind(upload_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut hasher = Sha256::new();
+        for (offset, size_bytes) in chunks {
+            let (start, end) = (offset as u64, (offset + size_bytes) as u64);
+            hasher.update(self.read_upload_range(upload_id, start, end).await?);
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// Turns a fully received upload into a file that owns its chunks. Returns `None` if
+    /// the session is gone or still missing bytes.
+    pub async fn complete_upload(&self, upload_id: &str) -> Result<Option<FileId>> {
+        let mut tx = self.pool.begin().await?;
+        let Some(row) = sqlx::query(
+            "DELETE FROM upload_sessions WHERE id = ? AND received_bytes = total_bytes
+             RETURNING uploader_user_id, guild_id, channel_id, mime_type, filename, total_bytes",
+        )
+        .bind(upload_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let file_id = new_file_id();
+        sqlx::query(
+            "INSERT INTO files (public_id, uploader_user_id, guild_id, channel_id, ciphertext, mime_type, filename, size_bytes, upload_id)
+             VALUES (?, ?, ?, ?, x'', ?, ?, ?, ?)",
+        )
+        .bind(&file_id.0)
+        .bind(row.get::<i64, _>(0))
+        .bind(row.get::<i64, _>(1))
+        .bind(row.get::<i64, _>(2))
+        .bind(row.get::<Option<String>, _>(3))
+        .bind(row.get::<Option<String>, _>(4))
+        .bind(row.get::<i64, _>(5))
+        .bind(upload_id)
+        .execute(&mut *tx)
+        .await?;
+        adjust_storage_usage(
+            &mut tx,
+            row.get::<i64, _>(1),
+            row.get::<i64, _>(0),
+            row.get::<i64, _>(5),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(Some(file_id))
+    }
+
+    pub async fn guild_storage_usage(&self, guild_id: GuildId) -> Result<StorageUsage> {
+        let mut conn = self.pool.acquire().await?;
+        storage_usage(&mut conn, QuotaScope::Guild, guild_id.0).await
+    }
+
+    pub async fn user_storage_usage(&self, user_id: UserId) -> Result<StorageUsage> {
+        let mut conn = self.pool.acquire().await?;
+        storage_usage(&mut conn, QuotaScope::User, user_id.0).await
+    }
+
+    /// Reads bytes `start..end` of an upload's chunks, which must cover the whole range.
+    async fn read_upload_range(&self, upload_id: &str, start: u64, end: u64) -> Result<Vec<u8>> {
+        let chunks: Vec<(i64, i64, Option<String>, Vec<u8>)> = sqlx::query_as(
+            "SELECT byte_offset, size_bytes, blob_key,
+                    substr(ciphertext, MAX(?2 - byte_offset, 0) + 1,
+                           MIN(byte_offset + size_bytes, ?3) - MAX(byte_offset, ?2))
+             FROM file_chunks
+             WHERE upload_id = ?1 AND byte_offset < ?3 AND byte_offset + size_bytes > ?2
+             ORDER BY byte_offset",
+        )
+        .bind(upload_id)
+        .bind(i64::try_from(start)?)
+        .bind(i64::try_from(end)?)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut bytes = Vec::with_capacity(usize::try_from(end - start)?);
+        for (offset, size_bytes, blob_key, inline) in chunks {
+            let Some(key) = blob_key else {
+                bytes.extend_from_slice(&inline);
+                continue;
+            };
+            let (offset, size_bytes) = (offset as u64, size_bytes as u64);
+            let local_start = start.saturating_sub(offset);
+            let local_end = end.min(offset + size_bytes) - offset;
+            let part = self
+                .blob_store_for(&key)?
+                .get_range(&key, local_start, local_end - local_start)
+                .await?
+                .ok_or_else(|| anyhow!("blob {key} for upload {upload_id} is missing"))?;
+            bytes.extend_from_slice(&part);
+        }
+        if bytes.len() as u64 != end - start {
+            return Err(anyhow!(
+                "upload {upload_id} is missing bytes in {start}..{end}"
+            ));
+        }
+        Ok(bytes)
+    }
+
+    fn blob_store_for(&self, key: &str) -> Result<&Arc<dyn BlobStore>> {
+        self.blobs
+            .as_ref()
+            .ok_or_else(|| anyhow!("blob {key} needs a blob store but none is configured"))
+    }
+
+    /// Moves up to `batch_size` inline attachments or upload chunks into the configured blob
+    /// store per round until none are left, returning how many moved. Run
+    /// [`Storage::compact`] afterwards to give the freed space back to the filesystem.
+    pub async fn migrate_files_to_blob_store(&self, batch_size: u32) -> Result<u64> {
+        let blobs = self
+            .blobs
+            .as_ref()
+            .ok_or_else(|| anyhow!("no blob store configured to migrate files into"))?;
+        let mut moved = 0;
+        loop {
+            let rows: Vec<(i64, Vec<u8>)> = sqlx::query_as(
+                "SELECT id, ciphertext FROM files
+                 WHERE blob_key IS NULL AND upload_id IS NULL ORDER BY id LIMIT ?",
+            )
+            .bind(i64::from(batch_size.max(1)))
+            .fetch_all(&self.pool)
+            .await?;
+            if rows.is_empty() {
+                break;
+            }
+            for (id, ciphertext) in rows {
+                let key = blobs.put(&ciphertext).await?;
+                sqlx::query(
+                    "UPDATE files SET blob_key = ?, ciphertext = x'' WHERE id = ? AND blob_key IS NULL",
+                )
+                .bind(key)
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+                moved += 1;
+            }
+        }
+        loop {
+            let rows: Vec<(String, i64, Vec<u8>)> = sqlx::query_as(
+                "SELECT upload_id, byte_offset, ciphertext FROM file_chunks
+                 WHERE blob_key IS NULL ORDER BY upload_id, byte_offset LIMIT ?",
+            )
+            .bind(i64::from(batch_size.max(1)))
+            .fetch_all(&self.pool)
+            .await?;
+            if rows.is_empty() {
+                return Ok(moved);
+            }
+            for (upload_id, offset, ciphertext) in rows {
+                let key = blobs.put(&ciphertext).await?;
+                sqlx::query(
+                    "UPDATE file_chunks SET blob_key = ?, ciphertext = x''
+                     WHERE upload_id = ? AND byte_offset = ? AND blob_key IS NULL",
+                )
+                .bind(key)
+                .bind(upload_id)
+                .bind(offset)
+                .execute(&self.pool)
+                .await?;
+                moved += 1;
+            }
+        }
+    }
+
+    /// Deletes blobs no file or chunk row references any more. Without a blob store the keys
+    /// are ignored.
+    async fn release_blobs(&self, keys: impl IntoIterator<Item = String>) -> Result<()> {
+        let Some(blobs) = &self.blobs else {
+            return Ok(());
+        };
+        for key in keys.into_iter().collect::<BTreeSet<_>>() {
+            let referenced: bool = sqlx::query_scalar(
+                "SELECT EXISTS(SELECT 1 FROM files WHERE blob_key = ?1)
+                     OR EXISTS(SELECT 1 FROM file_chunks WHERE blob_key = ?1)",
+            )
+            .bind(&key)
+            .fetch_one(&self.pool)
+            .await?;
+            if !referenced {
+                blobs
+                    .delete(&key)
+                    .await
+                    .with_context(|| format!("failed to delete released blob {key}"))?;
+            }
+        }
+        Ok(())
+    }
+
     pub async fn register_device(
         &self,
         user_id: UserId,
         device_name: &str,
         device_public_identity: &str,
     ) -> Result<StoredDeviceSummary> {
+        let mut tx = self.pool.begin().await?;
         let row = sqlx::query(
             "INSERT INTO user_devices (user_id, device_name, device_public_identity)
              VALUES (?, ?, ?)
              RETURNING device_id, user_id, device_name, device_public_identity, is_revoked",
         )
         .bind(user_id.0)
-        .bind(device_name)
-        .bind(device_public_identity)
-        .fetch_one(&self.pool)
+        .bind(device_name)
+        .bind(device_public_identity)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let device = StoredDeviceSummary {
+            device_id: DeviceId(row.get::<i64, _>(0)),
+            user_id: UserId(row.get::<i64, _>(1)),
+            device_name: row.get::<String, _>(2),
+            device_public_identity: row.get::<String, _>(3),
+            is_revoked: row.get::<bool, _>(4),
+        };
+        append_key_transparency_leaf(
+            &mut tx,
+            device.user_id,
+            device.device_id,
+            KeyTransparencyAction::Register,
+            &device.device_public_identity,
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(device)
+    }
+
+    /// Marks a device revoked and records the revocation in the key transparency log.
+    /// Returns `false` when the device does not exist or was already revoked.
+    pub async fn revoke_device(&self, user_id: UserId, device_id: DeviceId) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let row = sqlx::query(
+            "UPDATE user_devices SET is_revoked = 1
+             WHERE user_id = ? AND device_id = ? AND is_revoked = 0
+             RETURNING device_public_identity",
+        )
+        .bind(user_id.0)
+        .bind(device_id.0)
+        .fetch_optional(&mut *tx)
         .await?;
-
-        Ok(StoredDeviceSummary {
-            device_id: DeviceId(row.get::<i64, _>(0)),
-            user_id: UserId(row.get::<i64, _>(1)),
-            device_name: row.get::<String, _>(2),
-            device_public_identity: row.get::<String, _>(3),
-            is_revoked: row.get::<bool, _>(4),
-        })
+        let Some(row) = row else {
+            tx.commit().await?;
+            return Ok(false);
+        };
+        let device_public_identity = row.get::<String, _>(0);
+        append_key_transparency_leaf(
+            &mut tx,
+            user_id,
+            device_id,
+            KeyTransparencyAction::Revoke,
+            &device_public_identity,
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(true)
     }
 
     pub async fn get_device(
@@ -689,6 +2654,156 @@ impl Storage {
         Ok(updated > 0)
     }
 
+    /// Logs a `Register` entry for every device that predates the key transparency log.
+    pub async fn backfill_key_transparency_log(&self) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let rows = sqlx::query(
+            "SELECT device_id, user_id, device_public_identity, is_revoked
+             FROM user_devices
+             WHERE device_id NOT IN (SELECT device_id FROM key_transparency_log)
+             ORDER BY device_id ASC",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut appended = 0;
+        for row in rows {
+            let device_id = DeviceId(row.get::<i64, _>(0));
+            let user_id = UserId(row.get::<i64, _>(1));
+            let device_public_identity = row.get::<String, _>(2);
+            append_key_transparency_leaf(
+                &mut tx,
+                user_id,
+                device_id,
+                KeyTransparencyAction::Register,
+                &device_public_identity,
+            )
+            .await?;
+            appended += 1;
+            if row.get::<bool, _>(3) {
+                append_key_transparency_leaf(
+                    &mut tx,
+                    user_id,
+                    device_id,
+                    KeyTransparencyAction::Revoke,
+                    &device_public_identity,
+                )
+                .await?;
+                appended += 1;
+            }
+        }
+        tx.commit().await?;
+        Ok(appended)
+    }
+
+    pub async fn key_transparency_tree_size(&self) -> Result<u64> {
+        let size: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM key_transparency_log")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(size as u64)
+    }
+
+    /// Leaf hashes of the first `tree_size` log entries, in log order.
+    pub async fn list_key_transparency_leaf_hashes(&self, tree_size: u64) -> Result<Vec<TreeHash>> {
+        let rows = sqlx::query(
+            "SELECT leaf_hash FROM key_transparency_log
+             WHERE leaf_index < ?
+             ORDER BY leaf_index ASC",
+        )
+        .bind(tree_size as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let hash = row.get::<Vec<u8>, _>(0);
+                hash.as_slice()
+                    .try_into()
+                    .map_err(|_| anyhow!("corrupt key transparency leaf hash"))
+            })
+            .collect()
+    }
+
+    pub async fn load_key_transparency_leaf(
+        &self,
+        leaf_index: u64,
+    ) -> Result<Option<KeyTransparencyLeaf>> {
+        let row = sqlx::query(
+            "SELECT leaf_index, user_id, device_id, action, device_public_identity, logged_at_ms
+             FROM key_transparency_log
+             WHERE leaf_index = ?",
+        )
+        .bind(leaf_index as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(key_transparency_leaf_from_row).transpose()
+    }
+
+    pub async fn latest_key_transparency_leaf_for_device(
+        &self,
+        user_id: UserId,
+        device_id: DeviceId,
+    ) -> Result<Option<KeyTransparencyLeaf>> {
+        let row = sqlx::query(
+            "SELECT leaf_index, user_id, device_id, action, device_public_identity, logged_at_ms
+             FROM key_transparency_log
+             WHERE user_id = ? AND device_id = ?
+             ORDER BY leaf_index DESC
+             LIMIT 1",
+        )
+        .bind(user_id.0)
+        .bind(device_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(key_transparency_leaf_from_row).transpose()
+    }
+
+    /// Stores the log signing key unless one already exists, and returns the persisted key.
+    pub async fn load_or_store_key_transparency_signing_key(
+        &self,
+        candidate_secret_key: &[u8],
+    ) -> Result<Vec<u8>> {
+        sqlx::query(
+            "INSERT INTO key_transparency_signing_key (id, secret_key)
+             VALUES (1, ?)
+             ON CONFLICT(id) DO NOTHING",
+        )
+        .bind(candidate_secret_key)
+        .execute(&self.pool)
+        .await?;
+        let secret_key = sqlx::query_scalar::<_, Vec<u8>>(
+            "SELECT secret_key FROM key_transparency_signing_key WHERE id = 1",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(secret_key)
+    }
+
+    pub async fn store_mls_backup(&self, user_id: UserId, backup_blob: &[u8]) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO mls_state_backups (user_id, backup_blob, size_bytes, updated_at)
+             VALUES (?, ?, ?, CURRENT_TIMESTAMP)
+             ON CONFLICT(user_id) DO UPDATE SET
+               backup_blob = excluded.backup_blob,
+               size_bytes = excluded.size_bytes,
+               updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(user_id.0)
+        .bind(backup_blob)
+        .bind(backup_blob.len() as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn load_mls_backup(&self, user_id: UserId) -> Result<Option<Vec<u8>>> {
+        let row = sqlx::query("SELECT backup_blob FROM mls_state_backups WHERE user_id = ?")
+            .bind(user_id.0)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| r.get::<Vec<u8>, _>(0)))
+    }
+
     pub async fn insert_key_package(
         &self,
         guild_id: GuildId,
@@ -819,6 +2934,65 @@ impl Storage {
         }))
     }
 
+    pub async fn insert_history_bundle(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        sender_id: UserId,
+        target_user_id: UserId,
+        target_device_id: Option<DeviceId>,
+        bundle_bytes: &[u8],
+    ) -> Result<i64> {
+        let rec = sqlx::query(
+            "INSERT INTO mls_history_bundles (guild_id, channel_id, sender_user_id, target_user_id, target_device_id, bundle_bytes)
+             VALUES (?, ?, ?, ?, ?, ?)
+             RETURNING id",
+        )
+        .bind(guild_id.0)
+        .bind(channel_id.0)
+        .bind(sender_id.0)
+        .bind(target_user_id.0)
+        .bind(target_device_id.map(|id| id.0))
+        .bind(bundle_bytes)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(rec.get::<i64, _>(0))
+    }
+
+    /// History bundles addressed to a user, newest first. Bundles without a target device are
+    /// returned for every device of that user.
+    pub async fn list_history_bundles(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        target_user_id: UserId,
+        target_device_id: Option<DeviceId>,
+    ) -> Result<Vec<StoredHistoryBundle>> {
+        let rows = sqlx::query(
+            "SELECT sender_user_id, target_device_id, bundle_bytes, created_at
+             FROM mls_history_bundles
+             WHERE guild_id = ? AND channel_id = ? AND target_user_id = ?
+               AND (target_device_id IS NULL OR ? IS NULL OR target_device_id = ?)
+             ORDER BY id DESC",
+        )
+        .bind(guild_id.0)
+        .bind(channel_id.0)
+        .bind(target_user_id.0)
+        .bind(target_device_id.map(|id| id.0))
+        .bind(target_device_id.map(|id| id.0))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| StoredHistoryBundle {
+                sender_id: UserId(r.get::<i64, _>(0)),
+                target_device_id: r.get::<Option<i64>, _>(1).map(DeviceId),
+                bundle_bytes: r.get::<Vec<u8>, _>(2),
+                created_at: r.get::<DateTime<Utc>, _>(3),
+            })
+            .collect())
+    }
+
     pub async fn load_latest_welcome_any_state(
         &self,
         guild_id: GuildId,
@@ -903,34 +3077,339 @@ impl Storage {
         guild_id: GuildId,
         channel_id: ChannelId,
     ) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM mls_group_provider_entries WHERE user_id = ? AND device_id = ? AND guild_id = ? AND channel_id = ?",
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .bind(guild_id.0)
+        .bind(channel_id.0)
+        .execute(&mut *tx)
+        .await?;
         let result = sqlx::query(
             "DELETE FROM mls_group_states WHERE user_id = ? AND device_id = ? AND guild_id = ? AND channel_id = ?",
         )
-        .bind(user_id)
-        .bind(device_id)
-        .bind(guild_id.0)
-        .bind(channel_id.0)
-        .execute(&self.pool)
+        .bind(user_id)
+        .bind(device_id)
+        .bind(guild_id.0)
+        .bind(channel_id.0)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes all persisted MLS group snapshots for a user/device pair while keeping
+    /// identity keys intact. Useful when the server database has been reset in local dev.
+    pub async fn clear_all_mls_group_states_for_device(
+        &self,
+        user_id: i64,
+        device_id: &str,
+    ) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM mls_group_provider_entries WHERE user_id = ? AND device_id = ?")
+            .bind(user_id)
+            .bind(device_id)
+            .execute(&mut *tx)
+            .await?;
+        let result =
+            sqlx::query("DELETE FROM mls_group_states WHERE user_id = ? AND device_id = ?")
+                .bind(user_id)
+                .bind(device_id)
+                .execute(&mut *tx)
+                .await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Replaces a device's identity and every group snapshot in one transaction, so a
+    /// failed restore leaves the previous state untouched. Blobs are stored as given.
+    pub async fn replace_mls_state_for_device(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        identity_bytes: &[u8],
+        groups: Vec<(GuildId, ChannelId, PersistedGroupSnapshot)>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM mls_group_provider_entries WHERE user_id = ? AND device_id = ?")
+            .bind(user_id)
+            .bind(device_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM mls_group_states WHERE user_id = ? AND device_id = ?")
+            .bind(user_id)
+            .bind(device_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO mls_identity_keys (user_id, device_id, identity_bytes, updated_at) VALUES (?, ?, ?, CURRENT_TIMESTAMP)
+             ON CONFLICT(user_id, device_id) DO UPDATE SET identity_bytes = excluded.identity_bytes, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .bind(identity_bytes)
+        .execute(&mut *tx)
+        .await?;
+        for (guild_id, channel_id, snapshot) in groups {
+            sqlx::query(
+                "INSERT INTO mls_group_states (
+                    user_id,
+                    device_id,
+                    guild_id,
+                    channel_id,
+                    schema_version,
+                    group_state_blob,
+                    key_material_blob,
+                    group_state_bytes,
+                    updated_at
+                ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, CURRENT_TIMESTAMP)",
+            )
+            .bind(user_id)
+            .bind(device_id)
+            .bind(guild_id.0)
+            .bind(channel_id.0)
+            .bind(snapshot.schema_version)
+            .bind(snapshot.group_state_blob)
+            .bind(snapshot.key_material_blob)
+            .bind(Vec::<u8>::new())
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Lists the channels with a persisted MLS group snapshot for a user/device pair.
+    pub async fn list_group_states_for_device(
+        &self,
+        user_id: i64,
+        device_id: &str,
+    ) -> Result<Vec<(GuildId, ChannelId)>> {
+        let rows = sqlx::query(
+            "SELECT guild_id, channel_id FROM mls_group_states
+             WHERE user_id = ? AND device_id = ?
+             ORDER BY guild_id ASC, channel_id ASC",
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| (GuildId(r.get::<i64, _>(0)), ChannelId(r.get::<i64, _>(1))))
+            .collect())
+    }
+
+    pub async fn load_mls_at_rest_key(&self) -> Result<Option<StoredMlsAtRestKey>> {
+        let row = sqlx::query("SELECT key_source, wrapped_key FROM mls_at_rest_key WHERE id = 1")
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| StoredMlsAtRestKey {
+            key_source: r.get::<String, _>(0),
+            wrapped_key: r.get::<Vec<u8>, _>(1),
+        }))
+    }
+
+    /// Stores a device's serialized key transparency state, replacing the previous one.
+    pub async fn save_key_transparency_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        state_json: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO key_transparency_state (user_id, device_id, state_json, updated_at)
+             VALUES (?, ?, ?, CURRENT_TIMESTAMP)
+             ON CONFLICT(user_id, device_id) DO UPDATE SET
+               state_json = excluded.state_json,
+               updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .bind(state_json)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn load_key_transparency_state(
+        &self,
+        user_id: i64,
+        device_id: &str,
+    ) -> Result<Option<String>> {
+        let row = sqlx::query(
+            "SELECT state_json FROM key_transparency_state WHERE user_id = ? AND device_id = ?",
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| r.get::<String, _>(0)))
+    }
+
+    /// Every identity, group snapshot, provider entry and pending join row in this database,
+    /// for re-encryption.
+    /// Legacy group rows without snapshot blobs are skipped.
+    pub async fn list_mls_secret_blobs(&self) -> Result<Vec<StoredMlsBlob>> {
+        self.ensure_pending_join_table().await?;
+        let mut blobs = Vec::new();
+
+        let identities =
+            sqlx::query("SELECT user_id, device_id, identity_bytes FROM mls_identity_keys")
+                .fetch_all(&self.pool)
+                .await?;
+        blobs.extend(identities.into_iter().map(|r| StoredMlsBlob {
+            kind: StoredMlsBlobKind::Identity,
+            user_id: r.get(0),
+            device_id: r.get(1),
+            guild_id: GuildId(0),
+            channel_id: ChannelId(0),
+            primary_blob: r.get(2),
+            key_material_blob: Vec::new(),
+        }));
+
+        let groups = sqlx::query(
+            "SELECT user_id, device_id, guild_id, channel_id, group_state_blob, key_material_blob
+             FROM mls_group_states
+             WHERE group_state_blob IS NOT NULL AND key_material_blob IS NOT NULL",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        blobs.extend(groups.into_iter().map(|r| StoredMlsBlob {
+            kind: StoredMlsBlobKind::GroupState,
+            user_id: r.get(0),
+            device_id: r.get(1),
+            guild_id: GuildId(r.get(2)),
+            channel_id: ChannelId(r.get(3)),
+            primary_blob: r.get(4),
+            key_material_blob: r.get(5),
+        }));
+
+        let entries = sqlx::query(
+            "SELECT user_id, device_id, guild_id, channel_id, entry_value, entry_key
+             FROM mls_group_provider_entries",
+        )
+        .fetch_all(&self.pool)
         .await?;
+        blobs.extend(entries.into_iter().map(|r| StoredMlsBlob {
+            kind: StoredMlsBlobKind::GroupProviderEntry,
+            user_id: r.get(0),
+            device_id: r.get(1),
+            guild_id: GuildId(r.get(2)),
+            channel_id: ChannelId(r.get(3)),
+            primary_blob: r.get(4),
+            key_material_blob: r.get(5),
+        }));
 
-        Ok(result.rows_affected() > 0)
+        let pending = sqlx::query(
+            "SELECT user_id, device_id, guild_id, group_state_blob, key_material_blob
+             FROM mls_pending_join_state",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        blobs.extend(pending.into_iter().map(|r| StoredMlsBlob {
+            kind: StoredMlsBlobKind::PendingJoin,
+            user_id: r.get(0),
+            device_id: r.get(1),
+            guild_id: GuildId(r.get(2)),
+            channel_id: ChannelId(0),
+            primary_blob: r.get(3),
+            key_material_blob: r.get(4),
+        }));
+
+        Ok(blobs)
     }
 
-    /// Deletes all persisted MLS group snapshots for a user/device pair while keeping
-    /// identity keys intact. Useful when the server database has been reset in local dev.
-    pub async fn clear_all_mls_group_states_for_device(
+    /// Overwrites the given secret rows and the at-rest key record in one transaction, so a
+    /// crash mid-rotation never leaves rows sealed under a key that was not persisted.
+    pub async fn rewrite_mls_secret_blobs(
         &self,
-        user_id: i64,
-        device_id: &str,
-    ) -> Result<u64> {
-        let result =
-            sqlx::query("DELETE FROM mls_group_states WHERE user_id = ? AND device_id = ?")
-                .bind(user_id)
-                .bind(device_id)
-                .execute(&self.pool)
-                .await?;
+        blobs: &[StoredMlsBlob],
+        key: &StoredMlsAtRestKey,
+    ) -> Result<()> {
+        self.ensure_pending_join_table().await?;
+        let mut tx = self.pool.begin().await?;
 
-        Ok(result.rows_affected())
+        for blob in blobs {
+            match blob.kind {
+                StoredMlsBlobKind::Identity => {
+                    sqlx::query(
+                        "UPDATE mls_identity_keys SET identity_bytes = ?, updated_at = CURRENT_TIMESTAMP
+                         WHERE user_id = ? AND device_id = ?",
+                    )
+                    .bind(&blob.primary_blob)
+                    .bind(blob.user_id)
+                    .bind(&blob.device_id)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                StoredMlsBlobKind::GroupState => {
+                    sqlx::query(
+                        "UPDATE mls_group_states
+                         SET group_state_blob = ?, key_material_blob = ?, updated_at = CURRENT_TIMESTAMP
+                         WHERE user_id = ? AND device_id = ? AND guild_id = ? AND channel_id = ?",
+                    )
+                    .bind(&blob.primary_blob)
+                    .bind(&blob.key_material_blob)
+                    .bind(blob.user_id)
+                    .bind(&blob.device_id)
+                    .bind(blob.guild_id.0)
+                    .bind(blob.channel_id.0)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                StoredMlsBlobKind::PendingJoin => {
+                    sqlx::query(
+                        "UPDATE mls_pending_join_state
+                         SET group_state_blob = ?, key_material_blob = ?, updated_at = CURRENT_TIMESTAMP
+                         WHERE user_id = ? AND device_id = ? AND guild_id = ?",
+                    )
+                    .bind(&blob.primary_blob)
+                    .bind(&blob.key_material_blob)
+                    .bind(blob.user_id)
+                    .bind(&blob.device_id)
+                    .bind(blob.guild_id.0)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                StoredMlsBlobKind::GroupProviderEntry => {
+                    sqlx::query(
+                        "UPDATE mls_group_provider_entries
+                         SET entry_value = ?, updated_at = CURRENT_TIMESTAMP
+                         WHERE user_id = ? AND device_id = ? AND guild_id = ? AND channel_id = ?
+                           AND entry_key = ?",
+                    )
+                    .bind(&blob.primary_blob)
+                    .bind(blob.user_id)
+                    .bind(&blob.device_id)
+                    .bind(blob.guild_id.0)
+                    .bind(blob.channel_id.0)
+                    .bind(&blob.key_material_blob)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+        }
+
+        sqlx::query(
+            "INSERT INTO mls_at_rest_key (id, key_source, wrapped_key, updated_at)
+             VALUES (1, ?, ?, CURRENT_TIMESTAMP)
+             ON CONFLICT(id) DO UPDATE SET
+               key_source = excluded.key_source,
+               wrapped_key = excluded.wrapped_key,
+               updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(&key.key_source)
+        .bind(&key.wrapped_key)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
     }
 
     /// Full local MLS reset for a user/device: clears group snapshots and identity keys.
@@ -943,6 +3422,11 @@ impl Storage {
     ) -> Result<(u64, u64)> {
         let mut tx = self.pool.begin().await?;
 
+        sqlx::query("DELETE FROM mls_group_provider_entries WHERE user_id = ? AND device_id = ?")
+            .bind(user_id)
+            .bind(device_id)
+            .execute(&mut *tx)
+            .await?;
         let groups =
             sqlx::query("DELETE FROM mls_group_states WHERE user_id = ? AND device_id = ?")
                 .bind(user_id)
@@ -964,6 +3448,519 @@ impl Storage {
     }
 }
 
+/// Deletes what a guild keeps about one member besides the membership row itself.
+async fn delete_member_rows(
+    tx: &mut sqlx::Transaction<'_, Sqlite>,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<()> {
+    sqlx::query(
+        "DELETE FROM channel_permission_overwrites
+         WHERE target_kind = 'member' AND target_id = ?
+           AND channel_id IN (SELECT id FROM channels WHERE guild_id = ?)",
+    )
+    .bind(user_id.0)
+    .bind(guild_id.0)
+    .execute(&mut **tx)
+    .await?;
+    for statement in [
+        "DELETE FROM member_roles WHERE user_id = ? AND guild_id = ?",
+        "DELETE FROM mls_key_packages WHERE user_id = ? AND guild_id = ?",
+        "DELETE FROM pending_welcomes WHERE user_id = ? AND guild_id = ?",
+        "DELETE FROM mls_history_bundles WHERE target_user_id = ? AND guild_id = ?",
+    ] {
+        sqlx::query(statement)
+            .bind(user_id.0)
+            .bind(guild_id.0)
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(())
+}
+
+/// A JSON array of ids, for `json_each` in place of a variable-length `IN (...)` list.
+fn json_id_array(ids: impl IntoIterator<Item = i64>) -> String {
+    format!(
+        "[{}]",
+        ids.into_iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+/// A random v4 UUID in the same 32-digit hex form as the ids backfilled by migration 0025.
+fn new_file_id() -> FileId {
+    FileId(Uuid::new_v4().simple().to_string())
+}
+
+/// Formats a timestamp like SQLite's `CURRENT_TIMESTAMP` so the two compare as text.
+fn sql_timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn stored_message_from_row(row: &sqlx::sqlite::SqliteRow) -> StoredMessage {
+    StoredMessage {
+        message_id: MessageId(row.get::<i64, _>(0)),
+        channel_id: ChannelId(row.get::<i64, _>(1)),
+        sender_id: UserId(row.get::<i64, _>(2)),
+        ciphertext: row.get::<Vec<u8>, _>(3),
+        attachment: row
+            .get::<Option<String>, _>(5)
+            .map(|file_id| StoredAttachment {
+                file_id: FileId(file_id),
+                filename: row
+                    .get::<Option<String>, _>(6)
+                    .unwrap_or_else(|| "attachment.bin".to_string()),
+                size_bytes: row.get::<Option<i64>, _>(7).unwrap_or_default() as u64,
+                mime_type: row.get::<Option<String>, _>(8),
+            }),
+        thread_id: row.get::<Option<i64>, _>(9).map(ThreadId),
+        created_at: row.get::<DateTime<Utc>, _>(4),
+        expires_at: row.get::<Option<DateTime<Utc>>, _>(10),
+    }
+}
+
+const THREAD_SELECT: &str = "SELECT t.id, c.guild_id, t.channel_id, t.parent_message_id, t.creator_user_id, t.name, t.archived,
+        (SELECT COUNT(*) FROM messages m WHERE m.thread_id = t.id), t.created_at
+     FROM threads t
+     INNER JOIN channels c ON c.id = t.channel_id";
+
+fn thread_from_row(row: &sqlx::sqlite::SqliteRow) -> ThreadSummary {
+    ThreadSummary {
+        thread_id: ThreadId(row.get::<i64, _>(0)),
+        guild_id: GuildId(row.get::<i64, _>(1)),
+        channel_id: ChannelId(row.get::<i64, _>(2)),
+        parent_message_id: MessageId(row.get::<i64, _>(3)),
+        creator_id: UserId(row.get::<i64, _>(4)),
+        name: row.get::<String, _>(5),
+        archived: row.get::<bool, _>(6),
+        message_count: row.get::<i64, _>(7) as u64,
+        created_at: row.get::<DateTime<Utc>, _>(8),
+    }
+}
+
+/// Admin user columns; callers append the `WHERE` clause.
+const ADMIN_USER_SELECT: &str = "SELECT u.id, u.username, u.created_at, u.suspended_at,
+        u.suspension_reason,
+        (SELECT COUNT(*) FROM memberships m INNER JOIN guilds g ON g.id = m.guild_id
+         WHERE m.user_id = u.id AND m.banned = 0 AND g.is_direct = 0),
+        (SELECT COUNT(*) FROM user_devices d WHERE d.user_id = u.id AND d.is_revoked = 0)
+    FROM users u";
+
+fn admin_user_from_row(row: &sqlx::sqlite::SqliteRow) -> AdminUserSummary {
+    AdminUserSummary {
+        user_id: UserId(row.get::<i64, _>(0)),
+        username: row.get::<String, _>(1),
+        created_at: row.get::<DateTime<Utc>, _>(2),
+        suspended_at: row.get::<Option<DateTime<Utc>>, _>(3),
+        suspension_reason: row.get::<Option<String>, _>(4),
+        guild_count: row.get::<i64, _>(5).max(0) as u64,
+        device_count: row.get::<i64, _>(6).max(0) as u64,
+    }
+}
+
+fn invite_from_row(row: &sqlx::sqlite::SqliteRow) -> InviteSummary {
+    InviteSummary {
+        invite_code: row.get::<String, _>(0),
+        guild_id: GuildId(row.get::<i64, _>(1)),
+        creator_id: UserId(row.get::<i64, _>(2)),
+        created_at: row.get::<DateTime<Utc>, _>(3),
+    }
+}
+
+fn audit_entry_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<AuditLogEntry> {
+    let action = row.get::<String, _>(4);
+    Ok(AuditLogEntry {
+        entry_id: row.get::<i64, _>(0),
+        guild_id: GuildId(row.get::<i64, _>(1)),
+        actor_id: UserId(row.get::<i64, _>(2)),
+        actor_username: row.get::<Option<String>, _>(3),
+        action: AuditAction::parse(&action)
+            .ok_or_else(|| anyhow!("unknown audit action: {action}"))?,
+        target_user_id: row.get::<Option<i64>, _>(5).map(UserId),
+        target_username: row.get::<Option<String>, _>(6),
+        target_channel_id: row.get::<Option<i64>, _>(7).map(ChannelId),
+        target_role_id: row.get::<Option<i64>, _>(8).map(RoleId),
+        reason: row.get::<Option<String>, _>(9),
+        details: row.get::<Option<String>, _>(10),
+        created_at: row.get::<DateTime<Utc>, _>(11),
+    })
+}
+
+/// Deletes a channel's rows and adds the blob keys of its files and uploads to `released`.
+async fn delete_channel_rows(
+    tx: &mut sqlx::Transaction<'_, Sqlite>,
+    channel_id: ChannelId,
+    released: &mut Vec<String>,
+) -> Result<bool> {
+    // Messages go before files because attachments reference them. Threads and parent
+    // messages reference each other, so thread messages and threads are removed first.
+    for statement in [
+        "DELETE FROM channel_permission_overwrites WHERE channel_id = ?",
+        "DELETE FROM mls_history_bundles WHERE channel_id = ?",
+        "DELETE FROM pending_welcomes WHERE channel_id = ?",
+        "DELETE FROM messages WHERE channel_id = ? AND thread_id IS NOT NULL",
+        "DELETE FROM threads WHERE channel_id = ?",
+        "DELETE FROM messages WHERE channel_id = ?",
+    ] {
+        sqlx::query(statement)
+            .bind(channel_id.0)
+            .execute(&mut **tx)
+            .await?;
+    }
+    let deleted_files: Vec<DeletedFile> = sqlx::query_as(
+        "DELETE FROM files WHERE channel_id = ?
+         RETURNING blob_key, upload_id, guild_id, uploader_user_id,
+                   COALESCE(size_bytes, length(ciphertext))",
+    )
+    .bind(channel_id.0)
+    .fetch_all(&mut **tx)
+    .await?;
+    release_file_rows(tx, deleted_files, released).await?;
+    let uploads: Vec<String> =
+        sqlx::query_scalar("DELETE FROM upload_sessions WHERE channel_id = ? RETURNING id")
+            .bind(channel_id.0)
+            .fetch_all(&mut **tx)
+            .await?;
+    delete_upload_chunks(tx, uploads, released).await?;
+    let result = sqlx::query("DELETE FROM channels WHERE id = ?")
+        .bind(channel_id.0)
+        .execute(&mut **tx)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// A deleted file row: `(blob_key, upload_id, guild_id, uploader_user_id, size_bytes)`.
+type DeletedFile = (Option<String>, Option<String>, i64, i64, i64);
+
+/// Deletes the messages in `rows` (id, channel, guild) with threads anchored to them, then
+/// attachments nothing else references. Thread replies are added to `rows`; returns how many
+/// files went.
+async fn delete_message_rows(
+    tx: &mut sqlx::Transaction<'_, Sqlite>,
+    rows: &mut Vec<(i64, i64, i64)>,
+    released: &mut Vec<String>,
+) -> Result<u64> {
+    let parents = json_id_array(rows.iter().map(|(id, _, _)| *id));
+    let thread_rows: Vec<(i64, i64, i64)> = sqlx::query_as(
+        "SELECT m.id, m.channel_id, c.guild_id
+         FROM messages m
+         INNER JOIN threads t ON t.id = m.thread_id
+         INNER JOIN channels c ON c.id = m.channel_id
+         WHERE t.parent_message_id IN (SELECT value FROM json_each(?))",
+    )
+    .bind(&parents)
+    .fetch_all(&mut **tx)
+    .await?;
+    rows.extend(thread_rows);
+    rows.sort_unstable();
+    rows.dedup();
+
+    let message_ids = json_id_array(rows.iter().map(|(id, _, _)| *id));
+    let file_ids: Vec<i64> = sqlx::query_scalar(
+        "SELECT DISTINCT attachment_file_id FROM messages
+         WHERE attachment_file_id IS NOT NULL AND id IN (SELECT value FROM json_each(?))",
+    )
+    .bind(&message_ids)
+    .fetch_all(&mut **tx)
+    .await?;
+    // Thread messages reference their thread, and threads reference their parent.
+    for statement in [
+        "DELETE FROM messages
+         WHERE thread_id IS NOT NULL AND id IN (SELECT value FROM json_each(?))",
+        "DELETE FROM threads WHERE parent_message_id IN (SELECT value FROM json_each(?))",
+        "DELETE FROM messages WHERE id IN (SELECT value FROM json_each(?))",
+    ] {
+        sqlx::query(statement)
+            .bind(&message_ids)
+            .execute(&mut **tx)
+            .await?;
+    }
+    let deleted_files: Vec<DeletedFile> = sqlx::query_as(
+        "DELETE FROM files
+         WHERE id IN (SELECT value FROM json_each(?))
+           AND NOT EXISTS (SELECT 1 FROM messages m WHERE m.attachment_file_id = files.id)
+         RETURNING blob_key, upload_id, guild_id, uploader_user_id,
+                   COALESCE(size_bytes, length(ciphertext))",
+    )
+    .bind(json_id_array(file_ids))
+    .fetch_all(&mut **tx)
+    .await?;
+    Ok(deleted_files.len() as u64 + release_file_rows(tx, deleted_files, released).await?)
+}
+
+fn group_by_channel(mut rows: Vec<(i64, i64, i64)>) -> Vec<(GuildId, ChannelId, Vec<MessageId>)> {
+    let mut grouped: Vec<(GuildId, ChannelId, Vec<MessageId>)> = Vec::new();
+    rows.sort_by_key(|(id, channel_id, _)| (*channel_id, *id));
+    for (id, channel_id, guild_id) in rows {
+        match grouped.last_mut() {
+            Some((_, last_channel_id, ids)) if last_channel_id.0 == channel_id => {
+                ids.push(MessageId(id))
+            }
+            _ => grouped.push((
+                GuildId(guild_id),
+                ChannelId(channel_id),
+                vec![MessageId(id)],
+            )),
+        }
+    }
+    grouped
+}
+
+/// Adds the blobs of deleted file rows to `released`, deletes the chunks of chunked uploads
+/// and takes the files off their guild's and uploader's storage usage. Thumbnails of the
+/// deleted files go with them; returns how many were deleted that way.
+async fn release_file_rows(
+    tx: &mut sqlx::Transaction<'_, Sqlite>,
+    mut deleted_files: Vec<DeletedFile>,
+    released: &mut Vec<String>,
+) -> Result<u64> {
+    let thumbnails: Vec<DeletedFile> = sqlx::query_as(
+        "DELETE FROM files
+         WHERE thumbnail_of IS NOT NULL
+           AND NOT EXISTS (SELECT 1 FROM files p WHERE p.id = files.thumbnail_of)
+         RETURNING blob_key, upload_id, guild_id, uploader_user_id,
+                   COALESCE(size_bytes, length(ciphertext))",
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    let thumbnail_count = thumbnails.len() as u64;
+    deleted_files.extend(thumbnails);
+    let mut uploads = Vec::new();
+    for (blob_key, upload_id, guild_id, uploader_id, size_bytes) in deleted_files {
+        released.extend(blob_key);
+        uploads.extend(upload_id);
+        adjust_storage_usage(tx, guild_id, uploader_id, -size_bytes).await?;
+    }
+    delete_upload_chunks(tx, uploads, released).await?;
+    Ok(thumbnail_count)
+}
+
+/// Adds `delta` bytes to a guild's and a user's storage usage, never going below zero.
+async fn adjust_storage_usage(
+    tx: &mut sqlx::Transaction<'_, Sqlite>,
+    guild_id: i64,
+    user_id: i64,
+    delta: i64,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO guild_storage_usage (guild_id, used_bytes) VALUES (?1, MAX(?2, 0))
+         ON CONFLICT (guild_id) DO UPDATE SET used_bytes = MAX(used_bytes + ?2, 0)",
+    )
+    .bind(guild_id)
+    .bind(delta)
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query(
+        "INSERT INTO user_storage_usage (user_id, used_bytes) VALUES (?1, MAX(?2, 0))
+         ON CONFLICT (user_id) DO UPDATE SET used_bytes = MAX(used_bytes + ?2, 0)",
+    )
+    .bind(user_id)
+    .bind(delta)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+async fn storage_usage(
+    conn: &mut sqlx::SqliteConnection,
+    scope: QuotaScope,
+    id: i64,
+) -> Result<StorageUsage> {
+    let query = match scope {
+        QuotaScope::Guild => {
+            "SELECT COALESCE((SELECT used_bytes FROM guild_storage_usage WHERE guild_id = ?1), 0),
+                    COALESCE((SELECT SUM(total_bytes) FROM upload_sessions WHERE guild_id = ?1), 0)"
+        }
+        QuotaScope::User => {
+            "SELECT COALESCE((SELECT used_bytes FROM user_storage_usage WHERE user_id = ?1), 0),
+                    COALESCE((SELECT SUM(total_bytes) FROM upload_sessions WHERE uploader_user_id = ?1), 0)"
+        }
+    };
+    let (used_bytes, reserved_bytes): (i64, i64) =
+        sqlx::query_as(query).bind(id).fetch_one(&mut *conn).await?;
+    Ok(StorageUsage {
+        used_bytes: used_bytes as u64,
+        reserved_bytes: reserved_bytes as u64,
+    })
+}
+
+/// Fails with [`QuotaExceeded`] if storing `requested_bytes` more would go over a quota.
+async fn check_storage_quotas(
+    tx: &mut sqlx::Transaction<'_, Sqlite>,
+    quotas: StorageQuotas,
+    uploader_id: UserId,
+    guild_id: GuildId,
+    requested_bytes: u64,
+) -> Result<()> {
+    for (scope, id, quota) in [
+        (QuotaScope::Guild, guild_id.0, quotas.guild_bytes),
+        (QuotaScope::User, uploader_id.0, quotas.user_bytes),
+    ] {
+        let Some(quota_bytes) = quota else {
+            continue;
+        };
+        let used_bytes = storage_usage(tx, scope, id).await?.total_bytes();
+        if used_bytes.saturating_add(requested_bytes) > quota_bytes {
+            return Err(QuotaExceeded {
+                scope,
+                used_bytes,
+                requested_bytes,
+                quota_bytes,
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+async fn delete_upload_chunks(
+    tx: &mut sqlx::Transaction<'_, Sqlite>,
+    upload_ids: Vec<String>,
+    released: &mut Vec<String>,
+) -> Result<()> {
+    for upload_id in upload_ids {
+        let keys: Vec<Option<String>> =
+            sqlx::query_scalar("DELETE FROM file_chunks WHERE upload_id = ? RETURNING blob_key")
+                .bind(upload_id)
+                .fetch_all(&mut **tx)
+                .await?;
+        released.extend(keys.into_iter().flatten());
+    }
+    Ok(())
+}
+
+async fn append_key_transparency_leaf(
+    tx: &mut sqlx::Transaction<'_, Sqlite>,
+    user_id: UserId,
+    device_id: DeviceId,
+    action: KeyTransparencyAction,
+    device_public_identity: &str,
+) -> Result<KeyTransparencyLeaf> {
+    let leaf_index: i64 =
+        sqlx::query_scalar("SELECT COALESCE(MAX(leaf_index) + 1, 0) FROM key_transparency_log")
+            .fetch_one(&mut **tx)
+            .await?;
+    let logged_at = DateTime::from_timestamp_millis(Utc::now().timestamp_millis())
+        .ok_or_else(|| anyhow!("clock out of range"))?;
+    let leaf = KeyTransparencyLeaf {
+        leaf_index: leaf_index as u64,
+        user_id,
+        device_id,
+        action,
+        device_public_identity: device_public_identity.to_string(),
+        logged_at,
+    };
+    sqlx::query(
+        "INSERT INTO key_transparency_log
+           (leaf_index, user_id, device_id, action, device_public_identity, logged_at_ms, leaf_hash)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(leaf_index)
+    .bind(user_id.0)
+    .bind(device_id.0)
+    .bind(key_transparency_action_to_str(action))
+    .bind(device_public_identity)
+    .bind(logged_at.timestamp_millis())
+    .bind(leaf.leaf_hash().as_slice())
+    .execute(&mut **tx)
+    .await?;
+    Ok(leaf)
+}
+
+fn key_transparency_leaf_from_row(row: sqlx::sqlite::SqliteRow) -> Result<KeyTransparencyLeaf> {
+    let action = match row.get::<String, _>(3).as_str() {
+        "register" => KeyTransparencyAction::Register,
+        "revoke" => KeyTransparencyAction::Revoke,
+        other => return Err(anyhow!("unknown key transparency action: {other}")),
+    };
+    Ok(KeyTransparencyLeaf {
+        leaf_index: row.get::<i64, _>(0) as u64,
+        user_id: UserId(row.get::<i64, _>(1)),
+        device_id: DeviceId(row.get::<i64, _>(2)),
+        action,
+        device_public_identity: row.get::<String, _>(4),
+        logged_at: DateTime::from_timestamp_millis(row.get::<i64, _>(5))
+            .ok_or_else(|| anyhow!("invalid key transparency timestamp"))?,
+    })
+}
+
+fn overwrite_target_to_row(target: OverwriteTarget) -> (&'static str, i64) {
+    match target {
+        OverwriteTarget::Everyone => ("everyone", 0),
+        OverwriteTarget::Role(role_id) => ("role", role_id.0),
+        OverwriteTarget::Member(user_id) => ("member", user_id.0),
+    }
+}
+
+fn channel_overwrite_from_row(
+    row: &sqlx::sqlite::SqliteRow,
+) -> Option<(ChannelId, PermissionOverwrite)> {
+    let target_id = row.get::<i64, _>(2);
+    let target = match row.get::<String, _>(1).as_str() {
+        "everyone" => OverwriteTarget::Everyone,
+        "role" => OverwriteTarget::Role(RoleId(target_id)),
+        "member" => OverwriteTarget::Member(UserId(target_id)),
+        _ => return None,
+    };
+    Some((
+        ChannelId(row.get::<i64, _>(0)),
+        PermissionOverwrite {
+            target,
+            allow: Permissions::from_bits_truncate(row.get::<i64, _>(3) as u64),
+            deny: Permissions::from_bits_truncate(row.get::<i64, _>(4) as u64),
+        },
+    ))
+}
+
+fn channel_kind_str(kind: ChannelKind) -> &'static str {
+    match kind {
+        ChannelKind::Text => "text",
+        ChannelKind::Voice => "voice",
+        ChannelKind::Direct => "direct",
+    }
+}
+
+fn stored_channel_from_row(row: &sqlx::sqlite::SqliteRow) -> StoredChannel {
+    StoredChannel {
+        channel_id: ChannelId(row.get::<i64, _>(0)),
+        guild_id: GuildId(row.get::<i64, _>(1)),
+        name: row.get::<String, _>(2),
+        kind: match row.get::<String, _>(3).as_str() {
+            "voice" => ChannelKind::Voice,
+            "direct" => ChannelKind::Direct,
+            _ => ChannelKind::Text,
+        },
+        topic: row.get::<Option<String>, _>(4),
+        category: row.get::<Option<String>, _>(5),
+        position: row.get::<i64, _>(6),
+        history_sharing: row.get::<bool, _>(7),
+        retention: RetentionPolicy {
+            max_age_seconds: row.get::<Option<i64>, _>(8).map(|seconds| seconds as u64),
+            max_messages: row.get::<Option<i64>, _>(9).map(|count| count as u64),
+        },
+    }
+}
+
+fn stored_guild_role_from_row(row: &sqlx::sqlite::SqliteRow) -> StoredGuildRole {
+    StoredGuildRole {
+        role_id: RoleId(row.get::<i64, _>(0)),
+        guild_id: GuildId(row.get::<i64, _>(1)),
+        name: row.get::<String, _>(2),
+        color: row.get::<i64, _>(3) as u32,
+        position: row.get::<i64, _>(4),
+        permissions: Permissions::from_bits_truncate(row.get::<i64, _>(5) as u64),
+    }
+}
+
+fn key_transparency_action_to_str(action: KeyTransparencyAction) -> &'static str {
+    match action {
+        KeyTransparencyAction::Register => "register",
+        KeyTransparencyAction::Revoke => "revoke",
+    }
+}
+
 fn ensure_sqlite_parent_dir_exists(database_url: &str) -> Result<()> {
     let Some(path) = sqlite_path(database_url) else {
         return Ok(());
@@ -1041,36 +4038,15 @@ impl MlsStore for Storage {
         channel_id: ChannelId,
         snapshot: PersistedGroupSnapshot,
     ) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO mls_group_states (
-                user_id,
-                device_id,
-                guild_id,
-                channel_id,
-                schema_version,
-                group_state_blob,
-                key_material_blob,
-                group_state_bytes,
-                updated_at
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
-             ON CONFLICT(user_id, device_id, guild_id, channel_id) DO UPDATE SET
-                schema_version = excluded.schema_version,
-                group_state_blob = excluded.group_state_blob,
-                key_material_blob = excluded.key_material_blob,
-                group_state_bytes = excluded.group_state_bytes,
-                updated_at = CURRENT_TIMESTAMP",
+        // A self-contained snapshot supersedes any incrementally persisted entries.
+        let changes = ProviderChanges {
+            replace_all: true,
+            ..ProviderChanges::default()
+        };
+        self.save_group_state_incremental(
+            user_id, device_id, guild_id, channel_id, snapshot, &changes,
         )
-        .bind(user_id)
-        .bind(device_id)
-        .bind(guild_id.0)
-        .bind(channel_id.0)
-        .bind(snapshot.schema_version)
-        .bind(snapshot.group_state_blob)
-        .bind(snapshot.key_material_blob)
-        .bind(Vec::<u8>::new())
-        .execute(&self.pool)
-        .await?;
-        Ok(())
+        .await
     }
 
     async fn load_group_state(
@@ -1195,6 +4171,122 @@ impl MlsStore for Storage {
         .await?;
         Ok(())
     }
+
+    async fn load_group_provider_entries(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<Vec<ProviderEntry>> {
+        let rows = sqlx::query(
+            "SELECT entry_key, entry_value FROM mls_group_provider_entries
+             WHERE user_id = ? AND device_id = ? AND guild_id = ? AND channel_id = ?",
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .bind(guild_id.0)
+        .bind(channel_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| ProviderEntry {
+                key: r.get(0),
+                value: r.get(1),
+            })
+            .collect())
+    }
+
+    async fn save_group_state_incremental(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        snapshot: PersistedGroupSnapshot,
+        changes: &ProviderChanges,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        if changes.replace_all {
+            sqlx::query(
+                "DELETE FROM mls_group_provider_entries
+                 WHERE user_id = ? AND device_id = ? AND guild_id = ? AND channel_id = ?",
+            )
+            .bind(user_id)
+            .bind(device_id)
+            .bind(guild_id.0)
+            .bind(channel_id.0)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for key in &changes.deletes {
+            sqlx::query(
+                "DELETE FROM mls_group_provider_entries
+                 WHERE user_id = ? AND device_id = ? AND guild_id = ? AND channel_id = ?
+                   AND entry_key = ?",
+            )
+            .bind(user_id)
+            .bind(device_id)
+            .bind(guild_id.0)
+            .bind(channel_id.0)
+            .bind(key)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for entry in &changes.upserts {
+            sqlx::query(
+                "INSERT INTO mls_group_provider_entries (
+                    user_id, device_id, guild_id, channel_id, entry_key, entry_value, updated_at
+                ) VALUES (?, ?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
+                 ON CONFLICT(user_id, device_id, guild_id, channel_id, entry_key) DO UPDATE SET
+                    entry_value = excluded.entry_value,
+                    updated_at = CURRENT_TIMESTAMP",
+            )
+            .bind(user_id)
+            .bind(device_id)
+            .bind(guild_id.0)
+            .bind(channel_id.0)
+            .bind(&entry.key)
+            .bind(&entry.value)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query(
+            "INSERT INTO mls_group_states (
+                user_id,
+                device_id,
+                guild_id,
+                channel_id,
+                schema_version,
+                group_state_blob,
+                key_material_blob,
+                group_state_bytes,
+                updated_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
+             ON CONFLICT(user_id, device_id, guild_id, channel_id) DO UPDATE SET
+                schema_version = excluded.schema_version,
+                group_state_blob = excluded.group_state_blob,
+                key_material_blob = excluded.key_material_blob,
+                group_state_bytes = excluded.group_state_bytes,
+                updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .bind(guild_id.0)
+        .bind(channel_id.0)
+        .bind(snapshot.schema_version)
+        .bind(snapshot.group_state_blob)
+        .bind(snapshot.key_material_blob)
+        .bind(Vec::<u8>::new())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -19,6 +19,187 @@ async fn health_check_succeeds_for_live_pool() {
     storage.health_check().await.expect("health check");
 }
 
+#[tokio::test]
+async fn readiness_checks_cover_migrations_and_the_blob_store() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    storage
+        .check_migrations()
+        .await
+        .expect("migrations applied");
+    storage.check_blob_store().await.expect("no blob store");
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let storage = storage.with_blob_store(Some(Arc::new(FilesystemBlobStore::new(
+        dir.path().join("blobs"),
+    ))));
+    storage
+        .check_blob_store()
+        .await
+        .expect("writable blob store");
+
+    let blocked = dir.path().join("not-a-dir");
+    fs::write(&blocked, b"file").expect("blocker");
+    let storage = storage.with_blob_store(Some(Arc::new(FilesystemBlobStore::new(blocked))));
+    assert!(storage.check_blob_store().await.is_err());
+
+    sqlx::query("DELETE FROM _sqlx_migrations WHERE version = 26")
+        .execute(storage.pool())
+        .await
+        .expect("forget migration");
+    let error = storage.check_migrations().await.expect_err("missing");
+    assert!(
+        error.to_string().contains("26 (file thumbnails)"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn mls_queue_depths_count_unconsumed_welcomes_and_live_key_packages() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let user = storage.create_user("alice").await.expect("user");
+    let guild = storage.create_guild("queues", user).await.expect("guild");
+    let channel = storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+    for bytes in [b"kp-1".as_ref(), b"kp-2".as_ref()] {
+        storage
+            .insert_key_package(guild, user, None, bytes)
+            .await
+            .expect("key package");
+    }
+    for bytes in [b"welcome-1".as_ref(), b"welcome-2".as_ref()] {
+        storage
+            .insert_pending_welcome(guild, channel, user, None, bytes)
+            .await
+            .expect("welcome");
+    }
+    storage
+        .load_and_consume_pending_welcome(guild, channel, user)
+        .await
+        .expect("consume");
+
+    let depths = storage.mls_queue_depths().await.expect("depths");
+    assert_eq!(
+        depths,
+        MlsQueueDepths {
+            pending_welcomes: 1,
+            key_packages: 2,
+        }
+    );
+}
+
+#[tokio::test]
+async fn admin_listings_cover_users_guilds_and_suspension() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("alice");
+    let bob = storage.create_user("bob_smith").await.expect("bob");
+    storage.create_user("bobby").await.expect("bobby");
+    let guild = storage.create_guild("devs", alice).await.expect("guild");
+    storage
+        .add_membership(guild, bob, Role::Member, false, false)
+        .await
+        .expect("membership");
+    storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+
+    let found = storage.list_users(Some("bob"), 10).await.expect("search");
+    assert_eq!(found.len(), 2);
+    // `_` is matched literally, not as a wildcard.
+    let found = storage.list_users(Some("b_s"), 10).await.expect("search");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].user_id, bob);
+    assert_eq!(found[0].guild_count, 1);
+    assert_eq!(storage.list_users(None, 2).await.expect("page").len(), 2);
+
+    assert!(!storage.user_is_suspended(bob).await.expect("state"));
+    assert!(storage
+        .suspend_user(bob, Some("spam"))
+        .await
+        .expect("suspend"));
+    let suspended = storage.admin_user(bob).await.expect("load").expect("user");
+    assert!(suspended.suspended_at.is_some());
+    assert_eq!(suspended.suspension_reason.as_deref(), Some("spam"));
+    assert!(storage.user_is_suspended(bob).await.expect("state"));
+    assert!(storage.unsuspend_user(bob).await.expect("unsuspend"));
+    assert!(!storage.user_is_suspended(bob).await.expect("state"));
+    assert!(!storage
+        .suspend_user(UserId(9_999), None)
+        .await
+        .expect("missing"));
+
+    let guilds = storage.list_all_guilds().await.expect("guilds");
+    assert_eq!(guilds.len(), 1);
+    assert_eq!(guilds[0].member_count, 2);
+    assert_eq!(guilds[0].channel_count, 1);
+    let stats = storage.storage_stats().await.expect("stats");
+    assert_eq!((stats.users, stats.guilds, stats.channels), (3, 1, 1));
+}
+
+#[tokio::test]
+async fn force_deleting_messages_and_files_releases_their_bytes() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("user");
+    let guild = storage.create_guild("devs", alice).await.expect("guild");
+    let channel = storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+    let attach = |file_id: FileId| StoredAttachment {
+        file_id,
+        filename: "a.bin".to_string(),
+        size_bytes: 4,
+        mime_type: None,
+    };
+
+    let first = storage
+        .store_file_ciphertext(alice, guild, channel, b"blob", None, Some("a.bin"))
+        .await
+        .expect("file");
+    let message = storage
+        .insert_message_ciphertext(channel, alice, b"hello", Some(&attach(first.clone())))
+        .await
+        .expect("message");
+    assert_eq!(
+        storage.delete_message(message).await.expect("delete"),
+        Some((guild, channel, vec![message]))
+    );
+    assert_eq!(storage.delete_message(message).await.expect("again"), None);
+    assert!(storage
+        .load_file_metadata(&first)
+        .await
+        .expect("load")
+        .is_none());
+
+    let second = storage
+        .store_file_ciphertext(alice, guild, channel, b"blob", None, Some("a.bin"))
+        .await
+        .expect("file");
+    let kept = storage
+        .insert_message_ciphertext(channel, alice, b"kept", Some(&attach(second.clone())))
+        .await
+        .expect("message");
+    assert!(storage.delete_file(&second).await.expect("delete file"));
+    assert!(!storage.delete_file(&second).await.expect("again"));
+    let messages = storage
+        .list_channel_messages(channel, 10, None)
+        .await
+        .expect("messages");
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].message_id, kept);
+    assert!(messages[0].attachment.is_none());
+    assert_eq!(
+        storage
+            .guild_storage_usage(guild)
+            .await
+            .expect("usage")
+            .used_bytes,
+        0
+    );
+}
+
 #[tokio::test]
 async fn creates_database_file_when_missing() {
     let suffix = std::time::SystemTime::now()
@@ -385,7 +566,7 @@ async fn stores_message_attachment_metadata() {
             user,
             b"see attachment",
             Some(&StoredAttachment {
-                file_id,
+                file_id: file_id.clone(),
                 filename: "hello.txt".to_string(),
                 size_bytes: 15,
                 mime_type: Some("text/plain".to_string()),
@@ -403,3 +584,1390 @@ async fn stores_message_attachment_metadata() {
     assert_eq!(attachment.filename, "hello.txt");
     assert_eq!(attachment.size_bytes, 15);
 }
+
+#[tokio::test]
+async fn device_registration_and_revocation_append_to_transparency_log() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let user = storage.create_user("alice").await.expect("user");
+    let device = storage
+        .register_device(user, "laptop", "user:laptop")
+        .await
+        .expect("device");
+    assert!(storage
+        .revoke_device(user, device.device_id)
+        .await
+        .expect("revoke"));
+    assert!(!storage
+        .revoke_device(user, device.device_id)
+        .await
+        .expect("second revoke is a no-op"));
+
+    assert_eq!(storage.key_transparency_tree_size().await.expect("size"), 2);
+    let latest = storage
+        .latest_key_transparency_leaf_for_device(user, device.device_id)
+        .await
+        .expect("leaf")
+        .expect("logged");
+    assert_eq!(latest.leaf_index, 1);
+    assert_eq!(latest.action, KeyTransparencyAction::Revoke);
+
+    let hashes = storage
+        .list_key_transparency_leaf_hashes(2)
+        .await
+        .expect("hashes");
+    let first = storage
+        .load_key_transparency_leaf(0)
+        .await
+        .expect("leaf")
+        .expect("logged");
+    assert_eq!(hashes, vec![first.leaf_hash(), latest.leaf_hash()]);
+    assert_eq!(
+        storage
+            .backfill_key_transparency_log()
+            .await
+            .expect("backfill"),
+        0
+    );
+}
+
+#[tokio::test]
+async fn mls_backup_is_replaced_per_user() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("alice");
+    let bob = storage.create_user("bob").await.expect("bob");
+
+    assert!(storage
+        .load_mls_backup(alice)
+        .await
+        .expect("load")
+        .is_none());
+    storage
+        .store_mls_backup(alice, b"old")
+        .await
+        .expect("store old");
+    storage
+        .store_mls_backup(alice, b"new")
+        .await
+        .expect("store new");
+
+    assert_eq!(
+        storage.load_mls_backup(alice).await.expect("load"),
+        Some(b"new".to_vec())
+    );
+    assert!(storage.load_mls_backup(bob).await.expect("load").is_none());
+}
+
+#[tokio::test]
+async fn key_transparency_state_is_replaced_per_device() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+
+    storage
+        .save_key_transparency_state(7, "laptop", "old")
+        .await
+        .expect("save old");
+    storage
+        .save_key_transparency_state(7, "laptop", "new")
+        .await
+        .expect("save new");
+
+    assert_eq!(
+        storage
+            .load_key_transparency_state(7, "laptop")
+            .await
+            .expect("load"),
+        Some("new".to_string())
+    );
+    assert!(storage
+        .load_key_transparency_state(7, "phone")
+        .await
+        .expect("load")
+        .is_none());
+}
+
+#[tokio::test]
+async fn history_bundles_follow_channel_opt_in_and_target_device() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("alice");
+    let bob = storage.create_user("bob").await.expect("bob");
+    let guild = storage.create_guild("history", alice).await.expect("guild");
+    let channel = storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+
+    assert!(!storage
+        .channel_history_sharing(channel)
+        .await
+        .expect("flag"));
+    storage
+        .set_channel_history_sharing(channel, true)
+        .await
+        .expect("enable");
+    assert!(storage
+        .channel_history_sharing(channel)
+        .await
+        .expect("flag"));
+    assert_eq!(
+        storage
+            .list_history_sharing_channels(guild)
+            .await
+            .expect("list"),
+        vec![channel]
+    );
+
+    storage
+        .insert_history_bundle(
+            guild,
+            channel,
+            alice,
+            bob,
+            Some(DeviceId(1)),
+            b"for-device-1",
+        )
+        .await
+        .expect("insert");
+    storage
+        .insert_history_bundle(guild, channel, alice, bob, None, b"for-any-device")
+        .await
+        .expect("insert");
+
+    let device_two = storage
+        .list_history_bundles(guild, channel, bob, Some(DeviceId(2)))
+        .await
+        .expect("list");
+    assert_eq!(device_two.len(), 1);
+    assert_eq!(device_two[0].bundle_bytes, b"for-any-device");
+    assert_eq!(device_two[0].sender_id, alice);
+
+    let device_one = storage
+        .list_history_bundles(guild, channel, bob, Some(DeviceId(1)))
+        .await
+        .expect("list");
+    assert_eq!(device_one.len(), 2);
+    assert!(storage
+        .list_history_bundles(guild, channel, alice, None)
+        .await
+        .expect("list")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn guild_roles_order_by_position_and_cascade_assignments() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("alice");
+    let bob = storage.create_user("bob").await.expect("bob");
+    let guild = storage.create_guild("roles", alice).await.expect("guild");
+    storage
+        .add_membership(guild, bob, Role::Member, false, false)
+        .await
+        .expect("membership");
+
+    let helper = storage
+        .create_guild_role(guild, "helper", 0x00ff00, 1, Permissions::MUTE_MEMBERS)
+        .await
+        .expect("helper");
+    let admin = storage
+        .create_guild_role(guild, "admin", 0xff0000, 5, Permissions::ALL)
+        .await
+        .expect("admin");
+    let roles = storage.list_guild_roles(guild).await.expect("roles");
+    assert_eq!(
+        roles.iter().map(|role| role.role_id).collect::<Vec<_>>(),
+        vec![admin, helper]
+    );
+
+    storage
+        .assign_member_role(guild, bob, helper)
+        .await
+        .expect("assign");
+    storage
+        .assign_member_role(guild, bob, admin)
+        .await
+        .expect("assign");
+    storage
+        .assign_member_role(guild, bob, admin)
+        .await
+        .expect("assign is idempotent");
+    let members = storage
+        .list_members_for_guild(guild)
+        .await
+        .expect("members");
+    let bob_member = members.iter().find(|m| m.user_id == bob).expect("bob");
+    assert_eq!(bob_member.role_ids, vec![admin, helper]);
+
+    storage
+        .update_guild_role(helper, "helper", 0x00ff00, 9, Permissions::MUTE_MEMBERS)
+        .await
+        .expect("update");
+    let held = storage.list_member_roles(guild, bob).await.expect("held");
+    assert_eq!(held[0].role_id, helper);
+    assert_eq!(held[0].position, 9);
+
+    assert!(storage.delete_guild_role(admin).await.expect("delete"));
+    assert_eq!(
+        storage
+            .list_member_roles(guild, bob)
+            .await
+            .expect("held")
+            .into_iter()
+            .map(|role| role.role_id)
+            .collect::<Vec<_>>(),
+        vec![helper]
+    );
+    assert!(storage
+        .unassign_member_role(guild, bob, helper)
+        .await
+        .expect("unassign"));
+    assert!(storage.guild_role(admin).await.expect("lookup").is_none());
+}
+
+#[tokio::test]
+async fn channel_overwrites_upsert_per_target_and_drop_with_role() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("alice");
+    let guild = storage.create_guild("private", alice).await.expect("guild");
+    let channel = storage
+        .create_channel(guild, "staff", ChannelKind::Text)
+        .await
+        .expect("channel");
+    let staff = storage
+        .create_guild_role(guild, "staff", 0, 1, Permissions::NONE)
+        .await
+        .expect("role");
+
+    let hide = PermissionOverwrite {
+        target: OverwriteTarget::Everyone,
+        allow: Permissions::NONE,
+        deny: Permissions::VIEW_CHANNEL,
+    };
+    let staff_view = PermissionOverwrite {
+        target: OverwriteTarget::Role(staff),
+        allow: Permissions::VIEW_CHANNEL,
+        deny: Permissions::NONE,
+    };
+    storage
+        .upsert_channel_overwrite(channel, &hide)
+        .await
+        .expect("hide");
+    storage
+        .upsert_channel_overwrite(channel, &staff_view)
+        .await
+        .expect("staff");
+    storage
+        .upsert_channel_overwrite(
+            channel,
+            &PermissionOverwrite {
+                allow: Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+                ..staff_view
+            },
+        )
+        .await
+        .expect("replace staff");
+
+    let overwrites = storage
+        .list_channel_overwrites(channel)
+        .await
+        .expect("list");
+    assert_eq!(overwrites.len(), 2);
+    assert!(overwrites
+        .iter()
+        .any(|o| o.target == OverwriteTarget::Role(staff)
+            && o.allow.contains(Permissions::SEND_MESSAGES)));
+    assert_eq!(
+        storage
+            .list_guild_channel_overwrites(guild)
+            .await
+            .expect("guild list")
+            .len(),
+        2
+    );
+
+    assert!(storage.delete_guild_role(staff).await.expect("delete role"));
+    assert_eq!(
+        storage
+            .list_channel_overwrites(channel)
+            .await
+            .expect("list"),
+        vec![hide]
+    );
+    assert!(storage
+        .delete_channel_overwrite(channel, OverwriteTarget::Everyone)
+        .await
+        .expect("delete"));
+}
+
+#[tokio::test]
+async fn channel_and_guild_layout_reorders_and_cascades_on_delete() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("alice");
+    let first = storage.create_guild("first", alice).await.expect("guild");
+    let second = storage.create_guild("second", alice).await.expect("guild");
+    let general = storage
+        .create_channel(first, "general", ChannelKind::Text)
+        .await
+        .expect("general");
+    let voice = storage
+        .create_channel(first, "voice", ChannelKind::Voice)
+        .await
+        .expect("voice");
+
+    assert!(storage
+        .update_channel(general, "lobby", Some("say hi"), Some("chat"))
+        .await
+        .expect("update"));
+    storage
+        .reorder_channels(first, &[voice, general])
+        .await
+        .expect("reorder channels");
+    let channels = storage
+        .list_channels_for_guild(first)
+        .await
+        .expect("channels");
+    assert_eq!(
+        channels.iter().map(|c| c.channel_id).collect::<Vec<_>>(),
+        vec![voice, general]
+    );
+    let lobby = storage
+        .channel(general)
+        .await
+        .expect("lookup")
+        .expect("lobby");
+    assert_eq!(lobby.name, "lobby");
+    assert_eq!(lobby.topic.as_deref(), Some("say hi"));
+    assert_eq!(lobby.category.as_deref(), Some("chat"));
+
+    storage
+        .reorder_guilds(alice, &[second, first])
+        .await
+        .expect("reorder guilds");
+    let guilds = storage.list_guilds_for_user(alice).await.expect("guilds");
+    assert_eq!(
+        guilds.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+        vec![second, first]
+    );
+
+    storage
+        .insert_message_ciphertext(general, alice, b"hello", None)
+        .await
+        .expect("message");
+    assert!(storage.delete_channel(voice).await.expect("delete channel"));
+    assert!(storage.channel(voice).await.expect("lookup").is_none());
+
+    assert!(storage.delete_guild(first).await.expect("delete guild"));
+    assert!(storage.channel(general).await.expect("lookup").is_none());
+    assert!(storage
+        .membership_status(first, alice)
+        .await
+        .expect("membership")
+        .is_none());
+    assert_eq!(
+        storage.list_guilds_for_user(alice).await.expect("guilds"),
+        vec![(second, "second".to_string())]
+    );
+    assert!(!storage.delete_guild(first).await.expect("delete again"));
+}
+
+#[tokio::test]
+async fn removing_membership_drops_member_scoped_rows() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("alice");
+    let bob = storage.create_user("bob").await.expect("bob");
+    let guild = storage.create_guild("crew", alice).await.expect("guild");
+    let channel = storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+    storage
+        .add_membership(guild, bob, Role::Member, false, false)
+        .await
+        .expect("membership");
+    let helper = storage
+        .create_guild_role(guild, "helper", 0, 1, Permissions::NONE)
+        .await
+        .expect("role");
+    storage
+        .assign_member_role(guild, bob, helper)
+        .await
+        .expect("assign");
+    storage
+        .upsert_channel_overwrite(
+            channel,
+            &PermissionOverwrite {
+                target: OverwriteTarget::Member(bob),
+                allow: Permissions::SEND_MESSAGES,
+                deny: Permissions::NONE,
+            },
+        )
+        .await
+        .expect("overwrite");
+
+    storage
+        .transfer_guild_ownership(guild, alice, bob)
+        .await
+        .expect("transfer");
+    assert_eq!(
+        storage
+            .membership_status(guild, bob)
+            .await
+            .expect("status")
+            .map(|(role, _, _)| role),
+        Some(Role::Owner)
+    );
+    assert_eq!(
+        storage
+            .membership_status(guild, alice)
+            .await
+            .expect("status")
+            .map(|(role, _, _)| role),
+        Some(Role::Mod)
+    );
+
+    assert!(storage.remove_membership(guild, bob).await.expect("remove"));
+    assert!(storage
+        .membership_status(guild, bob)
+        .await
+        .expect("status")
+        .is_none());
+    assert!(storage
+        .list_member_roles(guild, bob)
+        .await
+        .expect("roles")
+        .is_empty());
+    assert!(storage
+        .list_channel_overwrites(channel)
+        .await
+        .expect("overwrites")
+        .is_empty());
+    assert!(!storage
+        .remove_membership(guild, bob)
+        .await
+        .expect("remove again"));
+    assert_eq!(
+        storage.guild_name(guild).await.expect("name").as_deref(),
+        Some("crew")
+    );
+}
+
+#[tokio::test]
+async fn bans_invites_and_audit_entries_round_trip() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("alice");
+    let bob = storage.create_user("bob").await.expect("bob");
+    let guild = storage.create_guild("crew", alice).await.expect("guild");
+
+    storage.ban_member(guild, bob).await.expect("ban");
+    assert_eq!(
+        storage.membership_status(guild, bob).await.expect("status"),
+        Some((Role::Member, true, false))
+    );
+    assert!(storage.unban_member(guild, bob).await.expect("unban"));
+    assert!(!storage.unban_member(guild, bob).await.expect("unban"));
+    assert_eq!(
+        storage.membership_status(guild, bob).await.expect("status"),
+        None
+    );
+
+    let invite = storage
+        .create_invite(guild, alice, "code-1")
+        .await
+        .expect("invite");
+    assert_eq!(invite.guild_id, guild);
+    assert_eq!(invite.creator_id, alice);
+    assert_eq!(
+        storage.invite("code-1").await.expect("lookup"),
+        Some(invite.clone())
+    );
+    assert_eq!(
+        storage.list_invites(guild).await.expect("list"),
+        vec![invite]
+    );
+    assert!(storage.delete_invite("code-1").await.expect("delete"));
+    assert_eq!(storage.invite("code-1").await.expect("lookup"), None);
+
+    for action in [
+        AuditAction::MemberBanned,
+        AuditAction::MemberUnbanned,
+        AuditAction::InviteCreated,
+    ] {
+        storage
+            .append_audit_entry(&NewAuditEntry {
+                target_user_id: Some(bob),
+                reason: Some("testing".to_string()),
+                ..NewAuditEntry::new(guild, alice, action)
+            })
+            .await
+            .expect("audit entry");
+    }
+    let entries = storage
+        .list_audit_entries(guild, &AuditLogFilter::default())
+        .await
+        .expect("entries");
+    assert_eq!(
+        entries.iter().map(|e| e.action).collect::<Vec<_>>(),
+        vec![
+            AuditAction::InviteCreated,
+            AuditAction::MemberUnbanned,
+            AuditAction::MemberBanned,
+        ]
+    );
+    assert_eq!(entries[0].actor_username.as_deref(), Some("alice"));
+    assert_eq!(entries[0].target_username.as_deref(), Some("bob"));
+
+    let older = storage
+        .list_audit_entries(
+            guild,
+            &AuditLogFilter {
+                before: Some(entries[0].entry_id),
+                limit: Some(1),
+                ..AuditLogFilter::default()
+            },
+        )
+        .await
+        .expect("page");
+    assert_eq!(older.len(), 1);
+    assert_eq!(older[0].action, AuditAction::MemberUnbanned);
+    let bans = storage
+        .list_audit_entries(
+            guild,
+            &AuditLogFilter {
+                action: Some(AuditAction::MemberBanned),
+                ..AuditLogFilter::default()
+            },
+        )
+        .await
+        .expect("filtered");
+    assert_eq!(bans.len(), 1);
+}
+
+#[tokio::test]
+async fn direct_channels_are_found_by_exact_participants_and_hidden_from_guilds() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("alice");
+    let bob = storage.create_user("bob").await.expect("bob");
+    let carol = storage.create_user("carol").await.expect("carol");
+    assert_eq!(
+        storage.user_id_for_username("bob").await.expect("lookup"),
+        Some(bob)
+    );
+    assert_eq!(
+        storage
+            .user_id_for_username("nobody")
+            .await
+            .expect("lookup"),
+        None
+    );
+
+    let pair = storage
+        .create_direct_channel(alice, &[bob])
+        .await
+        .expect("pair");
+    let group = storage
+        .create_direct_channel(alice, &[bob, carol, bob])
+        .await
+        .expect("group");
+    assert_eq!(
+        storage
+            .find_direct_channel(&[bob, alice])
+            .await
+            .expect("find"),
+        Some(pair)
+    );
+    assert_eq!(
+        storage
+            .find_direct_channel(&[alice, bob, carol])
+            .await
+            .expect("find"),
+        Some(group)
+    );
+    assert_eq!(
+        storage
+            .find_direct_channel(&[alice, carol])
+            .await
+            .expect("find"),
+        None
+    );
+
+    let channel = storage.channel(pair).await.expect("channel").expect("row");
+    assert_eq!(channel.kind, ChannelKind::Direct);
+    assert!(storage
+        .guild_is_direct(channel.guild_id)
+        .await
+        .expect("direct"));
+    assert!(storage
+        .list_guilds_for_user(alice)
+        .await
+        .expect("guilds")
+        .is_empty());
+    assert_eq!(
+        storage
+            .list_direct_channels_for_user(alice)
+            .await
+            .expect("list")
+            .iter()
+            .map(|channel| channel.channel_id)
+            .collect::<Vec<_>>(),
+        vec![group, pair]
+    );
+    assert_eq!(
+        storage
+            .list_direct_channels_for_user(carol)
+            .await
+            .expect("list")
+            .iter()
+            .map(|channel| channel.channel_id)
+            .collect::<Vec<_>>(),
+        vec![group]
+    );
+}
+
+#[tokio::test]
+async fn thread_messages_are_kept_out_of_channel_history() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("user");
+    let guild = storage.create_guild("devs", alice).await.expect("guild");
+    let channel = storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+    let parent = storage
+        .insert_message_ciphertext(channel, alice, b"parent", None)
+        .await
+        .expect("parent");
+    let thread = storage
+        .create_thread(channel, parent, alice, "follow-up")
+        .await
+        .expect("thread");
+    assert!(storage
+        .create_thread(channel, parent, alice, "again")
+        .await
+        .is_err());
+
+    for body in [b"one".as_slice(), b"two", b"three"] {
+        storage
+            .insert_thread_message_ciphertext(channel, Some(thread), alice, body, None, None)
+            .await
+            .expect("thread message");
+    }
+    let history = storage
+        .list_channel_messages(channel, 10, None)
+        .await
+        .expect("history");
+    assert_eq!(
+        history.iter().map(|m| m.message_id).collect::<Vec<_>>(),
+        vec![parent]
+    );
+    let page = storage
+        .list_thread_messages(thread, 2, None)
+        .await
+        .expect("thread page");
+    assert_eq!(
+        page.iter()
+            .map(|m| m.ciphertext.clone())
+            .collect::<Vec<_>>(),
+        vec![b"two".to_vec(), b"three".to_vec()]
+    );
+    assert!(page.iter().all(|m| m.thread_id == Some(thread)));
+    let older = storage
+        .list_thread_messages(thread, 2, Some(page[0].message_id.0))
+        .await
+        .expect("older page");
+    assert_eq!(older.len(), 1);
+    assert_eq!(
+        storage
+            .message_location(page[0].message_id)
+            .await
+            .expect("location"),
+        Some((channel, Some(thread)))
+    );
+
+    let summary = storage.thread(thread).await.expect("thread").expect("row");
+    assert_eq!(summary.guild_id, guild);
+    assert_eq!(summary.parent_message_id, parent);
+    assert_eq!(summary.message_count, 3);
+    assert!(storage
+        .set_thread_archived(thread, true)
+        .await
+        .expect("archive"));
+    assert!(storage
+        .list_threads(channel, false)
+        .await
+        .expect("open threads")
+        .is_empty());
+    assert_eq!(
+        storage
+            .list_threads(channel, true)
+            .await
+            .expect("all threads")
+            .len(),
+        1
+    );
+
+    assert!(storage.delete_channel(channel).await.expect("delete"));
+    assert!(storage.thread(thread).await.expect("thread").is_none());
+}
+
+#[tokio::test]
+async fn purge_expired_applies_timers_and_channel_retention() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("user");
+    let guild = storage.create_guild("devs", alice).await.expect("guild");
+    let timed = storage
+        .create_channel(guild, "timed", ChannelKind::Text)
+        .await
+        .expect("channel");
+    let capped = storage
+        .create_channel(guild, "capped", ChannelKind::Text)
+        .await
+        .expect("channel");
+    let now = Utc::now();
+
+    let file_id = storage
+        .store_file_ciphertext(alice, guild, timed, b"blob", None, Some("a.bin"))
+        .await
+        .expect("file");
+    let attachment = StoredAttachment {
+        file_id: file_id.clone(),
+        filename: "a.bin".to_string(),
+        size_bytes: 4,
+        mime_type: None,
+    };
+    let expiring = storage
+        .insert_thread_message_ciphertext(
+            timed,
+            None,
+            alice,
+            b"soon gone",
+            Some(&attachment),
+            Some(now + chrono::Duration::minutes(5)),
+        )
+        .await
+        .expect("expiring");
+    let kept = storage
+        .insert_message_ciphertext(timed, alice, b"kept", None)
+        .await
+        .expect("kept");
+    assert_eq!(
+        storage.purge_expired(now).await.expect("purge").messages,
+        Vec::new()
+    );
+
+    let mut capped_ids = Vec::new();
+    for body in [b"one".as_slice(), b"two", b"three"] {
+        capped_ids.push(
+            storage
+                .insert_message_ciphertext(capped, alice, body, None)
+                .await
+                .expect("message"),
+        );
+    }
+    let thread = storage
+        .create_thread(capped, capped_ids[0], alice, "old")
+        .await
+        .expect("thread");
+    let reply = storage
+        .insert_thread_message_ciphertext(capped, Some(thread), alice, b"reply", None, None)
+        .await
+        .expect("reply");
+    assert!(storage
+        .set_channel_retention(
+            capped,
+            RetentionPolicy {
+                max_age_seconds: None,
+                max_messages: Some(2),
+            },
+        )
+        .await
+        .expect("retention"));
+    assert_eq!(
+        storage
+            .channel(capped)
+            .await
+            .expect("channel")
+            .expect("row")
+            .retention
+            .max_messages,
+        Some(2)
+    );
+
+    for _ in 0..2 {
+        storage
+            .insert_pending_welcome(guild, timed, alice, None, b"welcome")
+            .await
+            .expect("welcome");
+        storage
+            .load_and_consume_pending_welcome(guild, timed, alice)
+            .await
+            .expect("consume")
+            .expect("welcome row");
+    }
+
+    let purge = storage
+        .purge_expired(now + chrono::Duration::minutes(10))
+        .await
+        .expect("purge");
+    assert_eq!(
+        purge.messages,
+        vec![
+            (guild, timed, vec![expiring]),
+            (guild, capped, vec![capped_ids[0], reply]),
+        ]
+    );
+    assert_eq!(purge.files, 1);
+    assert_eq!(purge.welcomes, 1);
+    assert!(storage.load_file(&file_id).await.expect("file").is_none());
+    assert!(storage.thread(thread).await.expect("thread").is_none());
+    assert_eq!(
+        storage
+            .list_channel_messages(timed, 10, None)
+            .await
+            .expect("history")
+            .iter()
+            .map(|message| message.message_id)
+            .collect::<Vec<_>>(),
+        vec![kept]
+    );
+    assert!(storage
+        .load_latest_welcome_any_state(guild, timed, alice, None)
+        .await
+        .expect("latest welcome")
+        .is_some());
+}
+
+#[tokio::test]
+async fn maintenance_reclaims_spent_rows_and_keeps_live_ones() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("user");
+    let bob = storage.create_user("bob").await.expect("user");
+    let guild = storage.create_guild("devs", alice).await.expect("guild");
+    let channel = storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+    let device = storage
+        .register_device(alice, "laptop", "alice:laptop")
+        .await
+        .expect("device")
+        .device_id;
+    let now = Utc::now();
+
+    // Bob's only Welcome is already consumed; recovery still needs it.
+    storage
+        .insert_pending_welcome(guild, channel, bob, None, b"bob welcome")
+        .await
+        .expect("welcome");
+    storage
+        .load_and_consume_pending_welcome(guild, channel, bob)
+        .await
+        .expect("consume")
+        .expect("pending");
+
+    for bytes in [b"old".as_slice(), b"new"] {
+        storage
+            .insert_key_package(guild, alice, Some(device), bytes)
+            .await
+            .expect("key package");
+    }
+    storage
+        .insert_pending_welcome(guild, channel, alice, Some(device), b"welcome")
+        .await
+        .expect("welcome");
+    storage
+        .load_and_consume_pending_welcome(guild, channel, alice)
+        .await
+        .expect("consume")
+        .expect("pending");
+    storage
+        .insert_pending_welcome(guild, channel, alice, Some(device), b"unread")
+        .await
+        .expect("welcome");
+    let spent = storage
+        .create_device_link_token(alice, device, "pk", now + chrono::Duration::hours(1))
+        .await
+        .expect("token");
+    storage
+        .store_device_link_bundle(alice, spent, device, device, "{}")
+        .await
+        .expect("bundle");
+    storage
+        .consume_device_link_bundle(alice, spent)
+        .await
+        .expect("consume bundle")
+        .expect("bundle");
+    let live = storage
+        .create_device_link_token(alice, device, "pk", now + chrono::Duration::hours(1))
+        .await
+        .expect("token");
+    storage
+        .store_file_ciphertext(alice, guild, channel, b"orphan", None, Some("o.bin"))
+        .await
+        .expect("orphan");
+    let attached = storage
+        .store_file_ciphertext(alice, guild, channel, b"kept", None, Some("k.bin"))
+        .await
+        .expect("attached");
+    storage
+        .insert_message_ciphertext(
+            channel,
+            alice,
+            b"hi",
+            Some(&StoredAttachment {
+                file_id: attached.clone(),
+                filename: "k.bin".to_string(),
+                size_bytes: 4,
+                mime_type: None,
+            }),
+        )
+        .await
+        .expect("message");
+    storage
+        .create_upload_session(alice, guild, channel, 10, None, Some("big.bin"))
+        .await
+        .expect("abandoned upload");
+
+    let policy = MaintenancePolicy {
+        consumed_welcome_ttl: chrono::Duration::zero(),
+        key_package_ttl: chrono::Duration::zero(),
+        device_link_ttl: chrono::Duration::zero(),
+        orphan_file_ttl: chrono::Duration::zero(),
+        upload_session_ttl: chrono::Duration::zero(),
+        compact: true,
+    };
+    let report = storage
+        .run_maintenance(policy, now + chrono::Duration::minutes(1))
+        .await
+        .expect("maintenance");
+    assert_eq!(
+        (
+            report.welcomes,
+            report.key_packages,
+            report.device_link_tokens,
+            report.device_link_bundles,
+            report.files,
+            report.upload_sessions,
+        ),
+        (1, 1, 1, 1, 1, 1)
+    );
+    assert_eq!(
+        storage
+            .load_latest_key_package(guild, alice, Some(device))
+            .await
+            .expect("key package")
+            .map(|(_, _, bytes)| bytes),
+        Some(b"new".to_vec())
+    );
+    assert!(storage
+        .load_pending_welcome(guild, channel, alice, Some(device))
+        .await
+        .expect("welcome")
+        .is_some());
+    assert!(storage
+        .load_device_link_token(alice, live)
+        .await
+        .expect("token")
+        .is_some());
+    assert!(storage.load_file(&attached).await.expect("file").is_some());
+
+    let again = storage
+        .run_maintenance(policy, now + chrono::Duration::minutes(1))
+        .await
+        .expect("second run");
+    assert_eq!(again.rows(), 0);
+
+    // Recovery re-issues Bob's newest Welcome, which maintenance kept.
+    let latest = storage
+        .load_latest_welcome_any_state(guild, channel, bob, None)
+        .await
+        .expect("latest welcome")
+        .expect("kept for recovery");
+    assert_eq!(latest.welcome_bytes, b"bob welcome".to_vec());
+    storage
+        .insert_pending_welcome(guild, channel, bob, None, &latest.welcome_bytes)
+        .await
+        .expect("recovery welcome");
+    assert_eq!(
+        storage
+            .load_and_consume_pending_welcome(guild, channel, bob)
+            .await
+            .expect("consume")
+            .map(|welcome| welcome.welcome_bytes),
+        Some(b"bob welcome".to_vec())
+    );
+}
+
+#[tokio::test]
+async fn blob_store_holds_new_files_and_migrates_inline_ones() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let inline = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = inline.create_user("alice").await.expect("user");
+    let guild = inline.create_guild("devs", alice).await.expect("guild");
+    let channel = inline
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+    let old = inline
+        .store_file_ciphertext(alice, guild, channel, b"legacy", None, Some("old.bin"))
+        .await
+        .expect("inline file");
+
+    let blobs: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+    let storage = inline.with_blob_store(Some(Arc::clone(&blobs)));
+    let new = storage
+        .store_file_ciphertext(alice, guild, channel, b"sealed", None, Some("new.bin"))
+        .await
+        .expect("blob file");
+    let stored_inline: Vec<u8> =
+        sqlx::query_scalar("SELECT ciphertext FROM files WHERE public_id = ?")
+            .bind(&new.0)
+            .fetch_one(storage.pool())
+            .await
+            .expect("row");
+    assert!(stored_inline.is_empty());
+    assert_eq!(
+        storage
+            .load_file(&new)
+            .await
+            .expect("load")
+            .expect("file")
+            .ciphertext,
+        b"sealed"
+    );
+    assert_eq!(
+        storage
+            .load_file(&old)
+            .await
+            .expect("load")
+            .expect("file")
+            .ciphertext,
+        b"legacy"
+    );
+
+    assert_eq!(
+        storage
+            .migrate_files_to_blob_store(1)
+            .await
+            .expect("migrate"),
+        1
+    );
+    assert_eq!(
+        storage.migrate_files_to_blob_store(1).await.expect("rerun"),
+        0
+    );
+    assert!(blobs
+        .get(&blob_key(b"legacy"))
+        .await
+        .expect("get")
+        .is_some());
+    assert_eq!(
+        storage
+            .load_file(&old)
+            .await
+            .expect("load")
+            .expect("file")
+            .ciphertext,
+        b"legacy"
+    );
+
+    assert!(storage.delete_channel(channel).await.expect("delete"));
+    for bytes in [b"legacy".as_slice(), b"sealed"] {
+        assert!(blobs.get(&blob_key(bytes)).await.expect("get").is_none());
+    }
+}
+
+#[tokio::test]
+async fn chunked_uploads_complete_in_order_and_serve_byte_ranges() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let blobs: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+    for store in [None, Some(Arc::clone(&blobs))] {
+        let storage = Storage::new("sqlite::memory:")
+            .await
+            .expect("db")
+            .with_blob_store(store);
+        let alice = storage.create_user("alice").await.expect("user");
+        let guild = storage.create_guild("devs", alice).await.expect("guild");
+        let channel = storage
+            .create_channel(guild, "general", ChannelKind::Text)
+            .await
+            .expect("channel");
+
+        let session = storage
+            .create_upload_session(alice, guild, channel, 10, Some("video/mp4"), Some("a.mp4"))
+            .await
+            .expect("session");
+        let id = session.upload_id.as_str();
+        assert_eq!(
+            storage
+                .append_upload_chunk(id, 0, b"0123")
+                .await
+                .expect("first"),
+            Some(4)
+        );
+        assert_eq!(
+            storage
+                .append_upload_chunk(id, 0, b"0123")
+                .await
+                .expect("replay"),
+            None
+        );
+        assert_eq!(
+            storage
+                .append_upload_chunk(id, 4, b"4567890")
+                .await
+                .expect("overrun"),
+            None
+        );
+        assert_eq!(storage.complete_upload(id).await.expect("early"), None);
+        assert_eq!(
+            storage
+                .append_upload_chunk(id, 4, b"456789")
+                .await
+                .expect("second"),
+            Some(10)
+        );
+        assert_eq!(
+            storage
+                .load_upload_session(id)
+                .await
+                .expect("load")
+                .expect("session")
+                .received_bytes,
+            10
+        );
+        assert_eq!(
+            storage.upload_sha256(id).await.expect("hash"),
+            blob_key(b"0123456789")
+        );
+
+        let file_id = storage
+            .complete_upload(id)
+            .await
+            .expect("complete")
+            .expect("file");
+        assert!(storage
+            .load_upload_session(id)
+            .await
+            .expect("load")
+            .is_none());
+        let metadata = storage
+            .load_file_metadata(&file_id)
+            .await
+            .expect("metadata")
+            .expect("file");
+        assert_eq!(
+            (metadata.size_bytes, metadata.filename.as_deref()),
+            (10, Some("a.mp4"))
+        );
+        assert_eq!(
+            storage
+                .read_file_range(&file_id, 2, 7)
+                .await
+                .expect("range"),
+            b"23456"
+        );
+        assert_eq!(
+            storage
+                .read_file_range(&file_id, 8, 100)
+                .await
+                .expect("tail"),
+            b"89"
+        );
+        assert_eq!(
+            storage
+                .load_file(&file_id)
+                .await
+                .expect("load")
+                .expect("file")
+                .ciphertext,
+            b"0123456789"
+        );
+
+        assert!(storage.delete_channel(channel).await.expect("delete"));
+        let chunks: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM file_chunks")
+            .fetch_one(storage.pool())
+            .await
+            .expect("count");
+        assert_eq!(chunks, 0);
+    }
+    assert!(blobs
+        .get(&blob_key(b"456789"))
+        .await
+        .expect("get")
+        .is_none());
+}
+
+#[tokio::test]
+async fn storage_quotas_count_files_and_open_uploads_until_deleted() {
+    let storage = Storage::new("sqlite::memory:")
+        .await
+        .expect("db")
+        .with_storage_quotas(StorageQuotas {
+            guild_bytes: Some(100),
+            user_bytes: Some(60),
+        });
+    let alice = storage.create_user("alice").await.expect("alice");
+    let bob = storage.create_user("bob").await.expect("bob");
+    let guild = storage.create_guild("devs", alice).await.expect("guild");
+    let channel = storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+
+    storage
+        .store_file_ciphertext(alice, guild, channel, &[1; 40], None, None)
+        .await
+        .expect("first file");
+    let error = storage
+        .store_file_ciphertext(alice, guild, channel, &[2; 30], None, None)
+        .await
+        .expect_err("over the user quota");
+    assert_eq!(
+        error.downcast_ref::<QuotaExceeded>(),
+        Some(&QuotaExceeded {
+            scope: QuotaScope::User,
+            used_bytes: 40,
+            requested_bytes: 30,
+            quota_bytes: 60,
+        })
+    );
+
+    storage
+        .store_file_ciphertext(bob, guild, channel, &[3; 50], None, None)
+        .await
+        .expect("bob's file");
+    let error = storage
+        .create_upload_session(bob, guild, channel, 20, None, None)
+        .await
+        .expect_err("over the guild quota");
+    assert_eq!(
+        error.downcast_ref::<QuotaExceeded>().map(|e| e.scope),
+        Some(QuotaScope::Guild)
+    );
+    storage
+        .create_upload_session(bob, guild, channel, 10, None, None)
+        .await
+        .expect("session fits");
+    assert_eq!(
+        storage.guild_storage_usage(guild).await.expect("usage"),
+        StorageUsage {
+            used_bytes: 90,
+            reserved_bytes: 10,
+        }
+    );
+    assert_eq!(
+        storage
+            .user_storage_usage(bob)
+            .await
+            .expect("usage")
+            .total_bytes(),
+        60
+    );
+
+    assert!(storage.delete_channel(channel).await.expect("delete"));
+    assert_eq!(
+        storage.guild_storage_usage(guild).await.expect("usage"),
+        StorageUsage::default()
+    );
+    assert_eq!(
+        storage.user_storage_usage(alice).await.expect("usage"),
+        StorageUsage::default()
+    );
+}
+
+#[tokio::test]
+async fn thumbnails_link_once_and_are_deleted_with_their_attachment() {
+    let storage = Storage::new("sqlite::memory:").await.expect("db");
+    let alice = storage.create_user("alice").await.expect("alice");
+    let guild = storage.create_guild("devs", alice).await.expect("guild");
+    let channel = storage
+        .create_channel(guild, "general", ChannelKind::Text)
+        .await
+        .expect("channel");
+    let store = |bytes: &'static [u8]| {
+        let storage = storage.clone();
+        async move {
+            storage
+                .store_file_ciphertext(alice, guild, channel, bytes, None, None)
+                .await
+                .expect("file")
+        }
+    };
+    let photo = store(b"full-size photo").await;
+    let thumbnail = store(b"thumb").await;
+    let spare = store(b"spare").await;
+
+    assert!(storage
+        .link_thumbnail(&photo, &thumbnail)
+        .await
+        .expect("link"));
+    assert!(!storage
+        .link_thumbnail(&photo, &spare)
+        .await
+        .expect("second thumbnail"));
+    assert!(!storage
+        .link_thumbnail(&thumbnail, &spare)
+        .await
+        .expect("thumbnail of a thumbnail"));
+    assert!(!storage
+        .link_thumbnail(&spare, &spare)
+        .await
+        .expect("own thumbnail"));
+    assert_eq!(
+        storage
+            .load_file_metadata(&thumbnail)
+            .await
+            .expect("metadata")
+            .expect("row")
+            .thumbnail_of,
+        Some(photo.clone())
+    );
+
+    let message = storage
+        .insert_thread_message_ciphertext(
+            channel,
+            None,
+            alice,
+            b"look",
+            Some(&StoredAttachment {
+                file_id: photo.clone(),
+                filename: "photo.jpg".to_string(),
+                size_bytes: 15,
+                mime_type: None,
+            }),
+            Some(Utc::now() + chrono::Duration::minutes(1)),
+        )
+        .await
+        .expect("message");
+    // Linked thumbnails are never orphans while their attachment is kept.
+    let report = storage
+        .run_maintenance(
+            MaintenancePolicy {
+                orphan_file_ttl: chrono::Duration::zero(),
+                ..MaintenancePolicy::default()
+            },
+            Utc::now() + chrono::Duration::seconds(30),
+        )
+        .await
+        .expect("maintenance");
+    assert_eq!(report.files, 1);
+    assert!(storage.load_file(&spare).await.expect("spare").is_none());
+    assert!(storage
+        .load_file(&thumbnail)
+        .await
+        .expect("thumb")
+        .is_some());
+
+    let purge = storage
+        .purge_expired(Utc::now() + chrono::Duration::minutes(2))
+        .await
+        .expect("purge");
+    assert_eq!(purge.messages, vec![(guild, channel, vec![message])]);
+    assert_eq!(purge.files, 2);
+    assert!(storage
+        .load_file(&thumbnail)
+        .await
+        .expect("thumb")
+        .is_none());
+    assert_eq!(
+        storage.guild_storage_usage(guild).await.expect("usage"),
+        StorageUsage::default()
+    );
+}